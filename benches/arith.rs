@@ -0,0 +1,51 @@
+// Runs straight-line code doing arithmetic on a few cells, with runs of `+`
+// and `-` that don't merge, in every `ArithMode`
+extern crate brainfuck;
+
+use std::time::{Duration, Instant};
+
+use brainfuck::brainfuck::{relink, ArithMode, Brainfuck, Inst};
+use brainfuck::brainfuck::Inst::*;
+
+const BLOCKS: usize = 1000;
+const ROUNDS: usize = 250;
+const RUNS: u32 = 20;
+
+// `ROUNDS` times round, counted down in the first cell, a run of
+// instructions changing each of the next three cells without taking them
+// out of range, which starts and ends with the cell at 100
+fn arithmetic() -> Vec<Inst> {
+    let mut insts = vec![IncVal(ROUNDS), JmpFwd(0)];
+    for block in 0..BLOCKS {
+        insts.push(IncPtr(1));
+        for step in 1..5 {
+            insts.push(IncVal(block % 7 + step));
+            insts.push(DecVal(step));
+        }
+        insts.push(DecVal(block % 7 * 4));
+        if block % 3 == 2 {
+            insts.push(DecPtr(3));
+        }
+    }
+    let ptr = BLOCKS - BLOCKS / 3 * 3;
+    insts.extend_from_slice(&[DecPtr(ptr), DecVal(1), JmpBack(0)]);
+    relink(&mut insts);
+    insts
+}
+
+fn main() {
+    let insts = arithmetic();
+    for &mode in &[ArithMode::Wrap, ArithMode::Saturate, ArithMode::Trap] {
+        let mut bf = Brainfuck::from_insts(insts.clone()).unwrap();
+        bf.set_initial_tape(&[0, 100, 100, 100]).unwrap();
+        bf.set_arith_mode(mode).unwrap();
+
+        let mut total = Duration::ZERO;
+        for _ in 0..RUNS {
+            let started = Instant::now();
+            bf.run().unwrap();
+            total += started.elapsed();
+        }
+        println!("{:<10} {:>10.2?}", format!("{:?}", mode), total / RUNS);
+    }
+}
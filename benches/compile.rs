@@ -0,0 +1,36 @@
+// Compiles a generated program of about 2 MB of source in memory a few
+// dozen times, printing how long the fastest of them took and how fast
+// that is. Only code generation is timed, the program is parsed once
+// beforehand.
+extern crate brainfuck;
+
+use std::time::{Duration, Instant};
+
+use brainfuck::brainfuck::{compile_insts, parse, CodegenOptions};
+
+const SOURCE_SIZE: usize = 2 << 20;
+const ROUNDS: usize = 30;
+
+// Loops, moves, runs, checks and I/O, with a comment now and then
+const PIECE: &str = "++++[>+++>++<<-]>[-]>[>]<[<]+++++.---- adds some >>[-]<<,[->+<]\n";
+
+fn main() {
+    let source = PIECE.repeat(SOURCE_SIZE / PIECE.len());
+    let insts = parse(&source).unwrap();
+    let options = CodegenOptions::default();
+
+    let mut fastest = Duration::MAX;
+    let mut size = 0;
+    for _ in 0..ROUNDS {
+        let started = Instant::now();
+        let code = compile_insts(&insts, &options).unwrap();
+        fastest = fastest.min(started.elapsed());
+        size = code.len();
+    }
+    let megabytes = |bytes: usize| bytes as f64 / (1 << 20) as f64;
+    println!("{:<12} {:>10} instructions, {:.1} MB of code", "program", insts.len(), megabytes(size));
+    println!(
+        "{:<12} {:>10.2?} {:>8.1} MB/s of code",
+        "compile", fastest, megabytes(size) / fastest.as_secs_f64()
+    );
+}
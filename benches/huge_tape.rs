@@ -0,0 +1,53 @@
+// Runs a random walk over a 256 MiB tape, on the heap and on huge pages,
+// touching cells too far apart for the TLB to cover
+extern crate brainfuck;
+
+use std::time::{Duration, Instant};
+
+use brainfuck::brainfuck::{relink, Brainfuck, Inst, TapeAlloc};
+use brainfuck::brainfuck::Inst::*;
+
+const TAPE_SIZE: usize = 256 << 20;
+const STEPS: usize = 4096;
+// the walk goes round `OUTER` * `INNER` times, often enough to make up for
+// clearing whole huge pages when it first touches them
+const OUTER: usize = 100;
+const INNER: usize = 100;
+const RUNS: u32 = 3;
+
+// The rounds over the same `STEPS` cells, counted down on the first two.
+// Built as instructions, as source the moves would take gigabytes.
+fn random_walk() -> Vec<Inst> {
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    let mut ptr = 1;
+    let mut insts = vec![IncVal(OUTER), JmpFwd(0), IncPtr(1), IncVal(INNER), JmpFwd(0)];
+    for _ in 0..STEPS {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        let target = 2 + (state % (TAPE_SIZE as u64 - 2)) as usize;
+        insts.push(if target > ptr { IncPtr(target - ptr) } else { DecPtr(ptr - target) });
+        insts.push(IncVal(1));
+        ptr = target;
+    }
+    insts.extend_from_slice(&[DecPtr(ptr - 1), DecVal(1), JmpBack(0), DecPtr(1), DecVal(1), JmpBack(0)]);
+    relink(&mut insts);
+    insts
+}
+
+fn main() {
+    let insts = random_walk();
+    for &alloc in &[TapeAlloc::Default, TapeAlloc::Huge, TapeAlloc::HugeTlb] {
+        let mut bf = Brainfuck::from_insts(insts.clone()).unwrap();
+        bf.set_tape_size(TAPE_SIZE).unwrap();
+        bf.set_tape_alloc(alloc);
+
+        let mut total = Duration::ZERO;
+        for _ in 0..RUNS {
+            let started = Instant::now();
+            bf.run().unwrap();
+            total += started.elapsed();
+        }
+        println!("{:?}: {:?} per run, the tape was {:?}", alloc, total / RUNS, bf.last_tape_alloc());
+    }
+}
@@ -0,0 +1,33 @@
+// Runs a program that barely touches its tape many times, on a tape of its
+// own every time and on one tape passed to `run_in`, cleared or not
+extern crate brainfuck;
+
+use std::time::{Duration, Instant};
+
+use brainfuck::brainfuck::Brainfuck;
+
+const RUNS: u32 = 2000;
+
+fn time<F: FnMut()>(mut run: F) -> Duration {
+    let started = Instant::now();
+    for _ in 0..RUNS {
+        run();
+    }
+    started.elapsed() / RUNS
+}
+
+fn main() {
+    for &tape_size in &[30_000, 1 << 20, 16 << 20] {
+        let mut bf = Brainfuck::new("++++++++[>++++++++<-]>[>+<-]").unwrap();
+        bf.set_tape_size(tape_size).unwrap();
+        let mut tape = vec![0; tape_size];
+
+        let fresh = time(|| bf.run().unwrap());
+        let zeroed = time(|| bf.run_in(&mut tape, true).unwrap());
+        let dirty = time(|| bf.run_in(&mut tape, false).unwrap());
+        println!(
+            "{:>9} cells: run {:>10?}, run_in zeroed {:>10?}, run_in dirty {:>10?}",
+            tape_size, fresh, zeroed, dirty
+        );
+    }
+}
@@ -0,0 +1,53 @@
+// Scans a tape of cells that aren't zero for the zero at its end, with `[>]`
+// and `[<]`, which search it 16 cells at a time, and with loops doing the
+// same a cell at a time
+extern crate brainfuck;
+
+use std::time::{Duration, Instant};
+
+use brainfuck::brainfuck::{relink, Brainfuck, Inst};
+use brainfuck::brainfuck::Inst::*;
+
+const TAPE_SIZE: usize = 16 << 20;
+const RUNS: u32 = 20;
+
+// `[>]` or `[<]`, with `+-` in the body to keep it from being recognized
+fn scan(right: bool, blocks: bool) -> Vec<Inst> {
+    let step = if right { IncPtr(1) } else { DecPtr(1) };
+    let mut insts = vec![JmpFwd(0), step];
+    if !blocks {
+        insts.extend_from_slice(&[IncVal(1), DecVal(1)]);
+    }
+    insts.push(JmpBack(0));
+    relink(&mut insts);
+    insts
+}
+
+fn main() {
+    let mut tape = vec![1; TAPE_SIZE];
+    for &right in &[true, false] {
+        let (start, zero) = if right { (0, TAPE_SIZE - 1) } else { (TAPE_SIZE - 1, 0) };
+        tape[TAPE_SIZE - 1 - zero] = 1;
+        tape[zero] = 0;
+
+        for &blocks in &[true, false] {
+            let mut bf = Brainfuck::from_insts(scan(right, blocks)).unwrap();
+            bf.set_tape_size(TAPE_SIZE).unwrap();
+            bf.set_initial_tape(&tape).unwrap();
+            bf.set_pointer_start(start).unwrap();
+
+            let mut total = Duration::ZERO;
+            for _ in 0..RUNS {
+                let started = Instant::now();
+                bf.run().unwrap();
+                total += started.elapsed();
+            }
+            println!(
+                "{:<5} {:<14} {:>10.2?}",
+                if right { "[>]" } else { "[<]" },
+                if blocks { "in blocks" } else { "cell by cell" },
+                total / RUNS
+            );
+        }
+    }
+}
@@ -0,0 +1,58 @@
+// Compiles a generated program of about 100 MB, from a file, first with
+// `compile_streaming` and then the way the whole source is compiled in
+// memory, printing how long each took and the peak memory after it. The
+// peak only grows, so the second one is the larger of the two.
+extern crate brainfuck;
+extern crate libc;
+
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Read, Write};
+use std::time::Instant;
+
+use brainfuck::brainfuck::{compile_insts, compile_streaming, parse, CodegenOptions};
+
+const SOURCE_SIZE: usize = 100 << 20;
+
+// Loops, moves, runs and output, with a comment now and then
+const PIECE: &str = "++++[>+++>++<<-]>[-]>[>]<[<]+++++.---- adds some >>[-]<<\n";
+
+fn peak_memory() -> usize {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) };
+    usage.ru_maxrss as usize * 1024
+}
+
+fn megabytes(bytes: usize) -> String {
+    format!("{:.1} MB", bytes as f64 / (1 << 20) as f64)
+}
+
+fn main() {
+    let dir = env::temp_dir();
+    let source_path = dir.join(format!("brainfuck-bench-streaming-{}.b", std::process::id()));
+    let out_path = dir.join(format!("brainfuck-bench-streaming-{}.bin", std::process::id()));
+
+    let mut source = BufWriter::new(File::create(&source_path).unwrap());
+    for _ in 0..SOURCE_SIZE / PIECE.len() {
+        source.write_all(PIECE.as_bytes()).unwrap();
+    }
+    source.flush().unwrap();
+    drop(source);
+    println!("{:<12} {:>10}", "source", megabytes(fs::metadata(&source_path).unwrap().len() as usize));
+    println!("{:<12} {:>10}", "baseline", megabytes(peak_memory()));
+
+    let started = Instant::now();
+    let mut out = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&out_path).unwrap();
+    compile_streaming(File::open(&source_path).unwrap(), &mut out).unwrap();
+    println!("{:<12} {:>10.2?} {:>10}", "streaming", started.elapsed(), megabytes(peak_memory()));
+
+    let started = Instant::now();
+    let mut code = String::new();
+    File::open(&source_path).unwrap().read_to_string(&mut code).unwrap();
+    let compiled = compile_insts(&parse(&code).unwrap(), &CodegenOptions::default()).unwrap();
+    println!("{:<12} {:>10.2?} {:>10}", "in memory", started.elapsed(), megabytes(peak_memory()));
+
+    assert!(fs::read(&out_path).unwrap() == compiled);
+    fs::remove_file(&source_path).unwrap();
+    fs::remove_file(&out_path).unwrap();
+}
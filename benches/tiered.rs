@@ -0,0 +1,39 @@
+// Runs a program with a lot of code that runs once and one loop that runs
+// for long, generated code paying for compiling all of it and the
+// interpreter for going round the loop, tiered execution for neither
+extern crate brainfuck;
+
+use std::time::{Duration, Instant};
+
+use brainfuck::brainfuck::{parse, Brainfuck, Inst, TieredProgram};
+use brainfuck::interp;
+
+// copies of a snippet with a loop going round three times
+const COLD: usize = 500_000;
+const RUNS: u32 = 3;
+
+// The cold code and then 10 * 255^3 rounds of a clear loop
+fn program() -> Vec<Inst> {
+    let mut source = ">+++[-]<".repeat(COLD);
+    source.push_str("++++++++++[>-[>-[>-[-]<-]<-]<-]");
+    parse(&source).unwrap()
+}
+
+fn time<F: FnMut()>(name: &str, mut run: F) {
+    let mut total = Duration::ZERO;
+    for _ in 0..RUNS {
+        let started = Instant::now();
+        run();
+        total += started.elapsed();
+    }
+    println!("{}: {:?} per run", name, total / RUNS);
+}
+
+fn main() {
+    let insts = program();
+    time("generated code", || Brainfuck::from_insts(insts.clone()).unwrap().run().unwrap());
+    time("interpreter", || {
+        interp::run_with_input(&insts, 30_000, b"").unwrap();
+    });
+    time("tiered", || TieredProgram::from_insts(insts.clone()).unwrap().run().unwrap());
+}
@@ -0,0 +1,43 @@
+// Clears a region of 10k cells over and over, with `[-]>[-]>…`, which is
+// cleared in one go, and with `[---]>[---]>…` under saturating arithmetic,
+// which clears the cells just as well but a loop at a time
+extern crate brainfuck;
+
+use std::time::{Duration, Instant};
+
+use brainfuck::brainfuck::{relink, ArithMode, Brainfuck, Inst};
+use brainfuck::brainfuck::Inst::*;
+
+const CELLS: usize = 10_000;
+const ROUNDS: u8 = 200;
+const RUNS: u32 = 20;
+
+// Counts down the first cell, clearing the `CELLS` after it every round
+fn clear_region(step: usize) -> Vec<Inst> {
+    let mut insts = vec![JmpFwd(0)];
+    for _ in 0..CELLS {
+        insts.extend_from_slice(&[IncPtr(1), JmpFwd(0), DecVal(step), JmpBack(0)]);
+    }
+    insts.extend_from_slice(&[DecPtr(CELLS), DecVal(1), JmpBack(0)]);
+    relink(&mut insts);
+    insts
+}
+
+fn main() {
+    let mut tape = vec![1; CELLS + 1];
+    tape[0] = ROUNDS;
+    for &(step, arith, name) in &[(1, ArithMode::Wrap, "in one go"), (3, ArithMode::Saturate, "loop by loop")] {
+        let mut bf = Brainfuck::from_insts(clear_region(step)).unwrap();
+        bf.set_tape_size(CELLS + 1).unwrap();
+        bf.set_initial_tape(&tape).unwrap();
+        bf.set_arith_mode(arith).unwrap();
+
+        let mut total = Duration::ZERO;
+        for _ in 0..RUNS {
+            let started = Instant::now();
+            bf.run().unwrap();
+            total += started.elapsed();
+        }
+        println!("{:<14} {:>10.2?}", name, total / RUNS);
+    }
+}
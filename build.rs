@@ -0,0 +1,38 @@
+// Generates include/brainfuck.h from src/ffi.rs with cbindgen when the `ffi`
+// feature is on, with the settings in cbindgen.toml. The header is checked
+// in for C projects that don't build the crate, it's only written when it
+// changed.
+#[cfg(feature = "ffi")]
+extern crate cbindgen;
+
+#[cfg(feature = "ffi")]
+fn generate_header() {
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+
+    let dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let config = cbindgen::Config::from_file(Path::new(&dir).join("cbindgen.toml")).unwrap();
+    let mut header = Vec::new();
+    cbindgen::Builder::new()
+        .with_crate(&dir)
+        .with_config(config)
+        .generate()
+        .expect("src/ffi.rs can't be turned into a header")
+        .write(&mut header);
+
+    let path = Path::new(&dir).join("include/brainfuck.h");
+    if fs::read(&path).ok().as_ref() != Some(&header) {
+        fs::write(&path, header).unwrap();
+    }
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "ffi")]
+    {
+        println!("cargo:rerun-if-changed=src/ffi.rs");
+        println!("cargo:rerun-if-changed=cbindgen.toml");
+        generate_header();
+    }
+}
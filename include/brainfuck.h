@@ -0,0 +1,61 @@
+/* C interface of the brainfuck JIT, built with `cargo build --features ffi`
+ * into libbrainfuck.so. Generated from src/ffi.rs by build.rs, don't edit. */
+
+#ifndef BRAINFUCK_H
+#define BRAINFUCK_H
+
+#include <stddef.h>
+
+typedef enum bf_status {
+  BF_OK = 0,
+  BF_COMPILE_ERROR = 1,
+  BF_RUNTIME_ERROR = 2,
+  BF_INVALID_ARGUMENT = 3,
+  BF_PANIC = 4,
+} bf_status;
+
+// Opaque to C
+typedef struct bf_program bf_program;
+
+// Zero for anything means the default
+typedef struct bf_options {
+  size_t tape_size;
+  size_t max_memory;
+} bf_options;
+
+// `getc` returns the next byte or a negative value at the end of the input,
+// `putc` a negative value if the byte couldn't be written. Either may be
+// NULL, for no input and discarded output.
+typedef struct bf_io_callbacks {
+  void *user_data;
+  int (*putc)(void*, int);
+  int (*getc)(void*);
+} bf_io_callbacks;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Compiles the `len` bytes at `src`, `*out` is only set on success and has
+// to be freed with `bf_program_free`. `options` may be NULL.
+enum bf_status bf_compile(const char *src,
+                          size_t len,
+                          const struct bf_options *options,
+                          struct bf_program **out);
+
+// Runs the program to completion. Without callbacks the generated code runs
+// on the process' stdin and stdout. With them the program runs in the
+// interpreter instead, as generated code can only do I/O through syscalls.
+enum bf_status bf_run(struct bf_program *program, const struct bf_io_callbacks *io);
+
+void bf_program_free(struct bf_program *program);
+
+// What made the last call on this thread fail, NULL if it didn't. Valid
+// until the next call on this thread.
+const char *bf_last_error_message(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* BRAINFUCK_H */
@@ -0,0 +1,133 @@
+// Compile time checked brainfuck snippets
+//
+// `bf!("++[>+<-]")` expands to the string literal itself, so it can be used
+// anywhere a `&'static str` can, including constants. Malformed programs are
+// rejected with a `compile_error!` instead of failing at runtime.
+
+extern crate proc_macro;
+
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
+
+/// Validates a brainfuck program at compile time and expands to it.
+///
+/// The program may only contain the eight commands and whitespace, so no
+/// command can hide in a comment, and its brackets have to be balanced.
+///
+/// ```
+/// #[macro_use] extern crate brainfuck_macros;
+///
+/// const CLEAR: &str = bf!("[-]");
+/// # fn main() { assert_eq!(CLEAR, "[-]"); }
+/// ```
+///
+/// ```compile_fail
+/// #[macro_use] extern crate brainfuck_macros;
+///
+/// // error: unmatched ']' at offset 7 of the program
+/// const BROKEN: &str = bf!("+[>+<-]]");
+/// # fn main() {}
+/// ```
+///
+/// ```compile_fail
+/// #[macro_use] extern crate brainfuck_macros;
+///
+/// // error: unexpected character 'x' at offset 1 of the program
+/// const COMMENTED: &str = bf!("+x");
+/// # fn main() {}
+/// ```
+#[proc_macro]
+pub fn bf(input: TokenStream) -> TokenStream {
+    let mut tokens = input.into_iter();
+
+    let literal = match (tokens.next(), tokens.next()) {
+        (Some(TokenTree::Literal(literal)), None) => literal,
+        (Some(token), _) => return compile_error("expected a single string literal", token.span()),
+        (None, _) => return compile_error("expected a string literal", Span::call_site()),
+    };
+
+    let program = match unquote(&literal.to_string()) {
+        Some(program) => program,
+        None => return compile_error("expected a string literal", literal.span()),
+    };
+
+    match validate(&program) {
+        Ok(()) => TokenTree::Literal(literal).into(),
+        Err(message) => compile_error(&message, literal.span()),
+    }
+}
+
+// `compile_error!("message")` reported at `span`
+fn compile_error(message: &str, span: Span) -> TokenStream {
+    let mut message = Literal::string(message);
+    message.set_span(span);
+    let mut bang = Punct::new('!', Spacing::Alone);
+    bang.set_span(span);
+    let mut args = Group::new(Delimiter::Parenthesis, TokenTree::Literal(message).into());
+    args.set_span(span);
+
+    vec![
+        TokenTree::Ident(Ident::new("compile_error", span)),
+        TokenTree::Punct(bang),
+        TokenTree::Group(args),
+    ].into_iter().collect()
+}
+
+// The value of a string literal as written in the source, `None` for other
+// literals and escapes that can't appear in a valid program anyway
+fn unquote(literal: &str) -> Option<String> {
+    if let Some(raw) = literal.strip_prefix('r') {
+        let hashes = raw.find('"')?;
+        let inner = &raw[hashes..raw.len() - hashes];
+        return Some(inner[1..inner.len() - 1].to_string());
+    }
+    let quoted = literal.strip_prefix('"').and_then(|l| l.strip_suffix('"'))?;
+
+    let mut value = String::new();
+    let mut chars = quoted.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'n' => value.push('\n'),
+            'r' => value.push('\r'),
+            't' => value.push('\t'),
+            '\\' => value.push('\\'),
+            '"' => value.push('"'),
+            '\'' => value.push('\''),
+            // line continuation, skips the leading whitespace of the next line
+            '\n' => while chars.peek().is_some_and(|c| c.is_whitespace()) {
+                chars.next();
+            },
+            _ => return None,
+        }
+    }
+
+    Some(value)
+}
+
+// Checks the charset and the bracket balance, the error names the offending
+// character by its offset into the program
+fn validate(program: &str) -> Result<(), String> {
+    let mut open = Vec::new();
+
+    for (offset, c) in program.chars().enumerate() {
+        match c {
+            '>' | '<' | '+' | '-' | '.' | ',' => {}
+            '[' => open.push(offset),
+            ']' => {
+                if open.pop().is_none() {
+                    return Err(format!("unmatched ']' at offset {} of the program", offset));
+                }
+            }
+            c if c.is_whitespace() => {}
+            c => return Err(format!("unexpected character {:?} at offset {} of the program", c, offset)),
+        }
+    }
+
+    match open.pop() {
+        Some(offset) => Err(format!("unclosed '[' at offset {} of the program", offset)),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,17 @@
+#[macro_use]
+extern crate brainfuck_macros;
+
+const HELLO: &str = bf!("
+    ++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]
+    >>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.
+");
+
+#[test]
+fn test_bf() {
+    assert_eq!(bf!(""), "");
+    assert_eq!(bf!("[-]"), "[-]");
+    assert_eq!(bf!(r#"+[>+<-]"#), "+[>+<-]");
+    assert_eq!(bf!("+\t.\
+                    -"), "+\t.-");
+    assert!(HELLO.trim().starts_with("++++++++["));
+}
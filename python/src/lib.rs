@@ -0,0 +1,90 @@
+// The `brainfuck_jit` Python module, built with maturin, see README.md
+// pyo3 0.22's wrappers convert the errors of methods into themselves
+#![allow(clippy::useless_conversion)]
+
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use brainfuck::brainfuck::{parse, ArithMode, Brainfuck, CompileError, SharedProgram};
+use brainfuck::optimize::Optimizer;
+
+
+fn compile_error(e: CompileError) -> PyErr {
+    PyValueError::new_err(match e.offset() {
+        Some(offset) => format!("{} at byte {}", e, offset),
+        None => e.to_string(),
+    })
+}
+
+// A compiled program, which can be run any number of times on fresh tapes
+#[pyclass(module = "brainfuck_jit", frozen)]
+struct Program {
+    program: Brainfuck,
+    // the code, mapped once for all runs
+    shared: SharedProgram,
+}
+
+#[pymethods]
+impl Program {
+    // opt_level 0 compiles the program as parsed, 1 runs the optimizer
+    // without unrolling loops, which makes for less code, and 2 with it
+    #[new]
+    #[pyo3(signature = (src, tape_size=30000, opt_level=2))]
+    fn new(src: &str, tape_size: usize, opt_level: u32) -> PyResult<Program> {
+        let optimizer = match opt_level {
+            0 => None,
+            1 => Some(Optimizer::new(ArithMode::Wrap).without_pass("unroll loops")),
+            2 => Some(Optimizer::new(ArithMode::Wrap)),
+            _ => return Err(PyValueError::new_err(format!("opt_level must be 0, 1 or 2, not {}", opt_level))),
+        };
+        let program = match optimizer {
+            Some(optimizer) => parse(src).and_then(|insts| optimizer.run(insts)).and_then(Brainfuck::from_insts),
+            None => Brainfuck::new(src),
+        };
+        let mut program = program.map_err(compile_error)?;
+        program.set_tape_size(tape_size).map_err(|e| PyValueError::new_err(e.to_string()))?;
+        let shared = program.share().map_err(compile_error)?;
+
+        Ok(Program { program, shared })
+    }
+
+    // Runs the generated code on `input` and returns what it printed. The
+    // code reads and writes a pipe each way, it runs without holding the GIL.
+    #[pyo3(signature = (input=&[][..]), text_signature = "(self, input=b\"\")")]
+    fn run<'py>(&self, py: Python<'py>, input: &[u8]) -> PyResult<Bound<'py, PyBytes>> {
+        let shared = &self.shared;
+        let execution = py
+            .allow_threads(|| shared.run(input))
+            .map_err(|e| PyRuntimeError::new_err(e.to_string()))?;
+
+        Ok(PyBytes::new_bound(py, &execution.output))
+    }
+
+    // The instructions a line each, indented by loop depth
+    fn dump(&self) -> String {
+        let mut out = Vec::new();
+        self.program.write_dump(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[getter]
+    fn instruction_count(&self) -> usize {
+        self.program.insts().len()
+    }
+
+    #[getter]
+    fn code_size(&self) -> PyResult<usize> {
+        self.program.code_size().map_err(compile_error)
+    }
+
+    #[getter]
+    fn tape_size(&self) -> usize {
+        self.program.tape_size()
+    }
+}
+
+#[pymodule]
+fn brainfuck_jit(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Program>()
+}
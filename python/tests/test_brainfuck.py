@@ -0,0 +1,74 @@
+import threading
+
+import pytest
+
+from brainfuck_jit import Program
+
+HELLO_WORLD = (
+    "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]"
+    ">>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++."
+)
+# upper-cases its input until the end of it
+UPPER = ",[--------------------------------.[-],]"
+
+
+def test_hello_world():
+    program = Program(HELLO_WORLD)
+    assert program.run() == b"Hello World!\n"
+    assert program.tape_size == 30000
+    assert program.instruction_count > 0
+    assert program.code_size > 0
+
+
+def test_input():
+    program = Program(UPPER, tape_size=1)
+    assert program.run(b"abc") == b"ABC"
+    # every run starts on a fresh tape
+    assert program.run(b"xyz") == b"XYZ"
+    assert program.run() == b""
+
+
+def test_opt_levels():
+    for opt_level in (0, 1, 2):
+        assert Program(HELLO_WORLD, opt_level=opt_level).run() == b"Hello World!\n"
+    # a loop at the very start never runs, the optimizer drops it
+    assert Program("[-]", opt_level=2).instruction_count < Program("[-]", opt_level=0).instruction_count
+    # only level 2 unrolls loops going round a known number of times,
+    # whose bodies then fold together
+    unrolled = "++[>+<-]>."
+    assert Program(unrolled, opt_level=2).instruction_count < Program(unrolled, opt_level=1).instruction_count
+    assert Program(unrolled, opt_level=1).run() == Program(unrolled, opt_level=2).run() == b"\x02"
+    with pytest.raises(ValueError):
+        Program(HELLO_WORLD, opt_level=3)
+
+
+def test_dump():
+    assert Program("+[>]").dump().splitlines()[2] == "    2: IncPtr(1)"
+
+
+def test_compile_error():
+    with pytest.raises(ValueError, match="unclosed '\\[' at byte 1"):
+        Program("+[")
+    with pytest.raises(ValueError, match="unmatched '\\]' at byte 0"):
+        Program("]")
+
+
+def test_runtime_error():
+    with pytest.raises(RuntimeError):
+        Program("+[>+]", tape_size=4).run()
+
+
+def test_threads():
+    # runs release the GIL, so they don't have to take turns
+    program = Program(UPPER, tape_size=1)
+    results = [None] * 4
+
+    def run(i):
+        results[i] = program.run(b"thread")
+
+    threads = [threading.Thread(target=run, args=(i,)) for i in range(4)]
+    for thread in threads:
+        thread.start()
+    for thread in threads:
+        thread.join()
+    assert results == [b"THREAD"] * 4
@@ -0,0 +1,192 @@
+use std::io;
+use std::ptr;
+use std::sync::{Arc, Mutex, RwLock};
+
+use libc;
+use mmap::{MapOption, MemoryMap};
+
+use brainfuck::CompileError;
+
+
+// Programs start at multiples of this, the alignment x86 prefers for branch targets
+const ALIGN: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protection {
+    // The whole region stays writable and executable, installing is just a copy
+    ReadWriteExecute,
+    // Pages are never writable and executable at once: installing makes the
+    // pages it touches writable, copies and makes them executable again
+    WriteXorExecute,
+}
+
+// A region of executable memory that many compiled programs share, instead
+// of every program mapping pages of its own. Programs are placed one after
+// the other and only freed all at once, by `reset` or when the arena and
+// every program in it are dropped.
+pub struct JitArena {
+    region: Arc<Region>,
+}
+
+struct Region {
+    mapping: MemoryMap,
+    protection: Protection,
+    used: Mutex<usize>,
+    // Held for reading while code runs. With `WriteXorExecute`, installing
+    // takes it for writing, as the pages it touches may hold running code.
+    running: RwLock<()>,
+}
+
+// The mapping is only written through `install`, behind the locks
+unsafe impl Send for Region {}
+unsafe impl Sync for Region {}
+
+// Code installed in an arena, keeping the arena alive
+pub struct ArenaCode {
+    region: Arc<Region>,
+    offset: usize,
+    len: usize,
+}
+
+fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+fn protect(base: *mut u8, len: usize, prot: libc::c_int) -> io::Result<()> {
+    if unsafe { libc::mprotect(base as *mut libc::c_void, len, prot) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+impl JitArena {
+    // Reserves `capacity` bytes, rounded up to whole pages
+    pub fn new(capacity: usize, protection: Protection) -> io::Result<JitArena> {
+        let page = page_size();
+        let capacity = capacity.max(1).div_ceil(page) * page;
+        let options = match protection {
+            Protection::ReadWriteExecute => &[
+                MapOption::MapReadable, MapOption::MapWritable, MapOption::MapExecutable
+            ][..],
+            Protection::WriteXorExecute => &[MapOption::MapReadable][..],
+        };
+        let mapping = MemoryMap::new(capacity, options)
+            .map_err(|e| io::Error::other(e.to_string()))?;
+
+        Ok(JitArena {
+            region: Arc::new(Region {
+                mapping,
+                protection,
+                used: Mutex::new(0),
+                running: RwLock::new(()),
+            }),
+        })
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.region.mapping.len()
+    }
+
+    pub fn used(&self) -> usize {
+        *self.region.used.lock().unwrap()
+    }
+
+    pub fn protection(&self) -> Protection {
+        self.region.protection
+    }
+
+    // Copies `code` to the next free, aligned spot
+    pub fn install(&self, code: &[u8]) -> Result<ArenaCode, CompileError> {
+        let region = &self.region;
+        let mut used = region.used.lock().unwrap();
+
+        let offset = used.div_ceil(ALIGN) * ALIGN;
+        let available = region.mapping.len().saturating_sub(offset);
+        if code.len() > available {
+            return Err(CompileError::ArenaFull { size: code.len(), available });
+        }
+
+        let base = region.mapping.data();
+        match region.protection {
+            Protection::ReadWriteExecute => unsafe {
+                ptr::copy_nonoverlapping(code.as_ptr(), base.add(offset), code.len());
+            },
+            Protection::WriteXorExecute => {
+                let _exclusive = region.running.write().unwrap();
+                let page = page_size();
+                let start = offset / page * page;
+                let end = (offset + code.len().max(1)).div_ceil(page) * page;
+                let pages = unsafe { base.add(start) };
+
+                protect(pages, end - start, libc::PROT_READ | libc::PROT_WRITE)?;
+                unsafe {
+                    ptr::copy_nonoverlapping(code.as_ptr(), base.add(offset), code.len());
+                }
+                protect(pages, end - start, libc::PROT_READ | libc::PROT_EXEC)?;
+            }
+        }
+        *used = offset + code.len();
+
+        Ok(ArenaCode { region: region.clone(), offset, len: code.len() })
+    }
+
+    // Frees every program at once, which is only possible once none of them
+    // is alive anymore. Returns whether the arena was reset.
+    pub fn reset(&mut self) -> bool {
+        let region = match Arc::get_mut(&mut self.region) {
+            Some(region) => region,
+            None => return false,
+        };
+        if region.protection == Protection::WriteXorExecute {
+            let (base, len) = (region.mapping.data(), region.mapping.len());
+            if protect(base, len, libc::PROT_READ).is_err() {
+                return false;
+            }
+        }
+        *region.used.get_mut().unwrap() = 0;
+
+        true
+    }
+}
+
+impl ArenaCode {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    // Calls `f` with the address of the code, which stays executable until
+    // `f` returns
+    pub fn with_code<R, F: FnOnce(*const u8) -> R>(&self, f: F) -> R {
+        let _running = self.region.running.read().unwrap();
+        f(unsafe { self.region.mapping.data().add(self.offset) })
+    }
+}
+
+
+#[test]
+fn test_arena_install() {
+    let mut arena = JitArena::new(1, Protection::WriteXorExecute).unwrap();
+    assert_eq!(arena.capacity(), page_size());
+
+    let a = arena.install(&[1, 2, 3]).unwrap();
+    let b = arena.install(&[4, 5]).unwrap();
+    assert_eq!((a.offset, b.offset), (0, ALIGN));
+    assert_eq!(arena.used(), ALIGN + 2);
+    b.with_code(|code| assert_eq!(unsafe { *code.add(1) }, 5));
+
+    let available = page_size() - 2 * ALIGN;
+    match arena.install(&vec![0; available + 1]) {
+        Err(CompileError::ArenaFull { size, available: left }) => {
+            assert_eq!((size, left), (available + 1, available));
+        }
+        _ => panic!("expected the arena to be full"),
+    }
+    // installs exactly up to the end of the region
+    arena.install(&vec![0; available]).unwrap();
+
+    assert!(!arena.reset());
+    drop((a, b));
+    assert!(arena.reset());
+    assert_eq!(arena.used(), 0);
+    assert_eq!(arena.install(&[6]).unwrap().offset, 0);
+}
@@ -0,0 +1,196 @@
+// Programs as a tree of loops instead of a flat instruction stream with
+// jumps, for analyses that walk loops rather than follow jump targets.
+// A tree only holds what the brackets say, `lower` links the jumps again.
+use std::mem;
+
+use brainfuck::{relink, ExtOp, Inst};
+
+
+// Any instruction but a jump
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SimpleOp {
+    IncPtr(usize),
+    DecPtr(usize),
+    IncVal(usize),
+    DecVal(usize),
+    PrintCell,
+    ReadChar,
+    Ext(ExtOp),
+    HostCall,
+}
+
+impl SimpleOp {
+    pub fn to_inst(self) -> Inst {
+        match self {
+            SimpleOp::IncPtr(a) => Inst::IncPtr(a),
+            SimpleOp::DecPtr(a) => Inst::DecPtr(a),
+            SimpleOp::IncVal(a) => Inst::IncVal(a),
+            SimpleOp::DecVal(a) => Inst::DecVal(a),
+            SimpleOp::PrintCell => Inst::PrintCell,
+            SimpleOp::ReadChar => Inst::ReadChar,
+            SimpleOp::Ext(op) => Inst::Ext(op),
+            SimpleOp::HostCall => Inst::HostCall,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node {
+    // instructions between brackets, never empty, two runs never follow
+    // each other
+    Run(Vec<SimpleOp>),
+    Loop(Vec<Node>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Ast {
+    pub nodes: Vec<Node>,
+}
+
+impl Ast {
+    // The tree of `insts`, whose brackets have to balance as for `relink`.
+    // Jump targets are ignored.
+    pub fn from_insts(insts: &[Inst]) -> Ast {
+        // the loops entered so far, innermost last, below the top level
+        let mut stack: Vec<Vec<Node>> = vec![Vec::new()];
+
+        for inst in insts {
+            let op = match *inst {
+                Inst::JmpFwd(_) => {
+                    stack.push(Vec::new());
+                    continue;
+                }
+                Inst::JmpBack(_) => {
+                    let body = stack.pop().unwrap();
+                    stack.last_mut().expect("unbalanced loops").push(Node::Loop(body));
+                    continue;
+                }
+                Inst::IncPtr(a) => SimpleOp::IncPtr(a),
+                Inst::DecPtr(a) => SimpleOp::DecPtr(a),
+                Inst::IncVal(a) => SimpleOp::IncVal(a),
+                Inst::DecVal(a) => SimpleOp::DecVal(a),
+                Inst::PrintCell => SimpleOp::PrintCell,
+                Inst::ReadChar => SimpleOp::ReadChar,
+                Inst::Ext(op) => SimpleOp::Ext(op),
+                Inst::HostCall => SimpleOp::HostCall,
+            };
+            let nodes = stack.last_mut().unwrap();
+            match nodes.last_mut() {
+                Some(&mut Node::Run(ref mut ops)) => ops.push(op),
+                _ => nodes.push(Node::Run(vec![op])),
+            }
+        }
+
+        assert!(stack.len() == 1, "unbalanced loops");
+        Ast { nodes: stack.pop().unwrap() }
+    }
+
+    // The instructions of the tree, with their jumps linked
+    pub fn lower(&self) -> Vec<Inst> {
+        let mut insts = Vec::new();
+        // the nodes left in every loop entered, innermost last
+        let mut stack = vec![self.nodes.iter()];
+
+        while let Some(nodes) = stack.last_mut() {
+            match nodes.next() {
+                Some(Node::Run(ops)) => insts.extend(ops.iter().map(|op| op.to_inst())),
+                Some(Node::Loop(body)) => {
+                    insts.push(Inst::JmpFwd(0));
+                    stack.push(body.iter());
+                }
+                None => {
+                    stack.pop();
+                    if !stack.is_empty() {
+                        insts.push(Inst::JmpBack(0));
+                    }
+                }
+            }
+        }
+
+        relink(&mut insts);
+        insts
+    }
+
+    // Calls `f` with the body of every loop, outer loops before the ones
+    // nested in them, which are the body as `f` left it
+    pub fn for_each_loop_mut<F: FnMut(&mut Vec<Node>)>(&mut self, mut f: F) {
+        let mut pending = vec![&mut self.nodes];
+        while let Some(nodes) = pending.pop() {
+            for node in nodes.iter_mut() {
+                if let Node::Loop(ref mut body) = *node {
+                    f(body);
+                    pending.push(body);
+                }
+            }
+        }
+    }
+}
+
+// Programs nest deeper than there's stack for dropping them recursively
+impl Drop for Ast {
+    fn drop(&mut self) {
+        let mut nodes = mem::take(&mut self.nodes);
+        while let Some(node) = nodes.pop() {
+            if let Node::Loop(body) = node {
+                nodes.extend(body);
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+use brainfuck::parse;
+#[cfg(test)]
+use optimize::optimize;
+
+#[test]
+fn test_from_insts() {
+    let ast = Ast::from_insts(&parse("+[>[-]<.][,]").unwrap());
+    assert_eq!(ast.nodes, [
+        Node::Run(vec![SimpleOp::IncVal(1)]),
+        Node::Loop(vec![
+            Node::Run(vec![SimpleOp::IncPtr(1)]),
+            Node::Loop(vec![Node::Run(vec![SimpleOp::DecVal(1)])]),
+            Node::Run(vec![SimpleOp::DecPtr(1), SimpleOp::PrintCell]),
+        ]),
+        Node::Loop(vec![Node::Run(vec![SimpleOp::ReadChar])]),
+    ]);
+    assert_eq!(Ast::from_insts(&parse("[]").unwrap()).nodes, [Node::Loop(vec![])]);
+    assert!(Ast::from_insts(&[]).nodes.is_empty());
+}
+
+#[test]
+fn test_round_trip() {
+    let sources = [
+        include_str!("../tests/programs/hello.b"),
+        include_str!("../tests/programs/rot13.b"),
+        include_str!("../tests/programs/sierpinski.b"),
+        include_str!("../tests/programs/squares.b"),
+        include_str!("../tests/fixtures/branch.b"),
+        include_str!("../tests/fixtures/livelock.b"),
+        include_str!("../tests/fixtures/unoptimized.b"),
+        "[][[]]+[[-]>[<]]",
+    ];
+    for source in &sources {
+        let insts = parse(source).unwrap();
+        for insts in &[insts.clone(), optimize(insts)] {
+            let ast = Ast::from_insts(insts);
+            assert_eq!(&ast.lower(), insts);
+            assert_eq!(Ast::from_insts(&ast.lower()), ast);
+        }
+    }
+}
+
+#[test]
+fn test_deep_nesting() {
+    // neither building, lowering nor dropping the tree recurses
+    let depth = 1_000_000;
+    let source = "[".repeat(depth) + "+" + &"]".repeat(depth);
+    let insts = parse(&source).unwrap();
+    let mut ast = Ast::from_insts(&insts);
+    let mut loops = 0;
+    ast.for_each_loop_mut(|_| loops += 1);
+    assert_eq!(loops, depth);
+    assert!(ast.lower() == insts);
+}
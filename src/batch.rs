@@ -0,0 +1,122 @@
+// Running a program as a filter of lines, for `--batch-lines`: once per
+// line, with the line as all of its input and on a fresh tape each time.
+// The code is mapped once for all lines, see `SharedProgram`, which can
+// run on several lines at once with `--jobs`.
+use std::io::{self, BufRead, Write};
+
+use brainfuck::{RunManyOptions, RuntimeError, SharedProgram};
+
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BatchOptions {
+    // whether a line's input ends with its newline, the last line only has
+    // one if the input ends with one
+    pub keep_newline: bool,
+    // whether the lines after one that failed still run
+    pub keep_going: bool,
+    // how many lines run at once, 0 is taken as 1. With more than one,
+    // lines are read ahead to keep them busy.
+    pub jobs: usize,
+}
+
+// Lines read ahead for every job
+const LINES_PER_JOB: usize = 16;
+
+// Runs `program` on every line of `input`, writing what it printed followed
+// by a newline to `output`, in the order of the lines. A failed run prints
+// nothing, it's handed to `failed` with its line number counted from 1.
+// Returns how many failed.
+pub fn run_lines<R: BufRead, W: Write>(
+    program: &SharedProgram, mut input: R, options: BatchOptions, mut output: W,
+    mut failed: impl FnMut(usize, RuntimeError),
+) -> io::Result<usize> {
+    let jobs = options.jobs.max(1);
+    let chunk = if jobs == 1 { 1 } else { jobs * LINES_PER_JOB };
+    let run_options = RunManyOptions { jobs, timeout: None };
+    let mut lines: Vec<Vec<u8>> = Vec::new();
+    let mut number = 0;
+    let mut failures = 0;
+
+    loop {
+        lines.clear();
+        while lines.len() < chunk {
+            let mut line = Vec::new();
+            if input.read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+            if !options.keep_newline && line.last() == Some(&b'\n') {
+                line.pop();
+            }
+            lines.push(line);
+        }
+        if lines.is_empty() {
+            break;
+        }
+
+        // the lines after one that failed ran as well, they just don't count
+        let inputs: Vec<&[u8]> = lines.iter().map(Vec::as_slice).collect();
+        for result in program.run_many(&inputs, run_options) {
+            number += 1;
+            match result {
+                Ok(printed) => {
+                    output.write_all(&printed)?;
+                    output.write_all(b"\n")?;
+                }
+                Err(e) => {
+                    failures += 1;
+                    failed(number, e);
+                    if !options.keep_going {
+                        output.flush()?;
+                        return Ok(failures);
+                    }
+                }
+            }
+        }
+    }
+    output.flush()?;
+
+    Ok(failures)
+}
+
+
+#[cfg(test)]
+use brainfuck::Brainfuck;
+
+#[test]
+fn test_run_lines() {
+    // prints its input backwards, lines of more than 2 bytes don't fit
+    let mut bf = Brainfuck::new(">,[>,]<[.<]").unwrap();
+    bf.set_tape_size(4).unwrap();
+    let program = bf.share().unwrap();
+    let run = |input: &[u8], options| {
+        let mut output = Vec::new();
+        let mut errors = Vec::new();
+        let failures = run_lines(&program, input, options, &mut output, |line, e| errors.push((line, e))).unwrap();
+        assert_eq!(failures, errors.len());
+        (String::from_utf8(output).unwrap(), errors.into_iter().map(|(line, _)| line).collect::<Vec<_>>())
+    };
+
+    let options = BatchOptions::default();
+    assert_eq!(run(b"ab\n\nxy", options), ("ba\n\nyx\n".to_string(), vec![]));
+    assert_eq!(run(b"", options), (String::new(), vec![]));
+    assert_eq!(run(b"ab\nabc\ncd\n", options), ("ba\n".to_string(), vec![2]));
+
+    let options = BatchOptions { keep_going: true, ..options };
+    assert_eq!(run(b"ab\nabc\ncd\nxyz", options), ("ba\ndc\n".to_string(), vec![2, 4]));
+
+    let options = BatchOptions { keep_newline: true, ..BatchOptions::default() };
+    assert_eq!(run(b"a\nb", options), ("\na\nb\n".to_string(), vec![]));
+    assert_eq!(run(b"ab\n", options), (String::new(), vec![1]));
+
+    // lines in parallel still come out in order, more of them than are read
+    // ahead at once
+    let input: String = (0..100).map(|i| format!("{}\n", &"abc"[..i % 4])).collect();
+    let sequential = run(input.as_bytes(), BatchOptions { keep_going: true, ..BatchOptions::default() });
+    let parallel = run(input.as_bytes(), BatchOptions { keep_going: true, jobs: 4, ..BatchOptions::default() });
+    assert_eq!(parallel, sequential);
+    assert_eq!(sequential.1.len(), 25);
+    let input = "ab\n".repeat(99) + "abc\nab\n";
+    let (output, failed) = run(input.as_bytes(), BatchOptions { jobs: 2, ..BatchOptions::default() });
+    assert_eq!(output, "ba\n".repeat(99));
+    assert_eq!(failed, [100]);
+}
@@ -0,0 +1,265 @@
+// Cells holding any non-negative integer, for trying out algorithms without
+// their values wrapping around. Only the interpreter here runs programs with
+// them, `ArithMode::Unbounded` is refused by the JIT. `.` prints the cell
+// modulo 256 and `,` stores the byte read, leaving the cell as it was at EOF
+// like everywhere else.
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use num_bigint::BigUint;
+use num_traits::Zero;
+
+use brainfuck::{ExtOp, Inst, RuntimeError};
+use brainfuck::Inst::*;
+
+
+// What `-` does on a cell that is 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Underflow {
+    // The cell stays at 0
+    Saturate,
+    // The run fails with `RuntimeError::CellUnderflow`
+    Trap,
+}
+
+// A cell's value
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BigCell {
+    value: BigUint,
+}
+
+impl BigCell {
+    pub fn is_zero(&self) -> bool {
+        self.value.is_zero()
+    }
+
+    // The value modulo 256, what `.` prints
+    pub fn low_byte(&self) -> u8 {
+        self.value.iter_u32_digits().next().map_or(0, |digit| digit as u8)
+    }
+
+    pub fn add(&mut self, amount: usize) {
+        self.value += amount;
+    }
+
+    // Subtracts `amount`, or leaves the cell as it is and returns false if
+    // that would go below 0
+    pub fn sub(&mut self, amount: usize) -> bool {
+        let amount = BigUint::from(amount);
+        if self.value < amount {
+            return false;
+        }
+        self.value -= amount;
+        true
+    }
+}
+
+impl From<u8> for BigCell {
+    fn from(byte: u8) -> BigCell {
+        BigCell { value: BigUint::from(byte) }
+    }
+}
+
+impl From<u64> for BigCell {
+    fn from(value: u64) -> BigCell {
+        BigCell { value: BigUint::from(value) }
+    }
+}
+
+// In decimal
+impl fmt::Display for BigCell {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.value, f)
+    }
+}
+
+// Runs programs on a tape of `BigCell`s, checking every pointer move like
+// `interp::Interp`
+pub struct BigInterp<'a> {
+    insts: &'a [Inst],
+    tape: Vec<BigCell>,
+    ptr: usize,
+    pc: usize,
+    underflow: Underflow,
+}
+
+impl<'a> BigInterp<'a> {
+    // Panics for the bitwise commands of Extended Type I, which need cells
+    // of a byte
+    pub fn new(insts: &'a [Inst], tape_size: usize, underflow: Underflow) -> BigInterp<'a> {
+        assert!(
+            !insts.iter().any(|inst| matches!(*inst, Ext(op) if op != ExtOp::End)),
+            "Extended Type I needs cells of a byte"
+        );
+        BigInterp { insts, tape: vec![BigCell::default(); tape_size], ptr: 0, pc: 0, underflow }
+    }
+
+    pub fn run<R: Read, W: Write>(&mut self, mut input: R, mut output: W) -> Result<(), RuntimeError> {
+        let tape = &mut self.tape;
+        while let Some(inst) = self.insts.get(self.pc) {
+            let cell = &mut tape[self.ptr];
+            match *inst {
+                IncPtr(a) => {
+                    if a >= tape.len() - self.ptr {
+                        return Err(RuntimeError::PointerOverflow { inst_index: self.pc });
+                    }
+                    self.ptr += a;
+                }
+                DecPtr(a) => {
+                    if a > self.ptr {
+                        return Err(RuntimeError::PointerUnderflow { inst_index: self.pc });
+                    }
+                    self.ptr -= a;
+                }
+                IncVal(a) => cell.add(a),
+                DecVal(a) => {
+                    if !cell.sub(a) {
+                        match self.underflow {
+                            Underflow::Saturate => *cell = BigCell::default(),
+                            Underflow::Trap => return Err(RuntimeError::CellUnderflow { inst_index: self.pc }),
+                        }
+                    }
+                }
+                PrintCell => output.write_all(&[cell.low_byte()])?,
+                ReadChar => {
+                    let mut byte = [0];
+                    loop {
+                        match input.read(&mut byte) {
+                            Ok(0) => break,
+                            Ok(_) => {
+                                *cell = BigCell::from(byte[0]);
+                                break;
+                            }
+                            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                            Err(e) => return Err(e.into()),
+                        }
+                    }
+                }
+                JmpFwd(n) => {
+                    if cell.is_zero() {
+                        self.pc = n;
+                    }
+                }
+                JmpBack(n) => {
+                    if !cell.is_zero() {
+                        self.pc = n;
+                    }
+                }
+                Ext(ExtOp::End) => {
+                    self.pc = self.insts.len();
+                    break;
+                }
+                Ext(_) => unreachable!(),
+                // there are no host functions for cells this large
+                HostCall => {
+                    return Err(RuntimeError::UnregisteredHostCall { id: cell.low_byte(), inst_index: self.pc });
+                }
+            }
+            self.pc += 1;
+        }
+
+        Ok(())
+    }
+
+    pub fn tape(&self) -> &[BigCell] {
+        &self.tape
+    }
+
+    pub fn tape_mut(&mut self) -> &mut [BigCell] {
+        &mut self.tape
+    }
+
+    pub fn ptr(&self) -> usize {
+        self.ptr
+    }
+
+    pub fn set_ptr(&mut self, ptr: usize) {
+        assert!(ptr < self.tape.len(), "pointer {} is past the tape", ptr);
+        self.ptr = ptr;
+    }
+}
+
+
+#[cfg(test)]
+use brainfuck::{parse, ArithMode, Brainfuck, CompileError};
+#[cfg(test)]
+use optimize::optimize_for;
+
+#[test]
+fn test_big_cell() {
+    let mut cell = BigCell::from(u64::MAX);
+    assert_eq!(cell.to_string(), "18446744073709551615");
+    cell.add(1);
+    assert_eq!(cell.to_string(), "18446744073709551616");
+    assert_eq!(cell.low_byte(), 0);
+    cell.add(0x41);
+    assert_eq!(cell.low_byte(), b'A');
+    assert!(cell.sub(0x42));
+    assert_eq!(cell, BigCell::from(u64::MAX));
+    assert!(cell.sub(usize::MAX));
+    assert!(cell.is_zero());
+    assert!(!cell.sub(1));
+
+    // past 64 bits
+    let mut cell = BigCell { value: BigUint::new(vec![0, 0, 1]) };
+    assert_eq!(cell.to_string(), "18446744073709551616");
+    assert!(cell.sub(1));
+    assert_eq!(cell, BigCell::from(u64::MAX));
+    let mut cell = BigCell { value: BigUint::new(vec![5, 0, 0, 1]) };
+    assert_eq!(cell.to_string(), "79228162514264337593543950341");
+    assert!(cell.sub(6));
+    assert_eq!(cell.value.to_u32_digits(), [u32::MAX, u32::MAX, u32::MAX]);
+    assert_eq!(cell.low_byte(), 0xff);
+    cell.add(1);
+    assert_eq!(cell.value.to_u32_digits(), [0, 0, 0, 1]);
+    assert!(cell.sub(usize::MAX));
+    assert!(!cell.is_zero());
+    assert_eq!(format!("{:>5}", BigCell::default()), "    0");
+}
+
+#[test]
+fn test_big_interp() {
+    let mut fact = String::from(">+");
+    for k in 2..=9 {
+        fact.push_str(&format!("[->{}<]>[-<+>]<", "+".repeat(k)));
+    }
+    // dividing by 10 and leaving the digits behind, then printing them from
+    // the last one back, without any cell going below 0
+    let print = format!(
+        "[>++++++++++<{}>[-]>{}>]<[.<<<]",
+        "[->>+<->>>+<<<[[->>>>+<<<<]>>>-<<<]>>>>[-<<<<+>>>>]<<<<>>>[-<<<++++++++++>[-]>+>]<<<<]",
+        "+".repeat(48)
+    );
+    let insts = parse(&(fact + &print)).unwrap();
+    let mut output = Vec::new();
+    BigInterp::new(&insts, 100, Underflow::Trap).run(&b""[..], &mut output).unwrap();
+    assert_eq!(output, b"362880");
+
+    // past what any fixed width holds, set up directly as counting there
+    // takes too long
+    let insts = parse(".-.").unwrap();
+    let mut interp = BigInterp::new(&insts, 1, Underflow::Trap);
+    interp.tape_mut()[0] = BigCell::from(u64::MAX);
+    interp.tape_mut()[0].add(0x42);
+    let mut output = Vec::new();
+    interp.run(&b""[..], &mut output).unwrap();
+    assert_eq!(output, b"A@");
+    assert_eq!(interp.tape()[0].to_string(), "18446744073709551680");
+
+    let insts = parse("+--+,.,.").unwrap();
+    let mut output = Vec::new();
+    let mut interp = BigInterp::new(&insts, 1, Underflow::Saturate);
+    interp.run(&b"x"[..], &mut output).unwrap();
+    assert_eq!(output, b"xx");
+    let result = BigInterp::new(&insts, 1, Underflow::Trap).run(&b""[..], io::sink());
+    assert!(matches!(result, Err(RuntimeError::CellUnderflow { inst_index: 1 })));
+
+    // nothing else runs them, or folds what only folds with wrapping cells
+    let mut bf = Brainfuck::new("+-").unwrap();
+    let err = bf.set_arith_mode(ArithMode::Unbounded(Underflow::Trap)).unwrap_err();
+    assert!(matches!(err, CompileError::UnsupportedArithMode(ArithMode::Unbounded(Underflow::Trap))));
+    assert_eq!(err.to_string(), "the JIT doesn't support Unbounded(Trap) cells, only the interpreter does");
+    assert_eq!(bf.arith_mode(), ArithMode::Wrap);
+    let insts = optimize_for(parse("+-[-]>-+").unwrap(), ArithMode::Unbounded(Underflow::Saturate));
+    assert_eq!(insts, parse("+-[-]>-+").unwrap());
+}
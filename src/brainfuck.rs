@@ -0,0 +1,5911 @@
+use std::{fmt, io};
+#[cfg(not(target_family = "wasm"))]
+use std::{mem, thread};
+#[cfg(all(test, not(target_family = "wasm")))]
+use std::ptr;
+use std::io::{Read, Write, Cursor, Seek, SeekFrom};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+#[cfg(not(target_family = "wasm"))]
+use std::collections::HashMap;
+#[cfg(not(target_family = "wasm"))]
+use std::collections::hash_map::Entry;
+use std::convert::TryFrom;
+use std::fs::{File, OpenOptions};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+#[cfg(not(target_family = "wasm"))]
+use std::path::Path;
+#[cfg(not(target_family = "wasm"))]
+use std::os::unix::io::{AsRawFd, RawFd};
+#[cfg(target_os = "linux")]
+use std::convert::Infallible;
+use std::sync::OnceLock;
+#[cfg(not(target_family = "wasm"))]
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+#[cfg(not(target_family = "wasm"))]
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(not(target_family = "wasm"))]
+use std::sync::mpsc::{self, RecvTimeoutError};
+#[cfg(not(target_family = "wasm"))]
+use std::time::Duration;
+use self::Inst::*;
+#[cfg(not(target_family = "wasm"))]
+use libc;
+#[cfg(not(target_family = "wasm"))]
+use arena::{ArenaCode, JitArena, Protection};
+use ast::Ast;
+#[cfg(feature = "bignum")]
+use bignum::Underflow;
+#[cfg(not(target_family = "wasm"))]
+use fault::FaultGuard;
+use host::HostFunctions;
+use interp::{Interp, StepOutcome, Steps};
+use memory::MemoryBudget;
+#[cfg(not(target_family = "wasm"))]
+use perfmap;
+use runlength::RunLengthIterator;
+use runstats::RunStats;
+use tape::Tape;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Inst {
+    IncPtr(usize),
+    DecPtr(usize),
+    IncVal(usize),
+    DecVal(usize),
+    PrintCell,
+    ReadChar,
+    JmpFwd(usize),
+    JmpBack(usize),
+    // A command of a dialect, see `Dialect`
+    Ext(ExtOp),
+    // Calls the host function the cell selects, see `host`
+    HostCall,
+}
+
+// What `to_source` writes for `HostCall`, whatever it was parsed from
+pub const HOST_CALL_COMMAND: char = '%';
+
+// The commands Extended Brainfuck Type I adds, one instruction per
+// character. `$` and `!` go through a storage register of one byte, the
+// bitwise ones combine the cell with what it holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ExtOp {
+    // `@`, the program ends right there
+    End,
+    // `$`, the cell goes into storage
+    Store,
+    // `!`, storage goes into the cell
+    Retrieve,
+    // `}` and `{`, the cell shifted by a bit
+    ShiftRight,
+    ShiftLeft,
+    // `~`, `^`, `&` and `|`
+    Not,
+    Xor,
+    And,
+    Or,
+}
+
+impl ExtOp {
+    pub const ALL: [ExtOp; 9] = [
+        ExtOp::End, ExtOp::Store, ExtOp::Retrieve, ExtOp::ShiftRight, ExtOp::ShiftLeft,
+        ExtOp::Not, ExtOp::Xor, ExtOp::And, ExtOp::Or,
+    ];
+
+    pub fn command(self) -> char {
+        match self {
+            ExtOp::End => '@',
+            ExtOp::Store => '$',
+            ExtOp::Retrieve => '!',
+            ExtOp::ShiftRight => '}',
+            ExtOp::ShiftLeft => '{',
+            ExtOp::Not => '~',
+            ExtOp::Xor => '^',
+            ExtOp::And => '&',
+            ExtOp::Or => '|',
+        }
+    }
+
+    pub fn from_command(c: char) -> Option<ExtOp> {
+        ExtOp::ALL.iter().cloned().find(|op| op.command() == c)
+    }
+
+    // What `--dump` and the IR call it
+    pub fn name(self) -> &'static str {
+        match self {
+            ExtOp::End => "end",
+            ExtOp::Store => "store",
+            ExtOp::Retrieve => "retrieve",
+            ExtOp::ShiftRight => "shr",
+            ExtOp::ShiftLeft => "shl",
+            ExtOp::Not => "not",
+            ExtOp::Xor => "xor",
+            ExtOp::And => "and",
+            ExtOp::Or => "or",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ExtOp> {
+        ExtOp::ALL.iter().cloned().find(|op| op.name() == name)
+    }
+
+    // Executes anything but `End` on `cell` and the storage register
+    pub fn apply(self, cell: &mut u8, storage: &mut u8) {
+        match self {
+            ExtOp::End => panic!("`@` doesn't change cells"),
+            ExtOp::Store => *storage = *cell,
+            ExtOp::Retrieve => *cell = *storage,
+            ExtOp::ShiftRight => *cell >>= 1,
+            ExtOp::ShiftLeft => *cell <<= 1,
+            ExtOp::Not => *cell = !*cell,
+            ExtOp::Xor => *cell ^= *storage,
+            ExtOp::And => *cell &= *storage,
+            ExtOp::Or => *cell |= *storage,
+        }
+    }
+}
+
+// The language a source is in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Dialect {
+    #[default]
+    Brainfuck,
+    // Extended Brainfuck Type I, with the commands of `ExtOp`
+    Extended1,
+    // Brainfuck with a command for `HostCall`, usually `HOST_CALL_COMMAND`.
+    // One of brainfuck's own stays what it is.
+    HostCalls(char),
+}
+
+impl Dialect {
+    pub fn is_command(self, c: char) -> bool {
+        is_command(c) || match self {
+            Dialect::Brainfuck => false,
+            Dialect::Extended1 => ExtOp::from_command(c).is_some(),
+            Dialect::HostCalls(command) => c == command,
+        }
+    }
+}
+
+// What `+` and `-` do to a cell at the ends of its range
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ArithMode {
+    // 255 + 1 is 0 and 0 - 1 is 255, as most implementations do it
+    #[default]
+    Wrap,
+    // Cells stay at 255 and 0
+    Saturate,
+    // The run fails with `RuntimeError::CellOverflow` or `CellUnderflow`,
+    // leaving the cell as it was
+    Trap,
+    // Cells hold any non-negative integer, `-` on 0 does what the
+    // `Underflow` says. Only `bignum::BigInterp` runs programs like that.
+    #[cfg(feature = "bignum")]
+    Unbounded(Underflow),
+}
+
+// How the tapes of runs of generated code are allocated. Huge pages only
+// pay off for tapes of many megabytes, smaller ones and every tape where
+// they aren't available go on the heap, see `Brainfuck::last_tape_alloc`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum TapeAlloc {
+    #[default]
+    Default,
+    // Transparent huge pages, a mapping aligned to 2 MiB that the kernel
+    // is advised to back with them
+    Huge,
+    // Pages from the huge page pool, which has to be set up through
+    // vm.nr_hugepages, `Huge` if it can't hand out enough
+    HugeTlb,
+}
+
+// What `Brainfuck::set_tape_file` does about a file that isn't as long as
+// the tape
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum TapeFileSize {
+    // The run fails with `RuntimeError::TapeFileSizeMismatch`
+    #[default]
+    Exact,
+    // The file is cut off after the tape or filled up with zeros to its end
+    Resize,
+}
+
+// When `Brainfuck::run_streaming` hands on what the program printed, as a
+// file redirect would see it written: whenever `STREAM_BUFFER` bytes are
+// together, before every `,` and at the end, and with `Line` also after
+// every newline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Buffering {
+    Full,
+    Line,
+}
+
+impl ArithMode {
+    // The cell after adding `amount`, `None` if that traps
+    pub fn add(self, cell: u8, amount: usize) -> Option<u8> {
+        match self {
+            ArithMode::Wrap => Some(cell.wrapping_add(amount as u8)),
+            ArithMode::Saturate => Some(u8::try_from(amount).map_or(u8::MAX, |a| cell.saturating_add(a))),
+            ArithMode::Trap => amount.checked_add(cell as usize).and_then(|n| u8::try_from(n).ok()),
+            #[cfg(feature = "bignum")]
+            ArithMode::Unbounded(_) => panic!("unbounded cells don't fit into a byte"),
+        }
+    }
+
+    // The cell after subtracting `amount`, `None` if that traps
+    pub fn sub(self, cell: u8, amount: usize) -> Option<u8> {
+        match self {
+            ArithMode::Wrap => Some(cell.wrapping_sub(amount as u8)),
+            ArithMode::Saturate => Some((cell as usize).saturating_sub(amount) as u8),
+            ArithMode::Trap => (cell as usize).checked_sub(amount).map(|n| n as u8),
+            #[cfg(feature = "bignum")]
+            ArithMode::Unbounded(_) => panic!("unbounded cells don't fit into a byte"),
+        }
+    }
+}
+
+fn emit_imm8<T: Write>(mem: &mut T, value: u8) -> io::Result<()> {
+    mem.write_all(&[value])
+}
+
+fn emit_imm32<T: Write>(mem: &mut T, value: u32) -> io::Result<()> {
+    mem.write_all(&value.to_le_bytes())
+}
+
+fn emit_inc<T: Write>(mem: &mut T, amount: usize) -> io::Result<()> {
+    if amount == 1 {
+        mem.write_all(&[
+            0x48, 0xff, 0xc6, // inc rsi
+        ])
+    } else {
+        mem.write_all(&[
+            0x48, 0x81, 0xc6, // add rsi, imm32
+        ])?;
+        emit_imm32(mem, amount as u32)
+    }
+}
+
+fn emit_dec<T: Write>(mem: &mut T, amount: usize) -> io::Result<()> {
+    if amount == 1 {
+        mem.write_all(&[
+            0x48, 0xff, 0xce, // dec rsi
+        ])
+    } else {
+        mem.write_all(&[
+            0x48, 0x81, 0xee, // sub rsi, imm32
+        ])?;
+        emit_imm32(mem, amount as u32)
+    }
+}
+
+fn emit_inc_val<T: Write>(mem: &mut T, amount: usize) -> io::Result<()> {
+    if amount == 1 {
+        mem.write_all(&[
+            0xfe, 0x06, // inc byte [rsi]
+        ])
+    } else {
+        mem.write_all(&[
+            0x80, 0x06, // add byte [rsi], imm8
+        ])?;
+        emit_imm8(mem, (amount & 0xff) as u8)
+    }
+}
+
+fn emit_dec_val<T: Write>(mem: &mut T, amount: usize) -> io::Result<()> {
+    if amount == 1 {
+        mem.write_all(&[
+            0xfe, 0x0e, // dec byte [rsi]
+        ])
+    } else {
+        mem.write_all(&[
+            0x80, 0x2e, // sub byte [rsi], imm8
+        ])?;
+        emit_imm8(mem, (amount & 0xff) as u8)
+    }
+}
+
+// `+` and `-` outside of `ArithMode::Wrap` work on the cell in eax, where
+// the result can leave the cell's range before it's stored back
+fn emit_load_cell<T: Write>(mem: &mut T) -> io::Result<()> {
+    mem.write_all(&[
+        0x0f, 0xb6, 0x06, // movzx eax, byte [rsi]
+    ])
+}
+
+fn emit_store_cell<T: Write>(mem: &mut T) -> io::Result<()> {
+    mem.write_all(&[
+        0x88, 0x06, // mov [rsi], al
+    ])
+}
+
+// `+` and `-` on the cell in al, while cells wrap
+fn emit_add_al<T: Write>(mem: &mut T, amount: usize) -> io::Result<()> {
+    mem.write_all(&[
+        0x04, amount as u8, // add al, imm8
+    ])
+}
+
+fn emit_sub_al<T: Write>(mem: &mut T, amount: usize) -> io::Result<()> {
+    mem.write_all(&[
+        0x2c, amount as u8, // sub al, imm8
+    ])
+}
+
+// Any amount past 255 takes every cell out of range, 256 does as well as
+// a larger one and keeps eax from wrapping
+fn cell_amount(amount: usize) -> u32 {
+    amount.min(256) as u32
+}
+
+fn emit_add_cell<T: Write>(mem: &mut T, amount: usize) -> io::Result<()> {
+    mem.write_all(&[
+        0x05, // add eax, imm32
+    ])?;
+    emit_imm32(mem, cell_amount(amount))
+}
+
+fn emit_sub_cell<T: Write>(mem: &mut T, amount: usize) -> io::Result<()> {
+    mem.write_all(&[
+        0x2d, // sub eax, imm32
+    ])?;
+    emit_imm32(mem, cell_amount(amount))
+}
+
+// Caps eax at 255 after `emit_add_cell`
+fn emit_saturate_high<T: Write>(mem: &mut T) -> io::Result<()> {
+    mem.write_all(&[
+        0xb9, 0xff, 0x00, 0x00, 0x00, // mov ecx, 0xff
+        0x39, 0xc8, // cmp eax, ecx
+        0x0f, 0x47, 0xc1, // cmova eax, ecx
+    ])
+}
+
+// Raises eax to 0 after `emit_sub_cell`, going by its borrow
+fn emit_saturate_low<T: Write>(mem: &mut T) -> io::Result<()> {
+    mem.write_all(&[
+        0xb9, 0x00, 0x00, 0x00, 0x00, // mov ecx, 0
+        0x0f, 0x42, 0xc1, // cmovb eax, ecx
+    ])
+}
+
+// Checks eax after `emit_add_cell` or `emit_sub_cell`, a result below 0 is
+// above 255 unsigned. `offset` is the distance to the stub returning
+// `STATUS_CELL_OVERFLOW` or `STATUS_CELL_UNDERFLOW`.
+fn emit_check_cell<T: Write>(mem: &mut T, offset: isize) -> io::Result<()> {
+    mem.write_all(&[
+        0x3d, 0xff, 0x00, 0x00, 0x00, // cmp eax, 0xff
+        0x0f, 0x87 // ja ...
+    ])?;
+    emit_imm32(mem, (offset - CELL_CHECK_SIZE) as i32 as u32)
+}
+
+fn emit_jmp_fwd<T: Write>(mem: &mut T, offset: usize) -> io::Result<()> {
+    mem.write_all(&[
+        0x80, 0x3e, 0x00, // cmp byte [rsi], 0
+        0x0f, 0x84 // je ...
+    ])?;
+    emit_imm32(mem, (offset as i32 - 9) as u32)
+}
+
+fn emit_jmp_back<T: Write>(mem: &mut T, offset: isize) -> io::Result<()> {
+    mem.write_all(&[
+        0x80, 0x3e, 0x00, // cmp byte [rsi], 0
+        0x0f, 0x85 // jne ...
+    ])?;
+    emit_imm32(mem, (offset as i32 - 9) as u32)
+}
+
+fn emit_print<T: Write>(mem: &mut T) -> io::Result<()> {
+    emit_io_syscall(mem, &[
+        0xb8, 0x01, 0x00, 0x00, 0x00, // mov eax, 1
+        0x41, 0x8b, 0x79, FRAME_OUTPUT, // mov edi, [r9 + FRAME_OUTPUT]
+        0xba, 0x01, 0x00, 0x00, 0x00, // mov edx, 1
+    ], Syscalls::Linux, true)
+}
+
+fn emit_read<T: Write>(mem: &mut T) -> io::Result<()> {
+    emit_io_syscall(mem, &[
+        0x48, 0x31, 0xc0, // xor rax, rax
+        0x41, 0x8b, 0x79, FRAME_INPUT, // mov edi, [r9 + FRAME_INPUT]
+        0xba, 0x01, 0x00, 0x00, 0x00, // mov edx, 1
+    ], Syscalls::Linux, false)
+}
+
+// The same on macOS, where the BSD calls are numbered from 0x2000000
+fn emit_darwin_print<T: Write>(mem: &mut T) -> io::Result<()> {
+    emit_io_syscall(mem, &[
+        0xb8, 0x04, 0x00, 0x00, 0x02, // mov eax, 0x2000004
+        0x41, 0x8b, 0x79, FRAME_OUTPUT, // mov edi, [r9 + FRAME_OUTPUT]
+        0xba, 0x01, 0x00, 0x00, 0x00, // mov edx, 1
+    ], Syscalls::Darwin, true)
+}
+
+fn emit_darwin_read<T: Write>(mem: &mut T) -> io::Result<()> {
+    emit_io_syscall(mem, &[
+        0xb8, 0x03, 0x00, 0x00, 0x02, // mov eax, 0x2000003
+        0x41, 0x8b, 0x79, FRAME_INPUT, // mov edi, [r9 + FRAME_INPUT]
+        0xba, 0x01, 0x00, 0x00, 0x00, // mov edx, 1
+    ], Syscalls::Darwin, false)
+}
+
+// `setup` for the system call of `.` or `,` followed by the call, made
+// again for as long as it's interrupted or would block and, for a write,
+// writes nothing. Leaves the result in rax, an error as the negated errno
+// also on macOS, where the carry flag tells them apart, and tested for
+// `emit_check_io`.
+fn emit_io_syscall<T: Write>(mem: &mut T, setup: &[u8], syscalls: Syscalls, write: bool) -> io::Result<()> {
+    let (eintr, eagain) = match syscalls {
+        Syscalls::Darwin => (4, 35),
+        _ => (4, 11),
+    };
+    mem.write_all(setup)?;
+    mem.write_all(&[
+        0x0f, 0x05, // syscall
+    ])?;
+    // how much of it there is so far, which a retry jumps back over
+    let mut len = setup.len() + 2;
+    if syscalls == Syscalls::Darwin {
+        mem.write_all(&[
+            0x73, 0x03, // jnc done
+            0x48, 0xf7, 0xd8, // neg rax
+            // done:
+        ])?;
+        len += 5;
+    }
+    let retry = |mem: &mut T, len: &mut usize| {
+        *len += 2;
+        mem.write_all(&[
+            0x74, -(*len as isize) as i8 as u8, // je retry
+        ])
+    };
+    for errno in [eintr, eagain] {
+        mem.write_all(&[
+            0x48, 0x83, 0xf8, (-errno) as i8 as u8, // cmp rax, -errno
+        ])?;
+        len += 4;
+        retry(mem, &mut len)?;
+    }
+    mem.write_all(&[
+        0x48, 0x85, 0xc0, // test rax, rax
+    ])?;
+    len += 3;
+    if write {
+        retry(mem, &mut len)?;
+    }
+    Ok(())
+}
+
+// Checks the result `emit_io_syscall` tested, `offset` is the distance to
+// the stub returning `STATUS_IO_FAILED`. The errno goes into the frame.
+fn emit_check_io<T: Write>(mem: &mut T, offset: isize) -> io::Result<()> {
+    mem.write_all(&[
+        0x79, 0x0b, // jns done
+        0xf7, 0xd8, // neg eax
+        0x41, 0x89, 0x41, FRAME_IO_ERROR, // mov [r9 + FRAME_IO_ERROR], eax
+        0xe9, // jmp ...
+    ])?;
+    emit_imm32(mem, (offset - IO_CHECK_SIZE) as i32 as u32)
+}
+
+// Windows has no system calls to make, the code of `,` and `.` calls the
+// routines a PE executable puts in its frame, see `pe`, with the stack
+// aligned as for `emit_host_call` and room for them to spill arguments.
+fn emit_windows_call<T: Write>(mem: &mut T, routine: u8) -> io::Result<()> {
+    mem.write_all(&[
+        0x41, 0x51, // push r9
+        0x56, // push rsi
+        0x48, 0x83, 0xec, 0x28, // sub rsp, 40
+        0x41, 0xff, 0x51, routine, // call [r9 + routine]
+        0x48, 0x83, 0xc4, 0x28, // add rsp, 40
+        0x5e, // pop rsi
+        0x41, 0x59, // pop r9
+    ])
+}
+
+// Extended Type I's storage register is kept in the frame, `$` and `!`
+// copy between it and the cell through al
+fn emit_store<T: Write>(mem: &mut T) -> io::Result<()> {
+    mem.write_all(&[
+        0x8a, 0x06, // mov al, [rsi]
+        0x41, 0x88, 0x41, FRAME_STORAGE, // mov [r9 + FRAME_STORAGE], al
+    ])
+}
+
+fn emit_retrieve<T: Write>(mem: &mut T) -> io::Result<()> {
+    mem.write_all(&[
+        0x41, 0x8a, 0x41, FRAME_STORAGE, // mov al, [r9 + FRAME_STORAGE]
+        0x88, 0x06, // mov [rsi], al
+    ])
+}
+
+// `}`, `{` and `~` in place, `^`, `&` and `|` with storage in al
+fn emit_bit_op<T: Write>(mem: &mut T, op: ExtOp) -> io::Result<()> {
+    match op {
+        ExtOp::ShiftRight => mem.write_all(&[0xd0, 0x2e]), // shr byte [rsi], 1
+        ExtOp::ShiftLeft => mem.write_all(&[0xd0, 0x26]), // shl byte [rsi], 1
+        ExtOp::Not => mem.write_all(&[0xf6, 0x16]), // not byte [rsi]
+        _ => {
+            mem.write_all(&[
+                0x41, 0x8a, 0x41, FRAME_STORAGE, // mov al, [r9 + FRAME_STORAGE]
+            ])?;
+            mem.write_all(&[match op {
+                ExtOp::Xor => 0x30, // xor [rsi], al
+                ExtOp::And => 0x20, // and [rsi], al
+                _ => 0x08, // or [rsi], al
+            }, 0x06])
+        }
+    }
+}
+
+// `%`, a call of `Frame::host_call` with the frame and the pointer. The
+// stack is 16-byte aligned for it after pushing the two registers the
+// callee may clobber and 8 bytes more.
+fn emit_host_call<T: Write>(mem: &mut T) -> io::Result<()> {
+    mem.write_all(&[
+        0x41, 0x51, // push r9
+        0x56, // push rsi
+        0x48, 0x83, 0xec, 0x08, // sub rsp, 8
+        0x4c, 0x89, 0xcf, // mov rdi, r9
+        0x41, 0xff, 0x51, FRAME_HOST_CALL, // call [r9 + FRAME_HOST_CALL]
+        0x48, 0x83, 0xc4, 0x08, // add rsp, 8
+        0x5e, // pop rsi
+        0x41, 0x59, // pop r9
+    ])
+}
+
+// Checks what `emit_host_call` returned, `offset` is the distance to the
+// stub returning `STATUS_HOST_FAILED`
+fn emit_check_host<T: Write>(mem: &mut T, offset: isize) -> io::Result<()> {
+    mem.write_all(&[
+        0x85, 0xc0, // test eax, eax
+        0x0f, 0x85 // jnz ...
+    ])?;
+    emit_imm32(mem, (offset - HOST_CHECK_SIZE) as i32 as u32)
+}
+
+fn emit_prologue<T: Write>(mem: &mut T) -> io::Result<()> {
+    mem.write_all(&[
+        0x49, 0x89, 0xf9, // mov r9, rdi
+    ])
+}
+
+// Leaves the loop early once the cancel flag is set, `offset` is the
+// distance to the stub returning `STATUS_CANCELLED`
+fn emit_poll<T: Write>(mem: &mut T, offset: isize) -> io::Result<()> {
+    mem.write_all(&[
+        0x49, 0x8b, 0x01, // mov rax, [r9]
+        0x80, 0x38, 0x00, // cmp byte [rax], 0
+        0x0f, 0x85 // jne ...
+    ])?;
+    emit_imm32(mem, (offset - POLL_SIZE) as i32 as u32)
+}
+
+// Checks the pointer after moving it left, `offset` is the distance to
+// the stub returning `STATUS_POINTER_UNDERFLOW`
+fn emit_check_underflow<T: Write>(mem: &mut T, offset: isize) -> io::Result<()> {
+    mem.write_all(&[
+        0x49, 0x3b, 0x71, FRAME_TAPE_START, // cmp rsi, [r9 + FRAME_TAPE_START]
+        0x0f, 0x82 // jb ...
+    ])?;
+    emit_imm32(mem, (offset - CHECK_SIZE) as i32 as u32)
+}
+
+// Checks the pointer after moving it right, `offset` is the distance to
+// the stub returning `STATUS_POINTER_OVERFLOW`
+fn emit_check_overflow<T: Write>(mem: &mut T, offset: isize) -> io::Result<()> {
+    mem.write_all(&[
+        0x49, 0x3b, 0x71, FRAME_TAPE_END, // cmp rsi, [r9 + FRAME_TAPE_END]
+        0x0f, 0x83 // jae ...
+    ])?;
+    emit_imm32(mem, (offset - CHECK_SIZE) as i32 as u32)
+}
+
+// Hands the pointer back in `Exit::aux` at the end of a fragment
+fn emit_pointer_out<T: Write>(mem: &mut T) -> io::Result<()> {
+    mem.write_all(&[
+        0x48, 0x89, 0xf2, // mov rdx, rsi
+    ])
+}
+
+// `[>]` in one go, up to its pointer check: a zero cell is searched for 16
+// cells at a time with SSE2 for as long as a whole block of them is left
+// before the end of the tape. The loads are unaligned, so the first block
+// starts right at the pointer. `emit_scan_right_tail` does the last few
+// cells one by one, after the pointer check of the `>`.
+fn emit_scan_right_head<T: Write>(mem: &mut T) -> io::Result<()> {
+    mem.write_all(&[
+        0x80, 0x3e, 0x00, // cmp byte [rsi], 0
+        0x74, 0x43, // je done
+        0x49, 0x8b, 0x51, FRAME_TAPE_END, // mov rdx, [r9 + FRAME_TAPE_END]
+        0x66, 0x0f, 0xef, 0xc0, // pxor xmm0, xmm0
+        // block:
+        0x48, 0x8d, 0x46, 0x10, // lea rax, [rsi + 16]
+        0x48, 0x39, 0xd0, // cmp rax, rdx
+        0x77, 0x1e, // ja tail
+        0xf3, 0x0f, 0x6f, 0x0e, // movdqu xmm1, [rsi]
+        0x66, 0x0f, 0x74, 0xc8, // pcmpeqb xmm1, xmm0
+        0x66, 0x0f, 0xd7, 0xc1, // pmovmskb eax, xmm1
+        0x85, 0xc0, // test eax, eax
+        0x75, 0x06, // jnz found
+        0x48, 0x83, 0xc6, 0x10, // add rsi, 16
+        0xeb, 0xe1, // jmp block
+        // found:
+        0x0f, 0xbc, 0xc0, // bsf eax, eax
+        0x48, 0x01, 0xc6, // add rsi, rax
+        0xeb, 0x14, // jmp done
+        // tail:
+    ])
+}
+
+fn emit_scan_right_tail<T: Write>(mem: &mut T) -> io::Result<()> {
+    mem.write_all(&[
+        0x80, 0x3e, 0x00, // cmp byte [rsi], 0
+        0x74, 0x05, // je done
+        0x48, 0xff, 0xc6, // inc rsi
+        0xeb, 0xec, // jmp tail
+        // done:
+    ])
+}
+
+// `[<]` like `emit_scan_right_head`, searching the blocks of 16 cells
+// ending at the pointer from their last cell
+fn emit_scan_left_head<T: Write>(mem: &mut T) -> io::Result<()> {
+    mem.write_all(&[
+        0x80, 0x3e, 0x00, // cmp byte [rsi], 0
+        0x74, 0x44, // je done
+        0x49, 0x8b, 0x51, FRAME_TAPE_START, // mov rdx, [r9 + FRAME_TAPE_START]
+        0x66, 0x0f, 0xef, 0xc0, // pxor xmm0, xmm0
+        // block:
+        0x48, 0x8d, 0x46, 0xf1, // lea rax, [rsi - 15]
+        0x48, 0x39, 0xd0, // cmp rax, rdx
+        0x72, 0x1f, // jb tail
+        0xf3, 0x0f, 0x6f, 0x08, // movdqu xmm1, [rax]
+        0x66, 0x0f, 0x74, 0xc8, // pcmpeqb xmm1, xmm0
+        0x66, 0x0f, 0xd7, 0xc9, // pmovmskb ecx, xmm1
+        0x85, 0xc9, // test ecx, ecx
+        0x75, 0x06, // jnz found
+        0x48, 0x83, 0xee, 0x10, // sub rsi, 16
+        0xeb, 0xe1, // jmp block
+        // found:
+        0x0f, 0xbd, 0xc9, // bsr ecx, ecx
+        0x48, 0x8d, 0x34, 0x08, // lea rsi, [rax + rcx]
+        0xeb, 0x14, // jmp done
+        // tail:
+    ])
+}
+
+fn emit_scan_left_tail<T: Write>(mem: &mut T) -> io::Result<()> {
+    mem.write_all(&[
+        0x80, 0x3e, 0x00, // cmp byte [rsi], 0
+        0x74, 0x05, // je done
+        0x48, 0xff, 0xce, // dec rsi
+        0xeb, 0xec, // jmp tail
+        // done:
+    ])
+}
+
+// `cells` cells cleared with `rep stosb`, from the pointer on to the right
+// or to the left of it, leaving the pointer on the last one. Falls through
+// to the code after it if the range doesn't fit on the tape, which is the
+// clears and moves as usual, and otherwise jumps past that, once its
+// displacement is patched in with `emit_jmp_over`.
+fn emit_zero_range<T: Write>(mem: &mut T, cells: usize, right: bool) -> io::Result<()> {
+    let last = cells as i32 - 1;
+    mem.write_all(&[
+        0x48, 0x8d, 0x86, // lea rax, [rsi + imm32]
+    ])?;
+    emit_imm32(mem, if right { last } else { -last } as u32)?;
+    if right {
+        mem.write_all(&[
+            0x49, 0x3b, 0x41, FRAME_TAPE_END, // cmp rax, [r9 + FRAME_TAPE_END]
+            0x0f, 0x83, 0x14, 0x00, 0x00, 0x00, // jae slow
+            0x48, 0x89, 0xf7, // mov rdi, rsi
+        ])?;
+    } else {
+        mem.write_all(&[
+            0x49, 0x3b, 0x41, FRAME_TAPE_START, // cmp rax, [r9 + FRAME_TAPE_START]
+            0x0f, 0x82, 0x14, 0x00, 0x00, 0x00, // jb slow
+            0x48, 0x89, 0xc7, // mov rdi, rax
+        ])?;
+    }
+    mem.write_all(&[
+        0x48, 0x89, 0xc6, // mov rsi, rax
+        0xb9, // mov ecx, imm32
+    ])?;
+    emit_imm32(mem, cells as u32)?;
+    mem.write_all(&[
+        0x31, 0xc0, // xor eax, eax
+        0xf3, 0xaa, // rep stosb
+    ])?;
+    emit_jmp_over(mem, 0x41414141) // insert dummy
+}
+
+// The jmp ending `emit_zero_range`, `offset` is the distance from its
+// start to where it goes
+fn emit_jmp_over<T: Write>(mem: &mut T, offset: isize) -> io::Result<()> {
+    mem.write_all(&[
+        0xe9, // jmp ...
+    ])?;
+    emit_imm32(mem, (offset - 5) as i32 as u32)
+}
+
+// `@`, a jmp to where the program finishes, `offset` is the distance from
+// its start to there
+fn emit_end<T: Write>(mem: &mut T, offset: isize) -> io::Result<()> {
+    mem.write_all(&[
+        0xe9, // jmp ...
+    ])?;
+    emit_imm32(mem, (offset - 5) as i32 as u32)
+}
+
+// Where `@` in a fragment goes, handing back the pointer with
+// `STATUS_HALTED` through the epilogue at `offset`
+fn emit_halted<T: Write>(mem: &mut T, offset: isize) -> io::Result<()> {
+    emit_pointer_out(mem)?;
+    mem.write_all(&[
+        0xb8, // mov eax, imm32
+    ])?;
+    emit_imm32(mem, STATUS_HALTED)?;
+    mem.write_all(&[
+        0xe9, // jmp ...
+    ])?;
+    emit_imm32(mem, (offset - HALTED_SIZE) as i32 as u32)
+}
+
+// Where the code runs into the epilogue once the program is done
+fn emit_finish<T: Write>(mem: &mut T) -> io::Result<()> {
+    mem.write_all(&[
+        0x31, 0xc0, // xor eax, eax
+    ])
+}
+
+fn emit_epilogue<T: Write>(mem: &mut T) -> io::Result<()> {
+    mem.write_all(&[
+        0xc3 // ret
+    ])
+}
+
+// Returns `status` and `aux` through the epilogue, `offset` is the
+// distance to it
+fn emit_stub<T: Write>(mem: &mut T, status: u32, aux: u32, offset: isize) -> io::Result<()> {
+    mem.write_all(&[
+        0xba, // mov edx, imm32
+    ])?;
+    emit_imm32(mem, aux)?;
+    mem.write_all(&[
+        0xb8, // mov eax, imm32
+    ])?;
+    emit_imm32(mem, status)?;
+    mem.write_all(&[
+        0xe9, // jmp ...
+    ])?;
+    emit_imm32(mem, (offset - STUB_SIZE) as i32 as u32)
+}
+
+// The whole code of a program that always prints `output` and leaves
+// `tape` at the start of the tape, see `Brainfuck::precompute`. Writes
+// are retried after being interrupted or blocking and continued after
+// coming up short, a failed one returns `STATUS_IO_FAILED` like `.` does.
+fn emit_constant<T: Write>(mem: &mut T, output: &[u8], tape: &[u8]) -> io::Result<()> {
+    emit_prologue(mem)?;
+    mem.write_all(&[
+        0x48, 0x8d, 0x35, // lea rsi, [rip + output]
+    ])?;
+    emit_imm32(mem, CONSTANT_SIZE as u32 - 10)?;
+    mem.write_all(&[
+        0xba, // mov edx, imm32
+    ])?;
+    emit_imm32(mem, output.len() as u32)?;
+    mem.write_all(&[
+        0x48, 0x85, 0xd2, // test rdx, rdx
+        0x74, 0x24, // jz copy
+        // write:
+        0xb8, 0x01, 0x00, 0x00, 0x00, // mov eax, 1
+        0x41, 0x8b, 0x79, FRAME_OUTPUT, // mov edi, [r9 + FRAME_OUTPUT]
+        0x0f, 0x05, // syscall
+        0x48, 0x83, 0xf8, 0xfc, // cmp rax, -EINTR
+        0x74, 0xef, // je write
+        0x48, 0x83, 0xf8, 0xf5, // cmp rax, -EAGAIN
+        0x74, 0xe9, // je write
+        0x48, 0x85, 0xc0, // test rax, rax
+        0x78, 0x1d, // js fail
+        0x48, 0x01, 0xc6, // add rsi, rax
+        0x48, 0x29, 0xc2, // sub rdx, rax
+        0x75, 0xdc, // jnz write
+        // copy:
+        0x48, 0x8d, 0x35, // lea rsi, [rip + tape]
+    ])?;
+    emit_imm32(mem, (CONSTANT_SIZE as usize - 63 + output.len()) as u32)?;
+    mem.write_all(&[
+        0x49, 0x8b, 0x79, FRAME_TAPE_START, // mov rdi, [r9 + FRAME_TAPE_START]
+        0xb9, // mov ecx, imm32
+    ])?;
+    emit_imm32(mem, tape.len() as u32)?;
+    mem.write_all(&[
+        0xf3, 0xa4, // rep movsb
+    ])?;
+    emit_finish(mem)?;
+    emit_epilogue(mem)?;
+    mem.write_all(&[
+        // fail:
+        0xf7, 0xd8, // neg eax
+        0x41, 0x89, 0x41, FRAME_IO_ERROR, // mov [r9 + FRAME_IO_ERROR], eax
+        0x31, 0xd2, // xor edx, edx
+        0xb8, // mov eax, imm32
+    ])?;
+    emit_imm32(mem, STATUS_IO_FAILED)?;
+    emit_epilogue(mem)?;
+    mem.write_all(output)?;
+    mem.write_all(tape)
+}
+
+// Size of the code `emit_constant` puts before the bytes
+const CONSTANT_SIZE: isize = 91;
+
+// Size of the cmp/jcc sequence emitted for `[` and `]`, jcc displacements
+// are relative to its end
+const JMP_SIZE: isize = 9;
+
+// Size of the cancel check emitted before every `]` if `CodegenOptions::polls`
+const POLL_SIZE: isize = 12;
+
+// Size of the pointer check emitted after every `<` and `>`
+const CHECK_SIZE: isize = 10;
+
+// Size of the cell check emitted for `+` and `-` under `ArithMode::Trap`
+const CELL_CHECK_SIZE: isize = 11;
+
+// Size of the check emitted after every host call
+const HOST_CHECK_SIZE: isize = 8;
+
+// Size of the check emitted after every `,` and `.`
+const IO_CHECK_SIZE: isize = 13;
+
+// Size of a stub, its jmp displacement is relative to its end
+const STUB_SIZE: isize = 15;
+
+// Size of the code `emit_halted` emits
+const HALTED_SIZE: isize = 13;
+
+// The generated code's ABI. It is called as a `JitFn`, with a `Frame` in rdi
+// and the address of the cell the pointer starts on in rsi. The frame stays
+// in r9 and the pointer in rsi, everything else may be clobbered by the
+// syscalls doing I/O and by host calls. The code only touches the stack
+// around host calls, where it can't fault, see `fault`.
+//
+// It returns an `Exit` in rax and rdx: one of the `STATUS_` values and,
+// where noted, a value going with it. The body runs into the epilogue, the
+// code's only `ret`, once the program is done. Every other way out is a
+// stub behind the epilogue that sets both registers and jumps there.
+#[cfg(not(target_family = "wasm"))]
+#[repr(C)]
+struct Frame {
+    cancel: *const AtomicBool,
+    input: libc::c_int,
+    output: libc::c_int,
+    // the tape's first cell and the one past its last
+    tape_start: *const u8,
+    tape_end: *const u8,
+    // the storage register of Extended Type I, which the code changes
+    storage: u8,
+    // the errno of the `,` or `.` that failed
+    io_error: i32,
+    // what `%` calls, with the frame and the address of the cell, and
+    // the `HostState` it goes by
+    host_call: extern "C" fn(*mut Frame, *mut u8) -> u32,
+    host: *mut libc::c_void,
+}
+
+// Offsets into `Frame`, as the generated code and `macho` address it
+pub const FRAME_INPUT: u8 = 8;
+pub const FRAME_OUTPUT: u8 = 12;
+pub const FRAME_TAPE_START: u8 = 16;
+pub const FRAME_TAPE_END: u8 = 24;
+pub const FRAME_STORAGE: u8 = 32;
+pub const FRAME_IO_ERROR: u8 = 36;
+pub const FRAME_HOST_CALL: u8 = 40;
+// Only in the frames of PE executables, the routines writing and reading
+// the cell at rsi
+pub const FRAME_WINDOWS_WRITE: u8 = 56;
+pub const FRAME_WINDOWS_READ: u8 = 64;
+
+#[cfg(not(target_family = "wasm"))]
+#[repr(C)]
+struct Exit {
+    status: u64,
+    aux: u64,
+}
+
+const STATUS_FINISHED: u32 = 0;
+// The pointer moved off the tape, `aux` is the index of the instruction
+// moving it
+const STATUS_POINTER_UNDERFLOW: u32 = 1;
+const STATUS_POINTER_OVERFLOW: u32 = 2;
+// A loop went round with the cancel flag set
+const STATUS_CANCELLED: u32 = 3;
+// `+` or `-` took the cell out of range under `ArithMode::Trap`, `aux` is
+// the index of the instruction
+const STATUS_CELL_OVERFLOW: u32 = 4;
+const STATUS_CELL_UNDERFLOW: u32 = 5;
+// A fragment ran into `@`, `aux` is the address of the cell the pointer is
+// on as at `STATUS_FINISHED`
+const STATUS_HALTED: u32 = 6;
+// A host call failed, `aux` is the index of the instruction and the
+// `HostState` of the frame has the reason
+const STATUS_HOST_FAILED: u32 = 7;
+// A `,` or `.` failed, `aux` is the index of the instruction and
+// `Frame::io_error` has the errno
+const STATUS_IO_FAILED: u32 = 8;
+
+// Ceiling for the generated code, checked while emitting so that absurd
+// programs are rejected before a mapping of that size is requested
+const MAX_CODE_SIZE: usize = 1 << 30;
+
+fn check_displacement(inst_index: usize, distance: isize) -> Result<(), CompileError> {
+    let rel = distance - JMP_SIZE;
+    if rel < i32::MIN as isize || rel > i32::MAX as isize {
+        return Err(CompileError::JumpOutOfRange { inst_index, distance });
+    }
+
+    Ok(())
+}
+
+// `emit_inc` and `emit_dec` move by an imm32, which is sign-extended
+fn check_operand(inst_index: usize, amount: usize) -> Result<(), CompileError> {
+    if amount > i32::MAX as usize {
+        return Err(CompileError::OperandTooLarge { inst_index, amount });
+    }
+
+    Ok(())
+}
+
+// Where the current cell is while the code of a block is emitted. Runs of
+// `+` and `-` keep it in eax, which is stored back before anything else,
+// as every other instruction either moves the pointer, jumps or is a
+// syscall clobbering eax.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CellCache {
+    // only on the tape
+    Memory,
+    // in eax as well
+    Clean,
+    // in eax, with the tape behind
+    Dirty,
+}
+
+fn emit_cached_load<T: Write>(mem: &mut T, cache: CellCache) -> io::Result<()> {
+    if cache == CellCache::Memory {
+        emit_load_cell(mem)?;
+    }
+    Ok(())
+}
+
+fn emit_spill<T: Write>(mem: &mut T, cache: CellCache) -> io::Result<()> {
+    if cache == CellCache::Dirty {
+        emit_store_cell(mem)?;
+    }
+    Ok(())
+}
+
+// Options for `compile_insts`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodegenOptions {
+    // Programs generating more code than this fail with `CodeTooLarge`
+    pub max_code_size: usize,
+    pub arith: ArithMode,
+    // Code for part of a program, which finishes with the address of the
+    // cell the pointer ended up on in `Exit::aux`, see `TieredProgram`
+    pub fragment: bool,
+    // The system whose calls `,` and `.` make
+    pub syscalls: Syscalls,
+    // Code checking the cancel flag every time a loop goes round, for
+    // runs that can be cancelled
+    pub polls: bool,
+}
+
+impl Default for CodegenOptions {
+    fn default() -> CodegenOptions {
+        CodegenOptions {
+            max_code_size: MAX_CODE_SIZE, arith: ArithMode::Wrap, fragment: false, syscalls: Syscalls::Linux,
+            polls: false,
+        }
+    }
+}
+
+// Code that isn't run here, see `macho` and `pe`, makes the calls of the
+// system it's written for. Only `,` and `.` make any, so this is all that
+// differs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Syscalls {
+    Linux,
+    Darwin,
+    Windows,
+}
+
+// The code `Brainfuck` generates for `insts`, without a program around it,
+// called as described at `Frame`. The jumps are verified first. The same
+// instructions and options always give the same bytes, wherever they're
+// compiled.
+pub fn compile_insts(insts: &[Inst], options: &CodegenOptions) -> Result<Vec<u8>, CompileError> {
+    verify(insts)?;
+    compile_with(insts, options)
+}
+
+// `compile_insts`, also returning where the code of every instruction
+// starts, followed by where the code after the last one starts
+pub fn compile_insts_with_offsets(insts: &[Inst], options: &CodegenOptions) -> Result<(Vec<u8>, Vec<u32>), CompileError> {
+    verify(insts)?;
+    compile_with_offsets(insts, options)
+}
+
+fn compile(insts: &[Inst], arith: ArithMode) -> Result<Vec<u8>, CompileError> {
+    compile_with(insts, &CodegenOptions { arith, ..CodegenOptions::default() })
+}
+
+fn compile_with(insts: &[Inst], options: &CodegenOptions) -> Result<Vec<u8>, CompileError> {
+    compile_code(insts, options, None)
+}
+
+// Fewest cells cleared in a row that are cleared in one go
+const MIN_ZERO_RANGE: usize = 4;
+
+// Instructions for the code generator, which takes them in order and
+// looks ahead of the current one only as far as a scan or a cleared range
+// goes
+trait InstSource {
+    // The instruction at `i`, never one before the last `release`
+    fn get(&mut self, i: usize) -> Result<Option<Inst>, CompileError>;
+
+    // Nothing before `i` is needed anymore
+    fn release(&mut self, _i: usize) {}
+}
+
+impl InstSource for &[Inst] {
+    fn get(&mut self, i: usize) -> Result<Option<Inst>, CompileError> {
+        Ok(<[Inst]>::get(self, i).cloned())
+    }
+}
+
+// Instructions as they are parsed, keeping the ones looked at ahead of the
+// current one until it gets there
+struct Window<I> {
+    insts: I,
+    ahead: VecDeque<Inst>,
+    // index of the first one kept
+    base: usize,
+}
+
+impl<I: Iterator<Item = Result<Inst, CompileError>>> InstSource for Window<I> {
+    fn get(&mut self, i: usize) -> Result<Option<Inst>, CompileError> {
+        while self.base + self.ahead.len() <= i {
+            match self.insts.next() {
+                Some(inst) => self.ahead.push_back(inst?),
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(self.ahead[i - self.base].clone()))
+    }
+
+    fn release(&mut self, i: usize) {
+        while self.base < i && self.ahead.pop_front().is_some() {
+            self.base += 1;
+        }
+    }
+}
+
+// The number of cells the clear loops and single moves between them from
+// `insts[i]` on clear, if they are at least `MIN_ZERO_RANGE` and all moves
+// go the same way, with that way. A loop clears a cell when it only adds
+// an odd amount and cells wrap, or only subtracts 1.
+fn zero_range<S: InstSource>(insts: &mut S, i: usize, arith: ArithMode) -> Result<Option<(usize, bool)>, CompileError> {
+    let clears = |insts: &mut S, at: usize| -> Result<bool, CompileError> {
+        if !matches!(insts.get(at)?, Some(JmpFwd(_))) || !matches!(insts.get(at + 2)?, Some(JmpBack(_))) {
+            return Ok(false);
+        }
+        Ok(match insts.get(at + 1)? {
+            Some(DecVal(1)) => true,
+            Some(IncVal(a)) | Some(DecVal(a)) => arith == ArithMode::Wrap && a % 2 == 1,
+            _ => false,
+        })
+    };
+
+    if !clears(insts, i)? {
+        return Ok(None);
+    }
+    let right = match insts.get(i + 3)? {
+        Some(IncPtr(1)) => true,
+        Some(DecPtr(1)) => false,
+        _ => return Ok(None),
+    };
+    let step = if right { IncPtr(1) } else { DecPtr(1) };
+    let mut cells = 1;
+    while insts.get(i + 4 * cells - 1)?.as_ref() == Some(&step) && clears(insts, i + 4 * cells)? {
+        cells += 1;
+    }
+
+    if cells >= MIN_ZERO_RANGE && cells <= i32::MAX as usize {
+        Ok(Some((cells, right)))
+    } else {
+        Ok(None)
+    }
+}
+
+// Where the code jumps to a stub, which only go after all of the code: a
+// cancel check, or a check of a pointer move, a cell change or a host call
+// with its instruction and the status of its stub
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Fixup {
+    offset: usize,
+    inst_index: usize,
+    status: u32,
+}
+
+impl Fixup {
+    const ENCODED_SIZE: usize = 20;
+
+    // Where its displacement goes and what it is with its stub at `stub`
+    fn patch(&self, stub: usize) -> Patch {
+        let size = match self.status {
+            STATUS_CANCELLED => POLL_SIZE,
+            STATUS_POINTER_OVERFLOW | STATUS_POINTER_UNDERFLOW => CHECK_SIZE,
+            STATUS_HOST_FAILED => HOST_CHECK_SIZE,
+            STATUS_IO_FAILED => IO_CHECK_SIZE,
+            _ => CELL_CHECK_SIZE,
+        };
+        let end = self.offset + size as usize;
+        (end - 4, (stub as isize - end as isize) as i32 as u32)
+    }
+}
+
+// The fixups of a program in the order of their offsets. There is one for
+// every pointer move, so a streamed program keeps them in a file.
+enum Fixups {
+    Memory(Vec<Fixup>),
+    File(io::BufWriter<File>),
+}
+
+impl Fixups {
+    // In a file of the temporary directory, gone once it's closed
+    fn temporary() -> io::Result<Fixups> {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+        let name = format!("brainfuck-fixups-{}-{}", ::std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed));
+        let path = ::std::env::temp_dir().join(name);
+        let file = OpenOptions::new().read(true).write(true).create_new(true).open(&path)?;
+        ::std::fs::remove_file(&path)?;
+
+        Ok(Fixups::File(io::BufWriter::new(file)))
+    }
+
+    fn push(&mut self, fixup: Fixup) -> io::Result<()> {
+        match *self {
+            Fixups::Memory(ref mut fixups) => {
+                fixups.push(fixup);
+                Ok(())
+            }
+            Fixups::File(ref mut file) => {
+                file.write_all(&(fixup.offset as u64).to_le_bytes())?;
+                file.write_all(&(fixup.inst_index as u64).to_le_bytes())?;
+                file.write_all(&fixup.status.to_le_bytes())
+            }
+        }
+    }
+
+    // All of them from the first on, the file is only read from here on
+    fn iter(&mut self) -> io::Result<FixupIter<'_>> {
+        Ok(match *self {
+            Fixups::Memory(ref fixups) => FixupIter::Memory(fixups.iter()),
+            Fixups::File(ref mut file) => {
+                file.flush()?;
+                let mut file = file.get_ref();
+                file.seek(SeekFrom::Start(0))?;
+                FixupIter::File(io::BufReader::new(file))
+            }
+        })
+    }
+}
+
+enum FixupIter<'a> {
+    Memory(::std::slice::Iter<'a, Fixup>),
+    File(io::BufReader<&'a File>),
+}
+
+impl<'a> Iterator for FixupIter<'a> {
+    type Item = io::Result<Fixup>;
+
+    fn next(&mut self) -> Option<io::Result<Fixup>> {
+        let reader = match *self {
+            FixupIter::Memory(ref mut fixups) => return fixups.next().cloned().map(Ok),
+            FixupIter::File(ref mut reader) => reader,
+        };
+        let mut record = [0; Fixup::ENCODED_SIZE];
+        match reader.read_exact(&mut record) {
+            Ok(()) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return None,
+            Err(e) => return Some(Err(e)),
+        }
+        let word = |at: usize| u64::from_le_bytes(<[u8; 8]>::try_from(&record[at..at + 8]).unwrap()) as usize;
+        let status = u32::from_le_bytes(<[u8; 4]>::try_from(&record[16..20]).unwrap());
+
+        Some(Ok(Fixup { offset: word(0), inst_index: word(8), status }))
+    }
+}
+
+// Counts the bytes written, the code is emitted without seeking back
+struct Counted<W> {
+    inner: W,
+    position: usize,
+}
+
+impl<W: Write> Write for Counted<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.position += n;
+        Ok(n)
+    }
+
+    // the emitters write a few bytes at a time, which for a `Vec` go in
+    // without the loop of the provided one
+    #[inline]
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.inner.write_all(buf)?;
+        self.position += buf.len();
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// A displacement only known after its instruction is emitted, where it
+// goes and what it is
+type Patch = (usize, u32);
+
+// The displacements still to write, in the order of where they go: those
+// of `patches` and of the fixups, whose stubs start at `stubs`, the first
+// one being where every cancel check goes if `polls`
+struct PatchQueue<'a> {
+    patches: ::std::iter::Peekable<::std::slice::Iter<'a, Patch>>,
+    fixups: ::std::iter::Peekable<FixupIter<'a>>,
+    stubs: usize,
+    next_stub: usize,
+    // the ones running over the end of the last chunk into the next one
+    straddling: Vec<Patch>,
+}
+
+impl<'a> PatchQueue<'a> {
+    fn new(patches: &'a mut [Patch], fixups: &'a mut Fixups, stubs: usize, polls: bool) -> io::Result<PatchQueue<'a>> {
+        patches.sort_unstable();
+        Ok(PatchQueue {
+            patches: patches.iter().peekable(),
+            fixups: fixups.iter()?.peekable(),
+            stubs,
+            next_stub: stubs + polls as usize * STUB_SIZE as usize,
+            straddling: Vec::new(),
+        })
+    }
+
+    // Writes those going into `chunk`, the code from `start` on. Chunks
+    // have to come in order, without gaps.
+    fn apply(&mut self, chunk: &mut [u8], start: usize) -> io::Result<()> {
+        let end = start + chunk.len();
+        // whether it runs into the next chunk
+        let mut apply = |(at, value): Patch| {
+            if at >= start && at + 4 <= end {
+                chunk[at - start..at - start + 4].copy_from_slice(&value.to_le_bytes());
+                return false;
+            }
+            for (i, &byte) in value.to_le_bytes().iter().enumerate() {
+                if at + i >= start && at + i < end {
+                    chunk[at + i - start] = byte;
+                }
+            }
+            at + 4 > end
+        };
+
+        let mut straddling = Vec::new();
+        for patch in self.straddling.drain(..) {
+            apply(patch);
+        }
+        while let Some(&&patch) = self.patches.peek().filter(|patch| patch.0 < end) {
+            self.patches.next();
+            if apply(patch) {
+                straddling.push(patch);
+            }
+        }
+        while self.fixups.peek().is_some_and(|fixup| fixup.as_ref().map_or(true, |fixup| fixup.offset < end)) {
+            let fixup = self.fixups.next().unwrap()?;
+            let stub = if fixup.status == STATUS_CANCELLED {
+                self.stubs
+            } else {
+                self.next_stub += STUB_SIZE as usize;
+                self.next_stub - STUB_SIZE as usize
+            };
+            let patch = fixup.patch(stub);
+            if apply(patch) {
+                straddling.push(patch);
+            }
+        }
+        self.straddling = straddling;
+        Ok(())
+    }
+}
+
+// Writes what's left to patch into the first `stubs` bytes of `code`, a
+// megabyte at a time
+fn apply_patches<F: Read + Write + Seek>(code: &mut F, emitted: &mut Emitted) -> io::Result<()> {
+    const CHUNK_SIZE: usize = 1 << 20;
+
+    let end = emitted.stubs;
+    let mut queue = PatchQueue::new(&mut emitted.patches, &mut emitted.fixups, emitted.stubs, emitted.polls)?;
+    let mut chunk = vec![0; CHUNK_SIZE.min(end)];
+    let mut start = 0;
+    while start < end {
+        let len = CHUNK_SIZE.min(end - start);
+        let chunk = &mut chunk[..len];
+        code.seek(SeekFrom::Start(start as u64))?;
+        code.read_exact(chunk)?;
+        queue.apply(chunk, start)?;
+        code.seek(SeekFrom::Start(start as u64))?;
+        code.write_all(chunk)?;
+        start += len;
+    }
+
+    code.seek(SeekFrom::End(0))?;
+    Ok(())
+}
+
+// What's left to patch once `emit_program` is done
+struct Emitted {
+    patches: Vec<Patch>,
+    fixups: Fixups,
+    // where the stubs start, nothing after that is patched
+    stubs: usize,
+    polls: bool,
+}
+
+// Emits the code of the instructions of `insts` and the stubs after it,
+// with dummies for every displacement `apply_patches` patches in. The
+// jumps don't have to be linked, `[` and `]` pair up by nesting.
+fn emit_program<S: InstSource, W: Write>(
+    insts: &mut S, mem: &mut Counted<W>, options: &CodegenOptions, mut offsets: Option<&mut Vec<u32>>, mut fixups: Fixups,
+) -> Result<Emitted, CompileError> {
+    #[cfg(feature = "bignum")]
+    if let ArithMode::Unbounded(_) = options.arith {
+        return Err(CompileError::UnsupportedArithMode(options.arith));
+    }
+
+    let mut patches = Vec::new();
+    // where the jz of every open `[` is, its instruction and where its
+    // loop starts
+    let mut open: Vec<(usize, usize, usize)> = Vec::new();
+    let mut polls = false;
+    let mut checks = 0;
+    // where the jmp of every `@` is
+    let mut ends = Vec::new();
+
+    // the instructions after a `[` up to here are part of its scan
+    let mut scan_end = 0;
+    // where the jmp of the last cleared range is, and the instruction
+    // after the range, where it goes
+    let mut zero_jump: Option<(usize, usize)> = None;
+    // the instructions up to here are part of the last cleared range
+    let mut zero_end = 0;
+    let mut cache = CellCache::Memory;
+
+    emit_prologue(mem)?;
+
+    let mut i = 0;
+    while let Some(inst) = insts.get(i)? {
+        insts.release(i);
+        if let Some(ref mut offsets) = offsets {
+            offsets.push(code_offset(mem.position)?);
+        }
+        if let Some((jump, _)) = zero_jump.filter(|&(_, end)| end == i) {
+            patches.push((jump + 1, (mem.position - jump - 5) as u32));
+            zero_jump = None;
+        }
+        let next = insts.get(i + 1)?;
+        let in_run = |inst: Option<&Inst>| matches!(inst, Some(&IncVal(_)) | Some(&DecVal(_)));
+        if !in_run(Some(&inst)) {
+            emit_spill(mem, cache)?;
+            cache = CellCache::Memory;
+        }
+        if i < scan_end {
+            i += 1;
+            continue;
+        }
+        if i >= zero_end {
+            if let Some((cells, right)) = zero_range(insts, i, options.arith)? {
+                emit_zero_range(mem, cells, right)?;
+                zero_end = i + 4 * cells - 1;
+                zero_jump = Some((mem.position - 5, zero_end));
+            }
+        }
+        let mut check = |mem: &mut Counted<W>, inst_index: usize, status: u32| -> io::Result<()> {
+            fixups.push(Fixup { offset: mem.position, inst_index, status })?;
+            checks += 1;
+            match status {
+                STATUS_POINTER_OVERFLOW => emit_check_overflow(mem, 0x41414141), // insert dummy
+                STATUS_POINTER_UNDERFLOW => emit_check_underflow(mem, 0x41414141), // insert dummy
+                STATUS_HOST_FAILED => emit_check_host(mem, 0x41414141), // insert dummy
+                STATUS_IO_FAILED => emit_check_io(mem, 0x41414141), // insert dummy
+                _ => emit_check_cell(mem, 0x41414141), // insert dummy
+            }
+        };
+        let scan = matches!(insts.get(i + 2)?, Some(JmpBack(_)));
+        match inst {
+            JmpFwd(_) if scan && next == Some(IncPtr(1)) => {
+                emit_scan_right_head(mem)?;
+                check(mem, i + 1, STATUS_POINTER_OVERFLOW)?;
+                emit_scan_right_tail(mem)?;
+                scan_end = i + 3;
+            }
+            JmpFwd(_) if scan && next == Some(DecPtr(1)) => {
+                emit_scan_left_head(mem)?;
+                check(mem, i + 1, STATUS_POINTER_UNDERFLOW)?;
+                emit_scan_left_tail(mem)?;
+                scan_end = i + 3;
+            }
+            IncPtr(a) => {
+                check_operand(i, a)?;
+                emit_inc(mem, a)?;
+                check(mem, i, STATUS_POINTER_OVERFLOW)?;
+            },
+            DecPtr(a) => {
+                check_operand(i, a)?;
+                emit_dec(mem, a)?;
+                check(mem, i, STATUS_POINTER_UNDERFLOW)?;
+            },
+            // a lone one changes the cell in place, a failing check leaves
+            // the tape as it was before the instruction
+            IncVal(a) => match options.arith {
+                ArithMode::Wrap if cache == CellCache::Memory && !in_run(next.as_ref()) => emit_inc_val(mem, a)?,
+                ArithMode::Wrap => {
+                    emit_cached_load(mem, cache)?;
+                    emit_add_al(mem, a)?;
+                    cache = CellCache::Dirty;
+                }
+                ArithMode::Saturate => {
+                    emit_cached_load(mem, cache)?;
+                    emit_add_cell(mem, a)?;
+                    emit_saturate_high(mem)?;
+                    cache = CellCache::Dirty;
+                }
+                ArithMode::Trap => {
+                    emit_cached_load(mem, cache)?;
+                    emit_add_cell(mem, a)?;
+                    check(mem, i, STATUS_CELL_OVERFLOW)?;
+                    emit_store_cell(mem)?;
+                    cache = CellCache::Clean;
+                }
+                #[cfg(feature = "bignum")]
+                ArithMode::Unbounded(_) => unreachable!(),
+            },
+            DecVal(a) => match options.arith {
+                ArithMode::Wrap if cache == CellCache::Memory && !in_run(next.as_ref()) => emit_dec_val(mem, a)?,
+                ArithMode::Wrap => {
+                    emit_cached_load(mem, cache)?;
+                    emit_sub_al(mem, a)?;
+                    cache = CellCache::Dirty;
+                }
+                ArithMode::Saturate => {
+                    emit_cached_load(mem, cache)?;
+                    emit_sub_cell(mem, a)?;
+                    emit_saturate_low(mem)?;
+                    cache = CellCache::Dirty;
+                }
+                ArithMode::Trap => {
+                    emit_cached_load(mem, cache)?;
+                    emit_sub_cell(mem, a)?;
+                    check(mem, i, STATUS_CELL_UNDERFLOW)?;
+                    emit_store_cell(mem)?;
+                    cache = CellCache::Clean;
+                }
+                #[cfg(feature = "bignum")]
+                ArithMode::Unbounded(_) => unreachable!(),
+            },
+            PrintCell => match options.syscalls {
+                Syscalls::Linux => {
+                    emit_print(mem)?;
+                    check(mem, i, STATUS_IO_FAILED)?;
+                }
+                Syscalls::Darwin => {
+                    emit_darwin_print(mem)?;
+                    check(mem, i, STATUS_IO_FAILED)?;
+                }
+                Syscalls::Windows => emit_windows_call(mem, FRAME_WINDOWS_WRITE)?,
+            },
+            ReadChar => match options.syscalls {
+                Syscalls::Linux => {
+                    emit_read(mem)?;
+                    check(mem, i, STATUS_IO_FAILED)?;
+                }
+                Syscalls::Darwin => {
+                    emit_darwin_read(mem)?;
+                    check(mem, i, STATUS_IO_FAILED)?;
+                }
+                Syscalls::Windows => emit_windows_call(mem, FRAME_WINDOWS_READ)?,
+            },
+            JmpFwd(_) => {
+                let site = mem.position;
+                emit_jmp_fwd(mem, 0x41414141)?; // insert dummy
+                open.push((site, i, mem.position));
+            },
+            JmpBack(_) => {
+                let (site, n, start) = open.pop().ok_or(CompileError::InvalidJump { inst_index: i })?;
+                if options.polls {
+                    fixups.push(Fixup { offset: mem.position, inst_index: i, status: STATUS_CANCELLED })?;
+                    polls = true;
+                    emit_poll(mem, 0x41414141)?; // insert dummy
+                }
+                let distance = mem.position as isize - start as isize;
+                check_displacement(i, -distance)?;
+                emit_jmp_back(mem, -distance)?;
+                let distance = mem.position - site;
+                check_displacement(n, distance as isize)?;
+                patches.push((site + 5, (distance as isize - JMP_SIZE) as i32 as u32));
+            },
+            Ext(ExtOp::End) => {
+                ends.push(mem.position);
+                emit_end(mem, 0x41414141)?; // insert dummy
+            }
+            Ext(ExtOp::Store) => emit_store(mem)?,
+            Ext(ExtOp::Retrieve) => emit_retrieve(mem)?,
+            Ext(op) => emit_bit_op(mem, op)?,
+            HostCall => {
+                emit_host_call(mem)?;
+                check(mem, i, STATUS_HOST_FAILED)?;
+            }
+        }
+
+        // the stubs count too, which keeps every jump to them in range
+        let size = mem.position + (checks + polls as usize) * STUB_SIZE as usize;
+        if size > options.max_code_size {
+            return Err(CompileError::CodeTooLarge { size, limit: options.max_code_size });
+        }
+        i += 1;
+    }
+    if let Some(&(_, n, _)) = open.last() {
+        return Err(CompileError::InvalidJump { inst_index: n });
+    }
+
+    emit_spill(mem, cache)?;
+    if let Some(ref mut offsets) = offsets {
+        offsets.push(code_offset(mem.position)?);
+    }
+    if let Some((jump, _)) = zero_jump {
+        patches.push((jump + 1, (mem.position - jump - 5) as u32));
+    }
+
+    if options.fragment {
+        emit_pointer_out(mem)?;
+    }
+    let mut end = mem.position;
+    emit_finish(mem)?;
+    let epilogue = mem.position as isize;
+    emit_epilogue(mem)?;
+    // a fragment tells apart running into `@` from leaving its loop
+    if options.fragment && !ends.is_empty() {
+        end = mem.position;
+        emit_halted(mem, epilogue - mem.position as isize)?;
+    }
+    for site in ends {
+        patches.push((site + 1, (end as isize - site as isize - 5) as i32 as u32));
+    }
+
+    let stubs = mem.position;
+    if polls {
+        emit_stub(mem, STATUS_CANCELLED, 0, epilogue - mem.position as isize)?;
+    }
+    for fixup in fixups.iter()? {
+        let fixup = fixup?;
+        if fixup.status != STATUS_CANCELLED {
+            emit_stub(mem, fixup.status, fixup.inst_index as u32, epilogue - mem.position as isize)?;
+        }
+    }
+
+    Ok(Emitted { patches, fixups, stubs, polls })
+}
+
+// About how much code `inst` comes to with its stub, if it has one, for
+// the size of the buffer the code goes into. Rather too much than too
+// little, running out means copying all of the code so far.
+fn estimated_size(inst: &Inst, options: &CodegenOptions) -> usize {
+    (match *inst {
+        IncPtr(_) | DecPtr(_) => 7 + CHECK_SIZE + STUB_SIZE,
+        IncVal(_) | DecVal(_) if options.arith == ArithMode::Wrap => 5,
+        IncVal(_) | DecVal(_) => 10 + CELL_CHECK_SIZE + STUB_SIZE,
+        PrintCell | ReadChar => 40 + IO_CHECK_SIZE + STUB_SIZE,
+        // with room for a scan or a cleared range starting with it
+        JmpFwd(_) => 48,
+        JmpBack(_) if options.polls => JMP_SIZE + POLL_SIZE,
+        JmpBack(_) => JMP_SIZE,
+        HostCall => 21 + HOST_CHECK_SIZE + STUB_SIZE,
+        Ext(_) => 6,
+    }) as usize
+}
+
+// Where code starts as kept in the offsets, `max_code_size` stops any
+// program long before it would be past the range
+fn code_offset(position: usize) -> Result<u32, CompileError> {
+    u32::try_from(position).map_err(|_| CompileError::CodeTooLarge { size: position, limit: u32::MAX as usize })
+}
+
+// Generates the code into a buffer big enough for most programs, patching
+// it where it is. Also fills in `offsets` if given, see
+// `compile_insts_with_offsets`.
+fn compile_code(insts: &[Inst], options: &CodegenOptions, offsets: Option<&mut Vec<u32>>) -> Result<Vec<u8>, CompileError> {
+    let _stage = stage!("compile", instructions = insts.len());
+    let estimate = CONSTANT_SIZE as usize + insts.iter().map(|inst| estimated_size(inst, options)).sum::<usize>();
+    let mut mem = Counted { inner: Vec::with_capacity(estimate.min(options.max_code_size)), position: 0 };
+
+    let mut emitted = emit_program(&mut { insts }, &mut mem, options, offsets, Fixups::Memory(Vec::new()))?;
+    let mut code = mem.inner;
+    let stubs = emitted.stubs;
+    PatchQueue::new(&mut emitted.patches, &mut emitted.fixups, emitted.stubs, emitted.polls)?.apply(&mut code[..stubs], 0)?;
+    stage_done!(code_size = code.len());
+
+    Ok(code)
+}
+
+fn compile_with_offsets(insts: &[Inst], options: &CodegenOptions) -> Result<(Vec<u8>, Vec<u32>), CompileError> {
+    let mut offsets = Vec::with_capacity(insts.len() + 1);
+    let code = compile_code(insts, options, Some(&mut offsets))?;
+    Ok((code, offsets))
+}
+
+// Compiles a program read from `source` into `out`, from the start of it,
+// without ever having all of its source or code in memory. Only a few
+// bytes for every loop stay there, all checks go to a temporary file until
+// their stubs are written. The code is the same `compile_insts` generates
+// for `parse` of the whole source, with the default options. `out` has to
+// be open for reading as well.
+pub fn compile_streaming<R: Read>(source: R, out: &mut File) -> Result<(), CompileError> {
+    out.set_len(0)?;
+    out.seek(SeekFrom::Start(0))?;
+
+    let mut insts = Window { insts: InstStream::new(source), ahead: VecDeque::new(), base: 0 };
+    let mut mem = Counted { inner: io::BufWriter::new(&mut *out), position: 0 };
+    let mut emitted = emit_program(&mut insts, &mut mem, &CodegenOptions::default(), None, Fixups::temporary()?)?;
+    mem.flush()?;
+    drop(mem);
+
+    apply_patches(out, &mut emitted)?;
+    Ok(())
+}
+
+// Byte range of the source an instruction was parsed from, for runs it
+// covers the whole run including any comments inside it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+pub fn is_command(c: char) -> bool {
+    matches!(c, '>' | '<' | '+' | '-' | '.' | ',' | '[' | ']')
+}
+
+pub fn parse(program: &str) -> Result<Vec<Inst>, CompileError> {
+    parse_with_spans(program).map(|(insts, _)| insts)
+}
+
+// Like `parse`, additionally returns the source span of every instruction
+pub fn parse_with_spans(program: &str) -> Result<(Vec<Inst>, Vec<Span>), CompileError> {
+    parse_dialect_with_spans(program, Dialect::Brainfuck)
+}
+
+// `parse` for a source in `dialect`, where everything else is a comment
+pub fn parse_dialect(program: &str, dialect: Dialect) -> Result<Vec<Inst>, CompileError> {
+    parse_dialect_with_spans(program, dialect).map(|(insts, _)| insts)
+}
+
+pub fn parse_dialect_with_spans(program: &str, dialect: Dialect) -> Result<(Vec<Inst>, Vec<Span>), CompileError> {
+    use self::CompileError::*;
+
+    let _stage = stage!("parse", bytes = program.len());
+
+    let mut insts = Vec::new();
+    let mut spans = Vec::new();
+    let mut stack = Vec::new();
+    let mut errors = Vec::new();
+
+    let commands: Vec<(usize, char)> = program.char_indices()
+        .filter(|&(_, c)| dialect.is_command(c))
+        .collect();
+    let mut pos = 0;
+
+    for (length, c) in commands.iter().map(|&(_, c)| c).run_length() {
+        // commands are ASCII but the one of `Dialect::HostCalls`, which may
+        // take more than a byte
+        let run = &commands[pos..pos + length];
+        let char_span = |i: usize| Span { start: run[i].0, end: run[i].0 + c.len_utf8() };
+        let run_span = Span { start: run[0].0, end: run[length - 1].0 + c.len_utf8() };
+        pos += length;
+
+        match c {
+            '>' => insts.push(IncPtr(length)),
+            '<' => insts.push(DecPtr(length)),
+            '+' => insts.push(IncVal(length)),
+            '-' => insts.push(DecVal(length)),
+            '.' => {
+                for i in 0..length {
+                    insts.push(PrintCell);
+                    spans.push(char_span(i));
+                }
+            }
+            ',' => {
+                for i in 0..length {
+                    insts.push(ReadChar);
+                    spans.push(char_span(i));
+                }
+            }
+            '[' => {
+                for i in 0..length {
+                    stack.push(insts.len());
+                    insts.push(JmpFwd(0)); // insert dummy;
+                    spans.push(char_span(i));
+                }
+            },
+            ']' => {
+                for i in 0..length {
+                    // a stray `]` is dropped, so the brackets after it
+                    // still pair up as intended
+                    let n = match stack.pop() {
+                        Some(n) => n,
+                        None => {
+                            errors.push(UnmatchedClose { offset: char_span(i).start });
+                            continue;
+                        }
+                    };
+                    insts[n] = JmpFwd(insts.len());
+                    insts.push(JmpBack(n));
+                    spans.push(char_span(i));
+                }
+            },
+            c => {
+                let inst = match ExtOp::from_command(c) {
+                    Some(op) if dialect == Dialect::Extended1 => Ext(op),
+                    _ => HostCall,
+                };
+                for i in 0..length {
+                    insts.push(inst.clone());
+                    spans.push(char_span(i));
+                }
+            }
+        };
+
+        if spans.len() < insts.len() {
+            spans.push(run_span);
+        }
+    }
+
+    errors.extend(stack.into_iter().map(|n| UnclosedOpen { offset: spans[n].start }));
+    errors.sort_by_key(|e| e.offset());
+    stage_done!(instructions = insts.len(), errors = errors.len());
+
+    match errors.len() {
+        0 => Ok((insts, spans)),
+        1 => Err(errors.remove(0)),
+        _ => Err(Multiple(errors)),
+    }
+}
+
+// The instructions `parse` gives for a source read from `R`, parsed as
+// they are taken. The jumps aren't linked, and the first bracket that
+// doesn't pair up ends them with its error.
+pub struct InstStream<R> {
+    bytes: io::Bytes<io::BufReader<R>>,
+    offset: usize,
+    // the run of `><+-` parsed so far, and its length
+    run: Option<(u8, usize)>,
+    // what ended the run, to be taken after it
+    queued: Option<Result<Inst, CompileError>>,
+    // the offsets of the `[`s still open
+    open: Vec<usize>,
+    done: bool,
+}
+
+impl<R: Read> InstStream<R> {
+    pub fn new(source: R) -> InstStream<R> {
+        InstStream {
+            bytes: io::BufReader::new(source).bytes(),
+            offset: 0,
+            run: None,
+            queued: None,
+            open: Vec::new(),
+            done: false,
+        }
+    }
+
+    fn take_run(&mut self) -> Option<Result<Inst, CompileError>> {
+        self.run.take().map(|(c, length)| Ok(match c {
+            b'>' => IncPtr(length),
+            b'<' => DecPtr(length),
+            b'+' => IncVal(length),
+            _ => DecVal(length),
+        }))
+    }
+
+    // `item` after the run before it, if there is one
+    fn after_run(&mut self, item: Result<Inst, CompileError>) -> Option<Result<Inst, CompileError>> {
+        match self.take_run() {
+            Some(run) => {
+                self.queued = Some(item);
+                Some(run)
+            }
+            None => Some(item),
+        }
+    }
+}
+
+impl<R: Read> Iterator for InstStream<R> {
+    type Item = Result<Inst, CompileError>;
+
+    fn next(&mut self) -> Option<Result<Inst, CompileError>> {
+        if let Some(item) = self.queued.take() {
+            return Some(item);
+        }
+        if self.done {
+            return None;
+        }
+
+        loop {
+            let at = self.offset;
+            let c = match self.bytes.next() {
+                Some(Ok(c)) => c,
+                Some(Err(e)) => {
+                    self.done = true;
+                    return Some(Err(e.into()));
+                }
+                None => {
+                    self.done = true;
+                    return match self.open.first() {
+                        Some(&offset) => self.after_run(Err(CompileError::UnclosedOpen { offset })),
+                        None => self.take_run(),
+                    };
+                }
+            };
+            self.offset += 1;
+
+            let item = match c {
+                b'>' | b'<' | b'+' | b'-' => match self.run {
+                    Some((run, ref mut length)) if run == c => {
+                        *length += 1;
+                        continue;
+                    }
+                    _ => {
+                        let run = self.take_run();
+                        self.run = Some((c, 1));
+                        match run {
+                            Some(run) => return Some(run),
+                            None => continue,
+                        }
+                    }
+                },
+                b'.' => Ok(PrintCell),
+                b',' => Ok(ReadChar),
+                b'[' => {
+                    self.open.push(at);
+                    Ok(JmpFwd(0))
+                }
+                b']' => match self.open.pop() {
+                    Some(_) => Ok(JmpBack(0)),
+                    None => {
+                        self.done = true;
+                        Err(CompileError::UnmatchedClose { offset: at })
+                    }
+                },
+                _ => continue,
+            };
+            return self.after_run(item);
+        }
+    }
+}
+
+// Recomputes all jump targets from the bracket structure, for
+// transformations that insert or remove instructions
+pub fn relink(insts: &mut [Inst]) {
+    let mut stack = Vec::new();
+
+    for i in 0..insts.len() {
+        match insts[i] {
+            JmpFwd(_) => stack.push(i),
+            JmpBack(_) => {
+                let n = stack.pop().expect("unbalanced loops");
+                insts[n] = JmpFwd(i);
+                insts[i] = JmpBack(n);
+            }
+            _ => {}
+        }
+    }
+}
+
+// Lowers an instruction stream back to source, with the commands of a
+// dialect for its `Ext` instructions
+pub fn to_source(insts: &[Inst]) -> String {
+    let mut source = String::new();
+
+    for inst in insts {
+        let (c, n) = match *inst {
+            IncPtr(a) => ('>', a),
+            DecPtr(a) => ('<', a),
+            IncVal(a) => ('+', a),
+            DecVal(a) => ('-', a),
+            PrintCell => ('.', 1),
+            ReadChar => (',', 1),
+            JmpFwd(_) => ('[', 1),
+            JmpBack(_) => (']', 1),
+            Ext(op) => (op.command(), 1),
+            HostCall => (HOST_CALL_COMMAND, 1),
+        };
+        source.extend(std::iter::repeat_n(c, n));
+    }
+
+    source
+}
+
+// Checks the jump structure of an instruction stream: every `JmpFwd` has to
+// point at the `JmpBack` pointing back at it, and loops have to nest
+pub fn verify(insts: &[Inst]) -> Result<(), CompileError> {
+    use self::CompileError::InvalidJump;
+
+    let mut stack = Vec::new();
+
+    for (i, inst) in insts.iter().enumerate() {
+        match *inst {
+            JmpFwd(n) => {
+                match insts.get(n) {
+                    Some(&JmpBack(m)) if m == i && n > i => stack.push(i),
+                    _ => return Err(InvalidJump { inst_index: i }),
+                }
+            }
+            JmpBack(n) if stack.pop() != Some(n) => {
+                return Err(InvalidJump { inst_index: i });
+            }
+            _ => {}
+        }
+    }
+
+    match stack.pop() {
+        Some(i) => Err(InvalidJump { inst_index: i }),
+        None => Ok(()),
+    }
+}
+
+// Two programs are equal if they consist of the same instructions and run
+// on the same tape, regardless of their source's layout or comments
+// and of the tape left behind by earlier runs
+#[derive(Clone)]
+pub struct Brainfuck {
+    insts: Vec<Inst>,
+    // where every instruction came from in the source, empty if there's
+    // no source, e.g. for `from_insts`
+    spans: Vec<Span>,
+    source_len: usize,
+    // whether runs of the same instruction are merged, as `parse` merges
+    // them, which `concat` keeps up across the seams
+    optimized: bool,
+    // where the sources of the fragments `concat` spliced start
+    fragments: Vec<usize>,
+    // generated the first time it's needed, see `jit_code`
+    jit_code: OnceLock<Vec<u8>>,
+    tape_size: usize,
+    // copied to the start of the tape before every run
+    initial_tape: Vec<u8>,
+    pointer_start: usize,
+    // bytes a run may allocate, see `MemoryBudget`
+    max_memory: usize,
+    arith: ArithMode,
+    tape_alloc: TapeAlloc,
+    // cells of `GUARD_BYTE` on either side of the tape, see `set_tape_guard`
+    tape_guard: usize,
+    // the file mapped as the tape, see `set_tape_file`
+    tape_file: Option<(PathBuf, TapeFileSize)>,
+    // whether `jit_code` only replays the output, see `precompute`
+    precomputed: bool,
+    // where to announce the code to `perf` and with which symbols
+    perf_map: Option<(PathBuf, Option<Vec<Span>>)>,
+    tape: Tape,
+}
+
+// A program compiled into a `JitArena`, which it keeps alive
+#[cfg(not(target_family = "wasm"))]
+pub struct JitProgram {
+    code: ArenaCode,
+    tape_size: usize,
+    initial_tape: Vec<u8>,
+    pointer_start: usize,
+    max_memory: usize,
+    tape_alloc: TapeAlloc,
+    required: usize,
+    tape: Tape,
+}
+
+// A program compiled once for runs on any number of threads at once, with
+// a tape of their own each. Clones share the code, which is unmapped with
+// the last of them.
+#[cfg(not(target_family = "wasm"))]
+#[derive(Clone)]
+pub struct SharedProgram {
+    code: Arc<ArenaCode>,
+    tape_size: usize,
+    initial_tape: Arc<[u8]>,
+    pointer_start: usize,
+    max_memory: usize,
+    required: usize,
+}
+
+// A program starting out in the interpreter, which compiles a top-level
+// loop once it went round often enough and runs the rest of it as
+// generated code, see `Interp::set_hot_threshold`. Only loops that get hot
+// are ever compiled, which pays off for large programs spending their time
+// in a few places. I/O goes straight to file descriptors, as in generated
+// code.
+#[cfg(not(target_family = "wasm"))]
+pub struct TieredProgram {
+    insts: Vec<Inst>,
+    tape_size: usize,
+    initial_tape: Vec<u8>,
+    pointer_start: usize,
+    max_memory: usize,
+    arith: ArithMode,
+    threshold: u64,
+    // generated code of the loops that got hot by the index of their `[`,
+    // mapped once and kept for later runs
+    fragments: HashMap<usize, ArenaCode>,
+    // where the next fragment goes, a new one replaces it once it's full
+    arena: Option<JitArena>,
+    tape: Vec<u8>,
+}
+
+// How `SharedProgram::run_many` runs its inputs. There is no step budget
+// as `Interp::run_for` takes: every run is generated code, which doesn't
+// count its steps, so a run is limited by time instead.
+#[cfg(not(target_family = "wasm"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunManyOptions {
+    // How many runs at once, on threads of their own if it's more than 1
+    pub jobs: usize,
+    // How long a run may take before it fails with `RuntimeError::Cancelled`
+    pub timeout: Option<Duration>,
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl Default for RunManyOptions {
+    // As many runs at once as there are CPUs, for as long as they take
+    fn default() -> RunManyOptions {
+        RunManyOptions { jobs: thread::available_parallelism().map_or(1, |n| n.get()), timeout: None }
+    }
+}
+
+// What a run of a `SharedProgram` left behind
+#[cfg(not(target_family = "wasm"))]
+#[derive(Debug)]
+pub struct Execution {
+    pub tape: Vec<u8>,
+    pub output: Vec<u8>,
+}
+
+// A finished background run, the program keeps the tape it left behind
+pub struct RunOutcome {
+    pub program: Brainfuck,
+}
+
+#[cfg(not(target_family = "wasm"))]
+pub struct RunHandle {
+    thread: thread::JoinHandle<Result<RunOutcome, RuntimeError>>,
+    cancel: Arc<AtomicBool>,
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl RunHandle {
+    // Stops the program the next time a loop goes round, the run then
+    // fails with `RuntimeError::Cancelled`. Does nothing if it already
+    // finished.
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    pub fn join(self) -> Result<RunOutcome, RuntimeError> {
+        self.thread.join().expect("program thread panicked")
+    }
+}
+
+impl PartialEq for Brainfuck {
+    fn eq(&self, other: &Brainfuck) -> bool {
+        self.insts == other.insts && self.tape_size == other.tape_size
+            && self.initial_tape == other.initial_tape && self.pointer_start == other.pointer_start
+            && self.arith == other.arith
+    }
+}
+
+impl Eq for Brainfuck {}
+
+pub const DEFAULT_TAPE_SIZE: usize = 30_000;
+
+// Instructions `--precompute` lets the interpreter run at most
+pub const DEFAULT_PRECOMPUTE_STEPS: usize = 100_000_000;
+
+// What `Brainfuck::precompute` made of a program, everything but `Output`
+// leaves its code as it was
+#[derive(Debug)]
+pub enum Precomputed {
+    // The code prints this many bytes and leaves the tape as the program
+    // would
+    Output(usize),
+    ReadsInput,
+    // The program was still running after the budget
+    OutOfSteps(usize),
+    // The program fails, which the generated code reports when it runs
+    Fails(RuntimeError),
+    // The output and the tape wouldn't fit in the code
+    TooLarge,
+    // The tape comes from a file, see `Brainfuck::set_tape_file`
+    TapeFile,
+}
+
+impl fmt::Display for Precomputed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Precomputed::Output(bytes) => write!(f, "precomputed {} bytes of output", bytes),
+            Precomputed::ReadsInput => write!(f, "not precomputed, the program reads input"),
+            Precomputed::OutOfSteps(steps) => write!(f, "not precomputed, still running after {} steps", steps),
+            Precomputed::Fails(ref err) => write!(f, "not precomputed, the program fails: {}", err),
+            Precomputed::TooLarge => write!(f, "not precomputed, the output is too large"),
+            Precomputed::TapeFile => write!(f, "not precomputed, the tape comes from a file"),
+        }
+    }
+}
+
+// How often the loops in a top-level loop go round before `TieredProgram`
+// compiles it
+pub const DEFAULT_TIER_THRESHOLD: u64 = 10_000;
+
+// Bytes of the arenas `TieredProgram` maps its fragments into, unless one
+// needs more
+#[cfg(not(target_family = "wasm"))]
+const FRAGMENT_ARENA: usize = 64 * 1024;
+
+// Output `Brainfuck::run_streaming` collects at most before handing it on
+pub const STREAM_BUFFER: usize = 8192;
+
+const FINGERPRINT_VERSION: u64 = 8;
+
+// Entry point of the generated code, see `Frame` for the ABI. Everything
+// it writes is on the tape or in the frame, so the same code can run on many
+// threads at once.
+#[cfg(not(target_family = "wasm"))]
+type JitFn = extern "C" fn(*mut Frame, *mut u8) -> Exit;
+
+#[derive(Debug)]
+pub enum CompileError {
+    // Offsets are byte offsets into the source
+    UnmatchedClose { offset: usize },
+    UnclosedOpen { offset: usize },
+    // Every bracket error of a program, in source order
+    Multiple(Vec<CompileError>),
+    InvalidJump { inst_index: usize },
+    JumpOutOfRange { inst_index: usize, distance: isize },
+    // A pointer move too far for the code, which moves by an imm32
+    OperandTooLarge { inst_index: usize, amount: usize },
+    CodeTooLarge { size: usize, limit: usize },
+    ArenaFull { size: usize, available: usize },
+    // An `optimize::Pass` left jumps that don't match
+    PassProducedInvalidIr { pass_name: String },
+    // Cells the generated code can't have, only the interpreter runs them
+    UnsupportedArithMode(ArithMode),
+    Io(io::Error),
+}
+
+impl From<io::Error> for CompileError {
+    fn from(err: io::Error) -> CompileError {
+        CompileError::Io(err)
+    }
+}
+
+impl CompileError {
+    // Where in the source the error is, if it points at a single character
+    pub fn offset(&self) -> Option<usize> {
+        match *self {
+            CompileError::UnmatchedClose { offset } | CompileError::UnclosedOpen { offset } => {
+                Some(offset)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::CompileError::*;
+
+        match *self {
+            UnmatchedClose { .. } => write!(f, "unmatched ']'"),
+            UnclosedOpen { .. } => write!(f, "unclosed '['"),
+            Multiple(ref errors) => {
+                write!(f, "{} errors", errors.len())?;
+                for (i, err) in errors.iter().enumerate() {
+                    write!(f, "{} {}", if i == 0 { ":" } else { ";" }, err)?;
+                }
+                Ok(())
+            }
+            InvalidJump { inst_index } => write!(f, "invalid jump at instruction {}", inst_index),
+            JumpOutOfRange { inst_index, distance } => write!(
+                f, "jump at instruction {} out of range ({} bytes)", inst_index, distance
+            ),
+            OperandTooLarge { inst_index, amount } => write!(
+                f, "pointer move at instruction {} too large ({} cells)", inst_index, amount
+            ),
+            CodeTooLarge { size, limit } => write!(
+                f, "generated code too large ({} bytes, limit is {})", size, limit
+            ),
+            ArenaFull { size, available } => write!(
+                f, "generated code doesn't fit into the arena ({} bytes, {} left)", size, available
+            ),
+            PassProducedInvalidIr { ref pass_name } => write!(f, "the {} pass produced invalid IR", pass_name),
+            UnsupportedArithMode(mode) => write!(f, "the JIT doesn't support {:?} cells, only the interpreter does", mode),
+            Io(ref err) => write!(f, "{}", err),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RuntimeError {
+    InvalidTapeSize(usize),
+    // A tape passed to `run_in` isn't as long as the program's
+    TapeLengthMismatch { len: usize, tape_size: usize },
+    TapeTooSmall { required: usize, tape_size: usize },
+    InitialTapeTooLarge { size: usize, tape_size: usize },
+    PointerStartOutOfRange { start: usize, tape_size: usize },
+    PointerUnderflow { inst_index: usize },
+    PointerOverflow { inst_index: usize },
+    // `+` or `-` at `inst_index` went past 255 or below 0 under `ArithMode::Trap`
+    CellOverflow { inst_index: usize },
+    CellUnderflow { inst_index: usize },
+    // The loop starting at `inst_index` went round without changing anything
+    NonTerminatingLoop { inst_index: usize },
+    // The program changed cells of the guards around the tape, see
+    // `Brainfuck::set_tape_guard`, the lowest and the highest of them.
+    // Cells before the tape count back from -1, the ones after it on
+    // from the tape's size.
+    GuardCorrupted { lowest: isize, highest: isize },
+    // The file mapped as the tape isn't as long as the tape, see
+    // `Brainfuck::set_tape_file`
+    TapeFileSizeMismatch { len: u64, tape_size: usize },
+    MemoryLimitExceeded { limit: usize, requested: usize },
+    // The generated code crashed, `rip` is the offset into the code
+    Fault { signal: i32, address: usize, rip: usize },
+    Cancelled,
+    SandboxFailed(io::Error),
+    // A hook of `Brainfuck::run_with_hooks` or a host function panicked,
+    // with its message
+    HookPanicked(String),
+    // The host call at `inst_index` was on a cell holding `id`, for which no
+    // host function is registered
+    UnregisteredHostCall { id: u8, inst_index: usize },
+    // The code couldn't be generated, which happens before the first run
+    // of generated code
+    Compile(CompileError),
+    Io(io::Error),
+}
+
+impl RuntimeError {
+    // The instruction the error happened at, if it tells
+    pub fn inst_index(&self) -> Option<usize> {
+        use self::RuntimeError::*;
+
+        match *self {
+            PointerUnderflow { inst_index } | PointerOverflow { inst_index } | CellOverflow { inst_index }
+            | CellUnderflow { inst_index } | NonTerminatingLoop { inst_index }
+            | UnregisteredHostCall { inst_index, .. } => Some(inst_index),
+            _ => None,
+        }
+    }
+}
+
+impl From<CompileError> for RuntimeError {
+    fn from(err: CompileError) -> RuntimeError {
+        RuntimeError::Compile(err)
+    }
+}
+
+impl From<io::Error> for RuntimeError {
+    fn from(err: io::Error) -> RuntimeError {
+        // errors hit behind a `Write`, like `memory::BudgetedBuffer` running
+        // out of budget, come back as they were
+        if err.get_ref().is_some_and(|inner| inner.is::<RuntimeError>()) {
+            return *err.into_inner().unwrap().downcast::<RuntimeError>().unwrap();
+        }
+        RuntimeError::Io(err)
+    }
+}
+
+impl ::std::error::Error for RuntimeError {}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::RuntimeError::*;
+
+        match *self {
+            InvalidTapeSize(size) => write!(f, "invalid tape size {}", size),
+            TapeLengthMismatch { len, tape_size } => write!(
+                f, "tape of {} cells given for a program running on {}", len, tape_size
+            ),
+            TapeTooSmall { required, tape_size } => write!(
+                f, "program needs at least {} cells, tape has {}", required, tape_size
+            ),
+            InitialTapeTooLarge { size, tape_size } => write!(
+                f, "initial tape of {} cells doesn't fit on the tape of {}", size, tape_size
+            ),
+            PointerStartOutOfRange { start, tape_size } => write!(
+                f, "pointer start {} is outside of the tape of {} cells", start, tape_size
+            ),
+            PointerUnderflow { inst_index } => write!(
+                f, "pointer moved below the start of the tape at instruction {}", inst_index
+            ),
+            PointerOverflow { inst_index } => write!(
+                f, "pointer moved past the end of the tape at instruction {}", inst_index
+            ),
+            CellOverflow { inst_index } => write!(
+                f, "cell went past 255 at instruction {}", inst_index
+            ),
+            CellUnderflow { inst_index } => write!(
+                f, "cell went below 0 at instruction {}", inst_index
+            ),
+            NonTerminatingLoop { inst_index } => write!(
+                f, "loop at instruction {} never terminates", inst_index
+            ),
+            MemoryLimitExceeded { limit, requested } => write!(
+                f, "memory limit of {} bytes exceeded, {} bytes needed", limit, requested
+            ),
+            #[cfg(not(target_family = "wasm"))]
+            Fault { signal, address, rip } => write!(
+                f, "generated code crashed with {} accessing {:#x} at offset {:#x}",
+                ::fault::signal_name(signal), address, rip
+            ),
+            // generated code never runs there
+            #[cfg(target_family = "wasm")]
+            Fault { signal, address, rip } => write!(
+                f, "generated code crashed with signal {} accessing {:#x} at offset {:#x}",
+                signal, address, rip
+            ),
+            GuardCorrupted { lowest, highest } => write!(
+                f, "the program changed cells of the guards off the tape, from {} to {}, its results \
+                    can't be trusted", lowest, highest
+            ),
+            TapeFileSizeMismatch { len, tape_size } => write!(
+                f, "tape file of {} bytes for a tape of {} cells", len, tape_size
+            ),
+            Cancelled => write!(f, "cancelled"),
+            SandboxFailed(ref err) => write!(f, "couldn't set up the sandbox: {}", err),
+            HookPanicked(ref message) => write!(f, "hook panicked: {}", message),
+            UnregisteredHostCall { id, inst_index } => write!(
+                f, "no host function {} for the host call at instruction {}", id, inst_index
+            ),
+            Compile(ref err) => write!(f, "{}", err),
+            Io(ref err) => write!(f, "{}", err),
+        }
+    }
+}
+
+// Number of cells the program is guaranteed to touch, judging by the
+// pointer moves before the first loop (which always execute)
+pub fn min_tape_size(insts: &[Inst]) -> usize {
+    let mut ptr: isize = 0;
+    let mut max = 0;
+
+    for inst in insts {
+        match *inst {
+            IncPtr(a) => ptr += a as isize,
+            DecPtr(a) => ptr -= a as isize,
+            JmpFwd(_) => break,
+            _ => {}
+        }
+        max = max.max(ptr);
+    }
+
+    max as usize + 1
+}
+
+impl Brainfuck {
+    pub fn new(program: &str) -> Result<Brainfuck, CompileError> {
+        Brainfuck::with_dialect(program, Dialect::Brainfuck)
+    }
+
+    pub fn with_dialect(program: &str, dialect: Dialect) -> Result<Brainfuck, CompileError> {
+        // `parse` only produces matching jumps
+        let (insts, spans) = parse_dialect_with_spans(program, dialect)?;
+        let mut bf = Brainfuck::from_insts_unchecked(insts)?;
+        bf.spans = spans;
+        bf.source_len = program.len();
+        bf.optimized = true;
+
+        Ok(bf)
+    }
+
+    // A program from instructions that didn't come from `parse`, e.g.
+    // loaded from bytecode, which are verified first
+    pub fn from_insts(insts: Vec<Inst>) -> Result<Brainfuck, CompileError> {
+        verify(&insts)?;
+        Brainfuck::from_insts_unchecked(insts)
+    }
+
+    // Like `from_insts` without verifying the jumps, for instructions that
+    // are known to be valid. Jumps that don't match up generate code jumping
+    // wherever they happen to point, which can do anything once it runs, or
+    // make code generation panic. No code is generated before it's needed.
+    pub fn from_insts_unchecked(insts: Vec<Inst>) -> Result<Brainfuck, CompileError> {
+        Ok(Brainfuck {
+            jit_code: OnceLock::new(),
+            insts,
+            spans: Vec::new(),
+            source_len: 0,
+            optimized: false,
+            fragments: Vec::new(),
+            tape_size: DEFAULT_TAPE_SIZE,
+            initial_tape: Vec::new(),
+            pointer_start: 0,
+            max_memory: usize::MAX,
+            arith: ArithMode::Wrap,
+            tape_alloc: TapeAlloc::Default,
+            tape_guard: 0,
+            tape_file: None,
+            precomputed: false,
+            perf_map: None,
+            tape: Tape::default(),
+        })
+    }
+
+    pub fn insts(&self) -> &[Inst] {
+        &self.insts
+    }
+
+    // The source span of every instruction, none without a source
+    pub fn spans(&self) -> &[Span] {
+        &self.spans
+    }
+
+    // Which of the fragments `concat` spliced the instruction at `index`
+    // came from, `None` for programs that weren't spliced or have no source
+    pub fn fragment_of(&self, index: usize) -> Option<usize> {
+        let start = self.spans.get(index)?.start;
+        // empty fragments start where the next one does
+        self.fragments.partition_point(|&fragment| fragment <= start).checked_sub(1)
+    }
+
+    // The instructions as a tree of loops, see `Ast`
+    pub fn ast(&self) -> Ast {
+        Ast::from_insts(&self.insts)
+    }
+
+    // Splices programs together as if their sources had been concatenated:
+    // runs meeting at a seam merge if the fragments on both sides have
+    // their runs merged, jumps are re-indexed and code is generated once
+    // for the whole program. The spans of the instructions are those in
+    // the concatenated sources, if all fragments have a source, see
+    // `fragment_of`. The tape is as large as the largest one of the
+    // fragments, the first one decides how it starts and how cells
+    // overflow. The smallest memory limit applies. Code is generated once
+    // it's needed.
+    pub fn concat(fragments: &[&Brainfuck]) -> Result<Brainfuck, CompileError> {
+        let mut insts: Vec<Inst> = Vec::new();
+        let mut spans: Vec<Span> = Vec::new();
+        let mut starts = Vec::new();
+        let mut source_len = 0;
+        // whether the fragment the last instruction came from is optimized
+        let mut merge = false;
+
+        for fragment in fragments {
+            let has_spans = fragment.spans.len() == fragment.insts.len();
+            let rebase = |span: &Span| Span { start: span.start + source_len, end: span.end + source_len };
+            let mut rest = &fragment.insts[..];
+            let mut rest_spans = if has_spans { &fragment.spans[..] } else { &[][..] };
+
+            if let (Some(last), Some(first), true) = (insts.last_mut(), rest.first(), merge && fragment.optimized) {
+                let merged = match (&*last, first) {
+                    (&IncPtr(a), &IncPtr(b)) => Some(IncPtr(a + b)),
+                    (&DecPtr(a), &DecPtr(b)) => Some(DecPtr(a + b)),
+                    (&IncVal(a), &IncVal(b)) => Some(IncVal(a + b)),
+                    (&DecVal(a), &DecVal(b)) => Some(DecVal(a + b)),
+                    _ => None,
+                };
+                if let Some(merged) = merged {
+                    *last = merged;
+                    rest = &rest[1..];
+                    if let (Some(span), Some(first)) = (spans.last_mut(), rest_spans.first()) {
+                        span.end = rebase(first).end;
+                        rest_spans = &rest_spans[1..];
+                    }
+                }
+            }
+            insts.extend_from_slice(rest);
+            spans.extend(rest_spans.iter().map(rebase));
+            if !fragment.insts.is_empty() {
+                merge = fragment.optimized;
+            }
+            starts.push(source_len);
+            source_len += fragment.source_len;
+        }
+        relink(&mut insts);
+        let arith = fragments.first().map_or(ArithMode::Wrap, |f| f.arith);
+        // spans for only some of the instructions are no use
+        if spans.len() != insts.len() {
+            spans.clear();
+        }
+
+        Ok(Brainfuck {
+            jit_code: OnceLock::new(),
+            insts,
+            spans,
+            source_len,
+            optimized: fragments.iter().all(|f| f.optimized),
+            fragments: starts,
+            tape_size: fragments.iter().map(|f| f.tape_size).max().unwrap_or(DEFAULT_TAPE_SIZE),
+            initial_tape: fragments.first().map_or_else(Vec::new, |f| f.initial_tape.clone()),
+            pointer_start: fragments.first().map_or(0, |f| f.pointer_start),
+            max_memory: fragments.iter().map(|f| f.max_memory).min().unwrap_or(usize::MAX),
+            arith,
+            tape_alloc: fragments.first().map_or(TapeAlloc::Default, |f| f.tape_alloc),
+            tape_guard: fragments.first().map_or(0, |f| f.tape_guard),
+            tape_file: fragments.first().and_then(|f| f.tape_file.clone()),
+            precomputed: false,
+            perf_map: None,
+            tape: Tape::default(),
+        })
+    }
+
+    // Stable identity of the program: an FNV-1a hash over the instructions,
+    // the arithmetic and how the tape starts out, so sources differing only
+    // in comments and layout hash identically. It only changes within a
+    // minor version if code generation changes, in which case
+    // `FINGERPRINT_VERSION` is bumped.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hash = 0xcbf2_9ce4_8422_2325u64;
+        let mut feed = |value: u64| {
+            for byte in &value.to_le_bytes() {
+                hash = (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3);
+            }
+        };
+
+        feed(FINGERPRINT_VERSION);
+        feed(self.tape_size as u64);
+        feed(match self.arith {
+            ArithMode::Wrap => 0,
+            ArithMode::Saturate => 1,
+            ArithMode::Trap => 2,
+            // `set_arith_mode` refuses them
+            #[cfg(feature = "bignum")]
+            ArithMode::Unbounded(_) => unreachable!(),
+        });
+        feed(self.pointer_start as u64);
+        feed(self.tape_guard as u64);
+        match self.tape_file {
+            Some((ref path, size)) => {
+                let path = path.to_string_lossy();
+                feed(1 + size as u64);
+                feed(path.len() as u64);
+                path.bytes().for_each(|byte| feed(byte.into()));
+            }
+            None => feed(0),
+        }
+        // after its length, so that it can't run into the instructions
+        feed(self.initial_tape.len() as u64);
+        self.initial_tape.iter().for_each(|&cell| feed(cell.into()));
+        for inst in &self.insts {
+            // jump targets follow from the order of the brackets
+            let (tag, amount) = match *inst {
+                IncPtr(a) => (0, a),
+                DecPtr(a) => (1, a),
+                IncVal(a) => (2, a),
+                DecVal(a) => (3, a),
+                PrintCell => (4, 1),
+                ReadChar => (5, 1),
+                JmpFwd(_) => (6, 1),
+                JmpBack(_) => (7, 1),
+                Ext(op) => (8 + op as u64, 1),
+                HostCall => (17, 1),
+            };
+            feed(tag);
+            feed(amount as u64);
+        }
+
+        hash
+    }
+
+    pub fn tape_size(&self) -> usize {
+        self.tape_size
+    }
+
+    pub fn set_tape_size(&mut self, size: usize) -> Result<(), RuntimeError> {
+        if size == 0 {
+            return Err(RuntimeError::InvalidTapeSize(size));
+        }
+        if self.initial_tape.len() > size {
+            return Err(RuntimeError::InitialTapeTooLarge { size: self.initial_tape.len(), tape_size: size });
+        }
+        if self.pointer_start >= size {
+            return Err(RuntimeError::PointerStartOutOfRange { start: self.pointer_start, tape_size: size });
+        }
+        self.tape_size = size;
+        self.forget_precomputed();
+
+        Ok(())
+    }
+
+    // Cells to copy to the start of the tape before every run, instead of
+    // having the program read them
+    pub fn set_initial_tape(&mut self, cells: &[u8]) -> Result<(), RuntimeError> {
+        if cells.len() > self.tape_size {
+            return Err(RuntimeError::InitialTapeTooLarge { size: cells.len(), tape_size: self.tape_size });
+        }
+        self.initial_tape = cells.to_vec();
+        self.forget_precomputed();
+
+        Ok(())
+    }
+
+    pub fn initial_tape(&self) -> &[u8] {
+        &self.initial_tape
+    }
+
+    // The cell the pointer starts on
+    pub fn set_pointer_start(&mut self, start: usize) -> Result<(), RuntimeError> {
+        if start >= self.tape_size {
+            return Err(RuntimeError::PointerStartOutOfRange { start, tape_size: self.tape_size });
+        }
+        self.pointer_start = start;
+        self.forget_precomputed();
+
+        Ok(())
+    }
+
+    pub fn pointer_start(&self) -> usize {
+        self.pointer_start
+    }
+
+    // Caps the memory a run may allocate, the tape included. Runs
+    // exceeding it fail with `RuntimeError::MemoryLimitExceeded`.
+    pub fn set_max_memory(&mut self, bytes: usize) {
+        self.max_memory = bytes;
+    }
+
+    pub fn max_memory(&self) -> usize {
+        self.max_memory
+    }
+
+    // How `+` and `-` treat the ends of a cell's range, in the interpreter
+    // as well as in the generated code, which is generated again when it's
+    // next needed. There is no code for `ArithMode::Unbounded`, which fails
+    // with `UnsupportedArithMode`.
+    pub fn set_arith_mode(&mut self, mode: ArithMode) -> Result<(), CompileError> {
+        #[cfg(feature = "bignum")]
+        if let ArithMode::Unbounded(_) = mode {
+            return Err(CompileError::UnsupportedArithMode(mode));
+        }
+        self.jit_code = OnceLock::new();
+        self.arith = mode;
+        self.precomputed = false;
+
+        Ok(())
+    }
+
+    // Runs a program that reads no input in the interpreter for at most
+    // `max_steps` instructions and, if it finishes, replaces its code with
+    // code printing what it printed in one go and copying the tape it left
+    // behind into place. Runs then take as long as the output takes to
+    // write. Changing the tape's settings or the arithmetic afterwards goes
+    // back to the normal code, as does anything else than `Output` coming
+    // back. `run_in` without `zero` gets the precomputed tape too, whatever
+    // was on it before.
+    pub fn precompute(&mut self, max_steps: usize) -> Precomputed {
+        if self.insts.contains(&ReadChar) {
+            return Precomputed::ReadsInput;
+        }
+        if self.tape_file.is_some() {
+            return Precomputed::TapeFile;
+        }
+
+        let mut interp = self.interp();
+        interp.set_shortcut_loops(true);
+        let mut output = Vec::new();
+        match interp.run_for(max_steps, io::empty(), &mut output) {
+            StepOutcome::Finished => {}
+            StepOutcome::Error(e) => return Precomputed::Fails(e),
+            StepOutcome::Paused | StepOutcome::NeedsInput => return Precomputed::OutOfSteps(max_steps),
+        }
+
+        let tape = interp.tape();
+        let used = tape.iter().rposition(|&cell| cell != 0).map_or(0, |last| last + 1);
+        if CONSTANT_SIZE as usize + output.len() + used > MAX_CODE_SIZE {
+            return Precomputed::TooLarge;
+        }
+        let mut code = Vec::with_capacity(CONSTANT_SIZE as usize + output.len() + used);
+        emit_constant(&mut code, &output, &tape[..used]).unwrap();
+        self.jit_code = OnceLock::from(code);
+        self.precomputed = true;
+
+        Precomputed::Output(output.len())
+    }
+
+    // Precomputed code only holds for the tape it was precomputed on
+    fn forget_precomputed(&mut self) {
+        if self.precomputed {
+            self.jit_code = OnceLock::new();
+            self.precomputed = false;
+        }
+    }
+
+    pub fn arith_mode(&self) -> ArithMode {
+        self.arith
+    }
+
+    // How to allocate the tape of runs of the generated code. The
+    // interpreter keeps its tape on the heap.
+    pub fn set_tape_alloc(&mut self, alloc: TapeAlloc) {
+        self.tape_alloc = alloc;
+    }
+
+    pub fn tape_alloc(&self) -> TapeAlloc {
+        self.tape_alloc
+    }
+
+    // Puts `cells` cells of `GUARD_BYTE` on either side of the tape of
+    // runs of the generated code, none with 0. The code goes into them
+    // like into the tape, only past them the pointer is off the tape. A
+    // run that changed any of them fails with `RuntimeError::GuardCorrupted`
+    // once it's done, which catches programs that go a few cells off the
+    // tape and back but would otherwise finish. Only `run` and the runs
+    // like it have guards, not the ones on a tape of their own or of the
+    // caller's.
+    pub fn set_tape_guard(&mut self, cells: usize) {
+        self.tape_guard = cells;
+    }
+
+    pub fn tape_guard(&self) -> usize {
+        self.tape_guard
+    }
+
+    // Maps the file at `path` as the tape of runs of the generated code
+    // instead of allocating one: the tape starts out with what the file
+    // holds, without the initial tape, and what the program writes to it
+    // ends up in the file, flushed once the run is over, failed or not. A
+    // file that isn't as long as the tape is resized as `size` says. Only
+    // `run` and the runs like it use the file, without guards, and nothing
+    // is precomputed for them.
+    #[cfg(not(target_family = "wasm"))]
+    pub fn set_tape_file(&mut self, path: Option<PathBuf>, size: TapeFileSize) {
+        self.tape_file = path.map(|path| (path, size));
+        self.forget_precomputed();
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    pub fn tape_file(&self) -> Option<&Path> {
+        self.tape_file.as_ref().map(|(path, _)| path.as_path())
+    }
+
+    // How the tape of the last run that finished was actually allocated
+    pub fn last_tape_alloc(&self) -> TapeAlloc {
+        self.tape.alloc()
+    }
+
+    // The instruction whose code `offset` into the generated code is in, as
+    // `RuntimeError::Fault` tells. `None` past the last one and for
+    // precomputed code, which has none.
+    #[cfg(not(target_family = "wasm"))]
+    pub fn inst_at(&self, offset: usize) -> Option<usize> {
+        if self.precomputed {
+            return None;
+        }
+        let options = CodegenOptions { arith: self.arith, ..CodegenOptions::default() };
+        let (_, offsets) = compile_with_offsets(&self.insts, &options).ok()?;
+        // the last one starting at or before it, `offsets` ends with where
+        // the code after the last instruction starts
+        match offsets.partition_point(|&start| start as usize <= offset) {
+            0 => None,
+            i if i > self.insts.len() => None,
+            i => Some(i - 1),
+        }
+    }
+
+    // Appends symbols for the code to the perf map at `path` every time
+    // it is mapped, before it runs, see `perfmap::symbols`. `spans` are
+    // those of the program's source, if there is one.
+    pub fn set_perf_map(&mut self, path: Option<PathBuf>, spans: Option<&[Span]>) {
+        self.perf_map = path.map(|path| (path, spans.map(<[Span]>::to_vec)));
+    }
+
+    // Appends the symbols of `code`, which polls if `polls`, to the perf map
+    #[cfg(not(target_family = "wasm"))]
+    fn announce(&self, code: *const u8, polls: bool) -> io::Result<()> {
+        let (path, spans) = match self.perf_map {
+            // the symbols are for the instructions, which precomputed code has none of
+            Some((ref path, ref spans)) if !self.precomputed => (path, spans.as_deref()),
+            _ => return Ok(()),
+        };
+        let options = CodegenOptions { arith: self.arith, polls, ..CodegenOptions::default() };
+        match compile_with_offsets(&self.insts, &options) {
+            Ok((generated, offsets)) => {
+                perfmap::append(path, code as usize, &perfmap::symbols(&self.insts, &offsets, generated.len(), spans))
+            }
+            // code that can't be generated never runs to be announced
+            Err(_) => Ok(()),
+        }
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    pub fn run(&mut self) -> Result<(), RuntimeError> {
+        self.run_cancellable(None, None)
+    }
+
+    // Like `run`, failing with `RuntimeError::Cancelled` the next time a
+    // loop goes round once `cancel` is set, e.g. from a signal handler
+    #[cfg(not(target_family = "wasm"))]
+    pub fn run_until(&mut self, cancel: &AtomicBool) -> Result<(), RuntimeError> {
+        self.run_cancellable(Some(cancel), None)
+    }
+
+    #[cfg(target_family = "wasm")]
+    pub fn run_until(&mut self, cancel: &AtomicBool) -> Result<(), RuntimeError> {
+        MemoryBudget::new(self.max_memory).charge(self.tape_size)?;
+        let mut interp = self.interp();
+        interp.set_cancel(Some(cancel));
+        interp.run(io::stdin().lock(), io::stdout().lock())?;
+        self.tape = interp.tape().to_vec().into();
+
+        Ok(())
+    }
+
+    // `run`, returning what's known of the run. Generated code counts
+    // nothing, only the wall time is, see `RunStats`.
+    pub fn run_with_stats(&mut self) -> Result<RunStats, RuntimeError> {
+        let started = ::std::time::Instant::now();
+        self.run()?;
+        Ok(RunStats { wall_time: started.elapsed(), ..RunStats::default() })
+    }
+
+    // Like `run`, with the program's host calls going to `host`, see
+    // `Dialect::HostCalls`
+    #[cfg(not(target_family = "wasm"))]
+    pub fn run_with_host(&mut self, host: &mut HostFunctions) -> Result<(), RuntimeError> {
+        self.run_cancellable(None, Some(host))
+    }
+
+    // There's no mapping code executable on WebAssembly, the interpreter
+    // runs the program instead, on the process' stdin and stdout all the same
+    #[cfg(target_family = "wasm")]
+    pub fn run(&mut self) -> Result<(), RuntimeError> {
+        let stdin = io::stdin();
+        let stdout = io::stdout();
+        self.interpret(stdin.lock(), stdout.lock())
+    }
+
+    // Runs the program with every byte it prints going to `on_output` and
+    // every byte it reads coming from `on_input`, `None` being the end of
+    // the input, which leaves the cell unchanged. Generated code can only
+    // do I/O on stdin and stdout, so this runs in the interpreter. A
+    // panicking hook ends the run with `RuntimeError::HookPanicked`.
+    pub fn run_with_hooks(
+        &mut self, mut on_output: impl FnMut(u8), mut on_input: impl FnMut() -> Option<u8>
+    ) -> Result<(), RuntimeError> {
+        let output = HookOutput(&mut on_output);
+        let input = HookInput(&mut on_input);
+        catch_hook_panic(|| self.interpret(input, output))
+    }
+
+    // Runs the program on `input` with what it prints going to `chunk` in
+    // pieces as `buffering` flushes them, so a program printing as it goes
+    // can be followed while it runs. The program waits for `chunk` to
+    // return. This runs in the interpreter, a panicking `chunk` ends the
+    // run with `RuntimeError::HookPanicked`. What was printed before a
+    // failure is still handed on.
+    pub fn run_streaming<R: Read>(
+        &mut self, input: R, buffering: Buffering, chunk: impl FnMut(&[u8])
+    ) -> Result<(), RuntimeError> {
+        let stream = RefCell::new(Stream { buf: Vec::with_capacity(STREAM_BUFFER), buffering, chunk });
+        catch_hook_panic(|| self.interpret(StreamInput(input, &stream), StreamOutput(&stream)))
+    }
+
+    // Runs the program in the interpreter, keeping the tape it leaves behind
+    fn interpret<R: Read, W: Write>(&mut self, input: R, output: W) -> Result<(), RuntimeError> {
+        self.interpret_with_host(input, output, &mut HostFunctions::new())
+    }
+
+    // Runs the program in the interpreter on `input` and `output`, with
+    // its host calls going to `host`. A panicking host function ends the
+    // run with `RuntimeError::HookPanicked`.
+    pub fn interpret_with_host<R: Read, W: Write>(
+        &mut self, input: R, output: W, host: &mut HostFunctions
+    ) -> Result<(), RuntimeError> {
+        let _stage = stage!("run", engine = "interp");
+        MemoryBudget::new(self.max_memory).charge(self.tape_size)?;
+        let mut interp = self.interp();
+        catch_hook_panic(|| interp.run_with_host(input, output, host))?;
+        self.tape = interp.tape().to_vec().into();
+
+        Ok(())
+    }
+
+    // The program's steps in the interpreter, see `Interp::step`. They are
+    // taken as the iterator is advanced, a program that never finishes
+    // steps forever. What it prints only goes into the steps.
+    pub fn steps<R: Read>(&self, input: R) -> Steps<'_, R> {
+        self.interp().into_steps(input)
+    }
+
+    // An interpreter about to run the program
+    fn interp(&self) -> Interp<'_> {
+        let mut interp = Interp::new(&self.insts, self.tape_size);
+        interp.tape_mut()[..self.initial_tape.len()].copy_from_slice(&self.initial_tape);
+        interp.set_ptr(self.pointer_start);
+        interp.set_arith_mode(self.arith);
+        interp
+    }
+
+    // Runs the program until it finishes or, with a `cancel` flag, until
+    // the flag is set, which code generated for that checks every time a
+    // loop goes round. The code of `jit_code` doesn't check anything.
+    #[cfg(not(target_family = "wasm"))]
+    fn run_cancellable(&mut self, cancel: Option<&AtomicBool>, host: Option<&mut HostFunctions>) -> Result<(), RuntimeError> {
+        let mapped = map_code(&self.code(cancel.is_some())?)?;
+        let _stage = stage!("run", engine = "jit", code_size = mapped.len());
+        mapped.with_code(|code| {
+            self.announce(code, cancel.is_some())?;
+            let code = unsafe { ::std::slice::from_raw_parts(code, mapped.len()) };
+            self.run_code(code, cancel, host)
+        })
+    }
+
+    // `run_cancellable` once the code is mapped
+    #[cfg(not(target_family = "wasm"))]
+    fn run_code(&mut self, code: &[u8], cancel: Option<&AtomicBool>, host: Option<&mut HostFunctions>) -> Result<(), RuntimeError> {
+        let required = min_tape_size(&self.insts);
+        let budget = MemoryBudget::new(self.max_memory);
+        let (tape_size, start, guard) = (self.tape_size, self.pointer_start, self.tape_guard);
+        let never = AtomicBool::new(false);
+        let io = CallIo { host, ..CallIo::stdio(cancel.unwrap_or(&never)) };
+        if let Some((ref path, size)) = self.tape_file {
+            let mut tape = file_tape(path, size, required, start, tape_size, &budget)?;
+            let result = call_in(code, io, required, &mut tape, start);
+            let flushed = tape.flush();
+            self.tape = tape;
+            result?;
+            flushed?;
+            return Ok(());
+        }
+        // precomputed code only writes the tape it was precomputed on
+        if guard == 0 || self.precomputed {
+            let mut tape = fresh_tape(required, start, tape_size, &self.initial_tape, self.tape_alloc, &budget)?;
+            call_in(code, io, required, &mut tape, start)?;
+            self.tape = tape;
+            return Ok(());
+        }
+
+        // what the program touches before its first loop may be in the guards
+        if start + required > tape_size + guard {
+            return Err(RuntimeError::TapeTooSmall { required: start + required, tape_size });
+        }
+        let mut guarded = fresh_tape(0, 0, tape_size + 2 * guard, &[], self.tape_alloc, &budget)?;
+        guarded[..guard].fill(GUARD_BYTE);
+        guarded[guard + tape_size..].fill(GUARD_BYTE);
+        guarded[guard..guard + self.initial_tape.len()].copy_from_slice(&self.initial_tape);
+        call_in(code, io, required, &mut guarded, start + guard)?;
+        check_guard(&guarded, guard)?;
+        let mut tape = Tape::new(tape_size, self.tape_alloc);
+        tape.copy_from_slice(&guarded[guard..guard + tape_size]);
+        self.tape = tape;
+
+        Ok(())
+    }
+
+    // Runs the program on `tape` instead of a tape of its own, which saves
+    // allocating and zeroing one for every run. The tape has to be exactly
+    // as long as the program's. With `zero` it's cleared and starts like
+    // any other run, otherwise the program continues on whatever the tape
+    // holds, e.g. what another program left there, without the initial
+    // tape. The tape isn't charged to the memory limit and `tape` still
+    // returns the one of the last `run`.
+    #[cfg(not(target_family = "wasm"))]
+    pub fn run_in(&mut self, tape: &mut [u8], zero: bool) -> Result<(), RuntimeError> {
+        prepare_tape(tape, self.tape_size, zero, &self.initial_tape)?;
+        let mapped = map_code(self.jit_code()?)?;
+
+        let required = min_tape_size(&self.insts);
+        mapped.with_code(|code| {
+            self.announce(code, false)?;
+            let code = unsafe { ::std::slice::from_raw_parts(code, mapped.len()) };
+            call_in(code, CallIo::stdio(&AtomicBool::new(false)), required, tape, self.pointer_start)
+        })
+    }
+
+    // The program to run tiered, with the same tape, see `TieredProgram`.
+    // Nothing is compiled until a loop gets hot.
+    #[cfg(not(target_family = "wasm"))]
+    pub fn tiered(&self) -> TieredProgram {
+        TieredProgram {
+            initial_tape: self.initial_tape.clone(),
+            pointer_start: self.pointer_start,
+            tape_size: self.tape_size,
+            max_memory: self.max_memory,
+            arith: self.arith,
+            ..TieredProgram::from_insts(self.insts.clone()).unwrap()
+        }
+    }
+
+    // Runs the program with the process restricted to reading stdin,
+    // writing stdout and stderr and exiting, see `sandbox`. That can't be
+    // undone, so there's no coming back from a run: the process exits as
+    // soon as the program finishes. Returns only if the program didn't
+    // run, nothing is restricted then.
+    #[cfg(target_os = "linux")]
+    pub fn run_sandboxed(&self) -> Result<Infallible, RuntimeError> {
+        let mapped = map_code(self.jit_code()?)?;
+
+        let required = min_tape_size(&self.insts);
+        mapped.with_code(|code| self.announce(code, false))?;
+        let budget = MemoryBudget::new(self.max_memory);
+        let tape = fresh_tape(
+            required, self.pointer_start, self.tape_size, &self.initial_tape, self.tape_alloc, &budget
+        )?;
+        mapped.with_code(|code| call_sandboxed(code, tape, self.pointer_start))
+    }
+
+    // Places the code in `arena` instead of mapping it for every run, with
+    // the program's current tape
+    #[cfg(not(target_family = "wasm"))]
+    pub fn compile_into(&self, arena: &JitArena) -> Result<JitProgram, CompileError> {
+        let code = arena.install(self.jit_code()?)?;
+        code.with_code(|code| self.announce(code, false))?;
+
+        Ok(JitProgram {
+            code,
+            tape_size: self.tape_size,
+            initial_tape: self.initial_tape.clone(),
+            pointer_start: self.pointer_start,
+            max_memory: self.max_memory,
+            tape_alloc: self.tape_alloc,
+            required: min_tape_size(&self.insts),
+            tape: Tape::default(),
+        })
+    }
+
+    // Maps the code once for a `SharedProgram`, with the program's current
+    // tape. Its runs can be cancelled, so the code polls.
+    #[cfg(not(target_family = "wasm"))]
+    pub fn share(&self) -> Result<SharedProgram, CompileError> {
+        let jit_code = self.code(true)?;
+        let arena = JitArena::new(jit_code.len(), Protection::WriteXorExecute)?;
+        let code = arena.install(&jit_code)?;
+        code.with_code(|code| self.announce(code, true))?;
+
+        Ok(SharedProgram {
+            code: Arc::new(code),
+            tape_size: self.tape_size,
+            initial_tape: self.initial_tape.clone().into(),
+            pointer_start: self.pointer_start,
+            max_memory: self.max_memory,
+            required: min_tape_size(&self.insts),
+        })
+    }
+
+    // Runs the program on a thread of its own, the returned handle can
+    // cancel it. The program still reads and writes the process' stdin and
+    // stdout.
+    #[cfg(not(target_family = "wasm"))]
+    pub fn spawn(mut self) -> RunHandle {
+        let cancel = Arc::new(AtomicBool::new(false));
+        let flag = cancel.clone();
+        let thread = thread::spawn(move || {
+            self.run_cancellable(Some(&flag), None).map(|_| RunOutcome { program: self })
+        });
+
+        RunHandle { thread, cancel }
+    }
+
+    // The tape as left behind by the last run, empty if the program was never run
+    pub fn tape(&self) -> &[u8] {
+        &self.tape
+    }
+
+    pub fn dump(&self) {
+        self.write_dump(io::stdout()).unwrap();
+    }
+
+    // The instructions a line each, see `dump::write_plain`
+    pub fn write_dump<W: Write>(&self, out: W) -> io::Result<()> {
+        ::dump::write_plain(&self.insts, out)
+    }
+
+    // The generated code, generated the first time it's needed with the
+    // settings at that time. A program whose code can't be generated still
+    // parsed, its instructions can be dumped or interpreted.
+    pub fn jit_code(&self) -> Result<&[u8], CompileError> {
+        if let Some(code) = self.jit_code.get() {
+            return Ok(code);
+        }
+        let code = compile(&self.insts, self.arith)?;
+        Ok(self.jit_code.get_or_init(|| code))
+    }
+
+    // `jit_code`, or with `polls` code checking the cancel flag every time
+    // a loop goes round, which is generated again every time. Precomputed
+    // code has no loops to go round.
+    fn code(&self, polls: bool) -> Result<Cow<'_, [u8]>, CompileError> {
+        if !polls || self.precomputed {
+            return self.jit_code().map(Cow::Borrowed);
+        }
+        compile_with(&self.insts, &CodegenOptions { arith: self.arith, polls, ..CodegenOptions::default() }).map(Cow::Owned)
+    }
+
+    // Size of the generated code in bytes
+    pub fn code_size(&self) -> Result<usize, CompileError> {
+        self.jit_code().map(<[u8]>::len)
+    }
+
+    pub fn dump_jit(&self) -> Result<(), CompileError> {
+        Ok(io::stdout().write_all(self.jit_code()?)?)
+    }
+
+    // The generated code as a hexdump, which is safe to print to a terminal
+    pub fn dump_jit_hex<W: Write>(&self, out: W) -> Result<(), CompileError> {
+        Ok(write_hexdump(self.jit_code()?, out)?)
+    }
+
+}
+
+// A panic in a hook as the error ending the run
+fn catch_hook_panic<F: FnOnce() -> Result<(), RuntimeError>>(run: F) -> Result<(), RuntimeError> {
+    match panic::catch_unwind(AssertUnwindSafe(run)) {
+        Ok(result) => result,
+        Err(payload) => {
+            let message = payload.downcast_ref::<&str>().map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown".to_string());
+            Err(RuntimeError::HookPanicked(message))
+        }
+    }
+}
+
+// The output buffer of `Brainfuck::run_streaming`, which its input flushes too
+struct Stream<F> {
+    buf: Vec<u8>,
+    buffering: Buffering,
+    chunk: F,
+}
+
+impl<F: FnMut(&[u8])> Stream<F> {
+    fn flush(&mut self) {
+        if !self.buf.is_empty() {
+            (self.chunk)(&self.buf);
+            self.buf.clear();
+        }
+    }
+}
+
+struct StreamOutput<'a, F: 'a>(&'a RefCell<Stream<F>>);
+
+impl<'a, F: FnMut(&[u8])> Write for StreamOutput<'a, F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut stream = self.0.borrow_mut();
+        for &byte in buf {
+            stream.buf.push(byte);
+            if stream.buf.len() == STREAM_BUFFER || (byte == b'\n' && stream.buffering == Buffering::Line) {
+                stream.flush();
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.borrow_mut().flush();
+        Ok(())
+    }
+}
+
+struct StreamInput<'a, R, F: 'a>(R, &'a RefCell<Stream<F>>);
+
+impl<'a, R: Read, F: FnMut(&[u8])> Read for StreamInput<'a, R, F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.1.borrow_mut().flush();
+        self.0.read(buf)
+    }
+}
+
+struct HookOutput<F>(F);
+
+impl<F: FnMut(u8)> Write for HookOutput<F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            (self.0)(byte);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct HookInput<F>(F);
+
+impl<F: FnMut() -> Option<u8>> Read for HookInput<F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        match (self.0)() {
+            Some(byte) => {
+                buf[0] = byte;
+                Ok(1)
+            }
+            None => Ok(0),
+        }
+    }
+}
+
+// The layout of `hexdump -C`: the offset, 16 bytes in two groups of
+// eight and the printable ones as ASCII, then the total length
+pub fn write_hexdump<W: Write>(bytes: &[u8], mut out: W) -> io::Result<()> {
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        let mut hex = String::new();
+        for i in 0..16 {
+            match chunk.get(i) {
+                Some(byte) => hex.push_str(&format!("{:02x} ", byte)),
+                None => hex.push_str("   "),
+            }
+            if i == 7 {
+                hex.push(' ');
+            }
+        }
+        let ascii: String = chunk.iter()
+            .map(|&byte| if (0x20..0x7f).contains(&byte) { byte as char } else { '.' })
+            .collect();
+        writeln!(out, "{:08x}  {} |{}|", line * 16, hex, ascii)?;
+    }
+
+    writeln!(out, "{:08x}", bytes.len())
+}
+
+// Reads and writes a file descriptor without buffering anything, so that
+// the interpreter and generated code can take turns on it. Like `,` and `.`
+// in generated code every call is made again for as long as it's
+// interrupted or would block.
+#[cfg(not(target_family = "wasm"))]
+struct RawIo(RawFd);
+
+#[cfg(not(target_family = "wasm"))]
+impl RawIo {
+    fn retry<F: FnMut() -> isize>(mut call: F) -> io::Result<usize> {
+        loop {
+            let n = call();
+            if n >= 0 {
+                return Ok(n as usize);
+            }
+            let e = io::Error::last_os_error();
+            match e.kind() {
+                io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock => {}
+                _ => return Err(e),
+            }
+        }
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl Read for RawIo {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        RawIo::retry(|| unsafe { libc::read(self.0, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) })
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl Write for RawIo {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        RawIo::retry(|| unsafe { libc::write(self.0, buf.as_ptr() as *const libc::c_void, buf.len()) })
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// What a run of generated code gets besides the tape: the flag cancelling
+// it, the file descriptors it reads from and writes to and the functions
+// its host calls go to, every one of them failing without any
+#[cfg(not(target_family = "wasm"))]
+struct CallIo<'a, 'h: 'a> {
+    cancel: &'a AtomicBool,
+    input: libc::c_int,
+    output: libc::c_int,
+    host: Option<&'a mut HostFunctions<'h>>,
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<'a, 'h> CallIo<'a, 'h> {
+    fn stdio(cancel: &'a AtomicBool) -> CallIo<'a, 'h> {
+        CallIo { cancel, input: libc::STDIN_FILENO, output: libc::STDOUT_FILENO, host: None }
+    }
+
+    // The frame of a run on `tape`, `host` has to outlive it
+    fn frame(&self, tape: &[u8], host: &mut HostState) -> Frame {
+        let range = tape.as_ptr_range();
+        Frame {
+            cancel: self.cancel,
+            input: self.input,
+            output: self.output,
+            tape_start: range.start,
+            tape_end: range.end,
+            storage: 0,
+            io_error: 0,
+            host_call: call_host,
+            host: host as *mut HostState as *mut libc::c_void,
+        }
+    }
+}
+
+// What `call_host` finds behind `Frame::host`: the functions of the run and
+// why the host call that ended it failed
+#[cfg(not(target_family = "wasm"))]
+struct HostState<'a, 'h: 'a> {
+    functions: Option<&'a mut HostFunctions<'h>>,
+    failure: Option<HostFailure>,
+}
+
+#[cfg(not(target_family = "wasm"))]
+enum HostFailure {
+    // nothing is registered for the value the cell had
+    Unregistered(u8),
+    Failed(RuntimeError),
+}
+
+// `Frame::host_call`, running the host function the cell selects. Returns 0
+// once it succeeded, anything else makes the code leave with
+// `STATUS_HOST_FAILED`. A panicking function fails the call with
+// `RuntimeError::HookPanicked`, it can't unwind through the code.
+#[cfg(not(target_family = "wasm"))]
+extern "C" fn call_host(frame: *mut Frame, cell: *mut u8) -> u32 {
+    let frame = unsafe { &*frame };
+    let state = unsafe { &mut *(frame.host as *mut HostState) };
+    let len = frame.tape_end as usize - frame.tape_start as usize;
+    let tape = unsafe { ::std::slice::from_raw_parts_mut(frame.tape_start as *mut u8, len) };
+    let ptr = cell as usize - frame.tape_start as usize;
+
+    let id = tape[ptr];
+    let result = match state.functions.as_mut().and_then(|functions| functions.get_mut(id)) {
+        Some(function) => catch_hook_panic(|| function(tape, ptr)).map_err(HostFailure::Failed),
+        None => Err(HostFailure::Unregistered(id)),
+    };
+    match result {
+        Ok(()) => 0,
+        Err(failure) => {
+            state.failure = Some(failure);
+            1
+        }
+    }
+}
+
+// What a way out of the generated code means for the run, `host` is the
+// failure of a host call left in its `HostState` and `io_error` the errno
+// the code left in its frame
+#[cfg(not(target_family = "wasm"))]
+fn exit_result(exit: Exit, host: Option<HostFailure>, io_error: i32) -> Result<(), RuntimeError> {
+    let inst_index = exit.aux as usize;
+    match exit.status as u32 {
+        STATUS_FINISHED => Ok(()),
+        STATUS_POINTER_UNDERFLOW => Err(RuntimeError::PointerUnderflow { inst_index }),
+        STATUS_POINTER_OVERFLOW => Err(RuntimeError::PointerOverflow { inst_index }),
+        STATUS_CANCELLED => Err(RuntimeError::Cancelled),
+        STATUS_CELL_OVERFLOW => Err(RuntimeError::CellOverflow { inst_index }),
+        STATUS_CELL_UNDERFLOW => Err(RuntimeError::CellUnderflow { inst_index }),
+        STATUS_HOST_FAILED => Err(match host.expect("a host call failed without a reason") {
+            HostFailure::Unregistered(id) => RuntimeError::UnregisteredHostCall { id, inst_index },
+            HostFailure::Failed(e) => e,
+        }),
+        STATUS_IO_FAILED => Err(RuntimeError::Io(io::Error::from_raw_os_error(io_error))),
+        status => panic!("generated code returned unknown status {}", status),
+    }
+}
+
+// Runs generated code on a fresh tape starting with `initial`, which is
+// returned if the code ran to completion. The pointer starts on `start`,
+// the code needs `required` cells from there. The tape is charged to
+// `budget`. Crashes in the code become `RuntimeError::Fault`.
+#[cfg(not(target_family = "wasm"))]
+fn call(
+    code: &[u8], io: CallIo, required: usize, tape_size: usize, initial: &[u8], start: usize,
+    budget: &MemoryBudget
+) -> Result<Vec<u8>, RuntimeError> {
+    let mut tape = fresh_tape(required, start, tape_size, initial, TapeAlloc::Default, budget)?;
+    call_in(code, io, required, &mut tape, start)?;
+
+    Ok(tape.into_vec())
+}
+
+// The tape `call` runs on, allocated as `alloc` asks for
+fn fresh_tape(
+    required: usize, start: usize, tape_size: usize, initial: &[u8], alloc: TapeAlloc, budget: &MemoryBudget
+) -> Result<Tape, RuntimeError> {
+    if start + required > tape_size {
+        return Err(RuntimeError::TapeTooSmall { required: start + required, tape_size });
+    }
+    budget.charge(tape_size)?;
+
+    let mut tape = Tape::new(tape_size, alloc);
+    tape[..initial.len()].copy_from_slice(initial);
+
+    Ok(tape)
+}
+
+// The tape `run` maps from the file at `path`, see `Brainfuck::set_tape_file`
+#[cfg(not(target_family = "wasm"))]
+fn file_tape(
+    path: &Path, size: TapeFileSize, required: usize, start: usize, tape_size: usize, budget: &MemoryBudget
+) -> Result<Tape, RuntimeError> {
+    if start + required > tape_size {
+        return Err(RuntimeError::TapeTooSmall { required: start + required, tape_size });
+    }
+    budget.charge(tape_size)?;
+
+    let file = OpenOptions::new().read(true).write(true).open(path)?;
+    let len = file.metadata()?.len();
+    if len != tape_size as u64 {
+        if size == TapeFileSize::Exact {
+            return Err(RuntimeError::TapeFileSizeMismatch { len, tape_size });
+        }
+        file.set_len(tape_size as u64)?;
+    }
+
+    Ok(Tape::map_file(&file, tape_size)?)
+}
+
+// What the guards around the tape hold until the program changes them, see
+// `Brainfuck::set_tape_guard`
+pub const GUARD_BYTE: u8 = 0xa5;
+
+// Cells of guard `--tape-guard` puts on either side of the tape
+pub const DEFAULT_TAPE_GUARD: usize = 64;
+
+// Fails with `RuntimeError::GuardCorrupted` if any of the `guard` cells at
+// either end of `guarded` doesn't hold `GUARD_BYTE` anymore
+fn check_guard(guarded: &[u8], guard: usize) -> Result<(), RuntimeError> {
+    let cells = (0..guard).chain(guarded.len() - guard..guarded.len());
+    let corrupted = |&i: &usize| guarded[i] != GUARD_BYTE;
+    match (cells.clone().find(corrupted), cells.rev().find(corrupted)) {
+        (Some(lowest), Some(highest)) => Err(RuntimeError::GuardCorrupted {
+            lowest: lowest as isize - guard as isize,
+            highest: highest as isize - guard as isize,
+        }),
+        _ => Ok(()),
+    }
+}
+
+// `call` on a tape the caller provides, as it is
+#[cfg(not(target_family = "wasm"))]
+fn call_in(code: &[u8], mut io: CallIo, required: usize, tape: &mut [u8], start: usize) -> Result<(), RuntimeError> {
+    if start + required > tape.len() {
+        return Err(RuntimeError::TapeTooSmall { required: start + required, tape_size: tape.len() });
+    }
+    let func: JitFn = unsafe {
+        mem::transmute(code.as_ptr())
+    };
+
+    let mut host = HostState { functions: io.host.take(), failure: None };
+    let mut frame = io.frame(tape, &mut host);
+    let guard = FaultGuard::enter(code)?;
+    let exit = func(&mut frame, tape[start..].as_mut_ptr());
+    if let Some(fault) = guard.take_fault() {
+        let (signal, address, rip) = (fault.signal, fault.address, fault.rip);
+        return Err(RuntimeError::Fault { signal, address, rip });
+    }
+
+    exit_result(exit, host.failure, frame.io_error)
+}
+
+// Maps `code` for a run on its own, executable but never writable at the
+// same time, see `Protection::WriteXorExecute`
+#[cfg(not(target_family = "wasm"))]
+fn map_code(code: &[u8]) -> Result<ArenaCode, RuntimeError> {
+    let arena = JitArena::new(code.len(), Protection::WriteXorExecute).map_err(CompileError::from)?;
+    Ok(arena.install(code)?)
+}
+
+// The code of the loop `insts`, from its `[` to its `]`, as a fragment
+#[cfg(not(target_family = "wasm"))]
+fn compile_fragment(insts: &[Inst], arith: ArithMode) -> Result<Vec<u8>, CompileError> {
+    let mut insts = insts.to_vec();
+    relink(&mut insts);
+    compile_with(&insts, &CodegenOptions { arith, fragment: true, ..CodegenOptions::default() })
+}
+
+// Maps a fragment from `compile_fragment` into `arena`, or into a new
+// arena that takes its place if it's full
+#[cfg(not(target_family = "wasm"))]
+fn install_fragment(arena: &mut Option<JitArena>, code: &[u8]) -> Result<ArenaCode, RuntimeError> {
+    if let Some(ref arena) = *arena {
+        match arena.install(code) {
+            Err(CompileError::ArenaFull { .. }) => {}
+            installed => return Ok(installed?),
+        }
+    }
+    let fresh = JitArena::new(code.len().max(FRAGMENT_ARENA), Protection::WriteXorExecute)
+        .map_err(CompileError::from)?;
+    let installed = fresh.install(code)?;
+    *arena = Some(fresh);
+    Ok(installed)
+}
+
+// Runs a fragment from `install_fragment` on `tape` with the pointer on
+// `ptr` and the storage register holding `storage`, and returns where the
+// pointer ended up and whether the fragment ran into `@`. The loop starts
+// at instruction `start` of the program, which errors are reported against.
+#[cfg(not(target_family = "wasm"))]
+fn call_fragment(
+    code: &ArenaCode, mut io: CallIo, tape: &mut [u8], ptr: usize, storage: &mut u8, start: usize
+) -> Result<(usize, bool), RuntimeError> {
+    code.with_code(|mapped| {
+        let code = unsafe { ::std::slice::from_raw_parts(mapped, code.len()) };
+        let func: JitFn = unsafe {
+            mem::transmute(code.as_ptr())
+        };
+
+        let mut host = HostState { functions: io.host.take(), failure: None };
+        let mut frame = Frame { storage: *storage, ..io.frame(tape, &mut host) };
+        let guard = FaultGuard::enter(code)?;
+        let exit = func(&mut frame, tape[ptr..].as_mut_ptr());
+        if let Some(fault) = guard.take_fault() {
+            let (signal, address, rip) = (fault.signal, fault.address, fault.rip);
+            return Err(RuntimeError::Fault { signal, address, rip });
+        }
+        *storage = frame.storage;
+
+        match exit.status as u32 {
+            STATUS_FINISHED => Ok((exit.aux as usize - tape.as_ptr() as usize, false)),
+            STATUS_HALTED => Ok((exit.aux as usize - tape.as_ptr() as usize, true)),
+            _ => {
+                let exit = Exit { status: exit.status, aux: exit.aux + start as u64 };
+                exit_result(exit, host.failure, frame.io_error).map(|_| (ptr, false))
+            }
+        }
+    })
+}
+
+// Checks a tape passed to `run_in` and, if asked to, clears it and copies
+// `initial` to its start
+fn prepare_tape(tape: &mut [u8], tape_size: usize, zero: bool, initial: &[u8]) -> Result<(), RuntimeError> {
+    if tape.len() != tape_size {
+        return Err(RuntimeError::TapeLengthMismatch { len: tape.len(), tape_size });
+    }
+    if zero {
+        tape.fill(0);
+        tape[..initial.len()].copy_from_slice(initial);
+    }
+
+    Ok(())
+}
+
+// Like `call`, but enters the sandbox right before jumping into the code
+// and exits the process once it returns: with 0 if the program finished,
+// with 2 after reporting the error on stderr if it didn't, like the
+// command line tool does for runtime errors
+#[cfg(target_os = "linux")]
+fn call_sandboxed(code: *const u8, mut tape: Tape, start: usize) -> Result<Infallible, RuntimeError> {
+    let cancel = AtomicBool::new(false);
+    let mut host = HostState { functions: None, failure: None };
+    let mut frame = CallIo::stdio(&cancel).frame(&tape, &mut host);
+    let func: JitFn = unsafe {
+        mem::transmute(code)
+    };
+
+    // nothing buffered gets out once only `_exit` is left
+    io::stdout().flush()?;
+    ::sandbox::enter().map_err(RuntimeError::SandboxFailed)?;
+    let exit = func(&mut frame, tape[start..].as_mut_ptr());
+    if let Err(e) = exit_result(exit, host.failure, frame.io_error) {
+        // formatted on the stack, allocating may need more than the
+        // sandbox allows
+        let mut message = [0u8; 256];
+        let mut cursor = Cursor::new(&mut message[..]);
+        let _ = writeln!(cursor, "error: {}", e);
+        let len = cursor.position() as usize;
+        unsafe {
+            libc::write(libc::STDERR_FILENO, message.as_ptr() as *const libc::c_void, len);
+            libc::_exit(2)
+        }
+    }
+    unsafe { libc::_exit(0) }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl JitProgram {
+    pub fn run(&mut self) -> Result<(), RuntimeError> {
+        let (required, tape_size) = (self.required, self.tape_size);
+        let (initial, start) = (&self.initial_tape, self.pointer_start);
+        let cancel = AtomicBool::new(false);
+        let budget = MemoryBudget::new(self.max_memory);
+        let mut tape = fresh_tape(required, start, tape_size, initial, self.tape_alloc, &budget)?;
+        let len = self.code.len();
+        self.code.with_code(|code| {
+            let code = unsafe { ::std::slice::from_raw_parts(code, len) };
+            call_in(code, CallIo::stdio(&cancel), required, &mut tape, start)
+        })?;
+        self.tape = tape;
+
+        Ok(())
+    }
+
+    // `Brainfuck::run_in` without mapping the code again
+    pub fn run_in(&mut self, tape: &mut [u8], zero: bool) -> Result<(), RuntimeError> {
+        prepare_tape(tape, self.tape_size, zero, &self.initial_tape)?;
+        let (required, start) = (self.required, self.pointer_start);
+        let cancel = AtomicBool::new(false);
+        let len = self.code.len();
+        self.code.with_code(|code| {
+            let code = unsafe { ::std::slice::from_raw_parts(code, len) };
+            call_in(code, CallIo::stdio(&cancel), required, tape, start)
+        })
+    }
+
+    // The tape as left behind by the last run, empty if the program was never run
+    pub fn tape(&self) -> &[u8] {
+        &self.tape
+    }
+}
+
+// Calls `run` with the file descriptors of a pipe each way, `input` going
+// into the first and what comes out of the second collected. The program
+// doesn't have to read all of it.
+#[cfg(not(target_family = "wasm"))]
+fn piped<T, F>(input: &[u8], run: F) -> Result<(T, Vec<u8>), RuntimeError>
+    where F: FnOnce(RawFd, RawFd) -> Result<T, RuntimeError>
+{
+    let (in_reader, mut in_writer) = io::pipe()?;
+    let (mut out_reader, out_writer) = io::pipe()?;
+    thread::scope(|scope| {
+        // fails with a broken pipe once the program finished early,
+        // which leaves nothing to do anyway
+        scope.spawn(move || in_writer.write_all(input));
+        let reader = scope.spawn(move || {
+            let mut output = Vec::new();
+            out_reader.read_to_end(&mut output).map(|_| output)
+        });
+
+        let result = run(in_reader.as_raw_fd(), out_writer.as_raw_fd());
+        // the reader only sees the end of the output with every
+        // writing end closed
+        drop((in_reader, out_writer));
+        let output = reader.join().expect("output reader panicked")?;
+
+        Ok((result?, output))
+    })
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl SharedProgram {
+    // Runs the program on a fresh tape, reading from the file descriptor
+    // `input` and writing to `output`, and returns the tape it leaves
+    // behind. Stops early with `RuntimeError::Cancelled` once `cancel` is set.
+    pub fn run_fds(
+        &self, input: RawFd, output: RawFd, cancel: &AtomicBool
+    ) -> Result<Vec<u8>, RuntimeError> {
+        self.call_io(CallIo { cancel, input, output, host: None })
+    }
+
+    fn call_io(&self, io: CallIo) -> Result<Vec<u8>, RuntimeError> {
+        let _stage = stage!("run", engine = "jit", code_size = self.code.len());
+        let (required, tape_size) = (self.required, self.tape_size);
+        let (initial, start) = (&self.initial_tape[..], self.pointer_start);
+        let budget = MemoryBudget::new(self.max_memory);
+        let len = self.code.len();
+        self.code.with_code(|code| {
+            let code = unsafe { ::std::slice::from_raw_parts(code, len) };
+            call(code, io, required, tape_size, initial, start, &budget)
+        })
+    }
+
+    // Runs the program with `input` as what it reads, through a pipe each
+    // way. The program doesn't have to read all of it.
+    pub fn run(&self, input: &[u8]) -> Result<Execution, RuntimeError> {
+        self.run_cancellable(input, &AtomicBool::new(false))
+    }
+
+    // Like `run`, stopping early with `RuntimeError::Cancelled` once `cancel` is set
+    pub fn run_cancellable(&self, input: &[u8], cancel: &AtomicBool) -> Result<Execution, RuntimeError> {
+        let (tape, output) = piped(input, |input, output| self.run_fds(input, output, cancel))?;
+        Ok(Execution { tape, output })
+    }
+
+    // Like `run`, with the program's host calls going to `host`
+    pub fn run_with_host(&self, input: &[u8], host: &mut HostFunctions) -> Result<Execution, RuntimeError> {
+        let cancel = AtomicBool::new(false);
+        let (tape, output) = piped(input, |input, output| {
+            self.call_io(CallIo { cancel: &cancel, input, output, host: Some(host) })
+        })?;
+        Ok(Execution { tape, output })
+    }
+
+    // Runs the program once for every input on a fresh tape, up to
+    // `options.jobs` runs at once, and returns what each printed in the order
+    // of `inputs`. A failed run fails on its own, the others carry on.
+    pub fn run_many(&self, inputs: &[&[u8]], options: RunManyOptions) -> Vec<Result<Vec<u8>, RuntimeError>> {
+        let jobs = options.jobs.max(1).min(inputs.len());
+        if jobs <= 1 {
+            return inputs.iter().map(|input| self.run_timed(input, options.timeout)).collect();
+        }
+
+        let next = &AtomicUsize::new(0);
+        let mut results: Vec<Option<Result<Vec<u8>, RuntimeError>>> = (0..inputs.len()).map(|_| None).collect();
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..jobs)
+                .map(|_| {
+                    scope.spawn(move || {
+                        let mut done = Vec::new();
+                        loop {
+                            let i = next.fetch_add(1, Ordering::Relaxed);
+                            match inputs.get(i) {
+                                Some(input) => done.push((i, self.run_timed(input, options.timeout))),
+                                None => return done,
+                            }
+                        }
+                    })
+                })
+                .collect();
+            for worker in workers {
+                for (i, result) in worker.join().unwrap() {
+                    results[i] = Some(result);
+                }
+            }
+        });
+
+        results.into_iter().map(Option::unwrap).collect()
+    }
+
+    // `run` for just the output, cancelled after `timeout`
+    fn run_timed(&self, input: &[u8], timeout: Option<Duration>) -> Result<Vec<u8>, RuntimeError> {
+        let cancel = AtomicBool::new(false);
+        let timeout = match timeout {
+            Some(timeout) => timeout,
+            None => return self.run_cancellable(input, &cancel).map(|execution| execution.output),
+        };
+
+        thread::scope(|scope| {
+            let (done, finished) = mpsc::channel::<()>();
+            let cancel = &cancel;
+            // the run dropping `done` wakes it up before the time is up
+            scope.spawn(move || {
+                if let Err(RecvTimeoutError::Timeout) = finished.recv_timeout(timeout) {
+                    cancel.store(true, Ordering::SeqCst);
+                }
+            });
+            let result = self.run_cancellable(input, cancel);
+            drop(done);
+            result.map(|execution| execution.output)
+        })
+    }
+
+    pub fn tape_size(&self) -> usize {
+        self.tape_size
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl TieredProgram {
+    // A program from instructions, verified first, with the default tape.
+    // `Brainfuck::tiered` takes over the tape of a configured program, but
+    // generates the code of all of it first.
+    pub fn from_insts(insts: Vec<Inst>) -> Result<TieredProgram, CompileError> {
+        verify(&insts)?;
+        Ok(TieredProgram {
+            insts,
+            tape_size: DEFAULT_TAPE_SIZE,
+            initial_tape: Vec::new(),
+            pointer_start: 0,
+            max_memory: usize::MAX,
+            arith: ArithMode::Wrap,
+            threshold: DEFAULT_TIER_THRESHOLD,
+            fragments: HashMap::new(),
+            arena: None,
+            tape: Vec::new(),
+        })
+    }
+
+    // After the loops in a top-level loop went round `threshold` times in
+    // all it's compiled, 0 compiles every loop the first time round
+    pub fn set_threshold(&mut self, threshold: u64) {
+        self.threshold = threshold;
+    }
+
+    pub fn threshold(&self) -> u64 {
+        self.threshold
+    }
+
+    // The `[` of every loop compiled so far, in order
+    pub fn compiled_loops(&self) -> Vec<usize> {
+        let mut loops: Vec<usize> = self.fragments.keys().cloned().collect();
+        loops.sort();
+        loops
+    }
+
+    pub fn run(&mut self) -> Result<(), RuntimeError> {
+        // anything still buffered comes before what the program prints
+        io::stdout().flush()?;
+        self.run_fds(libc::STDIN_FILENO, libc::STDOUT_FILENO)
+    }
+
+    // Runs the program reading from the file descriptor `input` and
+    // writing to `output`. A loop too large to compile leaves the rest of
+    // the run to the interpreter.
+    pub fn run_fds(&mut self, input: RawFd, output: RawFd) -> Result<(), RuntimeError> {
+        MemoryBudget::new(self.max_memory).charge(self.tape_size)?;
+        let mut interp = Interp::new(&self.insts, self.tape_size);
+        interp.tape_mut()[..self.initial_tape.len()].copy_from_slice(&self.initial_tape);
+        interp.set_ptr(self.pointer_start);
+        interp.set_arith_mode(self.arith);
+        interp.set_hot_threshold(Some(self.threshold));
+
+        let cancel = AtomicBool::new(false);
+        let (mut reader, mut writer) = (RawIo(input), RawIo(output));
+        loop {
+            match interp.run_for(usize::MAX, &mut reader, &mut writer) {
+                StepOutcome::Paused => {}
+                StepOutcome::Finished => break,
+                StepOutcome::NeedsInput => return Err(io::Error::from(io::ErrorKind::WouldBlock).into()),
+                StepOutcome::Error(e) => return Err(e),
+            }
+            let start = match interp.hot_loop() {
+                Some(start) => start,
+                None => continue,
+            };
+            let end = match self.insts[start] {
+                JmpFwd(end) => end,
+                _ => unreachable!("hot loop doesn't start at a `[`"),
+            };
+            let code = match self.fragments.entry(start) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => match compile_fragment(&self.insts[start..end + 1], self.arith) {
+                    Ok(code) => entry.insert(install_fragment(&mut self.arena, &code)?),
+                    Err(_) => {
+                        interp.set_hot_threshold(None);
+                        continue;
+                    }
+                },
+            };
+            let (ptr, mut storage) = (interp.ptr(), interp.storage());
+            let io = CallIo { cancel: &cancel, input, output, host: None };
+            let (ptr, halted) = call_fragment(code, io, interp.tape_mut(), ptr, &mut storage, start)?;
+            interp.set_ptr(ptr);
+            interp.set_storage(storage);
+            interp.set_pc(if halted { self.insts.len() } else { end + 1 });
+        }
+        self.tape = interp.tape().to_vec();
+
+        Ok(())
+    }
+
+    // Runs the program with `input` as what it reads, through a pipe each
+    // way, like `SharedProgram::run`
+    pub fn run_piped(&mut self, input: &[u8]) -> Result<Execution, RuntimeError> {
+        let ((), output) = piped(input, |input, output| self.run_fds(input, output))?;
+        Ok(Execution { tape: self.tape.clone(), output })
+    }
+
+    // The tape as the last run left it
+    pub fn tape(&self) -> &[u8] {
+        &self.tape
+    }
+}
+
+#[cfg(test)]
+fn emitted<F>(emit: F) -> Vec<u8>
+    where F: FnOnce(&mut Vec<u8>) -> io::Result<()>
+{
+    let mut buf = Vec::new();
+    emit(&mut buf).unwrap();
+    buf
+}
+
+#[test]
+fn test_emit_imm() {
+    assert_eq!(emitted(|b| emit_imm8(b, 0x80)), [0x80]);
+    assert_eq!(emitted(|b| emit_imm32(b, 0x1000)), [0x00, 0x10, 0x00, 0x00]);
+    assert_eq!(emitted(|b| emit_imm32(b, -9i32 as u32)), [0xf7, 0xff, 0xff, 0xff]);
+}
+
+#[test]
+fn test_emit_ptr() {
+    assert_eq!(emitted(|b| emit_inc(b, 1)), [0x48, 0xff, 0xc6]);
+    assert_eq!(emitted(|b| emit_inc(b, 2)), [0x48, 0x81, 0xc6, 0x02, 0x00, 0x00, 0x00]);
+    assert_eq!(emitted(|b| emit_inc(b, 127)), [0x48, 0x81, 0xc6, 0x7f, 0x00, 0x00, 0x00]);
+    assert_eq!(emitted(|b| emit_inc(b, 128)), [0x48, 0x81, 0xc6, 0x80, 0x00, 0x00, 0x00]);
+    assert_eq!(emitted(|b| emit_inc(b, 255)), [0x48, 0x81, 0xc6, 0xff, 0x00, 0x00, 0x00]);
+    assert_eq!(emitted(|b| emit_inc(b, 0x1000)), [0x48, 0x81, 0xc6, 0x00, 0x10, 0x00, 0x00]);
+
+    assert_eq!(emitted(|b| emit_dec(b, 1)), [0x48, 0xff, 0xce]);
+    assert_eq!(emitted(|b| emit_dec(b, 2)), [0x48, 0x81, 0xee, 0x02, 0x00, 0x00, 0x00]);
+    assert_eq!(emitted(|b| emit_dec(b, 127)), [0x48, 0x81, 0xee, 0x7f, 0x00, 0x00, 0x00]);
+    assert_eq!(emitted(|b| emit_dec(b, 128)), [0x48, 0x81, 0xee, 0x80, 0x00, 0x00, 0x00]);
+    assert_eq!(emitted(|b| emit_dec(b, 255)), [0x48, 0x81, 0xee, 0xff, 0x00, 0x00, 0x00]);
+    assert_eq!(emitted(|b| emit_dec(b, 0x1000)), [0x48, 0x81, 0xee, 0x00, 0x10, 0x00, 0x00]);
+}
+
+#[test]
+fn test_emit_val() {
+    // cell arithmetic wraps, so only the low byte of the amount is encoded
+    assert_eq!(emitted(|b| emit_inc_val(b, 1)), [0xfe, 0x06]);
+    assert_eq!(emitted(|b| emit_inc_val(b, 2)), [0x80, 0x06, 0x02]);
+    assert_eq!(emitted(|b| emit_inc_val(b, 127)), [0x80, 0x06, 0x7f]);
+    assert_eq!(emitted(|b| emit_inc_val(b, 128)), [0x80, 0x06, 0x80]);
+    assert_eq!(emitted(|b| emit_inc_val(b, 255)), [0x80, 0x06, 0xff]);
+    assert_eq!(emitted(|b| emit_inc_val(b, 0x1000)), [0x80, 0x06, 0x00]);
+
+    assert_eq!(emitted(|b| emit_dec_val(b, 1)), [0xfe, 0x0e]);
+    assert_eq!(emitted(|b| emit_dec_val(b, 2)), [0x80, 0x2e, 0x02]);
+    assert_eq!(emitted(|b| emit_dec_val(b, 127)), [0x80, 0x2e, 0x7f]);
+    assert_eq!(emitted(|b| emit_dec_val(b, 128)), [0x80, 0x2e, 0x80]);
+    assert_eq!(emitted(|b| emit_dec_val(b, 255)), [0x80, 0x2e, 0xff]);
+    assert_eq!(emitted(|b| emit_dec_val(b, 0x1000)), [0x80, 0x2e, 0x00]);
+}
+
+#[test]
+fn test_emit_cell() {
+    assert_eq!(emitted(emit_load_cell), [0x0f, 0xb6, 0x06]);
+    assert_eq!(emitted(emit_store_cell), [0x88, 0x06]);
+    // eax doesn't wrap, the full amount goes in up to the first that
+    // leaves every cell's range
+    assert_eq!(emitted(|b| emit_add_cell(b, 1)), [0x05, 0x01, 0x00, 0x00, 0x00]);
+    assert_eq!(emitted(|b| emit_add_cell(b, 255)), [0x05, 0xff, 0x00, 0x00, 0x00]);
+    assert_eq!(emitted(|b| emit_add_cell(b, 0x1000)), [0x05, 0x00, 0x01, 0x00, 0x00]);
+    assert_eq!(emitted(|b| emit_sub_cell(b, 2)), [0x2d, 0x02, 0x00, 0x00, 0x00]);
+    assert_eq!(emitted(|b| emit_sub_cell(b, usize::MAX)), [0x2d, 0x00, 0x01, 0x00, 0x00]);
+
+    assert_eq!(emitted(emit_saturate_high), [
+        0xb9, 0xff, 0x00, 0x00, 0x00, 0x39, 0xc8, 0x0f, 0x47, 0xc1
+    ]);
+    assert_eq!(emitted(emit_saturate_low), [0xb9, 0x00, 0x00, 0x00, 0x00, 0x0f, 0x42, 0xc1]);
+    // relative to the end of the 11 byte cmp/ja sequence
+    assert_eq!(emitted(|b| emit_check_cell(b, 0x100b)), [
+        0x3d, 0xff, 0x00, 0x00, 0x00, 0x0f, 0x87, 0x00, 0x10, 0x00, 0x00
+    ]);
+}
+
+#[test]
+fn test_emit_jmp() {
+    // displacements are relative to the end of the 9 byte cmp/jcc sequence
+    assert_eq!(emitted(|b| emit_jmp_fwd(b, 10)), [0x80, 0x3e, 0x00, 0x0f, 0x84, 0x01, 0x00, 0x00, 0x00]);
+    assert_eq!(emitted(|b| emit_jmp_fwd(b, 136)), [0x80, 0x3e, 0x00, 0x0f, 0x84, 0x7f, 0x00, 0x00, 0x00]);
+    assert_eq!(emitted(|b| emit_jmp_fwd(b, 137)), [0x80, 0x3e, 0x00, 0x0f, 0x84, 0x80, 0x00, 0x00, 0x00]);
+    assert_eq!(emitted(|b| emit_jmp_fwd(b, 264)), [0x80, 0x3e, 0x00, 0x0f, 0x84, 0xff, 0x00, 0x00, 0x00]);
+    assert_eq!(emitted(|b| emit_jmp_fwd(b, 0x1009)), [0x80, 0x3e, 0x00, 0x0f, 0x84, 0x00, 0x10, 0x00, 0x00]);
+
+    assert_eq!(emitted(|b| emit_jmp_back(b, -1)), [0x80, 0x3e, 0x00, 0x0f, 0x85, 0xf6, 0xff, 0xff, 0xff]);
+    assert_eq!(emitted(|b| emit_jmp_back(b, -2)), [0x80, 0x3e, 0x00, 0x0f, 0x85, 0xf5, 0xff, 0xff, 0xff]);
+    assert_eq!(emitted(|b| emit_jmp_back(b, -119)), [0x80, 0x3e, 0x00, 0x0f, 0x85, 0x80, 0xff, 0xff, 0xff]);
+    assert_eq!(emitted(|b| emit_jmp_back(b, -120)), [0x80, 0x3e, 0x00, 0x0f, 0x85, 0x7f, 0xff, 0xff, 0xff]);
+    assert_eq!(emitted(|b| emit_jmp_back(b, -0x1000)), [0x80, 0x3e, 0x00, 0x0f, 0x85, 0xf7, 0xef, 0xff, 0xff]);
+}
+
+#[test]
+fn test_emit_io() {
+    // the jumps back retry the whole call
+    assert_eq!(emitted(emit_print), [
+        0xb8, 0x01, 0x00, 0x00, 0x00,
+        0x41, 0x8b, 0x79, 0x0c,
+        0xba, 0x01, 0x00, 0x00, 0x00,
+        0x0f, 0x05,
+        0x48, 0x83, 0xf8, 0xfc, 0x74, 0xea,
+        0x48, 0x83, 0xf8, 0xf5, 0x74, 0xe4,
+        0x48, 0x85, 0xc0, 0x74, 0xdf,
+    ]);
+    assert_eq!(emitted(emit_read), [
+        0x48, 0x31, 0xc0,
+        0x41, 0x8b, 0x79, 0x08,
+        0xba, 0x01, 0x00, 0x00, 0x00,
+        0x0f, 0x05,
+        0x48, 0x83, 0xf8, 0xfc, 0x74, 0xec,
+        0x48, 0x83, 0xf8, 0xf5, 0x74, 0xe6,
+        0x48, 0x85, 0xc0,
+    ]);
+    // macOS returns errors as they are with the carry flag set
+    assert_eq!(emitted(emit_darwin_print)[..5], [0xb8, 0x04, 0x00, 0x00, 0x02]);
+    assert_eq!(emitted(emit_darwin_read)[..5], [0xb8, 0x03, 0x00, 0x00, 0x02]);
+    assert_eq!(emitted(emit_darwin_print)[5..16], emitted(emit_print)[5..16]);
+    assert_eq!(emitted(emit_darwin_print)[16..], [
+        0x73, 0x03, 0x48, 0xf7, 0xd8,
+        0x48, 0x83, 0xf8, 0xfc, 0x74, 0xe5,
+        0x48, 0x83, 0xf8, 0xdd, 0x74, 0xdf,
+        0x48, 0x85, 0xc0, 0x74, 0xda,
+    ]);
+    // the jmp is relative to the end of the 13 byte check
+    assert_eq!(emitted(|b| emit_check_io(b, 26)), [
+        0x79, 0x0b,
+        0xf7, 0xd8,
+        0x41, 0x89, 0x41, 0x24,
+        0xe9, 0x0d, 0x00, 0x00, 0x00,
+    ]);
+    assert_eq!(emitted(|b| emit_windows_call(b, FRAME_WINDOWS_READ))[7..11], [0x41, 0xff, 0x51, 0x40]);
+}
+
+#[test]
+fn test_emit_exit() {
+    assert_eq!(emitted(emit_finish), [0x31, 0xc0]);
+    assert_eq!(emitted(emit_epilogue), [0xc3]);
+    assert_eq!(emitted(emit_pointer_out), [0x48, 0x89, 0xf2]);
+    // the jmp is relative to the end of the 15 byte stub
+    assert_eq!(emitted(|b| emit_stub(b, STATUS_POINTER_OVERFLOW, 7, -30)), [
+        0xba, 0x07, 0x00, 0x00, 0x00,
+        0xb8, 0x02, 0x00, 0x00, 0x00,
+        0xe9, 0xd3, 0xff, 0xff, 0xff,
+    ]);
+    assert_eq!(emitted(|b| emit_stub(b, STATUS_CANCELLED, 0, -15)), [
+        0xba, 0x00, 0x00, 0x00, 0x00,
+        0xb8, 0x03, 0x00, 0x00, 0x00,
+        0xe9, 0xe2, 0xff, 0xff, 0xff,
+    ]);
+}
+
+#[test]
+fn test_emit_ext() {
+    assert_eq!(emitted(emit_store), [0x8a, 0x06, 0x41, 0x88, 0x41, 0x20]);
+    assert_eq!(emitted(emit_retrieve), [0x41, 0x8a, 0x41, 0x20, 0x88, 0x06]);
+    assert_eq!(emitted(|b| emit_bit_op(b, ExtOp::ShiftRight)), [0xd0, 0x2e]);
+    assert_eq!(emitted(|b| emit_bit_op(b, ExtOp::Not)), [0xf6, 0x16]);
+    assert_eq!(emitted(|b| emit_bit_op(b, ExtOp::Or)), [0x41, 0x8a, 0x41, 0x20, 0x08, 0x06]);
+    assert_eq!(emitted(|b| emit_end(b, 10)), [0xe9, 0x05, 0x00, 0x00, 0x00]);
+    // the jmp is relative to the end of the 13 bytes
+    assert_eq!(emitted(|b| emit_halted(b, -1)), [
+        0x48, 0x89, 0xf2,
+        0xb8, 0x06, 0x00, 0x00, 0x00,
+        0xe9, 0xf2, 0xff, 0xff, 0xff,
+    ]);
+}
+
+#[test]
+fn test_frame_layout() {
+    assert_eq!(mem::offset_of!(Frame, cancel), 0);
+    assert_eq!(mem::offset_of!(Frame, input), FRAME_INPUT as usize);
+    assert_eq!(mem::offset_of!(Frame, output), FRAME_OUTPUT as usize);
+    assert_eq!(mem::offset_of!(Frame, tape_start), FRAME_TAPE_START as usize);
+    assert_eq!(mem::offset_of!(Frame, tape_end), FRAME_TAPE_END as usize);
+    assert_eq!(mem::offset_of!(Frame, storage), FRAME_STORAGE as usize);
+    assert_eq!(mem::offset_of!(Frame, io_error), FRAME_IO_ERROR as usize);
+    assert_eq!(mem::offset_of!(Frame, host_call), FRAME_HOST_CALL as usize);
+}
+
+#[test]
+fn test_emit_host_call() {
+    assert_eq!(emitted(emit_host_call), [
+        0x41, 0x51, 0x56, 0x48, 0x83, 0xec, 0x08,
+        0x4c, 0x89, 0xcf, 0x41, 0xff, 0x51, 0x28,
+        0x48, 0x83, 0xc4, 0x08, 0x5e, 0x41, 0x59,
+    ]);
+    assert_eq!(emitted(|b| emit_check_host(b, 0x100)), [0x85, 0xc0, 0x0f, 0x85, 0xf8, 0x00, 0x00, 0x00]);
+}
+
+#[test]
+fn test_hexdump() {
+    let mut out = Vec::new();
+    Brainfuck::new("+").unwrap().dump_jit_hex(&mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "00000000  49 89 f9 fe 06 31 c0 c3                           |I....1..|\n\
+         00000008\n"
+    );
+
+    let mut out = Vec::new();
+    write_hexdump(b"0123456789abcdef\x00~\x7f", &mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "00000000  30 31 32 33 34 35 36 37  38 39 61 62 63 64 65 66  |0123456789abcdef|\n\
+         00000010  00 7e 7f                                          |.~.|\n\
+         00000013\n"
+    );
+}
+
+#[test]
+fn test_emit_poll() {
+    assert_eq!(emitted(emit_prologue), [0x49, 0x89, 0xf9]);
+    // like jumps, relative to the end of the 12 byte sequence
+    assert_eq!(emitted(|b| emit_poll(b, 14)), [
+        0x49, 0x8b, 0x01, 0x80, 0x38, 0x00, 0x0f, 0x85, 0x02, 0x00, 0x00, 0x00
+    ]);
+    assert_eq!(emitted(|b| emit_poll(b, 0x100c)), [
+        0x49, 0x8b, 0x01, 0x80, 0x38, 0x00, 0x0f, 0x85, 0x00, 0x10, 0x00, 0x00
+    ]);
+}
+
+#[test]
+fn test_emit_check() {
+    // relative to the end of the 10 byte cmp/jcc sequence
+    assert_eq!(emitted(|b| emit_check_overflow(b, 12)), [
+        0x49, 0x3b, 0x71, 0x18, 0x0f, 0x83, 0x02, 0x00, 0x00, 0x00
+    ]);
+    assert_eq!(emitted(|b| emit_check_underflow(b, 0x100a)), [
+        0x49, 0x3b, 0x71, 0x10, 0x0f, 0x82, 0x00, 0x10, 0x00, 0x00
+    ]);
+}
+
+#[test]
+fn test_jump_range() {
+    assert!(check_displacement(0, 9).is_ok());
+    assert!(check_displacement(0, i32::MAX as isize + 9).is_ok());
+    assert!(check_displacement(0, i32::MIN as isize + 9).is_ok());
+
+    match check_displacement(3, i32::MAX as isize + 10) {
+        Err(CompileError::JumpOutOfRange { inst_index: 3, distance }) => {
+            assert_eq!(distance, i32::MAX as isize + 10);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    match check_displacement(7, i32::MIN as isize + 8) {
+        Err(CompileError::JumpOutOfRange { inst_index: 7, .. }) => {}
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn test_code_size_limit() {
+    let insts = [IncVal(1), JmpFwd(3), DecVal(1), JmpBack(1)];
+    // the limit covers the body and the stubs, here the one of the cancel
+    // check, not the epilogue
+    let limited = |max_code_size| {
+        compile_with(&insts, &CodegenOptions { max_code_size, polls: true, ..CodegenOptions::default() })
+    };
+    assert_eq!(limited(52).unwrap().len(), 55);
+
+    match limited(51) {
+        Err(CompileError::CodeTooLarge { size: 52, limit: 51 }) => {}
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn test_lazy_codegen() {
+    assert!(check_operand(0, i32::MAX as usize).is_ok());
+
+    // parsing doesn't generate code, a program whose code can't be
+    // generated can still be dumped and interpreted
+    let far = 1 << 31;
+    let mut bf = Brainfuck::from_insts(vec![IncVal(1), IncPtr(far), PrintCell]).unwrap();
+    let mut dump = Vec::new();
+    bf.write_dump(&mut dump).unwrap();
+    assert!(!dump.is_empty());
+    assert!(matches!(bf.steps(io::empty()).last(), Some(Err(RuntimeError::PointerOverflow { inst_index: 1 }))));
+
+    // the error comes with the first thing needing the code
+    assert!(matches!(bf.code_size(), Err(CompileError::OperandTooLarge { inst_index: 1, amount }) if amount == far));
+    assert!(matches!(bf.dump_jit_hex(io::sink()), Err(CompileError::OperandTooLarge { .. })));
+    #[cfg(not(target_family = "wasm"))]
+    assert!(matches!(bf.run(), Err(RuntimeError::Compile(CompileError::OperandTooLarge { inst_index: 1, .. }))));
+    let bf = Brainfuck::from_insts(vec![DecPtr(far)]).unwrap();
+    assert!(matches!(bf.jit_code(), Err(CompileError::OperandTooLarge { inst_index: 0, .. })));
+
+    // the code is for the arithmetic at the time it's generated
+    let mut bf = Brainfuck::new("+").unwrap();
+    bf.set_arith_mode(ArithMode::Trap).unwrap();
+    assert_eq!(bf.jit_code().unwrap(), &compile(bf.insts(), ArithMode::Trap).unwrap()[..]);
+    bf.set_arith_mode(ArithMode::Wrap).unwrap();
+    assert_eq!(bf.jit_code().unwrap(), &jit_code("+")[..]);
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_tape_size() {
+    let mut bf = Brainfuck::new("+").unwrap();
+    match bf.set_tape_size(0) {
+        Err(RuntimeError::InvalidTapeSize(0)) => {}
+        other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(bf.tape_size(), 30_000);
+
+    let program = format!("{}+[>]", ">".repeat(50));
+    let mut bf = Brainfuck::new(&program).unwrap();
+    bf.set_tape_size(10).unwrap();
+    match bf.run() {
+        Err(RuntimeError::TapeTooSmall { required: 51, tape_size: 10 }) => {}
+        other => panic!("unexpected {:?}", other),
+    }
+
+    let mut bf = Brainfuck::new(&format!("{}+<<-", ">".repeat(50))).unwrap();
+    bf.set_tape_size(51).unwrap();
+    bf.run().unwrap();
+    assert_eq!(bf.tape()[50], 1);
+    assert_eq!(bf.tape()[48], 255);
+}
+
+#[test]
+fn test_emit_constant() {
+    let code = emitted(|b| emit_constant(b, b"hi", &[0, 7]));
+    assert_eq!(code.len(), CONSTANT_SIZE as usize + 4);
+    // the rip-relative leas point at the output and the tape after it
+    assert_eq!(code[3..10], [0x48, 0x8d, 0x35, 0x51, 0x00, 0x00, 0x00]);
+    assert_eq!(code[10..15], [0xba, 0x02, 0x00, 0x00, 0x00]);
+    assert_eq!(code[56..63], [0x48, 0x8d, 0x35, 0x1e, 0x00, 0x00, 0x00]);
+    assert_eq!(code[67..72], [0xb9, 0x02, 0x00, 0x00, 0x00]);
+    assert_eq!(code[72..77], [0xf3, 0xa4, 0x31, 0xc0, 0xc3]);
+    // a failed write returns its errno
+    assert_eq!(code[77..91], [0xf7, 0xd8, 0x41, 0x89, 0x41, 0x24, 0x31, 0xd2, 0xb8, 0x08, 0x00, 0x00, 0x00, 0xc3]);
+    assert_eq!(code[91..], [b'h', b'i', 0, 7]);
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_precompute() {
+    let hello = include_str!("../tests/programs/hello.b");
+    let expected = Brainfuck::new(hello).unwrap().share().unwrap().run(b"").unwrap();
+    let mut bf = Brainfuck::new(hello).unwrap();
+    bf.set_pointer_start(3).unwrap();
+    let normal = bf.share().unwrap().run(b"").unwrap();
+    assert!(matches!(bf.precompute(DEFAULT_PRECOMPUTE_STEPS), Precomputed::Output(13)));
+    let used = normal.tape.iter().rposition(|&cell| cell != 0).unwrap() + 1;
+    assert_eq!(bf.code_size().unwrap(), CONSTANT_SIZE as usize + 13 + used);
+    let precomputed = bf.share().unwrap().run(b"").unwrap();
+    assert_eq!(precomputed.output, normal.output);
+    assert_eq!(precomputed.tape, normal.tape);
+
+    // changing the tape goes back to the normal code
+    bf.set_pointer_start(0).unwrap();
+    assert_eq!(bf.jit_code().unwrap(), jit_code(hello));
+    let execution = bf.share().unwrap().run(b"").unwrap();
+    assert_eq!((execution.output, execution.tape), (expected.output, expected.tape));
+
+    // nothing to print, just a tape
+    let mut bf = Brainfuck::new("++>+++++[-<+>]").unwrap();
+    assert!(matches!(bf.precompute(100), Precomputed::Output(0)));
+    assert_eq!(bf.share().unwrap().run(b"").unwrap().tape[..3], [7, 0, 0]);
+
+    // and everything that keeps the normal code
+    for &(source, steps, expected) in &[
+        (",[.,]", 1000, "not precomputed, the program reads input"),
+        ("+[]", 1000, "not precomputed, still running after 1000 steps"),
+        ("+.<", 1000, "not precomputed, the program fails: pointer moved below the start of the tape at instruction 2"),
+    ] {
+        let mut bf = Brainfuck::new(source).unwrap();
+        assert_eq!(bf.precompute(steps).to_string(), expected);
+        assert_eq!(bf.jit_code().unwrap(), jit_code(source));
+    }
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_final_tape() {
+    let mut bf = Brainfuck::new("+++>++>+[-]>>+<").unwrap();
+    bf.set_tape_size(8).unwrap();
+    assert!(bf.tape().is_empty());
+
+    bf.run().unwrap();
+    assert_eq!(bf.tape(), [3, 2, 0, 0, 1, 0, 0, 0]);
+
+    // every run starts from a fresh tape
+    bf.run().unwrap();
+    assert_eq!(bf.tape(), [3, 2, 0, 0, 1, 0, 0, 0]);
+}
+
+// `[>]` and `[<]` with the zero cell `distance` cells away from where the
+// pointer starts, which is `start` cells away from the end of the tape it
+// runs towards, with cells that aren't zero in between. The cell the scan
+// stopped on is incremented after it.
+#[cfg(test)]
+fn scan(right: bool, distance: usize, start: usize, tape_size: usize) -> Result<Vec<u8>, RuntimeError> {
+    let mut tape = vec![7; tape_size];
+    let pointer = if right { start } else { tape_size - 1 - start };
+    if distance <= tape_size {
+        let zero = if right { pointer.checked_add(distance) } else { pointer.checked_sub(distance) };
+        if let Some(cell) = zero.and_then(|zero| tape.get_mut(zero)) {
+            *cell = 0;
+        }
+    }
+
+    let mut bf = Brainfuck::new(if right { "[>]+" } else { "[<]+" }).unwrap();
+    bf.set_tape_size(tape_size).unwrap();
+    bf.set_initial_tape(&tape).unwrap();
+    bf.set_pointer_start(pointer).unwrap();
+    bf.run()?;
+    let jit = bf.tape().to_vec();
+
+    // and the interpreter, searching the tape in one go
+    let mut interp = bf.interp();
+    interp.set_shortcut_loops(true);
+    interp.run(io::empty(), io::sink()).unwrap();
+    assert_eq!(interp.tape(), &jit[..]);
+
+    Ok(jit)
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_scan_loops() {
+    for &right in &[true, false] {
+        for &distance in &[0, 1, 2, 15, 16, 17, 31, 32, 33, 4321] {
+            // every alignment of the blocks, from both ends of the tape
+            for start in 0..17 {
+                for &tape_size in &[start + distance + 1, start + distance + 40] {
+                    let tape = scan(right, distance, start, tape_size).unwrap();
+                    let pointer = if right { start } else { tape_size - 1 - start };
+                    let stop = if right { pointer + distance } else { pointer - distance };
+                    for (i, &cell) in tape.iter().enumerate() {
+                        let expected = if i == stop { 1 } else { 7 };
+                        assert_eq!(cell, expected, "cell {} of {:?}", i, (right, distance, start, tape_size));
+                    }
+                }
+            }
+        }
+
+        // no zero before the end of the tape, in blocks or one by one
+        for &tape_size in &[1, 5, 16, 17, 32, 33, 4000] {
+            for &start in &[0, 1, tape_size - 1] {
+                if start >= tape_size {
+                    continue;
+                }
+                match scan(right, tape_size, start, tape_size) {
+                    Err(RuntimeError::PointerOverflow { inst_index: 1 }) if right => {}
+                    Err(RuntimeError::PointerUnderflow { inst_index: 1 }) if !right => {}
+                    other => panic!("unexpected {:?} for {:?}", other, (right, start, tape_size)),
+                }
+            }
+        }
+    }
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_zero_ranges() {
+    // `cells` clears going `right` or left from `start`, then a `+` on the
+    // cell the pointer ended up on, on a tape of 7s
+    let run = |cells: usize, right: bool, start: usize, arith: ArithMode| {
+        let step = if right { ">" } else { "<" };
+        let source = vec!["[-]"; cells].join(step) + "+";
+        let insts = parse(&source).unwrap();
+        assert_eq!(zero_range(&mut &insts[..], 0, arith).unwrap().is_some(), cells >= MIN_ZERO_RANGE);
+
+        let mut bf = Brainfuck::from_insts(insts).unwrap();
+        bf.set_tape_size(64).unwrap();
+        bf.set_initial_tape(&[7; 64]).unwrap();
+        bf.set_pointer_start(start).unwrap();
+        bf.set_arith_mode(arith).unwrap();
+        // on a tape of its own, which keeps what it holds when the run fails
+        let mut tape = vec![7; 64];
+        let result = bf.run_in(&mut tape, false);
+
+        let mut interp = bf.interp();
+        let reference = interp.run(io::empty(), io::sink());
+        assert_eq!(format!("{:?}", result), format!("{:?}", reference));
+        assert_eq!(tape, interp.tape());
+        (result, tape)
+    };
+
+    for &arith in &[ArithMode::Wrap, ArithMode::Saturate, ArithMode::Trap] {
+        for cells in 1..10 {
+            let (result, tape) = run(cells, true, 20, arith);
+            result.unwrap();
+            let mut expected = vec![7; 64];
+            expected[20..20 + cells].iter_mut().for_each(|c| *c = 0);
+            expected[20 + cells - 1] = 1;
+            assert_eq!(tape, expected);
+
+            let (result, tape) = run(cells, false, 20, arith);
+            result.unwrap();
+            let mut expected = vec![7; 64];
+            expected[21 - cells..21].iter_mut().for_each(|c| *c = 0);
+            expected[21 - cells] = 1;
+            assert_eq!(tape, expected);
+        }
+
+        // right up to the ends of the tape, and one cell past them, where
+        // the cells before the move off the tape are cleared
+        let (result, tape) = run(8, true, 56, arith);
+        result.unwrap();
+        assert_eq!(tape[55..], [7, 0, 0, 0, 0, 0, 0, 0, 1]);
+        let (result, tape) = run(8, false, 7, arith);
+        result.unwrap();
+        assert_eq!(tape[..9], [1, 0, 0, 0, 0, 0, 0, 0, 7]);
+        let (result, tape) = run(8, true, 57, arith);
+        assert!(matches!(result, Err(RuntimeError::PointerOverflow { inst_index: 27 })));
+        assert_eq!(tape[56..], [7, 0, 0, 0, 0, 0, 0, 0]);
+        let (result, tape) = run(8, false, 6, arith);
+        assert!(matches!(result, Err(RuntimeError::PointerUnderflow { inst_index: 27 })));
+        assert_eq!(tape[..8], [0, 0, 0, 0, 0, 0, 0, 7]);
+    }
+
+    // only loops that always end on zero count
+    let insts = parse("[-]>[+]>[-]>[-]").unwrap();
+    assert_eq!(zero_range(&mut &insts[..], 0, ArithMode::Wrap).unwrap(), Some((4, true)));
+    assert_eq!(zero_range(&mut &insts[..], 0, ArithMode::Saturate).unwrap(), None);
+    assert_eq!(zero_range(&mut &parse("[-]>[--]>[-]>[-]").unwrap()[..], 0, ArithMode::Wrap).unwrap(), None);
+    assert_eq!(zero_range(&mut &parse("[-]>[-]>>[-]>[-]").unwrap()[..], 0, ArithMode::Wrap).unwrap(), None);
+    assert_eq!(zero_range(&mut &parse("[-]>[-]<[-]>[-]").unwrap()[..], 0, ArithMode::Wrap).unwrap(), None);
+}
+
+#[test]
+fn test_initial_tape() {
+    // increments every cell up to the terminator
+    let mut bf = Brainfuck::new("[+>]").unwrap();
+    bf.set_tape_size(8).unwrap();
+    bf.set_initial_tape(b"HAL\0x").unwrap();
+    bf.run().unwrap();
+    assert_eq!(bf.tape(), b"IBM\0x\0\0\0");
+    // every run starts from the initial tape again
+    bf.run().unwrap();
+    assert_eq!(&bf.tape()[..3], b"IBM");
+
+    bf.set_pointer_start(1).unwrap();
+    bf.run().unwrap();
+    assert_eq!(bf.tape(), b"HBM\0x\0\0\0");
+
+    match bf.set_initial_tape(&[1; 9]) {
+        Err(RuntimeError::InitialTapeTooLarge { size: 9, tape_size: 8 }) => {}
+        other => panic!("unexpected {:?}", other),
+    }
+    match bf.set_pointer_start(8) {
+        Err(RuntimeError::PointerStartOutOfRange { start: 8, tape_size: 8 }) => {}
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(bf.set_tape_size(4).is_err());
+
+    // the cells the program needs count from where the pointer starts
+    let mut bf = Brainfuck::new(">>>+").unwrap();
+    bf.set_tape_size(4).unwrap();
+    bf.set_pointer_start(1).unwrap();
+    match bf.run() {
+        Err(RuntimeError::TapeTooSmall { required: 5, tape_size: 4 }) => {}
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn test_run_with_hooks() {
+    // upper-cases its input, then leaves a 1 behind
+    let mut bf = Brainfuck::new(",[--------------------------------.[-],]+").unwrap();
+    let mut input = b"abc".iter().cloned();
+    let mut output = Vec::new();
+    bf.run_with_hooks(|byte| output.push(byte), || input.next()).unwrap();
+    assert_eq!(output, b"ABC");
+    assert_eq!(bf.tape()[0], 1);
+
+    // the end of the input leaves the cell as it was
+    let mut bf = Brainfuck::new("+++,.").unwrap();
+    let mut output = Vec::new();
+    bf.run_with_hooks(|byte| output.push(byte), || None).unwrap();
+    assert_eq!(output, [3]);
+
+    let mut bf = Brainfuck::new("+.").unwrap();
+    match bf.run_with_hooks(|_| panic!("boom"), || None) {
+        Err(RuntimeError::HookPanicked(ref message)) if message == "boom" => {}
+        other => panic!("unexpected {:?}", other),
+    }
+    // still runs afterwards
+    bf.run_with_hooks(|_| {}, || None).unwrap();
+    assert_eq!(bf.tape()[0], 1);
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_host_calls() {
+    // squares the cell two cells to the right, for a cell of 5 or 7
+    fn square(tape: &mut [u8], ptr: usize) -> Result<(), RuntimeError> {
+        tape[ptr + 2] = tape[ptr].wrapping_mul(tape[ptr]);
+        Ok(())
+    }
+    let mut host = HostFunctions::new();
+    host.register(5, square);
+    host.register(7, square);
+    host.register(6, |_, _| Err(RuntimeError::Cancelled));
+    host.register(8, |_, _| panic!("boom"));
+
+    let dialect = Dialect::HostCalls(HOST_CALL_COMMAND);
+    let mut bf = Brainfuck::with_dialect("+++++%>>.<<++%>>.", dialect).unwrap();
+    assert_eq!(bf.insts()[1], HostCall);
+    let mut output = Vec::new();
+    bf.interpret_with_host(&b""[..], &mut output, &mut host).unwrap();
+    assert_eq!(output, [25, 49]);
+    assert_eq!(bf.tape()[..3], [7, 0, 49]);
+    let execution = bf.share().unwrap().run_with_host(b"", &mut host).unwrap();
+    assert_eq!(execution.output, [25, 49]);
+    assert_eq!(execution.tape[..3], [7, 0, 49]);
+
+    // a failing function ends the run with its error, a cell without a
+    // function with `UnregisteredHostCall`, in both alike
+    for &(source, message) in &[
+        ("++++++%", "cancelled"),
+        ("++++++++%", "hook panicked: boom"),
+        ("+++%", "no host function 3 for the host call at instruction 1"),
+    ] {
+        let mut bf = Brainfuck::with_dialect(source, dialect).unwrap();
+        let interpreted = bf.interpret_with_host(io::empty(), io::sink(), &mut host).unwrap_err();
+        assert_eq!(interpreted.to_string(), message);
+        let compiled = bf.share().unwrap().run_with_host(b"", &mut host).unwrap_err();
+        assert_eq!(compiled.to_string(), message);
+    }
+    // as does every host call without any functions
+    let mut bf = Brainfuck::with_dialect("+++++%", dialect).unwrap();
+    let unregistered = |result: Result<_, RuntimeError>| {
+        matches!(result, Err(RuntimeError::UnregisteredHostCall { id: 5, inst_index: 1 }))
+    };
+    assert!(unregistered(bf.run_with_hooks(|_| {}, || None).map(|_| ())));
+    assert!(unregistered(bf.share().unwrap().run(b"").map(|_| ())));
+
+    // any character may call the host, plain brainfuck only has comments there
+    assert_eq!(parse_dialect("+*+", Dialect::HostCalls('*')).unwrap(), [IncVal(1), HostCall, IncVal(1)]);
+    assert_eq!(to_source(&parse_dialect("+*+", Dialect::HostCalls('*')).unwrap()), "+%+");
+    assert_eq!(parse("+%+").unwrap(), [IncVal(2)]);
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_run_streaming() {
+    use std::cell::Cell;
+
+    // ten lines, a chunk each
+    let mut bf = Brainfuck::new("++++++++++[>+>+++++<<-]>>--<<++++++++++[>>.+<.<-]").unwrap();
+    let mut chunks = Vec::new();
+    bf.run_streaming(&b""[..], Buffering::Line, |chunk| chunks.push(chunk.to_vec())).unwrap();
+    assert!(chunks.len() >= 10);
+    assert_eq!(chunks.concat(), b"0\n1\n2\n3\n4\n5\n6\n7\n8\n9\n");
+    assert!(chunks.iter().all(|chunk| chunk.ends_with(b"\n")));
+
+    // fully buffered it all comes at the end
+    let mut chunks = Vec::new();
+    bf.run_streaming(&b""[..], Buffering::Full, |chunk| chunks.push(chunk.to_vec())).unwrap();
+    assert_eq!(chunks.len(), 1);
+
+    // or once the buffer is full, 255 * 40 bytes fill it once
+    let mut bf = Brainfuck::new(&format!("-[{}-]", ".".repeat(40))).unwrap();
+    let mut sizes = Vec::new();
+    bf.run_streaming(&b""[..], Buffering::Full, |chunk| sizes.push(chunk.len())).unwrap();
+    assert_eq!(sizes, [STREAM_BUFFER, 255 * 40 - STREAM_BUFFER]);
+
+    // everything printed before a `,` is out before it reads, however long
+    // that takes to hand on
+    struct Input<'a>(&'a Cell<usize>, Vec<usize>);
+    impl<'a> Read for Input<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.1.push(self.0.get());
+            buf[0] = b'x';
+            Ok(1)
+        }
+    }
+    let delivered = Cell::new(0);
+    let mut input = Input(&delivered, Vec::new());
+    let mut bf = Brainfuck::new("+.+.,.,").unwrap();
+    bf.run_streaming(&mut input, Buffering::Full, |chunk| {
+        thread::sleep(::std::time::Duration::from_millis(10));
+        delivered.set(delivered.get() + chunk.len());
+    }).unwrap();
+    assert_eq!(input.1, [2, 3]);
+    assert_eq!(bf.tape()[0], b'x');
+
+    // and before a failure
+    let mut bf = Brainfuck::new("+.<").unwrap();
+    let mut output = Vec::new();
+    assert!(bf.run_streaming(&b""[..], Buffering::Full, |chunk| output.extend_from_slice(chunk)).is_err());
+    assert_eq!(output, [1]);
+
+    match bf.run_streaming(&b""[..], Buffering::Full, |_| panic!("boom")) {
+        Err(RuntimeError::HookPanicked(ref message)) if message == "boom" => {}
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn test_max_memory() {
+    let mut bf = Brainfuck::new("+").unwrap();
+    bf.set_tape_size(64).unwrap();
+    bf.set_max_memory(63);
+    match bf.run() {
+        Err(RuntimeError::MemoryLimitExceeded { limit: 63, requested: 64 }) => {}
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(bf.tape().is_empty());
+
+    // a budget is per run
+    bf.set_max_memory(64);
+    bf.run().unwrap();
+    bf.run().unwrap();
+    assert_eq!(bf.tape()[0], 1);
+
+    let arena = JitArena::new(4096, ::arena::Protection::ReadWriteExecute).unwrap();
+    bf.set_max_memory(10);
+    assert!(matches!(
+        bf.compile_into(&arena).unwrap().run(),
+        Err(RuntimeError::MemoryLimitExceeded { limit: 10, .. })
+    ));
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_exit_statuses() {
+    let mut bf = Brainfuck::new("+>+").unwrap();
+    bf.set_tape_size(2).unwrap();
+    bf.run().unwrap();
+    assert_eq!(bf.tape(), [1, 1]);
+
+    // the pointer may go anywhere on the tape and no further
+    let mut bf = Brainfuck::new("+[>+]").unwrap();
+    bf.set_tape_size(8).unwrap();
+    match bf.run() {
+        Err(RuntimeError::PointerOverflow { inst_index: 2 }) => {}
+        other => panic!("unexpected {:?}", other),
+    }
+    let mut bf = Brainfuck::new(">+[<<<]").unwrap();
+    bf.set_tape_size(8).unwrap();
+    match bf.run() {
+        Err(RuntimeError::PointerUnderflow { inst_index: 3 }) => {}
+        other => panic!("unexpected {:?}", other),
+    }
+
+    // the same through a shared program, which reports its statuses alike
+    let program = Brainfuck::new(">>+[<<<]").unwrap().share().unwrap();
+    match program.run(b"") {
+        Err(RuntimeError::PointerUnderflow { inst_index: 3 }) => {}
+        other => panic!("unexpected {:?}", other),
+    }
+
+    // cancelling is a status too, see `test_spawn_cancel`
+    let program = Brainfuck::new("+[]").unwrap().share().unwrap();
+    let cancel = AtomicBool::new(true);
+    assert!(matches!(program.run_fds(0, 1, &cancel), Err(RuntimeError::Cancelled)));
+}
+
+// Takes one byte at a time, after every other write being interrupted
+#[cfg(test)]
+struct Trickle {
+    written: Vec<u8>,
+    interrupt: bool,
+}
+
+#[cfg(test)]
+impl Write for Trickle {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.interrupt = !self.interrupt;
+        if self.interrupt {
+            return Err(io::ErrorKind::Interrupted.into());
+        }
+        self.written.extend_from_slice(&buf[..buf.len().min(1)]);
+        Ok(buf.len().min(1))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_io_retry() {
+    let hello = include_str!("../tests/programs/hello.b");
+    let expected = b"Hello World!\n";
+    let is_broken_pipe = |result: Result<Vec<u8>, RuntimeError>| match result {
+        Err(RuntimeError::Io(ref e)) => e.kind() == io::ErrorKind::BrokenPipe,
+        _ => false,
+    };
+    let cancel = AtomicBool::new(false);
+
+    // interrupted and short writes are made again
+    let mut bf = Brainfuck::new(hello).unwrap();
+    let mut output = Trickle { written: Vec::new(), interrupt: false };
+    bf.interpret_with_host(&b""[..], &mut output, &mut HostFunctions::new()).unwrap();
+    assert_eq!(output.written, expected);
+
+    // printing to a pipe nobody reads fails, in generated and precomputed code
+    let (reader, writer) = io::pipe().unwrap();
+    drop(reader);
+    let program = Brainfuck::new(hello).unwrap().share().unwrap();
+    assert!(is_broken_pipe(program.run_fds(0, writer.as_raw_fd(), &cancel)));
+    let mut bf = Brainfuck::new(hello).unwrap();
+    assert!(matches!(bf.precompute(DEFAULT_PRECOMPUTE_STEPS), Precomputed::Output(13)));
+    assert!(is_broken_pipe(bf.share().unwrap().run_fds(0, writer.as_raw_fd(), &cancel)));
+    // and so does reading what can't be read
+    let program = Brainfuck::new(",").unwrap().share().unwrap();
+    match program.run_fds(writer.as_raw_fd(), 1, &cancel) {
+        Err(RuntimeError::Io(ref e)) if e.raw_os_error() == Some(libc::EBADF) => {}
+        other => panic!("unexpected {:?}", other),
+    }
+
+    // input that would block is waited for, by generated code and the
+    // interpreter running the rest of a tiered program alike
+    let echo = ",.,.";
+    let program = Brainfuck::new(echo).unwrap().share().unwrap();
+    let runs: [&dyn Fn(RawFd, RawFd) -> Result<(), RuntimeError>; 2] = [
+        &|input, output| program.run_fds(input, output, &cancel).map(|_| ()),
+        &|input, output| Brainfuck::new(echo).unwrap().tiered().run_fds(input, output),
+    ];
+    for run in runs.iter() {
+        let (reader, mut writer) = io::pipe().unwrap();
+        let (mut out_reader, out_writer) = io::pipe().unwrap();
+        unsafe { libc::fcntl(reader.as_raw_fd(), libc::F_SETFL, libc::O_NONBLOCK) };
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                writer.write_all(b"ok")
+            });
+            run(reader.as_raw_fd(), out_writer.as_raw_fd()).unwrap();
+        });
+        drop(out_writer);
+        let mut output = Vec::new();
+        out_reader.read_to_end(&mut output).unwrap();
+        assert_eq!(output, b"ok");
+    }
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_arith_modes() {
+    use self::ArithMode::*;
+
+    // the cell left behind by the program starting on one, in each mode,
+    // or the error it fails with
+    type Outcome<'a> = Result<u8, &'a str>;
+    let overflow = "cell went past 255 at instruction 0";
+    let underflow = "cell went below 0 at instruction 0";
+    let plus_300 = "+".repeat(300);
+    let minus_300 = "-".repeat(300);
+    let plus_255 = "+".repeat(255);
+    let minus_255 = "-".repeat(255);
+    let cases: &[(&str, u8, [Outcome; 3])] = &[
+        ("+", 254, [Ok(255), Ok(255), Ok(255)]),
+        ("+", 255, [Ok(0), Ok(255), Err(overflow)]),
+        ("-", 1, [Ok(0), Ok(0), Ok(0)]),
+        ("-", 0, [Ok(255), Ok(0), Err(underflow)]),
+        ("++", 254, [Ok(0), Ok(255), Err(overflow)]),
+        (&plus_255, 0, [Ok(255), Ok(255), Ok(255)]),
+        (&plus_255, 1, [Ok(0), Ok(255), Err(overflow)]),
+        (&minus_255, 255, [Ok(0), Ok(0), Ok(0)]),
+        (&minus_255, 254, [Ok(255), Ok(0), Err(underflow)]),
+        // past 255 at once
+        (&plus_300, 0, [Ok(44), Ok(255), Err(overflow)]),
+        (&minus_300, 255, [Ok(211), Ok(0), Err(underflow)]),
+        // the index is the instruction's
+        (">+<-", 0, [Ok(255), Ok(0), Err("cell went below 0 at instruction 3")]),
+        // a saturated loop never ends, `[-]` ends either way
+        ("[-]+", 200, [Ok(1), Ok(1), Ok(1)]),
+        ("[---]", 7, [Ok(0), Ok(0), Err("cell went below 0 at instruction 1")]),
+    ];
+
+    for &(source, cell, ref expected) in cases {
+        for (&mode, expected) in [Wrap, Saturate, Trap].iter().zip(expected) {
+            let mut bf = Brainfuck::new(source).unwrap();
+            bf.set_tape_size(2).unwrap();
+            bf.set_initial_tape(&[cell]).unwrap();
+            bf.set_arith_mode(mode).unwrap();
+            let expected = expected.map_err(|e| e.to_string());
+
+            let mut interp = bf.clone();
+            let interpreted = interp.run_with_hooks(|_| {}, || None)
+                .map(|_| interp.tape()[0]).map_err(|e| e.to_string());
+            let jitted = bf.share().unwrap().run(b"")
+                .map(|execution| execution.tape[0]).map_err(|e| e.to_string());
+            assert_eq!(interpreted, expected, "{:?} from {} in the interpreter, {:?}", source, cell, mode);
+            assert_eq!(jitted, expected, "{:?} from {} as generated code, {:?}", source, cell, mode);
+        }
+    }
+}
+
+// Straight-line code changing the cells a lot between the other
+// instructions, with `[-]`, which ends in every mode, as the only loop.
+// Runs of `+` and `-` aren't merged, as `parse` would.
+#[cfg(test)]
+fn arithmetic_program(seed: u64, len: usize) -> Vec<Inst> {
+    let mut state = seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+    let mut next = |n: u64| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state % n) as usize
+    };
+
+    let mut insts = Vec::new();
+    let mut ptr = 2;
+    while insts.len() < len {
+        let amount = 1 + if next(8) == 0 { next(300) } else { next(20) };
+        match next(10) {
+            0..=3 => insts.push(IncVal(amount)),
+            4..=6 => insts.push(DecVal(amount)),
+            7 => insts.push(PrintCell),
+            8 if ptr < 3 => {
+                insts.push(IncPtr(1));
+                ptr += 1;
+            }
+            8 => {
+                insts.push(DecPtr(1));
+                ptr -= 1;
+            }
+            _ => insts.extend_from_slice(&[JmpFwd(0), DecVal(1), JmpBack(0)]),
+        }
+    }
+    relink(&mut insts);
+    insts
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_cached_cells() {
+    use self::ArithMode::*;
+
+    for seed in 0..200 {
+        let insts = arithmetic_program(seed, 30);
+        // the same without output, which would go to stdout run on a tape
+        // of its own
+        let silent: Vec<_> = insts.iter().map(|inst| if *inst == PrintCell { IncPtr(0) } else { inst.clone() }).collect();
+        for &mode in &[Wrap, Saturate, Trap] {
+            let program = |insts: &[Inst]| {
+                let mut bf = Brainfuck::from_insts(insts.to_vec()).unwrap();
+                bf.set_tape_size(4).unwrap();
+                bf.set_initial_tape(&[0, 128, 255, 1]).unwrap();
+                bf.set_pointer_start(2).unwrap();
+                bf.set_arith_mode(mode).unwrap();
+                bf
+            };
+
+            let bf = program(&insts);
+            let mut interp = bf.interp();
+            let mut output = Vec::new();
+            let interpreted = interp.run(io::empty(), &mut output).map(|_| output).map_err(|e| e.to_string());
+            let jitted = bf.share().unwrap().run(b"").map(|execution| execution.output).map_err(|e| e.to_string());
+            assert_eq!(jitted, interpreted, "{:?} under {:?}", insts, mode);
+
+            // which keeps what the tape holds when the run fails
+            let mut tape = vec![0, 128, 255, 1];
+            let _ = program(&silent).run_in(&mut tape, false);
+            assert_eq!(&tape[..], interp.tape(), "{:?} under {:?}", insts, mode);
+        }
+    }
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_spawn_cancel() {
+    use std::time::{Duration, Instant};
+
+    let handle = Brainfuck::new("+[>+<]").unwrap().spawn();
+    thread::sleep(Duration::from_millis(20));
+    assert!(!handle.is_finished());
+
+    let cancelled = Instant::now();
+    handle.cancel();
+    match handle.join() {
+        Err(RuntimeError::Cancelled) => {}
+        Err(e) => panic!("unexpected {:?}", e),
+        Ok(_) => panic!("infinite loop finished"),
+    }
+    assert!(cancelled.elapsed() < Duration::from_secs(1));
+
+    // cancelling a finished run changes nothing
+    let handle = Brainfuck::new("++[>+<-]").unwrap().spawn();
+    while !handle.is_finished() {
+        thread::sleep(Duration::from_millis(1));
+    }
+    handle.cancel();
+    let outcome = handle.join().expect("finished run reported as cancelled");
+    assert_eq!(&outcome.program.tape()[..2], [0, 2]);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_perf_map() {
+    use std::fs;
+    use std::time::Duration;
+
+    let path = ::std::env::temp_dir().join(format!("brainfuck-perf-{}.map", ::std::process::id()));
+    let _ = fs::remove_file(&path);
+    let source = "+[>+[-]<]";
+    let (_, spans) = parse_with_spans(source).unwrap();
+    let mut bf = Brainfuck::new(source).unwrap();
+    bf.set_perf_map(Some(path.clone()), Some(&spans));
+    // a spawned run can be cancelled, its code polls
+    let len = bf.code(true).unwrap().len();
+
+    // the entries are there while the code runs
+    let handle = bf.spawn();
+    let mut map = String::new();
+    for _ in 0..1000 {
+        map = fs::read_to_string(&path).unwrap_or_default();
+        if !map.is_empty() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    let entries: Vec<(usize, usize, &str)> = map.lines()
+        .map(|line| {
+            let fields: Vec<&str> = line.split(' ').collect();
+            assert_eq!(fields.len(), 3, "{:?}", line);
+            let parse = |hex| usize::from_str_radix(hex, 16).unwrap();
+            (parse(fields[0]), parse(fields[1]), fields[2])
+        })
+        .collect();
+    let names: Vec<&str> = entries.iter().map(|e| e.2).collect();
+    assert_eq!(names, ["bf_program", "bf_loop_1_src_1", "bf_loop_4_src_4", "bf_loop_1_src_1", "bf_program"]);
+
+    // contiguous and covering exactly the code, in an executable mapping
+    let start = entries[0].0;
+    let mut end = start;
+    for &(address, size, _) in &entries {
+        assert_eq!(address, end);
+        end += size;
+    }
+    assert_eq!(end - start, len);
+    let maps = fs::read_to_string("/proc/self/maps").unwrap();
+    assert!(maps.lines().any(|line| {
+        let (range, perms) = line.split_at(line.find(' ').unwrap());
+        let mut bounds = range.split('-').map(|hex| usize::from_str_radix(hex, 16).unwrap());
+        let (low, high) = (bounds.next().unwrap(), bounds.next().unwrap());
+        low <= start && end <= high && perms[1..].starts_with("r-x")
+    }), "no mapping for {:x}-{:x}", start, end);
+
+    handle.cancel();
+    assert!(handle.join().is_err());
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_compile_into_arena() {
+    use arena::Protection;
+
+    for &protection in &[Protection::ReadWriteExecute, Protection::WriteXorExecute] {
+        let arena = JitArena::new(1 << 20, protection).unwrap();
+        let mut programs = Vec::new();
+        for i in 0..3000 {
+            let source = format!("{}[>+<-]>{}", "+".repeat(i % 13 + 1), ">".repeat(i % 3));
+            let mut bf = Brainfuck::new(&source).unwrap();
+            bf.set_tape_size(4).unwrap();
+            programs.push(bf.compile_into(&arena).unwrap());
+        }
+        assert!(arena.used() < arena.capacity());
+
+        for (i, program) in programs.iter_mut().enumerate().step_by(97) {
+            program.run().unwrap();
+            assert_eq!(program.tape(), [0, (i % 13 + 1) as u8, 0, 0]);
+        }
+    }
+
+    // the programs keep the arena alive
+    let program = {
+        let arena = JitArena::new(64, Protection::WriteXorExecute).unwrap();
+        Brainfuck::new("+++").unwrap().compile_into(&arena).unwrap()
+    };
+    let mut program = program;
+    program.run().unwrap();
+    assert_eq!(program.tape()[0], 3);
+
+    let arena = JitArena::new(1, Protection::ReadWriteExecute).unwrap();
+    let bf = Brainfuck::new(&"+>".repeat(arena.capacity())).unwrap();
+    match bf.compile_into(&arena) {
+        Err(CompileError::ArenaFull { available, .. }) => assert_eq!(available, arena.capacity()),
+        _ => panic!("expected the arena to be full"),
+    }
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_tape_alloc() {
+    use tape::HUGE_PAGE;
+
+    // marks a cell in every huge page and the last one
+    let source = format!("+{}", format!(">{}+", ">".repeat(HUGE_PAGE - 1)).repeat(3));
+    let mut tapes = Vec::new();
+    for &alloc in &[TapeAlloc::Default, TapeAlloc::Huge, TapeAlloc::HugeTlb] {
+        let mut bf = Brainfuck::new(&source).unwrap();
+        bf.set_tape_size(3 * HUGE_PAGE + 1).unwrap();
+        bf.set_initial_tape(&[1, 2]).unwrap();
+        bf.set_tape_alloc(alloc);
+        bf.run().unwrap();
+        // whatever the system can do, but never more than asked for
+        assert!(alloc != TapeAlloc::Default || bf.last_tape_alloc() == TapeAlloc::Default);
+        tapes.push(bf.tape().to_vec());
+    }
+    assert_eq!(tapes[0].iter().filter(|&&cell| cell != 0).count(), 5);
+    assert_eq!(tapes[0][..3], [2, 2, 0]);
+    assert!(tapes[0] == tapes[1] && tapes[1] == tapes[2]);
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_tape_guard() {
+    let run = |source: &str| {
+        let mut bf = Brainfuck::new(source).unwrap();
+        bf.set_tape_size(8).unwrap();
+        bf.set_initial_tape(&[1]).unwrap();
+        bf.set_pointer_start(1).unwrap();
+        bf.set_tape_guard(4);
+        bf.run().map(|_| bf.tape().to_vec())
+    };
+
+    // staying on the tape the guards go unnoticed
+    assert_eq!(run("+>+").unwrap(), [1, 1, 1, 0, 0, 0, 0, 0]);
+
+    // off either end and back
+    assert!(matches!(run("<<+>>"), Err(RuntimeError::GuardCorrupted { lowest: -1, highest: -1 })));
+    assert!(matches!(run(">>>>>>>>+<<"), Err(RuntimeError::GuardCorrupted { lowest: 9, highest: 9 })));
+    assert!(matches!(
+        run("<<<+>>>>>>>>>>>>+>+"), Err(RuntimeError::GuardCorrupted { lowest: -2, highest: 11 })
+    ));
+    // writing what the guard holds anyway goes unnoticed
+    assert!(run(&format!("<<[-]{}>>", "+".repeat(GUARD_BYTE as usize))).is_ok());
+
+    // past the guards the pointer is off the tape as ever
+    assert!(matches!(run("<<<<<<+"), Err(RuntimeError::PointerUnderflow { inst_index: 0 })));
+    assert!(matches!(run("+[>>>>>>>>>>>+]"), Err(RuntimeError::PointerOverflow { inst_index: 2 })));
+    assert!(matches!(run(">>>>>>>>>>>+"), Err(RuntimeError::TapeTooSmall { required: 13, tape_size: 8 })));
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_tape_file() {
+    use std::fs;
+
+    let path = ::std::env::temp_dir().join(format!("brainfuck-tape-{}", ::std::process::id()));
+    fs::write(&path, [0, 1, 0xfe, 0xff, 7]).unwrap();
+
+    // increments every cell, which is every byte of the file
+    let mut bf = Brainfuck::new("+>+>+>+>+").unwrap();
+    bf.set_tape_size(5).unwrap();
+    bf.set_tape_file(Some(path.clone()), TapeFileSize::Exact);
+    bf.run().unwrap();
+    assert_eq!(bf.tape(), [1, 2, 0xff, 0, 8]);
+    assert_eq!(fs::read(&path).unwrap(), [1, 2, 0xff, 0, 8]);
+
+    // and again from where the last run left the file
+    bf.run().unwrap();
+    assert_eq!(fs::read(&path).unwrap(), [2, 3, 0, 1, 9]);
+
+    bf.set_tape_size(7).unwrap();
+    assert!(matches!(bf.run(), Err(RuntimeError::TapeFileSizeMismatch { len: 5, tape_size: 7 })));
+    assert_eq!(fs::read(&path).unwrap(), [2, 3, 0, 1, 9]);
+    bf.set_tape_file(Some(path.clone()), TapeFileSize::Resize);
+    bf.run().unwrap();
+    assert_eq!(fs::read(&path).unwrap(), [3, 4, 1, 2, 10, 0, 0]);
+    bf.set_tape_size(2).unwrap();
+    assert!(matches!(bf.run(), Err(RuntimeError::TapeTooSmall { required: 5, tape_size: 2 })));
+    bf.set_tape_size(5).unwrap();
+    bf.run().unwrap();
+    assert_eq!(fs::read(&path).unwrap(), [4, 5, 2, 3, 11]);
+
+    // a failed run still leaves what it wrote
+    let mut bf = Brainfuck::new("[-]>[-]<<").unwrap();
+    bf.set_tape_size(5).unwrap();
+    bf.set_tape_file(Some(path.clone()), TapeFileSize::Exact);
+    assert!(bf.run().is_err());
+    assert_eq!(fs::read(&path).unwrap(), [0, 0, 2, 3, 11]);
+    assert!(matches!(bf.precompute(DEFAULT_PRECOMPUTE_STEPS), Precomputed::TapeFile));
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_run_in() {
+    let mut tape = vec![7; 4];
+
+    // the first program clears the tape, the second one picks up its result
+    let mut first = Brainfuck::new("+++++").unwrap();
+    first.set_tape_size(4).unwrap();
+    first.set_initial_tape(&[0, 0, 0, 1]).unwrap();
+    first.run_in(&mut tape, true).unwrap();
+    assert_eq!(tape, [5, 0, 0, 1]);
+
+    let mut second = Brainfuck::new("[>++<-]>").unwrap();
+    second.set_tape_size(4).unwrap();
+    second.set_initial_tape(&[9]).unwrap();
+    second.run_in(&mut tape, false).unwrap();
+    assert_eq!(tape, [0, 10, 0, 1]);
+    // the programs' own tapes stay as they were
+    assert!(first.tape().is_empty() && second.tape().is_empty());
+
+    // the same through an arena
+    let arena = JitArena::new(4096, Protection::WriteXorExecute).unwrap();
+    let mut program = second.compile_into(&arena).unwrap();
+    program.run_in(&mut tape, false).unwrap();
+    assert_eq!(tape, [0, 10, 0, 1]);
+    // starting from the initial tape
+    program.run_in(&mut tape, true).unwrap();
+    assert_eq!(tape, [0, 18, 0, 0]);
+    tape[0] = 3;
+    program.run_in(&mut tape, false).unwrap();
+    assert_eq!(tape, [0, 24, 0, 0]);
+
+    let mut short = [0; 3];
+    match second.run_in(&mut short, true) {
+        Err(RuntimeError::TapeLengthMismatch { len: 3, tape_size: 4 }) => {}
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(program.run_in(&mut [0; 5], false).is_err());
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_tiered() {
+    // the first loop is compiled once it and the loop in it went round four
+    // times, the clear loop after its fourth time round and the last one
+    // never gets there
+    let source = "+++[>++[>+<-]<-]>>.>++++++[-]+++[>+++<-]>.";
+    let mut tiered = Brainfuck::new(source).unwrap().tiered();
+    assert_eq!(tiered.threshold(), DEFAULT_TIER_THRESHOLD);
+    tiered.set_threshold(4);
+    let execution = tiered.run_piped(b"").unwrap();
+    assert_eq!(execution.output, [6, 9]);
+    assert_eq!(execution.tape[..5], [0, 0, 6, 0, 9]);
+    assert_eq!(tiered.compiled_loops(), [1, 17]);
+
+    // compiled loops are kept, mapped only once
+    let used = tiered.arena.as_ref().unwrap().used();
+    tiered.set_threshold(u64::MAX);
+    assert_eq!(tiered.run_piped(b"").unwrap().output, [6, 9]);
+    assert_eq!(tiered.compiled_loops(), [1, 17]);
+    assert_eq!(tiered.arena.as_ref().unwrap().used(), used);
+
+    // a full arena makes way for another one, the fragments in it still run
+    let mut full = TieredProgram::from_insts(parse("+++[>+<-]>.").unwrap()).unwrap();
+    full.set_threshold(0);
+    let small = JitArena::new(1, Protection::WriteXorExecute).unwrap();
+    let filler = small.install(&vec![0xc3; small.capacity()]).unwrap();
+    full.arena = Some(small);
+    assert_eq!(full.run_piped(b"").unwrap().output, [3]);
+    assert_eq!(full.compiled_loops(), [1]);
+    assert_eq!(full.arena.as_ref().unwrap().capacity(), FRAGMENT_ARENA);
+    drop(filler);
+
+    // I/O in compiled loops goes through the same file descriptors, the
+    // interpreter doesn't read ahead
+    let mut tiered = TieredProgram::from_insts(parse(",[.,]").unwrap()).unwrap();
+    tiered.set_threshold(0);
+    assert_eq!(tiered.run_piped(b"tiered\0").unwrap().output, b"tiered");
+    assert_eq!(tiered.compiled_loops(), [1]);
+
+    // errors point at the instruction in the whole program
+    let mut bf = Brainfuck::new("+[>+]").unwrap();
+    bf.set_tape_size(100).unwrap();
+    let mut tiered = bf.tiered();
+    tiered.set_threshold(0);
+    assert!(matches!(tiered.run_piped(b""), Err(RuntimeError::PointerOverflow { inst_index: 2 })));
+    let mut bf = Brainfuck::new("+>+[<+>]").unwrap();
+    bf.set_arith_mode(ArithMode::Trap).unwrap();
+    let mut tiered = bf.tiered();
+    tiered.set_threshold(0);
+    assert!(matches!(tiered.run_piped(b""), Err(RuntimeError::CellOverflow { inst_index: 5 })));
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_shared_program() {
+    fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+    let rot13 = include_str!("../tests/fixtures/rot13.b");
+    let program = Brainfuck::new(rot13).unwrap().share().unwrap();
+    assert_send_sync(&program);
+
+    let threads: Vec<_> = (0..8)
+        .map(|t| {
+            let program = program.clone();
+            thread::spawn(move || {
+                for i in 0..20 {
+                    let input = format!("thread {} run {} ", t, i).repeat(t + 1);
+                    let expected = format!("guernq {} eha {} ", t, i).repeat(t + 1);
+                    let execution = program.run(input.as_bytes()).unwrap();
+                    assert_eq!(String::from_utf8(execution.output).unwrap(), expected);
+                    assert_eq!(execution.tape.len(), program.tape_size());
+                }
+            })
+        })
+        .collect();
+    // the threads keep the code alive
+    drop(program);
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    // a program can stop before reading all of its input
+    let program = Brainfuck::new(",.").unwrap().share().unwrap();
+    let execution = program.run(&vec![b'x'; 1 << 20]).unwrap();
+    assert_eq!(execution.output, b"x");
+    assert_eq!(execution.tape[0], b'x');
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_run_many() {
+    // prints its input backwards, inputs of more than 6 bytes don't fit
+    let mut bf = Brainfuck::new(">,[>,]<[.<]").unwrap();
+    bf.set_tape_size(8).unwrap();
+    let program = bf.share().unwrap();
+    let inputs: Vec<Vec<u8>> = (0..100).map(|i| format!("in {}", "x".repeat(i % 12)).into_bytes()).collect();
+    let inputs: Vec<&[u8]> = inputs.iter().map(Vec::as_slice).collect();
+
+    let sequential = program.run_many(&inputs, RunManyOptions { jobs: 1, timeout: None });
+    let parallel = program.run_many(&inputs, RunManyOptions { jobs: 4, timeout: None });
+    assert_eq!(parallel.len(), 100);
+    for (i, (sequential, parallel)) in sequential.iter().zip(&parallel).enumerate() {
+        let expected = program.run(inputs[i]).map(|execution| execution.output);
+        match (sequential, parallel, expected) {
+            (Ok(a), Ok(b), Ok(expected)) => {
+                assert_eq!(a, &expected);
+                assert_eq!(b, &expected);
+            }
+            (Err(a), Err(b), Err(expected)) => {
+                assert_eq!(a.to_string(), expected.to_string());
+                assert_eq!(b.to_string(), expected.to_string());
+            }
+            results => panic!("input {} gave {:?}", i, results),
+        }
+    }
+    assert!(parallel.iter().any(Result::is_err));
+    assert_eq!(parallel[1].as_ref().unwrap(), b"x ni");
+    assert!(program.run_many(&[], RunManyOptions::default()).is_empty());
+
+    // a run that never ends only fails itself
+    let program = Brainfuck::new(",[.,]+[]").unwrap().share().unwrap();
+    let options = RunManyOptions { jobs: 2, timeout: Some(Duration::from_millis(50)) };
+    for result in program.run_many(&[&b"a"[..], b"b", b"c"], options) {
+        assert!(matches!(result, Err(RuntimeError::Cancelled)));
+    }
+    // the end of the input leaves the cell as it was, cleared before
+    let program = Brainfuck::new(",[.[-],]").unwrap().share().unwrap();
+    let results = program.run_many(&[&b"a"[..], b"bc"], options);
+    assert_eq!(results.into_iter().map(Result::unwrap).collect::<Vec<_>>(), [b"a".to_vec(), b"bc".to_vec()]);
+}
+
+#[test]
+fn test_min_tape_size() {
+    let bf = Brainfuck::new(">>><<+[>>>>>>]>>>>>>>>").unwrap();
+    assert_eq!(min_tape_size(&bf.insts), 4);
+    assert_eq!(min_tape_size(&[]), 1);
+}
+
+// Parsing and code generation never touch the mapping, keep it that way
+// so this runs under Miri
+#[test]
+fn test_parse() {
+    match Brainfuck::new("a+++b[>,.<-]]") {
+        Err(CompileError::UnmatchedClose { offset: 12 }) => {}
+        _ => panic!("unbalanced program accepted"),
+    }
+
+    let programs: &[(&str, &[Inst])] = &[
+        ("", &[]),
+        ("no commands at all", &[]),
+        ("+++ [>,.<-] comment", &[
+            IncVal(3), JmpFwd(7), IncPtr(1), ReadChar, PrintCell, DecPtr(1), DecVal(1), JmpBack(1),
+        ]),
+        (">>+-<<", &[IncPtr(2), IncVal(1), DecVal(1), DecPtr(2)]),
+        ("..,,", &[PrintCell, PrintCell, ReadChar, ReadChar]),
+        ("[[]][]", &[JmpFwd(3), JmpFwd(2), JmpBack(1), JmpBack(0), JmpFwd(5), JmpBack(4)]),
+        ("+ + comment + >", &[IncVal(3), IncPtr(1)]),
+    ];
+    for &(program, insts) in programs {
+        let bf = Brainfuck::new(program).unwrap();
+        assert_eq!(bf.insts(), insts, "{:?}", program);
+        assert_eq!(bf.tape_size(), 30_000);
+        assert!(bf.tape().is_empty());
+    }
+
+    // checked at compile time, can't fail to parse
+    assert_eq!(Brainfuck::new(bf!("+[>,.<-]")).unwrap().insts().len(), 8);
+}
+
+#[test]
+fn test_parse_extended1() {
+    let source = "$>!}{~^&|@ a comment";
+    let insts = parse_dialect(source, Dialect::Extended1).unwrap();
+    assert_eq!(insts, [
+        Ext(ExtOp::Store), IncPtr(1), Ext(ExtOp::Retrieve), Ext(ExtOp::ShiftRight), Ext(ExtOp::ShiftLeft),
+        Ext(ExtOp::Not), Ext(ExtOp::Xor), Ext(ExtOp::And), Ext(ExtOp::Or), Ext(ExtOp::End),
+    ]);
+    assert_eq!(to_source(&insts), "$>!}{~^&|@");
+    // comments in brainfuck, runs are one instruction each
+    assert_eq!(parse(source).unwrap(), [IncPtr(1)]);
+    let (insts, spans) = parse_dialect_with_spans("}}[@]", Dialect::Extended1).unwrap();
+    assert_eq!(insts, [Ext(ExtOp::ShiftRight), Ext(ExtOp::ShiftRight), JmpFwd(4), Ext(ExtOp::End), JmpBack(2)]);
+    assert_eq!(spans[1], Span { start: 1, end: 2 });
+    assert!(matches!(parse_dialect("@]", Dialect::Extended1), Err(CompileError::UnmatchedClose { offset: 1 })));
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_extended1() {
+    // storage carries over between instructions and `@` ends the program
+    // anywhere, the generated code has to agree with the interpreter
+    let programs: &[(&str, &[u8], &[u8])] = &[
+        ("+++{$>!^.<|.~.}}.{.", &[0, 6, 249, 62, 124], &[124, 0]),
+        ("+++++[-.@]+.", &[4], &[4]),
+        ("++[>+++[>+<-]<-]>>$<<!.@.", &[6], &[6, 0, 6]),
+        (",$>,&.!<|.", &[0x08, 0x0c], &[0x0c, 0x0c]),
+    ];
+    for &(source, output, tape) in programs {
+        let insts = parse_dialect(source, Dialect::Extended1).unwrap();
+        let input = if source.contains(',') { &b"\x0c\x0a"[..] } else { b"" };
+        assert_eq!(::interp::run_with_input(&insts, 16, input).unwrap(), output, "{}", source);
+
+        let mut bf = Brainfuck::with_dialect(source, Dialect::Extended1).unwrap();
+        bf.set_tape_size(16).unwrap();
+        let execution = bf.share().unwrap().run(input).unwrap();
+        assert_eq!((&execution.output[..], &execution.tape[..tape.len()]), (output, tape), "{}", source);
+
+        let mut tiered = bf.tiered();
+        tiered.set_threshold(0);
+        let execution = tiered.run_piped(input).unwrap();
+        assert_eq!((&execution.output[..], &execution.tape[..tape.len()]), (output, tape), "{}", source);
+    }
+
+    // storage set in the interpreter is there in a compiled loop and back
+    let mut tiered = Brainfuck::with_dialect("++++$[-]+++[>!<-]>.$[-]!.", Dialect::Extended1).unwrap().tiered();
+    tiered.set_threshold(0);
+    assert_eq!(tiered.run_piped(b"").unwrap().output, [4, 4]);
+    assert_eq!(tiered.compiled_loops(), [2, 6, 15]);
+}
+
+#[test]
+fn test_program_eq() {
+    let bf = Brainfuck::new("+[->+<]").unwrap();
+    assert!(bf == Brainfuck::new("+ [ - > + < ] the same").unwrap());
+    assert!(bf == bf.clone());
+    assert!(bf != Brainfuck::new("+[->+<]>").unwrap());
+
+    let mut small = bf.clone();
+    small.set_tape_size(10).unwrap();
+    assert!(bf != small);
+    assert_eq!(small.jit_code().unwrap(), bf.jit_code().unwrap());
+}
+
+#[test]
+fn test_parse_errors() {
+    let offsets = |program: &str| -> Vec<Option<usize>> {
+        match parse(program) {
+            Err(CompileError::Multiple(errors)) => errors.iter().map(|e| e.offset()).collect(),
+            Err(e) => vec![e.offset()],
+            Ok(_) => vec![],
+        }
+    };
+
+    assert_eq!(offsets("+[>+<-]]"), [Some(7)]);
+    assert_eq!(offsets("[[-]"), [Some(0)]);
+    // the stray brackets don't throw off the pairing of the ones after them
+    assert_eq!(offsets("]+[ [-] ]] [>"), [Some(0), Some(9), Some(11)]);
+    assert_eq!(offsets("]]] [[-]+[<]>]"), [Some(0), Some(1), Some(2)]);
+
+    match parse("[ ]]") {
+        Err(e @ CompileError::UnmatchedClose { .. }) => assert_eq!(e.to_string(), "unmatched ']'"),
+        _ => panic!("unbalanced program accepted"),
+    }
+    match parse("][") {
+        Err(e) => assert_eq!(e.to_string(), "2 errors: unmatched ']'; unclosed '['"),
+        _ => panic!("unbalanced program accepted"),
+    }
+}
+
+#[test]
+fn test_spans() {
+    let (insts, spans) = parse_with_spans("+ +\n>>[..]x-").unwrap();
+    assert_eq!(insts.len(), spans.len());
+
+    let spans: Vec<_> = spans.iter().map(|s| (s.start, s.end)).collect();
+    assert_eq!(spans, [(0, 3), (4, 6), (6, 7), (7, 8), (8, 9), (9, 10), (11, 12)]);
+}
+
+#[test]
+fn test_to_source() {
+    let source = "+++[>,.<-]>>><<--";
+    assert_eq!(to_source(&parse(source).unwrap()), source);
+    assert_eq!(to_source(&parse("a+b+c").unwrap()), "++");
+}
+
+#[test]
+fn test_relink() {
+    let mut insts = parse("[[]]").unwrap();
+    insts.insert(1, IncVal(1));
+    insts.insert(3, DecVal(1));
+    relink(&mut insts);
+    verify(&insts).unwrap();
+    assert_eq!(to_source(&insts), "[+[-]]");
+}
+
+#[test]
+fn test_fingerprint() {
+    let fingerprint = |program: &str| Brainfuck::new(program).unwrap().fingerprint();
+
+    let hello = include_str!("../tests/fixtures/hello.b");
+    let commented = format!("Hello World\n{}\n  with a comment ", hello.replace("[", "\n[ "));
+    assert_eq!(fingerprint(hello), fingerprint(&commented));
+
+    // pinned, so accidental changes to the hash are noticed
+    assert_eq!(fingerprint(""), 0x200f_4601_e477_95a6);
+
+    let variants = ["+[->+<]", "+[->+<]+", "+[->+<]-", "+[->>+<]", "+[-]>+<", "+[->+<.]", "+[->+<,]"];
+    for (i, a) in variants.iter().enumerate() {
+        for b in &variants[i + 1..] {
+            assert!(fingerprint(a) != fingerprint(b), "{} and {} collide", a, b);
+        }
+    }
+
+    let mut bf = Brainfuck::new(hello).unwrap();
+    bf.set_tape_size(100).unwrap();
+    assert!(bf.fingerprint() != fingerprint(hello));
+
+    // how the tape starts out is part of the program
+    let mut a = Brainfuck::new(hello).unwrap();
+    let mut b = Brainfuck::new(hello).unwrap();
+    a.set_initial_tape(&[1, 2]).unwrap();
+    b.set_initial_tape(&[1, 3]).unwrap();
+    assert!(a.fingerprint() != b.fingerprint());
+    assert!(a.fingerprint() != fingerprint(hello));
+
+    let tweaks: [&dyn Fn(&mut Brainfuck); 4] = [
+        &|bf| bf.set_pointer_start(1).unwrap(),
+        &|bf| bf.set_tape_guard(16),
+        &|bf| bf.set_tape_file(Some(PathBuf::from("tape")), TapeFileSize::Exact),
+        &|bf| bf.set_tape_file(Some(PathBuf::from("tape")), TapeFileSize::Resize),
+    ];
+    let mut fingerprints = vec![fingerprint(hello)];
+    for tweak in &tweaks {
+        let mut bf = Brainfuck::new(hello).unwrap();
+        tweak(&mut bf);
+        fingerprints.push(bf.fingerprint());
+    }
+    for (i, a) in fingerprints.iter().enumerate() {
+        assert!(!fingerprints[i + 1..].contains(a), "tweak {} collides", i);
+    }
+}
+
+#[test]
+fn test_concat() {
+    use interp::run_with_input;
+
+    let hello = include_str!("../tests/fixtures/hello.b");
+    // the first seam splits a run, the second one follows a loop
+    let (start, rest) = hello.split_at(4);
+    let (middle, end) = rest.split_at(rest.find("]>>.").unwrap() + 1);
+
+    let pieces: Vec<Brainfuck> = [start, "", middle, end].iter()
+        .map(|piece| Brainfuck::new(piece).unwrap())
+        .collect();
+    let concatenated = Brainfuck::concat(&pieces.iter().collect::<Vec<_>>()).unwrap();
+    let monolithic = Brainfuck::new(hello).unwrap();
+
+    assert!(concatenated == monolithic);
+    assert_eq!(concatenated.jit_code().unwrap(), monolithic.jit_code().unwrap());
+    assert_eq!(run_with_input(&concatenated.insts, 30_000, b"").unwrap(), b"Hello World!\n");
+    assert_eq!(concatenated.spans(), monolithic.spans());
+    // the run the first seam splits is the first fragment's
+    assert_eq!(concatenated.fragment_of(0), Some(0));
+    assert_eq!(concatenated.fragment_of(1), Some(2));
+    assert_eq!(concatenated.fragment_of(concatenated.insts.len() - 1), Some(3));
+    assert_eq!(concatenated.fragment_of(concatenated.insts.len()), None);
+    assert_eq!(monolithic.fragment_of(0), None);
+
+    assert!(Brainfuck::concat(&[]).unwrap().insts.is_empty());
+
+    // runs only merge if both sides have theirs merged
+    let parsed = Brainfuck::new("+++").unwrap();
+    let unmerged = Brainfuck::from_insts(vec![IncVal(1), IncVal(1)]).unwrap();
+    let concatenated = Brainfuck::concat(&[&parsed, &unmerged, &parsed]).unwrap();
+    assert_eq!(concatenated.insts, [IncVal(3), IncVal(1), IncVal(1), IncVal(3)]);
+    assert!(concatenated.spans().is_empty());
+    assert_eq!(Brainfuck::concat(&[&parsed, &parsed]).unwrap().insts, [IncVal(6)]);
+
+    let mut small = Brainfuck::new(">").unwrap();
+    small.set_tape_size(100).unwrap();
+    let concatenated = Brainfuck::concat(&[&small, &small]).unwrap();
+    assert_eq!(concatenated.insts, [IncPtr(2)]);
+    assert_eq!(concatenated.tape_size(), 100);
+}
+
+#[test]
+fn test_verify() {
+    verify(&parse("+[->[+]<]++[]").unwrap()).unwrap();
+    verify(&[]).unwrap();
+
+    let invalid: &[(&[Inst], usize)] = &[
+        (&[JmpFwd(1)], 0),
+        (&[JmpBack(0)], 0),
+        (&[JmpFwd(2), JmpFwd(3), JmpBack(0), JmpBack(1)], 2),
+        (&[JmpFwd(1), JmpBack(1)], 0),
+        (&[JmpFwd(5), JmpBack(0)], 0),
+        (&[IncVal(1), JmpBack(1)], 1),
+    ];
+    for &(insts, index) in invalid {
+        match verify(insts) {
+            Err(CompileError::InvalidJump { inst_index }) => assert_eq!(inst_index, index),
+            other => panic!("{:?} accepted: {:?}", insts, other),
+        }
+    }
+}
+
+#[test]
+fn test_from_insts() {
+    let sources = [
+        "",
+        "+[->[+]<]++[]",
+        include_str!("../tests/fixtures/hello.b"),
+        include_str!("../tests/fixtures/rot13.b"),
+    ];
+    for source in &sources {
+        let bf = Brainfuck::new(source).unwrap();
+        let from_insts = Brainfuck::from_insts(bf.insts().to_vec()).unwrap();
+        assert_eq!(from_insts.jit_code().unwrap(), bf.jit_code().unwrap());
+        let unchecked = Brainfuck::from_insts_unchecked(bf.insts().to_vec()).unwrap();
+        assert_eq!(unchecked.jit_code().unwrap(), bf.jit_code().unwrap());
+    }
+
+    // rejected before code generation could trip over them
+    for insts in [vec![JmpBack(0)], vec![JmpFwd(1), JmpBack(1)], vec![JmpFwd(7)]] {
+        assert!(matches!(Brainfuck::from_insts(insts), Err(CompileError::InvalidJump { inst_index: 0 })));
+    }
+}
+
+// Instructions covering every emitter, see `test_codegen_golden`
+#[cfg(test)]
+fn codegen_cases() -> Vec<(&'static str, Vec<Inst>)> {
+    vec![
+        ("inc ptr", vec![IncPtr(1)]),
+        ("add ptr", vec![IncPtr(300)]),
+        ("dec ptr", vec![DecPtr(1)]),
+        ("sub ptr", vec![DecPtr(300)]),
+        ("inc val", vec![IncVal(1)]),
+        ("add val", vec![IncVal(7)]),
+        ("dec val", vec![DecVal(1)]),
+        ("sub val", vec![DecVal(7)]),
+        ("print", vec![PrintCell]),
+        ("read", vec![ReadChar]),
+        ("empty loop", vec![JmpFwd(1), JmpBack(0)]),
+        ("nested loops", parse(",[>++[>+<-]<-.]").unwrap()),
+        ("scan right", parse("[>]").unwrap()),
+        ("scan left", parse("[<]").unwrap()),
+        ("zero range", parse("[-]>[-]>[+]>[---]<[-]<[-]<[-]<[-]").unwrap()),
+        ("value run", vec![IncVal(3), DecVal(1), IncVal(300), PrintCell, DecVal(2), IncPtr(1), IncVal(1), DecVal(1)]),
+    ]
+}
+
+// Pins the generated code in tests/fixtures/codegen.golden, a hexdump for
+// every case. After an intentional change to the emitters, running this
+// with BRAINFUCK_BLESS=1 writes the new code there, to be reviewed in the diff.
+#[test]
+fn test_codegen_golden() {
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+
+    let mut golden = Vec::new();
+    for (name, insts) in codegen_cases() {
+        writeln!(golden, "{}: {:?}", name, insts).unwrap();
+        write_hexdump(&compile_insts(&insts, &CodegenOptions::default()).unwrap(), &mut golden).unwrap();
+        writeln!(golden).unwrap();
+    }
+    let golden = String::from_utf8(golden).unwrap();
+
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/codegen.golden");
+    if env::var_os("BRAINFUCK_BLESS").is_some() {
+        fs::write(&path, &golden).unwrap();
+    }
+    let expected = fs::read_to_string(&path).unwrap();
+    assert!(golden == expected, "generated code changed, BRAINFUCK_BLESS=1 updates {}:\n{}", path.display(), golden);
+}
+
+#[test]
+fn test_compile_insts() {
+    let insts = parse(include_str!("../tests/fixtures/rot13.b")).unwrap();
+    let options = CodegenOptions::default();
+    let code = compile_insts(&insts, &options).unwrap();
+    assert_eq!(code, Brainfuck::new(include_str!("../tests/fixtures/rot13.b")).unwrap().jit_code().unwrap());
+
+    // deterministic, also on other threads
+    for _ in 0..10 {
+        assert_eq!(compile_insts(&insts, &options).unwrap(), code);
+    }
+    let insts = &insts;
+    let other = thread::scope(|scope| scope.spawn(|| compile_insts(insts, &CodegenOptions::default())).join());
+    assert_eq!(other.unwrap().unwrap(), code);
+
+    assert!(matches!(compile_insts(&[JmpBack(0)], &options), Err(CompileError::InvalidJump { inst_index: 0 })));
+    let small = CodegenOptions { max_code_size: 16, ..CodegenOptions::default() };
+    assert!(matches!(compile_insts(insts, &small), Err(CompileError::CodeTooLarge { limit: 16, .. })));
+}
+
+// `compile_streaming` of `source` into a temporary file, and the code it wrote
+#[cfg(test)]
+fn streamed(source: &[u8]) -> Result<Vec<u8>, CompileError> {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+    let name = format!("brainfuck-streamed-{}-{}", ::std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed));
+    let path = ::std::env::temp_dir().join(name);
+    let mut out = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path).unwrap();
+    // something to truncate
+    out.write_all(&[0xcc; 100]).unwrap();
+    let result = compile_streaming(source, &mut out);
+    let code = ::std::fs::read(&path).unwrap();
+    ::std::fs::remove_file(&path).unwrap();
+
+    result.map(|()| code)
+}
+
+#[test]
+fn test_inst_stream() {
+    let sources = [
+        "",
+        "comment only",
+        include_str!("../tests/fixtures/rot13.b"),
+        "+ä+ +\n-->x>.,,[[]]<<ö<[-]",
+        "[]]]",
+    ];
+    for source in &sources {
+        let streamed: Result<Vec<Inst>, CompileError> = InstStream::new(source.as_bytes()).collect();
+        match parse(source) {
+            Ok(insts) => {
+                let mut streamed = streamed.unwrap();
+                relink(&mut streamed);
+                assert_eq!(streamed, insts, "{:?}", source);
+            }
+            Err(_) => assert!(streamed.is_err()),
+        }
+    }
+
+    // the first bracket that doesn't pair up, after what comes before it
+    let insts: Vec<_> = InstStream::new(&b"++]+"[..]).collect();
+    assert!(matches!(insts[..], [Ok(IncVal(2)), Err(CompileError::UnmatchedClose { offset: 2 })]));
+    let insts: Vec<_> = InstStream::new(&b"x[[]--"[..]).collect();
+    assert!(matches!(
+        insts[..],
+        [Ok(JmpFwd(0)), Ok(JmpFwd(0)), Ok(JmpBack(0)), Ok(DecVal(2)), Err(CompileError::UnclosedOpen { offset: 1 })]
+    ));
+}
+
+#[test]
+fn test_compile_streaming() {
+    let mut sources = vec![
+        String::new(),
+        include_str!("../tests/programs/hello.b").to_string(),
+        include_str!("../tests/programs/rot13.b").to_string(),
+        include_str!("../tests/programs/squares.b").to_string(),
+        include_str!("../tests/programs/sierpinski.b").to_string(),
+        "+[>]<[<]>[-]>[-]>[+]>[---]<[-]<[-]<[-]<[-]".to_string(),
+        // a cleared range up to the end
+        ">>>>>[-]<[-]<[-]<[-]".to_string(),
+    ];
+    sources.extend((0..20).map(|seed| to_source(&arithmetic_program(seed, 100))));
+    // more code than `apply_patches` patches at a time, with jumps over
+    // the chunks
+    let mut large = "[".repeat(1000);
+    large.push_str(&">+<[>]-[<]".repeat(100_000));
+    large.push_str(&"]".repeat(1000));
+    sources.push(large);
+
+    for source in &sources {
+        let code = compile_insts(&parse(source).unwrap(), &CodegenOptions::default()).unwrap();
+        assert!(streamed(source.as_bytes()).unwrap() == code, "{:.100}", source);
+    }
+
+    assert!(matches!(streamed(b"+[[]"), Err(CompileError::UnclosedOpen { offset: 1 })));
+    assert!(matches!(streamed(b"+]["), Err(CompileError::UnmatchedClose { offset: 1 })));
+}
+
+#[cfg(test)]
+fn jit_code(program: &str) -> Vec<u8> {
+    Brainfuck::new(program).unwrap().jit_code().unwrap().to_vec()
+}
+
+#[test]
+fn test_compile_golden() {
+    assert_eq!(jit_code(""), [0x49, 0x89, 0xf9, 0x31, 0xc0, 0xc3]);
+    assert_eq!(jit_code("+"), [0x49, 0x89, 0xf9, 0xfe, 0x06, 0x31, 0xc0, 0xc3]);
+    // the stubs for the pointer and I/O checks come last, in program order
+    assert_eq!(jit_code("++>-<<,."), [
+        0x49, 0x89, 0xf9,
+        0x80, 0x06, 0x02,
+        0x48, 0xff, 0xc6,
+        0x49, 0x3b, 0x71, 0x18, 0x0f, 0x83, 0x6e, 0x00, 0x00, 0x00,
+        0xfe, 0x0e,
+        0x48, 0x81, 0xee, 0x02, 0x00, 0x00, 0x00,
+        0x49, 0x3b, 0x71, 0x10, 0x0f, 0x82, 0x6a, 0x00, 0x00, 0x00,
+        0x48, 0x31, 0xc0, 0x41, 0x8b, 0x79, 0x08,
+        0xba, 0x01, 0x00, 0x00, 0x00, 0x0f, 0x05,
+        0x48, 0x83, 0xf8, 0xfc, 0x74, 0xec, 0x48, 0x83, 0xf8, 0xf5, 0x74, 0xe6, 0x48, 0x85, 0xc0,
+        0x79, 0x0b, 0xf7, 0xd8, 0x41, 0x89, 0x41, 0x24, 0xe9, 0x4f, 0x00, 0x00, 0x00,
+        0xb8, 0x01, 0x00, 0x00, 0x00, 0x41, 0x8b, 0x79, 0x0c,
+        0xba, 0x01, 0x00, 0x00, 0x00, 0x0f, 0x05,
+        0x48, 0x83, 0xf8, 0xfc, 0x74, 0xea, 0x48, 0x83, 0xf8, 0xf5, 0x74, 0xe4, 0x48, 0x85, 0xc0, 0x74, 0xdf,
+        0x79, 0x0b, 0xf7, 0xd8, 0x41, 0x89, 0x41, 0x24, 0xe9, 0x30, 0x00, 0x00, 0x00,
+        0x31, 0xc0, 0xc3,
+        0xba, 0x01, 0x00, 0x00, 0x00, 0xb8, 0x02, 0x00, 0x00, 0x00, 0xe9, 0xf0, 0xff, 0xff, 0xff,
+        0xba, 0x03, 0x00, 0x00, 0x00, 0xb8, 0x01, 0x00, 0x00, 0x00, 0xe9, 0xe1, 0xff, 0xff, 0xff,
+        0xba, 0x04, 0x00, 0x00, 0x00, 0xb8, 0x08, 0x00, 0x00, 0x00, 0xe9, 0xd2, 0xff, 0xff, 0xff,
+        0xba, 0x05, 0x00, 0x00, 0x00, 0xb8, 0x08, 0x00, 0x00, 0x00, 0xe9, 0xc3, 0xff, 0xff, 0xff,
+    ][..]);
+    assert_eq!(jit_code("+[-]"), [
+        0x49, 0x89, 0xf9,
+        0xfe, 0x06,
+        0x80, 0x3e, 0x00, 0x0f, 0x84, 0x0b, 0x00, 0x00, 0x00,
+        0xfe, 0x0e,
+        0x80, 0x3e, 0x00, 0x0f, 0x85, 0xf5, 0xff, 0xff, 0xff,
+        0x31, 0xc0, 0xc3,
+    ][..]);
+    assert_eq!(jit_code("++[>+++[>+<-]<-]"), [
+        0x49, 0x89, 0xf9,
+        0x80, 0x06, 0x02,
+        0x80, 0x3e, 0x00, 0x0f, 0x84, 0x58, 0x00, 0x00, 0x00,
+        0x48, 0xff, 0xc6,
+        0x49, 0x3b, 0x71, 0x18, 0x0f, 0x83, 0x4e, 0x00, 0x00, 0x00,
+        0x80, 0x06, 0x03,
+        0x80, 0x3e, 0x00, 0x0f, 0x84, 0x27, 0x00, 0x00, 0x00,
+        0x48, 0xff, 0xc6,
+        0x49, 0x3b, 0x71, 0x18, 0x0f, 0x83, 0x44, 0x00, 0x00, 0x00,
+        0xfe, 0x06,
+        0x48, 0xff, 0xce,
+        0x49, 0x3b, 0x71, 0x10, 0x0f, 0x82, 0x44, 0x00, 0x00, 0x00,
+        0xfe, 0x0e,
+        0x80, 0x3e, 0x00, 0x0f, 0x85, 0xd9, 0xff, 0xff, 0xff,
+        0x48, 0xff, 0xce,
+        0x49, 0x3b, 0x71, 0x10, 0x0f, 0x82, 0x3b, 0x00, 0x00, 0x00,
+        0xfe, 0x0e,
+        0x80, 0x3e, 0x00, 0x0f, 0x85, 0xa8, 0xff, 0xff, 0xff,
+        0x31, 0xc0, 0xc3,
+        0xba, 0x02, 0x00, 0x00, 0x00, 0xb8, 0x02, 0x00, 0x00, 0x00, 0xe9, 0xf0, 0xff, 0xff, 0xff,
+        0xba, 0x05, 0x00, 0x00, 0x00, 0xb8, 0x02, 0x00, 0x00, 0x00, 0xe9, 0xe1, 0xff, 0xff, 0xff,
+        0xba, 0x07, 0x00, 0x00, 0x00, 0xb8, 0x01, 0x00, 0x00, 0x00, 0xe9, 0xd2, 0xff, 0xff, 0xff,
+        0xba, 0x0a, 0x00, 0x00, 0x00, 0xb8, 0x01, 0x00, 0x00, 0x00, 0xe9, 0xc3, 0xff, 0xff, 0xff,
+    ][..]);
+
+    let mut program = ">".repeat(10);
+    program.push_str(&"+".repeat(253));
+    program.push_str("<<-------");
+    assert_eq!(jit_code(&program), [
+        0x49, 0x89, 0xf9,
+        0x48, 0x81, 0xc6, 0x0a, 0x00, 0x00, 0x00,
+        0x49, 0x3b, 0x71, 0x18, 0x0f, 0x83, 0x1a, 0x00, 0x00, 0x00,
+        0x80, 0x06, 0xfd,
+        0x48, 0x81, 0xee, 0x02, 0x00, 0x00, 0x00,
+        0x49, 0x3b, 0x71, 0x10, 0x0f, 0x82, 0x15, 0x00, 0x00, 0x00,
+        0x80, 0x2e, 0x07,
+        0x31, 0xc0, 0xc3,
+        0xba, 0x00, 0x00, 0x00, 0x00, 0xb8, 0x02, 0x00, 0x00, 0x00, 0xe9, 0xf0, 0xff, 0xff, 0xff,
+        0xba, 0x02, 0x00, 0x00, 0x00, 0xb8, 0x01, 0x00, 0x00, 0x00, 0xe9, 0xe1, 0xff, 0xff, 0xff,
+    ][..]);
+}
+
+// Runs a hand-made code buffer like generated code on a tape of 16 cells
+#[cfg(test)]
+fn run_code(code: &[u8]) -> Result<Vec<u8>, RuntimeError> {
+    let mapped = map_code(code)?;
+    mapped.with_code(|code| {
+        let code = unsafe { ::std::slice::from_raw_parts(code, mapped.len()) };
+        call(code, CallIo::stdio(&AtomicBool::new(false)), 1, 16, &[], 0, &MemoryBudget::unlimited())
+    })
+}
+
+// `+++` with the tape pointer overwritten by the prologue
+#[cfg(test)]
+fn null_pointer_code() -> Vec<u8> {
+    let mut code = jit_code("+++");
+    assert_eq!(code[..3], [0x49, 0x89, 0xf9]);
+    code[..3].copy_from_slice(&[0x31, 0xf6, 0x90]); // xor esi, esi; nop
+    code
+}
+
+#[test]
+fn test_fault() {
+    match run_code(&null_pointer_code()) {
+        Err(RuntimeError::Fault { signal: libc::SIGSEGV, address: 0, rip: 3 }) => {}
+        other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(run_code(&jit_code("+++")).unwrap()[0], 3);
+
+    // runs on many threads at once each catch their own faults
+    let threads: Vec<_> = (0..8)
+        .map(|t| thread::spawn(move || {
+            for i in 0..50 {
+                if (t + i) % 2 == 0 {
+                    assert!(matches!(run_code(&null_pointer_code()), Err(RuntimeError::Fault { .. })));
+                } else {
+                    assert_eq!(run_code(&jit_code("++")).unwrap()[0], 2);
+                }
+            }
+        }))
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+}
+
+// In a forked child, as other tests install the handler concurrently
+#[cfg(target_os = "linux")]
+#[test]
+fn test_fault_handlers_restored() {
+    unsafe fn handler(signal: libc::c_int) -> libc::sighandler_t {
+        let mut action: libc::sigaction = mem::zeroed();
+        libc::sigaction(signal, ptr::null(), &mut action);
+        action.sa_sigaction
+    }
+
+    let code = null_pointer_code();
+    let pid = unsafe { libc::fork() };
+    assert!(pid >= 0);
+    if pid == 0 {
+        let before = unsafe { handler(libc::SIGSEGV) };
+        let caught = matches!(run_code(&code), Err(RuntimeError::Fault { .. }));
+        let restored = unsafe { handler(libc::SIGSEGV) } == before;
+        if !caught || !restored {
+            unsafe { libc::_exit(1) }
+        }
+
+        // faults outside the guarded code still crash
+        let _guard = FaultGuard::enter(&code).unwrap();
+        unsafe {
+            ptr::write_volatile(ptr::null_mut::<u8>().wrapping_add(8), 1);
+            libc::_exit(2)
+        }
+    }
+
+    let mut status = 0;
+    assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+    assert!(libc::WIFSIGNALED(status), "status {:#x}", status);
+    assert_eq!(libc::WTERMSIG(status), libc::SIGSEGV);
+}
+
+// Runs `code` in the sandbox in a forked child, as the sandbox can't be
+// left again. Returns the wait status and what the code wrote to stdout.
+#[cfg(all(test, target_os = "linux"))]
+fn run_in_sandboxed_child(code: &[u8]) -> (libc::c_int, Vec<u8>) {
+    use std::fs::File;
+    use std::io::Read;
+    use std::os::unix::io::FromRawFd;
+
+    let mapped = map_code(code).unwrap();
+    let mut pipe = [0; 2];
+    assert_eq!(unsafe { libc::pipe(pipe.as_mut_ptr()) }, 0);
+
+    let pid = unsafe { libc::fork() };
+    assert!(pid >= 0, "{}", io::Error::last_os_error());
+    if pid == 0 {
+        unsafe {
+            libc::dup2(pipe[1], libc::STDOUT_FILENO);
+            libc::close(pipe[0]);
+            libc::close(pipe[1]);
+        }
+        let _ = mapped.with_code(|code| call_sandboxed(code, Tape::new(100, TapeAlloc::Default), 0));
+        // only reached if the sandbox couldn't be entered
+        unsafe { libc::_exit(100) }
+    }
+
+    unsafe { libc::close(pipe[1]) };
+    let mut output = Vec::new();
+    unsafe { File::from_raw_fd(pipe[0]) }.read_to_end(&mut output).unwrap();
+    let mut status = 0;
+    assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+    (status, output)
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_sandbox() {
+    let (status, output) = run_in_sandboxed_child(&jit_code(include_str!("../tests/fixtures/hello.b")));
+    assert!(libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0, "status {:#x}", status);
+    assert_eq!(output, b"Hello World!\n");
+
+    // what a codegen bug could end up doing, this returns normally
+    // without the sandbox, failing to open or not
+    let openat = [
+        0xb8, 0x01, 0x01, 0x00, 0x00, // mov eax, 257
+        0xbf, 0x9c, 0xff, 0xff, 0xff, // mov edi, AT_FDCWD
+        0x31, 0xd2, // xor edx, edx
+        0x0f, 0x05, // syscall
+        0x31, 0xc0, 0xc3, // xor eax, eax; ret
+    ];
+    let (status, _) = run_in_sandboxed_child(&openat);
+    assert!(libc::WIFSIGNALED(status), "status {:#x}", status);
+    assert_eq!(libc::WTERMSIG(status), libc::SIGSYS);
+
+    // writing is fine, but only to stdout and stderr
+    let write_fd3 = [
+        0xb8, 0x01, 0x00, 0x00, 0x00, // mov eax, 1
+        0xbf, 0x03, 0x00, 0x00, 0x00, // mov edi, 3
+        0xba, 0x01, 0x00, 0x00, 0x00, // mov edx, 1
+        0x0f, 0x05, // syscall
+        0x31, 0xc0, 0xc3, // xor eax, eax; ret
+    ];
+    let (status, _) = run_in_sandboxed_child(&write_fd3);
+    assert!(libc::WIFSIGNALED(status) && libc::WTERMSIG(status) == libc::SIGSYS, "status {:#x}", status);
+}
@@ -0,0 +1,155 @@
+// Brainloller, brainfuck as the colors of the pixels of an image, for
+// `--lang brainloller`. The instruction pointer starts on the top left
+// pixel going right, the two cyans turn it, and the program ends where it
+// leaves the image. It always does, as where it was before every step can be
+// told from where it is, and the way back leads out of the top left corner.
+// Colors that aren't commands are comments.
+//
+// The pixels are translated to brainfuck for the parser, keeping where
+// every command came from, so that errors point at pixels. Reading images
+// needs the `brainloller` feature, the translation doesn't.
+use std::fmt;
+
+#[cfg(feature = "brainloller")]
+use image;
+
+use brainfuck::CompileError;
+use diagnostics::{Diagnostic, ErrorCode};
+
+
+const COMMANDS: [([u8; 3], char); 8] = [
+    ([255, 0, 0], '>'),
+    ([128, 0, 0], '<'),
+    ([0, 255, 0], '+'),
+    ([0, 128, 0], '-'),
+    ([0, 0, 255], '.'),
+    ([0, 0, 128], ','),
+    ([255, 255, 0], '['),
+    ([128, 128, 0], ']'),
+];
+const CLOCKWISE: [u8; 3] = [0, 255, 255];
+const COUNTERCLOCKWISE: [u8; 3] = [0, 128, 128];
+
+// Right, down, left and up, clockwise
+const DIRECTIONS: [(isize, isize); 4] = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+
+#[derive(Debug)]
+pub enum BrainlollerError {
+    // Not an image that can be read, or none at all
+    Image(String),
+    // Built without the `brainloller` feature
+    #[cfg_attr(feature = "brainloller", allow(dead_code))]
+    Unsupported,
+}
+
+impl fmt::Display for BrainlollerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BrainlollerError::Image(ref message) => write!(f, "invalid image: {}", message),
+            BrainlollerError::Unsupported => write!(f, "reading images needs the brainloller feature"),
+        }
+    }
+}
+
+impl ::std::error::Error for BrainlollerError {}
+
+impl ErrorCode for BrainlollerError {
+    fn code(&self) -> &'static str {
+        match *self {
+            BrainlollerError::Image(_) => "invalid-image",
+            BrainlollerError::Unsupported => "unsupported-language",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    // the commands in the order the instruction pointer meets them
+    pub source: String,
+    // the pixel of every command, by its offset into `source`
+    pub pixels: Vec<(usize, usize)>,
+}
+
+// Walks the `width` by `height` pixels `pixel` gives the color of by their
+// column and row
+pub fn translate<F>(width: usize, height: usize, pixel: F) -> Program
+    where F: Fn(usize, usize) -> [u8; 3]
+{
+    let mut program = Program { source: String::new(), pixels: Vec::new() };
+    let (mut x, mut y, mut direction) = (0, 0, 0);
+
+    while x < width && y < height {
+        let color = pixel(x, y);
+        if color == CLOCKWISE {
+            direction = (direction + 1) % 4;
+        } else if color == COUNTERCLOCKWISE {
+            direction = (direction + 3) % 4;
+        } else if let Some(&(_, command)) = COMMANDS.iter().find(|&&(rgb, _)| rgb == color) {
+            program.source.push(command);
+            program.pixels.push((x, y));
+        }
+
+        // leaving at the top or the left wraps, which the bounds catch
+        let (dx, dy) = DIRECTIONS[direction];
+        x = (x as isize + dx) as usize;
+        y = (y as isize + dy) as usize;
+    }
+
+    program
+}
+
+// The program of an image in any format the `image` crate reads, with the
+// colors of its pixels as 8 bit RGB
+#[cfg(feature = "brainloller")]
+pub fn decode(bytes: &[u8]) -> Result<Program, BrainlollerError> {
+    let image = image::load_from_memory(bytes).map_err(|e| BrainlollerError::Image(e.to_string()))?.to_rgb8();
+    Ok(translate(image.width() as usize, image.height() as usize, |x, y| image.get_pixel(x as u32, y as u32).0))
+}
+
+#[cfg(not(feature = "brainloller"))]
+pub fn decode(_: &[u8]) -> Result<Program, BrainlollerError> {
+    Err(BrainlollerError::Unsupported)
+}
+
+// `diagnostics::compile_error` for the translated program, with the pixel
+// of a command an error is at in the message
+pub fn compile_error(program: &Program, err: &CompileError) -> Vec<Diagnostic> {
+    match *err {
+        CompileError::Multiple(ref errors) => errors.iter().flat_map(|err| compile_error(program, err)).collect(),
+        _ => match err.offset().and_then(|offset| program.pixels.get(offset)) {
+            Some(&(x, y)) => vec![Diagnostic::new(err.code(), format!("{} at pixel ({}, {})", err, x, y))],
+            None => vec![Diagnostic::error(err)],
+        },
+    }
+}
+
+
+#[cfg(test)]
+fn pixels<'a>(rows: &'a [&'a str]) -> impl Fn(usize, usize) -> [u8; 3] + 'a {
+    // a character a pixel: the command, `r` and `l` for the cyans and
+    // anything else for white
+    move |x, y| match rows[y].as_bytes()[x] {
+        b'r' => CLOCKWISE,
+        b'l' => COUNTERCLOCKWISE,
+        c => COMMANDS.iter().find(|&&(_, command)| command as u8 == c).map_or([255, 255, 255], |&(rgb, _)| rgb),
+    }
+}
+
+#[test]
+fn test_translate() {
+    // along the first row, back along the second and along the third
+    let rows = ["+ +r", "l.<r", "l  -"];
+    let program = translate(4, 3, pixels(&rows));
+    assert_eq!(program.source, "++<.-");
+    assert_eq!(program.pixels, [(0, 0), (2, 0), (2, 1), (1, 1), (3, 2)]);
+
+    // back along the second row and out at the left
+    let program = translate(3, 2, pixels(&["+ r", ".-r"]));
+    assert_eq!(program.source, "+-.");
+
+    let program = translate(3, 1, pixels(&["+]+"]));
+    let err = ::brainfuck::parse(&program.source).unwrap_err();
+    let diagnostics = compile_error(&program, &err);
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].to_human(), "error: unmatched ']' at pixel (1, 0)");
+}
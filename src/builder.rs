@@ -0,0 +1,153 @@
+// Programs put together from code instead of text. Loops are closures, so
+// every loop a builder makes is closed again. Adjacent moves and changes in
+// the same direction merge into one instruction, as `parse` would merge
+// them, so `to_source` of the instructions parses back to the same ones.
+use brainfuck::{relink, to_source, Inst};
+use brainfuck::Inst::*;
+
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProgramBuilder {
+    insts: Vec<Inst>,
+}
+
+impl ProgramBuilder {
+    pub fn new() -> ProgramBuilder {
+        ProgramBuilder::default()
+    }
+
+    fn push(&mut self, inst: Inst) -> &mut ProgramBuilder {
+        let merged = match (self.insts.last_mut(), &inst) {
+            (Some(&mut IncPtr(ref mut a)), &IncPtr(b))
+            | (Some(&mut DecPtr(ref mut a)), &DecPtr(b))
+            | (Some(&mut IncVal(ref mut a)), &IncVal(b))
+            | (Some(&mut DecVal(ref mut a)), &DecVal(b)) => {
+                *a += b;
+                true
+            }
+            _ => false,
+        };
+        if !merged {
+            self.insts.push(inst);
+        }
+        self
+    }
+
+    // `+` `n` times, or `-` for a negative `n`
+    pub fn add(&mut self, n: isize) -> &mut ProgramBuilder {
+        match n {
+            0 => self,
+            n if n > 0 => self.push(IncVal(n as usize)),
+            n => self.push(DecVal(n.unsigned_abs())),
+        }
+    }
+
+    // `>` `n` times, or `<` for a negative `n`
+    pub fn move_ptr(&mut self, n: isize) -> &mut ProgramBuilder {
+        match n {
+            0 => self,
+            n if n > 0 => self.push(IncPtr(n as usize)),
+            n => self.push(DecPtr(n.unsigned_abs())),
+        }
+    }
+
+    pub fn output(&mut self) -> &mut ProgramBuilder {
+        self.push(PrintCell)
+    }
+
+    pub fn input(&mut self) -> &mut ProgramBuilder {
+        self.push(ReadChar)
+    }
+
+    // A loop around what `body` builds
+    pub fn loop_<F: FnOnce(&mut ProgramBuilder)>(&mut self, body: F) -> &mut ProgramBuilder {
+        self.push(JmpFwd(0));
+        body(self);
+        self.push(JmpBack(0))
+    }
+
+    // Sets the cell to `value`, clearing it with `[-]` first
+    pub fn set(&mut self, value: u8) -> &mut ProgramBuilder {
+        self.loop_(|b| {
+            b.add(-1);
+        });
+        self.add(value as isize)
+    }
+
+    // Prints `text` from the current cell, which it clears first and leaves
+    // at the last byte. Every byte is reached from the one before it, going
+    // whichever way round the cell's 256 values is shorter.
+    pub fn print_str(&mut self, text: &str) -> &mut ProgramBuilder {
+        self.set(0);
+        let mut cell = 0u8;
+        for byte in text.bytes() {
+            let up = byte.wrapping_sub(cell) as isize;
+            self.add(if up <= 128 { up } else { up - 256 }).output();
+            cell = byte;
+        }
+        self
+    }
+
+    // The instructions, with their jumps linked, ready for `from_insts`
+    pub fn build(&self) -> Vec<Inst> {
+        let mut insts = self.insts.clone();
+        relink(&mut insts);
+        insts
+    }
+
+    pub fn to_source(&self) -> String {
+        to_source(&self.insts)
+    }
+}
+
+
+#[cfg(test)]
+use brainfuck::parse;
+#[cfg(test)]
+use interp::run_with_input;
+
+#[test]
+fn test_merging() {
+    let mut builder = ProgramBuilder::new();
+    builder.add(2).add(3).add(-1).add(0).move_ptr(-2).move_ptr(-1).move_ptr(4).output().output();
+    assert_eq!(builder.build(), [IncVal(5), DecVal(1), DecPtr(3), IncPtr(4), PrintCell, PrintCell]);
+    assert_eq!(builder.to_source(), "+++++-<<<>>>>..");
+}
+
+#[test]
+fn test_loops() {
+    // 8 * 8 + 1 in the cell to the right
+    let mut builder = ProgramBuilder::new();
+    builder.set(8).loop_(|b| {
+        b.move_ptr(1).add(8).move_ptr(-1).add(-1);
+    });
+    builder.move_ptr(1).add(1).output().input().loop_(|b| {
+        b.loop_(|b| {
+            b.add(-1);
+        });
+    });
+
+    let insts = builder.build();
+    assert_eq!(insts[..5], [JmpFwd(2), DecVal(1), JmpBack(0), IncVal(8), JmpFwd(9)]);
+    assert_eq!(builder.to_source(), "[-]++++++++[>++++++++<-]>+.,[[-]]");
+    assert_eq!(parse(&builder.to_source()).unwrap(), insts);
+    assert_eq!(run_with_input(&insts, 2, b"x").unwrap(), b"A");
+}
+
+#[test]
+fn test_hello_world() {
+    let mut builder = ProgramBuilder::new();
+    builder.print_str("Hello World!\n");
+    let hello = parse(include_str!("../tests/fixtures/hello.b")).unwrap();
+    assert_eq!(
+        run_with_input(&builder.build(), 1, b"").unwrap(),
+        run_with_input(&hello, 30_000, b"").unwrap()
+    );
+    assert_eq!(parse(&builder.to_source()).unwrap(), builder.build());
+
+    // the way down from a byte can be shorter than the way up
+    let mut builder = ProgramBuilder::new();
+    builder.print_str("\u{7f}\0\u{ff}");
+    assert_eq!(run_with_input(&builder.build(), 1, b"").unwrap(), "\u{7f}\0\u{ff}".as_bytes());
+    assert_eq!(builder.to_source().len(), 3 + 127 + 1 + 127 + 1 + 61 + 1 + 4 + 1);
+}
@@ -0,0 +1,307 @@
+use std::fmt;
+
+use brainfuck::{verify, CompileError, ExtOp, Inst};
+use brainfuck::Inst::*;
+
+
+// Layout of a .bfc file, all integers little endian:
+//
+//   magic              4 bytes, "\0BFC"
+//   format version     u16
+//   cell width         u8, in bits
+//   EOF mode           u8, what `,` does at the end of input
+//   tape size          u64
+//   instruction count  varint
+//   instructions       a tag each, followed by a varint operand for
+//                      everything but I/O: the amount or the jump target
+//   checksum           u64, FNV-1a over everything before it
+//
+// Varints are LEB128, 7 bits per byte with the high bit set on all but the
+// last one.
+pub const MAGIC: &[u8; 4] = b"\0BFC";
+pub const VERSION: u16 = 3;
+
+const HEADER_SIZE: usize = 16;
+const CHECKSUM_SIZE: usize = 8;
+
+// The only semantics the JIT and the interpreter implement
+const CELL_WIDTH: u8 = 8;
+const EOF_UNCHANGED: u8 = 0;
+
+#[derive(Debug)]
+pub enum BytecodeError {
+    UnsupportedVersion(u16),
+    UnsupportedCellWidth(u8),
+    UnsupportedEofMode(u8),
+    Corrupted(&'static str),
+    Invalid(CompileError),
+}
+
+impl fmt::Display for BytecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::BytecodeError::*;
+
+        match *self {
+            UnsupportedVersion(version) if version > VERSION => write!(
+                f, "bytecode format version {} is newer than this build supports (up to {}), \
+                    compile the program again or upgrade", version, VERSION
+            ),
+            UnsupportedVersion(version) => write!(f, "unknown bytecode format version {}", version),
+            UnsupportedCellWidth(bits) => write!(f, "unsupported cell width of {} bits", bits),
+            UnsupportedEofMode(mode) => write!(f, "unsupported EOF mode {}", mode),
+            Corrupted(what) => write!(f, "corrupted bytecode: {}", what),
+            Invalid(ref err) => write!(f, "invalid program in bytecode: {}", err),
+        }
+    }
+}
+
+// A program as stored in a .bfc file
+#[derive(Debug, PartialEq, Eq)]
+pub struct Bytecode {
+    pub insts: Vec<Inst>,
+    pub tape_size: usize,
+}
+
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn push_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+pub fn encode(insts: &[Inst], tape_size: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(HEADER_SIZE + 2 * insts.len() + CHECKSUM_SIZE);
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    out.push(CELL_WIDTH);
+    out.push(EOF_UNCHANGED);
+    out.extend_from_slice(&(tape_size as u64).to_le_bytes());
+
+    push_varint(&mut out, insts.len() as u64);
+    for inst in insts {
+        let (tag, operand) = match *inst {
+            IncPtr(a) => (0, Some(a)),
+            DecPtr(a) => (1, Some(a)),
+            IncVal(a) => (2, Some(a)),
+            DecVal(a) => (3, Some(a)),
+            PrintCell => (4, None),
+            ReadChar => (5, None),
+            JmpFwd(n) => (6, Some(n)),
+            JmpBack(n) => (7, Some(n)),
+            // one tag for every command of a dialect
+            Ext(op) => (8 + op as u8, None),
+            HostCall => (17, None),
+        };
+        out.push(tag);
+        if let Some(operand) = operand {
+            push_varint(&mut out, operand as u64);
+        }
+    }
+
+    let sum = checksum(&out);
+    out.extend_from_slice(&sum.to_le_bytes());
+    out
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], BytecodeError> {
+        if n > self.bytes.len() {
+            return Err(BytecodeError::Corrupted("unexpected end of file"));
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8, BytecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn varint(&mut self) -> Result<usize, BytecodeError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value as usize);
+            }
+        }
+        Err(BytecodeError::Corrupted("varint too long"))
+    }
+}
+
+// Reads a program written by `encode`. Everything is validated before any
+// of it is used: the header, the checksum and the jumps, which have to pass
+// `verify`.
+pub fn decode(bytes: &[u8]) -> Result<Bytecode, BytecodeError> {
+    if !is_bytecode(bytes) {
+        return Err(BytecodeError::Corrupted("missing magic number"));
+    }
+    if bytes.len() >= 6 {
+        // checked first, a newer format may not even have the same checksum
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        // later versions only added instructions, older files still decode
+        if version == 0 || version > VERSION {
+            return Err(BytecodeError::UnsupportedVersion(version));
+        }
+    }
+    if bytes.len() < HEADER_SIZE + CHECKSUM_SIZE {
+        return Err(BytecodeError::Corrupted("unexpected end of file"));
+    }
+
+    let (content, sum) = bytes.split_at(bytes.len() - CHECKSUM_SIZE);
+    let mut expected = [0; CHECKSUM_SIZE];
+    expected.copy_from_slice(sum);
+    if checksum(content) != u64::from_le_bytes(expected) {
+        return Err(BytecodeError::Corrupted("checksum mismatch"));
+    }
+
+    match content[6] {
+        CELL_WIDTH => {}
+        bits => return Err(BytecodeError::UnsupportedCellWidth(bits)),
+    }
+    match content[7] {
+        EOF_UNCHANGED => {}
+        mode => return Err(BytecodeError::UnsupportedEofMode(mode)),
+    }
+    let mut tape_size = [0; 8];
+    tape_size.copy_from_slice(&content[8..HEADER_SIZE]);
+    let tape_size = u64::from_le_bytes(tape_size) as usize;
+    if tape_size == 0 {
+        return Err(BytecodeError::Corrupted("empty tape"));
+    }
+
+    let mut reader = Reader { bytes: &content[HEADER_SIZE..] };
+    let count = reader.varint()?;
+    // every instruction takes at least a byte, don't trust the count for more
+    let mut insts = Vec::with_capacity(count.min(reader.bytes.len()));
+    for _ in 0..count {
+        let inst = match reader.byte()? {
+            0 => IncPtr(reader.varint()?),
+            1 => DecPtr(reader.varint()?),
+            2 => IncVal(reader.varint()?),
+            3 => DecVal(reader.varint()?),
+            4 => PrintCell,
+            5 => ReadChar,
+            6 => JmpFwd(reader.varint()?),
+            7 => JmpBack(reader.varint()?),
+            tag @ 8..=16 => Ext(ExtOp::ALL[tag as usize - 8]),
+            17 => HostCall,
+            _ => return Err(BytecodeError::Corrupted("unknown instruction")),
+        };
+        insts.push(inst);
+    }
+    if !reader.bytes.is_empty() {
+        return Err(BytecodeError::Corrupted("trailing data after the instructions"));
+    }
+
+    verify(&insts).map_err(BytecodeError::Invalid)?;
+
+    Ok(Bytecode { insts, tape_size })
+}
+
+
+#[cfg(test)]
+use brainfuck::parse;
+
+// Replaces the checksum after editing the content of `bytes`
+#[cfg(test)]
+fn reseal(bytes: &mut Vec<u8>) {
+    let len = bytes.len() - CHECKSUM_SIZE;
+    bytes.truncate(len);
+    let sum = checksum(bytes);
+    bytes.extend_from_slice(&sum.to_le_bytes());
+}
+
+#[test]
+fn test_bytecode_round_trip() {
+    let sources = [
+        "",
+        include_str!("../tests/fixtures/hello.b"),
+        include_str!("../tests/fixtures/rot13.b"),
+        &">".repeat(300),
+    ];
+    for source in sources.iter() {
+        let insts = parse(source).unwrap();
+        let bytes = encode(&insts, 1234);
+        assert!(is_bytecode(&bytes));
+        assert_eq!(decode(&bytes).unwrap(), Bytecode { insts, tape_size: 1234 });
+    }
+
+    assert_eq!(
+        encode(&parse("+>[-]").unwrap(), 2)[..],
+        [b'\0', b'B', b'F', b'C', 3, 0, 8, 0, 2, 0, 0, 0, 0, 0, 0, 0,
+         5, 2, 1, 0, 1, 6, 4, 3, 1, 7, 2,
+         0xb9, 0xb4, 0x51, 0xf5, 0xa4, 0x0d, 0x6a, 0x50][..]
+    );
+    assert!(!is_bytecode(b"+[-]"));
+}
+
+#[test]
+fn test_bytecode_corrupted() {
+    let bytes = encode(&parse("++[>+<-]").unwrap(), 100);
+
+    let mut flipped = bytes.clone();
+    flipped[18] ^= 0x10;
+    match decode(&flipped) {
+        Err(BytecodeError::Corrupted("checksum mismatch")) => {}
+        other => panic!("unexpected {:?}", other),
+    }
+
+    for len in 0..bytes.len() {
+        assert!(decode(&bytes[..len]).is_err(), "truncated to {} bytes", len);
+    }
+
+    // a well-formed file whose loop jumps to the wrong place
+    let mut broken = bytes.clone();
+    assert_eq!(broken[19..21], [6, 6]);
+    broken[20] = 5;
+    reseal(&mut broken);
+    match decode(&broken) {
+        Err(BytecodeError::Invalid(CompileError::InvalidJump { inst_index: 1 })) => {}
+        other => panic!("unexpected {:?}", other),
+    }
+
+    let mut unknown = bytes.clone();
+    unknown[19] = 18;
+    reseal(&mut unknown);
+    assert!(matches!(decode(&unknown), Err(BytecodeError::Corrupted("unknown instruction"))));
+}
+
+#[test]
+fn test_bytecode_versions() {
+    let mut newer = encode(&parse("+").unwrap(), 1);
+    newer[4] = 4;
+    reseal(&mut newer);
+    let err = decode(&newer).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "bytecode format version 4 is newer than this build supports (up to 3), \
+         compile the program again or upgrade"
+    );
+
+    let mut older = encode(&parse("+").unwrap(), 1);
+    older[4] = 1;
+    reseal(&mut older);
+    assert_eq!(decode(&older).unwrap().insts, parse("+").unwrap());
+
+    let mut wide = encode(&parse("+").unwrap(), 1);
+    wide[6] = 16;
+    reseal(&mut wide);
+    assert_eq!(decode(&wide).unwrap_err().to_string(), "unsupported cell width of 16 bits");
+}
@@ -0,0 +1,186 @@
+// Compiled programs by the file they're loaded from, for embedders running
+// the same files over and over. A file is compiled again once its
+// modification time or size changed, the least recently used programs are
+// dropped once there are more than the cache holds.
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use brainfuck::{Brainfuck, CompileError, SharedProgram};
+
+
+// What a program was compiled from, as far as the file system tells
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Stamp {
+    modified: SystemTime,
+    len: u64,
+}
+
+struct Loaded {
+    stamp: Stamp,
+    program: Arc<SharedProgram>,
+}
+
+// Locked while the program is compiled, so that loads of the same file
+// wait for it instead of compiling it as well
+type Slot = Arc<Mutex<Option<Loaded>>>;
+
+struct Entries {
+    // the slot of every cached path and when it was last loaded
+    slots: HashMap<PathBuf, (u64, Slot)>,
+    clock: u64,
+}
+
+pub struct ProgramCache {
+    capacity: usize,
+    entries: Mutex<Entries>,
+}
+
+impl ProgramCache {
+    // A cache of at most `capacity` programs, which has to be at least 1
+    pub fn new(capacity: usize) -> ProgramCache {
+        assert!(capacity > 0, "a program cache needs room for a program");
+        ProgramCache { capacity, entries: Mutex::new(Entries { slots: HashMap::new(), clock: 0 }) }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // Number of programs cached, including ones whose file changed since
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // The program in the file at `path`, compiled on the first load and
+    // whenever the file changed since. Paths are canonicalized, so every
+    // way to name a file shares its program. A file that fails to compile
+    // is tried again on the next load.
+    pub fn load<P: AsRef<Path>>(&self, path: P) -> Result<Arc<SharedProgram>, CompileError> {
+        let path = fs::canonicalize(path)?;
+        let metadata = fs::metadata(&path)?;
+        let stamp = Stamp { modified: metadata.modified()?, len: metadata.len() };
+
+        let slot = {
+            let mut entries = self.entries.lock().unwrap();
+            entries.clock += 1;
+            let clock = entries.clock;
+            let slot = {
+                let entry = entries.slots.entry(path.clone()).or_insert_with(|| (0, Slot::default()));
+                entry.0 = clock;
+                entry.1.clone()
+            };
+            while entries.slots.len() > self.capacity {
+                let oldest = entries.slots.iter().min_by_key(|&(_, &(used, _))| used).map(|(path, _)| path.clone());
+                entries.slots.remove(&oldest.unwrap());
+            }
+            slot
+        };
+
+        let mut loaded = slot.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        match *loaded {
+            Some(ref loaded) if loaded.stamp == stamp => Ok(loaded.program.clone()),
+            _ => {
+                let source = fs::read_to_string(&path)?;
+                let program = Arc::new(Brainfuck::new(&source)?.share()?);
+                *loaded = Some(Loaded { stamp, program: program.clone() });
+                Ok(program)
+            }
+        }
+    }
+
+    // Drops every program, those still in use live on with their users
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().slots.clear();
+    }
+}
+
+
+#[cfg(test)]
+fn temp_program(name: &str, source: &str) -> PathBuf {
+    let path = ::std::env::temp_dir().join(format!("brainfuck-cache-{}-{}.b", ::std::process::id(), name));
+    fs::write(&path, source).unwrap();
+    path
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_load() {
+    let path = temp_program("load", "++++++++[>++++++++<-]>+.");
+    let cache = ProgramCache::new(4);
+
+    let program = cache.load(&path).unwrap();
+    assert_eq!(program.run(b"").unwrap().output, b"A");
+    // another name of the same file
+    let dotted = path.parent().unwrap().join(".").join(path.file_name().unwrap());
+    assert!(Arc::ptr_eq(&cache.load(&dotted).unwrap(), &program));
+    assert_eq!(cache.len(), 1);
+
+    // compiling a file once however many threads want it at once
+    let loaded: Vec<_> = ::std::thread::scope(|scope| {
+        let threads: Vec<_> = (0..4).map(|_| scope.spawn(|| cache.load(&path).unwrap())).collect();
+        threads.into_iter().map(|thread| thread.join().unwrap()).collect()
+    });
+    assert!(loaded.iter().all(|other| Arc::ptr_eq(other, &program)));
+
+    cache.clear();
+    assert!(cache.is_empty());
+    assert!(!Arc::ptr_eq(&cache.load(&path).unwrap(), &program));
+
+    fs::write(&path, "[").unwrap();
+    assert!(matches!(cache.load(&path), Err(CompileError::UnclosedOpen { offset: 0 })));
+    fs::remove_file(&path).unwrap();
+    assert!(matches!(cache.load(&path), Err(CompileError::Io(_))));
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_invalidation() {
+    use std::time::Duration;
+
+    let path = temp_program("invalidation", "+++++++[>++++++++++<-]>.");
+    let cache = ProgramCache::new(4);
+    let program = cache.load(&path).unwrap();
+    assert_eq!(program.run(b"").unwrap().output, b"F");
+
+    // same size, only the modification time tells them apart
+    let modified = fs::metadata(&path).unwrap().modified().unwrap();
+    fs::write(&path, "+++++++[>++++++++++<-]>+.").unwrap();
+    let file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+    file.set_modified(modified + Duration::from_secs(1)).unwrap();
+    let reloaded = cache.load(&path).unwrap();
+    assert_eq!(reloaded.run(b"").unwrap().output, b"G");
+    assert!(Arc::ptr_eq(&cache.load(&path).unwrap(), &reloaded));
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_eviction() {
+    let paths: Vec<_> = ["a", "b", "c"].iter().map(|name| temp_program(&format!("evict-{}", name), "+.")).collect();
+    let cache = ProgramCache::new(2);
+
+    let a = cache.load(&paths[0]).unwrap();
+    let b = cache.load(&paths[1]).unwrap();
+    // a was used last, so b goes
+    assert!(Arc::ptr_eq(&cache.load(&paths[0]).unwrap(), &a));
+    let c = cache.load(&paths[2]).unwrap();
+    assert_eq!(cache.len(), 2);
+    assert!(Arc::ptr_eq(&cache.load(&paths[0]).unwrap(), &a));
+    assert!(!Arc::ptr_eq(&cache.load(&paths[1]).unwrap(), &b));
+    // which took the place of c, used less recently than a
+    assert!(Arc::ptr_eq(&cache.load(&paths[1]).unwrap(), &cache.load(&paths[1]).unwrap()));
+    assert!(Arc::ptr_eq(&cache.load(&paths[0]).unwrap(), &a));
+    assert!(!Arc::ptr_eq(&cache.load(&paths[2]).unwrap(), &c));
+    assert_eq!(cache.len(), 2);
+
+    for path in &paths {
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,308 @@
+use std::fmt;
+
+
+// Breakpoint conditions like `cell(ptr) == 5 && hit_count % 1000 == 0`
+//
+// Values are integers, comparisons and `&&`/`||` evaluate to 0 or 1 and a
+// condition holds if it isn't 0. Operands are numbers, `ptr`, `pc`,
+// `hit_count`, `cell(n)` (0 outside of the tape) and parenthesized
+// expressions; `+`, `-`, `%` and the comparisons bind tighter than `&&`,
+// which binds tighter than `||`.
+#[derive(Debug)]
+pub struct Condition {
+    expr: Expr,
+}
+
+// What a condition is evaluated against, taken right before the instruction
+// at `pc` executes
+pub struct MachineState<'a> {
+    pub pc: usize,
+    pub ptr: usize,
+    pub tape: &'a [u8],
+    // How often the breakpoint's instruction was reached, including this time
+    pub hit_count: usize,
+}
+
+impl<'a> MachineState<'a> {
+    pub fn cell(&self, index: usize) -> u8 {
+        self.tape.get(index).cloned().unwrap_or(0)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ConditionError {
+    // Byte offset into the condition
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ConditionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at offset {}", self.message, self.offset)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Or, And,
+    Eq, Ne, Lt, Le, Gt, Ge,
+    Add, Sub, Rem,
+}
+
+#[derive(Debug)]
+enum Expr {
+    Num(i64),
+    Pc,
+    Ptr,
+    HitCount,
+    Cell(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn eval(&self, state: &MachineState) -> i64 {
+        match *self {
+            Expr::Num(n) => n,
+            Expr::Pc => state.pc as i64,
+            Expr::Ptr => state.ptr as i64,
+            Expr::HitCount => state.hit_count as i64,
+            Expr::Cell(ref index) => match index.eval(state) {
+                i if i < 0 => 0,
+                i => i64::from(state.cell(i as usize)),
+            },
+            Expr::Binary(op, ref a, ref b) => {
+                let a = a.eval(state);
+                // `&&` and `||` short-circuit, like everywhere else
+                match op {
+                    Op::And if a == 0 => return 0,
+                    Op::Or if a != 0 => return 1,
+                    _ => {}
+                }
+                let b = b.eval(state);
+                match op {
+                    Op::Or | Op::And => (b != 0) as i64,
+                    Op::Eq => (a == b) as i64,
+                    Op::Ne => (a != b) as i64,
+                    Op::Lt => (a < b) as i64,
+                    Op::Le => (a <= b) as i64,
+                    Op::Gt => (a > b) as i64,
+                    Op::Ge => (a >= b) as i64,
+                    Op::Add => a.wrapping_add(b),
+                    Op::Sub => a.wrapping_sub(b),
+                    // `x % 0` never holds rather than failing at hit time
+                    Op::Rem => a.checked_rem(b).unwrap_or(-1),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Num(i64),
+    Ident(String),
+    Op(Op),
+    Open,
+    Close,
+}
+
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, ConditionError> {
+    let bytes = source.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let start = i;
+        let c = bytes[i] as char;
+        let two = source.get(i..i + 2).unwrap_or("");
+
+        let token = if c.is_whitespace() {
+            i += 1;
+            continue;
+        } else if c.is_ascii_digit() {
+            while i < bytes.len() && bytes[i].is_ascii_digit() {
+                i += 1;
+            }
+            match source[start..i].parse() {
+                Ok(n) => Token::Num(n),
+                Err(_) => return Err(error(start, "number too large")),
+            }
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                i += 1;
+            }
+            Token::Ident(source[start..i].to_string())
+        } else {
+            let op = match two {
+                "||" => Some(Op::Or),
+                "&&" => Some(Op::And),
+                "==" => Some(Op::Eq),
+                "!=" => Some(Op::Ne),
+                "<=" => Some(Op::Le),
+                ">=" => Some(Op::Ge),
+                _ => None,
+            };
+            if let Some(op) = op {
+                i += 2;
+                Token::Op(op)
+            } else {
+                i += 1;
+                match c {
+                    '<' => Token::Op(Op::Lt),
+                    '>' => Token::Op(Op::Gt),
+                    '+' => Token::Op(Op::Add),
+                    '-' => Token::Op(Op::Sub),
+                    '%' => Token::Op(Op::Rem),
+                    '(' => Token::Open,
+                    ')' => Token::Close,
+                    _ => return Err(error(start, &format!("unexpected character {:?}", c))),
+                }
+            }
+        };
+        tokens.push((start, token));
+    }
+
+    Ok(tokens)
+}
+
+fn error(offset: usize, message: &str) -> ConditionError {
+    ConditionError { offset, message: message.to_string() }
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    // offset reported for errors at the end of the input
+    end: usize,
+}
+
+// Binding strength of the binary operators, higher binds tighter
+fn precedence(op: Op) -> u8 {
+    match op {
+        Op::Or => 1,
+        Op::And => 2,
+        Op::Eq | Op::Ne | Op::Lt | Op::Le | Op::Gt | Op::Ge => 3,
+        Op::Add | Op::Sub => 4,
+        Op::Rem => 5,
+    }
+}
+
+impl Parser {
+    fn offset(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.end, |&(offset, _)| offset)
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, token)| token)
+    }
+
+    fn expect(&mut self, expected: Token, what: &str) -> Result<(), ConditionError> {
+        if self.peek() != Some(&expected) {
+            return Err(error(self.offset(), &format!("expected {}", what)));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    // Precedence climbing over the binary operators
+    fn expr(&mut self, min_precedence: u8) -> Result<Expr, ConditionError> {
+        let mut lhs = self.operand()?;
+
+        while let Some(&Token::Op(op)) = self.peek() {
+            if precedence(op) < min_precedence {
+                break;
+            }
+            self.pos += 1;
+            let rhs = self.expr(precedence(op) + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+
+        Ok(lhs)
+    }
+
+    fn operand(&mut self) -> Result<Expr, ConditionError> {
+        let offset = self.offset();
+        let token = match self.peek() {
+            Some(token) => token.clone(),
+            None => return Err(error(offset, "expected an operand")),
+        };
+        self.pos += 1;
+
+        match token {
+            Token::Num(n) => Ok(Expr::Num(n)),
+            Token::Ident(ref name) => match &name[..] {
+                "pc" => Ok(Expr::Pc),
+                "ptr" => Ok(Expr::Ptr),
+                "hit_count" => Ok(Expr::HitCount),
+                "cell" => {
+                    self.expect(Token::Open, "'('")?;
+                    let index = self.expr(0)?;
+                    self.expect(Token::Close, "')'")?;
+                    Ok(Expr::Cell(Box::new(index)))
+                }
+                _ => Err(error(offset, &format!("unknown name '{}'", name))),
+            },
+            Token::Open => {
+                let expr = self.expr(0)?;
+                self.expect(Token::Close, "')'")?;
+                Ok(expr)
+            }
+            _ => Err(error(offset, "expected an operand")),
+        }
+    }
+}
+
+impl Condition {
+    pub fn parse(source: &str) -> Result<Condition, ConditionError> {
+        let mut parser = Parser { tokens: tokenize(source)?, pos: 0, end: source.len() };
+        let expr = parser.expr(0)?;
+        if parser.pos < parser.tokens.len() {
+            return Err(error(parser.offset(), "expected an operator"));
+        }
+
+        Ok(Condition { expr })
+    }
+
+    pub fn holds(&self, state: &MachineState) -> bool {
+        self.expr.eval(state) != 0
+    }
+}
+
+
+#[cfg(test)]
+fn holds(condition: &str, state: &MachineState) -> bool {
+    Condition::parse(condition).unwrap().holds(state)
+}
+
+#[test]
+fn test_condition() {
+    let tape = [0, 5, 7];
+    let state = MachineState { pc: 3, ptr: 1, tape: &tape, hit_count: 2000 };
+
+    assert!(holds("cell(ptr) == 5", &state));
+    assert!(holds("cell(ptr + 1) > cell(ptr)", &state));
+    assert!(holds("hit_count % 1000 == 0", &state));
+    assert!(!holds("hit_count % 1000 == 1", &state));
+    assert!(holds("ptr > 0 && (pc == 2 || pc == 3)", &state));
+    assert!(holds("cell(100) == 0 && cell(0 - 1) == 0", &state));
+    assert!(holds("1 + 2 % 2 == 1", &state));
+    assert!(holds("ptr", &state));
+    assert!(!holds("ptr % 0 == 0", &state));
+}
+
+#[test]
+fn test_condition_errors() {
+    let errors = [
+        ("", 0, "expected an operand"),
+        ("ptr >", 5, "expected an operand"),
+        ("cell(ptr == 5", 13, "expected ')'"),
+        ("cell ptr", 5, "expected '('"),
+        ("tape(0) == 1", 0, "unknown name 'tape'"),
+        ("ptr = 5", 4, "unexpected character '='"),
+        ("ptr 5", 4, "expected an operator"),
+        ("99999999999999999999 > 0", 0, "number too large"),
+    ];
+    for &(source, offset, message) in &errors {
+        assert_eq!(Condition::parse(source).unwrap_err(), error(offset, message), "{}", source);
+    }
+}
@@ -0,0 +1,138 @@
+// Runs the programs in tests/programs in the interpreter, as generated code,
+// tiered and, where node is installed, as JavaScript, and compares what they
+// print with what they should, byte for byte.
+// A program `name` is name.b, or name.eb in Extended Brainfuck Type I,
+// printing name.out, with name.in as its input if it reads any.
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{self, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use brainfuck::{Brainfuck, Dialect};
+use interp::{Interp, StepOutcome};
+use js::{self, JsFormat};
+
+
+// Every program with the number of steps the interpreter may take for it,
+// about ten times what it needs
+const PROGRAMS: &[(&str, Dialect, usize)] = &[
+    ("hello", Dialect::Brainfuck, 10_000),
+    ("rot13", Dialect::Brainfuck, 1_500_000),
+    ("squares", Dialect::Brainfuck, 10_000_000),
+    ("sierpinski", Dialect::Brainfuck, 2_000_000),
+    ("bits", Dialect::Extended1, 40_000),
+];
+
+// The generated code has no step count, a loop that goes wrong is cancelled
+// after this long instead
+const JIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+const TIER_THRESHOLD: u64 = 20;
+
+fn interpret(bf: &Brainfuck, input: &[u8], steps: usize) -> Result<Vec<u8>, String> {
+    let mut output = Vec::new();
+    let mut interp = Interp::new(bf.insts(), bf.tape_size());
+    match interp.run_for(steps, input, &mut output) {
+        StepOutcome::Finished => Ok(output),
+        StepOutcome::Paused => Err(format!("still running after {} steps", steps)),
+        StepOutcome::NeedsInput => Err("waiting for input".to_string()),
+        StepOutcome::Error(e) => Err(e.to_string()),
+    }
+}
+
+fn jit(bf: &Brainfuck, input: &[u8]) -> Result<Vec<u8>, String> {
+    let program = bf.share().map_err(|e| e.to_string())?;
+    let (cancel, done) = (AtomicBool::new(false), AtomicBool::new(false));
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            let started = Instant::now();
+            while !done.load(Ordering::SeqCst) && started.elapsed() < JIT_TIMEOUT {
+                thread::sleep(Duration::from_millis(5));
+            }
+            cancel.store(true, Ordering::SeqCst);
+        });
+        let result = program.run_cancellable(input, &cancel);
+        done.store(true, Ordering::SeqCst);
+        result.map(|execution| execution.output).map_err(|e| e.to_string())
+    })
+}
+
+// With a threshold low enough for every program to get some loops compiled
+fn tiered(bf: &Brainfuck, input: &[u8]) -> Result<Vec<u8>, String> {
+    let mut program = bf.tiered();
+    program.set_threshold(TIER_THRESHOLD);
+    let output = program.run_piped(input).map(|execution| execution.output).map_err(|e| e.to_string())?;
+    if program.compiled_loops().is_empty() {
+        return Err("no loop got hot".to_string());
+    }
+    Ok(output)
+}
+
+// What the program transpiled to JavaScript prints under node, if there's
+// a node to run it
+fn javascript(name: &str, bf: &Brainfuck, input: &[u8]) -> Option<Result<Vec<u8>, String>> {
+    let path = env::temp_dir().join(format!("brainfuck-corpus-{}-{}.js", process::id(), name));
+    fs::write(&path, js::to_string(bf.insts(), bf.tape_size(), JsFormat::Umd)).unwrap();
+    let child = Command::new("node")
+        .arg("-e")
+        .arg("process.stdout.write(require(process.argv[1]).run(require('fs').readFileSync(0)))")
+        .arg(&path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+            fs::remove_file(&path).unwrap();
+            return None;
+        }
+        Err(e) => panic!("{}", e),
+    };
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    let out = child.wait_with_output().unwrap();
+    fs::remove_file(&path).unwrap();
+
+    Some(if out.status.success() { Ok(out.stdout) } else { Err(String::from_utf8_lossy(&out.stderr).into_owned()) })
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_programs() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/programs");
+    let mut failures = Vec::new();
+
+    for &(name, dialect, steps) in PROGRAMS {
+        let extension = if dialect == Dialect::Extended1 { "eb" } else { "b" };
+        let source = fs::read_to_string(dir.join(format!("{}.{}", name, extension))).unwrap();
+        let input = fs::read(dir.join(format!("{}.in", name))).unwrap_or_default();
+        let expected = fs::read(dir.join(format!("{}.out", name))).unwrap();
+        let bf = Brainfuck::with_dialect(&source, dialect).unwrap();
+
+        let mut engines = vec![
+            ("interpreter", interpret(&bf, &input, steps)),
+            ("generated code", jit(&bf, &input)),
+            ("tiered execution", tiered(&bf, &input)),
+        ];
+        // JavaScript where node is installed
+        if let Some(result) = javascript(name, &bf, &input) {
+            engines.push(("JavaScript", result));
+        }
+        for (engine, result) in engines {
+            match result {
+                Ok(ref output) if *output == expected => {}
+                Ok(output) => failures.push(format!(
+                    "{}: the {} printed {:?}, expected {:?}",
+                    name, engine, String::from_utf8_lossy(&output), String::from_utf8_lossy(&expected)
+                )),
+                Err(e) => failures.push(format!("{}: the {} failed: {}", name, engine, e)),
+            }
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
@@ -0,0 +1,171 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+
+use brainfuck::{is_command, Span};
+
+
+// Execution counts attributed back to the source, one entry per byte: `None`
+// for everything that isn't a command, otherwise how often the instruction
+// the command was parsed into executed
+pub fn source_counts(source: &str, spans: &[Span], counts: &[u64]) -> Vec<Option<u64>> {
+    let mut per_byte = vec![None; source.len()];
+
+    for (span, &count) in spans.iter().zip(counts) {
+        for (i, c) in source[span.start..span.end].char_indices() {
+            if is_command(c) {
+                per_byte[span.start + i] = Some(count);
+            }
+        }
+    }
+
+    per_byte
+}
+
+// The lines containing commands that never executed, each followed by a line
+// marking them, and the share of executed commands
+pub fn report(source: &str, per_byte: &[Option<u64>]) -> String {
+    let mut out = String::new();
+    let (mut total, mut executed) = (0, 0);
+    let mut start = 0;
+
+    for line in source.split_inclusive('\n') {
+        let counts = &per_byte[start..start + line.len()];
+        let mut marks = String::new();
+
+        // one mark per character, whose count is the one of its first byte
+        for (i, c) in line.trim_end_matches('\n').char_indices() {
+            match counts[i] {
+                Some(0) => marks.push('^'),
+                Some(_) => marks.push(' '),
+                // keep tabs so the marks line up
+                None => marks.push(if c == '\t' { '\t' } else { ' ' }),
+            }
+        }
+        total += counts.iter().filter(|count| count.is_some()).count();
+        executed += counts.iter().filter(|count| matches!(**count, Some(n) if n > 0)).count();
+
+        if marks.contains('^') {
+            out.push_str(line.trim_end_matches('\n'));
+            out.push('\n');
+            out.push_str(marks.trim_end());
+            out.push('\n');
+        }
+        start += line.len();
+    }
+
+    let percent = if total == 0 { 100.0 } else { executed as f64 * 100.0 / total as f64 };
+    out.push_str(&format!("coverage: {:.1}% ({}/{} commands executed)\n", percent, executed, total));
+
+    out
+}
+
+const HEADER: &str = "brainfuck-coverage 1";
+
+// Reads counts saved by `save`, `None` if the file doesn't exist. They only
+// make sense for the same program, identified by its fingerprint.
+pub fn load(path: &str, fingerprint: u64) -> io::Result<Option<Vec<u64>>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+
+    let mut lines = BufReader::new(file).lines();
+    let header = lines.next().unwrap_or_else(|| Ok(String::new()))?;
+    if header != format!("{} {:016x}", HEADER, fingerprint) {
+        return Err(invalid("coverage data is for a different program"));
+    }
+
+    let mut counts = Vec::new();
+    for line in lines {
+        counts.push(line?.parse().map_err(|_| invalid("invalid coverage data"))?);
+    }
+
+    Ok(Some(counts))
+}
+
+pub fn save(path: &str, fingerprint: u64, counts: &[u64]) -> io::Result<()> {
+    let mut out = format!("{} {:016x}\n", HEADER, fingerprint);
+    for count in counts {
+        out.push_str(&format!("{}\n", count));
+    }
+
+    File::create(path)?.write_all(out.as_bytes())
+}
+
+// Adds the counts of another run, both have to be for the same program
+pub fn merge(counts: &mut [u64], other: &[u64]) {
+    assert_eq!(counts.len(), other.len(), "coverage of different programs");
+    for (count, other) in counts.iter_mut().zip(other) {
+        *count += other;
+    }
+}
+
+
+#[cfg(test)]
+fn run_with_coverage(source: &str, input: &[u8]) -> (Vec<Span>, Vec<u64>) {
+    use brainfuck::parse_with_spans;
+    use interp::Interp;
+
+    let (insts, spans) = parse_with_spans(source).unwrap();
+    let mut interp = Interp::new(&insts, 10);
+    interp.set_coverage(true);
+    interp.run(input, io::sink()).unwrap();
+
+    let counts = interp.coverage().unwrap().to_vec();
+    (spans, counts)
+}
+
+#[test]
+fn test_coverage() {
+    // the second loop is dead, the cell is always zero after the first
+    let source = "++ clear\n[-]\n[>+ dead <-]\n.\n";
+    let (spans, counts) = run_with_coverage(source, b"");
+    assert_eq!(counts, [1, 1, 2, 2, 1, 0, 0, 0, 0, 0, 1]);
+
+    let per_byte = source_counts(source, &spans, &counts);
+    assert_eq!(&per_byte[..4], [Some(1), Some(1), None, None]);
+    assert_eq!(
+        report(source, &per_byte),
+        "[>+ dead <-]\n\
+         \x20^^      ^^^\n\
+         coverage: 58.3% (7/12 commands executed)\n"
+    );
+
+    // marks stay under their commands after characters of several bytes
+    let source = "é[,]+";
+    let (spans, counts) = run_with_coverage(source, b"");
+    assert_eq!(
+        report(source, &source_counts(source, &spans, &counts)),
+        "é[,]+\n\
+         \x20 ^^\n\
+         coverage: 50.0% (2/4 commands executed)\n"
+    );
+
+    let (spans, counts) = run_with_coverage("+[-]", b"");
+    let per_byte = source_counts("+[-]", &spans, &counts);
+    assert_eq!(report("+[-]", &per_byte), "coverage: 100.0% (4/4 commands executed)\n");
+}
+
+#[test]
+fn test_coverage_merge() {
+    // only input starting with a non-zero byte enters the loop
+    let source = ",[>+<[-]]";
+    let (spans, mut counts) = run_with_coverage(source, b"");
+    assert!(report(source, &source_counts(source, &spans, &counts)).ends_with("(2/9 commands executed)\n"));
+
+    let path = std::env::temp_dir().join(format!("brainfuck-coverage-{}", std::process::id()));
+    let path = path.to_str().unwrap();
+    assert!(load(path, 42).unwrap().is_none());
+    save(path, 42, &counts).unwrap();
+    assert!(load(path, 43).is_err());
+
+    let (_, other) = run_with_coverage(source, b"a");
+    merge(&mut counts, &load(path, 42).unwrap().unwrap());
+    merge(&mut counts, &other);
+    let _ = std::fs::remove_file(path);
+
+    assert_eq!(counts, [3, 3, 1, 1, 1, 1, 97, 97, 1]);
+    assert!(report(source, &source_counts(source, &spans, &counts)).ends_with("(9/9 commands executed)\n"));
+}
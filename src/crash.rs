@@ -0,0 +1,241 @@
+use std::fmt::Write;
+
+use brainfuck::{Inst, RuntimeError, Span};
+use diagnostics::{json_string, position, ErrorCode};
+use interp::Interp;
+use tapedump::{self, TapeFormat};
+
+
+// Cells around the pointer a report shows, in whole hexdump lines
+const CELLS: usize = 32;
+// Instructions executed last a report shows, the abbreviated one shows none
+pub const RECENT: usize = 16;
+
+// What's known of a run that failed, for `--crash-report`. The interpreter
+// knows all of it. Generated code keeps neither the pointer nor the tape
+// and counts nothing, of its runs the failing instruction is known if the
+// error or the code map tells, and the instructions before it up to the
+// last bracket, which must have run right before it.
+pub struct CrashReport<'a> {
+    error: &'a RuntimeError,
+    insts: &'a [Inst],
+    // the program's source and the span of every instruction, if there is one
+    source: Option<(&'a str, &'a [Span])>,
+    pub inst_index: Option<usize>,
+    pub ptr: Option<usize>,
+    // the first of the cells shown and the cells
+    pub cells: Option<(usize, Vec<u8>)>,
+    // oldest first
+    pub recent: Vec<usize>,
+    pub steps: Option<u64>,
+}
+
+impl<'a> CrashReport<'a> {
+    // The report on what generated code left behind, the instruction the
+    // code faulted in is for the caller to look up in the code map
+    pub fn new(error: &'a RuntimeError, insts: &'a [Inst], source: Option<(&'a str, &'a [Span])>) -> CrashReport<'a> {
+        let mut report = CrashReport {
+            error, insts, source, inst_index: None, ptr: None, cells: None, recent: Vec::new(), steps: None,
+        };
+        report.set_inst_index(error.inst_index());
+        report
+    }
+
+    // The report on an interpreter the run failed in, with as many of the
+    // last steps as its history kept
+    pub fn from_interp(
+        error: &'a RuntimeError, interp: &Interp<'a>, source: Option<(&'a str, &'a [Span])>
+    ) -> CrashReport<'a> {
+        let tape = interp.tape();
+        let ptr = interp.ptr();
+        let start = ptr.saturating_sub(CELLS / 2) / 16 * 16;
+        let end = (start + CELLS).min(tape.len());
+
+        CrashReport {
+            error,
+            insts: interp.insts(),
+            source,
+            // the pc stays on the instruction that failed
+            inst_index: error.inst_index().or(Some(interp.pc()).filter(|&pc| pc < interp.insts().len())),
+            ptr: Some(ptr),
+            cells: Some((start, tape[start..end].to_vec())),
+            recent: interp.recent(RECENT),
+            steps: interp.stats().instructions,
+        }
+    }
+
+    // Sets the failing instruction, and as the last steps the ones before
+    // it that have to have run, up to the last bracket, which either
+    // jumped or didn't
+    pub fn set_inst_index(&mut self, inst_index: Option<usize>) {
+        self.inst_index = inst_index;
+        self.recent = match inst_index {
+            Some(index) => {
+                let start = self.insts[..index].iter()
+                    .rposition(|inst| matches!(inst, Inst::JmpFwd(_) | Inst::JmpBack(_)))
+                    .map_or(0, |bracket| bracket + 1);
+                (start.max(index.saturating_sub(RECENT))..index).collect()
+            }
+            None => Vec::new(),
+        };
+    }
+
+    fn span(&self, index: usize) -> Option<Span> {
+        self.source.and_then(|(_, spans)| spans.get(index).copied())
+    }
+
+    // The failing instruction's line with a caret under it, indented
+    fn excerpt(&self) -> Option<String> {
+        let (source, _) = self.source?;
+        let span = self.span(self.inst_index?)?;
+        let (line, column) = position(source, span.start);
+        let text = source.lines().nth(line - 1).unwrap_or("");
+        let gutter = line.to_string();
+
+        Some(format!(
+            "{} | {}\n{} | {}^\n", gutter, text, " ".repeat(gutter.len()), " ".repeat(column - 1)
+        ))
+    }
+
+    // The error, where it happened and how far the run got, with `full`
+    // also the cells around the pointer and the last steps
+    pub fn to_text(&self, file: &str, full: bool) -> String {
+        let mut out = String::new();
+        writeln!(out, "crash report: {}", self.error).unwrap();
+        match (self.inst_index, self.inst_index.and_then(|index| self.span(index))) {
+            (Some(index), Some(span)) => {
+                let (line, column) = position(self.source.unwrap().0, span.start);
+                writeln!(out, "at {}:{}:{}, instruction {} {:?}", file, line, column, index, self.insts[index]).unwrap();
+                out.push_str(&self.excerpt().unwrap());
+            }
+            (Some(index), None) => writeln!(out, "at instruction {} {:?}", index, self.insts[index]).unwrap(),
+            (None, _) => writeln!(out, "at an unknown instruction").unwrap(),
+        }
+        match self.ptr {
+            Some(ptr) => writeln!(out, "pointer {}", ptr).unwrap(),
+            None => writeln!(out, "pointer unknown, generated code doesn't keep it").unwrap(),
+        }
+        match self.steps {
+            Some(steps) => writeln!(out, "{} steps", steps).unwrap(),
+            None => writeln!(out, "steps unknown, generated code doesn't count them").unwrap(),
+        }
+        if !full {
+            return out;
+        }
+
+        if let Some((start, ref cells)) = self.cells {
+            writeln!(out, "cells {} to {}:", start, start + cells.len()).unwrap();
+            let mut dump = Vec::new();
+            tapedump::write_tape_from(cells, start as isize, TapeFormat::Hex, true, &mut dump).unwrap();
+            out.push_str(&String::from_utf8(dump).unwrap());
+        }
+        if !self.recent.is_empty() {
+            writeln!(out, "last {} steps, oldest first:", self.recent.len()).unwrap();
+            for &index in &self.recent {
+                match self.span(index) {
+                    Some(span) => {
+                        let (line, column) = position(self.source.unwrap().0, span.start);
+                        writeln!(out, "  {:>6}  {}:{}  {:?}", index, line, column, self.insts[index]).unwrap();
+                    }
+                    None => writeln!(out, "  {:>6}  {:?}", index, self.insts[index]).unwrap(),
+                }
+            }
+        }
+
+        out
+    }
+
+    // Everything `to_text` has, null where it isn't known. `offset` is the
+    // byte offset of the failing instruction in the source.
+    pub fn to_json(&self, file: &str) -> String {
+        fn known<T: ToString>(value: Option<T>) -> String {
+            value.map_or("null".to_string(), |value| value.to_string())
+        }
+
+        let offset = self.inst_index.and_then(|index| self.span(index)).map(|span| span.start);
+        let (line, column) = match (offset, self.source) {
+            (Some(offset), Some((source, _))) => {
+                let (line, column) = position(source, offset);
+                (Some(line), Some(column))
+            }
+            _ => (None, None),
+        };
+        let inst = |index: usize| json_string(&format!("{:?}", self.insts[index]));
+        let cells = match self.cells {
+            Some((start, ref cells)) => format!(
+                "{{\"start\":{},\"values\":[{}]}}",
+                start, cells.iter().map(u8::to_string).collect::<Vec<_>>().join(",")
+            ),
+            None => "null".to_string(),
+        };
+        let recent: Vec<String> = self.recent.iter().map(|&index| format!(
+            "{{\"index\":{},\"inst\":{},\"offset\":{}}}",
+            index, inst(index), known(self.span(index).map(|span| span.start))
+        )).collect();
+
+        format!(
+            "{{\"code\":{},\"message\":{},\"file\":{},\"inst_index\":{},\"inst\":{},\"offset\":{},\"line\":{},\
+             \"column\":{},\"pointer\":{},\"steps\":{},\"cells\":{},\"recent\":[{}]}}",
+            json_string(self.error.code()), json_string(&self.error.to_string()), json_string(file),
+            known(self.inst_index), self.inst_index.map_or("null".to_string(), inst), known(offset),
+            known(line), known(column), known(self.ptr), known(self.steps), cells, recent.join(",")
+        )
+    }
+}
+
+
+#[test]
+fn test_crash_report() {
+    use brainfuck::parse_with_spans;
+
+    let source = "+\n[>+]";
+    let (insts, spans) = parse_with_spans(source).unwrap();
+    let mut interp = Interp::new(&insts, 4);
+    interp.set_recording(Some(::interp::history_budget(RECENT)));
+    let error = interp.run(&b""[..], ::std::io::sink()).unwrap_err();
+    let report = CrashReport::from_interp(&error, &interp, Some((source, &spans)));
+
+    // the `>` running off the end of the tape the third time round
+    let mut expected = "\
+crash report: pointer moved past the end of the tape at instruction 2
+at crash.b:2:2, instruction 2 IncPtr(1)
+2 | [>+]
+  |  ^
+pointer 3
+11 steps
+".to_string();
+    assert_eq!(report.to_text("crash.b", false), expected);
+    expected.push_str("cells 0 to 4:\n");
+    expected.push_str(&format!("00000000  {:<49} |....|\n00000004\n", "01 01 01 01"));
+    expected.push_str("last 11 steps, oldest first:\n       0  1:1  IncVal(1)\n       1  2:1  JmpFwd(4)\n");
+    for _ in 0..3 {
+        expected.push_str("       2  2:2  IncPtr(1)\n       3  2:3  IncVal(1)\n       4  2:4  JmpBack(1)\n");
+    }
+    assert_eq!(report.to_text("crash.b", true), expected);
+
+    let json = report.to_json("crash.b");
+    assert!(json.starts_with(
+        "{\"code\":\"pointer-overflow\",\"message\":\"pointer moved past the end of the tape at instruction 2\",\
+         \"file\":\"crash.b\",\"inst_index\":2,\"inst\":\"IncPtr(1)\",\"offset\":3,\"line\":2,\"column\":2,\
+         \"pointer\":3,\"steps\":11,\"cells\":{\"start\":0,\"values\":[1,1,1,1]},\"recent\":[\
+         {\"index\":0,\"inst\":\"IncVal(1)\",\"offset\":0},{\"index\":1,\"inst\":\"JmpFwd(4)\",\"offset\":2},"
+    ), "{}", json);
+    assert!(json.ends_with("{\"index\":4,\"inst\":\"JmpBack(1)\",\"offset\":5}]}"), "{}", json);
+
+    // of generated code only what comes before it in its stretch without
+    // brackets is known
+    let mut report = CrashReport::new(&error, &insts, Some((source, &spans)));
+    assert_eq!((report.inst_index, report.recent.len()), (Some(2), 0));
+    report.set_inst_index(Some(3));
+    assert_eq!(report.to_text("crash.b", true), "\
+crash report: pointer moved past the end of the tape at instruction 2
+at crash.b:2:3, instruction 3 IncVal(1)
+2 | [>+]
+  |   ^
+pointer unknown, generated code doesn't keep it
+steps unknown, generated code doesn't count them
+last 1 steps, oldest first:
+       2  2:2  IncPtr(1)
+");
+    assert!(report.to_json("crash.b").contains(",\"pointer\":null,\"steps\":null,\"cells\":null,"));
+}
@@ -0,0 +1,451 @@
+use std::io::{self, Read, Write};
+use std::ops::Range;
+
+use brainfuck::{ExtOp, Inst, RuntimeError, Span};
+use brainfuck::Inst::*;
+use condition::{Condition, ConditionError, MachineState};
+use interp::{Interp, StepOutcome};
+
+
+struct Watch {
+    cells: Range<usize>,
+    // also stop when the cell is only read
+    on_read: bool,
+}
+
+type Predicate<'a> = Box<dyn FnMut(&MachineState) -> bool + 'a>;
+
+struct Breakpoint<'a> {
+    inst_index: usize,
+    hit_count: usize,
+    condition: Option<Predicate<'a>>,
+}
+
+// A watched cell that was accessed, `old` and `new` are equal for reads
+#[derive(Debug, PartialEq, Eq)]
+pub struct WatchHit {
+    pub inst_index: usize,
+    pub span: Span,
+    pub cell: usize,
+    pub old: u8,
+    pub new: u8,
+}
+
+// Why `Debugger::cont` returned
+#[derive(Debug)]
+pub enum Event {
+    // About to execute the instruction
+    Breakpoint { inst_index: usize },
+    Watchpoint(WatchHit),
+    Finished,
+    NeedsInput,
+    Error(RuntimeError),
+    // `reverse_continue` ran out of recorded history
+    StartOfHistory,
+    // `step` executed an instruction and nothing else happened
+    Stepped,
+}
+
+// Runs a program in the interpreter, stopping at breakpoints and whenever a
+// watchpoint fires
+//
+// Without either the program runs at full interpreter speed, otherwise it is
+// single-stepped and every instruction is checked.
+pub struct Debugger<'a> {
+    interp: Interp<'a>,
+    insts: &'a [Inst],
+    spans: &'a [Span],
+    watches: Vec<Watch>,
+    breakpoints: Vec<Breakpoint<'a>>,
+    // the breakpoint execution stopped at, which mustn't stop it again
+    resume_at: Option<usize>,
+}
+
+impl<'a> Debugger<'a> {
+    // `spans` as returned by `parse_with_spans` for `insts`
+    pub fn new(insts: &'a [Inst], spans: &'a [Span], tape_size: usize) -> Debugger<'a> {
+        Debugger {
+            interp: Interp::new(insts, tape_size),
+            insts,
+            spans,
+            watches: Vec::new(),
+            breakpoints: Vec::new(),
+            resume_at: None,
+        }
+    }
+
+    // Stops before executing the instruction at `inst_index`
+    pub fn break_at(&mut self, inst_index: usize) {
+        self.add_breakpoint(inst_index, None);
+    }
+
+    // Like `break_at`, but only stops if `condition` holds for the state
+    // right before the instruction executes
+    pub fn break_if<F>(&mut self, inst_index: usize, condition: F)
+        where F: FnMut(&MachineState) -> bool + 'a
+    {
+        self.add_breakpoint(inst_index, Some(Box::new(condition)));
+    }
+
+    // `break_if` with a condition expression, see `Condition`
+    pub fn break_when(&mut self, inst_index: usize, condition: &str) -> Result<(), ConditionError> {
+        let condition = Condition::parse(condition)?;
+        self.break_if(inst_index, move |state| condition.holds(state));
+
+        Ok(())
+    }
+
+    fn add_breakpoint(&mut self, inst_index: usize, condition: Option<Predicate<'a>>) {
+        self.breakpoints.push(Breakpoint { inst_index, hit_count: 0, condition });
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    // Counts the hit of every breakpoint at the current instruction, returns
+    // whether any of them stops execution. Going backwards the hits aren't
+    // counted again.
+    fn breakpoint_hit(&mut self, count: bool) -> bool {
+        let (pc, ptr) = (self.interp.pc(), self.interp.ptr());
+        if self.resume_at.take() == Some(pc) {
+            return false;
+        }
+
+        let mut hit = false;
+        for breakpoint in self.breakpoints.iter_mut().filter(|b| b.inst_index == pc) {
+            if count {
+                breakpoint.hit_count += 1;
+            }
+            let state = MachineState {
+                pc,
+                ptr,
+                tape: self.interp.tape(),
+                hit_count: breakpoint.hit_count,
+            };
+            hit |= breakpoint.condition.as_mut().is_none_or(|condition| condition(&state));
+        }
+        if hit {
+            self.resume_at = Some(pc);
+        }
+
+        hit
+    }
+
+    // Stops whenever the value of `cell` changes
+    pub fn watch(&mut self, cell: usize) {
+        self.watch_range(cell..cell + 1);
+    }
+
+    pub fn watch_range(&mut self, cells: Range<usize>) {
+        self.watches.push(Watch { cells, on_read: false });
+    }
+
+    // Like `watch_range`, but also stops when the cells are read, by `.` or
+    // by a loop testing them
+    pub fn watch_reads(&mut self, cells: Range<usize>) {
+        self.watches.push(Watch { cells, on_read: true });
+    }
+
+    pub fn clear_watches(&mut self) {
+        self.watches.clear();
+    }
+
+    fn watched(&self, cell: usize) -> bool {
+        self.watches.iter().any(|watch| watch.cells.start <= cell && cell < watch.cells.end)
+    }
+
+    // Keeps the last `budget` bytes worth of history for going backwards,
+    // see `Interp::set_recording`
+    pub fn record(&mut self, budget: usize) {
+        self.interp.set_recording(Some(budget));
+    }
+
+    // Undoes the last instruction, returns false without a history to undo
+    // it with. Continuing forward from there doesn't stop at a breakpoint on
+    // the instruction right away.
+    pub fn step_back(&mut self) -> bool {
+        let stepped = self.interp.step_back();
+        self.resume_at = Some(self.interp.pc());
+
+        stepped
+    }
+
+    // Runs backwards until the state right before a breakpoint, or until an
+    // instruction that changed a watched cell was undone. Output written on
+    // the way isn't taken back, it just isn't written again going forward.
+    pub fn reverse_continue(&mut self) -> Event {
+        loop {
+            let (inst_index, old) = match self.interp.last_step() {
+                Some(step) => step,
+                None => return Event::StartOfHistory,
+            };
+            let cell = self.interp.ptr();
+            let new = self.interp.tape()[cell];
+            self.interp.step_back();
+
+            if let Some(old) = old {
+                if old != new && self.watched(cell) {
+                    self.resume_at = Some(inst_index);
+                    let span = self.spans[inst_index];
+                    return Event::Watchpoint(WatchHit { inst_index, span, cell, old, new });
+                }
+            }
+            if self.breakpoint_hit(false) {
+                return Event::Breakpoint { inst_index };
+            }
+        }
+    }
+
+    pub fn interp(&self) -> &Interp<'a> {
+        &self.interp
+    }
+
+    pub fn interp_mut(&mut self) -> &mut Interp<'a> {
+        &mut self.interp
+    }
+
+    // Continues execution until the next event
+    pub fn cont<R: Read, W: Write>(&mut self, mut input: R, mut output: W) -> Event {
+        if self.watches.is_empty() && self.breakpoints.is_empty() {
+            loop {
+                match self.interp.run_for(usize::MAX, &mut input, &mut output) {
+                    StepOutcome::Paused => {}
+                    outcome => return Event::from(outcome),
+                }
+            }
+        }
+
+        loop {
+            let inst_index = self.interp.pc();
+            if inst_index >= self.insts.len() {
+                return Event::Finished;
+            }
+            if self.breakpoint_hit(true) {
+                return Event::Breakpoint { inst_index };
+            }
+            if let Some(event) = self.execute(&mut input, &mut output) {
+                return event;
+            }
+        }
+    }
+
+    // Executes the next instruction whatever breakpoints are on it, with
+    // watchpoints firing like in `cont`. Continuing from there doesn't stop
+    // at a breakpoint on the instruction it stopped before right away.
+    pub fn step<R: Read, W: Write>(&mut self, input: R, output: W) -> Event {
+        if self.interp.pc() >= self.insts.len() {
+            return Event::Finished;
+        }
+        let event = self.execute(input, output).unwrap_or(Event::Stepped);
+        self.resume_at = Some(self.interp.pc());
+
+        event
+    }
+
+    // Executes the next instruction, the event it caused if any
+    fn execute<R: Read, W: Write>(&mut self, input: R, output: W) -> Option<Event> {
+        let inst_index = self.interp.pc();
+        let step = match self.interp.step(input, output) {
+            Ok(Some(step)) => step,
+            Ok(None) => return Some(Event::Finished),
+            Err(RuntimeError::Io(ref e)) if e.kind() == io::ErrorKind::WouldBlock => return Some(Event::NeedsInput),
+            Err(e) => return Some(Event::Error(e)),
+        };
+
+        let (cell, old, new) = (step.ptr, step.cell_before, step.cell_after);
+        let reads = match *step.inst {
+            IncPtr(_) | DecPtr(_) | ReadChar => false,
+            IncVal(_) | DecVal(_) | PrintCell | JmpFwd(_) | JmpBack(_) => true,
+            Ext(ExtOp::End) | Ext(ExtOp::Retrieve) => false,
+            Ext(_) | HostCall => true,
+        };
+        let hit = self.watches.iter()
+            .filter(|watch| watch.cells.start <= cell && cell < watch.cells.end)
+            .any(|watch| new != old || (reads && watch.on_read));
+        if hit {
+            let span = self.spans[inst_index];
+            return Some(Event::Watchpoint(WatchHit { inst_index, span, cell, old, new }));
+        }
+
+        None
+    }
+}
+
+impl From<StepOutcome> for Event {
+    fn from(outcome: StepOutcome) -> Event {
+        match outcome {
+            StepOutcome::Paused => unreachable!("paused runs are continued"),
+            StepOutcome::Finished => Event::Finished,
+            StepOutcome::NeedsInput => Event::NeedsInput,
+            StepOutcome::Error(e) => Event::Error(e),
+        }
+    }
+}
+
+
+#[cfg(test)]
+use brainfuck::parse_with_spans;
+
+#[test]
+fn test_watch() {
+    let (insts, spans) = parse_with_spans("++[->+>+<<]").unwrap();
+    let mut debugger = Debugger::new(&insts, &spans, 3);
+    debugger.watch(2);
+
+    let mut hits = Vec::new();
+    loop {
+        match debugger.cont(io::empty(), io::sink()) {
+            Event::Watchpoint(hit) => hits.push(hit),
+            Event::Finished => break,
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    let span = Span { start: 7, end: 8 };
+    assert_eq!(hits, [
+        WatchHit { inst_index: 6, span, cell: 2, old: 0, new: 1 },
+        WatchHit { inst_index: 6, span, cell: 2, old: 1, new: 2 },
+    ]);
+    assert_eq!(debugger.interp().tape(), [0, 2, 2]);
+}
+
+#[test]
+fn test_conditional_breakpoints() {
+    // counts to 250 in cell 1, the `+` is instruction 3
+    let (insts, spans) = parse_with_spans(&format!("{}[>+<-]", "-".repeat(6))).unwrap();
+    let mut debugger = Debugger::new(&insts, &spans, 2);
+    debugger.break_when(3, "hit_count % 100 == 0").unwrap();
+
+    let mut stops = Vec::new();
+    loop {
+        match debugger.cont(io::empty(), io::sink()) {
+            Event::Breakpoint { inst_index } => {
+                assert_eq!(inst_index, 3);
+                stops.push(debugger.interp().tape()[1]);
+            }
+            Event::Finished => break,
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+    // 250 iterations, stopped before the 100th and 200th `+`
+    assert_eq!(stops, [99, 199]);
+
+    let mut debugger = Debugger::new(&insts, &spans, 2);
+    debugger.break_when(3, "cell(ptr) == 42").unwrap();
+    assert!(matches!(debugger.cont(io::empty(), io::sink()), Event::Breakpoint { inst_index: 3 }));
+    assert_eq!(debugger.interp().tape(), [250 - 42, 42]);
+    debugger.break_at(6);
+    // the `]` stops on every iteration
+    assert!(matches!(debugger.cont(io::empty(), io::sink()), Event::Breakpoint { inst_index: 6 }));
+    assert!(matches!(debugger.cont(io::empty(), io::sink()), Event::Breakpoint { inst_index: 6 }));
+    assert_eq!(debugger.interp().tape(), [250 - 44, 44]);
+
+    // closures can keep state of their own
+    let mut seen = Vec::new();
+    {
+        let mut debugger = Debugger::new(&insts, &spans, 2);
+        debugger.break_if(1, |state| {
+            seen.push(state.cell(0));
+            false
+        });
+        assert!(matches!(debugger.cont(io::empty(), io::sink()), Event::Finished));
+    }
+    assert_eq!(seen, [250]);
+
+    // invalid conditions are rejected right away
+    assert!(debugger.break_when(3, "cell(ptr) = 5").is_err());
+}
+
+#[test]
+fn test_reverse_continue() {
+    let (insts, spans) = parse_with_spans("++[->+>+<<]>>.").unwrap();
+    let mut debugger = Debugger::new(&insts, &spans, 3);
+    debugger.record(1 << 16);
+    let mut output = Vec::new();
+    assert!(matches!(debugger.cont(io::empty(), &mut output), Event::Finished));
+    assert_eq!(output, [2]);
+
+    // back from the end over the `.` to the last change of cell 2
+    debugger.watch(2);
+    match debugger.reverse_continue() {
+        Event::Watchpoint(hit) => assert_eq!((hit.inst_index, hit.old, hit.new), (6, 1, 2)),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(debugger.interp().tape(), [0, 2, 1]);
+    assert_eq!(debugger.interp().pc(), 6);
+
+    debugger.clear_watches();
+    debugger.break_at(3);
+    assert!(matches!(debugger.reverse_continue(), Event::Breakpoint { inst_index: 3 }));
+    assert_eq!(debugger.interp().tape(), [0, 1, 1]);
+    assert!(matches!(debugger.reverse_continue(), Event::Breakpoint { inst_index: 3 }));
+    assert_eq!(debugger.interp().tape(), [1, 0, 0]);
+    assert!(matches!(debugger.reverse_continue(), Event::StartOfHistory));
+    assert_eq!((debugger.interp().tape(), debugger.interp().pc()), (&[0, 0, 0][..], 0));
+
+    // forward again to the same end, without printing a second time
+    debugger.clear_breakpoints();
+    assert!(!debugger.step_back());
+    assert!(matches!(debugger.cont(io::empty(), &mut output), Event::Finished));
+    assert_eq!(output, [2]);
+    assert_eq!(debugger.interp().tape(), [0, 2, 2]);
+}
+
+#[test]
+fn test_watch_reads() {
+    let (insts, spans) = parse_with_spans(",[>+<-]>.").unwrap();
+    let mut debugger = Debugger::new(&insts, &spans, 2);
+    debugger.watch_range(0..1);
+    debugger.watch_reads(1..2);
+
+    let mut hits = Vec::new();
+    let mut input = &[1u8][..];
+    loop {
+        match debugger.cont(&mut input, io::sink()) {
+            Event::Watchpoint(hit) => hits.push((hit.inst_index, hit.cell, hit.old, hit.new)),
+            Event::Finished => break,
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    // the read, the `+`, the `-` and the `.`
+    assert_eq!(hits, [(0, 0, 0, 1), (3, 1, 0, 1), (5, 0, 1, 0), (8, 1, 1, 1)]);
+
+    // without watchpoints the run isn't interrupted
+    let mut debugger = Debugger::new(&insts, &spans, 2);
+    let mut output = Vec::new();
+    assert!(matches!(debugger.cont(&[7u8][..], &mut output), Event::Finished));
+    assert_eq!(output, [7]);
+}
+
+#[test]
+fn test_step() {
+    let (insts, spans) = parse_with_spans("+[>+<-]").unwrap();
+    let mut debugger = Debugger::new(&insts, &spans, 2);
+    debugger.break_at(2);
+    debugger.watch(1);
+
+    assert!(matches!(debugger.step(io::empty(), io::sink()), Event::Stepped));
+    assert!(matches!(debugger.step(io::empty(), io::sink()), Event::Stepped));
+    // stepping onto the breakpoint and over it
+    assert_eq!(debugger.interp().pc(), 2);
+    assert!(matches!(debugger.step(io::empty(), io::sink()), Event::Stepped));
+    assert!(matches!(debugger.step(io::empty(), io::sink()), Event::Watchpoint(WatchHit { inst_index: 3, .. })));
+    assert!(matches!(debugger.cont(io::empty(), io::sink()), Event::Finished));
+    assert!(matches!(debugger.step(io::empty(), io::sink()), Event::Finished));
+
+    // a `,` without input yet is tried again
+    struct Pending;
+    impl Read for Pending {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::WouldBlock.into())
+        }
+    }
+    let (insts, spans) = parse_with_spans(",").unwrap();
+    let mut debugger = Debugger::new(&insts, &spans, 1);
+    assert!(matches!(debugger.step(Pending, io::sink()), Event::NeedsInput));
+    assert_eq!(debugger.interp().pc(), 0);
+    assert!(matches!(debugger.step(&[5u8][..], io::sink()), Event::Stepped));
+    assert_eq!(debugger.interp().tape(), [5]);
+}
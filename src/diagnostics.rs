@@ -0,0 +1,308 @@
+// Everything the command line tool reports going wrong goes through `report`,
+// which writes it for people, like `hello.b:3:7: error: unmatched ']'`, or
+// with `--diagnostics json` as JSON Lines for editors, an object a line:
+//
+//   {"severity":"error","code":"unmatched-close","message":"unmatched ']'","file":"hello.b",
+//    "offset":41,"line":3,"column":7,"end_offset":42,"end_line":3,"end_column":8}
+//
+// `code` names the kind of error and doesn't change, unlike the message.
+// Where an error isn't about a file or a place in it, the fields are null.
+// Offsets are in bytes and the end is just past what the error points at,
+// lines and columns count from 1, columns in characters. There are no
+// lints yet, every diagnostic is an error.
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+use brainfuck::{CompileError, RuntimeError};
+use bytecode::BytecodeError;
+use ir::IrError;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Human,
+    Json,
+}
+
+// Where `report` writes to and how, stderr for people until `set_output`
+static OUTPUT: Mutex<Option<(Format, Box<dyn Write + Send>)>> = Mutex::new(None);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub code: &'static str,
+    pub message: String,
+    pub file: Option<String>,
+    pub offset: Option<usize>,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub end_offset: Option<usize>,
+    pub end_line: Option<usize>,
+    pub end_column: Option<usize>,
+}
+
+// The kinds of errors the tool reports, by their `code`
+pub trait ErrorCode: fmt::Display {
+    fn code(&self) -> &'static str;
+}
+
+impl ErrorCode for io::Error {
+    fn code(&self) -> &'static str {
+        "io"
+    }
+}
+
+impl ErrorCode for CompileError {
+    fn code(&self) -> &'static str {
+        use brainfuck::CompileError::*;
+
+        match *self {
+            UnmatchedClose { .. } => "unmatched-close",
+            UnclosedOpen { .. } => "unclosed-open",
+            Multiple(_) => "multiple",
+            InvalidJump { .. } => "invalid-jump",
+            JumpOutOfRange { .. } => "jump-out-of-range",
+            OperandTooLarge { .. } => "operand-too-large",
+            CodeTooLarge { .. } => "code-too-large",
+            ArenaFull { .. } => "arena-full",
+            PassProducedInvalidIr { .. } => "pass-produced-invalid-ir",
+            UnsupportedArithMode(_) => "unsupported-arith-mode",
+            Io(_) => "io",
+        }
+    }
+}
+
+impl ErrorCode for RuntimeError {
+    fn code(&self) -> &'static str {
+        use brainfuck::RuntimeError::*;
+
+        match *self {
+            InvalidTapeSize(_) => "invalid-tape-size",
+            TapeLengthMismatch { .. } => "tape-length-mismatch",
+            TapeTooSmall { .. } => "tape-too-small",
+            InitialTapeTooLarge { .. } => "initial-tape-too-large",
+            PointerStartOutOfRange { .. } => "pointer-start-out-of-range",
+            PointerUnderflow { .. } => "pointer-underflow",
+            PointerOverflow { .. } => "pointer-overflow",
+            CellOverflow { .. } => "cell-overflow",
+            CellUnderflow { .. } => "cell-underflow",
+            NonTerminatingLoop { .. } => "non-terminating-loop",
+            GuardCorrupted { .. } => "guard-corrupted",
+            TapeFileSizeMismatch { .. } => "tape-file-size-mismatch",
+            MemoryLimitExceeded { .. } => "memory-limit-exceeded",
+            Fault { .. } => "fault",
+            Cancelled => "cancelled",
+            SandboxFailed(_) => "sandbox-failed",
+            HookPanicked(_) => "hook-panicked",
+            UnregisteredHostCall { .. } => "unregistered-host-call",
+            Compile(ref err) => err.code(),
+            Io(_) => "io",
+        }
+    }
+}
+
+impl ErrorCode for IrError {
+    fn code(&self) -> &'static str {
+        match *self {
+            IrError::Syntax { .. } => "ir-syntax",
+            IrError::Invalid(ref err) => err.code(),
+        }
+    }
+}
+
+impl ErrorCode for BytecodeError {
+    fn code(&self) -> &'static str {
+        use bytecode::BytecodeError::*;
+
+        match *self {
+            UnsupportedVersion(_) => "unsupported-bytecode-version",
+            UnsupportedCellWidth(_) => "unsupported-cell-width",
+            UnsupportedEofMode(_) => "unsupported-eof-mode",
+            Corrupted(_) => "corrupted-bytecode",
+            Invalid(ref err) => err.code(),
+        }
+    }
+}
+
+// 1-based line and column of a byte offset into `source`
+pub fn position(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+
+    (before.matches('\n').count() + 1, before[line_start..].chars().count() + 1)
+}
+
+impl Diagnostic {
+    pub fn new<M: fmt::Display>(code: &'static str, message: M) -> Diagnostic {
+        Diagnostic {
+            code,
+            message: message.to_string(),
+            file: None,
+            offset: None,
+            line: None,
+            column: None,
+            end_offset: None,
+            end_line: None,
+            end_column: None,
+        }
+    }
+
+    pub fn error<E: ErrorCode>(err: &E) -> Diagnostic {
+        Diagnostic::new(err.code(), err)
+    }
+
+    pub fn file(mut self, file: &str) -> Diagnostic {
+        self.file = Some(file.to_string());
+        self
+    }
+
+    // A line of the file, without pointing anywhere in it
+    pub fn line(mut self, line: usize) -> Diagnostic {
+        self.line = Some(line);
+        self
+    }
+
+    // Points at `start..end` of `source`, what the file holds
+    pub fn span(mut self, source: &str, start: usize, end: usize) -> Diagnostic {
+        let (line, column) = position(source, start);
+        let (end_line, end_column) = position(source, end);
+        self.offset = Some(start);
+        self.line = Some(line);
+        self.column = Some(column);
+        self.end_offset = Some(end);
+        self.end_line = Some(end_line);
+        self.end_column = Some(end_column);
+        self
+    }
+
+    // Points at the character at `offset` of `source`
+    pub fn at(self, source: &str, offset: usize) -> Diagnostic {
+        let len = source[offset..].chars().next().map_or(0, char::len_utf8);
+        self.span(source, offset, offset + len)
+    }
+
+    // `file:line:column: error: message`, as far as it's known. A line
+    // without a file would look like one, so would a column without a line.
+    pub fn to_human(&self) -> String {
+        let mut location = String::new();
+        if let Some(ref file) = self.file {
+            location.push_str(&format!("{}:", file));
+            if let Some(line) = self.line {
+                location.push_str(&format!("{}:", line));
+                if let Some(column) = self.column {
+                    location.push_str(&format!("{}:", column));
+                }
+            }
+            location.push(' ');
+        }
+
+        format!("{}error: {}", location, self.message)
+    }
+
+    pub fn to_json(&self) -> String {
+        let number = |n: Option<usize>| n.map_or("null".to_string(), |n| n.to_string());
+        format!(
+            "{{\"severity\":\"error\",\"code\":{},\"message\":{},\"file\":{},\"offset\":{},\"line\":{},\
+             \"column\":{},\"end_offset\":{},\"end_line\":{},\"end_column\":{}}}",
+            json_string(self.code), json_string(&self.message),
+            self.file.as_ref().map_or("null".to_string(), |file| json_string(file)),
+            number(self.offset), number(self.line), number(self.column),
+            number(self.end_offset), number(self.end_line), number(self.end_column)
+        )
+    }
+}
+
+pub fn json_string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// Makes `report` write in `format` to `path`, which is created anew, or to
+// stderr
+pub fn set_output(format: Format, path: Option<&str>) -> io::Result<()> {
+    let out: Box<dyn Write + Send> = match path {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stderr()),
+    };
+    *OUTPUT.lock().unwrap() = Some((format, out));
+    Ok(())
+}
+
+// Reports `diagnostic` where `set_output` said, a line each. Failing to is
+// nothing that could be reported.
+pub fn report(diagnostic: Diagnostic) {
+    let mut output = OUTPUT.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    match *output {
+        Some((Format::Json, ref mut out)) => {
+            let _ = writeln!(out, "{}", diagnostic.to_json());
+        }
+        Some((Format::Human, ref mut out)) => {
+            let _ = writeln!(out, "{}", diagnostic.to_human());
+        }
+        None => eprintln!("{}", diagnostic.to_human()),
+    }
+}
+
+// A diagnostic for every error of `CompileError::Multiple`, or for the one
+// error, pointing at the character of `source` if it's known
+pub fn compile_error(source: &str, err: &CompileError) -> Vec<Diagnostic> {
+    match *err {
+        CompileError::Multiple(ref errors) => errors.iter().flat_map(|err| compile_error(source, err)).collect(),
+        _ => match err.offset() {
+            Some(offset) => vec![Diagnostic::error(err).at(source, offset)],
+            None => vec![Diagnostic::error(err)],
+        },
+    }
+}
+
+// Reports a compile error of `source` as read from `path`
+pub fn report_compile_error(path: &str, source: &str, err: &CompileError) {
+    for diagnostic in compile_error(source, err) {
+        report(diagnostic.file(path));
+    }
+}
+
+
+#[test]
+fn test_to_human() {
+    let source = "+\n>]";
+    let diagnostic = Diagnostic::error(&CompileError::UnmatchedClose { offset: 3 });
+    assert_eq!(diagnostic.to_human(), "error: unmatched ']'");
+    assert_eq!(diagnostic.clone().file("a.b").to_human(), "a.b: error: unmatched ']'");
+    assert_eq!(diagnostic.clone().file("a.b").line(2).to_human(), "a.b:2: error: unmatched ']'");
+    assert_eq!(diagnostic.clone().file("a.b").at(source, 3).to_human(), "a.b:2:2: error: unmatched ']'");
+    // without a file, the position would look like one
+    assert_eq!(diagnostic.at(source, 3).to_human(), "error: unmatched ']'");
+}
+
+#[test]
+fn test_to_json() {
+    let source = "é[\n";
+    let diagnostic = Diagnostic::error(&CompileError::UnclosedOpen { offset: 2 }).file("dir/\"a\".b").at(source, 2);
+    assert_eq!(
+        diagnostic.to_json(),
+        "{\"severity\":\"error\",\"code\":\"unclosed-open\",\"message\":\"unclosed '['\",\
+         \"file\":\"dir/\\\"a\\\".b\",\"offset\":2,\"line\":1,\"column\":2,\"end_offset\":3,\"end_line\":1,\
+         \"end_column\":3}"
+    );
+
+    let diagnostic = Diagnostic::new("io", "a\tb\\\n\u{1}");
+    assert_eq!(
+        diagnostic.to_json(),
+        "{\"severity\":\"error\",\"code\":\"io\",\"message\":\"a\\tb\\\\\\n\\u0001\",\"file\":null,\
+         \"offset\":null,\"line\":null,\"column\":null,\"end_offset\":null,\"end_line\":null,\"end_column\":null}"
+    );
+}
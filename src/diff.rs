@@ -0,0 +1,218 @@
+// Differences between two programs by their instructions, which unlike a
+// diff of their sources don't care about comments and layout. Instructions
+// are the same if they are equal up to where their jumps go, and the
+// brackets of a loop are only the same as those of a loop whose other
+// bracket is the same too, so that the programs match loop by loop.
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use brainfuck::Inst;
+use brainfuck::Inst::*;
+
+
+// A step from the first program to the second, by instruction indices
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edit {
+    Same(usize, usize),
+    Removed(usize),
+    Inserted(usize),
+    // One instruction in place of the other, neither of them a bracket
+    Changed(usize, usize),
+}
+
+impl Edit {
+    pub fn is_same(&self) -> bool {
+        matches!(*self, Edit::Same(..))
+    }
+}
+
+// How an instruction shows up in a diff, brackets without where they jump
+pub fn describe(inst: &Inst) -> String {
+    match *inst {
+        JmpFwd(_) => "[".to_string(),
+        JmpBack(_) => "]".to_string(),
+        ref inst => format!("{:?}", inst),
+    }
+}
+
+fn is_jump(inst: &Inst) -> bool {
+    matches!(*inst, JmpFwd(_) | JmpBack(_))
+}
+
+fn hash<T: Hash>(value: T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+// Instructions with the same key are the same. With `whole_loops` the key
+// of a bracket covers its whole loop, it's then only the same as the
+// bracket of an identical loop.
+fn keys(insts: &[Inst], whole_loops: bool) -> Vec<u64> {
+    let described: Vec<String> = insts.iter().map(describe).collect();
+    (0..insts.len())
+        .map(|i| match insts[i] {
+            JmpFwd(end) if whole_loops => hash(&described[i..=end]),
+            JmpBack(start) if whole_loops => hash((&described[start..=i], "]")),
+            _ => hash(&described[i]),
+        })
+        .collect()
+}
+
+// The edits turning `a` into `b`, both with their jumps linked, in the
+// order of both programs
+pub fn diff(a: &[Inst], b: &[Inst]) -> Vec<Edit> {
+    let (plain_a, plain_b) = (keys(a, false), keys(b, false));
+
+    // identical loops first, so that the brackets of one don't end up as
+    // the brackets of another, then whatever is left in between
+    let loops = longest_common_subsequence(&keys(a, true), &keys(b, true));
+    let mut pairs = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    for (next_i, next_j) in loops.into_iter().chain(Some((a.len(), b.len()))) {
+        let between = longest_common_subsequence(&plain_a[i..next_i], &plain_b[j..next_j]);
+        pairs.extend(between.into_iter().map(|(x, y)| (i + x, j + y)));
+        pairs.push((next_i, next_j));
+        i = next_i + 1;
+        j = next_j + 1;
+    }
+    pairs.pop();
+    let pairs = loop_aware(a, b, pairs);
+
+    let mut edits = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    for (next_i, next_j) in pairs.into_iter().chain(Some((a.len(), b.len()))) {
+        while i < next_i || j < next_j {
+            if i < next_i && j < next_j && !is_jump(&a[i]) && !is_jump(&b[j]) {
+                edits.push(Edit::Changed(i, j));
+                i += 1;
+                j += 1;
+            } else if i < next_i {
+                edits.push(Edit::Removed(i));
+                i += 1;
+            } else {
+                edits.push(Edit::Inserted(j));
+                j += 1;
+            }
+        }
+        if next_i < a.len() {
+            edits.push(Edit::Same(next_i, next_j));
+            i += 1;
+            j += 1;
+        }
+    }
+
+    edits
+}
+
+// Index pairs of the keys both have in the same order, as many as there are
+fn longest_common_subsequence(a: &[u64], b: &[u64]) -> Vec<(usize, usize)> {
+    // whatever both start and end with doesn't need the table
+    let prefix = a.iter().zip(b).take_while(|&(a, b)| a == b).count();
+    let suffix = a[prefix..].iter().rev().zip(b[prefix..].iter().rev()).take_while(|&(a, b)| a == b).count();
+    let (rest_a, rest_b) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+
+    // lengths[i * width + j] is the length of the longest common
+    // subsequence of `rest_a[i..]` and `rest_b[j..]`
+    let width = rest_b.len() + 1;
+    let mut lengths = vec![0u32; (rest_a.len() + 1) * width];
+    for i in (0..rest_a.len()).rev() {
+        for j in (0..rest_b.len()).rev() {
+            lengths[i * width + j] = if rest_a[i] == rest_b[j] {
+                lengths[(i + 1) * width + j + 1] + 1
+            } else {
+                lengths[(i + 1) * width + j].max(lengths[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut pairs: Vec<(usize, usize)> = (0..prefix).map(|i| (i, i)).collect();
+    let (mut i, mut j) = (0, 0);
+    while i < rest_a.len() && j < rest_b.len() {
+        if rest_a[i] == rest_b[j] {
+            pairs.push((prefix + i, prefix + j));
+            i += 1;
+            j += 1;
+        } else if lengths[(i + 1) * width + j] >= lengths[i * width + j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    pairs.extend((0..suffix).map(|k| (a.len() - suffix + k, b.len() - suffix + k)));
+
+    pairs
+}
+
+// Drops the pairs of brackets whose other brackets aren't a pair as well,
+// until every pair of brackets belongs to a pair of loops
+fn loop_aware(a: &[Inst], b: &[Inst], mut pairs: Vec<(usize, usize)>) -> Vec<(usize, usize)> {
+    let other = |inst: &Inst| match *inst {
+        JmpFwd(n) | JmpBack(n) => n,
+        _ => unreachable!(),
+    };
+
+    loop {
+        let mut in_b = vec![None; a.len()];
+        for &(i, j) in &pairs {
+            in_b[i] = Some(j);
+        }
+
+        let before = pairs.len();
+        pairs.retain(|&(i, j)| !is_jump(&a[i]) || in_b[other(&a[i])] == Some(other(&b[j])));
+        if pairs.len() == before {
+            return pairs;
+        }
+    }
+}
+
+
+#[cfg(test)]
+use brainfuck::parse;
+
+#[cfg(test)]
+fn edits(a: &str, b: &str) -> Vec<Edit> {
+    diff(&parse(a).unwrap(), &parse(b).unwrap())
+}
+
+#[test]
+fn test_diff() {
+    use self::Edit::*;
+
+    // comments and layout aren't instructions
+    assert!(edits("+[->+<] a comment\n.", "+ [\n  - > + <\n]\n.").iter().all(Edit::is_same));
+    assert_eq!(edits("", ""), []);
+
+    assert_eq!(edits("+>.", "+>>."), [Same(0, 0), Changed(1, 1), Same(2, 2)]);
+    assert_eq!(edits("+.", "+,."), [Same(0, 0), Inserted(1), Same(1, 2)]);
+    assert_eq!(edits("+,.", "+."), [Same(0, 0), Removed(1), Same(2, 1)]);
+    assert_eq!(edits(".", ","), [Changed(0, 0)]);
+    assert_eq!(edits("+[-]", "+"), [Same(0, 0), Removed(1), Removed(2), Removed(3)]);
+}
+
+#[test]
+fn test_diff_loops() {
+    use self::Edit::*;
+
+    // a loop around the same code adds both brackets, even though the
+    // first `[` of both could be the same as well
+    assert_eq!(
+        edits("[-].", "[[-].]"),
+        [Inserted(0), Same(0, 1), Same(1, 2), Same(2, 3), Same(3, 4), Inserted(5)]
+    );
+    // a changed loop is still the same loop
+    assert_eq!(
+        edits("+[->+<]", "+[->++<]"),
+        [Same(0, 0), Same(1, 1), Same(2, 2), Same(3, 3), Changed(4, 4), Same(5, 5), Same(6, 6)]
+    );
+    // but two loops aren't one, the brackets of one `[>]` and the other
+    // `[<]` don't make a loop
+    assert_eq!(
+        edits("[>]+[<]", "[>+<]"),
+        [Removed(0), Inserted(0), Same(1, 1), Removed(2), Same(3, 2), Removed(4), Same(5, 3), Removed(6), Inserted(4)]
+    );
+
+    assert_eq!(describe(&JmpFwd(3)), "[");
+    assert_eq!(describe(&JmpBack(0)), "]");
+    assert_eq!(describe(&IncVal(2)), "IncVal(2)");
+}
@@ -0,0 +1,171 @@
+use std::io::{self, Write};
+
+use brainfuck::{Inst, Span};
+use brainfuck::Inst::*;
+
+
+const INDENT: &str = "    ";
+
+// Foreground colors of matching brackets, cycling by how deep they are
+// nested: yellow, magenta, cyan, green, blue and red
+const PALETTE: [u8; 6] = [33, 35, 36, 32, 34, 31];
+
+// How many loops every instruction is in, a loop's brackets count as
+// outside of it. Both dumps are laid out by this.
+fn depths(insts: &[Inst]) -> Vec<usize> {
+    let mut depth = 0;
+    insts.iter()
+        .map(|inst| match *inst {
+            JmpFwd(_) => {
+                depth += 1;
+                depth - 1
+            }
+            JmpBack(_) => {
+                depth -= 1;
+                depth
+            }
+            _ => depth,
+        })
+        .collect()
+}
+
+// Label numbers of the loops, for both of their brackets: `L0`, `L1` and
+// so on in the order the loops start. The labels of a program don't depend
+// on how its instructions are numbered, so they are the same across dumps
+// and stay meaningful in diffs.
+pub fn loop_labels(insts: &[Inst]) -> Vec<Option<usize>> {
+    let mut labels = vec![None; insts.len()];
+    let mut next = 0;
+    for (i, inst) in insts.iter().enumerate() {
+        match *inst {
+            JmpFwd(_) => {
+                labels[i] = Some(next);
+                next += 1;
+            }
+            JmpBack(target) => labels[i] = labels[target],
+            _ => {}
+        }
+    }
+
+    labels
+}
+
+// The instructions a line each, indented by how deep in loops they are
+pub fn write_plain<W: Write>(insts: &[Inst], mut out: W) -> io::Result<()> {
+    for (i, (inst, depth)) in insts.iter().zip(depths(insts)).enumerate() {
+        writeln!(out, "{}{}: {:?}", INDENT.repeat(depth), i, inst)?;
+    }
+
+    Ok(())
+}
+
+// Like `write_plain` for terminals: the brackets of a loop share a color
+// and point at each other, indices and arguments are dimmed
+pub fn write_colored<W: Write>(insts: &[Inst], mut out: W) -> io::Result<()> {
+    for (i, (inst, depth)) in insts.iter().zip(depths(insts)).enumerate() {
+        write!(out, "{}\x1b[2m{}:\x1b[0m ", INDENT.repeat(depth), i)?;
+        let color = PALETTE[depth % PALETTE.len()];
+        match *inst {
+            JmpFwd(target) => writeln!(out, "\x1b[1;{}m[ ──▶ {}\x1b[0m", color, target)?,
+            JmpBack(target) => writeln!(out, "\x1b[1;{}m] ◀── {}\x1b[0m", color, target)?,
+            IncPtr(n) => writeln!(out, "IncPtr\x1b[2m({})\x1b[0m", n)?,
+            DecPtr(n) => writeln!(out, "DecPtr\x1b[2m({})\x1b[0m", n)?,
+            IncVal(n) => writeln!(out, "IncVal\x1b[2m({})\x1b[0m", n)?,
+            DecVal(n) => writeln!(out, "DecVal\x1b[2m({})\x1b[0m", n)?,
+            PrintCell | ReadChar | Ext(_) | HostCall => writeln!(out, "{:?}", inst)?,
+        }
+    }
+
+    Ok(())
+}
+
+// Like `write_plain` with loops as `L3: [` and `] -> L3` instead of
+// instruction indices, followed by where every label is: its instruction
+// and, with `spans`, its bytes in the source
+pub fn write_labeled<W: Write>(insts: &[Inst], spans: Option<&[Span]>, mut out: W) -> io::Result<()> {
+    let labels = loop_labels(insts);
+    for (i, (inst, depth)) in insts.iter().zip(depths(insts)).enumerate() {
+        let indent = INDENT.repeat(depth);
+        match (inst, labels[i]) {
+            (&JmpFwd(_), Some(label)) => writeln!(out, "{}L{}: [", indent, label)?,
+            (&JmpBack(_), Some(label)) => writeln!(out, "{}] -> L{}", indent, label)?,
+            _ => writeln!(out, "{}{:?}", indent, inst)?,
+        }
+    }
+
+    let mut heads = insts.iter().enumerate().filter(|&(_, inst)| matches!(*inst, JmpFwd(_))).peekable();
+    if heads.peek().is_some() {
+        writeln!(out)?;
+    }
+    for (i, _) in heads {
+        let label = labels[i].unwrap();
+        match spans {
+            Some(spans) => writeln!(
+                out, "L{}: instruction {}, source {}..{}", label, i, spans[i].start, spans[i].end
+            )?,
+            None => writeln!(out, "L{}: instruction {}", label, i)?,
+        }
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+use brainfuck::{parse, parse_with_spans};
+
+#[test]
+fn test_depths() {
+    let insts = parse("+[>[-]<]").unwrap();
+    assert_eq!(depths(&insts), [0, 0, 1, 1, 2, 1, 1, 0]);
+}
+
+#[test]
+fn test_write_colored() {
+    let insts = parse("[[-]]").unwrap();
+    let mut out = Vec::new();
+    write_colored(&insts, &mut out).unwrap();
+    let expected = "\x1b[2m0:\x1b[0m \x1b[1;33m[ ──▶ 4\x1b[0m\n\
+                    \x20   \x1b[2m1:\x1b[0m \x1b[1;35m[ ──▶ 3\x1b[0m\n\
+                    \x20       \x1b[2m2:\x1b[0m DecVal\x1b[2m(1)\x1b[0m\n\
+                    \x20   \x1b[2m3:\x1b[0m \x1b[1;35m] ◀── 1\x1b[0m\n\
+                    \x1b[2m4:\x1b[0m \x1b[1;33m] ◀── 0\x1b[0m\n";
+    assert_eq!(String::from_utf8(out).unwrap(), expected);
+}
+
+#[test]
+fn test_loop_labels() {
+    let insts = parse("+[>[-]<]").unwrap();
+    assert_eq!(loop_labels(&insts), [None, Some(0), None, Some(1), None, Some(1), None, Some(0)]);
+
+    // more instructions in front renumber everything but the labels
+    let insts = parse("++>+[>[-]<]").unwrap();
+    assert_eq!(loop_labels(&insts).into_iter().flatten().collect::<Vec<_>>(), [0, 1, 1, 0]);
+}
+
+#[test]
+fn test_write_labeled() {
+    let (insts, spans) = parse_with_spans("+ [>[-]<]").unwrap();
+    let mut out = Vec::new();
+    write_labeled(&insts, Some(&spans), &mut out).unwrap();
+    let expected = "IncVal(1)\n\
+                    L0: [\n\
+                    \x20   IncPtr(1)\n\
+                    \x20   L1: [\n\
+                    \x20       DecVal(1)\n\
+                    \x20   ] -> L1\n\
+                    \x20   DecPtr(1)\n\
+                    ] -> L0\n\
+                    \n\
+                    L0: instruction 1, source 2..3\n\
+                    L1: instruction 3, source 4..5\n";
+    assert_eq!(String::from_utf8(out).unwrap(), expected);
+
+    // no table without loops, no source without spans
+    let mut out = Vec::new();
+    write_labeled(&parse("+.").unwrap(), None, &mut out).unwrap();
+    assert_eq!(out, b"IncVal(1)\nPrintCell\n");
+    let mut out = Vec::new();
+    write_labeled(&parse("[]").unwrap(), None, &mut out).unwrap();
+    assert_eq!(out, b"L0: [\n] -> L0\n\nL0: instruction 0\n");
+}
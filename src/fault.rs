@@ -0,0 +1,162 @@
+use std::cell::{Cell, UnsafeCell};
+use std::io;
+use std::mem;
+use std::ptr;
+use std::sync::Mutex;
+
+use libc;
+
+
+// Signals a bug in generated code can raise
+const SIGNALS: [libc::c_int; 3] = [libc::SIGSEGV, libc::SIGBUS, libc::SIGFPE];
+
+// A fault in generated code, `rip` is relative to the start of the code, as
+// in `--dump-jit`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fault {
+    pub signal: libc::c_int,
+    pub address: usize,
+    pub rip: usize,
+}
+
+thread_local! {
+    // Start and end of the code running on this thread under a guard, empty
+    // if there is none. Signals for faults are delivered to the faulting
+    // thread, so the handler only has to look at its own.
+    static RUNNING: Cell<(usize, usize)> = const { Cell::new((0, 0)) };
+    static FAULT: Cell<Option<Fault>> = const { Cell::new(None) };
+}
+
+// The handlers in place before ours, in the order of `SIGNALS`. Written
+// while `INSTALLED` goes from 0 to 1, before the handler is installed.
+struct Previous(UnsafeCell<Option<[libc::sigaction; 3]>>);
+
+unsafe impl Sync for Previous {}
+
+static PREVIOUS: Previous = Previous(UnsafeCell::new(None));
+// Number of guards alive on any thread, the handler is installed while
+// there are any
+static INSTALLED: Mutex<usize> = Mutex::new(0);
+
+fn restore(signal: libc::c_int) {
+    unsafe {
+        if let Some(ref previous) = *PREVIOUS.0.get() {
+            if let Some(i) = SIGNALS.iter().position(|&s| s == signal) {
+                libc::sigaction(signal, &previous[i], ptr::null_mut());
+            }
+        }
+    }
+}
+
+extern "C" fn handle_fault(signal: libc::c_int, info: *mut libc::siginfo_t, context: *mut libc::c_void) {
+    // only async-signal-safe calls in here, the thread locals are
+    // const-initialized and plain reads
+    unsafe {
+        let gregs = &mut (*(context as *mut libc::ucontext_t)).uc_mcontext.gregs;
+        let rip = gregs[libc::REG_RIP as usize] as usize;
+        let (start, end) = RUNNING.try_with(Cell::get).unwrap_or((0, 0));
+        if rip < start || rip >= end {
+            // not ours, the previous handler sees the fault once the
+            // instruction runs again
+            restore(signal);
+            return;
+        }
+
+        let address = (*info).si_addr() as usize;
+        let _ = FAULT.try_with(|fault| fault.set(Some(Fault { signal, address, rip: rip - start })));
+
+        // generated code only touches the stack around host calls, where
+        // nothing faults, so returning from it is a `ret` from wherever it
+        // faulted
+        let rsp = gregs[libc::REG_RSP as usize] as usize;
+        gregs[libc::REG_RIP as usize] = *(rsp as *const libc::greg_t);
+        gregs[libc::REG_RSP as usize] = (rsp + mem::size_of::<usize>()) as libc::greg_t;
+        gregs[libc::REG_RAX as usize] = 0;
+    }
+}
+
+// Turns faults in the code at `code` into a return from that code for as long
+// as it's alive, `take_fault` then tells what happened. Faults anywhere else
+// go to whatever handled them before. Guards on different threads don't
+// interfere, the handler is installed with the first one and the previous
+// handlers restored with the last one.
+pub struct FaultGuard {
+    outer: (usize, usize),
+}
+
+impl FaultGuard {
+    pub fn enter(code: &[u8]) -> io::Result<FaultGuard> {
+        {
+            let mut installed = INSTALLED.lock().unwrap();
+            if *installed == 0 {
+                install()?;
+            }
+            *installed += 1;
+        }
+
+        let start = code.as_ptr() as usize;
+        let outer = RUNNING.with(|running| running.replace((start, start + code.len())));
+        FAULT.with(|fault| fault.set(None));
+
+        Ok(FaultGuard { outer })
+    }
+
+    // The fault that ended the code early, if any
+    pub fn take_fault(&self) -> Option<Fault> {
+        FAULT.with(Cell::take)
+    }
+}
+
+impl Drop for FaultGuard {
+    fn drop(&mut self) {
+        RUNNING.with(|running| running.set(self.outer));
+
+        let mut installed = INSTALLED.lock().unwrap();
+        *installed -= 1;
+        if *installed == 0 {
+            for &signal in &SIGNALS {
+                restore(signal);
+            }
+        }
+    }
+}
+
+fn install() -> io::Result<()> {
+    unsafe {
+        let mut previous: [libc::sigaction; 3] = mem::zeroed();
+        for (i, &signal) in SIGNALS.iter().enumerate() {
+            if libc::sigaction(signal, ptr::null(), &mut previous[i]) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        *PREVIOUS.0.get() = Some(previous);
+
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = handle_fault as *const () as libc::sighandler_t;
+        // on the alternate stack if the thread has one, like the handler for
+        // stack overflows the standard library installs
+        action.sa_flags = libc::SA_SIGINFO | libc::SA_ONSTACK;
+        libc::sigemptyset(&mut action.sa_mask);
+        for (i, &signal) in SIGNALS.iter().enumerate() {
+            if libc::sigaction(signal, &action, ptr::null_mut()) != 0 {
+                let err = io::Error::last_os_error();
+                for &installed in &SIGNALS[..i] {
+                    restore(installed);
+                }
+                return Err(err);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Name of the signals a fault can raise, for messages
+pub fn signal_name(signal: libc::c_int) -> &'static str {
+    match signal {
+        libc::SIGSEGV => "SIGSEGV",
+        libc::SIGBUS => "SIGBUS",
+        libc::SIGFPE => "SIGFPE",
+        _ => "signal",
+    }
+}
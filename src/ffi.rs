@@ -0,0 +1,254 @@
+// The C interface, declared in include/brainfuck.h, which build.rs
+// generates from this file. What callers have to uphold is in the doc
+// comments, the only ones here as cbindgen copies them into the header.
+// Every function catches panics and reports them as `BF_PANIC`, what went
+// wrong is left for `bf_last_error_message`.
+#![allow(non_camel_case_types, clippy::missing_safety_doc)]
+
+use std::cell::RefCell;
+use std::ffi::CString;
+use std::io::{self, Read, Write};
+use std::os::raw::{c_char, c_int, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+use std::str;
+
+use brainfuck::Brainfuck;
+use interp::Interp;
+use memory::MemoryBudget;
+
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum bf_status {
+    BF_OK = 0,
+    BF_COMPILE_ERROR = 1,
+    BF_RUNTIME_ERROR = 2,
+    BF_INVALID_ARGUMENT = 3,
+    BF_PANIC = 4,
+}
+
+use self::bf_status::*;
+
+/// Zero for anything means the default
+#[repr(C)]
+pub struct bf_options {
+    pub tape_size: usize,
+    pub max_memory: usize,
+}
+
+/// `getc` returns the next byte or a negative value at the end of the input,
+/// `putc` a negative value if the byte couldn't be written. Either may be
+/// NULL, for no input and discarded output.
+#[repr(C)]
+pub struct bf_io_callbacks {
+    pub user_data: *mut c_void,
+    pub putc: Option<extern "C" fn(*mut c_void, c_int) -> c_int>,
+    pub getc: Option<extern "C" fn(*mut c_void) -> c_int>,
+}
+
+/// Opaque to C
+pub struct bf_program {
+    program: Brainfuck,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_error(message: String) {
+    // messages don't contain NULs, but a panic message might
+    let message = CString::new(message.replace('\0', " ")).unwrap();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+fn catch<F: FnOnce() -> bf_status>(f: F) -> bf_status {
+    LAST_ERROR.with(|last| *last.borrow_mut() = None);
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(status) => status,
+        Err(payload) => {
+            let message = payload.downcast_ref::<&str>().map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown".to_string());
+            set_error(format!("panic: {}", message));
+            BF_PANIC
+        }
+    }
+}
+
+struct CallbackInput<'a>(&'a bf_io_callbacks);
+
+impl<'a> Read for CallbackInput<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let getc = match self.0.getc {
+            Some(getc) if !buf.is_empty() => getc,
+            _ => return Ok(0),
+        };
+        match getc(self.0.user_data) {
+            byte if byte < 0 => Ok(0),
+            byte => {
+                buf[0] = byte as u8;
+                Ok(1)
+            }
+        }
+    }
+}
+
+struct CallbackOutput<'a>(&'a bf_io_callbacks);
+
+impl<'a> Write for CallbackOutput<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(putc) = self.0.putc {
+            for &byte in buf {
+                if putc(self.0.user_data, c_int::from(byte)) < 0 {
+                    return Err(io::Error::other("putc callback failed"));
+                }
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Compiles the `len` bytes at `src`, `*out` is only set on success and has
+/// to be freed with `bf_program_free`. `options` may be NULL.
+#[no_mangle]
+pub unsafe extern "C" fn bf_compile(
+    src: *const c_char, len: usize, options: *const bf_options, out: *mut *mut bf_program
+) -> bf_status {
+    catch(|| {
+        if (src.is_null() && len > 0) || out.is_null() {
+            set_error("src and out must not be NULL".to_string());
+            return BF_INVALID_ARGUMENT;
+        }
+        let bytes = if len == 0 { &[][..] } else { slice::from_raw_parts(src as *const u8, len) };
+        let source = match str::from_utf8(bytes) {
+            Ok(source) => source,
+            Err(e) => {
+                set_error(format!("source is not valid UTF-8: {}", e));
+                return BF_INVALID_ARGUMENT;
+            }
+        };
+
+        let mut program = match Brainfuck::new(source) {
+            Ok(program) => program,
+            Err(e) => {
+                set_error(match e.offset() {
+                    Some(offset) => format!("{} at byte {}", e, offset),
+                    None => e.to_string(),
+                });
+                return BF_COMPILE_ERROR;
+            }
+        };
+        if let Some(options) = options.as_ref() {
+            if options.tape_size != 0 {
+                if let Err(e) = program.set_tape_size(options.tape_size) {
+                    set_error(e.to_string());
+                    return BF_INVALID_ARGUMENT;
+                }
+            }
+            if options.max_memory != 0 {
+                program.set_max_memory(options.max_memory);
+            }
+        }
+
+        *out = Box::into_raw(Box::new(bf_program { program }));
+        BF_OK
+    })
+}
+
+/// Runs the program to completion. Without callbacks the generated code runs
+/// on the process' stdin and stdout. With them the program runs in the
+/// interpreter instead, as generated code can only do I/O through syscalls.
+#[no_mangle]
+pub unsafe extern "C" fn bf_run(program: *mut bf_program, io: *const bf_io_callbacks) -> bf_status {
+    catch(|| {
+        let program = match program.as_mut() {
+            Some(program) => &mut program.program,
+            None => {
+                set_error("program must not be NULL".to_string());
+                return BF_INVALID_ARGUMENT;
+            }
+        };
+
+        let result = match io.as_ref() {
+            None => program.run(),
+            Some(io) => MemoryBudget::new(program.max_memory()).charge(program.tape_size()).and_then(|_| {
+                let mut interp = Interp::new(program.insts(), program.tape_size());
+                interp.tape_mut()[..program.initial_tape().len()].copy_from_slice(program.initial_tape());
+                interp.set_ptr(program.pointer_start());
+                interp.run(CallbackInput(io), CallbackOutput(io))
+            }),
+        };
+        match result {
+            Ok(()) => BF_OK,
+            Err(e) => {
+                set_error(e.to_string());
+                BF_RUNTIME_ERROR
+            }
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn bf_program_free(program: *mut bf_program) {
+    if !program.is_null() {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(program))));
+    }
+}
+
+/// What made the last call on this thread fail, NULL if it didn't. Valid
+/// until the next call on this thread.
+#[no_mangle]
+pub extern "C" fn bf_last_error_message() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |message| message.as_ptr()))
+}
+
+
+#[cfg(test)]
+extern "C" fn push_byte(user_data: *mut c_void, byte: c_int) -> c_int {
+    unsafe { (*(user_data as *mut Vec<u8>)).push(byte as u8) };
+    byte
+}
+
+#[test]
+fn test_ffi() {
+    use std::ffi::CStr;
+
+    unsafe {
+        let source = "++++++++[>++++++++<-]>+.+.";
+        let mut program = ptr::null_mut();
+        let options = bf_options { tape_size: 2, max_memory: 0 };
+        assert_eq!(bf_compile(source.as_ptr() as *const c_char, source.len(), &options, &mut program), BF_OK);
+        assert!(bf_last_error_message().is_null());
+
+        let mut output: Vec<u8> = Vec::new();
+        let io = bf_io_callbacks {
+            user_data: &mut output as *mut Vec<u8> as *mut c_void,
+            putc: Some(push_byte),
+            getc: None,
+        };
+        assert_eq!(bf_run(program, &io), BF_OK);
+        assert_eq!(output, b"AB");
+        bf_program_free(program);
+
+        let mut program = ptr::null_mut();
+        assert_eq!(bf_compile(b"+]".as_ptr() as *const c_char, 2, ptr::null(), &mut program), BF_COMPILE_ERROR);
+        assert!(program.is_null());
+        assert_eq!(CStr::from_ptr(bf_last_error_message()).to_str().unwrap(), "unmatched ']' at byte 1");
+
+        assert_eq!(bf_run(ptr::null_mut(), ptr::null()), BF_INVALID_ARGUMENT);
+    }
+}
+
+#[test]
+fn test_ffi_panic() {
+    let status = catch(|| panic!("boom"));
+    assert_eq!(status, BF_PANIC);
+    let message = unsafe { ::std::ffi::CStr::from_ptr(bf_last_error_message()) };
+    assert_eq!(message.to_str().unwrap(), "panic: boom");
+}
@@ -0,0 +1,254 @@
+use brainfuck::{is_command, parse_with_spans, CompileError};
+use brainfuck::Inst::*;
+
+
+pub struct FormatOptions {
+    // Maximum line length including indentation, runs longer than that are
+    // never split and end up on a line of their own
+    pub width: usize,
+    pub strip_comments: bool,
+}
+
+impl Default for FormatOptions {
+    fn default() -> FormatOptions {
+        FormatOptions { width: 80, strip_comments: false }
+    }
+}
+
+const INDENT: &str = "    ";
+
+enum Item<'a> {
+    Code(String),
+    Comment(&'a str),
+    Open,
+    Close,
+}
+
+struct Layout {
+    out: String,
+    line: String,
+    depth: usize,
+    width: usize,
+}
+
+impl Layout {
+    fn available(&self) -> usize {
+        self.width.saturating_sub(self.depth * INDENT.len())
+    }
+
+    fn flush(&mut self) {
+        if !self.line.is_empty() {
+            let line = self.line.split_off(0);
+            self.own_line(&line);
+        }
+    }
+
+    fn own_line(&mut self, text: &str) {
+        for _ in 0..self.depth {
+            self.out.push_str(INDENT);
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn word(&mut self, word: &str) {
+        if !self.line.is_empty() && self.line.len() + word.len() > self.available() {
+            self.flush();
+        }
+        self.line.push_str(word);
+    }
+}
+
+fn push_code<'a>(items: &mut Vec<Item<'a>>, code: &str) {
+    if code.is_empty() {
+        return;
+    }
+    // keep runs of `.` and `,`, which parse into one instruction per
+    // character, together as a single word
+    if let Some(&mut Item::Code(ref mut last)) = items.last_mut() {
+        if last.ends_with(&code[..1]) {
+            last.push_str(code);
+            return;
+        }
+    }
+    items.push(Item::Code(code.to_string()));
+}
+
+// Lays out a program canonically: straight-line code is wrapped at the
+// configured width without splitting runs, loops are indented one level per
+// nesting depth unless they are simple enough to fit inline, and comments
+// are kept on lines of their own at the position they appeared in.
+//
+// The result always parses to the same instructions as `source`.
+pub fn format<'a>(source: &'a str, options: &FormatOptions) -> Result<String, CompileError> {
+    let (insts, spans) = parse_with_spans(source)?;
+    let mut items = Vec::new();
+
+    {
+        let push_comment = |items: &mut Vec<Item<'a>>, text: &'a str| {
+            if !options.strip_comments && !text.trim().is_empty() {
+                items.push(Item::Comment(text));
+            }
+        };
+
+        let mut last = 0;
+        for (inst, span) in insts.iter().zip(&spans) {
+            push_comment(&mut items, &source[last..span.start]);
+
+            match *inst {
+                JmpFwd(_) => items.push(Item::Open),
+                JmpBack(_) => items.push(Item::Close),
+                _ => {
+                    // comments inside a run split it in the output, which
+                    // still parses back to the same run
+                    let mut rest = &source[span.start..span.end];
+                    while !rest.is_empty() {
+                        let end = rest.find(|c| !is_command(c)).unwrap_or(rest.len());
+                        push_code(&mut items, &rest[..end]);
+                        rest = &rest[end..];
+
+                        let end = rest.find(is_command).unwrap_or(rest.len());
+                        push_comment(&mut items, &rest[..end]);
+                        rest = &rest[end..];
+                    }
+                }
+            }
+
+            last = span.end;
+        }
+        push_comment(&mut items, &source[last..]);
+    }
+
+    let mut layout = Layout {
+        out: String::new(),
+        line: String::new(),
+        depth: 0,
+        width: options.width,
+    };
+
+    let mut i = 0;
+    while i < items.len() {
+        match items[i] {
+            Item::Code(ref code) => layout.word(code),
+            Item::Comment(text) => {
+                layout.flush();
+                for line in text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+                    layout.own_line(line);
+                }
+            }
+            Item::Open => {
+                if let Some((inline, close)) = inline_loop(&items[i..]) {
+                    if inline.len() <= layout.available() {
+                        layout.word(&inline);
+                        i += close + 1;
+                        continue;
+                    }
+                }
+                layout.flush();
+                layout.own_line("[");
+                layout.depth += 1;
+            }
+            Item::Close => {
+                layout.flush();
+                layout.depth -= 1;
+                layout.own_line("]");
+            }
+        }
+        i += 1;
+    }
+    layout.flush();
+
+    Ok(layout.out)
+}
+
+// Renders a loop without nested loops or comments on a single line, returns
+// it together with the index of its closing item
+fn inline_loop(items: &[Item]) -> Option<(String, usize)> {
+    let mut inline = String::from("[");
+
+    for (i, item) in items.iter().enumerate().skip(1) {
+        match *item {
+            Item::Code(ref code) => inline.push_str(code),
+            Item::Close => {
+                inline.push(']');
+                return Some((inline, i));
+            }
+            Item::Open | Item::Comment(_) => return None,
+        }
+    }
+
+    None
+}
+
+
+#[cfg(test)]
+fn assert_round_trip(source: &str, options: &FormatOptions) -> String {
+    use brainfuck::parse;
+
+    let formatted = format(source, options).unwrap();
+    assert_eq!(
+        parse(source).unwrap(), parse(&formatted).unwrap(),
+        "formatting changed the program:\n{}", formatted
+    );
+
+    // formatting is idempotent
+    assert_eq!(formatted, format(&formatted, options).unwrap());
+
+    formatted
+}
+
+#[test]
+fn test_format() {
+    let options = FormatOptions::default();
+
+    assert_eq!(assert_round_trip("", &options), "");
+    assert_eq!(assert_round_trip("  +++ +  .. .  ", &options), "++++...\n");
+    assert_eq!(
+        assert_round_trip("set up\n++++[>++ ++[>+>++<<-]<-] >>.\nend", &options),
+        "set up\n\
+         ++++\n\
+         [\n\
+         \x20   >++++[>+>++<<-]<-\n\
+         ]\n\
+         >>.\n\
+         end\n"
+    );
+    assert_eq!(
+        assert_round_trip("+[-  a comment\n>]", &options),
+        "+\n\
+         [\n\
+         \x20   -\n\
+         \x20   a comment\n\
+         \x20   >\n\
+         ]\n"
+    );
+}
+
+#[test]
+fn test_format_strip_comments() {
+    let options = FormatOptions { width: 80, strip_comments: true };
+
+    assert_eq!(assert_round_trip("++ two more ++ [-] done", &options), "++++[-]\n");
+    assert_eq!(assert_round_trip("[\n  comment loop: +-<>.,\n]", &options), "[+-<>.,]\n");
+}
+
+#[test]
+fn test_format_width() {
+    let options = FormatOptions { width: 8, strip_comments: true };
+
+    assert_eq!(assert_round_trip("+++>>>---<<<", &options), "+++>>>\n---<<<\n");
+    // runs are never split, even when they don't fit
+    assert_eq!(assert_round_trip("+++++++++++>", &options), "+++++++++++\n>\n");
+    assert_eq!(
+        assert_round_trip("[[->+<]>>>>>>>]", &FormatOptions { width: 10, strip_comments: true }),
+        "[\n\
+         \x20   [->+<]\n\
+         \x20   >>>>>>>\n\
+         ]\n"
+    );
+
+    let hello = include_str!("../tests/fixtures/hello.b");
+    for &width in &[1, 10, 40, 80] {
+        assert_round_trip(hello, &FormatOptions { width, strip_comments: false });
+    }
+}
@@ -0,0 +1,400 @@
+// Random programs for property tests, fuzzing and benchmarks, always the
+// same ones for the same seed and settings, so that a failure can be
+// reproduced from the seed it reports.
+//
+// Programs are generated as trees of `Node`s, which property tests can
+// shrink, and lowered to source by `source`. Brackets always match. Those
+// of `generate_terminating` also end: the pointer stays on a tape of
+// `tape_size` cells, and a loop's body returns to the cell it started on
+// and decrements it at its end, without touching it otherwise. Every loop
+// thus goes round at most 255 times, see `step_bound`.
+
+pub const DEFAULT_MAX_LEN: usize = 64;
+pub const DEFAULT_MAX_DEPTH: usize = 2;
+pub const DEFAULT_LOOP_BIAS: f64 = 0.25;
+pub const DEFAULT_TAPE_SIZE: usize = 16;
+
+// Nodes in a loop's body before the generator closes it
+const MAX_BODY: usize = 8;
+// The longest run of one command
+const MAX_RUN: usize = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node {
+    Move(isize),
+    Add(usize),
+    Sub(usize),
+    Print,
+    Read,
+    Loop(Vec<Node>),
+}
+
+// xorshift64, enough to spread programs around and reproducible from a seed
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    // xorshift gets stuck on 0
+    pub fn new(seed: u64) -> Rng {
+        Rng(if seed == 0 { 0x5eed } else { seed })
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    // true with probability `p`
+    pub fn chance(&mut self, p: f64) -> bool {
+        ((self.next_u64() >> 11) as f64 / (1u64 << 53) as f64) < p
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ProgramGenerator {
+    rng: Rng,
+    max_len: usize,
+    max_depth: usize,
+    loop_bias: f64,
+    io: bool,
+    tape_size: usize,
+}
+
+// Where a block is being generated
+struct Context {
+    // commands left for the rest of the program
+    budget: usize,
+    depth: usize,
+    terminating: bool,
+    ptr: usize,
+    // the cells of the enclosing loops, which only they may change
+    protected: Vec<usize>,
+}
+
+impl ProgramGenerator {
+    pub fn new(seed: u64) -> ProgramGenerator {
+        ProgramGenerator {
+            rng: Rng::new(seed),
+            max_len: DEFAULT_MAX_LEN,
+            max_depth: DEFAULT_MAX_DEPTH,
+            loop_bias: DEFAULT_LOOP_BIAS,
+            io: true,
+            tape_size: DEFAULT_TAPE_SIZE,
+        }
+    }
+
+    // Commands in a program at most, comments aside
+    pub fn max_len(mut self, max_len: usize) -> ProgramGenerator {
+        self.max_len = max_len;
+        self
+    }
+
+    // Loops nested in each other at most
+    pub fn max_depth(mut self, max_depth: usize) -> ProgramGenerator {
+        self.max_depth = max_depth;
+        self
+    }
+
+    // How likely each node is a loop where one may go, from 0 to 1
+    pub fn loop_bias(mut self, loop_bias: f64) -> ProgramGenerator {
+        self.loop_bias = loop_bias;
+        self
+    }
+
+    // Whether programs have `.` and `,`
+    pub fn io(mut self, io: bool) -> ProgramGenerator {
+        self.io = io;
+        self
+    }
+
+    // The tape the pointer of `generate_terminating` stays on
+    pub fn tape_size(mut self, tape_size: usize) -> ProgramGenerator {
+        assert!(tape_size > 0, "a tape needs cells");
+        self.tape_size = tape_size;
+        self
+    }
+
+    pub fn generate(&mut self) -> String {
+        source(&self.nodes())
+    }
+
+    // A program that ends within `step_bound` steps
+    pub fn generate_terminating(&mut self) -> String {
+        source(&self.terminating_nodes())
+    }
+
+    pub fn nodes(&mut self) -> Vec<Node> {
+        self.program(false)
+    }
+
+    pub fn terminating_nodes(&mut self) -> Vec<Node> {
+        self.program(true)
+    }
+
+    // Input for a program, up to `max_len` bytes
+    pub fn input(&mut self, max_len: usize) -> Vec<u8> {
+        (0..self.rng.below(max_len + 1)).map(|_| self.rng.next_u64() as u8).collect()
+    }
+
+    // Steps a program of `generate_terminating` takes at most in the
+    // interpreter: every command runs at most 255 times per loop around it
+    pub fn step_bound(&self) -> u64 {
+        (self.max_len as u64).saturating_mul(255u64.saturating_pow(self.max_depth as u32))
+    }
+
+    fn program(&mut self, terminating: bool) -> Vec<Node> {
+        let budget = self.rng.below(self.max_len + 1);
+        let mut context = Context { budget, depth: 0, terminating, ptr: 0, protected: Vec::new() };
+        let mut nodes = Vec::new();
+        while context.budget > 0 && self.node(&mut context, &mut nodes) {}
+
+        nodes
+    }
+
+    // A loop's body, with up to `MAX_BODY` nodes
+    fn body(&mut self, context: &mut Context) -> Vec<Node> {
+        let mut nodes = Vec::new();
+        for _ in 0..self.rng.below(MAX_BODY) {
+            if context.budget == 0 || !self.node(context, &mut nodes) {
+                break;
+            }
+        }
+
+        nodes
+    }
+
+    // Adds the next node to `nodes`, false if none fits into what's left
+    fn node(&mut self, context: &mut Context, nodes: &mut Vec<Node>) -> bool {
+        let writable = !context.protected.contains(&context.ptr);
+        // what a loop takes besides its body: its brackets, and in a
+        // terminating one what sets its cell, the `-` and moves back to the
+        // cell reserved for the worst case
+        let overhead = if context.terminating { MAX_RUN + 3 + (self.tape_size - 1) } else { 2 };
+        if context.depth < self.max_depth && writable && context.budget >= overhead
+            && self.rng.chance(self.loop_bias)
+        {
+            nodes.extend(self.loop_node(context, overhead));
+            return true;
+        }
+
+        // terminating programs move to any cell as far as the budget goes
+        let (nearest, farthest) = (
+            context.ptr.saturating_sub(context.budget),
+            (context.ptr + context.budget).min(self.tape_size - 1),
+        );
+        let mut kinds = Vec::with_capacity(8);
+        if !context.terminating || farthest > nearest {
+            kinds.extend_from_slice(&[0, 0]);
+        }
+        if writable {
+            kinds.extend_from_slice(&[2, 2, 3, 3]);
+        }
+        if self.io {
+            kinds.push(4);
+            if writable {
+                kinds.push(5);
+            }
+        }
+        if kinds.is_empty() {
+            return false;
+        }
+
+        let run = self.rng.below(MAX_RUN.min(context.budget)) + 1;
+        let node = match kinds[self.rng.below(kinds.len())] {
+            0 if context.terminating => {
+                let mut target = nearest + self.rng.below(farthest - nearest);
+                if target >= context.ptr {
+                    target += 1;
+                }
+                let node = Node::Move(target as isize - context.ptr as isize);
+                context.ptr = target;
+                node
+            }
+            0 if self.rng.chance(0.5) => Node::Move(run as isize),
+            0 => Node::Move(-(run as isize)),
+            2 => Node::Add(run),
+            3 => Node::Sub(run),
+            4 => Node::Print,
+            _ => Node::Read,
+        };
+        context.budget -= match node {
+            Node::Move(n) => n.unsigned_abs(),
+            Node::Add(n) | Node::Sub(n) => n,
+            _ => 1,
+        };
+        nodes.push(node);
+
+        true
+    }
+
+    // A loop, in a terminating program preceded by what sets its cell
+    fn loop_node(&mut self, context: &mut Context, overhead: usize) -> Vec<Node> {
+        context.budget -= overhead;
+        context.depth += 1;
+        if !context.terminating {
+            let body = self.body(context);
+            context.depth -= 1;
+            return vec![Node::Loop(body)];
+        }
+
+        // a loop mostly runs when its cell was just set
+        let set = self.rng.below(MAX_RUN) + 1;
+        let start = context.ptr;
+        context.protected.push(start);
+        let mut body = self.body(context);
+        let back = start as isize - context.ptr as isize;
+        if back != 0 {
+            body.push(Node::Move(back));
+        }
+        body.push(Node::Sub(1));
+        context.budget += (MAX_RUN - set) + (self.tape_size - 1) - back.unsigned_abs();
+        context.ptr = start;
+        context.protected.pop();
+        context.depth -= 1;
+
+        vec![Node::Add(set), Node::Loop(body)]
+    }
+}
+
+// Whether `nodes` are as `generate_terminating` makes them for a tape of
+// `tape_size` cells, with loops nested up to `max_depth` deep: the pointer
+// stays on the tape and every loop ends with a `-` on the cell it started
+// on, which nothing else in it changes
+pub fn terminates(nodes: &[Node], tape_size: usize, max_depth: usize) -> bool {
+    fn check(nodes: &[Node], tape_size: usize, max_depth: usize, ptr: &mut isize, protected: &mut Vec<isize>) -> bool {
+        for node in nodes {
+            match *node {
+                Node::Move(n) => {
+                    *ptr += n;
+                    if *ptr < 0 || *ptr >= tape_size as isize {
+                        return false;
+                    }
+                }
+                Node::Add(_) | Node::Sub(_) | Node::Read if protected.contains(ptr) => return false,
+                Node::Loop(ref body) => {
+                    let start = *ptr;
+                    let (last, rest) = match body.split_last() {
+                        Some((&Node::Sub(1), rest)) => (start, rest),
+                        _ => return false,
+                    };
+                    if protected.len() >= max_depth || protected.contains(&start) {
+                        return false;
+                    }
+                    protected.push(start);
+                    let ok = check(rest, tape_size, max_depth, ptr, protected) && *ptr == last;
+                    protected.pop();
+                    if !ok {
+                        return false;
+                    }
+                }
+                _ => {}
+            }
+        }
+        true
+    }
+
+    check(nodes, tape_size, max_depth, &mut 0, &mut Vec::new())
+}
+
+// The source of `nodes`, without comments
+pub fn source(nodes: &[Node]) -> String {
+    let mut source = String::new();
+    for node in nodes {
+        match *node {
+            Node::Move(n) if n < 0 => source.push_str(&"<".repeat(-n as usize)),
+            Node::Move(n) => source.push_str(&">".repeat(n as usize)),
+            Node::Add(n) => source.push_str(&"+".repeat(n)),
+            Node::Sub(n) => source.push_str(&"-".repeat(n)),
+            Node::Print => source.push('.'),
+            Node::Read => source.push(','),
+            Node::Loop(ref body) => {
+                source.push('[');
+                source.push_str(&self::source(body));
+                source.push(']');
+            }
+        }
+    }
+    source
+}
+
+#[cfg(test)]
+use interp::{Interp, StepOutcome};
+
+#[cfg(test)]
+fn depth(nodes: &[Node]) -> usize {
+    nodes.iter().map(|node| match *node {
+        Node::Loop(ref body) => 1 + depth(body),
+        _ => 0,
+    }).max().unwrap_or(0)
+}
+
+#[test]
+fn test_deterministic() {
+    let programs = |seed| {
+        let mut generator = ProgramGenerator::new(seed);
+        (0..20).map(|_| (generator.generate(), generator.generate_terminating())).collect::<Vec<_>>()
+    };
+    assert_eq!(programs(42), programs(42));
+    assert_ne!(programs(42), programs(43));
+    assert_eq!(ProgramGenerator::new(7).input(16), ProgramGenerator::new(7).input(16));
+}
+
+#[test]
+fn test_generate() {
+    for seed in 0..500 {
+        let mut generator = ProgramGenerator::new(seed).max_len(48).max_depth(3).loop_bias(0.4);
+        let nodes = generator.nodes();
+        let program = source(&nodes);
+        assert!(program.len() <= 48, "{}", program);
+        assert!(depth(&nodes) <= 3, "{}", program);
+        let mut open = 0;
+        for c in program.chars() {
+            match c {
+                '[' => open += 1,
+                ']' => open -= 1,
+                _ => {}
+            }
+            assert!(open >= 0, "{}", program);
+        }
+        assert_eq!(open, 0, "{}", program);
+
+        let mut generator = ProgramGenerator::new(seed).io(false).loop_bias(1.0);
+        let program = generator.generate() + &generator.generate_terminating();
+        assert!(!program.contains('.') && !program.contains(','), "{}", program);
+    }
+}
+
+#[test]
+fn test_generate_terminating() {
+    let mut loops = 0;
+    for seed in 0..300 {
+        for &(tape_size, max_depth) in &[(DEFAULT_TAPE_SIZE, DEFAULT_MAX_DEPTH), (1, 1), (4, 3)] {
+            let mut generator = ProgramGenerator::new(seed).tape_size(tape_size).max_depth(max_depth).loop_bias(0.5);
+            let nodes = generator.terminating_nodes();
+            let program = source(&nodes);
+            assert!(program.len() <= DEFAULT_MAX_LEN, "{}", program);
+            assert!(terminates(&nodes, tape_size, max_depth), "{}", program);
+            loops += program.matches('[').count();
+
+            let input = generator.input(8);
+            let insts = ::brainfuck::parse(&program).unwrap();
+            let mut interp = Interp::new(&insts, tape_size);
+            let outcome = interp.run_for(generator.step_bound() as usize, &mut &input[..], &mut Vec::new());
+            assert!(matches!(outcome, StepOutcome::Finished), "{}", program);
+        }
+    }
+    assert!(loops > 300, "{}", loops);
+
+    assert!(terminates(&[Node::Add(2), Node::Loop(vec![Node::Move(1), Node::Print, Node::Move(-1), Node::Sub(1)])], 2, 1));
+    // a loop not ending on its cell, one changing it elsewhere, nested too deep
+    assert!(!terminates(&[Node::Loop(vec![Node::Move(1), Node::Sub(1)])], 2, 1));
+    assert!(!terminates(&[Node::Loop(vec![Node::Read, Node::Sub(1)])], 2, 1));
+    assert!(!terminates(&[Node::Loop(vec![Node::Move(1), Node::Loop(vec![Node::Sub(1)]), Node::Move(-1), Node::Sub(1)])], 2, 1));
+    assert!(!terminates(&[Node::Move(-1)], 2, 1));
+}
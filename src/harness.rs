@@ -0,0 +1,277 @@
+// A test runner for brainfuck programs, for `brainfuck-jit test`. A test
+// `name` in a directory is name.b, printing name.expected, with name.in as
+// its input if there is one. Programs without an .expected file are left
+// alone. What a program prints is compared byte for byte, a failure shows
+// the difference as a unified diff of the lines.
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use brainfuck::{Brainfuck, RuntimeError};
+use interp::{Interp, StepOutcome};
+
+
+pub const DEFAULT_STEPS: usize = 100_000_000;
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// How many steps the interpreter takes between looking at the clock
+const STEPS_PER_CHECK: usize = 1 << 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+    Interpreter,
+    Jit,
+    // both, which also have to agree with each other
+    Both,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct HarnessOptions {
+    pub engine: Engine,
+    // steps the interpreter may take, generated code doesn't count them
+    pub steps: usize,
+    // how long either engine may run a program
+    pub timeout: Duration,
+    // tests running at the same time
+    pub jobs: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestCase {
+    pub name: String,
+    pub program: PathBuf,
+    pub input: Option<PathBuf>,
+    pub expected: PathBuf,
+}
+
+// The tests in `dir`, by name
+pub fn discover(dir: &Path) -> io::Result<Vec<TestCase>> {
+    let mut cases = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let program = entry?.path();
+        if program.extension().is_none_or(|extension| extension != "b") {
+            continue;
+        }
+        let expected = program.with_extension("expected");
+        if !expected.is_file() {
+            continue;
+        }
+        let input = Some(program.with_extension("in")).filter(|input| input.is_file());
+        let name = program.file_stem().unwrap().to_string_lossy().into_owned();
+        cases.push(TestCase { name, program, input, expected });
+    }
+    cases.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(cases)
+}
+
+// Runs `case`, on failure what went wrong
+pub fn run_case(case: &TestCase, options: &HarnessOptions) -> Result<(), String> {
+    let source = fs::read_to_string(&case.program).map_err(|e| format!("{}: {}", case.program.display(), e))?;
+    let input = match case.input {
+        Some(ref path) => fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?,
+        None => Vec::new(),
+    };
+    let expected = fs::read(&case.expected).map_err(|e| format!("{}: {}", case.expected.display(), e))?;
+    let bf = Brainfuck::new(&source).map_err(|e| e.to_string())?;
+
+    let check = |engine: &str, output: Vec<u8>| -> Result<Vec<u8>, String> {
+        if output == expected {
+            Ok(output)
+        } else {
+            Err(format!("the {} printed something else\n{}", engine, unified_diff("expected", &expected, "actual", &output)))
+        }
+    };
+    match options.engine {
+        Engine::Interpreter => check("interpreter", interpret(&bf, &input, options)?).map(|_| ()),
+        Engine::Jit => check("generated code", jit(&bf, &input, options)?).map(|_| ()),
+        Engine::Both => {
+            let (interpreted, jitted) = (interpret(&bf, &input, options), jit(&bf, &input, options));
+            match (interpreted, jitted) {
+                (Ok(interpreted), Ok(jitted)) if interpreted != jitted => Err(format!(
+                    "the interpreter and generated code printed different things\n{}",
+                    unified_diff("interpreter", &interpreted, "generated code", &jitted)
+                )),
+                (Ok(interpreted), Ok(_)) => check("interpreter and generated code", interpreted).map(|_| ()),
+                (Err(e), Ok(_)) => Err(format!("only the interpreter failed: {}", e)),
+                (Ok(_), Err(e)) => Err(format!("only the generated code failed: {}", e)),
+                (Err(e), Err(_)) => Err(e),
+            }
+        }
+    }
+}
+
+// Runs every case on `options.jobs` threads, the results in the order of
+// the cases
+pub fn run_all(cases: &[TestCase], options: &HarnessOptions) -> Vec<Result<(), String>> {
+    let results = Mutex::new(vec![None; cases.len()]);
+    let next = AtomicUsize::new(0);
+
+    thread::scope(|scope| {
+        for _ in 0..options.jobs.clamp(1, cases.len().max(1)) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                let case = match cases.get(i) {
+                    Some(case) => case,
+                    None => break,
+                };
+                let result = run_case(case, options);
+                results.lock().unwrap()[i] = Some(result);
+            });
+        }
+    });
+
+    results.into_inner().unwrap().into_iter().map(Option::unwrap).collect()
+}
+
+fn interpret(bf: &Brainfuck, input: &[u8], options: &HarnessOptions) -> Result<Vec<u8>, String> {
+    let mut output = Vec::new();
+    let mut interp = Interp::new(bf.insts(), bf.tape_size());
+    let mut input = input;
+    let started = Instant::now();
+    let mut remaining = options.steps;
+
+    loop {
+        let steps = remaining.min(STEPS_PER_CHECK);
+        match interp.run_for(steps, &mut input, &mut output) {
+            StepOutcome::Finished => return Ok(output),
+            StepOutcome::Paused => {}
+            StepOutcome::NeedsInput => return Err("the interpreter is waiting for input".to_string()),
+            StepOutcome::Error(e) => return Err(format!("the interpreter failed: {}", e)),
+        }
+        remaining -= steps;
+        if remaining == 0 {
+            return Err(format!("the interpreter is still running after {} steps", options.steps));
+        }
+        if started.elapsed() >= options.timeout {
+            return Err(format!("the interpreter is still running after {:?}", options.timeout));
+        }
+    }
+}
+
+fn jit(bf: &Brainfuck, input: &[u8], options: &HarnessOptions) -> Result<Vec<u8>, String> {
+    let program = bf.share().map_err(|e| e.to_string())?;
+    let (cancel, done) = (AtomicBool::new(false), AtomicBool::new(false));
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            let started = Instant::now();
+            while !done.load(Ordering::SeqCst) && started.elapsed() < options.timeout {
+                thread::sleep(Duration::from_millis(5));
+            }
+            cancel.store(true, Ordering::SeqCst);
+        });
+        let result = program.run_cancellable(input, &cancel);
+        done.store(true, Ordering::SeqCst);
+        result.map(|execution| execution.output).map_err(|e| match e {
+            RuntimeError::Cancelled => format!("the generated code is still running after {:?}", options.timeout),
+            e => format!("the generated code failed: {}", e),
+        })
+    })
+}
+
+// The lines of `old` and `new` that differ, with up to 3 lines around them,
+// in the format of `diff -u` with the names of the two in the header
+pub fn unified_diff(old_name: &str, old: &[u8], new_name: &str, new: &[u8]) -> String {
+    const CONTEXT: usize = 3;
+
+    let old: Vec<&[u8]> = old.split_inclusive(|&byte| byte == b'\n').collect();
+    let new: Vec<&[u8]> = new.split_inclusive(|&byte| byte == b'\n').collect();
+
+    // longest common subsequence from every pair of positions on
+    let mut lcs = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+        }
+    }
+    // the edit script, ' ', '-' or '+' with the line and where it is in
+    // `old` and `new`
+    let mut edits = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            edits.push((' ', old[i], i, j));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            edits.push(('-', old[i], i, j));
+            i += 1;
+        } else {
+            edits.push(('+', new[j], i, j));
+            j += 1;
+        }
+    }
+
+    let mut out = format!("--- {}\n+++ {}\n", old_name, new_name);
+    let mut k = 0;
+    while let Some(first) = edits[k..].iter().position(|edit| edit.0 != ' ').map(|first| k + first) {
+        // the hunk goes on while changes are less than two contexts apart
+        let start = first.saturating_sub(CONTEXT);
+        let mut end = first;
+        while let Some(next) = edits[end + 1..].iter().position(|edit| edit.0 != ' ') {
+            if next > 2 * CONTEXT {
+                break;
+            }
+            end += 1 + next;
+        }
+        let end = (end + 1 + CONTEXT).min(edits.len());
+
+        let hunk = &edits[start..end];
+        let old_len = hunk.iter().filter(|edit| edit.0 != '+').count();
+        let new_len = hunk.iter().filter(|edit| edit.0 != '-').count();
+        let (_, _, old_start, new_start) = hunk[0];
+        out.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            range(old_start, old_len), range(new_start, new_len)
+        ));
+        for &(kind, line, _, _) in hunk {
+            out.push(kind);
+            out.push_str(&String::from_utf8_lossy(line));
+            if !line.ends_with(b"\n") {
+                out.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+        k = end;
+    }
+
+    out
+}
+
+// A hunk's range of lines, `start` counted from 0
+fn range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, len),
+    }
+}
+
+
+#[test]
+fn test_unified_diff() {
+    let diff = |old: &str, new: &str| unified_diff("expected", old.as_bytes(), "actual", new.as_bytes());
+
+    assert_eq!(diff("a\nb\n", "a\nb\n"), "--- expected\n+++ actual\n");
+    assert_eq!(
+        diff("1\n2\n3\n4\n5\n6\n7\n8\n9\n", "1\n2\n3\n4\nfive\n6\n7\n8\n9\n"),
+        "--- expected\n+++ actual\n@@ -2,7 +2,7 @@\n 2\n 3\n 4\n-5\n+five\n 6\n 7\n 8\n"
+    );
+    assert_eq!(
+        diff("Hello", "Hello\nWorld\n"),
+        "--- expected\n+++ actual\n@@ -1 +1,2 @@\n-Hello\n\\ No newline at end of file\n+Hello\n+World\n"
+    );
+    assert_eq!(diff("", "x\n"), "--- expected\n+++ actual\n@@ -0,0 +1 @@\n+x\n");
+
+    // changes far apart get hunks of their own
+    let old: String = (1..=20).map(|i| format!("{}\n", i)).collect();
+    let new = old.replacen("2\n", "two\n", 1).replace("\n19\n", "\nnineteen\n");
+    let hunks = diff(&old, &new);
+    assert_eq!(hunks.matches("@@ -").count(), 2, "{}", hunks);
+    assert!(hunks.contains("@@ -1,5 +1,5 @@\n 1\n-2\n+two\n 3\n 4\n 5\n"), "{}", hunks);
+    assert!(hunks.contains("@@ -16,5 +16,5 @@\n 16\n 17\n 18\n-19\n+nineteen\n 20\n"), "{}", hunks);
+}
@@ -0,0 +1,171 @@
+use std::fmt::Write;
+
+
+// Background colors from the xterm 256-color palette, from never executed
+// over cold to hot
+const PALETTE: [u8; 8] = [236, 21, 33, 51, 46, 226, 208, 196];
+
+// How a character of the source is drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Style {
+    // not a command
+    Dim,
+    Bucket(usize),
+}
+
+// Index into `PALETTE` for a command that executed `count` times, where the
+// hottest one executed `max` times. Zero gets its own bucket, the others are
+// spread on a log scale, so a loop running a million times doesn't flatten
+// everything else.
+pub fn bucket(count: u64, max: u64) -> usize {
+    if count == 0 {
+        return 0;
+    }
+    let fraction = ((count + 1) as f64).ln() / ((max.max(count) + 1) as f64).ln();
+    1 + (fraction * (PALETTE.len() - 2) as f64).floor() as usize
+}
+
+fn styles(per_byte: &[Option<u64>]) -> Vec<Style> {
+    let max = per_byte.iter().filter_map(|&count| count).max().unwrap_or(0);
+    per_byte.iter()
+        .map(|count| match *count {
+            Some(count) => Style::Bucket(bucket(count, max)),
+            None => Style::Dim,
+        })
+        .collect()
+}
+
+// The source with each command on the background color of its bucket and
+// everything else dimmed, for terminals
+pub fn to_ansi(source: &str, per_byte: &[Option<u64>]) -> String {
+    let styles = styles(per_byte);
+    let mut out = String::new();
+    let mut current = None;
+
+    for (i, c) in source.char_indices() {
+        if c == '\n' {
+            if current.take().is_some() {
+                out.push_str("\x1b[0m");
+            }
+            out.push('\n');
+            continue;
+        }
+        if current != Some(styles[i]) {
+            match styles[i] {
+                Style::Dim => out.push_str("\x1b[0;2m"),
+                Style::Bucket(0) => write!(out, "\x1b[0;48;5;{}m", PALETTE[0]).unwrap(),
+                // dark text stays readable on the bright colors
+                Style::Bucket(n) => write!(out, "\x1b[0;30;48;5;{}m", PALETTE[n]).unwrap(),
+            }
+            current = Some(styles[i]);
+        }
+        out.push(c);
+    }
+    if current.is_some() {
+        out.push_str("\x1b[0m");
+    }
+
+    out
+}
+
+// RGB value of a color in the xterm 256-color palette, for the non-standard
+// ones, 16 and up
+fn xterm_rgb(color: u8) -> (u8, u8, u8) {
+    const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    match color {
+        16..=231 => {
+            let i = (color - 16) as usize;
+            (LEVELS[i / 36], LEVELS[i / 6 % 6], LEVELS[i % 6])
+        }
+        232..=255 => {
+            let grey = 8 + 10 * (color - 232);
+            (grey, grey, grey)
+        }
+        _ => panic!("color {} depends on the terminal", color),
+    }
+}
+
+// A standalone HTML page with the same colors as `to_ansi`
+pub fn to_html(title: &str, source: &str, per_byte: &[Option<u64>]) -> String {
+    let styles = styles(per_byte);
+    let mut out = String::new();
+
+    out.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    writeln!(out, "<title>{}</title>", escape(title)).unwrap();
+    out.push_str("<style>\n");
+    out.push_str("body { background: #1c1c1c; color: #d0d0d0; }\n");
+    out.push_str(".d { opacity: 0.5; }\n");
+    for (n, &color) in PALETTE.iter().enumerate() {
+        let (r, g, b) = xterm_rgb(color);
+        let text = if n == 0 { "inherit" } else { "#000" };
+        writeln!(out, ".b{} {{ background: #{:02x}{:02x}{:02x}; color: {}; }}", n, r, g, b, text).unwrap();
+    }
+    out.push_str("</style>\n</head>\n<body>\n<pre>");
+
+    let mut start = 0;
+    let chars: Vec<(usize, char)> = source.char_indices().collect();
+    while start < chars.len() {
+        let style = styles[chars[start].0];
+        let end = chars[start..].iter()
+            .position(|&(i, _)| styles[i] != style)
+            .map_or(chars.len(), |n| start + n);
+        let text: String = chars[start..end].iter().map(|&(_, c)| c).collect();
+
+        match style {
+            Style::Dim => write!(out, "<span class=\"d\">{}</span>", escape(&text)).unwrap(),
+            Style::Bucket(n) => write!(out, "<span class=\"b{}\">{}</span>", n, escape(&text)).unwrap(),
+        }
+        start = end;
+    }
+
+    out.push_str("</pre>\n</body>\n</html>\n");
+    out
+}
+
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+
+#[test]
+fn test_bucket() {
+    assert_eq!(bucket(0, 0), 0);
+    assert_eq!(bucket(0, 1000), 0);
+    assert_eq!(bucket(1, 1), 7);
+    assert_eq!(bucket(1000, 1000), 7);
+    // log scale: a tenth of the hottest count is still in the upper half
+    assert_eq!(bucket(100, 1000), 5);
+    assert_eq!(bucket(10, 1000), 3);
+    assert_eq!(bucket(1, 1000), 1);
+    assert_eq!(bucket(1, 1_000_000), 1);
+}
+
+#[test]
+fn test_xterm_rgb() {
+    assert_eq!(xterm_rgb(16), (0, 0, 0));
+    assert_eq!(xterm_rgb(21), (0, 0, 255));
+    assert_eq!(xterm_rgb(208), (255, 135, 0));
+    assert_eq!(xterm_rgb(236), (48, 48, 48));
+}
+
+#[test]
+fn test_to_ansi() {
+    let per_byte = [Some(1), Some(1), None, Some(0), None];
+    assert_eq!(
+        to_ansi("++ -\n", &per_byte),
+        "\x1b[0;30;48;5;196m++\x1b[0;2m \x1b[0;48;5;236m-\x1b[0m\n"
+    );
+    assert_eq!(to_ansi("", &[]), "");
+}
+
+#[test]
+fn test_to_html() {
+    let per_byte = [Some(3), None, None, Some(1), Some(0)];
+    let html = to_html("a<b>.b", "> x+-", &per_byte);
+    assert!(html.contains("<title>a&lt;b&gt;.b</title>"));
+    assert!(html.contains(".b0 { background: #303030; color: inherit; }"));
+    assert!(html.contains(
+        "<pre><span class=\"b7\">&gt;</span><span class=\"d\"> x</span>\
+         <span class=\"b4\">+</span><span class=\"b0\">-</span></pre>"
+    ));
+}
@@ -0,0 +1,67 @@
+// Functions of the program embedding brainfuck, which the brainfuck program
+// calls with `Inst::HostCall`. The cell the pointer is on picks which one
+// runs, by the number it was registered with, and the function gets the
+// whole tape with the pointer to do with as it pleases.
+use std::collections::HashMap;
+
+use brainfuck::RuntimeError;
+
+
+// A host function, called with the tape and the index of the cell the
+// pointer is on. An error ends the run with it.
+pub type HostFn<'h> = dyn FnMut(&mut [u8], usize) -> Result<(), RuntimeError> + 'h;
+
+#[derive(Default)]
+pub struct HostFunctions<'h> {
+    functions: HashMap<u8, Box<HostFn<'h>>>,
+}
+
+impl<'h> HostFunctions<'h> {
+    pub fn new() -> HostFunctions<'h> {
+        HostFunctions::default()
+    }
+
+    // Runs `function` for host calls on a cell holding `id`, instead of
+    // whatever was registered for it before
+    pub fn register<F>(&mut self, id: u8, function: F)
+        where F: FnMut(&mut [u8], usize) -> Result<(), RuntimeError> + 'h
+    {
+        self.functions.insert(id, Box::new(function));
+    }
+
+    pub fn get_mut(&mut self, id: u8) -> Option<&mut HostFn<'h>> {
+        self.functions.get_mut(&id).map(|function| &mut **function)
+    }
+
+    // Runs the function the cell at `ptr` selects for the host call at
+    // instruction `inst_index`, which fails if nothing is registered for it
+    pub fn call(&mut self, tape: &mut [u8], ptr: usize, inst_index: usize) -> Result<(), RuntimeError> {
+        let id = tape[ptr];
+        match self.get_mut(id) {
+            Some(function) => function(tape, ptr),
+            None => Err(RuntimeError::UnregisteredHostCall { id, inst_index }),
+        }
+    }
+}
+
+
+#[test]
+fn test_host_functions() {
+    let mut calls = Vec::new();
+    {
+        let mut host = HostFunctions::new();
+        host.register(1, |tape: &mut [u8], ptr| {
+            calls.push(ptr);
+            tape[ptr + 1] = 7;
+            Ok(())
+        });
+        let mut tape = [0, 1, 0];
+        host.call(&mut tape, 1, 4).unwrap();
+        assert_eq!(tape, [0, 1, 7]);
+        match host.call(&mut tape, 2, 4) {
+            Err(RuntimeError::UnregisteredHostCall { id: 7, inst_index: 4 }) => {}
+            result => panic!("{:?}", result),
+        }
+    }
+    assert_eq!(calls, [1]);
+}
@@ -0,0 +1,269 @@
+// A page going through a run step by step, for `--report-html`: the source
+// with the next instruction marked, the cells around the pointer and what
+// was printed so far, at whichever step a slider is on. The run is in the
+// page as JSON and a script in it replays it, so the page is one file that
+// needs nothing else. It holds every step, so only short runs get one.
+use std::fmt::Write;
+use std::io::Read;
+
+use brainfuck::{parse_with_spans, to_source, Brainfuck, RuntimeError, Span};
+use diagnostics::{json_string, ErrorCode};
+use heatmap::escape;
+use interp::Step;
+
+
+// The most steps a run can take to get a report
+pub const MAX_STEPS: usize = 50_000;
+
+// A run of a program, up to where it failed if it did
+pub struct Run<'a> {
+    pub steps: Vec<Step<'a>>,
+    pub error: Option<RuntimeError>,
+    // the tape and where the pointer was when the run ended
+    pub tape: Vec<u8>,
+    pub ptr: usize,
+}
+
+impl<'a> Run<'a> {
+    // What the run printed
+    pub fn output(&self) -> Vec<u8> {
+        self.steps.iter().filter_map(|step| step.output).collect()
+    }
+}
+
+// Runs `bf` in the interpreter on `input`, `None` if that takes more than
+// `MAX_STEPS` steps
+pub fn record<R: Read>(bf: &Brainfuck, input: R) -> Option<Run<'_>> {
+    let mut steps = bf.steps(input);
+    let mut run = Run { steps: Vec::new(), error: None, tape: Vec::new(), ptr: 0 };
+    for step in &mut steps {
+        match step {
+            Ok(_) if run.steps.len() == MAX_STEPS => return None,
+            Ok(step) => run.steps.push(step),
+            Err(e) => run.error = Some(e),
+        }
+    }
+    run.tape = steps.interp().tape().to_vec();
+    run.ptr = steps.interp().ptr();
+    Some(run)
+}
+
+// The run as the page embeds it: the tape it started with and every step
+// with the fields of `trace`, the cell being the value it was left with
+fn to_json(bf: &Brainfuck, run: &Run) -> String {
+    let mut out = String::new();
+    write!(
+        out, "{{\"tape_size\":{},\"pointer_start\":{},\"initial_tape\":{:?},\"ptr\":{},\"steps\":[",
+        bf.tape_size(), bf.pointer_start(), bf.initial_tape(), run.ptr
+    ).unwrap();
+    for (n, step) in run.steps.iter().enumerate() {
+        let separator = if n == 0 { "" } else { "," };
+        write!(
+            out, "{}\n{{\"index\":{},\"op\":{},\"ptr\":{},\"cell\":{}",
+            separator, step.index, json_string(&format!("{:?}", step.inst)), step.ptr, step.cell_after
+        ).unwrap();
+        if let Some(byte) = step.output {
+            write!(out, ",\"output\":{}", byte).unwrap();
+        }
+        if let Some(byte) = step.input {
+            write!(out, ",\"input\":{}", byte).unwrap();
+        }
+        out.push('}');
+    }
+    out.push_str("],\"error\":");
+    match run.error {
+        Some(ref e) => write!(
+            out, "{{\"code\":{},\"message\":{}}}", json_string(e.code()), json_string(&e.to_string())
+        ).unwrap(),
+        None => out.push_str("null"),
+    }
+    out.push('}');
+    // the JSON sits in a script element, which only `</` could end early
+    out.replace("</", "<\\/")
+}
+
+// The source with every instruction in an element of its own, `i` and its
+// index for an id. Without `code`, as for other languages than brainfuck,
+// that's the instructions written back as brainfuck.
+fn source_html(bf: &Brainfuck, code: Option<&str>) -> String {
+    let (code, spans) = match code {
+        Some(code) => (code.to_string(), parse_with_spans(code).unwrap().1),
+        None => {
+            let mut code = String::new();
+            let mut spans = Vec::new();
+            for inst in bf.insts() {
+                let start = code.len();
+                code.push_str(&to_source(std::slice::from_ref(inst)));
+                spans.push(Span { start, end: code.len() });
+            }
+            (code, spans)
+        }
+    };
+
+    let mut out = String::new();
+    let mut at = 0;
+    for (index, span) in spans.iter().enumerate() {
+        out.push_str(&escape(&code[at..span.start]));
+        write!(out, "<span id=\"i{}\">{}</span>", index, escape(&code[span.start..span.end])).unwrap();
+        at = span.end;
+    }
+    out.push_str(&escape(&code[at..]));
+    out
+}
+
+const STYLE: &str = "\
+body { background: #1c1c1c; color: #d0d0d0; font-family: monospace; }
+pre { white-space: pre-wrap; }
+.next { background: #ffd700; color: #000; }
+table { border-collapse: collapse; }
+td { border: 1px solid #585858; padding: 2px 6px; text-align: right; }
+td.ptr { background: #5f87ff; color: #000; }
+#error { color: #ff5f5f; }
+";
+
+const SCRIPT: &str = "\
+const run = JSON.parse(document.getElementById('run').textContent);
+const slider = document.getElementById('slider');
+const radius = 8;
+let tape, output, at;
+
+function reset() {
+  tape = new Uint8Array(run.tape_size);
+  tape.set(run.initial_tape);
+  output = [];
+  at = 0;
+}
+
+function escaped(bytes) {
+  return bytes.map(byte => byte == 10 || (byte >= 32 && byte < 127)
+    ? String.fromCharCode(byte)
+    : '\\\\x' + byte.toString(16).padStart(2, '0')).join('');
+}
+
+function show(n) {
+  if (n < at) reset();
+  for (; at < n; at++) {
+    const step = run.steps[at];
+    tape[step.ptr] = step.cell;
+    if (step.output !== undefined) output.push(step.output);
+  }
+  const next = run.steps[n];
+  const ptr = next ? next.ptr : run.ptr;
+
+  document.querySelectorAll('.next').forEach(element => element.classList.remove('next'));
+  let state = 'finished';
+  if (next) {
+    document.getElementById('i' + next.index).classList.add('next');
+    state = 'next #' + next.index + ' ' + next.op;
+  }
+  document.getElementById('state').textContent = 'step ' + n + ' of ' + run.steps.length + ', ' + state;
+  document.getElementById('error').textContent = !next && run.error ? run.error.message : '';
+
+  const first = Math.max(0, ptr - radius), last = Math.min(run.tape_size, ptr + radius + 1);
+  let cells = '<tr>', values = '<tr>';
+  for (let i = first; i < last; i++) {
+    const ptrClass = i == ptr ? ' class=\"ptr\"' : '';
+    cells += '<td' + ptrClass + '>' + i + '</td>';
+    values += '<td' + ptrClass + '>' + tape[i] + '</td>';
+  }
+  document.getElementById('tape').innerHTML = cells + '</tr>' + values + '</tr>';
+  document.getElementById('output').textContent = escaped(output);
+}
+
+slider.max = run.steps.length;
+slider.addEventListener('input', () => show(Number(slider.value)));
+function move(by) {
+  slider.value = Number(slider.value) + by;
+  show(Number(slider.value));
+}
+document.getElementById('previous').addEventListener('click', () => move(-1));
+document.getElementById('next').addEventListener('click', () => move(1));
+reset();
+show(0);
+";
+
+// The page for `run` of `bf`, its source being `code` if given
+pub fn to_html(title: &str, bf: &Brainfuck, code: Option<&str>, run: &Run) -> String {
+    let mut out = String::new();
+
+    out.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    writeln!(out, "<title>{}</title>", escape(title)).unwrap();
+    writeln!(out, "<style>\n{}</style>\n</head>\n<body>", STYLE).unwrap();
+    writeln!(out, "<pre id=\"source\">{}</pre>", source_html(bf, code)).unwrap();
+    out.push_str("<p><button id=\"previous\">&lt;</button> <input id=\"slider\" type=\"range\" min=\"0\" value=\"0\"> \
+                  <button id=\"next\">&gt;</button> <span id=\"state\"></span></p>\n");
+    out.push_str("<p id=\"error\"></p>\n<table id=\"tape\"></table>\n<p>output</p>\n<pre id=\"output\"></pre>\n");
+    writeln!(out, "<script type=\"application/json\" id=\"run\">{}</script>", to_json(bf, run)).unwrap();
+    writeln!(out, "<script>\n{}</script>\n</body>\n</html>", SCRIPT).unwrap();
+    out
+}
+
+
+// The run embedded in `html`, one line for every step
+#[cfg(test)]
+fn embedded(html: &str) -> Vec<&str> {
+    let start = html.find("<script type=\"application/json\" id=\"run\">").unwrap();
+    let json = &html[start..];
+    let json = &json[json.find('>').unwrap() + 1..json.find("</script>").unwrap()];
+    json.lines().collect()
+}
+
+#[test]
+fn test_to_html() {
+    // ten steps, reading and printing
+    let source = "+[->,.<]>.";
+    let bf = Brainfuck::new(source).unwrap();
+    let run = record(&bf, &b"x"[..]).unwrap();
+    assert_eq!(run.steps.len(), 10);
+    assert_eq!(run.output(), b"xx");
+    assert!(run.error.is_none());
+    let html = to_html("a<b>.b", &bf, Some(source), &run);
+    assert!(html.contains("<title>a&lt;b&gt;.b</title>"));
+    assert!(html.contains(
+        "<pre id=\"source\"><span id=\"i0\">+</span><span id=\"i1\">[</span><span id=\"i2\">-</span>"
+    ));
+
+    let lines = embedded(&html);
+    assert_eq!(lines[0], "{\"tape_size\":30000,\"pointer_start\":0,\"initial_tape\":[],\"ptr\":1,\"steps\":[");
+    let expected: Vec<String> = bf.steps(&b"x"[..])
+        .map(|step| {
+            let step = step.unwrap();
+            let mut line = format!(
+                "{{\"index\":{},\"op\":{},\"ptr\":{},\"cell\":{}",
+                step.index, json_string(&format!("{:?}", step.inst)), step.ptr, step.cell_after
+            );
+            if let Some(byte) = step.output {
+                line += &format!(",\"output\":{}", byte);
+            }
+            if let Some(byte) = step.input {
+                line += &format!(",\"input\":{}", byte);
+            }
+            line + "}"
+        })
+        .collect();
+    assert_eq!(lines.len(), 1 + expected.len());
+    for (n, (line, step)) in lines[1..].iter().zip(&expected).enumerate() {
+        let separator = if n + 1 < expected.len() { "," } else { "" };
+        let line = line.strip_suffix("],\"error\":null}").unwrap_or(line);
+        assert_eq!(*line, format!("{}{}", step, separator));
+    }
+    assert!(lines.last().unwrap().ends_with("],\"error\":null}"));
+
+    // the error is in it, written back instructions stand in for the source
+    let bf = Brainfuck::new("+<").unwrap();
+    let run = record(&bf, std::io::empty()).unwrap();
+    assert_eq!(run.steps.len(), 1);
+    let html = to_html("t", &bf, None, &run);
+    assert!(html.contains("<pre id=\"source\"><span id=\"i0\">+</span><span id=\"i1\">&lt;</span></pre>"));
+    assert!(embedded(&html).last().unwrap().ends_with(
+        "],\"error\":{\"code\":\"pointer-underflow\",\"message\":\"pointer moved below the start of the tape at \
+         instruction 1\"}}"
+    ));
+}
+
+#[test]
+fn test_record_limit() {
+    assert!(record(&Brainfuck::new("+[]").unwrap(), std::io::empty()).is_none());
+    let bf = Brainfuck::new(&"+>".repeat(MAX_STEPS / 2)).unwrap();
+    assert_eq!(record(&bf, std::io::empty()).unwrap().steps.len(), MAX_STEPS);
+}
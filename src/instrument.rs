@@ -0,0 +1,112 @@
+// Spans and events of the `tracing` feature around parsing, the optimizer
+// passes, code generation and runs. Without the feature they're nothing,
+// not even the values of their fields are computed.
+
+// Enters a span named `$name`, left again when what it returns is dropped
+#[cfg(feature = "tracing")]
+macro_rules! stage {
+    ($name:expr $(, $field:ident = $value:expr)*) => {
+        ::tracing::debug_span!($name $(, $field = $value)*).entered()
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! stage {
+    ($name:expr $(, $field:ident = $value:expr)*) => {
+        $crate::instrument::Stage
+    };
+}
+
+// What came out of the stage of the current span, e.g. its size
+#[cfg(feature = "tracing")]
+macro_rules! stage_done {
+    ($($field:ident = $value:expr),*) => {
+        ::tracing::debug!($($field = $value),*)
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! stage_done {
+    ($($field:ident = $value:expr),*) => {
+        ()
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+pub struct Stage;
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use tracing::{Event, Metadata, Subscriber};
+    use tracing::span::{Attributes, Id, Record};
+
+    use brainfuck::Brainfuck;
+
+    // Keeps the names of the spans entered, in order
+    struct Capture {
+        next: AtomicU64,
+        names: Mutex<Vec<&'static str>>,
+        entered: Arc<Mutex<Vec<&'static str>>>,
+    }
+
+    impl Subscriber for Capture {
+        fn enabled(&self, _: &Metadata) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes) -> Id {
+            self.names.lock().unwrap().push(span.metadata().name());
+            Id::from_u64(self.next.fetch_add(1, Ordering::SeqCst))
+        }
+
+        fn record(&self, _: &Id, _: &Record) {}
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, _: &Event) {}
+
+        fn enter(&self, span: &Id) {
+            let name = self.names.lock().unwrap()[span.into_u64() as usize - 1];
+            self.entered.lock().unwrap().push(name);
+        }
+
+        fn exit(&self, _: &Id) {}
+    }
+
+    fn stages<F: FnOnce()>(f: F) -> Vec<&'static str> {
+        let entered = Arc::new(Mutex::new(Vec::new()));
+        let capture = Capture { next: AtomicU64::new(1), names: Mutex::new(Vec::new()), entered: entered.clone() };
+        ::tracing::subscriber::with_default(capture, f);
+        let entered = entered.lock().unwrap();
+        entered.clone()
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_stages() {
+        let entered = stages(|| {
+            let bf = Brainfuck::new("++[>+<-]>.").unwrap();
+            let output = bf.share().unwrap().run(b"").unwrap().output;
+            assert_eq!(output, b"\x02");
+        });
+        assert_eq!(entered, ["parse", "compile", "run"]);
+
+        let entered = stages(|| {
+            let mut bf = Brainfuck::new("+.").unwrap();
+            bf.run_streaming(&b""[..], ::brainfuck::Buffering::Full, |_| ()).unwrap();
+        });
+        assert_eq!(entered, ["parse", "run"]);
+
+        let optimizer = ::optimize::Optimizer::new(::brainfuck::ArithMode::Wrap);
+        let entered = stages(|| {
+            optimizer.run(::brainfuck::parse("+-").unwrap()).unwrap();
+        });
+        assert_eq!(entered.first(), Some(&"parse"));
+        assert_eq!(entered.get(1), Some(&"optimize"));
+        assert!(entered[2..].iter().all(|&name| name == "pass"), "{:?}", entered);
+        assert!(entered.len() > 2);
+    }
+}
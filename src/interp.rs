@@ -0,0 +1,1524 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+use brainfuck::{ArithMode, ExtOp, Inst, RuntimeError};
+use brainfuck::Inst::*;
+use host::HostFunctions;
+use memory::{BudgetedBuffer, MemoryBudget};
+use runstats::RunStats;
+
+
+// An interpreter over the instruction stream
+//
+// It has the same semantics as the generated code (cells wrapping unless told
+// otherwise, `,` leaves the cell untouched on EOF) but checks every pointer move, which makes it the
+// reference for testing the JIT and program transformations.
+//
+// Unless anything is recorded per step (livelock detection, coverage, undo
+// history), it runs a pre-decoded copy of the program, a handler per
+// instruction called through a function pointer, instead of matching every
+// instruction. `execute` stays the reference the decoded one is tested
+// against.
+pub struct Interp<'a> {
+    insts: &'a [Inst],
+    ops: Vec<Op>,
+    // partner of every bracket by instruction index, 0 for everything else
+    jumps: Vec<usize>,
+    // whether to run the decoded program when possible
+    decoded: bool,
+    shortcut_loops: bool,
+    arith: ArithMode,
+    tape: Vec<u8>,
+    ptr: usize,
+    pc: usize,
+    // the register of Extended Type I's `$` and `!`
+    storage: u8,
+    livelock: Option<LivelockDetector>,
+    history: Option<History>,
+    // how often each instruction executed
+    coverage: Option<Vec<u64>>,
+    hot: Option<HotLoops>,
+    // stops the run with `RuntimeError::Cancelled` at a `]` going round
+    // once it's set
+    cancel: Option<&'a AtomicBool>,
+    // input bytes given back by stepping back over `,`, read again first
+    replay: VecDeque<u8>,
+    // `.` stepped back over, their output was already written and isn't
+    // written again when they execute once more
+    replayed_output: usize,
+    // what `stats` tells, counted whatever else is recorded
+    executed: u64,
+    max_ptr: usize,
+    bytes_read: u64,
+    bytes_written: u64,
+}
+
+// The decoded program: a handler per `Inst`, so that pcs and step counts
+// are the same, called with its operand. Value changes are folded into a
+// single wrapping add unless cells don't wrap, jumps take their target from
+// the jump table.
+#[derive(Clone, Copy)]
+struct Op {
+    run: Handler,
+    arg: usize,
+}
+
+// Executes the instruction at `pc` and returns the pc of the next one, or
+// `STOP` once the run stops, with why in `Machine::result` and where in
+// `Machine::pc`
+type Handler = fn(&mut Machine, usize, usize) -> usize;
+
+const STOP: usize = usize::MAX;
+
+// What a run of the decoded program works on, see `execute_decoded`
+struct Machine<'r, 'h> {
+    insts: &'r [Inst],
+    jumps: &'r [usize],
+    tape: &'r mut [u8],
+    ptr: usize,
+    // the current cell lives here and is only written back when the pointer
+    // moves, which takes memory out of the `[-]`-style hot loops
+    cell: u8,
+    // where the run stopped
+    pc: usize,
+    remaining: usize,
+    result: Result<StepOutcome, RuntimeError>,
+    storage: &'r mut u8,
+    max_ptr: usize,
+    bytes_read: u64,
+    bytes_written: u64,
+    arith: ArithMode,
+    shortcut_loops: bool,
+    cancel: Option<&'r AtomicBool>,
+    hot: Option<&'r mut HotLoops>,
+    input: &'r mut dyn Read,
+    output: &'r mut dyn Write,
+    host: &'r mut HostFunctions<'h>,
+}
+
+impl<'r, 'h> Machine<'r, 'h> {
+    fn stop(&mut self, pc: usize, result: Result<StepOutcome, RuntimeError>) -> usize {
+        self.pc = pc;
+        self.result = result;
+        STOP
+    }
+
+    // The cell goes back on the tape before the pointer moves to `ptr`
+    fn move_to(&mut self, ptr: usize) {
+        self.tape[self.ptr] = self.cell;
+        self.ptr = ptr;
+        self.cell = self.tape[ptr];
+    }
+}
+
+fn op_right(m: &mut Machine, a: usize, pc: usize) -> usize {
+    if a >= m.tape.len() - m.ptr {
+        return m.stop(pc, Err(RuntimeError::PointerOverflow { inst_index: pc }));
+    }
+    m.move_to(m.ptr + a);
+    m.max_ptr = m.max_ptr.max(m.ptr);
+    pc + 1
+}
+
+fn op_left(m: &mut Machine, a: usize, pc: usize) -> usize {
+    if a > m.ptr {
+        return m.stop(pc, Err(RuntimeError::PointerUnderflow { inst_index: pc }));
+    }
+    m.move_to(m.ptr - a);
+    pc + 1
+}
+
+fn op_add(m: &mut Machine, a: usize, pc: usize) -> usize {
+    m.cell = m.cell.wrapping_add(a as u8);
+    pc + 1
+}
+
+// `+` and `-` under any other `ArithMode` than `Wrap`
+fn op_increase(m: &mut Machine, a: usize, pc: usize) -> usize {
+    match m.arith.add(m.cell, a) {
+        Some(value) => m.cell = value,
+        None => return m.stop(pc, Err(RuntimeError::CellOverflow { inst_index: pc })),
+    }
+    pc + 1
+}
+
+fn op_decrease(m: &mut Machine, a: usize, pc: usize) -> usize {
+    match m.arith.sub(m.cell, a) {
+        Some(value) => m.cell = value,
+        None => return m.stop(pc, Err(RuntimeError::CellUnderflow { inst_index: pc })),
+    }
+    pc + 1
+}
+
+fn op_print(m: &mut Machine, _: usize, pc: usize) -> usize {
+    if let Err(e) = m.output.write_all(&[m.cell]) {
+        return m.stop(pc, Err(e.into()));
+    }
+    m.bytes_written += 1;
+    pc + 1
+}
+
+fn op_read(m: &mut Machine, _: usize, pc: usize) -> usize {
+    match read_byte(&mut m.input) {
+        Ok(Some(Some(byte))) => {
+            m.cell = byte;
+            m.bytes_read += 1;
+        }
+        Ok(Some(None)) => {}
+        Ok(None) => return m.stop(pc, Ok(StepOutcome::NeedsInput)),
+        Err(e) => return m.stop(pc, Err(e.into())),
+    }
+    pc + 1
+}
+
+fn op_jump_if_zero(m: &mut Machine, _: usize, pc: usize) -> usize {
+    if m.cell == 0 {
+        return m.jumps[pc] + 1;
+    }
+    pc + 1
+}
+
+// `JumpIfZero` of a loop only adding this odd amount, which always ends
+// with the cell cleared when cells wrap
+fn op_clear_loop(m: &mut Machine, step: usize, pc: usize) -> usize {
+    if m.cell == 0 {
+        return m.jumps[pc] + 1;
+    }
+    if m.shortcut_loops {
+        // the body and the `]` for every time round
+        let body = 2 * clear_loop_iterations(m.cell, step as u8);
+        if body <= m.remaining {
+            m.remaining -= body;
+            m.cell = 0;
+            return m.jumps[pc] + 1;
+        }
+    }
+    pc + 1
+}
+
+// `JumpIfZero` of `[>]` and `[<]`, going to the nearest zero cell
+fn op_scan_right(m: &mut Machine, _: usize, pc: usize) -> usize {
+    scan(m, pc, true)
+}
+
+fn op_scan_left(m: &mut Machine, _: usize, pc: usize) -> usize {
+    scan(m, pc, false)
+}
+
+fn scan(m: &mut Machine, pc: usize, right: bool) -> usize {
+    if m.cell == 0 {
+        return m.jumps[pc] + 1;
+    }
+    if m.shortcut_loops {
+        // without a zero before the end of the tape the loop runs into it
+        // one step at a time
+        let ptr = m.ptr;
+        m.tape[ptr] = m.cell;
+        let distance = if right {
+            m.tape[ptr..].iter().position(|&c| c == 0)
+        } else {
+            m.tape[..ptr].iter().rposition(|&c| c == 0).map(|zero| ptr - zero)
+        };
+        if let Some(distance) = distance.filter(|&d| 2 * d <= m.remaining) {
+            m.remaining -= 2 * distance;
+            m.ptr = if right { ptr + distance } else { ptr - distance };
+            m.max_ptr = m.max_ptr.max(m.ptr);
+            m.cell = 0;
+            return m.jumps[pc] + 1;
+        }
+    }
+    pc + 1
+}
+
+fn op_jump_unless_zero(m: &mut Machine, _: usize, pc: usize) -> usize {
+    if m.cell == 0 {
+        return pc + 1;
+    }
+    if m.cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed)) {
+        return m.stop(pc, Err(RuntimeError::Cancelled));
+    }
+    if let Some(ref mut hot) = m.hot {
+        let top = hot.top[pc];
+        hot.counts[top] += 1;
+        if pc == top && hot.counts[top] >= hot.threshold {
+            hot.found = Some(m.jumps[pc]);
+            return m.stop(m.jumps[pc], Ok(StepOutcome::Paused));
+        }
+    }
+    m.jumps[pc] + 1
+}
+
+fn op_end(m: &mut Machine, _: usize, _: usize) -> usize {
+    m.stop(m.insts.len(), Ok(StepOutcome::Paused))
+}
+
+// any other command of Extended Type I
+fn op_ext(m: &mut Machine, _: usize, pc: usize) -> usize {
+    if let Ext(op) = m.insts[pc] {
+        op.apply(&mut m.cell, m.storage);
+    }
+    pc + 1
+}
+
+fn op_host_call(m: &mut Machine, _: usize, pc: usize) -> usize {
+    m.tape[m.ptr] = m.cell;
+    if let Err(e) = m.host.call(m.tape, m.ptr, pc) {
+        return m.stop(pc, Err(e));
+    }
+    m.cell = m.tape[m.ptr];
+    pc + 1
+}
+
+fn decode(insts: &[Inst], arith: ArithMode) -> Vec<Op> {
+    let wrap = arith == ArithMode::Wrap;
+    let op = |run: Handler, arg: usize| Op { run, arg };
+    let mut ops: Vec<Op> = insts.iter()
+        .map(|inst| match *inst {
+            IncPtr(a) => op(op_right, a),
+            DecPtr(a) => op(op_left, a),
+            IncVal(a) if wrap => op(op_add, a as u8 as usize),
+            DecVal(a) if wrap => op(op_add, (a as u8).wrapping_neg() as usize),
+            IncVal(a) => op(op_increase, a),
+            DecVal(a) => op(op_decrease, a),
+            PrintCell => op(op_print, 0),
+            ReadChar => op(op_read, 0),
+            JmpFwd(_) => op(op_jump_if_zero, 0),
+            JmpBack(_) => op(op_jump_unless_zero, 0),
+            Ext(ExtOp::End) => op(op_end, 0),
+            Ext(_) => op(op_ext, 0),
+            HostCall => op(op_host_call, 0),
+        })
+        .collect();
+
+    for (i, window) in insts.windows(3).enumerate() {
+        match *window {
+            [JmpFwd(_), IncVal(a), JmpBack(_)] | [JmpFwd(_), DecVal(a), JmpBack(_)] if wrap && a % 2 == 1 => {
+                ops[i] = op(op_clear_loop, ops[i + 1].arg);
+            }
+            [JmpFwd(_), IncPtr(1), JmpBack(_)] => ops[i] = op(op_scan_right, 0),
+            [JmpFwd(_), DecPtr(1), JmpBack(_)] => ops[i] = op(op_scan_left, 0),
+            _ => {}
+        }
+    }
+
+    ops
+}
+
+fn jump_table(insts: &[Inst]) -> Vec<usize> {
+    insts.iter()
+        .map(|inst| match *inst {
+            JmpFwd(n) | JmpBack(n) => n,
+            _ => 0,
+        })
+        .collect()
+}
+
+// How often a clear loop adding the odd amount `step` goes round starting
+// from `cell`: the k for which cell + k * step is 0 modulo 256
+fn clear_loop_iterations(cell: u8, step: u8) -> usize {
+    // an odd number is its own inverse modulo 8, every Newton step doubles
+    // the number of correct bits
+    let mut inverse = step;
+    for _ in 0..3 {
+        inverse = inverse.wrapping_mul(2u8.wrapping_sub(step.wrapping_mul(inverse)));
+    }
+
+    cell.wrapping_neg().wrapping_mul(inverse) as usize
+}
+
+// What it takes to undo one instruction besides restoring the pc
+#[derive(Debug, Clone, Copy)]
+enum Undo {
+    Nothing,
+    Cell(u8),
+    Ptr(usize),
+    Read { old: u8, byte: Option<u8> },
+    Print,
+    Storage(u8),
+}
+
+// How often the loops in every top-level loop went round, see
+// `Interp::set_hot_threshold`
+struct HotLoops {
+    threshold: u64,
+    // the `]` of the top-level loop around every `]`
+    top: Vec<usize>,
+    // by the `]` of the top-level loop
+    counts: Vec<u64>,
+    // the `[` of the loop that got hot, if `run_for` stopped at one
+    found: Option<usize>,
+}
+
+impl HotLoops {
+    fn new(insts: &[Inst], threshold: u64) -> HotLoops {
+        let mut top = vec![0; insts.len()];
+        let (mut current, mut depth) = (0, 0);
+        for (i, inst) in insts.iter().enumerate() {
+            match *inst {
+                JmpFwd(end) => {
+                    if depth == 0 {
+                        current = end;
+                    }
+                    depth += 1;
+                }
+                JmpBack(_) => {
+                    depth -= 1;
+                    top[i] = current;
+                }
+                _ => {}
+            }
+        }
+        HotLoops { threshold, top, counts: vec![0; insts.len()], found: None }
+    }
+}
+
+// The budget `Interp::set_recording` needs to keep the last `steps` steps
+pub fn history_budget(steps: usize) -> usize {
+    steps * mem::size_of::<(usize, Undo)>()
+}
+
+// The most recent undo entries, as many as fit in the memory budget
+struct History {
+    entries: VecDeque<(usize, Undo)>,
+    capacity: usize,
+}
+
+// Where `Interp::run_for` stopped
+#[derive(Debug)]
+pub enum StepOutcome {
+    // The step budget ran out, calling `run_for` again continues
+    Paused,
+    Finished,
+    // A `,` is waiting for input, it is executed again on the next call
+    NeedsInput,
+    Error(RuntimeError),
+}
+
+// One executed instruction, see `Interp::step`. `ptr` is where the pointer
+// was when it executed, the cell values are those of that cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Step<'a> {
+    pub index: usize,
+    pub inst: &'a Inst,
+    pub ptr: usize,
+    pub cell_before: u8,
+    pub cell_after: u8,
+    // the byte a `.` printed
+    pub output: Option<u8>,
+    // the byte a `,` read, `None` at the end of the input
+    pub input: Option<u8>,
+}
+
+// The steps of a program as an iterator, see `Interp::into_steps`
+pub struct Steps<'a, R> {
+    interp: Interp<'a>,
+    input: R,
+    failed: bool,
+}
+
+impl<'a, R: Read> Iterator for Steps<'a, R> {
+    // ends after the first error
+    type Item = Result<Step<'a>, RuntimeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        match self.interp.step(&mut self.input, io::sink()) {
+            Ok(step) => step.map(Ok),
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+impl<'a, R> Steps<'a, R> {
+    // The machine as the steps so far left it
+    pub fn interp(&self) -> &Interp<'a> {
+        &self.interp
+    }
+}
+
+// Cells written in a single loop iteration beyond which the detector stops
+// tracking that iteration instead of growing its journal
+const MAX_TRACKED_WRITES: usize = 64;
+
+// Detects loops that are stuck, i.e. whose back-edge is reached twice with the
+// machine in exactly the same state. Execution is deterministic, so such a
+// loop repeats forever.
+//
+// Instead of snapshotting the tape, the detector keeps the original value of
+// every cell written since the last back-edge. The state repeated if the
+// pointer is back where it was and each of these cells holds its original
+// value again. Iterations doing I/O (input position is part of the state) or
+// writing too many cells are never reported, so there are no false positives,
+// only loops that go unnoticed.
+struct LivelockDetector {
+    // `JmpFwd` index of the loop whose back-edge was taken last
+    loop_start: Option<usize>,
+    ptr: usize,
+    written: Vec<(usize, u8)>,
+    inconclusive: bool,
+}
+
+impl LivelockDetector {
+    fn new() -> LivelockDetector {
+        LivelockDetector {
+            loop_start: None,
+            ptr: 0,
+            written: Vec::with_capacity(MAX_TRACKED_WRITES),
+            inconclusive: false,
+        }
+    }
+
+    fn write(&mut self, index: usize, old: u8) {
+        if self.inconclusive || self.written.iter().any(|&(i, _)| i == index) {
+            return;
+        }
+        if self.written.len() == MAX_TRACKED_WRITES {
+            self.inconclusive = true;
+        } else {
+            self.written.push((index, old));
+        }
+    }
+
+    fn io(&mut self) {
+        self.inconclusive = true;
+    }
+
+    // Called when the back-edge of the loop starting at `start` is taken,
+    // returns true if the machine is in the same state as the last time
+    fn back_edge(&mut self, start: usize, ptr: usize, tape: &[u8]) -> bool {
+        let repeated = self.loop_start == Some(start)
+            && !self.inconclusive
+            && self.ptr == ptr
+            && self.written.iter().all(|&(i, old)| tape[i] == old);
+
+        self.loop_start = Some(start);
+        self.ptr = ptr;
+        self.written.clear();
+        self.inconclusive = false;
+
+        repeated
+    }
+}
+
+impl<'a> Interp<'a> {
+    pub fn new(insts: &'a [Inst], tape_size: usize) -> Interp<'a> {
+        Interp {
+            insts,
+            ops: decode(insts, ArithMode::Wrap),
+            jumps: jump_table(insts),
+            decoded: true,
+            shortcut_loops: false,
+            arith: ArithMode::Wrap,
+            tape: vec![0; tape_size],
+            ptr: 0,
+            pc: 0,
+            storage: 0,
+            livelock: None,
+            history: None,
+            coverage: None,
+            hot: None,
+            cancel: None,
+            replay: VecDeque::new(),
+            replayed_output: 0,
+            executed: 0,
+            max_ptr: 0,
+            bytes_read: 0,
+            bytes_written: 0,
+        }
+    }
+
+    // Abort with `RuntimeError::NonTerminatingLoop` when a loop is provably
+    // stuck, see `LivelockDetector`
+    pub fn set_detect_livelock(&mut self, enabled: bool) {
+        self.livelock = if enabled { Some(LivelockDetector::new()) } else { None };
+    }
+
+    // Runs loops like `[-]` in a single step instead of going round them,
+    // whenever the step budget covers the whole loop. Only takes effect
+    // while nothing is recorded per step.
+    pub fn set_shortcut_loops(&mut self, enabled: bool) {
+        self.shortcut_loops = enabled;
+    }
+
+    // Runs the pre-decoded program while nothing is recorded per step, as
+    // it does by default. Without it every step goes through `execute`,
+    // which is what the decoded program is compared with.
+    pub fn set_decoded(&mut self, enabled: bool) {
+        self.decoded = enabled;
+    }
+
+    // What `+` and `-` do at the ends of a cell's range, wrapping by default.
+    // Panics for `ArithMode::Unbounded`, which `bignum::BigInterp` runs.
+    pub fn set_arith_mode(&mut self, mode: ArithMode) {
+        #[cfg(feature = "bignum")]
+        assert!(!matches!(mode, ArithMode::Unbounded(_)), "unbounded cells only run in bignum::BigInterp");
+        self.arith = mode;
+        self.ops = decode(self.insts, mode);
+    }
+
+    // Stops `run_for` with `Paused` when a top-level loop is about to go
+    // round once more after the loops in it, itself included, went round
+    // `threshold` times in all, so that it can be run some other way.
+    // `hot_loop` then tells which loop, the pc is on its `[`. Only counts
+    // while nothing is recorded per step.
+    pub fn set_hot_threshold(&mut self, threshold: Option<u64>) {
+        self.hot = threshold.map(|threshold| HotLoops::new(self.insts, threshold));
+    }
+
+    // The `[` of the loop that made `run_for` stop, see `set_hot_threshold`.
+    // Going on from there runs it in the interpreter, where it stops again
+    // the next time round.
+    pub fn hot_loop(&mut self) -> Option<usize> {
+        self.hot.as_mut().and_then(|hot| hot.found.take())
+    }
+
+    // Fails the run with `RuntimeError::Cancelled` the next time a loop is
+    // about to go round once `cancel` is set, the pc stays on its `]`. Like
+    // generated code polls its cancel flag, see `Brainfuck::run_until`.
+    pub fn set_cancel(&mut self, cancel: Option<&'a AtomicBool>) {
+        self.cancel = cancel;
+    }
+
+    // Counts how often every instruction executes, see `coverage`
+    pub fn set_coverage(&mut self, enabled: bool) {
+        self.coverage = if enabled { Some(vec![0; self.insts.len()]) } else { None };
+    }
+
+    // Execution counts parallel to the instructions, if enabled
+    pub fn coverage(&self) -> Option<&[u64]> {
+        self.coverage.as_ref().map(|counts| &counts[..])
+    }
+
+    // Records an undo entry for every executed instruction so that
+    // `step_back` can rewind execution. The oldest entries are dropped once
+    // the history would take more than `budget` bytes, `None` stops recording.
+    pub fn set_recording(&mut self, budget: Option<usize>) {
+        self.history = budget.map(|budget| {
+            let capacity = budget / mem::size_of::<(usize, Undo)>();
+            History { entries: VecDeque::with_capacity(capacity.min(1 << 16)), capacity }
+        });
+    }
+
+    // Number of instructions `step_back` can undo
+    pub fn history_len(&self) -> usize {
+        self.history.as_ref().map_or(0, |history| history.entries.len())
+    }
+
+    // The pcs of the last `n` executed instructions the history still has,
+    // oldest first
+    pub fn recent(&self, n: usize) -> Vec<usize> {
+        let entries = match self.history {
+            Some(ref history) => &history.entries,
+            None => return Vec::new(),
+        };
+        entries.iter().skip(entries.len().saturating_sub(n)).map(|&(pc, _)| pc).collect()
+    }
+
+    // Undoes the last executed instruction, returns false if there is no
+    // history left. Input read by a `,` is kept and read again when going
+    // forward, output can't be taken back and isn't written a second time.
+    pub fn step_back(&mut self) -> bool {
+        let (pc, undo) = match self.history.as_mut().and_then(|history| history.entries.pop_back()) {
+            Some(entry) => entry,
+            None => return false,
+        };
+
+        self.forget_state();
+        self.pc = pc;
+        if let Some(ref mut counts) = self.coverage {
+            counts[pc] -= 1;
+        }
+        match undo {
+            Undo::Nothing => {}
+            Undo::Cell(old) => self.tape[self.ptr] = old,
+            Undo::Ptr(old) => self.ptr = old,
+            Undo::Read { old, byte } => {
+                self.tape[self.ptr] = old;
+                if let Some(byte) = byte {
+                    self.replay.push_front(byte);
+                }
+            }
+            Undo::Print => self.replayed_output += 1,
+            Undo::Storage(old) => self.storage = old,
+        }
+
+        true
+    }
+
+    // The instruction `step_back` would undo next together with the value
+    // the current cell had before it, if it changed the cell
+    pub fn last_step(&self) -> Option<(usize, Option<u8>)> {
+        let &(pc, undo) = self.history.as_ref()?.entries.back()?;
+        let old = match undo {
+            Undo::Cell(old) | Undo::Read { old, .. } => Some(old),
+            _ => None,
+        };
+
+        Some((pc, old))
+    }
+
+    // Runs until the program finishes, fails or needs input that isn't
+    // available yet (only with non-blocking input), continuing from wherever
+    // an earlier `run_for` stopped
+    pub fn run<R: Read, W: Write>(&mut self, input: R, output: W) -> Result<(), RuntimeError> {
+        self.run_with_host(input, output, &mut HostFunctions::new())
+    }
+
+    // `run` with host calls going to `host`, without it they fail
+    pub fn run_with_host<R: Read, W: Write>(
+        &mut self, mut input: R, mut output: W, host: &mut HostFunctions
+    ) -> Result<(), RuntimeError> {
+        loop {
+            match self.run_for_with_host(usize::MAX, &mut input, &mut output, host) {
+                StepOutcome::Paused => {}
+                StepOutcome::Finished => return Ok(()),
+                StepOutcome::NeedsInput => return Err(io::Error::from(io::ErrorKind::WouldBlock).into()),
+                StepOutcome::Error(e) => return Err(e),
+            }
+        }
+    }
+
+    // Executes at most `steps` instructions. A `,` whose read fails with
+    // `WouldBlock` stops execution with `NeedsInput` and is retried on the
+    // next call, so a GUI can supply input as it arrives.
+    pub fn run_for<R: Read, W: Write>(&mut self, steps: usize, input: R, output: W) -> StepOutcome {
+        self.run_for_with_host(steps, input, output, &mut HostFunctions::new())
+    }
+
+    pub fn run_for_with_host<R: Read, W: Write>(
+        &mut self, steps: usize, mut input: R, mut output: W, host: &mut HostFunctions
+    ) -> StepOutcome {
+        let instrumented = self.livelock.is_some() || self.coverage.is_some() || self.history.is_some();
+        // replayed I/O only comes from stepping back, which needs the history
+        let result = if self.decoded && !instrumented && self.replay.is_empty() && self.replayed_output == 0 {
+            self.execute_decoded(steps, &mut input, &mut output, host)
+        } else {
+            self.execute(steps, &mut input, &mut output, host)
+        };
+        let outcome = match result {
+            Ok(outcome) => outcome,
+            Err(e) => StepOutcome::Error(e),
+        };
+        if let Err(e) = output.flush() {
+            return StepOutcome::Error(e.into());
+        }
+
+        outcome
+    }
+
+    // Executes the next instruction and describes what it did, `Ok(None)`
+    // once the program finished. Input that isn't available yet fails with
+    // `WouldBlock` like in `run`, the `,` is executed again next time.
+    pub fn step<R: Read, W: Write>(&mut self, input: R, output: W) -> Result<Option<Step<'a>>, RuntimeError> {
+        let insts = self.insts;
+        let (index, ptr) = (self.pc, self.ptr);
+        let inst = match insts.get(index) {
+            Some(inst) => inst,
+            None => return Ok(None),
+        };
+        let cell_before = self.tape[ptr];
+        // a byte read again after stepping back comes from `replay`
+        let read = (self.bytes_read, self.replay.len());
+
+        match self.run_for(1, input, output) {
+            StepOutcome::Paused | StepOutcome::Finished => {}
+            StepOutcome::NeedsInput => return Err(io::Error::from(io::ErrorKind::WouldBlock).into()),
+            StepOutcome::Error(e) => return Err(e),
+        }
+
+        Ok(Some(Step {
+            index,
+            inst,
+            ptr,
+            cell_before,
+            cell_after: self.tape[ptr],
+            output: if let PrintCell = *inst { Some(cell_before) } else { None },
+            input: if (self.bytes_read, self.replay.len()) != read { Some(self.tape[ptr]) } else { None },
+        }))
+    }
+
+    // Steps through the program lazily, one `Step` per executed
+    // instruction, with output only going into the steps
+    pub fn into_steps<R: Read>(self, input: R) -> Steps<'a, R> {
+        Steps { interp: self, input, failed: false }
+    }
+
+    fn execute<R: Read, W: Write>(
+        &mut self, steps: usize, input: &mut R, output: &mut W, host: &mut HostFunctions
+    ) -> Result<StepOutcome, RuntimeError>
+    {
+        for _ in 0..steps {
+            let pc = self.pc;
+            if pc >= self.insts.len() {
+                return Ok(StepOutcome::Finished);
+            }
+            let (ptr, cell) = (self.ptr, self.tape[self.ptr]);
+
+            let undo = match self.insts[pc] {
+                IncPtr(a) => {
+                    if a >= self.tape.len() - self.ptr {
+                        return Err(RuntimeError::PointerOverflow { inst_index: pc });
+                    }
+                    self.ptr += a;
+                    self.max_ptr = self.max_ptr.max(self.ptr);
+                    Undo::Ptr(ptr)
+                }
+                DecPtr(a) => {
+                    if a > self.ptr {
+                        return Err(RuntimeError::PointerUnderflow { inst_index: pc });
+                    }
+                    self.ptr -= a;
+                    Undo::Ptr(ptr)
+                }
+                IncVal(a) => {
+                    if let Some(ref mut livelock) = self.livelock {
+                        livelock.write(ptr, cell);
+                    }
+                    self.tape[ptr] = match self.arith.add(cell, a) {
+                        Some(cell) => cell,
+                        None => return Err(RuntimeError::CellOverflow { inst_index: pc }),
+                    };
+                    Undo::Cell(cell)
+                }
+                DecVal(a) => {
+                    if let Some(ref mut livelock) = self.livelock {
+                        livelock.write(ptr, cell);
+                    }
+                    self.tape[ptr] = match self.arith.sub(cell, a) {
+                        Some(cell) => cell,
+                        None => return Err(RuntimeError::CellUnderflow { inst_index: pc }),
+                    };
+                    Undo::Cell(cell)
+                }
+                PrintCell => {
+                    if let Some(ref mut livelock) = self.livelock {
+                        livelock.io();
+                    }
+                    if self.replayed_output > 0 {
+                        self.replayed_output -= 1;
+                    } else {
+                        output.write_all(&[cell])?;
+                        self.bytes_written += 1;
+                    }
+                    Undo::Print
+                }
+                ReadChar => {
+                    let byte = match self.replay.pop_front() {
+                        Some(byte) => Some(byte),
+                        None => match read_byte(input)? {
+                            Some(byte) => {
+                                self.bytes_read += byte.is_some() as u64;
+                                byte
+                            }
+                            None => return Ok(StepOutcome::NeedsInput),
+                        },
+                    };
+                    if let Some(byte) = byte {
+                        self.tape[ptr] = byte;
+                    }
+                    if let Some(ref mut livelock) = self.livelock {
+                        livelock.io();
+                    }
+                    Undo::Read { old: cell, byte }
+                }
+                JmpFwd(_) => {
+                    if cell == 0 {
+                        self.pc = self.jumps[pc];
+                    }
+                    Undo::Nothing
+                }
+                JmpBack(_) => {
+                    let n = self.jumps[pc];
+                    if cell != 0 {
+                        if self.cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed)) {
+                            return Err(RuntimeError::Cancelled);
+                        }
+                        if let Some(ref mut livelock) = self.livelock {
+                            if livelock.back_edge(n, ptr, &self.tape) {
+                                return Err(RuntimeError::NonTerminatingLoop { inst_index: n });
+                            }
+                        }
+                        self.pc = n;
+                    }
+                    Undo::Nothing
+                }
+                // the pc goes past the last instruction below
+                Ext(ExtOp::End) => {
+                    self.pc = self.insts.len() - 1;
+                    Undo::Nothing
+                }
+                Ext(ExtOp::Store) => {
+                    // storage isn't part of the state the detector compares
+                    if let Some(ref mut livelock) = self.livelock {
+                        livelock.io();
+                    }
+                    let old = self.storage;
+                    self.storage = cell;
+                    Undo::Storage(old)
+                }
+                Ext(op) => {
+                    if let Some(ref mut livelock) = self.livelock {
+                        livelock.write(ptr, cell);
+                    }
+                    op.apply(&mut self.tape[ptr], &mut self.storage);
+                    Undo::Cell(cell)
+                }
+                // whatever the host function did to the tape can't be undone
+                HostCall => {
+                    if let Some(ref mut livelock) = self.livelock {
+                        livelock.io();
+                    }
+                    host.call(&mut self.tape, ptr, pc)?;
+                    Undo::Nothing
+                }
+            };
+
+            if let Some(ref mut counts) = self.coverage {
+                counts[pc] += 1;
+            }
+            if let Some(ref mut history) = self.history {
+                if history.capacity > 0 {
+                    if history.entries.len() == history.capacity {
+                        history.entries.pop_front();
+                    }
+                    history.entries.push_back((pc, undo));
+                }
+            }
+            self.executed += 1;
+            self.pc += 1;
+        }
+
+        if self.pc >= self.insts.len() {
+            Ok(StepOutcome::Finished)
+        } else {
+            Ok(StepOutcome::Paused)
+        }
+    }
+
+    // `execute` without the per-step bookkeeping, over the decoded program,
+    // calling the handler of one instruction after the other
+    fn execute_decoded<R: Read, W: Write>(
+        &mut self, steps: usize, input: &mut R, output: &mut W, host: &mut HostFunctions
+    ) -> Result<StepOutcome, RuntimeError>
+    {
+        let ops = &self.ops[..];
+        let mut m = Machine {
+            insts: self.insts,
+            jumps: &self.jumps,
+            cell: self.tape[self.ptr],
+            tape: &mut self.tape,
+            ptr: self.ptr,
+            pc: 0,
+            remaining: steps,
+            result: Ok(StepOutcome::Paused),
+            storage: &mut self.storage,
+            max_ptr: self.max_ptr,
+            bytes_read: 0,
+            bytes_written: 0,
+            arith: self.arith,
+            shortcut_loops: self.shortcut_loops,
+            cancel: self.cancel,
+            hot: self.hot.as_mut(),
+            input,
+            output,
+            host,
+        };
+
+        let mut pc = self.pc;
+        while m.remaining > 0 {
+            let op = match ops.get(pc) {
+                Some(&op) => op,
+                None => break,
+            };
+            m.remaining -= 1;
+            pc = (op.run)(&mut m, op.arg, pc);
+            if pc == STOP {
+                pc = m.pc;
+                break;
+            }
+        }
+
+        m.tape[m.ptr] = m.cell;
+        let (remaining, result) = (m.remaining, m.result);
+        self.ptr = m.ptr;
+        self.pc = pc;
+        // an instruction that failed or waits for input didn't execute
+        let unfinished = !matches!(result, Ok(StepOutcome::Paused));
+        self.executed += (steps - remaining - unfinished as usize) as u64;
+        self.max_ptr = m.max_ptr;
+        self.bytes_read += m.bytes_read;
+        self.bytes_written += m.bytes_written;
+        match result {
+            Ok(StepOutcome::Paused) if pc >= ops.len() => Ok(StepOutcome::Finished),
+            result => result,
+        }
+    }
+
+    pub fn insts(&self) -> &'a [Inst] {
+        self.insts
+    }
+
+    pub fn tape(&self) -> &[u8] {
+        &self.tape
+    }
+
+    // Cells can be changed while paused, the program continues with them
+    pub fn tape_mut(&mut self) -> &mut [u8] {
+        self.forget_state();
+        &mut self.tape
+    }
+
+    pub fn ptr(&self) -> usize {
+        self.ptr
+    }
+
+    pub fn set_ptr(&mut self, ptr: usize) {
+        assert!(ptr < self.tape.len(), "pointer {} outside of the tape", ptr);
+        self.forget_state();
+        self.ptr = ptr;
+        self.max_ptr = self.max_ptr.max(ptr);
+    }
+
+    // What the runs so far did, all of it but the wall time, which is for
+    // whoever runs it to measure. Instructions stepped back over still
+    // count, they did execute.
+    pub fn stats(&self) -> RunStats {
+        RunStats {
+            instructions: Some(self.executed),
+            bytes_read: Some(self.bytes_read),
+            bytes_written: Some(self.bytes_written),
+            max_pointer: Some(self.max_ptr),
+            final_pointer: Some(self.ptr),
+            wall_time: Default::default(),
+        }
+    }
+
+    // The storage register of Extended Type I
+    pub fn storage(&self) -> u8 {
+        self.storage
+    }
+
+    pub fn set_storage(&mut self, storage: u8) {
+        self.forget_state();
+        self.storage = storage;
+    }
+
+    // Index of the next instruction to execute
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    // Continues at another instruction, e.g. after a loop was run elsewhere
+    pub fn set_pc(&mut self, pc: usize) {
+        assert!(pc <= self.insts.len(), "pc {} outside of the program", pc);
+        self.forget_state();
+        self.pc = pc;
+    }
+
+    // The livelock detector must not compare against a state from before
+    // the machine was changed from the outside
+    fn forget_state(&mut self) {
+        if self.livelock.is_some() {
+            self.livelock = Some(LivelockDetector::new());
+        }
+    }
+}
+
+// Reads a single byte, `Ok(None)` if the read would block and
+// `Ok(Some(None))` at EOF
+fn read_byte<R: Read>(input: &mut R) -> io::Result<Option<Option<u8>>> {
+    let mut byte = [0];
+    loop {
+        match input.read(&mut byte) {
+            Ok(0) => return Ok(Some(None)),
+            Ok(_) => return Ok(Some(Some(byte[0]))),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Runs a program to completion on a fresh tape, returning everything it printed
+pub fn run_with_input(insts: &[Inst], tape_size: usize, input: &[u8]) -> Result<Vec<u8>, RuntimeError> {
+    run_with_limit(insts, tape_size, input, usize::MAX)
+}
+
+// `run_with_input`, also returning what the run did
+pub fn run_with_stats(insts: &[Inst], tape_size: usize, input: &[u8]) -> Result<(Vec<u8>, RunStats), RuntimeError> {
+    let started = Instant::now();
+    let mut interp = Interp::new(insts, tape_size);
+    let mut output = Vec::new();
+    interp.run(input, &mut output)?;
+
+    Ok((output, RunStats { wall_time: started.elapsed(), ..interp.stats() }))
+}
+
+// Like `run_with_input`, with the tape, the input and the collected output
+// taking at most `max_memory` bytes together
+pub fn run_with_limit(insts: &[Inst], tape_size: usize, input: &[u8], max_memory: usize)
+    -> Result<Vec<u8>, RuntimeError>
+{
+    let budget = MemoryBudget::new(max_memory);
+    budget.charge(tape_size)?;
+    budget.charge(input.len())?;
+    let mut output = BudgetedBuffer::new(budget);
+    Interp::new(insts, tape_size).run(input, &mut output)?;
+
+    Ok(output.into_inner())
+}
+
+
+#[cfg(test)]
+use brainfuck::parse;
+
+#[test]
+fn test_interp() {
+    let hello = parse(include_str!("../tests/fixtures/hello.b")).unwrap();
+    assert_eq!(run_with_input(&hello, 30_000, b"").unwrap(), b"Hello World!\n");
+
+    let rot13 = parse(include_str!("../tests/fixtures/rot13.b")).unwrap();
+    assert_eq!(run_with_input(&rot13, 30_000, b"Hello, abc-XYZ").unwrap(), b"Uryyb, nop-KLM");
+
+    let insts = parse("+++>-,<[->+<]").unwrap();
+    let mut interp = Interp::new(&insts, 4);
+    interp.run(&b""[..], io::sink()).unwrap();
+    assert_eq!(interp.tape(), [0, 2, 0, 0]);
+    assert_eq!(interp.ptr(), 0);
+}
+
+#[test]
+fn test_steps() {
+    use brainfuck::Brainfuck;
+
+    let bf = Brainfuck::new(",+[->+<]>.").unwrap();
+    let steps: Vec<Step> = bf.steps(&[1u8][..]).map(Result::unwrap).collect();
+    let actual: Vec<_> = steps.iter()
+        .map(|step| (step.index, step.ptr, step.cell_before, step.cell_after, step.output))
+        .collect();
+    assert_eq!(actual, [
+        (0, 0, 0, 1, None),
+        (1, 0, 1, 2, None),
+        (2, 0, 2, 2, None),
+        (3, 0, 2, 1, None),
+        (4, 0, 1, 1, None),
+        (5, 1, 0, 1, None),
+        (6, 1, 1, 1, None),
+        (7, 0, 1, 1, None),
+        (3, 0, 1, 0, None),
+        (4, 0, 0, 0, None),
+        (5, 1, 1, 2, None),
+        (6, 1, 2, 2, None),
+        (7, 0, 0, 0, None),
+        (8, 0, 0, 0, None),
+        (9, 1, 2, 2, Some(2)),
+    ]);
+    assert_eq!(*steps[7].inst, JmpBack(2));
+    assert_eq!(steps[0].input, Some(1));
+    assert!(steps[1..].iter().all(|step| step.input.is_none()));
+    // nothing read at the end of the input
+    let bf = Brainfuck::new("+,").unwrap();
+    assert_eq!(bf.steps(io::empty()).last().unwrap().unwrap().input, None);
+
+    // lazy, an endless loop steps as far as asked
+    let bf = Brainfuck::new("+[]").unwrap();
+    assert_eq!(bf.steps(io::empty()).take(1_000_000).count(), 1_000_000);
+
+    // errors end the steps
+    let insts = parse("<+").unwrap();
+    let mut steps = Interp::new(&insts, 1).into_steps(io::empty());
+    assert!(matches!(steps.next(), Some(Err(RuntimeError::PointerUnderflow { inst_index: 0 }))));
+    assert!(steps.next().is_none());
+    assert_eq!(steps.interp().pc(), 0);
+}
+
+#[test]
+fn test_run_with_limit() {
+    // endless output runs out of budget instead of memory
+    let insts = parse("+[.]").unwrap();
+    match run_with_limit(&insts, 16, b"", 1024) {
+        Err(RuntimeError::MemoryLimitExceeded { limit: 1024, requested: 1025 }) => {}
+        other => panic!("unexpected {:?}", other),
+    }
+
+    // the tape and the input count too
+    let rot13 = parse(include_str!("../tests/fixtures/rot13.b")).unwrap();
+    assert_eq!(run_with_limit(&rot13, 100, b"abc", 106).unwrap(), b"nop");
+    match run_with_limit(&rot13, 100, b"abc", 105) {
+        Err(RuntimeError::MemoryLimitExceeded { limit: 105, requested: 106 }) => {}
+        other => panic!("unexpected {:?}", other),
+    }
+    match run_with_limit(&rot13, 100, b"", 99) {
+        Err(RuntimeError::MemoryLimitExceeded { limit: 99, requested: 100 }) => {}
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn test_run_for() {
+    let rot13 = parse(include_str!("../tests/fixtures/rot13.b")).unwrap();
+    let input = b"Hello, abc-XYZ";
+    let expected = run_with_input(&rot13, 30_000, input).unwrap();
+
+    // single steps only
+    let mut interp = Interp::new(&rot13, 30_000);
+    let (mut reader, mut output) = (&input[..], Vec::new());
+    let mut steps = 0;
+    loop {
+        match interp.run_for(1, &mut reader, &mut output) {
+            StepOutcome::Paused => steps += 1,
+            StepOutcome::Finished => break,
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+    assert_eq!(output, expected);
+    assert_eq!(interp.pc(), rot13.len());
+    assert!(steps > rot13.len());
+
+    // single steps mixed with longer stretches and a full run
+    let mut interp = Interp::new(&rot13, 30_000);
+    let (mut reader, mut output) = (&input[..], Vec::new());
+    for n in 1..50 {
+        match interp.run_for(if n % 2 == 0 { 1 } else { n * 7 }, &mut reader, &mut output) {
+            StepOutcome::Paused => {}
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+    interp.run(&mut reader, &mut output).unwrap();
+    assert_eq!(output, expected);
+
+    // finishing exactly on the last step
+    let insts = parse("+>+").unwrap();
+    let mut interp = Interp::new(&insts, 2);
+    assert!(matches!(interp.run_for(2, io::empty(), io::sink()), StepOutcome::Paused));
+    assert!(matches!(interp.run_for(1, io::empty(), io::sink()), StepOutcome::Finished));
+    assert!(matches!(interp.run_for(1, io::empty(), io::sink()), StepOutcome::Finished));
+}
+
+#[test]
+fn test_hot_loops() {
+    // the inner loop counts towards the outer one, which only stops at its
+    // own `]`, the first time after two rounds of the inner one and its own
+    let insts = parse("+[-]>++[>+++[>+<-]<-]>>>+").unwrap();
+    let mut interp = Interp::new(&insts, 10);
+    interp.set_hot_threshold(Some(3));
+    assert!(matches!(interp.run_for(usize::MAX, io::empty(), io::sink()), StepOutcome::Paused));
+    assert_eq!(interp.hot_loop(), Some(6));
+    assert_eq!((interp.pc(), interp.ptr()), (6, 1));
+    assert_eq!(interp.tape()[..4], [0, 1, 0, 3]);
+    assert_eq!(interp.hot_loop(), None);
+
+    // skipping the rest of the loop
+    interp.tape_mut()[1] = 0;
+    interp.set_pc(interp.insts.len() - 2);
+    assert!(matches!(interp.run_for(usize::MAX, io::empty(), io::sink()), StepOutcome::Finished));
+    assert_eq!(interp.hot_loop(), None);
+    assert_eq!(interp.tape()[..5], [0, 0, 0, 3, 1]);
+
+    // or going on in the interpreter, stopping every time round
+    let mut interp = Interp::new(&insts, 10);
+    interp.set_hot_threshold(Some(3));
+    let mut stops = 0;
+    loop {
+        match interp.run_for(usize::MAX, io::empty(), io::sink()) {
+            StepOutcome::Paused => {
+                assert_eq!(interp.hot_loop(), Some(6));
+                stops += 1;
+            }
+            StepOutcome::Finished => break,
+            outcome => panic!("unexpected {:?}", outcome),
+        }
+    }
+    assert_eq!(stops, 1);
+    assert_eq!(interp.tape()[..5], [0, 0, 0, 6, 1]);
+}
+
+#[cfg(test)]
+struct NonBlocking<'a>(&'a [u8]);
+
+#[cfg(test)]
+impl<'a> Read for NonBlocking<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.0.is_empty() {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        self.0.read(buf)
+    }
+}
+
+#[test]
+fn test_needs_input() {
+    let insts = parse("+[,.]").unwrap();
+    let mut interp = Interp::new(&insts, 1);
+    let mut output = Vec::new();
+
+    assert!(matches!(interp.run_for(100, NonBlocking(b"ab"), &mut output), StepOutcome::NeedsInput));
+    assert_eq!(output, b"ab");
+    let pc = interp.pc();
+    assert!(matches!(interp.run_for(100, NonBlocking(b""), &mut output), StepOutcome::NeedsInput));
+    assert_eq!(interp.pc(), pc);
+
+    assert!(matches!(interp.run_for(100, NonBlocking(b"c\0"), &mut output), StepOutcome::Finished));
+    assert_eq!(output, b"abc\0");
+}
+
+#[test]
+fn test_pause_and_modify() {
+    let insts = parse("+++[>+<-]>.").unwrap();
+    let mut interp = Interp::new(&insts, 4);
+    let mut output = Vec::new();
+
+    // `+++` and `[` once, the loop body three times and the `>`, which stops
+    // right before the `.`
+    assert!(matches!(interp.run_for(2 + 3 * 5 + 1, io::empty(), &mut output), StepOutcome::Paused));
+    assert_eq!(insts[interp.pc()], PrintCell);
+    assert_eq!(interp.tape(), [0, 3, 0, 0]);
+
+    interp.tape_mut()[2] = b'x';
+    interp.set_ptr(2);
+    interp.run(io::empty(), &mut output).unwrap();
+    assert_eq!(output, b"x");
+}
+
+#[cfg(test)]
+fn assert_same_as_reference(insts: &[Inst], input: &[u8], steps: usize) {
+    for &shortcut_loops in &[false, true] {
+        assert_same_as_reference_with(insts, input, steps, shortcut_loops);
+    }
+}
+
+#[cfg(test)]
+fn assert_same_as_reference_with(insts: &[Inst], input: &[u8], steps: usize, shortcut_loops: bool) {
+    let mut decoded = Interp::new(insts, 16);
+    decoded.set_shortcut_loops(shortcut_loops);
+    let mut reference = Interp::new(insts, 16);
+    reference.decoded = false;
+
+    let (mut decoded_input, mut decoded_output) = (input, Vec::new());
+    let (mut reference_input, mut reference_output) = (input, Vec::new());
+    for _ in 0..3 {
+        let decoded_outcome = decoded.run_for(steps, &mut decoded_input, &mut decoded_output);
+        let reference_outcome = reference.run_for(steps, &mut reference_input, &mut reference_output);
+
+        assert_eq!(format!("{:?}", decoded_outcome), format!("{:?}", reference_outcome));
+        assert_eq!(decoded.tape(), reference.tape());
+        assert_eq!((decoded.ptr(), decoded.pc()), (reference.ptr(), reference.pc()));
+        assert_eq!(decoded_output, reference_output);
+        assert_eq!(decoded.stats(), reference.stats());
+    }
+}
+
+#[test]
+fn test_decoded_matches_reference() {
+    use generator::ProgramGenerator;
+
+    let rot13 = parse(include_str!("../tests/fixtures/rot13.b")).unwrap();
+    assert_same_as_reference(&rot13, b"Hello, abc-XYZ", 1000);
+    let hello = parse(include_str!("../tests/fixtures/hello.b")).unwrap();
+    assert_same_as_reference(&hello, b"", 1000);
+
+    // programs that need not end, so pointers run off the tape too
+    let mut generator = ProgramGenerator::new(132).max_len(40);
+    for _ in 0..500 {
+        let source = generator.generate();
+        let insts = parse(&source).unwrap();
+        assert_same_as_reference(&insts, b"\x01\xffab", 300);
+    }
+
+    // clear and scan loops that fit into the step budget and ones that
+    // don't, scans running into the ends of the tape too
+    for &source in &["+++[-]+", ",[---]>,[+]", "-[>-[>-[-]<-]<-]", "+>+>+>>+<<<[>]+[<]", "+>+>+<[<]", "+[[>]+]", ">>+[[<]+]"] {
+        let insts = parse(source).unwrap();
+        for &steps in &[1, 2, 5, 7, 8, 500, 1 << 20] {
+            assert_same_as_reference(&insts, b"\x03\xff", steps);
+        }
+    }
+}
+
+#[test]
+fn test_run_stats() {
+    let insts = parse(",>++[<.>-]<,").unwrap();
+    let (output, stats) = run_with_stats(&insts, 4, b"xy").unwrap();
+    assert_eq!(output, b"xx");
+    assert_eq!(
+        (stats.instructions, stats.bytes_read, stats.bytes_written, stats.max_pointer, stats.final_pointer),
+        (Some(16), Some(2), Some(2), Some(1), Some(0))
+    );
+
+    // a `,` waiting for input isn't executed until it gets some, a failing
+    // instruction not at all
+    let mut interp = Interp::new(&insts, 4);
+    assert!(matches!(interp.run_for(100, NonBlocking(b"x"), io::sink()), StepOutcome::NeedsInput));
+    assert_eq!(interp.stats().instructions, Some(15));
+    assert_eq!(interp.stats().bytes_read, Some(1));
+    let insts = parse(">>>+[>]").unwrap();
+    for &decoded in &[true, false] {
+        let mut interp = Interp::new(&insts, 4);
+        interp.decoded = decoded;
+        assert!(interp.run(&b""[..], io::sink()).is_err());
+        // `>>>`, `+` and `[`, the `>` running off the tape didn't execute
+        assert_eq!((interp.stats().instructions, interp.stats().max_pointer), (Some(3), Some(3)));
+    }
+}
+
+#[test]
+fn test_cancel() {
+    // set before the run, the loop never goes round
+    let insts = parse("+[>+<]").unwrap();
+    let cancel = AtomicBool::new(true);
+    for &decoded in &[true, false] {
+        let mut interp = Interp::new(&insts, 2);
+        interp.decoded = decoded;
+        interp.set_cancel(Some(&cancel));
+        assert!(matches!(interp.run(&b""[..], io::sink()), Err(RuntimeError::Cancelled)));
+        assert_eq!((interp.pc(), interp.stats().instructions), (5, Some(5)));
+        assert_eq!(interp.tape(), [1, 1]);
+    }
+
+    // set while running, from another thread
+    let cancel = AtomicBool::new(false);
+    let mut interp = Interp::new(&insts, 2);
+    interp.set_cancel(Some(&cancel));
+    ::std::thread::scope(|scope| {
+        scope.spawn(|| cancel.store(true, Ordering::SeqCst));
+        assert!(matches!(interp.run(&b""[..], io::sink()), Err(RuntimeError::Cancelled)));
+    });
+    assert_eq!(interp.pc(), 5);
+}
+
+#[test]
+fn test_clear_loop_iterations() {
+    assert_eq!(clear_loop_iterations(3, 255), 3);
+    assert_eq!(clear_loop_iterations(3, 1), 253);
+    assert_eq!(clear_loop_iterations(0, 1), 0);
+    for step in (1..=255u8).step_by(2) {
+        for cell in 0..=255u8 {
+            let k = clear_loop_iterations(cell, step);
+            assert!(k < 256);
+            assert_eq!(cell.wrapping_add((k as u8).wrapping_mul(step)), 0, "{} {}", cell, step);
+        }
+    }
+}
+
+#[cfg(test)]
+fn assert_rewind_matches(source: &str, input: &[u8], forward: usize, back: usize, rounds: usize) {
+    let insts = parse(source).unwrap();
+
+    let mut straight = Interp::new(&insts, 100);
+    let (mut straight_input, mut straight_output) = (input, Vec::new());
+    straight.run_for(rounds * forward, &mut straight_input, &mut straight_output);
+
+    let mut interp = Interp::new(&insts, 100);
+    interp.set_recording(Some(1 << 20));
+    let (mut input, mut output) = (input, Vec::new());
+    for _ in 0..rounds {
+        interp.run_for(forward, &mut input, &mut output);
+        for _ in 0..back {
+            assert!(interp.step_back());
+        }
+        interp.run_for(back, &mut input, &mut output);
+    }
+
+    assert_eq!(interp.tape(), straight.tape());
+    assert_eq!(interp.ptr(), straight.ptr());
+    assert_eq!(interp.pc(), straight.pc());
+    assert_eq!(output, straight_output);
+    assert_eq!(interp.history_len(), rounds * forward);
+
+    // both finish the same way
+    interp.run(&mut input, &mut output).unwrap();
+    straight.run(&mut straight_input, &mut straight_output).unwrap();
+    assert_eq!(output, straight_output);
+}
+
+#[test]
+fn test_step_back() {
+    assert_rewind_matches(include_str!("../tests/fixtures/rot13.b"), b"Hello", 100, 40, 1);
+    assert_rewind_matches(include_str!("../tests/fixtures/rot13.b"), b"Hello", 100, 40, 3);
+    // going back over `,` and `.` doesn't read or write anything twice
+    assert_rewind_matches(",[.,]", b"Hello, World!\0", 10, 4, 3);
+    assert_rewind_matches(",[.,]", b"Hello, World!\0", 10, 10, 2);
+}
+
+#[test]
+fn test_recording_budget() {
+    let insts = parse("+++++>+++++").unwrap();
+    let mut interp = Interp::new(&insts, 2);
+    interp.set_recording(Some(2 * mem::size_of::<(usize, Undo)>()));
+    interp.run(io::empty(), io::sink()).unwrap();
+
+    assert_eq!(interp.history_len(), 2);
+    assert_eq!(interp.last_step(), Some((2, Some(0))));
+    assert!(interp.step_back());
+    assert!(interp.step_back());
+    assert!(!interp.step_back());
+    assert_eq!((interp.tape(), interp.ptr(), interp.pc()), (&[5, 0][..], 0, 1));
+
+    // without recording there's nothing to go back to
+    let mut interp = Interp::new(&insts, 2);
+    interp.run(io::empty(), io::sink()).unwrap();
+    assert!(!interp.step_back());
+}
+
+#[cfg(test)]
+fn run_detecting_livelock(source: &str, input: &[u8]) -> Result<Vec<u8>, RuntimeError> {
+    let insts = parse(source).unwrap();
+    let mut interp = Interp::new(&insts, 30_000);
+    interp.set_detect_livelock(true);
+
+    let mut output = Vec::new();
+    interp.run(input, &mut output).map(|_| output)
+}
+
+#[test]
+fn test_livelock() {
+    for &(source, inst_index) in &[("+[]", 1), ("+[>+-<]", 1), (">+[>[-]+<]", 2), ("+[-+[[-]+]]", 4)] {
+        match run_detecting_livelock(source, b"") {
+            Err(RuntimeError::NonTerminatingLoop { inst_index: i }) => assert_eq!(i, inst_index),
+            other => panic!("{} not detected: {:?}", source, other),
+        }
+    }
+}
+
+#[test]
+fn test_livelock_false_positives() {
+    // long running, the inner loop always starts from the same state but the
+    // outer one never does
+    let counting = "-[>-[>+<-]>[<+>-]<<-]>>.";
+    assert_eq!(run_detecting_livelock(counting, b"").unwrap(), b"\x00");
+
+    // the state repeats, but consuming input changes what happens next
+    assert_eq!(run_detecting_livelock(",[.,]", b"aaa\x00").unwrap(), b"aaa");
+
+    assert_eq!(
+        run_detecting_livelock(include_str!("../tests/fixtures/rot13.b"), b"Hello").unwrap(),
+        b"Uryyb"
+    );
+    assert_eq!(
+        run_detecting_livelock(include_str!("../tests/fixtures/hello.b"), b"").unwrap(),
+        b"Hello World!\n"
+    );
+}
+
+#[test]
+fn test_interp_bounds() {
+    let insts = parse("+<").unwrap();
+    match Interp::new(&insts, 4).run(io::empty(), io::sink()) {
+        Err(RuntimeError::PointerUnderflow { inst_index: 1 }) => {}
+        other => panic!("unexpected {:?}", other),
+    }
+
+    let insts = parse(">>>+>").unwrap();
+    match Interp::new(&insts, 4).run(io::empty(), io::sink()) {
+        Err(RuntimeError::PointerOverflow { inst_index: 2 }) => {}
+        other => panic!("unexpected {:?}", other),
+    }
+}
@@ -0,0 +1,76 @@
+use std::cell::UnsafeCell;
+use std::io;
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use libc;
+
+
+// Set by the first Ctrl-C, runs given it as their cancel flag stop the next
+// time a loop goes round
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+// The handler in place before ours. Written before ours is installed and
+// only read by it.
+struct Previous(UnsafeCell<Option<libc::sigaction>>);
+
+unsafe impl Sync for Previous {}
+
+static PREVIOUS: Previous = Previous(UnsafeCell::new(None));
+
+// The flag the first Ctrl-C sets
+pub fn flag() -> &'static AtomicBool {
+    &INTERRUPTED
+}
+
+extern "C" fn handle_interrupt(_: libc::c_int) {
+    // only async-signal-safe calls in here. The previous handler takes the
+    // next Ctrl-C, which ends the process if shutting down hangs, restoring
+    // the terminal if `RawInput` put its handler there.
+    INTERRUPTED.store(true, Ordering::SeqCst);
+    unsafe {
+        if let Some(ref previous) = *PREVIOUS.0.get() {
+            libc::sigaction(libc::SIGINT, previous, ptr::null_mut());
+        }
+    }
+}
+
+// Turns the first Ctrl-C into setting `flag` for as long as it's alive,
+// instead of ending the process. The handler is restored when dropped, so
+// this has to go before anything it was installed after.
+pub struct InterruptGuard {
+    previous: libc::sigaction,
+}
+
+impl InterruptGuard {
+    pub fn install() -> io::Result<InterruptGuard> {
+        unsafe {
+            let mut previous: libc::sigaction = mem::zeroed();
+            if libc::sigaction(libc::SIGINT, ptr::null(), &mut previous) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            *PREVIOUS.0.get() = Some(previous);
+
+            let mut action: libc::sigaction = mem::zeroed();
+            action.sa_sigaction = handle_interrupt as *const () as libc::sighandler_t;
+            // a program waiting for input keeps waiting, it's interrupted
+            // once it gets some and loops again
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            if libc::sigaction(libc::SIGINT, &action, ptr::null_mut()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(InterruptGuard { previous })
+        }
+    }
+}
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        unsafe {
+            libc::sigaction(libc::SIGINT, &self.previous, ptr::null_mut());
+        }
+    }
+}
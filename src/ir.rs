@@ -0,0 +1,220 @@
+// A line-oriented text form of the instructions, for looking at what the
+// optimizer made of a program, editing it and running the result:
+//
+//   # comments run to the end of the line
+//   move +3      `>>>`, `move -3` is `<<<`
+//   add +5       `+++++`, `add -5` is `-----`
+//   print        `.`
+//   read         `,`
+//   loop {       `[`
+//   }            `]`
+//   host         `%`, a host call
+//
+// Amounts keep their sign even when they are 0, so that every instruction
+// comes back as it was. A missing sign is a `+`. Loop bodies are indented
+// by four spaces, which is only for reading them.
+use std::fmt;
+use std::io::{self, Write};
+
+use brainfuck::{relink, verify, CompileError, ExtOp, Inst};
+use brainfuck::Inst::*;
+
+
+const INDENT: &str = "    ";
+
+#[derive(Debug)]
+pub enum IrError {
+    // What's wrong with the 1-based line `line`
+    Syntax { line: usize, message: String },
+    Invalid(CompileError),
+}
+
+impl fmt::Display for IrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            IrError::Syntax { line, ref message } => write!(f, "line {}: {}", line, message),
+            IrError::Invalid(ref err) => write!(f, "invalid program: {}", err),
+        }
+    }
+}
+
+impl ::std::error::Error for IrError {}
+
+pub fn write<W: Write>(insts: &[Inst], mut out: W) -> io::Result<()> {
+    let mut depth = 0;
+    for inst in insts {
+        if let JmpBack(_) = *inst {
+            depth -= 1;
+        }
+        let indent = INDENT.repeat(depth);
+        match *inst {
+            IncPtr(a) => writeln!(out, "{}move +{}", indent, a)?,
+            DecPtr(a) => writeln!(out, "{}move -{}", indent, a)?,
+            IncVal(a) => writeln!(out, "{}add +{}", indent, a)?,
+            DecVal(a) => writeln!(out, "{}add -{}", indent, a)?,
+            PrintCell => writeln!(out, "{}print", indent)?,
+            ReadChar => writeln!(out, "{}read", indent)?,
+            JmpFwd(_) => {
+                writeln!(out, "{}loop {{", indent)?;
+                depth += 1;
+            }
+            JmpBack(_) => writeln!(out, "{}}}", indent)?,
+            Ext(op) => writeln!(out, "{}{}", indent, op.name())?,
+            HostCall => writeln!(out, "{}host", indent)?,
+        }
+    }
+
+    Ok(())
+}
+
+pub fn to_string(insts: &[Inst]) -> String {
+    let mut text = Vec::new();
+    write(insts, &mut text).unwrap();
+    String::from_utf8(text).unwrap()
+}
+
+// `+5`, `5` or `-5` as whether it's negative and the amount
+fn amount(token: &str) -> Option<(bool, usize)> {
+    let (negative, digits) = match token.as_bytes().first() {
+        Some(b'-') => (true, &token[1..]),
+        Some(b'+') => (false, &token[1..]),
+        _ => (false, token),
+    };
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    digits.parse().ok().map(|amount| (negative, amount))
+}
+
+// The instructions `text` describes, verified like any other program
+pub fn parse(text: &str) -> Result<Vec<Inst>, IrError> {
+    let mut insts = Vec::new();
+    // the lines of the loops that are open
+    let mut open = Vec::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let line_number = i + 1;
+        let syntax = |message: String| IrError::Syntax { line: line_number, message };
+        let code = line.split('#').next().unwrap();
+        let tokens: Vec<&str> = code.split_whitespace().collect();
+
+        let inst = match tokens[..] {
+            [] => continue,
+            ["move", n] | ["add", n] => {
+                let (negative, a) = amount(n).ok_or_else(|| syntax(format!("invalid amount '{}'", n)))?;
+                match (tokens[0], negative) {
+                    ("move", false) => IncPtr(a),
+                    ("move", true) => DecPtr(a),
+                    (_, false) => IncVal(a),
+                    (_, true) => DecVal(a),
+                }
+            }
+            ["print"] => PrintCell,
+            ["read"] => ReadChar,
+            ["loop", "{"] => {
+                open.push(line_number);
+                JmpFwd(0)
+            }
+            ["}"] => {
+                if open.pop().is_none() {
+                    return Err(syntax("'}' without a loop to close".to_string()));
+                }
+                JmpBack(0)
+            }
+            [name] if ExtOp::from_name(name).is_some() => Ext(ExtOp::from_name(name).unwrap()),
+            ["host"] => HostCall,
+            ["move"] | ["add"] => return Err(syntax(format!("'{}' needs an amount", tokens[0]))),
+            ["loop"] => return Err(syntax("'loop' needs a '{'".to_string())),
+            ["loop", "{", extra, ..] | ["move", _, extra, ..] | ["add", _, extra, ..]
+                | ["print", extra, ..] | ["read", extra, ..] | ["loop", extra, ..] | ["}", extra, ..]
+                | ["host", extra, ..] => {
+                return Err(syntax(format!("unexpected '{}' after '{}'", extra, tokens[0])));
+            }
+            [other, ..] => return Err(syntax(format!("unknown instruction '{}'", other))),
+        };
+        insts.push(inst);
+    }
+
+    if let Some(line) = open.pop() {
+        return Err(IrError::Syntax { line, message: "loop isn't closed".to_string() });
+    }
+    relink(&mut insts);
+    verify(&insts).map_err(IrError::Invalid)?;
+
+    Ok(insts)
+}
+
+
+#[cfg(test)]
+use brainfuck::parse as parse_source;
+#[cfg(test)]
+use optimize::optimize;
+
+#[test]
+fn test_write() {
+    let mut insts = vec![
+        IncPtr(3), DecPtr(0), IncVal(5), DecVal(1), JmpFwd(0), ReadChar, JmpFwd(0), PrintCell, JmpBack(0), JmpBack(0),
+    ];
+    relink(&mut insts);
+    assert_eq!(to_string(&insts), "\
+move +3
+move -0
+add +5
+add -1
+loop {
+    read
+    loop {
+        print
+    }
+}
+");
+}
+
+#[test]
+fn test_round_trip() {
+    let fixtures = [
+        include_str!("../tests/programs/hello.b"),
+        include_str!("../tests/programs/rot13.b"),
+        include_str!("../tests/programs/squares.b"),
+        include_str!("../tests/programs/sierpinski.b"),
+        include_str!("../tests/fixtures/branch.b"),
+        include_str!("../tests/fixtures/livelock.b"),
+        include_str!("../tests/fixtures/unoptimized.b"),
+    ];
+    for source in &fixtures {
+        let insts = parse_source(source).unwrap();
+        for insts in &[insts.clone(), optimize(insts)] {
+            assert_eq!(&parse(&to_string(insts)).unwrap(), insts);
+        }
+    }
+
+    // every variant, including amounts of 0 that only the sign tells apart
+    let mut insts = vec![IncPtr(0), DecPtr(0), IncVal(0), DecVal(0), JmpFwd(0), PrintCell, ReadChar, JmpBack(0)];
+    relink(&mut insts);
+    assert_eq!(parse(&to_string(&insts)).unwrap(), insts);
+}
+
+#[test]
+fn test_parse() {
+    let text = "  # a comment\n\nmove 2 # unsigned\nadd -3\nloop {\n}\n";
+    assert_eq!(parse(text).unwrap(), [IncPtr(2), DecVal(3), JmpFwd(3), JmpBack(2)]);
+
+    let errors = [
+        ("add +1\nmove\n", "line 2: 'move' needs an amount"),
+        ("add x\n", "line 1: invalid amount 'x'"),
+        ("add --1\n", "line 1: invalid amount '--1'"),
+        ("add 99999999999999999999999\n", "line 1: invalid amount '99999999999999999999999'"),
+        ("print now\n", "line 1: unexpected 'now' after 'print'"),
+        ("loop\n", "line 1: 'loop' needs a '{'"),
+        ("loop (\n}\n", "line 1: unexpected '(' after 'loop'"),
+        ("add 1 2\n", "line 1: unexpected '2' after 'add'"),
+        ("loop { print\n}\n", "line 1: unexpected 'print' after 'loop'"),
+        ("jump 3\n", "line 1: unknown instruction 'jump'"),
+        ("loop {\n}\n}\n", "line 3: '}' without a loop to close"),
+        ("loop {\nloop {\n}\n", "line 1: loop isn't closed"),
+    ];
+    for &(text, message) in &errors {
+        assert_eq!(parse(text).unwrap_err().to_string(), message, "{:?}", text);
+    }
+}
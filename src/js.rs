@@ -0,0 +1,184 @@
+// Programs as JavaScript, for `compile --emit js`, to run in a page or under
+// node without a wasm toolchain. The module's `run` takes the input as a
+// Uint8Array and returns what the program printed as another one. It's
+// written from the optimized instructions, a statement per instruction,
+// with the loops that clear a cell as one.
+//
+// Cells wrap like in the default `ArithMode::Wrap`, and the pointer moving
+// off the tape throws a RangeError with the message of the `RuntimeError`
+// the other engines fail with.
+use std::io::{self, Write};
+
+use brainfuck::{ExtOp, Inst};
+use brainfuck::Inst::*;
+
+
+const INDENT: &str = "    ";
+
+// How the module makes `run` available
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JsFormat {
+    // as `require(...).run` under node and as the global `brainfuck.run`
+    // in a page
+    Umd,
+    // as an export, for `import`
+    Esm,
+}
+
+impl JsFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            JsFormat::Umd => "js",
+            JsFormat::Esm => "mjs",
+        }
+    }
+}
+
+// The statements of the instructions from `insts[start]` up to the end of
+// the loop they're in, or of all of them, each line `depth` levels deep.
+// Returns where it stopped, after the `JmpBack` of the loop.
+fn write_block<W: Write>(
+    insts: &[Inst], start: usize, tape_size: usize, depth: usize, out: &mut W
+) -> io::Result<usize> {
+    let indent = INDENT.repeat(depth);
+    let mut i = start;
+    while i < insts.len() {
+        match insts[i] {
+            IncPtr(a) => {
+                writeln!(out, "{}p += {};", indent, a)?;
+                writeln!(
+                    out, "{}if (p >= {}) throw new RangeError(\"pointer moved past the end of the tape at instruction {}\");",
+                    indent, tape_size, i
+                )?;
+            }
+            DecPtr(a) => {
+                writeln!(out, "{}p -= {};", indent, a)?;
+                writeln!(
+                    out, "{}if (p < 0) throw new RangeError(\"pointer moved below the start of the tape at instruction {}\");",
+                    indent, i
+                )?;
+            }
+            IncVal(a) => writeln!(out, "{}tape[p] = (tape[p] + {}) & 0xff;", indent, a % 256)?,
+            DecVal(a) => writeln!(out, "{}tape[p] = (tape[p] - {}) & 0xff;", indent, a % 256)?,
+            PrintCell => writeln!(out, "{}output.push(tape[p]);", indent)?,
+            // at the end of the input the cell stays as it is
+            ReadChar => writeln!(out, "{}if (i < input.length) tape[p] = input[i++];", indent)?,
+            JmpFwd(_) => match (insts.get(i + 1), insts.get(i + 2)) {
+                (Some(&IncVal(a)), Some(&JmpBack(_))) | (Some(&DecVal(a)), Some(&JmpBack(_))) if a % 2 == 1 => {
+                    writeln!(out, "{}tape[p] = 0;", indent)?;
+                    i += 3;
+                    continue;
+                }
+                _ => {
+                    writeln!(out, "{}while (tape[p] !== 0) {{", indent)?;
+                    i = write_block(insts, i + 1, tape_size, depth + 1, out)?;
+                    writeln!(out, "{}}}", indent)?;
+                    continue;
+                }
+            },
+            JmpBack(_) => return Ok(i + 1),
+            Ext(op) => match op {
+                ExtOp::End => writeln!(out, "{}return Uint8Array.from(output);", indent)?,
+                ExtOp::Store => writeln!(out, "{}storage = tape[p];", indent)?,
+                ExtOp::Retrieve => writeln!(out, "{}tape[p] = storage;", indent)?,
+                ExtOp::ShiftRight => writeln!(out, "{}tape[p] >>= 1;", indent)?,
+                ExtOp::ShiftLeft => writeln!(out, "{}tape[p] = (tape[p] << 1) & 0xff;", indent)?,
+                ExtOp::Not => writeln!(out, "{}tape[p] = ~tape[p] & 0xff;", indent)?,
+                ExtOp::Xor => writeln!(out, "{}tape[p] ^= storage;", indent)?,
+                ExtOp::And => writeln!(out, "{}tape[p] &= storage;", indent)?,
+                ExtOp::Or => writeln!(out, "{}tape[p] |= storage;", indent)?,
+            },
+            // there's no host to call
+            HostCall => writeln!(
+                out, "{}throw new Error(\"host calls aren't supported at instruction {}\");", indent, i
+            )?,
+        }
+        i += 1;
+    }
+
+    Ok(i)
+}
+
+// `run`, declared `depth` levels deep
+fn write_run<W: Write>(insts: &[Inst], tape_size: usize, depth: usize, out: &mut W) -> io::Result<()> {
+    let (outer, inner) = (INDENT.repeat(depth), INDENT.repeat(depth + 1));
+    writeln!(out, "{}function run(input) {{", outer)?;
+    writeln!(out, "{}input = input || new Uint8Array(0);", inner)?;
+    writeln!(out, "{}const tape = new Uint8Array({});", inner, tape_size)?;
+    writeln!(out, "{}const output = [];", inner)?;
+    writeln!(out, "{}let p = 0, i = 0, storage = 0;", inner)?;
+    write_block(insts, 0, tape_size, depth + 1, out)?;
+    writeln!(out, "{}return Uint8Array.from(output);", inner)?;
+    writeln!(out, "{}}}", outer)
+}
+
+// The module running `insts`, which have to be verified, on a tape of
+// `tape_size` cells
+pub fn write<W: Write>(insts: &[Inst], tape_size: usize, format: JsFormat, mut out: W) -> io::Result<()> {
+    writeln!(out, "// Generated by brainfuck-jit, run(input) takes the input as a Uint8Array")?;
+    writeln!(out, "// and returns what the program printed as another one.")?;
+    match format {
+        JsFormat::Umd => {
+            writeln!(out, "(function (root, factory) {{")?;
+            writeln!(out, "{}if (typeof module === \"object\" && module.exports) {{", INDENT)?;
+            writeln!(out, "{0}{0}module.exports = factory();", INDENT)?;
+            writeln!(out, "{}}} else {{", INDENT)?;
+            writeln!(out, "{0}{0}root.brainfuck = factory();", INDENT)?;
+            writeln!(out, "{}}}", INDENT)?;
+            writeln!(out, "}})(typeof self !== \"undefined\" ? self : this, function () {{")?;
+            writeln!(out, "{}\"use strict\";", INDENT)?;
+            write_run(insts, tape_size, 1, &mut out)?;
+            writeln!(out, "{}return {{ run: run }};", INDENT)?;
+            writeln!(out, "}});")
+        }
+        JsFormat::Esm => {
+            write_run(insts, tape_size, 0, &mut out)?;
+            writeln!(out, "export {{ run }};")?;
+            writeln!(out, "export default run;")
+        }
+    }
+}
+
+pub fn to_string(insts: &[Inst], tape_size: usize, format: JsFormat) -> String {
+    let mut text = Vec::new();
+    write(insts, tape_size, format, &mut text).unwrap();
+    String::from_utf8(text).unwrap()
+}
+
+
+#[test]
+fn test_write() {
+    use brainfuck::parse;
+
+    let insts = parse(",[>+++<[-]]>.").unwrap();
+    assert_eq!(to_string(&insts, 16, JsFormat::Esm), "\
+// Generated by brainfuck-jit, run(input) takes the input as a Uint8Array
+// and returns what the program printed as another one.
+function run(input) {
+    input = input || new Uint8Array(0);
+    const tape = new Uint8Array(16);
+    const output = [];
+    let p = 0, i = 0, storage = 0;
+    if (i < input.length) tape[p] = input[i++];
+    while (tape[p] !== 0) {
+        p += 1;
+        if (p >= 16) throw new RangeError(\"pointer moved past the end of the tape at instruction 2\");
+        tape[p] = (tape[p] + 3) & 0xff;
+        p -= 1;
+        if (p < 0) throw new RangeError(\"pointer moved below the start of the tape at instruction 4\");
+        tape[p] = 0;
+    }
+    p += 1;
+    if (p >= 16) throw new RangeError(\"pointer moved past the end of the tape at instruction 9\");
+    output.push(tape[p]);
+    return Uint8Array.from(output);
+}
+export { run };
+export default run;
+");
+
+    let umd = to_string(&insts, 16, JsFormat::Umd);
+    assert!(umd.contains("\n        module.exports = factory();\n"), "{}", umd);
+    assert!(umd.contains("\n    function run(input) {\n        input = input || new Uint8Array(0);\n"), "{}", umd);
+    assert!(umd.ends_with("\n    }\n    return { run: run };\n});\n"), "{}", umd);
+}
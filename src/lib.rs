@@ -0,0 +1,79 @@
+// The library only exists to embed the JIT, from Rust with the `embed`
+// feature and from C through the interface of the `ffi` feature, or to run
+// programs in the interpreter in a browser with the `wasm` feature, which
+// leaves the JIT out when building for WebAssembly. Without any of them
+// there's nothing in here, the command line tool builds the same modules
+// itself.
+#[cfg(all(any(feature = "embed", feature = "wasm"), not(target_family = "wasm")))]
+extern crate mmap;
+#[cfg(all(any(feature = "embed", feature = "wasm"), not(target_family = "wasm")))]
+extern crate libc;
+#[cfg(all(any(feature = "embed", feature = "wasm"), feature = "bignum"))]
+extern crate num_bigint;
+#[cfg(all(any(feature = "embed", feature = "wasm"), feature = "bignum"))]
+extern crate num_traits;
+#[cfg(all(any(feature = "embed", feature = "wasm"), feature = "tracing"))]
+extern crate tracing;
+#[cfg(all(test, feature = "embed"))]
+#[macro_use]
+extern crate brainfuck_macros;
+
+// first, for its macros to be there in all the others
+#[cfg(any(feature = "embed", feature = "wasm"))]
+#[macro_use]
+mod instrument;
+
+#[cfg(any(feature = "embed", feature = "wasm"))]
+#[allow(dead_code)]
+mod runlength;
+#[cfg(all(any(feature = "embed", feature = "wasm"), not(target_family = "wasm")))]
+#[allow(dead_code)]
+mod arena;
+#[cfg(any(feature = "embed", feature = "wasm"))]
+pub mod ast;
+#[cfg(all(any(feature = "embed", feature = "wasm"), feature = "bignum"))]
+pub mod bignum;
+#[cfg(feature = "embed")]
+pub mod builder;
+#[cfg(all(feature = "embed", not(target_family = "wasm")))]
+pub mod cache;
+#[cfg(any(feature = "embed", feature = "wasm"))]
+pub mod dump;
+#[cfg(all(any(feature = "embed", feature = "wasm"), not(target_family = "wasm")))]
+mod fault;
+#[cfg(feature = "embed")]
+pub mod generator;
+#[cfg(any(feature = "embed", feature = "wasm"))]
+pub mod host;
+#[cfg(feature = "embed")]
+pub mod ir;
+#[cfg(any(feature = "embed", feature = "wasm"))]
+#[allow(dead_code)]
+pub mod interp;
+#[cfg(any(feature = "embed", feature = "wasm"))]
+#[allow(dead_code)]
+mod memory;
+#[cfg(any(feature = "embed", feature = "wasm"))]
+#[allow(dead_code)]
+mod perfmap;
+#[cfg(all(any(feature = "embed", feature = "wasm"), target_os = "linux"))]
+#[allow(dead_code)]
+mod sandbox;
+#[cfg(any(feature = "embed", feature = "wasm"))]
+pub mod optimize;
+#[cfg(any(feature = "embed", feature = "wasm"))]
+pub mod runstats;
+#[cfg(any(feature = "embed", feature = "wasm"))]
+#[cfg_attr(target_family = "wasm", allow(dead_code))]
+mod tape;
+#[cfg(feature = "embed")]
+pub mod twosided;
+
+#[cfg(any(feature = "embed", feature = "wasm"))]
+#[allow(dead_code)]
+pub mod brainfuck;
+
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "wasm")]
+pub mod wasm;
@@ -0,0 +1,308 @@
+// A classic compiler listing: every line of the source, followed by the
+// instructions starting on it and the code generated for each of them,
+// disassembled. Loops are named like in `dump::write_labeled`, brackets
+// name the line of the other one if it's on a different line.
+//
+//      1  +[->+<
+//          0: IncVal(1)
+//             0003  fe 06                  inc byte [rsi]
+//          1: L0: [  ; ] on line 3
+//             0005  80 3e 00               cmp byte [rsi], 0
+//             0008  0f 84 33 00 00 00      je 0x0041
+//     ...
+//      4  [-]
+//          (eliminated)
+//
+// A line with commands none of which made it into an instruction is
+// marked as eliminated, a line of comments is just the line. The code
+// before the first line and after the last one comes as the prologue,
+// epilogue and the stubs behind it.
+use std::io::{self, Write};
+
+use brainfuck::{is_command, Inst, Span};
+use brainfuck::Inst::*;
+use dump::loop_labels;
+
+
+// The instructions of the code generator as bytes, padded to the longest
+const BYTES_WIDTH: usize = 3 * 7;
+
+const CONDITIONS: [&str; 16] = [
+    "jo", "jno", "jb", "jae", "je", "jne", "jbe", "ja", "js", "jns", "jp", "jnp", "jl", "jge", "jle", "jg",
+];
+
+// Decodes the machine instruction at `at`, one of those the code generator
+// emits: how long it is and how it reads as assembly. Anything else is a
+// single byte, `db 0x..`. Jump targets are offsets into `code`.
+pub fn disassemble(code: &[u8], at: usize) -> (usize, String) {
+    let bytes = &code[at..];
+    let imm32 = |i: usize| bytes.get(i..i + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+    let target = |len: usize, rel: u32| (at + len) as isize + rel as i32 as isize;
+
+    let decoded = match *bytes {
+        [0x49, 0x89, 0xf9, ..] => Some((3, "mov r9, rdi".to_string())),
+        [0x48, 0xff, 0xc6, ..] => Some((3, "inc rsi".to_string())),
+        [0x48, 0xff, 0xce, ..] => Some((3, "dec rsi".to_string())),
+        [0x48, 0x81, 0xc6, ..] => imm32(3).map(|n| (7, format!("add rsi, {}", n))),
+        [0x48, 0x81, 0xee, ..] => imm32(3).map(|n| (7, format!("sub rsi, {}", n))),
+        [0x48, 0x31, 0xc0, ..] => Some((3, "xor rax, rax".to_string())),
+        [0x48, 0x89, 0xf2, ..] => Some((3, "mov rdx, rsi".to_string())),
+        [0x49, 0x8b, 0x01, ..] => Some((3, "mov rax, [r9]".to_string())),
+        [0x49, 0x8b, 0x51, disp, ..] => Some((4, format!("mov rdx, [r9 + {}]", disp))),
+        [0x48, 0x8d, 0x46, disp, ..] => Some((4, format!("lea rax, [rsi {:+}]", disp as i8))),
+        [0x48, 0x8d, 0x86, ..] => imm32(3).map(|n| (7, format!("lea rax, [rsi {:+}]", n as i32))),
+        [0x49, 0x3b, 0x41, disp, ..] => Some((4, format!("cmp rax, [r9 + {}]", disp))),
+        [0x48, 0x89, 0xf7, ..] => Some((3, "mov rdi, rsi".to_string())),
+        [0x48, 0x89, 0xc7, ..] => Some((3, "mov rdi, rax".to_string())),
+        [0x48, 0x89, 0xc6, ..] => Some((3, "mov rsi, rax".to_string())),
+        [0xf3, 0xaa, ..] => Some((2, "rep stosb".to_string())),
+        [0x48, 0x8d, 0x34, 0x08, ..] => Some((4, "lea rsi, [rax + rcx]".to_string())),
+        [0x48, 0x39, 0xd0, ..] => Some((3, "cmp rax, rdx".to_string())),
+        [0x48, 0x83, 0xf8, n, ..] => Some((4, format!("cmp rax, {}", n as i8))),
+        [0x48, 0x85, 0xc0, ..] => Some((3, "test rax, rax".to_string())),
+        [0x48, 0xf7, 0xd8, ..] => Some((3, "neg rax".to_string())),
+        [0xf7, 0xd8, ..] => Some((2, "neg eax".to_string())),
+        [0x41, 0x89, 0x41, disp, ..] => Some((4, format!("mov [r9 + {}], eax", disp))),
+        [0x48, 0x01, 0xc6, ..] => Some((3, "add rsi, rax".to_string())),
+        [0x48, 0x83, 0xc6, n, ..] => Some((4, format!("add rsi, {}", n))),
+        [0x48, 0x83, 0xee, n, ..] => Some((4, format!("sub rsi, {}", n))),
+        [0x66, 0x0f, 0xef, 0xc0, ..] => Some((4, "pxor xmm0, xmm0".to_string())),
+        [0xf3, 0x0f, 0x6f, 0x0e, ..] => Some((4, "movdqu xmm1, [rsi]".to_string())),
+        [0xf3, 0x0f, 0x6f, 0x08, ..] => Some((4, "movdqu xmm1, [rax]".to_string())),
+        [0x66, 0x0f, 0x74, 0xc8, ..] => Some((4, "pcmpeqb xmm1, xmm0".to_string())),
+        [0x66, 0x0f, 0xd7, 0xc1, ..] => Some((4, "pmovmskb eax, xmm1".to_string())),
+        [0x66, 0x0f, 0xd7, 0xc9, ..] => Some((4, "pmovmskb ecx, xmm1".to_string())),
+        [0x85, 0xc0, ..] => Some((2, "test eax, eax".to_string())),
+        [0x85, 0xc9, ..] => Some((2, "test ecx, ecx".to_string())),
+        [0x0f, 0xbc, 0xc0, ..] => Some((3, "bsf eax, eax".to_string())),
+        [0x0f, 0xbd, 0xc9, ..] => Some((3, "bsr ecx, ecx".to_string())),
+        [0x49, 0x3b, 0x71, disp, ..] => Some((4, format!("cmp rsi, [r9 + {}]", disp))),
+        [0x41, 0x8b, 0x79, disp, ..] => Some((4, format!("mov edi, [r9 + {}]", disp))),
+        [0xfe, 0x06, ..] => Some((2, "inc byte [rsi]".to_string())),
+        [0xfe, 0x0e, ..] => Some((2, "dec byte [rsi]".to_string())),
+        [0x80, 0x06, n, ..] => Some((3, format!("add byte [rsi], {}", n))),
+        [0x80, 0x2e, n, ..] => Some((3, format!("sub byte [rsi], {}", n))),
+        [0x80, 0x3e, n, ..] => Some((3, format!("cmp byte [rsi], {}", n))),
+        [0x80, 0x38, n, ..] => Some((3, format!("cmp byte [rax], {}", n))),
+        [0x0f, 0xb6, 0x06, ..] => Some((3, "movzx eax, byte [rsi]".to_string())),
+        [0x88, 0x06, ..] => Some((2, "mov [rsi], al".to_string())),
+        [0x39, 0xc8, ..] => Some((2, "cmp eax, ecx".to_string())),
+        [0x0f, 0x47, 0xc1, ..] => Some((3, "cmova eax, ecx".to_string())),
+        [0x0f, 0x42, 0xc1, ..] => Some((3, "cmovb eax, ecx".to_string())),
+        [0x0f, 0x05, ..] => Some((2, "syscall".to_string())),
+        [0x0f, cc @ 0x80..=0x8f, ..] => {
+            imm32(2).map(|rel| (6, format!("{} 0x{:04x}", CONDITIONS[(cc & 0xf) as usize], target(6, rel))))
+        }
+        [0x04, n, ..] => Some((2, format!("add al, {}", n))),
+        [0x2c, n, ..] => Some((2, format!("sub al, {}", n))),
+        [0x05, ..] => imm32(1).map(|n| (5, format!("add eax, {}", n))),
+        [0x2d, ..] => imm32(1).map(|n| (5, format!("sub eax, {}", n))),
+        [0x3d, ..] => imm32(1).map(|n| (5, format!("cmp eax, {}", n))),
+        [0xb8, ..] => imm32(1).map(|n| (5, format!("mov eax, {}", n))),
+        [0xb9, ..] => imm32(1).map(|n| (5, format!("mov ecx, {}", n))),
+        [0xba, ..] => imm32(1).map(|n| (5, format!("mov edx, {}", n))),
+        [cc @ 0x70..=0x7f, rel, ..] => {
+            Some((2, format!("{} 0x{:04x}", CONDITIONS[(cc & 0xf) as usize], target(2, rel as i8 as i32 as u32))))
+        }
+        [0xeb, rel, ..] => Some((2, format!("jmp 0x{:04x}", target(2, rel as i8 as i32 as u32)))),
+        [0xe9, ..] => imm32(1).map(|rel| (5, format!("jmp 0x{:04x}", target(5, rel)))),
+        [0x31, 0xc0, ..] => Some((2, "xor eax, eax".to_string())),
+        [0xc3, ..] => Some((1, "ret".to_string())),
+        _ => None,
+    };
+
+    decoded.unwrap_or_else(|| (1, format!("db 0x{:02x}", bytes[0])))
+}
+
+// The code from `start` to `end` an instruction a line, returning where
+// the last one ends, which is past `end` if it doesn't end there
+fn write_code<W: Write>(code: &[u8], start: usize, end: usize, out: &mut W) -> io::Result<usize> {
+    let mut at = start;
+    while at < end {
+        let (len, text) = disassemble(code, at);
+        let bytes: Vec<String> = code[at..at + len].iter().map(|b| format!("{:02x}", b)).collect();
+        writeln!(out, "          {:04x}  {:<width$}  {}", at, bytes.join(" "), text, width = BYTES_WIDTH)?;
+        at += len;
+    }
+
+    Ok(at)
+}
+
+// The listing of `insts` as optimized from `source`, with their `spans`
+// in it. `code` and `offsets` are the generated code and where every
+// instruction's code starts, see `compile_insts_with_offsets`.
+pub fn write<W: Write>(
+    source: &str, insts: &[Inst], spans: &[Span], code: &[u8], offsets: &[u32], mut out: W
+) -> io::Result<()> {
+    let labels = loop_labels(insts);
+
+    let mut line_starts = vec![0];
+    line_starts.extend(source.match_indices('\n').map(|(i, _)| i + 1));
+    let line_of = |offset: usize| line_starts.partition_point(|&start| start <= offset);
+
+    // the bytes of the source that made it into an instruction
+    let mut covered = vec![false; source.len()];
+    for span in spans {
+        covered[span.start..span.end].iter_mut().for_each(|c| *c = true);
+    }
+
+    writeln!(out, "       (prologue)")?;
+    write_code(code, 0, offsets[0] as usize, &mut out)?;
+
+    let mut i = 0;
+    for (n, &start) in line_starts.iter().enumerate() {
+        let end = line_starts.get(n + 1).cloned().unwrap_or(source.len());
+        let line = source[start..end].trim_end_matches(['\n', '\r']);
+        if end == start && n > 0 {
+            // nothing after the last newline
+            break;
+        }
+        writeln!(out, "{}", format!("{:>4}  {}", n + 1, line).trim_end())?;
+
+        let commands = line.char_indices().filter(|&(_, c)| is_command(c));
+        if commands.clone().next().is_some() && commands.clone().all(|(j, _)| !covered[start + j]) {
+            writeln!(out, "       (eliminated)")?;
+        }
+
+        while i < insts.len() && spans[i].start < end {
+            let other_line = |j: usize| Some(line_of(spans[j].start)).filter(|&line| line != n + 1);
+            let text = match (&insts[i], labels[i]) {
+                (&JmpFwd(close), Some(label)) => match other_line(close) {
+                    Some(line) => format!("L{}: [  ; ] on line {}", label, line),
+                    None => format!("L{}: [", label),
+                },
+                (&JmpBack(open), Some(label)) => match other_line(open) {
+                    Some(line) => format!("] -> L{}  ; [ on line {}", label, line),
+                    None => format!("] -> L{}", label),
+                },
+                (inst, _) => format!("{:?}", inst),
+            };
+            writeln!(out, "       {}: {}", i, text)?;
+            write_code(code, offsets[i] as usize, offsets[i + 1] as usize, &mut out)?;
+            i += 1;
+        }
+    }
+
+    // the end of the program runs into the only `ret`, the stubs the
+    // checks jump to follow it
+    let end = offsets[insts.len()] as usize;
+    let ret = code[end..].iter().position(|&b| b == 0xc3).map_or(code.len(), |at| end + at + 1);
+    writeln!(out, "       (epilogue)")?;
+    write_code(code, end, ret, &mut out)?;
+    if ret < code.len() {
+        writeln!(out, "       (stubs)")?;
+        write_code(code, ret, code.len(), &mut out)?;
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+use brainfuck::{compile_insts_with_offsets, parse_with_spans, CodegenOptions};
+#[cfg(test)]
+use brainfuck::ArithMode;
+#[cfg(test)]
+use optimize::optimize_with_spans;
+
+#[cfg(test)]
+fn listing(source: &str, options: &CodegenOptions) -> String {
+    let (insts, spans) = parse_with_spans(source).unwrap();
+    let (insts, spans) = optimize_with_spans(insts, spans, options.arith);
+    let (code, offsets) = compile_insts_with_offsets(&insts, options).unwrap();
+    let mut out = Vec::new();
+    write(source, &insts, &spans, &code, &offsets, &mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn test_disassemble() {
+    let mut code = Vec::new();
+    for arith in &[ArithMode::Wrap, ArithMode::Saturate, ArithMode::Trap] {
+        let options = CodegenOptions { arith: *arith, ..CodegenOptions::default() };
+        let (insts, _) = parse_with_spans("+>>-<<,+++.---[-][>][<][-]>[-]>[-]>[-]<[-]<[-]<[-]+-+").unwrap();
+        code.extend(compile_insts_with_offsets(&insts, &options).unwrap().0);
+    }
+    let options = CodegenOptions { fragment: true, ..CodegenOptions::default() };
+    code.extend(compile_insts_with_offsets(&[], &options).unwrap().0);
+
+    // everything the code generator emits is known
+    let mut at = 0;
+    while at < code.len() {
+        let (len, text) = disassemble(&code, at);
+        assert!(!text.starts_with("db"), "unknown instruction at {}: {:02x?}", at, &code[at..]);
+        at += len;
+    }
+
+    assert_eq!(disassemble(&[0x90, 0x0f, 0x84, 0xf9, 0xff, 0xff, 0xff], 1), (6, "je 0x0000".to_string()));
+    assert_eq!(disassemble(&[0x90, 0x0f, 0x84, 0x10, 0x00, 0x00, 0x00], 1), (6, "je 0x0017".to_string()));
+    assert_eq!(disassemble(&[0x90, 0x77, 0xfd], 1), (2, "ja 0x0000".to_string()));
+    assert_eq!(disassemble(&[0x0f, 0x84, 0x10], 0), (1, "db 0x0f".to_string()));
+    assert_eq!(disassemble(&[0xcc], 0), (1, "db 0xcc".to_string()));
+}
+
+#[test]
+fn test_listing() {
+    let source = "-[->+<\n  comment\n]\n[-]\n>>\n<<.\n";
+    let expected = "\
+\x20      (prologue)
+          0000  49 89 f9               mov r9, rdi
+   1  -[->+<
+       0: DecVal(1)
+          0003  fe 0e                  dec byte [rsi]
+       1: L0: [  ; ] on line 3
+          0005  80 3e 00               cmp byte [rsi], 0
+          0008  0f 84 27 00 00 00      je 0x0035
+       2: DecVal(1)
+          000e  fe 0e                  dec byte [rsi]
+       3: IncPtr(1)
+          0010  48 ff c6               inc rsi
+          0013  49 3b 71 18            cmp rsi, [r9 + 24]
+          0017  0f 83 49 00 00 00      jae 0x0066
+       4: IncVal(1)
+          001d  fe 06                  inc byte [rsi]
+       5: DecPtr(1)
+          001f  48 ff ce               dec rsi
+          0022  49 3b 71 10            cmp rsi, [r9 + 16]
+          0026  0f 82 49 00 00 00      jb 0x0075
+   2    comment
+   3  ]
+       6: ] -> L0  ; [ on line 1
+          002c  80 3e 00               cmp byte [rsi], 0
+          002f  0f 85 d9 ff ff ff      jne 0x000e
+   4  [-]
+       (eliminated)
+   5  >>
+       (eliminated)
+   6  <<.
+       7: PrintCell
+          0035  b8 01 00 00 00         mov eax, 1
+          003a  41 8b 79 0c            mov edi, [r9 + 12]
+          003e  ba 01 00 00 00         mov edx, 1
+          0043  0f 05                  syscall
+          0045  48 83 f8 fc            cmp rax, -4
+          0049  74 ea                  je 0x0035
+          004b  48 83 f8 f5            cmp rax, -11
+          004f  74 e4                  je 0x0035
+          0051  48 85 c0               test rax, rax
+          0054  74 df                  je 0x0035
+          0056  79 0b                  jns 0x0063
+          0058  f7 d8                  neg eax
+          005a  41 89 41 24            mov [r9 + 36], eax
+          005e  e9 21 00 00 00         jmp 0x0084
+       (epilogue)
+          0063  31 c0                  xor eax, eax
+          0065  c3                     ret
+       (stubs)
+          0066  ba 03 00 00 00         mov edx, 3
+          006b  b8 02 00 00 00         mov eax, 2
+          0070  e9 f0 ff ff ff         jmp 0x0065
+          0075  ba 05 00 00 00         mov edx, 5
+          007a  b8 01 00 00 00         mov eax, 1
+          007f  e9 e1 ff ff ff         jmp 0x0065
+          0084  ba 07 00 00 00         mov edx, 7
+          0089  b8 08 00 00 00         mov eax, 8
+          008e  e9 d2 ff ff ff         jmp 0x0065
+";
+    assert_eq!(listing(source, &CodegenOptions::default()), expected);
+}
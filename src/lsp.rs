@@ -0,0 +1,641 @@
+// Just enough of the Language Server Protocol over stdio for editors to
+// show bracket errors as they are typed, highlight the bracket matching the
+// one at the cursor and tell what instruction the code at the cursor is.
+// Documents are synced whole, every change sends all of the text again.
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::str;
+
+use brainfuck::{parse_with_spans, Inst};
+use brainfuck::Inst::*;
+use diagnostics::{self, json_string};
+
+
+// JSON-RPC error codes
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+
+// What `initialize` answers
+const CAPABILITIES: &str = "{\"capabilities\":{\"textDocumentSync\":1,\"hoverProvider\":true,\
+                            \"documentHighlightProvider\":true},\"serverInfo\":{\"name\":\"brainfuck-jit\"}}";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    // Fields in the order they came in
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn parse(text: &str) -> Option<Json> {
+        let mut parser = Parser { text: text.as_bytes(), pos: 0 };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.pos == parser.text.len() {
+            Some(value)
+        } else {
+            None
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match *self {
+            Json::Object(ref fields) => fields.iter().find(|field| field.0 == key).map(|field| &field.1),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Json::String(ref s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_usize(&self) -> Option<usize> {
+        match *self {
+            Json::Number(n) if n >= 0.0 && n.fract() == 0.0 => Some(n as usize),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match *self {
+            Json::Array(ref items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            // ids are integers, which shouldn't turn into 1.0
+            Json::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => write!(f, "{}", n as i64),
+            Json::Number(n) => write!(f, "{}", n),
+            Json::String(ref s) => write!(f, "{}", json_string(s)),
+            Json::Array(ref items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    write!(f, "{}{}", if i == 0 { "" } else { "," }, item)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(ref fields) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    write!(f, "{}{}:{}", if i == 0 { "" } else { "," }, json_string(key), value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_whitespace(&mut self) {
+        while self.text.get(self.pos).is_some_and(|&b| matches!(b, b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn eat(&mut self, literal: &str) -> bool {
+        if self.text[self.pos..].starts_with(literal.as_bytes()) {
+            self.pos += literal.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn value(&mut self) -> Option<Json> {
+        self.skip_whitespace();
+        match *self.text.get(self.pos)? {
+            b'n' if self.eat("null") => Some(Json::Null),
+            b't' if self.eat("true") => Some(Json::Bool(true)),
+            b'f' if self.eat("false") => Some(Json::Bool(false)),
+            b'"' => self.string().map(Json::String),
+            b'[' => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                self.skip_whitespace();
+                if self.eat("]") {
+                    return Some(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    self.skip_whitespace();
+                    if self.eat("]") {
+                        return Some(Json::Array(items));
+                    }
+                    if !self.eat(",") {
+                        return None;
+                    }
+                }
+            }
+            b'{' => {
+                self.pos += 1;
+                let mut fields = Vec::new();
+                self.skip_whitespace();
+                if self.eat("}") {
+                    return Some(Json::Object(fields));
+                }
+                loop {
+                    self.skip_whitespace();
+                    if self.text.get(self.pos) != Some(&b'"') {
+                        return None;
+                    }
+                    let key = self.string()?;
+                    self.skip_whitespace();
+                    if !self.eat(":") {
+                        return None;
+                    }
+                    fields.push((key, self.value()?));
+                    self.skip_whitespace();
+                    if self.eat("}") {
+                        return Some(Json::Object(fields));
+                    }
+                    if !self.eat(",") {
+                        return None;
+                    }
+                }
+            }
+            b'-' | b'0'..=b'9' => {
+                let start = self.pos;
+                while self.text.get(self.pos).is_some_and(|&b| matches!(b, b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')) {
+                    self.pos += 1;
+                }
+                str::from_utf8(&self.text[start..self.pos]).ok()?.parse().ok().map(Json::Number)
+            }
+            _ => None,
+        }
+    }
+
+    // A string from its opening quote on
+    fn string(&mut self) -> Option<String> {
+        self.pos += 1;
+        let mut out = String::new();
+        loop {
+            let start = self.pos;
+            while self.text.get(self.pos).is_some_and(|&b| b != b'"' && b != b'\\') {
+                self.pos += 1;
+            }
+            out.push_str(str::from_utf8(&self.text[start..self.pos]).ok()?);
+            if *self.text.get(self.pos)? == b'"' {
+                self.pos += 1;
+                return Some(out);
+            }
+
+            let escape = *self.text.get(self.pos + 1)?;
+            self.pos += 2;
+            out.push(match escape {
+                b'"' => '"',
+                b'\\' => '\\',
+                b'/' => '/',
+                b'b' => '\u{8}',
+                b'f' => '\u{c}',
+                b'n' => '\n',
+                b'r' => '\r',
+                b't' => '\t',
+                b'u' => {
+                    let mut code = self.hex4()?;
+                    // characters past the first 65536 come as two halves,
+                    // a half on its own isn't a character
+                    if (0xd800..0xdc00).contains(&code) && self.text[self.pos..].starts_with(b"\\u") {
+                        self.pos += 2;
+                        let low = self.hex4()?;
+                        code = if (0xdc00..0xe000).contains(&low) {
+                            0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00)
+                        } else {
+                            0xfffd
+                        };
+                    }
+                    char::from_u32(code).unwrap_or('\u{fffd}')
+                }
+                _ => return None,
+            });
+        }
+    }
+
+    fn hex4(&mut self) -> Option<u32> {
+        let digits = self.text.get(self.pos..self.pos + 4)?;
+        if !digits.iter().all(u8::is_ascii_hexdigit) {
+            return None;
+        }
+        self.pos += 4;
+        u32::from_str_radix(str::from_utf8(digits).ok()?, 16).ok()
+    }
+}
+
+// LSP positions count lines from 0 and characters in UTF-16 code units
+fn position(source: &str, offset: usize) -> String {
+    let before = &source[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    format!(
+        "{{\"line\":{},\"character\":{}}}",
+        before.matches('\n').count(), before[line_start..].encode_utf16().count()
+    )
+}
+
+fn range(source: &str, start: usize, end: usize) -> String {
+    format!("{{\"start\":{},\"end\":{}}}", position(source, start), position(source, end))
+}
+
+// The byte offset of an LSP position, the end of the line for characters
+// past it and the end of the source for lines past it
+fn offset_at(source: &str, line: usize, character: usize) -> usize {
+    let line_start = match line {
+        0 => 0,
+        _ => match source.match_indices('\n').nth(line - 1) {
+            Some((i, _)) => i + 1,
+            None => return source.len(),
+        },
+    };
+
+    let mut units = 0;
+    for (i, c) in source[line_start..].char_indices() {
+        if c == '\n' || units >= character {
+            return line_start + i;
+        }
+        units += c.len_utf16();
+    }
+    source.len()
+}
+
+// How many loops instruction `index` is in, the brackets of a loop aren't
+// in it
+fn loop_depth(insts: &[Inst], index: usize) -> usize {
+    let depth = insts[..index].iter().fold(0, |depth, inst| match *inst {
+        JmpFwd(_) => depth + 1,
+        JmpBack(_) => depth - 1,
+        _ => depth,
+    });
+    match insts[index] {
+        JmpBack(_) => depth - 1,
+        _ => depth,
+    }
+}
+
+fn response(id: &Json, result: &str) -> String {
+    format!("{{\"jsonrpc\":\"2.0\",\"id\":{},\"result\":{}}}", id, result)
+}
+
+fn error_response(id: &Json, code: i32, message: &str) -> String {
+    format!(
+        "{{\"jsonrpc\":\"2.0\",\"id\":{},\"error\":{{\"code\":{},\"message\":{}}}}}",
+        id, code, json_string(message)
+    )
+}
+
+pub struct Server {
+    // Texts of the open documents by their URIs
+    documents: HashMap<String, String>,
+    shut_down: bool,
+}
+
+impl Server {
+    pub fn new() -> Server {
+        Server { documents: HashMap::new(), shut_down: false }
+    }
+
+    // The messages to send for `message`, its response if it's a request
+    // and the notifications it leads to
+    pub fn handle(&mut self, message: &Json) -> Vec<String> {
+        let method = match message.get("method").and_then(Json::as_str) {
+            Some(method) => method,
+            // responses to requests, which the server doesn't make
+            None => return Vec::new(),
+        };
+        let params = message.get("params").unwrap_or(&Json::Null);
+        let id = match message.get("id") {
+            Some(id) => id,
+            // notifications are never answered, not even if they fail
+            None => return self.notification(method, params),
+        };
+        if self.shut_down {
+            return vec![error_response(id, INVALID_REQUEST, "the server is shut down")];
+        }
+
+        let result = match method {
+            "initialize" => CAPABILITIES.to_string(),
+            "shutdown" => {
+                self.shut_down = true;
+                "null".to_string()
+            }
+            "textDocument/hover" => self.hover(params),
+            "textDocument/documentHighlight" => self.highlight(params),
+            _ => return vec![error_response(id, METHOD_NOT_FOUND, &format!("unknown method '{}'", method))],
+        };
+        vec![response(id, &result)]
+    }
+
+    fn notification(&mut self, method: &str, params: &Json) -> Vec<String> {
+        let document = match params.get("textDocument") {
+            Some(document) => document,
+            None => return Vec::new(),
+        };
+        let uri = match document.get("uri").and_then(Json::as_str) {
+            Some(uri) => uri.to_string(),
+            None => return Vec::new(),
+        };
+
+        let text = match method {
+            "textDocument/didOpen" => document.get("text").and_then(Json::as_str),
+            // every change holds all of the text, only the last one counts
+            "textDocument/didChange" => params
+                .get("contentChanges")
+                .and_then(Json::as_array)
+                .and_then(|changes| changes.last())
+                .and_then(|change| change.get("text"))
+                .and_then(Json::as_str),
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                // clears what was published for it
+                return vec![publish(&uri, "[]")];
+            }
+            _ => None,
+        };
+        match text {
+            Some(text) => {
+                self.documents.insert(uri.clone(), text.to_string());
+                vec![publish(&uri, &diagnostics_of(text))]
+            }
+            None => Vec::new(),
+        }
+    }
+
+    // The text of the document of a request and the offset of its position
+    fn cursor(&self, params: &Json) -> Option<(&str, usize)> {
+        let uri = params.get("textDocument")?.get("uri")?.as_str()?;
+        let text = self.documents.get(uri)?;
+        let position = params.get("position")?;
+        let offset = offset_at(text, position.get("line")?.as_usize()?, position.get("character")?.as_usize()?);
+        Some((text.as_str(), offset))
+    }
+
+    // The instruction at the cursor, runs folded as they run, and how deep
+    // in loops it is. Only programs that parse have instructions.
+    fn hover(&self, params: &Json) -> String {
+        self.cursor(params)
+            .and_then(|(text, offset)| {
+                let (insts, spans) = parse_with_spans(text).ok()?;
+                let i = spans.iter().position(|span| span.start <= offset && offset < span.end)?;
+                let value = format!("{:?} at instruction {}, loop depth {}", insts[i], i, loop_depth(&insts, i));
+                Some(format!(
+                    "{{\"contents\":{{\"kind\":\"plaintext\",\"value\":{}}},\"range\":{}}}",
+                    json_string(&value), range(text, spans[i].start, spans[i].end)
+                ))
+            })
+            .unwrap_or_else(|| "null".to_string())
+    }
+
+    // Both brackets of the loop whose bracket is at the cursor
+    fn highlight(&self, params: &Json) -> String {
+        self.cursor(params)
+            .and_then(|(text, offset)| {
+                let (insts, spans) = parse_with_spans(text).ok()?;
+                let i = spans.iter().position(|span| span.start <= offset && offset < span.end)?;
+                let other = match insts[i] {
+                    JmpFwd(n) | JmpBack(n) => n,
+                    _ => return None,
+                };
+                let (open, close) = (i.min(other), i.max(other));
+                Some(format!(
+                    "[{{\"range\":{},\"kind\":1}},{{\"range\":{},\"kind\":1}}]",
+                    range(text, spans[open].start, spans[open].end), range(text, spans[close].start, spans[close].end)
+                ))
+            })
+            .unwrap_or_else(|| "null".to_string())
+    }
+}
+
+fn publish(uri: &str, diagnostics: &str) -> String {
+    format!(
+        "{{\"jsonrpc\":\"2.0\",\"method\":\"textDocument/publishDiagnostics\",\"params\":{{\"uri\":{},\
+         \"diagnostics\":{}}}}}",
+        json_string(uri), diagnostics
+    )
+}
+
+// The bracket errors of `text` as LSP diagnostics
+fn diagnostics_of(text: &str) -> String {
+    let err = match parse_with_spans(text) {
+        Ok(_) => return "[]".to_string(),
+        Err(err) => err,
+    };
+    let diagnostics: Vec<String> = diagnostics::compile_error(text, &err)
+        .iter()
+        .map(|diagnostic| {
+            format!(
+                "{{\"range\":{},\"severity\":1,\"code\":{},\"source\":\"brainfuck\",\"message\":{}}}",
+                range(text, diagnostic.offset.unwrap_or(0), diagnostic.end_offset.unwrap_or(0)),
+                json_string(diagnostic.code), json_string(&diagnostic.message)
+            )
+        })
+        .collect();
+    format!("[{}]", diagnostics.join(","))
+}
+
+// The body of the next message, None at the end of `input`
+fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<String>> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+
+    let mut length = None;
+    let mut headers = 0;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return if headers == 0 { Ok(None) } else { Err(invalid("message ends in its header")) };
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            if headers == 0 {
+                continue;
+            }
+            break;
+        }
+        headers += 1;
+        let (name, value) = header.split_once(':').ok_or_else(|| invalid("malformed header"))?;
+        if name.trim().eq_ignore_ascii_case("content-length") {
+            length = Some(value.trim().parse().map_err(|_| invalid("invalid Content-Length"))?);
+        }
+    }
+
+    let mut body = vec![0; length.ok_or_else(|| invalid("message without a Content-Length"))?];
+    input.read_exact(&mut body)?;
+    String::from_utf8(body).map(Some).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn write_message<W: Write>(output: &mut W, body: &str) -> io::Result<()> {
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+// Answers the messages of `input` until `exit`, and returns the exit code
+// the protocol asks for, 0 only if the server was shut down before
+pub fn serve<R: BufRead, W: Write>(mut input: R, mut output: W) -> io::Result<i32> {
+    let mut server = Server::new();
+    while let Some(body) = read_message(&mut input)? {
+        let messages = match Json::parse(&body) {
+            Some(ref message) if message.get("method").and_then(Json::as_str) == Some("exit") => {
+                return Ok(if server.shut_down { 0 } else { 1 });
+            }
+            Some(message) => server.handle(&message),
+            None => vec![error_response(&Json::Null, PARSE_ERROR, "invalid JSON")],
+        };
+        for message in messages {
+            write_message(&mut output, &message)?;
+        }
+    }
+
+    // the client went away without saying so
+    Ok(1)
+}
+
+
+#[test]
+fn test_json() {
+    let text = r#" {"id": 7, "params": {"a": [1, -2.5e1, true, false, null], "b": "q\"\\\/\n\u00e9\ud83d\ude00"}} "#;
+    let json = Json::parse(text).unwrap();
+    assert_eq!(json.get("id").and_then(Json::as_usize), Some(7));
+    let params = json.get("params").unwrap();
+    assert_eq!(
+        params.get("a").unwrap(),
+        &Json::Array(vec![Json::Number(1.0), Json::Number(-25.0), Json::Bool(true), Json::Bool(false), Json::Null])
+    );
+    assert_eq!(params.get("b").and_then(Json::as_str), Some("q\"\\/\né😀"));
+    assert_eq!(params.get("c"), None);
+    assert_eq!(json.to_string(), r#"{"id":7,"params":{"a":[1,-25,true,false,null],"b":"q\"\\/\né😀"}}"#);
+    assert_eq!(Json::parse("[0.5, \"\\ud800x\"]").unwrap().to_string(), "[0.5,\"\u{fffd}x\"]");
+
+    for text in &["", "{", "[1,]", "{\"a\" 1}", "{1: 2}", "\"a", "\"\\x\"", "tru", "[] []", "\"\\u12\""] {
+        assert_eq!(Json::parse(text), None, "{}", text);
+    }
+}
+
+#[test]
+fn test_positions() {
+    // é is one UTF-16 unit and two bytes, 😀 two units and four bytes
+    let source = "é+\n😀[\n";
+    assert_eq!(position(source, 2), "{\"line\":0,\"character\":1}");
+    assert_eq!(position(source, 8), "{\"line\":1,\"character\":2}");
+    assert_eq!(offset_at(source, 0, 1), 2);
+    assert_eq!(offset_at(source, 1, 2), 8);
+    assert_eq!(offset_at(source, 1, 10), 9);
+    assert_eq!(offset_at(source, 2, 0), 10);
+    assert_eq!(offset_at(source, 5, 0), 10);
+
+    let insts = parse_with_spans("[[-]+]").unwrap().0;
+    let depths: Vec<usize> = (0..insts.len()).map(|i| loop_depth(&insts, i)).collect();
+    assert_eq!(depths, [0, 1, 2, 1, 1, 0]);
+}
+
+#[test]
+fn test_server() {
+    let mut server = Server::new();
+    let mut send = |message: &str| server.handle(&Json::parse(message).unwrap());
+
+    assert_eq!(
+        send(r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#),
+        [format!("{{\"jsonrpc\":\"2.0\",\"id\":1,\"result\":{}}}", CAPABILITIES)]
+    );
+    assert_eq!(send(r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#), Vec::<String>::new());
+
+    assert_eq!(
+        send(r#"{"jsonrpc":"2.0","method":"textDocument/didOpen",
+                 "params":{"textDocument":{"uri":"file:///a.b","languageId":"brainfuck","version":1,"text":"+]\n[>"}}}"#),
+        ["{\"jsonrpc\":\"2.0\",\"method\":\"textDocument/publishDiagnostics\",\"params\":{\"uri\":\"file:///a.b\",\
+          \"diagnostics\":[{\"range\":{\"start\":{\"line\":0,\"character\":1},\"end\":{\"line\":0,\"character\":2}},\
+          \"severity\":1,\"code\":\"unmatched-close\",\"source\":\"brainfuck\",\"message\":\"unmatched ']'\"},\
+          {\"range\":{\"start\":{\"line\":1,\"character\":0},\"end\":{\"line\":1,\"character\":1}},\
+          \"severity\":1,\"code\":\"unclosed-open\",\"source\":\"brainfuck\",\"message\":\"unclosed '['\"}]}}"]
+    );
+    // nothing to tell about a program that doesn't parse
+    assert_eq!(
+        send(r#"{"jsonrpc":"2.0","id":2,"method":"textDocument/hover",
+                 "params":{"textDocument":{"uri":"file:///a.b"},"position":{"line":0,"character":0}}}"#),
+        ["{\"jsonrpc\":\"2.0\",\"id\":2,\"result\":null}"]
+    );
+
+    assert_eq!(
+        send(r#"{"jsonrpc":"2.0","method":"textDocument/didChange","params":{"textDocument":{"uri":"file:///a.b","version":2},
+                 "contentChanges":[{"text":"+"},{"text":"++[\n >+++<-]"}]}}"#),
+        ["{\"jsonrpc\":\"2.0\",\"method\":\"textDocument/publishDiagnostics\",\"params\":{\"uri\":\"file:///a.b\",\
+          \"diagnostics\":[]}}"]
+    );
+    assert_eq!(
+        send(r#"{"jsonrpc":"2.0","id":"h","method":"textDocument/hover",
+                 "params":{"textDocument":{"uri":"file:///a.b"},"position":{"line":1,"character":3}}}"#),
+        ["{\"jsonrpc\":\"2.0\",\"id\":\"h\",\"result\":{\"contents\":{\"kind\":\"plaintext\",\
+          \"value\":\"IncVal(3) at instruction 3, loop depth 1\"},\
+          \"range\":{\"start\":{\"line\":1,\"character\":2},\"end\":{\"line\":1,\"character\":5}}}}"]
+    );
+    assert_eq!(
+        send(r#"{"jsonrpc":"2.0","id":3,"method":"textDocument/documentHighlight",
+                 "params":{"textDocument":{"uri":"file:///a.b"},"position":{"line":1,"character":7}}}"#),
+        ["{\"jsonrpc\":\"2.0\",\"id\":3,\"result\":[\
+          {\"range\":{\"start\":{\"line\":0,\"character\":2},\"end\":{\"line\":0,\"character\":3}},\"kind\":1},\
+          {\"range\":{\"start\":{\"line\":1,\"character\":7},\"end\":{\"line\":1,\"character\":8}},\"kind\":1}]}"]
+    );
+    // not on a bracket, or not in a document
+    assert_eq!(
+        send(r#"{"jsonrpc":"2.0","id":4,"method":"textDocument/documentHighlight",
+                 "params":{"textDocument":{"uri":"file:///a.b"},"position":{"line":0,"character":0}}}"#),
+        ["{\"jsonrpc\":\"2.0\",\"id\":4,\"result\":null}"]
+    );
+    assert_eq!(
+        send(r#"{"jsonrpc":"2.0","id":5,"method":"textDocument/hover",
+                 "params":{"textDocument":{"uri":"file:///b.b"},"position":{"line":0,"character":0}}}"#),
+        ["{\"jsonrpc\":\"2.0\",\"id\":5,\"result\":null}"]
+    );
+
+    assert_eq!(
+        send(r#"{"jsonrpc":"2.0","method":"textDocument/didClose","params":{"textDocument":{"uri":"file:///a.b"}}}"#),
+        ["{\"jsonrpc\":\"2.0\",\"method\":\"textDocument/publishDiagnostics\",\"params\":{\"uri\":\"file:///a.b\",\
+          \"diagnostics\":[]}}"]
+    );
+    assert_eq!(
+        send(r#"{"jsonrpc":"2.0","id":6,"method":"textDocument/formatting","params":{}}"#),
+        ["{\"jsonrpc\":\"2.0\",\"id\":6,\"error\":{\"code\":-32601,\
+          \"message\":\"unknown method 'textDocument/formatting'\"}}"]
+    );
+    assert_eq!(send(r#"{"jsonrpc":"2.0","id":7,"method":"shutdown"}"#), ["{\"jsonrpc\":\"2.0\",\"id\":7,\"result\":null}"]);
+    assert_eq!(
+        send(r#"{"jsonrpc":"2.0","id":8,"method":"initialize","params":{}}"#),
+        ["{\"jsonrpc\":\"2.0\",\"id\":8,\"error\":{\"code\":-32600,\"message\":\"the server is shut down\"}}"]
+    );
+}
+
+#[test]
+fn test_serve() {
+    let frame = |body: &str| format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+
+    let input = frame(r#"{"jsonrpc":"2.0","id":1,"method":"shutdown"}"#) + &frame("{oops") + &frame(r#"{"jsonrpc":"2.0","method":"exit"}"#);
+    let mut output = Vec::new();
+    assert_eq!(serve(input.as_bytes(), &mut output).unwrap(), 0);
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        frame("{\"jsonrpc\":\"2.0\",\"id\":1,\"result\":null}")
+            + &frame("{\"jsonrpc\":\"2.0\",\"id\":null,\"error\":{\"code\":-32700,\"message\":\"invalid JSON\"}}")
+    );
+
+    // exiting without shutting down, or not even exiting
+    let input = frame(r#"{"jsonrpc":"2.0","method":"exit"}"#);
+    assert_eq!(serve(input.as_bytes(), io::sink()).unwrap(), 1);
+    assert_eq!(serve(&b""[..], io::sink()).unwrap(), 1);
+    assert!(serve(&b"Content-Length: 10\r\n\r\n{}"[..], io::sink()).is_err());
+    assert!(serve(&b"Content-Type: x\r\n\r\n{}"[..], io::sink()).is_err());
+}
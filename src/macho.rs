@@ -0,0 +1,200 @@
+// Executables for macOS, for `compile --emit macho`: the machine code of a
+// program with the calls of `Syscalls::Darwin`, behind a stub setting up
+// the frame and a zeroed tape and exiting with the status the code returns.
+// Nothing is linked, the kernel starts the stub itself, as LC_UNIXTHREAD
+// says.
+//
+// The code is x86_64 only, so Apple Silicon runs it under Rosetta 2. That's
+// also why it isn't signed: only arm64 code has to be. Where policy wants a
+// signature anyway, `codesign -s - FILE` adds an ad-hoc one, there's room
+// for its load command and __LINKEDIT is last for it to go in.
+use brainfuck::{FRAME_OUTPUT, FRAME_TAPE_END, FRAME_TAPE_START};
+
+
+const MH_MAGIC_64: u32 = 0xfeed_facf;
+const CPU_TYPE_X86_64: u32 = 0x0100_0007;
+const CPU_SUBTYPE_X86_64_ALL: u32 = 3;
+const MH_EXECUTE: u32 = 2;
+const MH_NOUNDEFS: u32 = 1;
+const LC_SEGMENT_64: u32 = 0x19;
+const LC_UNIXTHREAD: u32 = 0x5;
+const X86_THREAD_STATE64: u32 = 4;
+// rax to gs, of which only rip is set
+const THREAD_REGISTERS: usize = 21;
+const RIP: usize = 16;
+const S_ZEROFILL: u32 = 0x1;
+const S_ATTR_CODE: u32 = 0x8000_0400;
+
+const SEGMENT_SIZE: u32 = 72;
+const SECTION_SIZE: u32 = 80;
+const THREAD_SIZE: u32 = 16 + THREAD_REGISTERS as u32 * 8;
+
+// Segments are aligned to the pages of arm64, which x86_64 ones divide
+const PAGE: u64 = 0x4000;
+const BASE: u64 = 0x1_0000_0000;
+// Where the stub starts in the file, leaving room after the load commands
+const CODE_OFFSET: u64 = 0x400;
+const STUB_SIZE: u64 = 66;
+// In __DATA, the frame, the cancel flag nothing sets and then the tape
+const CANCEL: u64 = 48;
+const TAPE: u64 = 64;
+
+fn page_align(n: u64) -> u64 {
+    n.div_ceil(PAGE) * PAGE
+}
+
+fn put32(out: &mut Vec<u8>, values: &[u32]) {
+    for value in values {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+fn put64(out: &mut Vec<u8>, values: &[u64]) {
+    for value in values {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+fn put_name(out: &mut Vec<u8>, name: &str) {
+    let mut padded = [0; 16];
+    padded[..name.len()].copy_from_slice(name.as_bytes());
+    out.extend_from_slice(&padded);
+}
+
+// The command of a segment with `nsects` sections following it, its
+// address and size in memory, offset and size in the file and protection
+fn put_segment(out: &mut Vec<u8>, name: &str, vm: (u64, u64), file: (u64, u64), prot: u32, nsects: u32) {
+    put32(out, &[LC_SEGMENT_64, SEGMENT_SIZE + nsects * SECTION_SIZE]);
+    put_name(out, name);
+    put64(out, &[vm.0, vm.1, file.0, file.1]);
+    put32(out, &[prot, prot, nsects, 0]);
+}
+
+fn put_section(out: &mut Vec<u8>, name: &str, segment: &str, vm: (u64, u64), offset: u64, flags: u32) {
+    put_name(out, name);
+    put_name(out, segment);
+    put64(out, &[vm.0, vm.1]);
+    // aligned to 16 bytes, without relocations
+    put32(out, &[offset as u32, 4, 0, 0, flags, 0, 0, 0]);
+}
+
+// Loads the frame's address into rdi, the tape's into rsi, calls the code
+// at `entry + STUB_SIZE` and exits with the status it returns
+fn stub(entry: u64, data: u64, tape_size: u64) -> Vec<u8> {
+    // the displacement of `target` from the end of the instruction ending
+    // at `end` in the stub
+    let rel = |end: u64, target: u64| (target.wrapping_sub(entry + end) as u32).to_le_bytes();
+    let mut stub = Vec::new();
+
+    stub.extend_from_slice(&[0x48, 0x8d, 0x3d]); // lea rdi, [rip + frame]
+    stub.extend_from_slice(&rel(7, data));
+    stub.extend_from_slice(&[0x48, 0x8d, 0x05]); // lea rax, [rip + cancel]
+    stub.extend_from_slice(&rel(14, data + CANCEL));
+    stub.extend_from_slice(&[0x48, 0x89, 0x07]); // mov [rdi], rax
+    stub.extend_from_slice(&[0xc7, 0x47, FRAME_OUTPUT, 0x01, 0x00, 0x00, 0x00]); // mov dword [rdi + FRAME_OUTPUT], 1
+    stub.extend_from_slice(&[0x48, 0x8d, 0x35]); // lea rsi, [rip + tape]
+    stub.extend_from_slice(&rel(31, data + TAPE));
+    stub.extend_from_slice(&[0x48, 0x89, 0x77, FRAME_TAPE_START]); // mov [rdi + FRAME_TAPE_START], rsi
+    stub.extend_from_slice(&[0x48, 0xb8]); // mov rax, tape_size
+    stub.extend_from_slice(&tape_size.to_le_bytes());
+    stub.extend_from_slice(&[0x48, 0x01, 0xf0]); // add rax, rsi
+    stub.extend_from_slice(&[0x48, 0x89, 0x47, FRAME_TAPE_END]); // mov [rdi + FRAME_TAPE_END], rax
+    stub.push(0xe8); // call code
+    stub.extend_from_slice(&rel(57, entry + STUB_SIZE));
+    stub.extend_from_slice(&[
+        0x89, 0xc7, // mov edi, eax
+        0xb8, 0x01, 0x00, 0x00, 0x02, // mov eax, 0x2000001
+        0x0f, 0x05, // syscall
+    ]);
+    debug_assert_eq!(stub.len() as u64, STUB_SIZE);
+
+    stub
+}
+
+// An executable running `code`, generated with `Syscalls::Darwin`, on a tape
+// of `tape_size` cells. It exits with 0 once the program is done, or with
+// the `STATUS_` value the code returned, 1 and 2 for the pointer moving off
+// the tape.
+pub fn executable(code: &[u8], tape_size: usize) -> Vec<u8> {
+    let entry = BASE + CODE_OFFSET;
+    let text_size = page_align(CODE_OFFSET + STUB_SIZE + code.len() as u64);
+    let data = BASE + text_size;
+    let data_size = page_align(TAPE + tape_size as u64);
+    let linkedit = data + data_size;
+
+    let mut commands = Vec::new();
+    put_segment(&mut commands, "__PAGEZERO", (0, BASE), (0, 0), 0, 0);
+    // the header is part of __TEXT, which starts at the file's start
+    put_segment(&mut commands, "__TEXT", (BASE, text_size), (0, text_size), 5, 1);
+    put_section(
+        &mut commands, "__text", "__TEXT", (entry, STUB_SIZE + code.len() as u64), CODE_OFFSET, S_ATTR_CODE
+    );
+    put_segment(&mut commands, "__DATA", (data, data_size), (text_size, 0), 3, 1);
+    put_section(&mut commands, "__bss", "__DATA", (data, TAPE + tape_size as u64), 0, S_ZEROFILL);
+    put_segment(&mut commands, "__LINKEDIT", (linkedit, PAGE), (text_size, 0), 1, 0);
+    put32(&mut commands, &[LC_UNIXTHREAD, THREAD_SIZE, X86_THREAD_STATE64, THREAD_REGISTERS as u32 * 2]);
+    let mut registers = [0; THREAD_REGISTERS];
+    // a zero rsp gets the default stack
+    registers[RIP] = entry;
+    put64(&mut commands, &registers);
+
+    let mut out = Vec::with_capacity(text_size as usize);
+    put32(&mut out, &[MH_MAGIC_64, CPU_TYPE_X86_64, CPU_SUBTYPE_X86_64_ALL, MH_EXECUTE, 5]);
+    put32(&mut out, &[commands.len() as u32, MH_NOUNDEFS, 0]);
+    out.extend_from_slice(&commands);
+    debug_assert!(out.len() as u64 <= CODE_OFFSET);
+    out.resize(CODE_OFFSET as usize, 0);
+    out.extend_from_slice(&stub(entry, data, tape_size as u64));
+    out.extend_from_slice(code);
+    // all of __TEXT is mapped from the file
+    out.resize(text_size as usize, 0);
+
+    out
+}
+
+
+#[test]
+fn test_executable() {
+    use brainfuck::{compile_insts, parse, CodegenOptions, Syscalls};
+
+    let u32_at = |bytes: &[u8], at: usize| {
+        let mut word = [0; 4];
+        word.copy_from_slice(&bytes[at..at + 4]);
+        u32::from_le_bytes(word)
+    };
+    let u64_at = |bytes: &[u8], at: usize| {
+        let mut word = [0; 8];
+        word.copy_from_slice(&bytes[at..at + 8]);
+        u64::from_le_bytes(word)
+    };
+
+    let options = CodegenOptions { syscalls: Syscalls::Darwin, ..CodegenOptions::default() };
+    let code = compile_insts(&parse("+.").unwrap(), &options).unwrap();
+    let file = executable(&code, 100);
+
+    assert_eq!(file.len() as u64, PAGE);
+    assert_eq!(u32_at(&file, 0), MH_MAGIC_64);
+    assert_eq!(u32_at(&file, 4), CPU_TYPE_X86_64);
+    assert_eq!(u32_at(&file, 16), 5);
+    let commands = u32_at(&file, 20) as usize;
+    assert_eq!(commands, 4 * SEGMENT_SIZE as usize + 2 * SECTION_SIZE as usize + THREAD_SIZE as usize);
+    assert!(file[32 + commands..CODE_OFFSET as usize].iter().all(|&b| b == 0));
+
+    // the thread starts at the stub, which calls the code behind it
+    let thread = 32 + commands - THREAD_SIZE as usize;
+    assert_eq!(u32_at(&file, thread), LC_UNIXTHREAD);
+    assert_eq!(u64_at(&file, thread + 16 + RIP * 8), BASE + CODE_OFFSET);
+    let call = (CODE_OFFSET + 52) as usize;
+    assert_eq!(file[call], 0xe8);
+    assert_eq!(call + 5 + u32_at(&file, call + 1) as usize, (CODE_OFFSET + STUB_SIZE) as usize);
+    let start = (CODE_OFFSET + STUB_SIZE) as usize;
+    assert_eq!(&file[start..start + code.len()], &code[..]);
+
+    // the tape is the end of __DATA, right after __TEXT
+    let data = 32 + 2 * SEGMENT_SIZE as usize + SECTION_SIZE as usize;
+    assert_eq!(&file[data + 8..data + 18], b"__DATA\0\0\0\0");
+    assert_eq!((u64_at(&file, data + 24), u64_at(&file, data + 32)), (BASE + PAGE, PAGE));
+    let tape = (CODE_OFFSET + 24) as usize;
+    assert_eq!(&file[tape..tape + 3], [0x48, 0x8d, 0x35]);
+    assert_eq!(u32_at(&file, tape + 3) as u64, BASE + PAGE + TAPE - (BASE + CODE_OFFSET + 31));
+}
@@ -1,356 +1,2248 @@
+#[cfg(not(target_os = "wasi"))]
 extern crate mmap;
+#[cfg(not(target_os = "wasi"))]
+extern crate notify_debouncer_mini;
 extern crate clap;
+extern crate libc;
+#[cfg(feature = "bignum")]
+extern crate num_bigint;
+#[cfg(feature = "bignum")]
+extern crate num_traits;
+extern crate flate2;
+#[cfg(feature = "brainloller")]
+extern crate image;
+#[cfg(feature = "tracing")]
+extern crate tracing;
+#[cfg(feature = "tracing")]
+extern crate tracing_subscriber;
+#[cfg(test)]
+#[macro_use]
+extern crate brainfuck_macros;
 
+// first, for its macros to be there in all the others
+#[macro_use]
+mod instrument;
+
+#[allow(dead_code)]
 mod runlength;
+#[cfg(not(target_os = "wasi"))]
+#[allow(dead_code)]
+mod arena;
+#[allow(dead_code)]
+mod condition;
+#[cfg(all(test, not(target_os = "wasi")))]
+mod corpus;
+#[allow(dead_code)]
+mod ast;
+#[cfg(not(target_os = "wasi"))]
+mod batch;
+#[cfg(feature = "bignum")]
+#[allow(dead_code)]
+mod bignum;
+#[cfg_attr(not(feature = "brainloller"), allow(dead_code))]
+mod brainloller;
+#[allow(dead_code)]
+mod builder;
+mod bytecode;
+#[cfg(not(target_os = "wasi"))]
+#[allow(dead_code)]
+mod cache;
+mod coverage;
+mod crash;
+#[allow(dead_code)]
+mod debugger;
+mod diagnostics;
+mod diff;
+mod dump;
+#[cfg(not(target_os = "wasi"))]
+mod fault;
+mod formatter;
+#[cfg(test)]
+mod generator;
+#[cfg(not(target_os = "wasi"))]
+mod harness;
+mod heatmap;
+mod htmlreport;
+#[cfg(not(target_os = "wasi"))]
+mod interrupt;
+#[allow(dead_code)]
+mod host;
+mod ir;
+mod js;
+mod listing;
+mod lsp;
+mod macho;
+#[allow(dead_code)]
+mod interp;
+#[allow(dead_code)]
+mod memory;
+#[allow(dead_code)]
+mod optimize;
+mod pe;
+#[cfg_attr(target_os = "wasi", allow(dead_code))]
+mod perfmap;
+#[cfg(not(target_os = "wasi"))]
+mod pipeline;
+mod profile;
+#[cfg(all(test, not(target_os = "wasi")))]
+mod property;
+mod record;
+mod runstats;
+#[cfg(target_os = "linux")]
+mod sandbox;
+mod stats;
+mod tape;
+mod tapedump;
+#[cfg(not(target_os = "wasi"))]
+mod terminal;
+mod trace;
+#[cfg(not(target_os = "wasi"))]
+#[allow(dead_code)]
+mod tui;
+#[allow(dead_code)]
+mod twosided;
+#[cfg(not(target_os = "wasi"))]
+mod visualize;
+#[cfg(test)]
+mod wasm;
+#[cfg(not(target_os = "wasi"))]
+mod watch;
 
 #[allow(dead_code)]
-#[allow(unused_must_use)]
-mod brainfuck {
-    use std::{mem, ptr, io};
-    use std::io::{Read, Write, Cursor, Seek, SeekFrom};
-    use std::collections::HashMap;
-    use self::Inst::*;
-    use mmap::*;
-    use runlength::RunLengthIterator;
-
-    #[derive(Debug)]
-    pub enum Inst {
-        IncPtr(usize),
-        DecPtr(usize),
-        IncVal(usize),
-        DecVal(usize),
-        PrintCell,
-        ReadChar,
-        JmpFwd(usize),
-        JmpBack(usize),
-    }
-
-    impl Inst {
-        fn is_jmp_fwd(&self) -> bool {
-            match *self {
-                JmpFwd(_) => true,
-                _ => false
-            }
-        }
-
-        fn is_jmp_back(&self) -> bool {
-            match *self {
-                JmpBack(_) => true,
-                _ => false
-            }
-        }
-    }
-
-    fn default_vec<T: Clone>(size: usize, default: T) -> Vec<T> {
-        use std::iter::repeat;
-
-        repeat(default).take(size).collect()
-    }
-
-    fn compile(insts: &[Inst]) -> Vec<u8> {
-        let mut mem = Cursor::new(Vec::new());
-
-        fn emit_inc<T: Write>(mem: &mut T, amount: usize) {
-            if amount == 1 {
-                mem.write(&[
-                    0x48, 0xff, 0xc6, // inc rsi
-                ]);
-            } else {
-                let raw: *const u8 = unsafe { mem::transmute(&(amount as u32)) };
-                unsafe {
-                    mem.write(&[
-                        0x48, 0x81, 0xc6,
-                        *raw.offset(0),
-                        *raw.offset(1),
-                        *raw.offset(2),
-                        *raw.offset(3),
-                    ]);
-                }
+mod brainfuck;
+
+use diagnostics::{report, report_compile_error, Diagnostic};
+
+
+// Exit codes of the command line tool
+const EXIT_COMPILE_ERROR: i32 = 1;
+const EXIT_RUNTIME_ERROR: i32 = 2;
+const EXIT_IO_ERROR: i32 = 3;
+
+#[cfg(not(feature = "bignum"))]
+const ARITH_MODES: &[&str] = &["wrap", "saturate", "trap"];
+#[cfg(feature = "bignum")]
+const ARITH_MODES: &[&str] = &["wrap", "saturate", "trap", "unbounded", "unbounded-trap"];
+
+fn read_source(path: &str) -> std::io::Result<String> {
+    use std::fs::File;
+    use std::io::Read;
+
+    let mut code = String::new();
+    File::open(path)?.read_to_string(&mut code)?;
+
+    Ok(code)
+}
+
+// Reports errors as `--diagnostics` and `--diagnostics-out` say, if either
+// was given
+fn set_diagnostics_output(matches: &clap::ArgMatches) {
+    let out = matches.value_of("diagnostics-out");
+    let format = match matches.value_of("diagnostics") {
+        Some("json") => diagnostics::Format::Json,
+        Some(_) => diagnostics::Format::Human,
+        None if out.is_some() => diagnostics::Format::Human,
+        None => return,
+    };
+    if let Err(e) = diagnostics::set_output(format, out) {
+        report(Diagnostic::error(&e).file(out.unwrap()));
+        std::process::exit(EXIT_IO_ERROR);
+    }
+}
+
+// Prints the spans of src/instrument.rs to stderr as they close, with how
+// long each took
+#[cfg(feature = "tracing")]
+fn trace_stages() {
+    use tracing_subscriber::fmt::format::FmtSpan;
+
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_max_level(tracing::Level::DEBUG)
+        .with_span_events(FmtSpan::CLOSE)
+        .init();
+}
+
+// Parses and verifies every file without generating code, returns the exit code
+fn check<'a, I: Iterator<Item=&'a str>>(files: I) -> i32 {
+    use brainfuck::*;
+
+    let (mut passed, mut failed) = (0, 0);
+
+    for path in files {
+        let code = match read_source(path) {
+            Ok(code) => code,
+            Err(e) => {
+                report(Diagnostic::error(&e).file(path));
+                failed += 1;
+                continue;
             }
         };
+        match parse(&code).and_then(|insts| verify(&insts)) {
+            Ok(()) => passed += 1,
+            Err(e) => {
+                report_compile_error(path, &code, &e);
+                failed += 1;
+            }
+        }
+    }
+
+    println!("checked {} file(s): {} passed, {} failed", passed + failed, passed, failed);
+
+    if failed > 0 { EXIT_COMPILE_ERROR } else { 0 }
+}
+
+// Where `write_atomic` writes to before renaming
+fn temporary_path(path: &std::path::Path) -> std::path::PathBuf {
+    let name = path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+    path.with_file_name(format!(".{}.{}.tmp", name, std::process::id()))
+}
+
+// Writes to a temporary file next to `path` and renames it into place, so
+// readers never observe a partially written file
+fn write_atomic(path: &str, contents: &[u8]) -> std::io::Result<()> {
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::path::Path;
+
+    let path = Path::new(path);
+    let tmp = temporary_path(path);
+
+    let result = File::create(&tmp)
+        .and_then(|mut file| file.write_all(contents).and_then(|_| file.sync_all()))
+        .and_then(|_| fs::rename(&tmp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
 
-        fn emit_dec<T: Write>(mem: &mut T, amount: usize) {
-            if amount == 1 {
-                mem.write(&[
-                    0x48, 0xff, 0xce, // dec rsi
-                ]);
-            } else {
-                let raw: *const u8 = unsafe { mem::transmute(&(amount as u32)) };
-                unsafe {
-                    mem.write(&[
-                        0x48, 0x81, 0xee,
-                        *raw.offset(0),
-                        *raw.offset(1),
-                        *raw.offset(2),
-                        *raw.offset(3),
-                    ]);
+    result
+}
+
+// Formats a file in place, or with `check` only reports whether it would change
+fn fmt(path: &str, options: &formatter::FormatOptions, check: bool) -> i32 {
+    let code = match read_source(path) {
+        Ok(code) => code,
+        Err(e) => {
+            report(Diagnostic::error(&e).file(path));
+            return EXIT_IO_ERROR;
+        }
+    };
+    let formatted = match formatter::format(&code, options) {
+        Ok(formatted) => formatted,
+        Err(e) => {
+            report_compile_error(path, &code, &e);
+            return EXIT_COMPILE_ERROR;
+        }
+    };
+
+    if formatted == code {
+        return 0;
+    }
+    if check {
+        eprintln!("{}: would reformat", path);
+        return EXIT_COMPILE_ERROR;
+    }
+    if let Err(e) = write_atomic(path, formatted.as_bytes()) {
+        report(Diagnostic::error(&e).file(path));
+        return EXIT_IO_ERROR;
+    }
+
+    0
+}
+
+// Writes the optimized program lowered back to brainfuck to `out` (stdout if
+// not given) and reports the size change on stderr
+fn minify(path: &str, out: Option<&str>) -> i32 {
+    use brainfuck::{parse, to_source, ArithMode};
+    use std::io::Write;
+
+    let code = match read_source(path) {
+        Ok(code) => code,
+        Err(e) => {
+            report(Diagnostic::error(&e).file(path));
+            return EXIT_IO_ERROR;
+        }
+    };
+    let minified = match parse(&code) {
+        // unrolled loops are longer in brainfuck
+        Ok(insts) => {
+            let optimizer = optimize::Optimizer::new(ArithMode::Wrap).without_pass("unroll loops");
+            to_source(&optimizer.run(insts).expect("the built-in passes keep programs valid"))
+        }
+        Err(e) => {
+            report_compile_error(path, &code, &e);
+            return EXIT_COMPILE_ERROR;
+        }
+    };
+
+    let result = match out {
+        Some(out) => write_atomic(out, minified.as_bytes()),
+        None => writeln!(std::io::stdout(), "{}", minified),
+    };
+    if let Err(e) = result {
+        report(Diagnostic::error(&e).file(out.unwrap_or("<stdout>")));
+        return EXIT_IO_ERROR;
+    }
+
+    eprintln!("{}: {} -> {} characters", path, code.chars().count(), minified.len());
+
+    0
+}
+
+// Writes the machine code of a program, unoptimized and compiled while it's
+// read, so that programs too large to hold in memory compile too. Like
+// `write_atomic` it's renamed into place once done.
+fn compile_code(path: &str, out: &str) -> i32 {
+    use std::fs::{self, File, OpenOptions};
+
+    let source = match File::open(path) {
+        Ok(source) => source,
+        Err(e) => {
+            report(Diagnostic::error(&e).file(path));
+            return EXIT_IO_ERROR;
+        }
+    };
+    let tmp = temporary_path(std::path::Path::new(out));
+    let written = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&tmp)
+        .map_err(brainfuck::CompileError::Io)
+        .and_then(|mut file| {
+            brainfuck::compile_streaming(source, &mut file)?;
+            file.sync_all()?;
+            Ok(file.metadata()?.len())
+        });
+    let size = match written.and_then(|size| fs::rename(&tmp, out).map(|_| size).map_err(Into::into)) {
+        Ok(size) => size,
+        Err(brainfuck::CompileError::Io(e)) => {
+            let _ = fs::remove_file(&tmp);
+            report(Diagnostic::error(&e).file(out));
+            return EXIT_IO_ERROR;
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            // only the error's line is needed, but finding it takes the source
+            match read_source(path) {
+                Ok(code) => report_compile_error(path, &code, &e),
+                Err(_) => report(Diagnostic::error(&e).file(path)),
+            }
+            return EXIT_COMPILE_ERROR;
+        }
+    };
+
+    eprintln!("{}: machine code -> {} ({} bytes)", path, out, size);
+
+    0
+}
+
+// Parses and optimizes a program once and writes it as bytecode, which runs
+// without parsing it again, as the textual IR, as a listing of the code
+// generated for every line, as JavaScript or as an executable for macOS or
+// Windows. Machine code alone is written by `compile_code`.
+fn compile(path: &str, out: Option<&str>, emit: &str, tape_size: usize, js_format: js::JsFormat) -> i32 {
+    use brainfuck::{compile_insts, compile_insts_with_offsets, parse_with_spans, ArithMode, CodegenOptions, Syscalls};
+
+    let extension = match emit {
+        "listing" => "lst",
+        "code" => "bin",
+        "macho" => "",
+        "pe" => "exe",
+        "js" => js_format.extension(),
+        _ => emit,
+    };
+    let default_out = std::path::Path::new(path).with_extension(extension);
+    let out = out.unwrap_or_else(|| default_out.to_str().unwrap());
+    if emit == "code" {
+        return compile_code(path, out);
+    }
+
+    let code = match read_source(path) {
+        Ok(code) => code,
+        Err(e) => {
+            report(Diagnostic::error(&e).file(path));
+            return EXIT_IO_ERROR;
+        }
+    };
+    let (insts, spans) = match parse_with_spans(&code) {
+        Ok((insts, spans)) => optimize::optimize_with_spans(insts, spans, ArithMode::Wrap),
+        Err(e) => {
+            report_compile_error(path, &code, &e);
+            return EXIT_COMPILE_ERROR;
+        }
+    };
+
+    let bytes = match emit {
+        "ir" => ir::to_string(&insts).into_bytes(),
+        "js" => js::to_string(&insts, tape_size, js_format).into_bytes(),
+        "listing" => {
+            let (jit_code, offsets) = match compile_insts_with_offsets(&insts, &CodegenOptions::default()) {
+                Ok(compiled) => compiled,
+                Err(e) => {
+                    report(Diagnostic::error(&e).file(path));
+                    return EXIT_COMPILE_ERROR;
+                }
+            };
+            let mut listing = Vec::new();
+            listing::write(&code, &insts, &spans, &jit_code, &offsets, &mut listing).unwrap();
+            listing
+        }
+        "macho" | "pe" => {
+            let syscalls = if emit == "macho" { Syscalls::Darwin } else { Syscalls::Windows };
+            let options = CodegenOptions { syscalls, ..CodegenOptions::default() };
+            match compile_insts(&insts, &options) {
+                Ok(jit_code) if emit == "macho" => macho::executable(&jit_code, tape_size),
+                Ok(jit_code) => pe::executable(&jit_code, tape_size),
+                Err(e) => {
+                    report(Diagnostic::error(&e).file(path));
+                    return EXIT_COMPILE_ERROR;
                 }
             }
+        }
+        _ => bytecode::encode(&insts, tape_size),
+    };
+    if let Err(e) = write_atomic(out, &bytes) {
+        report(Diagnostic::error(&e).file(out));
+        return EXIT_IO_ERROR;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        if emit == "macho" {
+            if let Err(e) = std::fs::set_permissions(out, std::fs::Permissions::from_mode(0o755)) {
+                report(Diagnostic::error(&e).file(out));
+                return EXIT_IO_ERROR;
+            }
+        }
+    }
+
+    eprintln!("{}: {} instructions -> {} ({} bytes)", path, insts.len(), out, bytes.len());
+
+    0
+}
+
+// Runs the tests in `dir`, see `harness`, printing a line per test and
+// what went wrong with the ones that failed
+#[cfg(not(target_os = "wasi"))]
+fn test(dir: &str, options: &harness::HarnessOptions) -> i32 {
+    let cases = match harness::discover(std::path::Path::new(dir)) {
+        Ok(cases) => cases,
+        Err(e) => {
+            report(Diagnostic::error(&e).file(dir));
+            return EXIT_IO_ERROR;
+        }
+    };
+    let results = harness::run_all(&cases, options);
+
+    for (case, result) in cases.iter().zip(&results) {
+        println!("{} ... {}", case.name, if result.is_ok() { "ok" } else { "FAILED" });
+    }
+    for (case, result) in cases.iter().zip(&results) {
+        if let Err(ref report) = *result {
+            println!("\n---- {} ----\n{}", case.name, report.trim_end());
+        }
+    }
+    let failed = results.iter().filter(|result| result.is_err()).count();
+    println!("\nran {} test(s): {} passed, {} failed", cases.len(), cases.len() - failed, failed);
+
+    if failed > 0 { EXIT_RUNTIME_ERROR } else { 0 }
+}
+
+// Compares two programs by their instructions, optimized if `optimized`,
+// and prints every difference at where it is in the files. Returns 0 only
+// if there's none.
+fn diff(paths: [&str; 2], optimized: bool) -> i32 {
+    use brainfuck::{parse_with_spans, ArithMode};
+    use diagnostics::position;
+    use diff::{describe, Edit};
+
+    let mut programs = Vec::new();
+    for &path in &paths {
+        let code = match read_source(path) {
+            Ok(code) => code,
+            Err(e) => {
+                report(Diagnostic::error(&e).file(path));
+                return EXIT_IO_ERROR;
+            }
         };
+        let (insts, spans) = match parse_with_spans(&code) {
+            Ok((insts, spans)) if optimized => optimize::optimize_with_spans(insts, spans, ArithMode::Wrap),
+            Ok(parsed) => parsed,
+            Err(e) => {
+                report_compile_error(path, &code, &e);
+                return EXIT_COMPILE_ERROR;
+            }
+        };
+        programs.push((path, code, insts, spans));
+    }
 
-        fn emit_inc_val<T: Write>(mem: &mut T, amount: usize) {
-            if amount == 1 {
-                mem.write(&[
-                    0xfe, 0x06, // inc byte [rsi]
-                ]);
-            } else {
-                let raw: *const u8 = unsafe { mem::transmute(&((amount & 0xff) as u8)) };
-                unsafe {
-                    mem.write(&[
-                        0x80, 0x06, *raw.offset(0)
-                    ]);
-                }
+    // where the `i`th instruction of a program came from
+    let at = |program: usize, i: usize| {
+        let (path, ref code, _, ref spans) = programs[program];
+        let (line, column) = position(code, spans[i].start);
+        format!("{}:{}:{}", path, line, column)
+    };
+    let (a, b) = (&programs[0].2, &programs[1].2);
+    let edits = diff::diff(a, b);
+    for edit in &edits {
+        match *edit {
+            Edit::Same(..) => {}
+            Edit::Removed(i) => println!("{}: removed {}", at(0, i), describe(&a[i])),
+            Edit::Inserted(j) => println!("{}: inserted {}", at(1, j), describe(&b[j])),
+            Edit::Changed(i, j) => {
+                println!("{}: changed {} to {} at {}", at(0, i), describe(&a[i]), describe(&b[j]), at(1, j));
             }
         }
+    }
 
-        fn emit_dec_val<T: Write>(mem: &mut T, amount: usize) {
-            if amount == 1 {
-                mem.write(&[
-                    0xfe, 0x0e, // dec byte [rsi]
-                ]);
-            } else {
-                let raw: *const u8 = unsafe { mem::transmute(&((amount & 0xff) as u8)) };
-                unsafe {
-                    mem.write(&[
-                        0x80, 0x2e, *raw.offset(0)
-                    ]);
-                }
+    if edits.iter().all(Edit::is_same) { 0 } else { EXIT_COMPILE_ERROR }
+}
+
+fn stats(path: &str, json: bool) -> i32 {
+    use brainfuck::parse;
+
+    let insts = match read_source(path) {
+        Ok(code) => match parse(&code) {
+            Ok(insts) => insts,
+            Err(e) => {
+                report_compile_error(path, &code, &e);
+                return EXIT_COMPILE_ERROR;
             }
+        },
+        Err(e) => {
+            report(Diagnostic::error(&e).file(path));
+            return EXIT_IO_ERROR;
         }
+    };
 
-        fn emit_jmp_fwd<T: Write>(mem: &mut T, offset: usize) {
-            mem.write(&[
-                0x80, 0x3e, 0x00, // cmp byte [rsi], 0
-                0x0f, 0x84 // je ...
-            ]);
-            let offset = offset as i32 - 9;
-            let raw: *const u8 = unsafe { mem::transmute(&offset) };
-            unsafe {
-                mem.write(&[
-                    *raw.offset(0),
-                    *raw.offset(1),
-                    *raw.offset(2),
-                    *raw.offset(3),
-                ]);
-            }
-        }
-
-        fn emit_jmp_back<T: Write>(mem: &mut T, offset: isize) {
-            mem.write(&[
-                0x80, 0x3e, 0x00, // cmp byte [rsi], 0
-                0x0f, 0x85 // jne ...
-            ]);
-            let offset = (offset as i32) - 9;
-            let raw: *const u8 = unsafe { mem::transmute(&offset) };
-            unsafe {
-                mem.write(&[
-                    *raw.offset(0),
-                    *raw.offset(1),
-                    *raw.offset(2),
-                    *raw.offset(3),
-                ]);
-            }
-        }
-
-        fn emit_print<T: Write>(mem: &mut T) {
-            mem.write(&[
-                0xb8, 0x01, 0x00, 0x00, 0x00, // mov rax, 1
-                0xbf, 0x01, 0x00, 0x00, 0x00, // mov rdi, 1
-                0xba, 0x01, 0x00, 0x00, 0x00, // mov edx, 1
-                0x0f, 0x05 // syscall
-            ]);
-        }
-
-        fn emit_read<T: Write>(mem: &mut T) {
-            mem.write(&[
-                0x48, 0x31, 0xc0, // xor rax, rax
-                0x48, 0x31, 0xff, // xor rdi, rdi
-                0xba, 0x01, 0x00, 0x00, 0x00, // mov edx, 1
-                0x0f, 0x05 // syscall
-            ]);
-        }
-
-        fn emit_ret<T: Write>(mem: &mut T) {
-            mem.write(&[
-                0xc3 // ret
-            ]);
-        }
-
-        let mut addr_mapping: HashMap<usize, usize> = HashMap::new();
-        let mut fwd_jumps: Vec<(usize, usize)> = Vec::new();
-
-        for (i, inst) in insts.iter().enumerate() {
-            match *inst {
-                IncPtr(a) => emit_inc(&mut mem, a),
-                DecPtr(a) => emit_dec(&mut mem, a),
-                IncVal(a) => emit_inc_val(&mut mem, a),
-                DecVal(a) => emit_dec_val(&mut mem, a),
-                PrintCell => emit_print(&mut mem),
-                ReadChar => emit_read(&mut mem),
-                JmpFwd(n) => {
-                    fwd_jumps.push((mem.position() as usize, n));
-                    emit_jmp_fwd(&mut mem, 0x41414141); // insert dummy
-                    addr_mapping.insert(i, mem.position() as usize);
-                },
-                JmpBack(n) => {
-                    let distance = mem.position() as isize - addr_mapping[&n] as isize;
-                    emit_jmp_back(&mut mem, -distance);
-                    addr_mapping.insert(i, mem.position() as usize);
-                },
+    let stats = stats::ProgramStats::new(&insts);
+    if json {
+        println!("{}", stats.to_json());
+    } else {
+        print!("{}", stats.to_text());
+    }
+
+    0
+}
+
+// What to do with the execution counts of a run
+struct CoverageOptions<'a> {
+    // file to accumulate the counts of several runs in
+    out: Option<&'a str>,
+    report: bool,
+    heatmap: bool,
+    heatmap_html: Option<&'a str>,
+    // file to write the loops' statistics to as JSON, see `profile`
+    profile_out: Option<&'a str>,
+    // file to write the collapsed stacks to, see `profile::to_folded`
+    profile_folded: Option<&'a str>,
+}
+
+// Adds the counts of a run to the ones in `out` if given and renders the
+// coverage report and heatmaps of all of them, returns the exit code
+fn report_coverage(
+    path: &str, code: &str, bf: &brainfuck::Brainfuck, counts: &[u64], options: &CoverageOptions
+) -> i32 {
+    let mut counts = counts.to_vec();
+
+    if let Some(out) = options.out {
+        let result = coverage::load(out, bf.fingerprint()).and_then(|previous| {
+            if let Some(previous) = previous {
+                coverage::merge(&mut counts, &previous);
             }
+            coverage::save(out, bf.fingerprint(), &counts)
+        });
+        if let Err(e) = result {
+            report(Diagnostic::error(&e).file(out));
+            return EXIT_IO_ERROR;
         }
+    }
+
+    let (_, spans) = brainfuck::parse_with_spans(code).unwrap();
+    let per_byte = coverage::source_counts(code, &spans, &counts);
 
-        for (offset, n) in fwd_jumps {
-            mem.set_position(offset as u64);
-            let distance = addr_mapping[&n] - offset;
-            emit_jmp_fwd(&mut mem, distance);
+    if options.report {
+        eprintln!("{}: coverage", path);
+        eprint!("{}", coverage::report(code, &per_byte));
+    }
+    if options.heatmap {
+        eprint!("{}", heatmap::to_ansi(code, &per_byte));
+    }
+    if let Some(html) = options.heatmap_html {
+        if let Err(e) = std::fs::write(html, heatmap::to_html(path, code, &per_byte)) {
+            report(Diagnostic::error(&e).file(html));
+            return EXIT_IO_ERROR;
         }
+    }
+
+    0
+}
 
-        mem.seek(SeekFrom::End(0)).unwrap();
-        emit_ret(&mut mem);
+// Where and how to write the tape left behind by a run
+struct TapeDumpOptions<'a> {
+    // stderr if not given, stdout belongs to the program
+    path: Option<&'a str>,
+    format: tapedump::TapeFormat,
+    full: bool,
+}
 
-        mem.into_inner()
+fn tape_dump_options<'a>(matches: &'a clap::ArgMatches) -> Option<TapeDumpOptions<'a>> {
+    if !matches.is_present("tape-dump") {
+        return None;
     }
+    Some(TapeDumpOptions {
+        path: matches.value_of("tape-dump"),
+        format: match matches.value_of("tape-dump-format") {
+            Some("raw") => tapedump::TapeFormat::Raw,
+            Some("nonzero") => tapedump::TapeFormat::NonZero,
+            _ => tapedump::TapeFormat::Hex,
+        },
+        full: matches.is_present("tape-dump-full"),
+    })
+}
 
-    pub struct Brainfuck {
-        insts: Vec<Inst>,
-        jit_code: Vec<u8>,
-        tape_size: usize,
+fn dump_tape(tape: &[u8], options: &TapeDumpOptions) -> i32 {
+    let mut dump = Vec::new();
+    tapedump::write_tape(tape, options.format, options.full, &mut dump).unwrap();
+    write_tape_dump(&dump, options)
+}
+
+fn write_tape_dump(dump: &[u8], options: &TapeDumpOptions) -> i32 {
+    use std::io::Write;
+
+    let result = match options.path {
+        Some(path) => write_atomic(path, dump),
+        None => std::io::stderr().write_all(dump),
+    };
+    if let Err(e) = result {
+        report(Diagnostic::error(&e).file(options.path.unwrap_or("<stderr>")));
+        return EXIT_IO_ERROR;
     }
 
-    #[derive(Debug)]
-    pub enum CompileError {
-        UnbalancedBrackets,
+    0
+}
+
+// Where `--crash-report` writes the report on a failed run, the
+// abbreviated one to stderr without a path
+struct CrashReportOptions<'a> {
+    path: Option<&'a str>,
+    json: bool,
+}
+
+// Where a run's input comes from and where it's recorded
+struct InputOptions<'a> {
+    record: Option<&'a str>,
+    replay: Option<&'a str>,
+    // whether stdin follows the recorded input
+    then_stdin: bool,
+}
+
+// The input `options` ask for, the exit status if it can't be opened
+fn open_input(options: &InputOptions) -> Result<Box<dyn std::io::Read>, i32> {
+    let stdin: Box<dyn std::io::Read> = Box::new(std::io::stdin().lock());
+    let input: Box<dyn std::io::Read> = match options.replay {
+        Some(path) => {
+            let recorded = std::fs::read(path).map_err(|e| {
+                report(Diagnostic::error(&e).file(path));
+                EXIT_IO_ERROR
+            })?;
+            Box::new(record::Replay::new(recorded, if options.then_stdin { Some(stdin) } else { None }))
+        }
+        None => stdin,
+    };
+
+    match options.record {
+        Some(path) => {
+            let file = std::fs::File::create(path).map_err(|e| {
+                report(Diagnostic::error(&e).file(path));
+                EXIT_IO_ERROR
+            })?;
+            Ok(Box::new(record::Recorder::new(input, file)))
+        }
+        None => Ok(input),
     }
+}
+
+// Writes the `RunStats` of a run to stderr as `--stats` asked for, in
+// `format`
+fn print_run_stats(stats: &runstats::RunStats, format: &str) {
+    if format == "json" {
+        eprintln!("{}", stats.to_json());
+    } else {
+        eprint!("{}", stats.to_text());
+    }
+}
+
+// Writes a crash report as `options` ask for, returns the exit code if
+// that fails
+fn write_crash_report(crash: &crash::CrashReport, filename: &str, options: &CrashReportOptions) -> i32 {
+    match options.path {
+        Some(path) => {
+            let text = if options.json { crash.to_json(filename) + "\n" } else { crash.to_text(filename, true) };
+            if let Err(e) = write_atomic(path, text.as_bytes()) {
+                report(Diagnostic::error(&e).file(path));
+                return EXIT_IO_ERROR;
+            }
+        }
+        None if options.json => eprintln!("{}", crash.to_json(filename)),
+        None => eprint!("{}", crash.to_text(filename, false)),
+    }
+
+    0
+}
+
+// What else than the program's output a run of `run` is asked for
+struct RunOptions<'a> {
+    detect_livelock: bool,
+    coverage: &'a CoverageOptions<'a>,
+    input: &'a InputOptions<'a>,
+    tape_dump: Option<&'a TapeDumpOptions<'a>>,
+    // the format to print the run's statistics in
+    stats: Option<&'a str>,
+    crash_report: Option<&'a CrashReportOptions<'a>>,
+}
 
-    impl Brainfuck {
-        pub fn new(program: &str) -> Result<Brainfuck, CompileError> {
-            use self::CompileError::*;
+// Runs a program, in the interpreter if anything has to be observed while it
+// runs, returns the exit code
+fn run(filename: &str, code: Option<&str>, bf: &mut brainfuck::Brainfuck, options: RunOptions) -> i32 {
+    use brainfuck::{parse_with_spans, RuntimeError};
 
-            let mut insts = Vec::new();
-            let mut stack = Vec::new();
+    let RunOptions {
+        detect_livelock, coverage: coverage_options, input: input_options, tape_dump, stats, crash_report
+    } = options;
 
-            let program: String = program.chars().filter(
-                |&c| match c {
-                    '>' | '<' | '+' | '-' | '.' | ',' | '[' | ']' => true,
-                    _ => false,
+    let coverage = coverage_options.out.is_some() || coverage_options.report
+        || coverage_options.heatmap || coverage_options.heatmap_html.is_some();
+    let profiling = coverage_options.profile_out.is_some() || coverage_options.profile_folded.is_some();
+    // only the interpreter reads through a `Read`, which is where input is
+    // recorded and replayed
+    let recorded = input_options.record.is_some() || input_options.replay.is_some();
+    // set by Ctrl-C, see `interrupt`
+    #[cfg(not(target_os = "wasi"))]
+    let cancel = interrupt::flag();
+    #[cfg(target_os = "wasi")]
+    let cancel = &std::sync::atomic::AtomicBool::new(false);
+
+    if detect_livelock || coverage || profiling || recorded {
+        // the interpreter's tape counts just like the JIT's
+        if let Err(e) = memory::MemoryBudget::new(bf.max_memory()).charge(bf.tape_size()) {
+            report(Diagnostic::error(&e));
+            return EXIT_RUNTIME_ERROR;
+        }
+        let mut input = match open_input(input_options) {
+            Ok(input) => profile::CountingReader::new(input),
+            Err(status) => return status,
+        };
+        let stdout = std::io::stdout();
+        let mut interp = interp::Interp::new(bf.insts(), bf.tape_size());
+        interp.tape_mut()[..bf.initial_tape().len()].copy_from_slice(bf.initial_tape());
+        interp.set_ptr(bf.pointer_start());
+        interp.set_arith_mode(bf.arith_mode());
+        interp.set_detect_livelock(detect_livelock);
+        interp.set_coverage(coverage || profiling);
+        interp.set_cancel(Some(cancel));
+        if crash_report.is_some() {
+            interp.set_recording(Some(interp::history_budget(crash::RECENT)));
+        }
+
+        let started = std::time::Instant::now();
+        let result = interp.run(&mut input, stdout.lock());
+        let run_stats = runstats::RunStats { wall_time: started.elapsed(), ..interp.stats() };
+        if let Some(format) = stats {
+            print_run_stats(&run_stats, format);
+        }
+
+        // also for failed runs, the coverage up to the failure is still useful
+        if let (Some(counts), Some(code)) = (interp.coverage().filter(|_| coverage), code) {
+            let status = report_coverage(filename, code, bf, counts, coverage_options);
+            if status != 0 {
+                return status;
+            }
+        }
+        if let Some(counts) = interp.coverage().filter(|_| profiling) {
+            let spans = code.map(|code| parse_with_spans(code).unwrap().1);
+            if let Some(path) = coverage_options.profile_out {
+                let mut profile = profile::Profile::new(bf.insts(), spans.as_deref(), counts, input.count());
+                profile.run = Some(run_stats);
+                if let Err(e) = write_atomic(path, profile.to_json().as_bytes()) {
+                    report(Diagnostic::error(&e).file(path));
+                    return EXIT_IO_ERROR;
                 }
-            ).collect();
-
-            for (length, c) in program.chars().run_length() {
-
-                match c {
-                    '>' => insts.push(IncPtr(length)),
-                    '<' => insts.push(DecPtr(length)),
-                    '+' => insts.push(IncVal(length)),
-                    '-' => insts.push(DecVal(length)),
-                    '.' => {
-                        for _ in 0..length {
-                            insts.push(PrintCell);
-                        }
-                    }
-                    ',' => {
-                        for _ in 0..length {
-                            insts.push(ReadChar);
-                        }
+            }
+            if let Some(path) = coverage_options.profile_folded {
+                let folded = profile::to_folded(bf.insts(), spans.as_deref(), counts);
+                if let Err(e) = write_atomic(path, folded.as_bytes()) {
+                    report(Diagnostic::error(&e).file(path));
+                    return EXIT_IO_ERROR;
+                }
+            }
+        }
+        if let Some(options) = tape_dump {
+            let status = dump_tape(interp.tape(), options);
+            if status != 0 {
+                return status;
+            }
+        }
+
+        if let Err(e) = result {
+            match e {
+                RuntimeError::NonTerminatingLoop { inst_index } => match code {
+                    Some(code) => {
+                        let (_, spans) = parse_with_spans(code).unwrap();
+                        let span = spans[inst_index];
+                        report(Diagnostic::error(&e).file(filename).span(code, span.start, span.end));
                     }
-                    '[' => {
-                        for _ in 0..length {
-                            stack.push(insts.len());
-                            insts.push(JmpFwd(0)); // insert dummy;
+                    None => report(Diagnostic::error(&e).file(filename)),
+                },
+                // what it printed so far comes before the notice
+                RuntimeError::Cancelled => {
+                    let _ = std::io::Write::flush(&mut std::io::stdout());
+                    let steps = run_stats.instructions.unwrap_or(0);
+                    report(match code {
+                        Some(code) => {
+                            let (_, spans) = parse_with_spans(code).unwrap();
+                            let span = spans[interp.pc()];
+                            let message = format!("interrupted after {} steps at source offset {}", steps, span.start);
+                            Diagnostic::new("interrupted", message).file(filename).span(code, span.start, span.end)
                         }
-                    },
-                    ']' => {
-                        for _ in 0..length {
-                            let n = match stack.pop() {
-                                Some(n) => n,
-                                None => return Err(UnbalancedBrackets),
-                            };
-                            insts[n] = JmpFwd(insts.len());
-                            insts.push(JmpBack(n));
+                        None => {
+                            let message = format!("interrupted after {} steps at instruction {}", steps, interp.pc());
+                            Diagnostic::new("interrupted", message).file(filename)
                         }
-                    },
-                    _ => unreachable!(),
-                };
+                    });
+                }
+                _ => report(Diagnostic::error(&e)),
+            }
+            // an interrupted run didn't crash
+            if let Some(options) = crash_report.filter(|_| !matches!(e, RuntimeError::Cancelled)) {
+                let spans = code.map(|code| parse_with_spans(code).unwrap().1);
+                let crash = crash::CrashReport::from_interp(&e, &interp, code.zip(spans.as_deref()));
+                let status = write_crash_report(&crash, filename, options);
+                if status != 0 {
+                    return status;
+                }
+            }
+            return EXIT_RUNTIME_ERROR;
+        }
+        return 0;
+    }
+
+    let started = std::time::Instant::now();
+    match bf.run_until(cancel) {
+        Ok(()) => {
+            if let Some(format) = stats {
+                print_run_stats(&runstats::RunStats { wall_time: started.elapsed(), ..Default::default() }, format);
+            }
+        }
+        // code is generated for the first run
+        Err(RuntimeError::Compile(e)) => {
+            report(Diagnostic::error(&e).file(filename));
+            return EXIT_COMPILE_ERROR;
+        }
+        // generated code doesn't count steps, nor tell which loop it left
+        Err(RuntimeError::Cancelled) => {
+            let message = format!("interrupted after {:?}, generated code counts no steps", started.elapsed());
+            report(Diagnostic::new("interrupted", message).file(filename));
+            return EXIT_RUNTIME_ERROR;
+        }
+        Err(e) => {
+            report(Diagnostic::error(&e));
+            if let Some(options) = crash_report {
+                let spans = code.map(|code| parse_with_spans(code).unwrap().1);
+                let mut crash = crash::CrashReport::new(&e, bf.insts(), code.zip(spans.as_deref()));
+                #[cfg(not(target_os = "wasi"))]
+                if let RuntimeError::Fault { rip, .. } = e {
+                    crash.set_inst_index(bf.inst_at(rip));
+                }
+                let status = write_crash_report(&crash, filename, options);
+                if status != 0 {
+                    return status;
+                }
+            }
+            return EXIT_RUNTIME_ERROR;
+        }
+    }
+    if let Some(options) = tape_dump {
+        return dump_tape(bf.tape(), options);
+    }
+
+    0
+}
 
+// Runs a program with cells of unbounded size in `bignum::BigInterp`,
+// which has none of the options observing a run or needing generated code
+#[cfg(feature = "bignum")]
+fn run_unbounded(bf: &brainfuck::Brainfuck, underflow: bignum::Underflow, matches: &clap::ArgMatches) -> i32 {
+    const UNSUPPORTED: &[&str] = &[
+        "tape-model", "tape-alloc", "tape-dump", "detect-livelock", "raw-input", "record-input", "replay-input", "sandbox",
+        "perf-map", "precompute", "dump", "dump-jit", "coverage", "coverage-out", "heatmap", "heatmap-html",
+        "profile-out", "profile-folded", "visualize", "stats", "crash-report", "tape-guard", "tape-file",
+        "trace-json", "report-html",
+    ];
+    if let Some(flag) = UNSUPPORTED.iter().find(|&&flag| matches.occurrences_of(flag) > 0) {
+        report(Diagnostic::new("unsupported-option", format!("--{} needs cells of 8 bits", flag)));
+        return EXIT_RUNTIME_ERROR;
+    }
+    if let Err(e) = memory::MemoryBudget::new(bf.max_memory()).charge(bf.tape_size()) {
+        report(Diagnostic::error(&e));
+        return EXIT_RUNTIME_ERROR;
+    }
+
+    let mut interp = bignum::BigInterp::new(bf.insts(), bf.tape_size(), underflow);
+    for (cell, &byte) in interp.tape_mut().iter_mut().zip(bf.initial_tape()) {
+        *cell = bignum::BigCell::from(byte);
+    }
+    interp.set_ptr(bf.pointer_start());
+
+    let stdout = std::io::stdout();
+    if let Err(e) = interp.run(std::io::stdin().lock(), stdout.lock()) {
+        report(Diagnostic::error(&e));
+        return EXIT_RUNTIME_ERROR;
+    }
+
+    0
+}
+
+// Runs a program on a tape without ends in `twosided::TwoSidedInterp`,
+// which only has input and a dump of the tape for options
+fn run_two_sided(bf: &brainfuck::Brainfuck, matches: &clap::ArgMatches, tape_dump: Option<&TapeDumpOptions>) -> i32 {
+    const UNSUPPORTED: &[&str] = &[
+        "tape-size", "tape-alloc", "detect-livelock", "raw-input", "record-input", "replay-input", "sandbox",
+        "perf-map", "precompute", "dump", "dump-jit", "coverage", "coverage-out", "heatmap", "heatmap-html",
+        "profile-out", "profile-folded", "visualize", "stats", "crash-report", "tape-guard", "tape-file",
+        "trace-json", "report-html",
+    ];
+    if let Some(flag) = UNSUPPORTED.iter().find(|&&flag| matches.occurrences_of(flag) > 0) {
+        report(Diagnostic::new("unsupported-option", format!("--{} needs a tape with ends", flag)));
+        return EXIT_RUNTIME_ERROR;
+    }
+
+    let budget = memory::MemoryBudget::new(bf.max_memory());
+    let mut interp = twosided::TwoSidedInterp::new(bf.insts(), bf.arith_mode(), budget);
+    for (i, &byte) in bf.initial_tape().iter().enumerate() {
+        match interp.tape_mut().cell_mut(i as isize) {
+            Ok(cell) => *cell = byte,
+            Err(e) => {
+                report(Diagnostic::error(&e));
+                return EXIT_RUNTIME_ERROR;
             }
+        }
+    }
+    interp.set_ptr(bf.pointer_start() as isize);
+
+    let stdout = std::io::stdout();
+    let result = interp.run(std::io::stdin().lock(), stdout.lock());
+    if let Some(options) = tape_dump {
+        let (first, cells) = interp.tape().cells();
+        let mut dump = Vec::new();
+        tapedump::write_tape_from(&cells, first, options.format, options.full, &mut dump).unwrap();
+        let status = write_tape_dump(&dump, options);
+        if status != 0 {
+            return status;
+        }
+    }
+    if let Err(e) = result {
+        report(Diagnostic::error(&e));
+        return EXIT_RUNTIME_ERROR;
+    }
+
+    0
+}
 
-            if !stack.is_empty() {
-                return Err(UnbalancedBrackets);
+// Runs the program once per line of `path` or stdin, see `batch`
+#[cfg(not(target_os = "wasi"))]
+fn run_batch(bf: &brainfuck::Brainfuck, path: Option<&str>, options: batch::BatchOptions) -> i32 {
+    use std::io::BufReader;
+
+    let program = match bf.share() {
+        Ok(program) => program,
+        Err(e) => {
+            report(Diagnostic::error(&e));
+            return EXIT_COMPILE_ERROR;
+        }
+    };
+    let stdin = std::io::stdin();
+    let input: Box<dyn std::io::BufRead> = match path {
+        Some(path) => match std::fs::File::open(path) {
+            Ok(file) => Box::new(BufReader::new(file)),
+            Err(e) => {
+                report(Diagnostic::error(&e).file(path));
+                return EXIT_IO_ERROR;
             }
+        },
+        None => Box::new(stdin.lock()),
+    };
+    let name = path.unwrap_or("<stdin>");
 
-            Ok(Brainfuck {
-                jit_code: compile(&insts),
-                insts: insts,
-                tape_size: 30_000,
-            })
+    let stdout = std::io::stdout();
+    let result = batch::run_lines(&program, input, options, stdout.lock(), |line, e| {
+        report(Diagnostic::error(&e).file(name).line(line));
+    });
+    match result {
+        Ok(0) => 0,
+        Ok(_) => EXIT_RUNTIME_ERROR,
+        Err(e) => {
+            report(Diagnostic::error(&e));
+            EXIT_IO_ERROR
         }
+    }
+}
+
+// Runs the programs at `paths` as a pipeline from stdin to stdout, see
+// `pipeline`
+#[cfg(not(target_os = "wasi"))]
+fn pipe<'a, I: Iterator<Item=&'a str>>(paths: I, tape_size: Option<usize>) -> i32 {
+    let paths: Vec<&str> = paths.collect();
+    let mut programs = Vec::new();
+    for &path in &paths {
+        let code = match read_source(path) {
+            Ok(code) => code,
+            Err(e) => {
+                report(Diagnostic::error(&e).file(path));
+                return EXIT_IO_ERROR;
+            }
+        };
+        let mut bf = match brainfuck::Brainfuck::new(&code) {
+            Ok(bf) => bf,
+            Err(e) => {
+                report_compile_error(path, &code, &e);
+                return EXIT_COMPILE_ERROR;
+            }
+        };
+        if let Some(size) = tape_size {
+            bf.set_tape_size(size).unwrap();
+        }
+        match bf.share() {
+            Ok(program) => programs.push(program),
+            Err(e) => {
+                report(Diagnostic::error(&e).file(path));
+                return EXIT_COMPILE_ERROR;
+            }
+        }
+    }
+
+    match pipeline::run(&programs, libc::STDIN_FILENO, libc::STDOUT_FILENO) {
+        Ok(()) => 0,
+        Err(e) => {
+            let diagnostic = Diagnostic::error(&e.error).file(paths[e.stage]);
+            report(Diagnostic { message: format!("stage {} failed: {}", e.stage + 1, e.error), ..diagnostic });
+            EXIT_RUNTIME_ERROR
+        }
+    }
+}
+
+// Runs a program in the interpreter writing every `sample`th step of it to
+// a trace at `path`, see `trace`
+fn run_traced(
+    bf: &brainfuck::Brainfuck, path: &str, sample: u64, input_options: &InputOptions,
+    tape_dump: Option<&TapeDumpOptions>
+) -> i32 {
+    if let Err(e) = memory::MemoryBudget::new(bf.max_memory()).charge(bf.tape_size()) {
+        report(Diagnostic::error(&e));
+        return EXIT_RUNTIME_ERROR;
+    }
+    let input = match open_input(input_options) {
+        Ok(input) => input,
+        Err(status) => return status,
+    };
+    let mut trace = match trace::TraceWriter::create(path) {
+        Ok(trace) => trace,
+        Err(e) => {
+            report(Diagnostic::error(&e).file(path));
+            return EXIT_IO_ERROR;
+        }
+    };
+
+    let stdout = std::io::stdout();
+    let (tape, result) = match trace::run(bf, input, stdout.lock(), sample, &mut trace) {
+        Ok(run) => run,
+        Err(e) => {
+            report(Diagnostic::error(&e));
+            return EXIT_IO_ERROR;
+        }
+    };
+    if let Err(e) = trace.finish() {
+        report(Diagnostic::error(&e).file(path));
+        return EXIT_IO_ERROR;
+    }
+    if let Some(options) = tape_dump {
+        let status = dump_tape(&tape, options);
+        if status != 0 {
+            return status;
+        }
+    }
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            report(Diagnostic::error(&e));
+            EXIT_RUNTIME_ERROR
+        }
+    }
+}
+
+// Runs a program in the interpreter and writes a page going through the run
+// step by step to `path`, see `htmlreport`
+fn run_reported(
+    code: Option<&str>, bf: &brainfuck::Brainfuck, filename: &str, path: &str, input_options: &InputOptions,
+    tape_dump: Option<&TapeDumpOptions>
+) -> i32 {
+    use std::io::Write;
 
-        pub fn tape_size(&self) -> usize {
-            self.tape_size
+    if let Err(e) = memory::MemoryBudget::new(bf.max_memory()).charge(bf.tape_size()) {
+        report(Diagnostic::error(&e));
+        return EXIT_RUNTIME_ERROR;
+    }
+    let input = match open_input(input_options) {
+        Ok(input) => input,
+        Err(status) => return status,
+    };
+    let run = match htmlreport::record(bf, input) {
+        Some(run) => run,
+        None => {
+            report(Diagnostic::new("too-many-steps", format!(
+                "the run takes more than {} steps, too many for --report-html", htmlreport::MAX_STEPS
+            )).file(filename));
+            return EXIT_RUNTIME_ERROR;
         }
+    };
+    if let Err(e) = std::fs::write(path, htmlreport::to_html(filename, bf, code, &run)) {
+        report(Diagnostic::error(&e).file(path));
+        return EXIT_IO_ERROR;
+    }
 
-        pub fn set_tape_size(&mut self, size: usize) {
-            self.tape_size = size;
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    if let Err(e) = stdout.write_all(&run.output()).and_then(|_| stdout.flush()) {
+        report(Diagnostic::error(&e));
+        return EXIT_IO_ERROR;
+    }
+    if let Some(options) = tape_dump {
+        let status = dump_tape(&run.tape, options);
+        if status != 0 {
+            return status;
+        }
+    }
+    match run.error {
+        Some(e) => {
+            report(Diagnostic::error(&e));
+            EXIT_RUNTIME_ERROR
         }
+        None => 0,
+    }
+}
+
+// Runs a program in the interpreter while drawing it on stdout if that's a
+// terminal, or with a summary of the run on stderr when it's done if not
+#[cfg(not(target_os = "wasi"))]
+fn visualize(
+    code: Option<&str>, bf: &brainfuck::Brainfuck, mut options: visualize::VisualizeOptions, detect_livelock: bool,
+    input_options: &InputOptions, tape_dump: Option<&TapeDumpOptions>
+) -> i32 {
+    use std::io::{IsTerminal, Write};
+
+    if let Err(e) = memory::MemoryBudget::new(bf.max_memory()).charge(bf.tape_size()) {
+        report(Diagnostic::error(&e));
+        return EXIT_RUNTIME_ERROR;
+    }
+    let input = match open_input(input_options) {
+        Ok(input) => input,
+        Err(status) => return status,
+    };
+    let mut interp = interp::Interp::new(bf.insts(), bf.tape_size());
+    interp.tape_mut()[..bf.initial_tape().len()].copy_from_slice(bf.initial_tape());
+    interp.set_ptr(bf.pointer_start());
+    interp.set_arith_mode(bf.arith_mode());
+    interp.set_detect_livelock(detect_livelock);
+
+    let spans = code.map(|code| brainfuck::parse_with_spans(code).unwrap().1);
+    let source = code.zip(spans.as_deref()).map(|(code, spans)| visualize::Source { code, spans });
+    let stdout = std::io::stdout();
+    options.live = stdout.is_terminal();
 
-        pub fn run(&mut self) {
-            let tape = default_vec(self.tape_size, 0u8);
-            let rwx = &[
-                MapOption::MapReadable,
-                MapOption::MapWritable,
-                MapOption::MapExecutable
-            ];
-            let mapping = MemoryMap::new(self.jit_code.len(), rwx).unwrap();
-            unsafe {
-                ptr::copy(self.jit_code.as_ptr(), mapping.data(), self.jit_code.len());
+    // `process::exit` skips destructors, the cursor has to be back first
+    let cursor = if options.live {
+        match terminal::HiddenCursor::hide(libc::STDOUT_FILENO) {
+            Ok(cursor) => cursor,
+            Err(e) => {
+                report(Diagnostic::error(&e));
+                return EXIT_IO_ERROR;
             }
-            let func: fn(*const u8, *const u8) = unsafe {
-                mem::transmute(mapping.data())
-            };
-            func(ptr::null(), tape.as_ptr());  // jitted code expects tape in rsi
         }
+    } else {
+        None
+    };
+    let screen: Box<dyn Write> = if options.live { Box::new(&stdout) } else { Box::new(std::io::stderr()) };
+    let result = visualize::run(&mut interp, input, source.as_ref(), &options, &stdout, screen);
+    drop(cursor);
 
-        pub fn dump(&self) {
-            let mut shift = 0;
-            let indent = "    ";
-            for (i, inst) in self.insts.iter().enumerate() {
-                if inst.is_jmp_back() {
-                    shift -= 1;
-                }
-                for _ in 0..shift {
-                    print!("{}", indent);
-                }
-                println!("{}: {:?}", i, inst);
-                if inst.is_jmp_fwd() {
-                    shift += 1;
-                }
+    if let Some(options) = tape_dump {
+        let status = dump_tape(interp.tape(), options);
+        if status != 0 {
+            return status;
+        }
+    }
+    match result {
+        Ok(Ok(())) => 0,
+        Ok(Err(e)) => {
+            report(Diagnostic::error(&e));
+            EXIT_RUNTIME_ERROR
+        }
+        Err(e) => {
+            report(Diagnostic::error(&e));
+            EXIT_IO_ERROR
+        }
+    }
+}
+
+// Debugs a program full screen, see `tui`, with `input` as all of its input
+// if given
+#[cfg(not(target_os = "wasi"))]
+fn debug(path: &str, input: Option<&str>, tape_size: usize) -> i32 {
+    use std::io::{IsTerminal, Write};
+
+    if !std::io::stdin().is_terminal() || !std::io::stdout().is_terminal() {
+        report(Diagnostic::new("not-a-terminal", "debug needs a terminal"));
+        return EXIT_IO_ERROR;
+    }
+    let code = match read_source(path) {
+        Ok(code) => code,
+        Err(e) => {
+            report(Diagnostic::error(&e).file(path));
+            return EXIT_IO_ERROR;
+        }
+    };
+    let (insts, spans) = match brainfuck::parse_with_spans(&code) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            report_compile_error(path, &code, &e);
+            return EXIT_COMPILE_ERROR;
+        }
+    };
+    let mut session = tui::Session::new(&insts, &spans, &code, tape_size);
+    if let Some(input) = input {
+        match std::fs::read(input) {
+            Ok(bytes) => session.add_input(&bytes),
+            Err(e) => {
+                report(Diagnostic::error(&e).file(input));
+                return EXIT_IO_ERROR;
             }
         }
+        session.close_input();
+    }
 
-        pub fn dump_jit(&self) {
-            io::stdout().write(&self.jit_code);
+    let guards = terminal::RawInput::enable(libc::STDIN_FILENO)
+        .and_then(|raw| Ok((raw, terminal::HiddenCursor::full_screen(libc::STDOUT_FILENO)?)));
+    let guards = match guards {
+        Ok(guards) => guards,
+        Err(e) => {
+            report(Diagnostic::error(&e));
+            return EXIT_IO_ERROR;
         }
+    };
+    let stdout = std::io::stdout();
+    let size = || terminal::size(libc::STDOUT_FILENO).unwrap_or((80, 24));
+    let result = tui::run(&mut session, std::io::stdin().lock(), &stdout, size);
+    stdout.lock().flush().ok();
+    drop(guards);
 
+    if let Err(e) = result {
+        report(Diagnostic::error(&e));
+        return EXIT_IO_ERROR;
     }
+
+    0
 }
 
+// Runs the program as without `--watch` in a child process, and again after
+// every change to its file until interrupted. The child reports errors,
+// which don't end the watching.
+#[cfg(not(target_os = "wasi"))]
+fn watch(filename: &str) -> i32 {
+    use std::io::{IsTerminal, Write};
+    use std::path::Path;
+    use std::process::Command;
+
+    let exe = match std::env::current_exe() {
+        Ok(exe) => exe,
+        Err(e) => {
+            report(Diagnostic::error(&e));
+            return EXIT_IO_ERROR;
+        }
+    };
+    let args: Vec<_> = std::env::args_os().skip(1).filter(|arg| arg != "--watch").collect();
+    let watcher = match watch::Watcher::new(Path::new(filename)) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            report(Diagnostic::error(&e).file(filename));
+            return EXIT_IO_ERROR;
+        }
+    };
+
+    loop {
+        if std::io::stdout().is_terminal() {
+            print!("\x1b[2J\x1b[H");
+            std::io::stdout().flush().ok();
+        }
+        match Command::new(&exe).args(&args).status() {
+            Ok(status) => {
+                if let Some(code) = status.code().filter(|&code| code != 0) {
+                    eprintln!("{}: exited with {}, waiting for changes", filename, code);
+                }
+            }
+            Err(e) => {
+                report(Diagnostic::error(&e));
+                return EXIT_IO_ERROR;
+            }
+        }
+        if let Err(e) = watcher.wait_for_change() {
+            report(Diagnostic::error(&e).file(filename));
+            return EXIT_IO_ERROR;
+        }
+    }
+}
 
-#[cfg(target_arch="x86_64")]
+// Under WASI programs run in the interpreter, see `Brainfuck::run`
+#[cfg(any(target_arch="x86_64", target_os = "wasi"))]
 fn main() {
-    use std::fs::File;
-    use std::io::Read;
     use brainfuck::*;
-    use clap::{App, Arg};
+    use clap::{App, AppSettings, Arg, SubCommand};
+
+    use std::process;
 
     let matches = App::new("brainfuck-jit")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        // without it, paths like tests/x.b are taken for misspellings of `test`
+        .setting(AppSettings::InferSubcommands)
         .arg(Arg::with_name("filename").required(true))
+        .arg(Arg::with_name("lang")
+             .long("lang")
+             .value_name("LANG")
+             .possible_values(&["bf", "ir", "extended1", "brainloller"])
+             .help("Language of the program, extended1 is Extended Brainfuck Type I, brainloller \
+                    an image with the commands as colors [default: ir for .ir files, brainloller \
+                    for .png, bf otherwise]"))
+        .arg(Arg::with_name("tape-size")
+             .long("tape-size")
+             .value_name("CELLS")
+             .help("Number of cells on the tape [default: 30000]"))
+        .arg(Arg::with_name("max-memory")
+             .long("max-memory")
+             .value_name("SIZE")
+             .help("Fail once running the program takes more than SIZE bytes, like 64M"))
+        .arg(Arg::with_name("tape-init")
+             .long("tape-init")
+             .value_name("FILE")
+             .help("Copy the bytes of FILE to the start of the tape before running"))
+        .arg(Arg::with_name("pointer-start")
+             .long("pointer-start")
+             .value_name("CELL")
+             .help("Cell the pointer starts on [default: 0]"))
+        .arg(Arg::with_name("arith")
+             .long("arith")
+             .possible_values(ARITH_MODES)
+             .default_value("wrap")
+             .help(if cfg!(feature = "bignum") {
+                 "Whether cells wrap around, stay at 0 and 255 or fail the run past them, or \
+                  hold any number in the interpreter, staying at 0 or failing below it"
+             } else {
+                 "Whether cells wrap around, stay at 0 and 255 or fail the run past them"
+             }))
+        .arg(Arg::with_name("tape-model")
+             .long("tape-model")
+             .possible_values(&["bounded", "unbounded"])
+             .default_value("bounded")
+             .help("Whether the tape has --tape-size cells from 0 on, or goes on to both sides \
+                    without end in the interpreter"))
+        .arg(Arg::with_name("tape-alloc")
+             .long("tape-alloc")
+             .possible_values(&["default", "huge", "hugetlb"])
+             .default_value("default")
+             .help("Put tapes of 2 MiB and more on transparent huge pages or ones from the \
+                    huge page pool, where available"))
+        .arg(Arg::with_name("tape-guard")
+             .long("tape-guard")
+             .value_name("CELLS")
+             .min_values(0)
+             .require_equals(true)
+             .help("Put CELLS cells [default: 64] on either side of the tape that the generated \
+                    code may go into, and fail the run if it changed any of them. Catches programs \
+                    going a few cells off the tape and back, which would otherwise finish. The \
+                    interpreter stops them right away."))
+        .arg(Arg::with_name("tape-file")
+             .long("tape-file")
+             .value_name("FILE")
+             .conflicts_with_all(&[
+                 "tape-init", "tape-guard", "detect-livelock", "coverage", "coverage-out", "heatmap",
+                 "heatmap-html", "profile-out", "profile-folded", "record-input", "replay-input", "sandbox",
+                 "precompute", "visualize",
+             ])
+             .help("Map FILE as the tape, which starts out with what it holds and keeps what the \
+                    program writes. FILE has to be --tape-size bytes long."))
+        .arg(Arg::with_name("tape-file-resize")
+             .long("tape-file-resize")
+             .requires("tape-file")
+             .help("Cut off or fill up the --tape-file with zeros to --tape-size bytes, \
+                    instead of failing if it isn't as long"))
+        .arg(Arg::with_name("tape-dump")
+             .long("tape-dump")
+             .value_name("FILE")
+             .min_values(0)
+             .require_equals(true)
+             .help("Write the tape after the run to FILE [default: stderr]"))
+        .arg(Arg::with_name("tape-dump-format")
+             .long("tape-dump-format")
+             .possible_values(&["hex", "raw", "nonzero"])
+             .default_value("hex")
+             .help("Hexdump, raw bytes or a line per cell that isn't zero"))
+        .arg(Arg::with_name("tape-dump-full")
+             .long("tape-dump-full")
+             .help("Also dump the zero cells after the last one that isn't"))
+        .arg(Arg::with_name("detect-livelock")
+             .long("detect-livelock")
+             .help("Run in the interpreter and abort loops that are stuck"))
+        .arg(Arg::with_name("raw-input")
+             .long("raw-input")
+             .help("Pass every keypress to the program right away when reading from a terminal"))
+        .arg(Arg::with_name("record-input")
+             .long("record-input")
+             .value_name("FILE")
+             .help("Run in the interpreter and write every byte the program reads to FILE"))
+        .arg(Arg::with_name("replay-input")
+             .long("replay-input")
+             .value_name("FILE")
+             .help("Run in the interpreter with the bytes recorded in FILE as the input, \
+                    failing once the program reads more than them"))
+        .arg(Arg::with_name("replay-then-stdin")
+             .long("replay-then-stdin")
+             .requires("replay-input")
+             .help("Go on reading stdin once the recorded input is used up"))
+        .arg(Arg::with_name("sandbox")
+             .long("sandbox")
+             .conflicts_with_all(&[
+                 "detect-livelock", "raw-input", "tape-dump", "coverage", "coverage-out",
+                 "heatmap", "heatmap-html", "profile-out", "profile-folded", "record-input", "replay-input",
+                 "stats", "crash-report", "tape-guard",
+             ])
+             .help("Only allow the generated code to read stdin, write stdout and stderr \
+                    and exit, enforced with seccomp (Linux only)"))
+        .arg(Arg::with_name("perf-map")
+             .long("perf-map")
+             .conflicts_with_all(&[
+                 "detect-livelock", "coverage", "coverage-out", "heatmap", "heatmap-html",
+                 "profile-out", "profile-folded", "record-input", "replay-input",
+             ])
+             .help("Write symbols for the generated code to /tmp/perf-<pid>.map, for perf"))
+        .arg(Arg::with_name("precompute")
+             .long("precompute")
+             .value_name("STEPS")
+             .min_values(0)
+             .require_equals(true)
+             .help("Run a program that reads no input in the interpreter for at most STEPS \
+                    instructions [default: 100000000] and, if it finishes, only write \
+                    its output when running it"))
+        .arg(Arg::with_name("batch-lines")
+             .long("batch-lines")
+             .value_name("FILE")
+             .min_values(0)
+             .require_equals(true)
+             .conflicts_with_all(&[
+                 "detect-livelock", "raw-input", "tape-dump", "coverage", "coverage-out", "heatmap",
+                 "heatmap-html", "profile-out", "profile-folded", "record-input", "replay-input",
+                 "sandbox", "precompute", "dump", "dump-jit", "stats", "crash-report", "tape-guard",
+                 "tape-file",
+             ])
+             .help("Run the program once per line of FILE [default: stdin], with the line as \
+                    its input and a newline after its output"))
+        .arg(Arg::with_name("keep-newline")
+             .long("keep-newline")
+             .requires("batch-lines")
+             .help("End the input of a line with its newline"))
+        .arg(Arg::with_name("keep-going")
+             .long("keep-going")
+             .requires("batch-lines")
+             .help("Go on with the next line after one failed"))
+        .arg(Arg::with_name("jobs")
+             .long("jobs")
+             .short("j")
+             .value_name("N")
+             .requires("batch-lines")
+             .help("Run the program on N lines at once, their output still comes in order [default: 1]"))
+        .arg(Arg::with_name("watch")
+             .long("watch")
+             .help("Run the program again whenever its file changes, until interrupted"))
+        .arg(Arg::with_name("visualize")
+             .long("visualize")
+             .conflicts_with_all(&[
+                 "batch-lines", "coverage", "coverage-out", "heatmap", "heatmap-html", "profile-out",
+                 "profile-folded", "sandbox", "precompute", "dump", "dump-jit", "stats", "crash-report",
+             ])
+             .help("Run in the interpreter and draw the cells around the pointer and the next \
+                    instruction as it runs, or a summary of the run on stderr when stdout \
+                    isn't a terminal"))
+        .arg(Arg::with_name("visualize-delay")
+             .long("visualize-delay")
+             .value_name("MS")
+             .requires("visualize")
+             .help("Pause for MS milliseconds after every frame of --visualize [default: 100]"))
+        .arg(Arg::with_name("visualize-every")
+             .long("visualize-every")
+             .value_name("N")
+             .requires("visualize")
+             .help("Draw a frame of --visualize every N steps [default: 1]"))
+        .arg(Arg::with_name("trace-json")
+             .long("trace-json")
+             .value_name("FILE")
+             .conflicts_with_all(&[
+                 "batch-lines", "coverage", "coverage-out", "heatmap", "heatmap-html", "profile-out",
+                 "profile-folded", "sandbox", "precompute", "dump", "dump-jit", "stats", "crash-report",
+                 "visualize", "detect-livelock", "tape-file",
+             ])
+             .help("Run in the interpreter and write every step to FILE as JSON Lines, see \
+                    src/trace.rs, gzipped if FILE ends in .gz"))
+        .arg(Arg::with_name("trace-sample")
+             .long("trace-sample")
+             .value_name("N")
+             .requires("trace-json")
+             .help("Only write every Nth step to the --trace-json [default: 1]"))
+        .arg(Arg::with_name("report-html")
+             .long("report-html")
+             .value_name("FILE")
+             .conflicts_with_all(&[
+                 "batch-lines", "coverage", "coverage-out", "heatmap", "heatmap-html", "profile-out",
+                 "profile-folded", "sandbox", "precompute", "dump", "dump-jit", "stats", "crash-report",
+                 "visualize", "detect-livelock", "tape-file", "trace-json",
+             ])
+             .help("Run in the interpreter and write a page going through the run step by step to \
+                    FILE, for runs of up to 50000 steps"))
+        .arg(Arg::with_name("verbose")
+             .long("verbose")
+             .help("Report how long compiling and running took on stderr"))
+        .arg(Arg::with_name("dump")
+             .long("dump")
+             .conflicts_with("dump-jit")
+             .help("Print the instructions instead of running them"))
+        .arg(Arg::with_name("dump-labels")
+             .long("dump-labels")
+             .requires("dump")
+             .help("Name loops L0, L1, ... in --dump instead of numbering jumps, \
+                    followed by where they are, without colors"))
+        .arg(Arg::with_name("color")
+             .long("color")
+             .value_name("WHEN")
+             .possible_values(&["auto", "always", "never"])
+             .default_value("auto")
+             .help("Color the output of --dump, auto colors it on terminals"))
+        .arg(Arg::with_name("dump-jit")
+             .long("dump-jit")
+             .help("Print the generated machine code instead of running it, \
+                    as a hexdump on terminals"))
+        .arg(Arg::with_name("raw")
+             .long("raw")
+             .requires("dump-jit")
+             .help("Print the machine code as raw bytes even on terminals"))
+        .arg(Arg::with_name("coverage")
+             .long("coverage")
+             .help("Run in the interpreter and report commands that never executed"))
+        .arg(Arg::with_name("coverage-out")
+             .long("coverage-out")
+             .value_name("FILE")
+             .help("Run in the interpreter and add the execution counts to FILE"))
+        .arg(Arg::with_name("heatmap")
+             .long("heatmap")
+             .help("Run in the interpreter and print the program colored by execution counts"))
+        .arg(Arg::with_name("heatmap-html")
+             .long("heatmap-html")
+             .value_name("FILE")
+             .help("Run in the interpreter and write the heatmap to FILE as HTML"))
+        .arg(Arg::with_name("profile-out")
+             .long("profile-out")
+             .value_name("FILE")
+             .help("Run in the interpreter and write how often each loop was entered and went \
+                    round to FILE as JSON"))
+        .arg(Arg::with_name("profile-folded")
+             .long("profile-folded")
+             .value_name("FILE")
+             .help("Run in the interpreter and write collapsed stacks of the loops to FILE, \
+                    counting every instruction executed for the innermost loop it's in, \
+                    for flamegraph.pl or inferno"))
+        .arg(Arg::with_name("stats")
+             .long("stats")
+             .value_name("FORMAT")
+             .min_values(0)
+             .require_equals(true)
+             .possible_values(&["text", "json"])
+             .help("Write what the run did to stderr once it's done [default: text]: how many \
+                    instructions it executed, bytes it read and wrote and where the pointer went. \
+                    Generated code counts none of it, only the interpreter does, which runs the \
+                    program with any of the options that need it."))
+        .arg(Arg::with_name("crash-report")
+             .long("crash-report")
+             .value_name("FILE")
+             .min_values(0)
+             .require_equals(true)
+             .help("When the run fails, write a report on it to FILE: the error, where in the \
+                    source, the pointer, the cells around it, the last instructions executed and \
+                    how many there were. Without FILE an abbreviated one goes to stderr. Of \
+                    generated code only where it failed is known, the interpreter knows it all \
+                    and runs the program with any of the options that need it."))
+        .arg(Arg::with_name("crash-report-format")
+             .long("crash-report-format")
+             .value_name("FORMAT")
+             .possible_values(&["text", "json"])
+             .requires("crash-report")
+             .help("Format of the crash report [default: text]"))
+        .arg(Arg::with_name("diagnostics")
+             .long("diagnostics")
+             .value_name("FORMAT")
+             .possible_values(&["human", "json"])
+             .help("Report errors for people, or as JSON Lines with an object per error [default: human]"))
+        .arg(Arg::with_name("diagnostics-out")
+             .long("diagnostics-out")
+             .value_name("FILE")
+             .help("Report errors to FILE instead of stderr"))
+        .subcommand(SubCommand::with_name("check")
+                    .about("Validates programs without running them")
+                    .arg(Arg::with_name("files").required(true).multiple(true))
+                    .arg(Arg::with_name("diagnostics")
+                         .long("diagnostics")
+                         .value_name("FORMAT")
+                         .possible_values(&["human", "json"])
+                         .help("Report errors for people, or as JSON Lines with an object per error \
+                                [default: human]"))
+                    .arg(Arg::with_name("diagnostics-out")
+                         .long("diagnostics-out")
+                         .value_name("FILE")
+                         .help("Report errors to FILE instead of stderr")))
+        .subcommand(SubCommand::with_name("fmt")
+                    .about("Rewrites a program in canonical layout")
+                    .arg(Arg::with_name("filename").required(true))
+                    .arg(Arg::with_name("check")
+                         .long("check")
+                         .help("Only report whether the file would change"))
+                    .arg(Arg::with_name("strip-comments")
+                         .long("strip-comments")
+                         .help("Drop everything that isn't a command"))
+                    .arg(Arg::with_name("width")
+                         .long("width")
+                         .value_name("COLUMNS")
+                         .help("Maximum line width [default: 80]")))
+        .subcommand(SubCommand::with_name("minify")
+                    .about("Writes an optimized, equivalent program without comments")
+                    .arg(Arg::with_name("filename").required(true))
+                    .arg(Arg::with_name("output")
+                         .short("o")
+                         .value_name("FILE")
+                         .help("Output file [default: stdout]")))
+        .subcommand(SubCommand::with_name("compile")
+                    .about("Writes an optimized program as bytecode, which runs without parsing, as IR, as a listing, \
+                            as machine code, as JavaScript or as an executable for macOS or Windows")
+                    .arg(Arg::with_name("filename").required(true))
+                    .arg(Arg::with_name("emit")
+                         .long("emit")
+                         .possible_values(&["bfc", "ir", "listing", "code", "js", "macho", "pe"])
+                         .default_value("bfc")
+                         .help("Bytecode, the instructions as text to read and edit, \
+                                the source with the machine code of every line, the \
+                                unoptimized machine code alone, compiled as the source is read, \
+                                a JavaScript module, an x86_64 Mach-O executable, which Apple Silicon runs under \
+                                Rosetta 2 without a signature, or a PE32+ one for Windows"))
+                    .arg(Arg::with_name("output")
+                         .short("o")
+                         .value_name("FILE")
+                         .help("Output file [default: the input with a .bfc, .ir, .lst, .bin, .js, .mjs \
+                                or .exe extension, without one for macho]"))
+                    .arg(Arg::with_name("js-format")
+                         .long("js-format")
+                         .possible_values(&["umd", "esm"])
+                         .default_value("umd")
+                         .help("How the JavaScript module exports `run`: for require() and a <script> tag, \
+                                or for import"))
+                    .arg(Arg::with_name("tape-size")
+                         .long("tape-size")
+                         .value_name("CELLS")
+                         .help("Number of cells on the tape [default: 30000]")))
+        .subcommand(SubCommand::with_name("stats")
+                    .about("Prints static metrics of a program")
+                    .arg(Arg::with_name("filename").required(true))
+                    .arg(Arg::with_name("format")
+                         .long("format")
+                         .possible_values(&["text", "json"])
+                         .default_value("text")))
+        .subcommand(SubCommand::with_name("diff")
+                    .about("Compares the instructions of two programs, whatever their comments and layout, \
+                            and exits with 0 only if they are the same")
+                    .arg(Arg::with_name("a").required(true))
+                    .arg(Arg::with_name("b").required(true))
+                    .arg(Arg::with_name("semantic-only")
+                         .long("semantic-only")
+                         .help("Compare the programs as optimized, which makes many equivalent ones the same")))
+        .subcommand(SubCommand::with_name("lsp")
+                    .about("Serves editors as a language server over stdin and stdout, with bracket errors, \
+                            the matching bracket and what instruction is at the cursor"))
+        .subcommand(SubCommand::with_name("test")
+                    .about("Runs every NAME.b in a directory that has a NAME.expected with NAME.in as \
+                            its input, if there is one, and compares what it prints")
+                    .arg(Arg::with_name("dir").required(true))
+                    .arg(Arg::with_name("engine")
+                         .long("engine")
+                         .possible_values(&["interp", "jit", "both"])
+                         .default_value("jit")
+                         .help("Run in the interpreter, as generated code, or both, which have to \
+                                print the same"))
+                    .arg(Arg::with_name("steps")
+                         .long("steps")
+                         .value_name("STEPS")
+                         .help("Fail a test after the interpreter took STEPS steps [default: 100000000]"))
+                    .arg(Arg::with_name("timeout")
+                         .long("timeout")
+                         .value_name("SECONDS")
+                         .help("Fail a test still running after SECONDS [default: 10]"))
+                    .arg(Arg::with_name("jobs")
+                         .short("j")
+                         .long("jobs")
+                         .value_name("N")
+                         .help("Run N tests at the same time [default: 1]")))
+        .subcommand(SubCommand::with_name("pipe")
+                    .about("Runs programs as a pipeline, each reading what the one before it prints, \
+                            the first one reading stdin and the last one writing stdout")
+                    .arg(Arg::with_name("programs").required(true).multiple(true).min_values(2))
+                    .arg(Arg::with_name("tape-size")
+                         .long("tape-size")
+                         .value_name("CELLS")
+                         .help("Number of cells on the tape of every program [default: 30000]")))
+        .subcommand(SubCommand::with_name("debug")
+                    .about("Steps through a program in the interpreter, full screen on a terminal")
+                    .arg(Arg::with_name("filename").required(true))
+                    .arg(Arg::with_name("input")
+                         .long("input")
+                         .value_name("FILE")
+                         .help("Give the program FILE as its whole input, instead of typing it \
+                                in with :input as it's read"))
+                    .arg(Arg::with_name("tape-size")
+                         .long("tape-size")
+                         .value_name("CELLS")
+                         .help("Number of cells on the tape [default: 30000]")))
         .get_matches();
 
-    let mut code = String::new();
+    set_diagnostics_output(&matches);
+    match matches.subcommand() {
+        ("check", Some(matches)) => {
+            set_diagnostics_output(matches);
+            process::exit(check(matches.values_of("files").unwrap()));
+        }
+        ("fmt", Some(matches)) => {
+            let mut options = formatter::FormatOptions {
+                strip_comments: matches.is_present("strip-comments"),
+                ..Default::default()
+            };
+            if let Some(width) = matches.value_of("width") {
+                options.width = width.parse().unwrap_or_else(|_| {
+                    report(Diagnostic::new("invalid-option", format!("invalid width '{}'", width)));
+                    process::exit(EXIT_COMPILE_ERROR);
+                });
+            }
+            let filename = matches.value_of("filename").unwrap();
+            process::exit(fmt(filename, &options, matches.is_present("check")));
+        }
+        ("minify", Some(matches)) => {
+            let filename = matches.value_of("filename").unwrap();
+            process::exit(minify(filename, matches.value_of("output")));
+        }
+        ("compile", Some(matches)) => {
+            let filename = matches.value_of("filename").unwrap();
+            let tape_size = match matches.value_of("tape-size") {
+                Some(size) => match size.parse() {
+                    Ok(size) if size > 0 => size,
+                    _ => {
+                        report(Diagnostic::new("invalid-option", format!("invalid tape size '{}'", size)));
+                        process::exit(EXIT_COMPILE_ERROR);
+                    }
+                },
+                None => Brainfuck::new("").unwrap().tape_size(),
+            };
+            let emit = matches.value_of("emit").unwrap();
+            let js_format = match matches.value_of("js-format").unwrap() {
+                "esm" => js::JsFormat::Esm,
+                _ => js::JsFormat::Umd,
+            };
+            process::exit(compile(filename, matches.value_of("output"), emit, tape_size, js_format));
+        }
+        ("stats", Some(matches)) => {
+            let filename = matches.value_of("filename").unwrap();
+            process::exit(stats(filename, matches.value_of("format") == Some("json")));
+        }
+        ("diff", Some(matches)) => {
+            let paths = [matches.value_of("a").unwrap(), matches.value_of("b").unwrap()];
+            process::exit(diff(paths, matches.is_present("semantic-only")));
+        }
+        ("lsp", Some(_)) => {
+            let stdin = std::io::stdin();
+            match lsp::serve(stdin.lock(), std::io::stdout()) {
+                Ok(code) => process::exit(code),
+                Err(e) => {
+                    report(Diagnostic::error(&e));
+                    process::exit(EXIT_IO_ERROR);
+                }
+            }
+        }
+        #[cfg(not(target_os = "wasi"))]
+        ("test", Some(matches)) => {
+            let number = |name: &str, default: u64| match matches.value_of(name) {
+                Some(value) => value.parse().unwrap_or_else(|_| {
+                    report(Diagnostic::new("invalid-option", format!("invalid {} '{}'", name, value)));
+                    process::exit(EXIT_RUNTIME_ERROR);
+                }),
+                None => default,
+            };
+            let options = harness::HarnessOptions {
+                engine: match matches.value_of("engine") {
+                    Some("interp") => harness::Engine::Interpreter,
+                    Some("both") => harness::Engine::Both,
+                    _ => harness::Engine::Jit,
+                },
+                steps: number("steps", harness::DEFAULT_STEPS as u64) as usize,
+                timeout: std::time::Duration::from_secs(number("timeout", harness::DEFAULT_TIMEOUT.as_secs())),
+                jobs: number("jobs", 1) as usize,
+            };
+            process::exit(test(matches.value_of("dir").unwrap(), &options));
+        }
+        #[cfg(not(target_os = "wasi"))]
+        ("pipe", Some(matches)) => {
+            let tape_size = matches.value_of("tape-size").map(|size| match size.parse() {
+                Ok(size) if size > 0 => size,
+                _ => {
+                    report(Diagnostic::new("invalid-option", format!("invalid tape size '{}'", size)));
+                    process::exit(EXIT_RUNTIME_ERROR);
+                }
+            });
+            process::exit(pipe(matches.values_of("programs").unwrap(), tape_size));
+        }
+        #[cfg(not(target_os = "wasi"))]
+        ("debug", Some(matches)) => {
+            let tape_size = match matches.value_of("tape-size") {
+                Some(size) => match size.parse() {
+                    Ok(size) if size > 0 => size,
+                    _ => {
+                        report(Diagnostic::new("invalid-option", format!("invalid tape size '{}'", size)));
+                        process::exit(EXIT_RUNTIME_ERROR);
+                    }
+                },
+                None => Brainfuck::new("").unwrap().tape_size(),
+            };
+            process::exit(debug(matches.value_of("filename").unwrap(), matches.value_of("input"), tape_size));
+        }
+        _ => {}
+    }
 
-    File::open(matches.value_of("filename").unwrap()).unwrap()
-        .read_to_string(&mut code).unwrap();
+    #[cfg(target_os = "wasi")]
+    for flag in &["perf-map", "raw-input", "watch", "batch-lines", "visualize", "tape-file"] {
+        if matches.is_present(flag) {
+            report(Diagnostic::new("unsupported-option", format!("--{} isn't supported under WASI", flag)));
+            process::exit(EXIT_RUNTIME_ERROR);
+        }
+    }
 
-    Brainfuck::new(&code).unwrap().run();
+    let filename = matches.value_of("filename").unwrap();
+    #[cfg(not(target_os = "wasi"))]
+    if matches.is_present("watch") {
+        process::exit(watch(filename));
+    }
+    let verbose = matches.is_present("verbose");
+    #[cfg(feature = "tracing")]
+    if verbose {
+        trace_stages();
+    }
+    let bytes = std::fs::read(filename).unwrap_or_else(|e| {
+        report(Diagnostic::error(&e).file(filename));
+        process::exit(EXIT_IO_ERROR);
+    });
+    let started = std::time::Instant::now();
+
+    let lang = matches.value_of("lang").unwrap_or_else(|| {
+        if filename.ends_with(".ir") {
+            "ir"
+        } else if filename.ends_with(".png") {
+            "brainloller"
+        } else {
+            "bf"
+        }
+    });
+
+    // bytecode skips parsing, but there's no source to point errors at then
+    let (mut bf, code) = if bytecode::is_bytecode(&bytes) {
+        let program = bytecode::decode(&bytes)
+            .and_then(|program| {
+                let mut bf = Brainfuck::from_insts(program.insts).map_err(bytecode::BytecodeError::Invalid)?;
+                bf.set_tape_size(program.tape_size).unwrap();
+                Ok(bf)
+            });
+        let bf = program.unwrap_or_else(|e| {
+            report(Diagnostic::error(&e).file(filename));
+            process::exit(EXIT_COMPILE_ERROR);
+        });
+        (bf, None)
+    } else if lang == "ir" {
+        let text = String::from_utf8(bytes).unwrap_or_else(|_| {
+            report(Diagnostic::new("invalid-utf8", "stream did not contain valid UTF-8").file(filename));
+            process::exit(EXIT_IO_ERROR);
+        });
+        let bf = ir::parse(&text)
+            .and_then(|insts| Brainfuck::from_insts(insts).map_err(ir::IrError::Invalid))
+            .unwrap_or_else(|e| {
+                report(Diagnostic::error(&e).file(filename));
+                process::exit(EXIT_COMPILE_ERROR);
+            });
+        (bf, None)
+    } else if lang == "extended1" {
+        let code = String::from_utf8(bytes).unwrap_or_else(|_| {
+            report(Diagnostic::new("invalid-utf8", "stream did not contain valid UTF-8").file(filename));
+            process::exit(EXIT_IO_ERROR);
+        });
+        let bf = Brainfuck::with_dialect(&code, Dialect::Extended1).unwrap_or_else(|e| {
+            report_compile_error(filename, &code, &e);
+            process::exit(EXIT_COMPILE_ERROR);
+        });
+        // what maps instructions back to the source only knows brainfuck
+        (bf, None)
+    } else if lang == "brainloller" {
+        let program = brainloller::decode(&bytes).unwrap_or_else(|e| {
+            report(Diagnostic::error(&e).file(filename));
+            process::exit(EXIT_COMPILE_ERROR);
+        });
+        let bf = Brainfuck::new(&program.source).unwrap_or_else(|e| {
+            for diagnostic in brainloller::compile_error(&program, &e) {
+                report(diagnostic.file(filename));
+            }
+            process::exit(EXIT_COMPILE_ERROR);
+        });
+        // there's no text for errors at run time to point into
+        (bf, None)
+    } else {
+        let code = String::from_utf8(bytes).unwrap_or_else(|_| {
+            report(Diagnostic::new("invalid-utf8", "stream did not contain valid UTF-8").file(filename));
+            process::exit(EXIT_IO_ERROR);
+        });
+        let bf = Brainfuck::new(&code).unwrap_or_else(|e| {
+            report_compile_error(filename, &code, &e);
+            process::exit(EXIT_COMPILE_ERROR);
+        });
+        (bf, Some(code))
+    };
+
+    if verbose {
+        match bf.code_size() {
+            Ok(size) => eprintln!(
+                "{}: compiled {} instructions to {} bytes of code in {:?}",
+                filename, bf.insts().len(), size, started.elapsed()
+            ),
+            // reported if the code runs
+            Err(_) => eprintln!("{}: parsed {} instructions in {:?}", filename, bf.insts().len(), started.elapsed()),
+        }
+    }
+
+    if let Some(size) = matches.value_of("tape-size") {
+        let size = size.parse().unwrap_or_else(|_| {
+            report(Diagnostic::new("invalid-option", format!("invalid tape size '{}'", size)));
+            process::exit(EXIT_RUNTIME_ERROR);
+        });
+        if let Err(e) = bf.set_tape_size(size) {
+            report(Diagnostic::error(&e));
+            process::exit(EXIT_RUNTIME_ERROR);
+        }
+    }
+
+    if let Some(size) = matches.value_of("max-memory") {
+        let bytes = memory::parse_size(size).unwrap_or_else(|| {
+            report(Diagnostic::new("invalid-option", format!("invalid memory limit '{}'", size)));
+            process::exit(EXIT_RUNTIME_ERROR);
+        });
+        bf.set_max_memory(bytes);
+    }
+
+    if let Some(path) = matches.value_of("tape-init") {
+        let cells = std::fs::read(path).unwrap_or_else(|e| {
+            report(Diagnostic::error(&e).file(path));
+            process::exit(EXIT_IO_ERROR);
+        });
+        if let Err(e) = bf.set_initial_tape(&cells) {
+            report(Diagnostic::error(&e).file(path));
+            process::exit(EXIT_RUNTIME_ERROR);
+        }
+    }
+
+    if matches.is_present("tape-guard") {
+        bf.set_tape_guard(match matches.value_of("tape-guard") {
+            Some(cells) => cells.parse().unwrap_or_else(|_| {
+                report(Diagnostic::new("invalid-option", format!("invalid tape guard '{}'", cells)));
+                process::exit(EXIT_RUNTIME_ERROR);
+            }),
+            None => DEFAULT_TAPE_GUARD,
+        });
+    }
+
+    #[cfg(not(target_os = "wasi"))]
+    if let Some(path) = matches.value_of("tape-file") {
+        let size = if matches.is_present("tape-file-resize") { TapeFileSize::Resize } else { TapeFileSize::Exact };
+        bf.set_tape_file(Some(path.into()), size);
+    }
+
+    bf.set_tape_alloc(match matches.value_of("tape-alloc") {
+        Some("huge") => TapeAlloc::Huge,
+        Some("hugetlb") => TapeAlloc::HugeTlb,
+        _ => TapeAlloc::Default,
+    });
+
+    if let Some(start) = matches.value_of("pointer-start") {
+        let start = start.parse().unwrap_or_else(|_| {
+            report(Diagnostic::new("invalid-option", format!("invalid pointer start '{}'", start)));
+            process::exit(EXIT_RUNTIME_ERROR);
+        });
+        if let Err(e) = bf.set_pointer_start(start) {
+            report(Diagnostic::error(&e));
+            process::exit(EXIT_RUNTIME_ERROR);
+        }
+    }
+
+    let arith = match matches.value_of("arith") {
+        Some("saturate") => ArithMode::Saturate,
+        Some("trap") => ArithMode::Trap,
+        #[cfg(feature = "bignum")]
+        Some("unbounded") => ArithMode::Unbounded(bignum::Underflow::Saturate),
+        #[cfg(feature = "bignum")]
+        Some("unbounded-trap") => ArithMode::Unbounded(bignum::Underflow::Trap),
+        _ => ArithMode::Wrap,
+    };
+    #[cfg(feature = "bignum")]
+    if let ArithMode::Unbounded(underflow) = arith {
+        if bf.insts().iter().any(|inst| matches!(*inst, Inst::Ext(op) if op != ExtOp::End)) {
+            report(Diagnostic::new("unsupported-arith-mode", "Extended Type I needs cells of a byte").file(filename));
+            process::exit(EXIT_COMPILE_ERROR);
+        }
+        process::exit(run_unbounded(&bf, underflow, &matches));
+    }
+    if let Err(e) = bf.set_arith_mode(arith) {
+        report(Diagnostic::error(&e).file(filename));
+        process::exit(EXIT_COMPILE_ERROR);
+    }
+    let tape_model = match matches.value_of("tape-model") {
+        Some("unbounded") => twosided::TapeModel::Unbounded,
+        _ => twosided::TapeModel::Bounded,
+    };
+    if tape_model == twosided::TapeModel::Unbounded {
+        process::exit(run_two_sided(&bf, &matches, tape_dump_options(&matches).as_ref()));
+    }
+
+    #[cfg(not(target_os = "wasi"))]
+    if matches.is_present("batch-lines") {
+        let options = batch::BatchOptions {
+            keep_newline: matches.is_present("keep-newline"),
+            keep_going: matches.is_present("keep-going"),
+            jobs: match matches.value_of("jobs").map(str::parse) {
+                Some(Ok(jobs)) if jobs > 0 => jobs,
+                Some(_) => {
+                    let jobs = matches.value_of("jobs").unwrap();
+                    report(Diagnostic::new("invalid-option", format!("invalid number of jobs '{}'", jobs)));
+                    process::exit(EXIT_RUNTIME_ERROR);
+                }
+                None => 1,
+            },
+        };
+        process::exit(run_batch(&bf, matches.value_of("batch-lines"), options));
+    }
+
+    if matches.is_present("precompute") {
+        let steps = match matches.value_of("precompute") {
+            Some(steps) => steps.parse().unwrap_or_else(|_| {
+                report(Diagnostic::new("invalid-option", format!("invalid step budget '{}'", steps)));
+                process::exit(EXIT_RUNTIME_ERROR);
+            }),
+            None => DEFAULT_PRECOMPUTE_STEPS,
+        };
+        let started = std::time::Instant::now();
+        let precomputed = bf.precompute(steps);
+        if verbose {
+            eprintln!("{}: {} in {:?}", filename, precomputed, started.elapsed());
+        }
+    }
+
+    if matches.is_present("perf-map") {
+        let spans = code.as_ref().map(|code| parse_with_spans(code).unwrap().1);
+        bf.set_perf_map(Some(perfmap::default_path()), spans.as_ref().map(|spans| &spans[..]));
+    }
+
+    let coverage_options = CoverageOptions {
+        out: matches.value_of("coverage-out"),
+        report: matches.is_present("coverage"),
+        heatmap: matches.is_present("heatmap"),
+        heatmap_html: matches.value_of("heatmap-html"),
+        profile_out: matches.value_of("profile-out"),
+        profile_folded: matches.value_of("profile-folded"),
+    };
+    let coverage = coverage_options.out.is_some() || coverage_options.report
+        || coverage_options.heatmap || coverage_options.heatmap_html.is_some();
+
+    if matches.is_present("dump") {
+        use std::io::IsTerminal;
+
+        let stdout = std::io::stdout();
+        let color = match matches.value_of("color") {
+            Some("always") => true,
+            Some("never") => false,
+            _ => stdout.is_terminal(),
+        };
+        let result = if matches.is_present("dump-labels") {
+            let spans = code.as_ref().map(|code| parse_with_spans(code).unwrap().1);
+            dump::write_labeled(bf.insts(), spans.as_ref().map(|spans| &spans[..]), stdout.lock())
+        } else if color {
+            dump::write_colored(bf.insts(), stdout.lock())
+        } else {
+            bf.write_dump(stdout.lock())
+        };
+        if let Err(e) = result {
+            report(Diagnostic::error(&e));
+            process::exit(EXIT_IO_ERROR);
+        }
+        return;
+    }
+
+    if matches.is_present("dump-jit") {
+        use std::io::IsTerminal;
+
+        let stdout = std::io::stdout();
+        let result = if stdout.is_terminal() && !matches.is_present("raw") {
+            bf.dump_jit_hex(stdout.lock())
+        } else {
+            bf.dump_jit()
+        };
+        match result {
+            Ok(()) => return,
+            Err(CompileError::Io(e)) => {
+                report(Diagnostic::error(&e));
+                process::exit(EXIT_IO_ERROR);
+            }
+            Err(e) => {
+                report(Diagnostic::error(&e).file(filename));
+                process::exit(EXIT_COMPILE_ERROR);
+            }
+        }
+    }
+
+    if coverage && code.is_none() {
+        report(Diagnostic::new("unsupported-option", "coverage needs the program source, not bytecode").file(filename));
+        process::exit(EXIT_COMPILE_ERROR);
+    }
+
+    if matches.is_present("sandbox") {
+        // only comes back if the program didn't run
+        #[cfg(target_os = "linux")]
+        let diagnostic = match bf.run_sandboxed() {
+            Ok(never) => match never {},
+            Err(e) => Diagnostic::error(&e),
+        };
+        #[cfg(not(target_os = "linux"))]
+        let diagnostic = Diagnostic::new("unsupported-option", "the sandbox is only supported on Linux");
+        report(diagnostic);
+        process::exit(EXIT_RUNTIME_ERROR);
+    }
+
+    // `process::exit` skips destructors, the terminal has to be restored first
+    #[cfg(not(target_os = "wasi"))]
+    let raw_input = if matches.is_present("raw-input") {
+        terminal::RawInput::enable(libc::STDIN_FILENO).unwrap_or_else(|e| {
+            report(Diagnostic::error(&e));
+            process::exit(EXIT_IO_ERROR);
+        })
+    } else {
+        None
+    };
+    let tape_dump = tape_dump_options(&matches);
+    let input_options = InputOptions {
+        record: matches.value_of("record-input"),
+        replay: matches.value_of("replay-input"),
+        then_stdin: matches.is_present("replay-then-stdin"),
+    };
+    #[cfg(not(target_os = "wasi"))]
+    if matches.is_present("visualize") {
+        let number = |name: &str, default: u64| match matches.value_of(name) {
+            Some(value) => value.parse().ok().filter(|&n| n > 0 || name == "visualize-delay").unwrap_or_else(|| {
+                report(Diagnostic::new("invalid-option", format!("invalid --{} '{}'", name, value)));
+                process::exit(EXIT_RUNTIME_ERROR);
+            }),
+            None => default,
+        };
+        let options = visualize::VisualizeOptions {
+            every: number("visualize-every", 1),
+            delay: std::time::Duration::from_millis(
+                number("visualize-delay", visualize::DEFAULT_DELAY.as_millis() as u64)
+            ),
+            live: false,
+        };
+        let status = visualize(
+            code.as_ref().map(|code| &code[..]), &bf, options, matches.is_present("detect-livelock"),
+            &input_options, tape_dump.as_ref()
+        );
+        drop(raw_input);
+        process::exit(status);
+    }
+    if let Some(path) = matches.value_of("trace-json") {
+        let sample = match matches.value_of("trace-sample").map(str::parse) {
+            Some(Ok(sample)) if sample > 0 => sample,
+            Some(_) => {
+                let sample = matches.value_of("trace-sample").unwrap();
+                report(Diagnostic::new("invalid-option", format!("invalid --trace-sample '{}'", sample)));
+                process::exit(EXIT_RUNTIME_ERROR);
+            }
+            None => 1,
+        };
+        let status = run_traced(&bf, path, sample, &input_options, tape_dump.as_ref());
+        #[cfg(not(target_os = "wasi"))]
+        drop(raw_input);
+        process::exit(status);
+    }
+    if let Some(path) = matches.value_of("report-html") {
+        let status = run_reported(
+            code.as_ref().map(|code| &code[..]), &bf, filename, path, &input_options, tape_dump.as_ref()
+        );
+        #[cfg(not(target_os = "wasi"))]
+        drop(raw_input);
+        process::exit(status);
+    }
+    let stats = if matches.is_present("stats") { Some(matches.value_of("stats").unwrap_or("text")) } else { None };
+    let crash_report = if matches.is_present("crash-report") {
+        Some(CrashReportOptions {
+            path: matches.value_of("crash-report"),
+            json: matches.value_of("crash-report-format") == Some("json"),
+        })
+    } else {
+        None
+    };
+    // after `RawInput`, whose handler then takes a second Ctrl-C
+    #[cfg(not(target_os = "wasi"))]
+    let interrupt_guard = interrupt::InterruptGuard::install().unwrap_or_else(|e| {
+        report(Diagnostic::error(&e));
+        process::exit(EXIT_IO_ERROR);
+    });
+    let started = std::time::Instant::now();
+    let options = RunOptions {
+        detect_livelock: matches.is_present("detect-livelock"),
+        coverage: &coverage_options,
+        input: &input_options,
+        tape_dump: tape_dump.as_ref(),
+        stats,
+        crash_report: crash_report.as_ref(),
+    };
+    let status = run(filename, code.as_ref().map(|code| &code[..]), &mut bf, options);
+    if verbose {
+        eprintln!("{}: ran in {:?}", filename, started.elapsed());
+        if bf.tape_alloc() != TapeAlloc::Default {
+            eprintln!("{}: tape allocated as {:?}", filename, bf.last_tape_alloc());
+        }
+    }
+    #[cfg(not(target_os = "wasi"))]
+    {
+        drop(interrupt_guard);
+        drop(raw_input);
+    }
+
+    if status != 0 {
+        process::exit(status);
+    }
 }
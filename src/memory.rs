@@ -0,0 +1,167 @@
+use std::io::{self, Write};
+use std::mem;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use brainfuck::RuntimeError;
+
+
+// How much memory a run may allocate on behalf of the program, shared by
+// everything that does: the tape, input held in memory and output collected
+// in memory. Allocations are charged before they happen, so a program
+// hitting the limit fails with `RuntimeError::MemoryLimitExceeded` instead
+// of the allocator aborting the process. Clones share the same budget.
+#[derive(Debug, Clone)]
+pub struct MemoryBudget {
+    limit: usize,
+    used: Arc<AtomicUsize>,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: usize) -> MemoryBudget {
+        MemoryBudget { limit, used: Arc::new(AtomicUsize::new(0)) }
+    }
+
+    pub fn unlimited() -> MemoryBudget {
+        MemoryBudget::new(usize::MAX)
+    }
+
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::SeqCst)
+    }
+
+    // Takes `bytes` from the budget, nothing is taken if they don't fit
+    pub fn charge(&self, bytes: usize) -> Result<(), RuntimeError> {
+        let limit = self.limit;
+        self.used
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                used.checked_add(bytes).filter(|&total| total <= limit)
+            })
+            .map(|_| ())
+            .map_err(|used| RuntimeError::MemoryLimitExceeded { limit, requested: used.saturating_add(bytes) })
+    }
+
+    pub fn release(&self, bytes: usize) {
+        self.used.fetch_sub(bytes, Ordering::SeqCst);
+    }
+}
+
+// A size in bytes with an optional binary suffix, like "64M"
+pub fn parse_size(size: &str) -> Option<usize> {
+    let (digits, shift) = match size.as_bytes().last() {
+        Some(b'K') | Some(b'k') => (&size[..size.len() - 1], 10),
+        Some(b'M') | Some(b'm') => (&size[..size.len() - 1], 20),
+        Some(b'G') | Some(b'g') => (&size[..size.len() - 1], 30),
+        _ => (size, 0),
+    };
+    let value: usize = digits.parse().ok()?;
+
+    value.checked_mul(1 << shift)
+}
+
+// Output collected in memory, growing only as far as the budget allows. The
+// buffer's capacity is what's charged, as that's what is allocated. A write
+// that doesn't fit fails with an error that converts back into
+// `RuntimeError::MemoryLimitExceeded`.
+pub struct BudgetedBuffer {
+    buf: Vec<u8>,
+    // the capacity paid for, `reserve_exact` may hand out more
+    charged: usize,
+    budget: MemoryBudget,
+}
+
+impl BudgetedBuffer {
+    pub fn new(budget: MemoryBudget) -> BudgetedBuffer {
+        BudgetedBuffer { buf: Vec::new(), charged: 0, budget }
+    }
+
+    pub fn into_inner(mut self) -> Vec<u8> {
+        mem::take(&mut self.buf)
+    }
+}
+
+impl Write for BudgetedBuffer {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let needed = self.buf.len() + data.len();
+        if needed > self.charged {
+            // the usual doubling where the budget allows, just what's needed
+            // otherwise
+            let doubled = needed.max(2 * self.charged).max(64);
+            let capacity = if self.budget.charge(doubled - self.charged).is_ok() {
+                doubled
+            } else {
+                self.budget.charge(needed - self.charged).map_err(io::Error::other)?;
+                needed
+            };
+            self.buf.reserve_exact(capacity - self.buf.len());
+            self.charged = capacity;
+        }
+        self.buf.extend_from_slice(data);
+
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for BudgetedBuffer {
+    fn drop(&mut self) {
+        self.budget.release(self.charged);
+    }
+}
+
+
+#[test]
+fn test_memory_budget() {
+    let budget = MemoryBudget::new(100);
+    let shared = budget.clone();
+    budget.charge(60).unwrap();
+    match shared.charge(41) {
+        Err(RuntimeError::MemoryLimitExceeded { limit: 100, requested: 101 }) => {}
+        other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(shared.used(), 60);
+    shared.charge(40).unwrap();
+    budget.release(100);
+    assert_eq!(budget.used(), 0);
+
+    assert!(MemoryBudget::unlimited().charge(usize::MAX).is_ok());
+    assert!(budget.charge(usize::MAX).is_err());
+}
+
+#[test]
+fn test_parse_size() {
+    assert_eq!(parse_size("0"), Some(0));
+    assert_eq!(parse_size("4096"), Some(4096));
+    assert_eq!(parse_size("64K"), Some(64 << 10));
+    assert_eq!(parse_size("64M"), Some(64 << 20));
+    assert_eq!(parse_size("2g"), Some(2 << 30));
+    assert_eq!(parse_size(""), None);
+    assert_eq!(parse_size("M"), None);
+    assert_eq!(parse_size("-1"), None);
+    assert_eq!(parse_size("1T"), None);
+}
+
+#[test]
+fn test_budgeted_buffer() {
+    let budget = MemoryBudget::new(100);
+    let mut buffer = BudgetedBuffer::new(budget.clone());
+    buffer.write_all(&[1; 64]).unwrap();
+    assert_eq!(budget.used(), 64);
+
+    // doubling to 128 bytes doesn't fit, growing just as far as needed does
+    buffer.write_all(&[2; 36]).unwrap();
+    assert_eq!(budget.used(), 100);
+    let err = buffer.write_all(&[3]).unwrap_err();
+    match RuntimeError::from(err) {
+        RuntimeError::MemoryLimitExceeded { limit: 100, requested: 101 } => {}
+        other => panic!("unexpected {:?}", other),
+    }
+
+    let mut expected = vec![1; 64];
+    expected.extend_from_slice(&[2; 36]);
+    assert_eq!(buffer.into_inner(), expected);
+    assert_eq!(budget.used(), 0);
+}
@@ -0,0 +1,834 @@
+use std::collections::HashMap;
+
+use ast::{Ast, Node, SimpleOp};
+use brainfuck::{relink, verify, ArithMode, CompileError, Inst, Span};
+use brainfuck::Inst::*;
+
+
+// Rewrites a program into an equivalent, shorter instruction stream
+//
+// The passes only ever produce the plain instruction set, so the result can
+// be lowered back to brainfuck with `to_source`. They run until none of them
+// changes anything anymore. Cells wrap, see `optimize_for`.
+//
+// Unrolled loops take fewer instructions but usually more characters, a
+// program only meant to get shorter is better off without "unroll loops".
+pub fn optimize(insts: Vec<Inst>) -> Vec<Inst> {
+    optimize_for(insts, ArithMode::Wrap)
+}
+
+// `optimize` for a program running with `arith`. Only wrapping cells make
+// `+` and `-` cancel out, every odd step clear a cell and the values of
+// cells known, otherwise runs only merge in the same direction and loops
+// are left as they are.
+pub fn optimize_for(insts: Vec<Inst>, arith: ArithMode) -> Vec<Inst> {
+    let spans = vec![Span { start: 0, end: 0 }; insts.len()];
+    optimize_with_spans(insts, spans, arith).0
+}
+
+// `optimize_for` keeping track of where the instructions came from: merged
+// instructions span everything they were merged from, instructions that
+// were optimized away take their spans with them
+pub fn optimize_with_spans(insts: Vec<Inst>, spans: Vec<Span>, arith: ArithMode) -> (Vec<Inst>, Vec<Span>) {
+    Optimizer::new(arith).run_with_spans(insts, spans).expect("the built-in passes keep programs valid")
+}
+
+// A rewrite of the instruction stream, one step of an `Optimizer`
+pub trait Pass {
+    // Names the pass in errors and for `Optimizer::with_pass_before`
+    fn name(&self) -> &str;
+
+    // Gets `insts` with their jumps linked and has to link the jumps of
+    // what it returns as well, e.g. with `relink`
+    fn run(&self, insts: Vec<Inst>) -> Vec<Inst>;
+
+    // `run` keeping track of where the instructions came from. Unless a
+    // pass knows better, the spans stay as they are if the number of
+    // instructions does, otherwise every instruction spans all of them.
+    fn run_with_spans(&self, insts: Vec<(Inst, Span)>) -> Vec<(Inst, Span)> {
+        let (insts, spans): (Vec<Inst>, Vec<Span>) = insts.into_iter().unzip();
+        let len = insts.len();
+        let insts = self.run(insts);
+        if insts.len() == len {
+            return insts.into_iter().zip(spans).collect();
+        }
+
+        let all = Span {
+            start: spans.iter().map(|span| span.start).min().unwrap_or(0),
+            end: spans.iter().map(|span| span.end).max().unwrap_or(0),
+        };
+        insts.into_iter().map(|inst| (inst, all)).collect()
+    }
+}
+
+// The passes rewriting a program, run one after another until none of
+// them changes the number of instructions anymore
+pub struct Optimizer {
+    // every pass, and whether it's one of the built-in ones, which are
+    // trusted to keep programs valid
+    passes: Vec<(Box<dyn Pass>, bool)>,
+}
+
+impl Optimizer {
+    // The built-in passes of `optimize_for`
+    pub fn new(arith: ArithMode) -> Optimizer {
+        let wrap = arith == ArithMode::Wrap;
+        let mut passes: Vec<(Box<dyn Pass>, bool)> = vec![
+            (Box::new(FoldRuns { wrap }), true),
+            (Box::new(RemoveDeadLoops), true),
+        ];
+        if wrap {
+            passes.push((Box::new(NormalizeClearLoops), true));
+            passes.push((Box::new(UnrollLoops), true));
+            passes.push((Box::new(FoldStraightLine), true));
+        }
+        Optimizer { passes }
+    }
+
+    // Names of the passes in the order they run
+    pub fn pass_names(&self) -> Vec<&str> {
+        self.passes.iter().map(|(pass, _)| pass.name()).collect()
+    }
+
+    // Runs `pass` after all others
+    pub fn with_pass(mut self, pass: Box<dyn Pass>) -> Optimizer {
+        self.passes.push((pass, false));
+        self
+    }
+
+    // Runs `pass` right before the one named `name`, which has to exist
+    pub fn with_pass_before(mut self, name: &str, pass: Box<dyn Pass>) -> Optimizer {
+        let at = self.passes.iter().position(|(other, _)| other.name() == name);
+        let at = at.unwrap_or_else(|| panic!("no pass named {:?}", name));
+        self.passes.insert(at, (pass, false));
+        self
+    }
+
+    // Leaves out the pass named `name`, which has to exist
+    pub fn without_pass(mut self, name: &str) -> Optimizer {
+        let at = self.passes.iter().position(|(pass, _)| pass.name() == name);
+        let at = at.unwrap_or_else(|| panic!("no pass named {:?}", name));
+        self.passes.remove(at);
+        self
+    }
+
+    // Fails with `CompileError::PassProducedInvalidIr` if a pass that
+    // isn't built in leaves jumps that `verify` rejects
+    pub fn run(&self, insts: Vec<Inst>) -> Result<Vec<Inst>, CompileError> {
+        let spans = vec![Span { start: 0, end: 0 }; insts.len()];
+        self.run_with_spans(insts, spans).map(|(insts, _)| insts)
+    }
+
+    pub fn run_with_spans(&self, insts: Vec<Inst>, spans: Vec<Span>) -> Result<(Vec<Inst>, Vec<Span>), CompileError> {
+        let _stage = stage!("optimize", instructions = insts.len());
+        let mut insts: Vec<(Inst, Span)> = insts.into_iter().zip(spans).collect();
+        loop {
+            let before = insts.len();
+
+            for &(ref pass, built_in) in &self.passes {
+                let _pass = stage!("pass", name = pass.name());
+                relink_spanned(&mut insts);
+                insts = pass.run_with_spans(insts);
+                stage_done!(instructions = insts.len());
+                if !built_in {
+                    let linked: Vec<Inst> = insts.iter().map(|(inst, _)| inst.clone()).collect();
+                    if verify(&linked).is_err() {
+                        return Err(CompileError::PassProducedInvalidIr { pass_name: pass.name().to_string() });
+                    }
+                }
+            }
+
+            if insts.len() == before {
+                break;
+            }
+        }
+
+        let (mut insts, spans): (Vec<Inst>, Vec<Span>) = insts.into_iter().unzip();
+        relink(&mut insts);
+        stage_done!(instructions = insts.len());
+        Ok((insts, spans))
+    }
+}
+
+fn relink_spanned(insts: &mut [(Inst, Span)]) {
+    let mut linked: Vec<Inst> = insts.iter().map(|(inst, _)| inst.clone()).collect();
+    relink(&mut linked);
+    for ((inst, _), linked) in insts.iter_mut().zip(linked) {
+        *inst = linked;
+    }
+}
+
+// The built-in passes, as described at their functions. None of them
+// needs the jumps linked, they only go by the brackets.
+struct FoldRuns {
+    wrap: bool,
+}
+
+struct RemoveDeadLoops;
+
+struct NormalizeClearLoops;
+
+struct UnrollLoops;
+
+struct FoldStraightLine;
+
+fn without_spans<P: Pass>(pass: &P, insts: Vec<Inst>) -> Vec<Inst> {
+    let spans = vec![Span { start: 0, end: 0 }; insts.len()];
+    let (mut insts, _): (Vec<Inst>, Vec<Span>) = pass.run_with_spans(insts.into_iter().zip(spans).collect()).into_iter().unzip();
+    relink(&mut insts);
+    insts
+}
+
+impl Pass for FoldRuns {
+    fn name(&self) -> &str {
+        "fold runs"
+    }
+
+    fn run(&self, insts: Vec<Inst>) -> Vec<Inst> {
+        without_spans(self, insts)
+    }
+
+    fn run_with_spans(&self, insts: Vec<(Inst, Span)>) -> Vec<(Inst, Span)> {
+        fold_runs(insts, self.wrap)
+    }
+}
+
+impl Pass for RemoveDeadLoops {
+    fn name(&self) -> &str {
+        "remove dead loops"
+    }
+
+    fn run(&self, insts: Vec<Inst>) -> Vec<Inst> {
+        without_spans(self, insts)
+    }
+
+    fn run_with_spans(&self, insts: Vec<(Inst, Span)>) -> Vec<(Inst, Span)> {
+        remove_dead_loops(insts)
+    }
+}
+
+impl Pass for NormalizeClearLoops {
+    fn name(&self) -> &str {
+        "normalize clear loops"
+    }
+
+    fn run(&self, insts: Vec<Inst>) -> Vec<Inst> {
+        without_spans(self, insts)
+    }
+
+    fn run_with_spans(&self, insts: Vec<(Inst, Span)>) -> Vec<(Inst, Span)> {
+        normalize_clear_loops(insts)
+    }
+}
+
+impl Pass for UnrollLoops {
+    fn name(&self) -> &str {
+        "unroll loops"
+    }
+
+    fn run(&self, insts: Vec<Inst>) -> Vec<Inst> {
+        without_spans(self, insts)
+    }
+
+    fn run_with_spans(&self, insts: Vec<(Inst, Span)>) -> Vec<(Inst, Span)> {
+        unroll_loops(insts)
+    }
+}
+
+impl Pass for FoldStraightLine {
+    fn name(&self) -> &str {
+        "fold straight-line code"
+    }
+
+    fn run(&self, insts: Vec<Inst>) -> Vec<Inst> {
+        without_spans(self, insts)
+    }
+
+    fn run_with_spans(&self, insts: Vec<(Inst, Span)>) -> Vec<(Inst, Span)> {
+        fold_straight_line(insts)
+    }
+}
+
+// Net effect of a value change, modulo 256 since cells wrap
+fn value_delta(inst: &Inst) -> Option<isize> {
+    match *inst {
+        IncVal(a) => Some((a % 256) as isize),
+        DecVal(a) => Some(-((a % 256) as isize)),
+        _ => None,
+    }
+}
+
+fn ptr_delta(inst: &Inst) -> Option<isize> {
+    match *inst {
+        IncPtr(a) => Some(a as isize),
+        DecPtr(a) => Some(-(a as isize)),
+        _ => None,
+    }
+}
+
+// Merges adjacent value changes and pointer moves into a single net
+// instruction each, dropping the ones that cancel out (`+-`, `<>`). Value
+// changes pick whichever direction is shorter, `+` * 255 becomes `-`.
+// Unless cells `wrap` only value changes in the same direction merge, 255
+// `+` and a `-` leave a cell at 254 if it saturates at 255.
+fn fold_runs(insts: Vec<(Inst, Span)>, wrap: bool) -> Vec<(Inst, Span)> {
+    let mut out = Vec::with_capacity(insts.len());
+    let mut iter = insts.into_iter().peekable();
+
+    while let Some((inst, mut span)) = iter.next() {
+        if let (false, IncVal(mut net)) = (wrap, &inst) {
+            while let Some(&(IncVal(a), next)) = iter.peek() {
+                net += a;
+                span.end = next.end;
+                iter.next();
+            }
+            out.push((IncVal(net), span));
+        } else if let (false, DecVal(mut net)) = (wrap, &inst) {
+            while let Some(&(DecVal(a), next)) = iter.peek() {
+                net += a;
+                span.end = next.end;
+                iter.next();
+            }
+            out.push((DecVal(net), span));
+        } else if let Some(mut net) = value_delta(&inst) {
+            while let Some((delta, next)) = iter.peek().and_then(|&(ref inst, next)| Some((value_delta(inst)?, next))) {
+                net += delta;
+                span.end = next.end;
+                iter.next();
+            }
+            match net.rem_euclid(256) {
+                0 => {}
+                n if n <= 128 => out.push((IncVal(n as usize), span)),
+                n => out.push((DecVal(256 - n as usize), span)),
+            }
+        } else if let Some(mut net) = ptr_delta(&inst) {
+            while let Some((delta, next)) = iter.peek().and_then(|&(ref inst, next)| Some((ptr_delta(inst)?, next))) {
+                net += delta;
+                span.end = next.end;
+                iter.next();
+            }
+            match net {
+                0 => {}
+                n if n > 0 => out.push((IncPtr(n as usize), span)),
+                n => out.push((DecPtr(-n as usize), span)),
+            }
+        } else {
+            out.push((inst, span));
+        }
+    }
+
+    out
+}
+
+// Indices of the loops that can never be entered: the ones at the start of
+// the program, where every cell is still zero, and the ones directly following
+// another loop, which only exits once the current cell is zero. Loops nested
+// in a dead loop aren't listed separately.
+pub fn dead_loops(insts: &[Inst]) -> Vec<usize> {
+    let mut dead = Vec::new();
+    // every cell is still zero
+    let mut untouched = true;
+    let mut cell_is_zero = true;
+    let mut i = 0;
+
+    while i < insts.len() {
+        match insts[i] {
+            JmpFwd(n) if cell_is_zero => {
+                dead.push(i);
+                i = n + 1;
+                continue;
+            }
+            JmpBack(_) => cell_is_zero = true,
+            IncPtr(_) | DecPtr(_) => cell_is_zero = untouched,
+            PrintCell => {}
+            _ => {
+                untouched = false;
+                cell_is_zero = false;
+            }
+        }
+        i += 1;
+    }
+
+    dead
+}
+
+fn remove_dead_loops(mut insts: Vec<(Inst, Span)>) -> Vec<(Inst, Span)> {
+    let mut linked: Vec<Inst> = insts.iter().map(|(inst, _)| inst.clone()).collect();
+    relink(&mut linked);
+
+    for i in dead_loops(&linked).into_iter().rev() {
+        if let JmpFwd(n) = linked[i] {
+            insts.drain(i..n + 1);
+        }
+    }
+
+    insts
+}
+
+// Loops whose body only adds or subtracts an odd amount clear the cell (an
+// odd step reaches zero from any value modulo 256), write all of them as `[-]`
+fn normalize_clear_loops(insts: Vec<(Inst, Span)>) -> Vec<(Inst, Span)> {
+    let (insts, spans): (Vec<Inst>, Vec<Span>) = insts.into_iter().unzip();
+    let mut ast = Ast::from_insts(&insts);
+    ast.for_each_loop_mut(|body| {
+        if let [Node::Run(ref mut ops)] = body[..] {
+            if ops.len() == 1 && matches!(value_delta(&ops[0].to_inst()), Some(delta) if delta % 2 != 0) {
+                ops[0] = SimpleOp::DecVal(1);
+            }
+        }
+    });
+
+    // every instruction is still where it was, and so are the spans
+    ast.lower().into_iter().zip(spans).collect()
+}
+
+// The most times `unroll_loops` writes out a loop's body, and the most
+// instructions all of them may take together
+const MAX_TRIP_COUNT: usize = 16;
+const UNROLL_BUDGET: usize = 64;
+
+// What's known about the cells at some point of a program, relative to
+// where the pointer was at its start
+struct Known {
+    ptr: isize,
+    // cells that were changed, with their value if it's known
+    cells: HashMap<isize, Option<u8>>,
+    // whether the cells that weren't are still zero
+    rest_zero: bool,
+}
+
+impl Known {
+    fn new() -> Known {
+        Known { ptr: 0, cells: HashMap::new(), rest_zero: true }
+    }
+
+    fn cell(&self) -> Option<u8> {
+        match self.cells.get(&self.ptr) {
+            Some(&value) => value,
+            None if self.rest_zero => Some(0),
+            None => None,
+        }
+    }
+
+    fn set_cell(&mut self, value: Option<u8>) {
+        self.cells.insert(self.ptr, value);
+    }
+
+    // After what could have changed any cell, only `cell` is known
+    fn forget(&mut self, cell: Option<u8>) {
+        self.cells.clear();
+        self.rest_zero = false;
+        self.set_cell(cell);
+    }
+
+    // Follows anything but a loop
+    fn step(&mut self, inst: &Inst) {
+        if let Some(delta) = ptr_delta(inst) {
+            self.ptr += delta;
+        } else if let Some(delta) = value_delta(inst) {
+            let value = self.cell().map(|value| (value as isize + delta).rem_euclid(256) as u8);
+            self.set_cell(value);
+        } else if *inst == ReadChar {
+            self.set_cell(None);
+        } else if *inst != PrintCell {
+            self.forget(None);
+        }
+    }
+}
+
+// Whether a loop's body takes exactly one off its cell every time round,
+// only adding to and moving between cells on its way back there
+fn counts_down(body: &[Inst]) -> bool {
+    let mut ptr = 0;
+    let mut delta = 0;
+    for inst in body {
+        if let Some(moved) = ptr_delta(inst) {
+            ptr += moved;
+        } else if let Some(added) = value_delta(inst) {
+            if ptr == 0 {
+                delta += added;
+            }
+        } else {
+            return false;
+        }
+    }
+
+    ptr == 0 && delta.rem_euclid(256) == 255
+}
+
+// A loop that counts down a cell whose value is known when it starts goes
+// round exactly that many times, write its body out that many times
+// instead, so that `fold_straight_line` can fold it with the code around
+// it. Values are known from the start of the program, where every cell is
+// zero, up to input and loops that stay loops. Loops in other loops are
+// never known to go round some number of times, and only loops going round
+// at most `MAX_TRIP_COUNT` times within `UNROLL_BUDGET` are unrolled.
+fn unroll_loops(mut insts: Vec<(Inst, Span)>) -> Vec<(Inst, Span)> {
+    let mut linked: Vec<Inst> = insts.iter().map(|(inst, _)| inst.clone()).collect();
+    relink(&mut linked);
+
+    let mut known = Known::new();
+    // the loops to unroll, where they end and how often they go round
+    let mut unrolled = Vec::new();
+    let mut i = 0;
+    while i < linked.len() {
+        let end = match linked[i] {
+            JmpFwd(end) => end,
+            ref inst => {
+                known.step(inst);
+                i += 1;
+                continue;
+            }
+        };
+
+        let body = &linked[i + 1..end];
+        match known.cell() {
+            // never entered, nothing changes
+            Some(0) => {}
+            Some(count) if counts_down(body)
+                && (count as usize) <= MAX_TRIP_COUNT && (count as usize) * body.len() <= UNROLL_BUDGET => {
+                for _ in 0..count {
+                    for inst in body {
+                        known.step(inst);
+                    }
+                }
+                unrolled.push((i, end, count as usize));
+            }
+            // where the pointer ends up, the cell is zero
+            _ => known.forget(Some(0)),
+        }
+        i = end + 1;
+    }
+
+    for (start, end, count) in unrolled.into_iter().rev() {
+        let body = insts[start + 1..end].to_vec();
+        let len = body.len();
+        insts.splice(start..end + 1, body.into_iter().cycle().take(count * len));
+    }
+
+    insts
+}
+
+// Additions and moves in a row change every cell they get to by some
+// amount, no matter the order. Write each such run as one addition per cell
+// in the order the run first got to it, followed by the move to where the
+// run ended, where that takes fewer instructions. The new instructions span
+// the whole run.
+fn fold_straight_line(insts: Vec<(Inst, Span)>) -> Vec<(Inst, Span)> {
+    let mut out = Vec::with_capacity(insts.len());
+    let mut run: Vec<(Inst, Span)> = Vec::new();
+
+    for (inst, span) in insts {
+        if value_delta(&inst).is_some() || ptr_delta(&inst).is_some() {
+            run.push((inst, span));
+        } else {
+            fold_run(&mut run, &mut out);
+            out.push((inst, span));
+        }
+    }
+    fold_run(&mut run, &mut out);
+
+    out
+}
+
+// Moves a run of `fold_straight_line` to `out`, folded if that's shorter
+fn fold_run(run: &mut Vec<(Inst, Span)>, out: &mut Vec<(Inst, Span)>) {
+    if run.is_empty() {
+        return;
+    }
+
+    let mut ptr = 0;
+    let mut order = Vec::new();
+    let mut deltas = HashMap::new();
+    for (inst, _) in run.iter() {
+        if let Some(moved) = ptr_delta(inst) {
+            ptr += moved;
+        } else if let Some(added) = value_delta(inst) {
+            if !deltas.contains_key(&ptr) {
+                order.push(ptr);
+            }
+            *deltas.entry(ptr).or_insert(0) += added;
+        }
+    }
+
+    let mut folded = Vec::new();
+    let mut at = 0;
+    let mut move_to = |folded: &mut Vec<Inst>, to: isize| {
+        match to - at {
+            0 => {}
+            n if n > 0 => folded.push(IncPtr(n as usize)),
+            n => folded.push(DecPtr(-n as usize)),
+        }
+        at = to;
+    };
+    for cell in order {
+        match deltas[&cell].rem_euclid(256) {
+            0 => {}
+            n => {
+                move_to(&mut folded, cell);
+                folded.push(if n <= 128 { IncVal(n as usize) } else { DecVal(256 - n as usize) });
+            }
+        }
+    }
+    move_to(&mut folded, ptr);
+
+    if folded.len() < run.len() {
+        let all = Span { start: run[0].1.start, end: run[run.len() - 1].1.end };
+        out.extend(folded.into_iter().map(|inst| (inst, all)));
+        run.clear();
+    } else {
+        out.append(run);
+    }
+}
+
+
+#[cfg(test)]
+use brainfuck::{parse, to_source};
+
+#[cfg(test)]
+fn minify(source: &str) -> String {
+    to_source(&optimize(parse(source).unwrap()))
+}
+
+#[test]
+fn test_fold_runs() {
+    assert_eq!(minify("+-+-<>><"), "");
+    assert_eq!(minify("+++--->>+<"), ">>+<");
+    assert_eq!(minify(&"+".repeat(255)), "-");
+    assert_eq!(minify(&"-".repeat(300)), "-".repeat(44));
+    assert_eq!(minify(&"+".repeat(129)), "-".repeat(127));
+}
+
+#[test]
+fn test_remove_dead_loops() {
+    assert_eq!(minify("[comment, with. commands]+"), "+");
+    assert_eq!(minify(">>[-]<+"), ">+");
+    assert_eq!(minify("+[>]<[+][[-]]-"), "+[>]<[-]-");
+    assert_eq!(minify(".>[-]<,"), ".,");
+    assert_eq!(minify(",[-][-]-[+]++"), ",[-]-[-]++");
+    // removing a loop can make the code around it fold together
+    assert_eq!(minify(">[-]<+"), "+");
+    assert_eq!(minify(",[>+<-]>[-]"), ",[>+<-]>[-]");
+
+    assert_eq!(dead_loops(&parse("[[]]+[-][>][<]>[]").unwrap()), [0, 8, 11]);
+}
+
+#[test]
+fn test_normalize_clear_loops() {
+    assert_eq!(minify(",[+]"), ",[-]");
+    assert_eq!(minify(",[---]"), ",[-]");
+    // an even step doesn't clear odd values
+    assert_eq!(minify(",[--]"), ",[--]");
+}
+
+#[test]
+fn test_unroll_loops() {
+    fn unrolled(source: &str) -> String {
+        to_source(&UnrollLoops.run(parse(source).unwrap()))
+    }
+
+    assert_eq!(unrolled("++[>+<-]"), "++>+<->+<-");
+    assert_eq!(unrolled("+[-]"), "+-");
+    // the loops before tell what the cells hold
+    assert_eq!(unrolled("+[>++<-]>[-<+>]"), "+>++<->-<+>-<+>");
+    assert_eq!(unrolled(",[-]+[>+<-]"), ",[-]+>+<-");
+    assert_eq!(unrolled("+>+<[>+<-]"), "+>+<>+<-");
+    // the count isn't known
+    assert_eq!(unrolled(",[>+<-]"), ",[>+<-]");
+    assert_eq!(unrolled("+[>]<+[>+<-]"), "+[>]<+[>+<-]");
+    assert_eq!(unrolled("+[>+[>+<-]<-]"), "+[>+[>+<-]<-]");
+    // it isn't counted down one by one, or not only
+    assert_eq!(unrolled("++[>+<--]"), "++[>+<--]");
+    assert_eq!(unrolled("+[>+<]"), "+[>+<]");
+    assert_eq!(unrolled("+[>+<->]"), "+[>+<->]");
+    assert_eq!(unrolled("+[>.<-]"), "+[>.<-]");
+    assert_eq!(unrolled("+[,-]"), "+[,-]");
+    // too often, or too much code
+    assert_eq!(unrolled(&format!("{}[>+<-]", "+".repeat(16))), format!("{}{}", "+".repeat(16), ">+<-".repeat(16)));
+    assert_eq!(unrolled(&format!("{}[>+<-]", "+".repeat(17))), format!("{}[>+<-]", "+".repeat(17)));
+    assert_eq!(unrolled("++++++++++++[>+>+<<-]"), "++++++++++++[>+>+<<-]");
+}
+
+#[test]
+fn test_fold_straight_line() {
+    fn folded(source: &str) -> String {
+        to_source(&FoldStraightLine.run(parse(source).unwrap()))
+    }
+
+    assert_eq!(folded(">+<->+<-"), ">++<--");
+    assert_eq!(folded("+>+<->-<."), ".");
+    assert_eq!(folded(">+<+>+<,>+<+"), ">++<+,>+<+");
+    assert_eq!(folded("[>+<->+<-]"), "[>++<--]");
+    // nothing shorter
+    assert_eq!(folded("+>+<"), "+>+<");
+    assert_eq!(folded(">+<+"), ">+<+");
+
+    // constant setups fold into the values they set
+    assert_eq!(minify("++++++++[>++++<-]>."), format!(">{}.", "+".repeat(32)));
+    assert_eq!(minify("++++[>++++<-]>[<++++>-]<."), format!("{}.", "+".repeat(64)));
+    assert_eq!(minify("++++[>+++>++<<-]>>.<."), format!(">{}>{}.<.", "+".repeat(12), "+".repeat(8)));
+}
+
+// Random programs do the same after optimizing, see `generator`
+#[test]
+fn test_optimize_generated() {
+    use generator::ProgramGenerator;
+    use interp::Interp;
+
+    fn run(insts: &[Inst], input: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let mut interp = Interp::new(insts, 16);
+        let mut output = Vec::new();
+        interp.run(input, &mut output).unwrap();
+        (output, interp.tape().to_vec())
+    }
+
+    let mut generator = ProgramGenerator::new(190).tape_size(16);
+    for _ in 0..200 {
+        let source = generator.generate_terminating();
+        let input = generator.input(4);
+        let insts = parse(&source).unwrap();
+        let optimized = optimize(insts.clone());
+        assert_eq!(run(&insts, &input), run(&optimized, &input), "{} optimized to {}", source, to_source(&optimized));
+    }
+}
+
+#[test]
+fn test_optimize_for() {
+    fn optimized(source: &str, arith: ArithMode) -> String {
+        to_source(&optimize_for(parse(source).unwrap(), arith))
+    }
+
+    // + and - only cancel out when cells wrap, pointer moves always do
+    assert_eq!(optimized("+-+-<>><", ArithMode::Wrap), "");
+    assert_eq!(optimized("+-+-<>><", ArithMode::Saturate), "+-+-");
+    assert_eq!(optimized("++>+<<>>+", ArithMode::Trap), "++>++");
+    assert_eq!(optimized(&"+".repeat(300), ArithMode::Wrap), "+".repeat(44));
+    assert_eq!(optimized(&"+".repeat(300), ArithMode::Trap), "+".repeat(300));
+    // neither is every odd step a clear loop
+    assert_eq!(optimized(",[---]", ArithMode::Wrap), ",[-]");
+    assert_eq!(optimized(",[---]", ArithMode::Trap), ",[---]");
+    // nor are values known
+    assert_eq!(optimized("++[>+<-]", ArithMode::Wrap), ">++<");
+    assert_eq!(optimized("++[>+<-]", ArithMode::Saturate), "++[>+<-]");
+    // dead loops are dead either way
+    assert_eq!(optimized("[-]>[-]<+[>][+]", ArithMode::Saturate), "+[>]");
+}
+
+#[test]
+fn test_optimize_with_spans() {
+    use brainfuck::parse_with_spans;
+
+    let (insts, spans) = parse_with_spans("[.]+ +-\n<>>,[+++]").unwrap();
+    let (insts, spans) = optimize_with_spans(insts, spans, ArithMode::Wrap);
+    assert_eq!(insts, [IncVal(1), IncPtr(1), ReadChar, JmpFwd(5), DecVal(1), JmpBack(3)]);
+    let spans: Vec<_> = spans.iter().map(|s| (s.start, s.end)).collect();
+    assert_eq!(spans, [(3, 7), (8, 11), (11, 12), (12, 13), (13, 16), (16, 17)]);
+
+    // an unrolled loop folds into one run with the code before it
+    let (insts, spans) = parse_with_spans("++ [>+++<-] .").unwrap();
+    let (insts, spans) = optimize_with_spans(insts, spans, ArithMode::Wrap);
+    assert_eq!(insts, [IncPtr(1), IncVal(6), DecPtr(1), PrintCell]);
+    let spans: Vec<_> = spans.iter().map(|s| (s.start, s.end)).collect();
+    assert_eq!(spans, [(0, 10), (0, 10), (0, 10), (12, 13)]);
+}
+
+#[test]
+fn test_minify_corpus() {
+    use interp::run_with_input;
+
+    let corpus: &[(&str, &[u8])] = &[
+        (include_str!("../tests/fixtures/hello.b"), b""),
+        (include_str!("../tests/fixtures/rot13.b"), b"Hello, World!\nzZ aA 0123"),
+        (include_str!("../tests/fixtures/unoptimized.b"), b"x"),
+    ];
+
+    for &(source, input) in corpus {
+        let original = parse(source).unwrap();
+        let minified = parse(&minify(source)).unwrap();
+        assert!(minified.len() <= original.len());
+
+        assert_eq!(
+            run_with_input(&original, 30_000, input).unwrap(),
+            run_with_input(&minified, 30_000, input).unwrap(),
+            "minified program differs:\n{}", to_source(&minified)
+        );
+    }
+}
+
+// Drops the clears and moves at the very end of a program, for programs
+// whose tape nobody looks at afterwards
+#[cfg(test)]
+struct DropTrailingClears;
+
+#[cfg(test)]
+impl Pass for DropTrailingClears {
+    fn name(&self) -> &str {
+        "drop trailing clears"
+    }
+
+    fn run(&self, mut insts: Vec<Inst>) -> Vec<Inst> {
+        loop {
+            let len = insts.len();
+            if matches!(insts[len.saturating_sub(1)..], [IncPtr(_)] | [DecPtr(_)]) {
+                insts.pop();
+            } else if matches!(insts[len.saturating_sub(3)..], [JmpFwd(_), DecVal(1), JmpBack(_)]) {
+                insts.truncate(len - 3);
+            } else {
+                return insts;
+            }
+        }
+    }
+}
+
+// Forgets the last `]`
+#[cfg(test)]
+struct Broken;
+
+#[cfg(test)]
+impl Pass for Broken {
+    fn name(&self) -> &str {
+        "broken"
+    }
+
+    fn run(&self, mut insts: Vec<Inst>) -> Vec<Inst> {
+        if let Some(last) = insts.iter().rposition(|inst| matches!(*inst, JmpBack(_))) {
+            insts.remove(last);
+        }
+        insts
+    }
+}
+
+#[test]
+fn test_custom_passes() {
+    let optimizer = Optimizer::new(ArithMode::Wrap);
+    assert_eq!(optimizer.pass_names(), [
+        "fold runs", "remove dead loops", "normalize clear loops", "unroll loops", "fold straight-line code",
+    ]);
+    let source = ",[>++<-]>.[+++]>[-]<<";
+
+    // after the built-in passes, which turn `[+++]` into a `[-]` it drops
+    let optimizer = optimizer.with_pass(Box::new(DropTrailingClears));
+    assert_eq!(to_source(&optimizer.run(parse(source).unwrap()).unwrap()), ",[>++<-]>.");
+    // before the built-in passes it stops at `[+++]` at first, which is
+    // `[-]` by the next round
+    let optimizer = Optimizer::new(ArithMode::Wrap).with_pass_before("fold runs", Box::new(DropTrailingClears));
+    assert_eq!(optimizer.pass_names()[0], "drop trailing clears");
+    assert_eq!(to_source(&optimizer.run(parse(source).unwrap()).unwrap()), ",[>++<-]>.");
+    let optimizer = Optimizer::new(ArithMode::Trap).with_pass(Box::new(DropTrailingClears));
+    assert_eq!(to_source(&optimizer.run(parse(source).unwrap()).unwrap()), ",[>++<-]>.[+++]");
+    let optimizer = Optimizer::new(ArithMode::Wrap).without_pass("unroll loops");
+    assert_eq!(optimizer.pass_names().len(), 4);
+    assert_eq!(to_source(&optimizer.run(parse("++[>+<-]").unwrap()).unwrap()), "++[>+<-]");
+
+    // built-in passes through the trait, linking what they return
+    let folded = FoldRuns { wrap: true }.run(parse("+[-+-]").unwrap());
+    assert_eq!(folded, [IncVal(1), JmpFwd(3), DecVal(1), JmpBack(1)]);
+
+    let optimizer = Optimizer::new(ArithMode::Wrap).with_pass(Box::new(Broken));
+    match optimizer.run(parse(source).unwrap()) {
+        Err(CompileError::PassProducedInvalidIr { ref pass_name }) => assert_eq!(pass_name, "broken"),
+        other => panic!("unexpected {:?}", other),
+    }
+    // nothing to break
+    assert_eq!(optimizer.run(parse("+.").unwrap()).unwrap(), [IncVal(1), PrintCell]);
+}
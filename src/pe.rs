@@ -0,0 +1,315 @@
+// Executables for Windows, for `compile --emit pe`: a PE32+ image with the
+// machine code of a program generated with `Syscalls::Windows` in .text,
+// its imports from kernel32 in .idata and the frame and tape in .bss, which
+// the loader zeroes. Nothing but kernel32 is needed, so it runs on any
+// 64-bit Windows as it's installed.
+//
+// .text starts with the entry point, which gets the standard handles, sets
+// up the frame and calls the code, and the two routines the code of `.`
+// and `,` calls, which write and read a byte with `WriteFile` and
+// `ReadFile`. The image loads at a fixed address, without relocations.
+use brainfuck::{FRAME_TAPE_END, FRAME_TAPE_START, FRAME_WINDOWS_READ, FRAME_WINDOWS_WRITE};
+
+
+const IMAGE_BASE: u64 = 0x1_4000_0000;
+const SECTION_ALIGNMENT: u32 = 0x1000;
+const FILE_ALIGNMENT: u32 = 0x200;
+const PE_OFFSET: u32 = 0x40;
+const OPTIONAL_HEADER_SIZE: u16 = 240;
+const SECTIONS: u16 = 3;
+const HEADERS_SIZE: u32 = 0x200;
+const TEXT: u32 = 0x1000;
+
+const IMAGE_FILE_MACHINE_AMD64: u16 = 0x8664;
+// relocations stripped, executable, large address aware
+const IMAGE_FILE_CHARACTERISTICS: u16 = 0x0023;
+const IMAGE_SUBSYSTEM_WINDOWS_CUI: u16 = 3;
+// NX compatible, terminal server aware, no ASLR without relocations
+const IMAGE_DLL_CHARACTERISTICS: u16 = 0x8100;
+const IMAGE_DIRECTORY_ENTRY_IMPORT: usize = 1;
+const IMAGE_DIRECTORY_ENTRY_IAT: usize = 12;
+const IMAGE_SCN_TEXT: u32 = 0x6000_0020;
+const IMAGE_SCN_IDATA: u32 = 0xc000_0040;
+const IMAGE_SCN_BSS: u32 = 0xc000_0080;
+
+const STD_INPUT_HANDLE: u32 = -10i32 as u32;
+const STD_OUTPUT_HANDLE: u32 = -11i32 as u32;
+
+// What's imported, in the order of the import address table
+const IMPORTS: [&str; 4] = ["GetStdHandle", "WriteFile", "ReadFile", "ExitProcess"];
+const GET_STD_HANDLE: u32 = 0;
+const WRITE_FILE: u32 = 1;
+const READ_FILE: u32 = 2;
+const EXIT_PROCESS: u32 = 3;
+// In .idata, the descriptor of kernel32 and the null one ending the list,
+// then the lookup table and the address table of 8 byte entries with a null
+// one ending them, then the names
+const LOOKUP_TABLE: u32 = 40;
+const ADDRESS_TABLE: u32 = LOOKUP_TABLE + 8 * (IMPORTS.len() as u32 + 1);
+const NAMES: u32 = ADDRESS_TABLE + 8 * (IMPORTS.len() as u32 + 1);
+
+// In .bss, the frame, the cancel flag nothing sets, the standard handles
+// and then the tape
+const CANCEL: u32 = 72;
+const STDIN: u32 = 80;
+const STDOUT: u32 = 88;
+const TAPE: u32 = 128;
+
+fn align(n: u32, alignment: u32) -> u32 {
+    n.div_ceil(alignment) * alignment
+}
+
+fn put16(out: &mut Vec<u8>, values: &[u16]) {
+    for value in values {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+fn put32(out: &mut Vec<u8>, values: &[u32]) {
+    for value in values {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+fn put64(out: &mut Vec<u8>, values: &[u64]) {
+    for value in values {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+// Code assembled at `rva`, where every instruction addressing memory
+// relative to rip ends with the displacement
+struct Asm {
+    rva: u32,
+    bytes: Vec<u8>,
+}
+
+impl Asm {
+    fn op(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    // `bytes` followed by the displacement of `target` from the
+    // instruction's end
+    fn rip(&mut self, bytes: &[u8], target: u32) {
+        self.bytes.extend_from_slice(bytes);
+        let end = self.rva + self.bytes.len() as u32 + 4;
+        put32(&mut self.bytes, &[target.wrapping_sub(end)]);
+    }
+
+    fn here(&self) -> u32 {
+        self.rva + self.bytes.len() as u32
+    }
+}
+
+// The size of everything in .text before the code
+const STUB_SIZE: u32 = 120;
+const ROUTINE_SIZE: u32 = 45;
+
+// Called by the code of `.` or `,` with the cell at rsi: `function` of the
+// import address table on the handle at `handle` of .bss, with the number
+// of bytes done going to the stack
+fn routine(asm: &mut Asm, function: u32, handle: u32) {
+    asm.op(&[0x48, 0x83, 0xec, 0x38]); // sub rsp, 56
+    asm.rip(&[0x48, 0x8b, 0x0d], handle); // mov rcx, [rip + handle]
+    asm.op(&[0x48, 0x89, 0xf2]); // mov rdx, rsi
+    asm.op(&[0x41, 0xb8, 0x01, 0x00, 0x00, 0x00]); // mov r8d, 1
+    asm.op(&[0x4c, 0x8d, 0x4c, 0x24, 0x30]); // lea r9, [rsp + 48]
+    asm.op(&[0x48, 0xc7, 0x44, 0x24, 0x20, 0x00, 0x00, 0x00, 0x00]); // mov qword [rsp + 32], 0
+    asm.rip(&[0xff, 0x15], function); // call [rip + function]
+    asm.op(&[0x48, 0x83, 0xc4, 0x38]); // add rsp, 56
+    asm.op(&[0xc3]); // ret
+}
+
+// The entry point and the routines, for .idata at `idata` and .bss at `bss`
+fn stub(idata: u32, bss: u32, tape_size: u64) -> Vec<u8> {
+    let import = |function: u32| idata + ADDRESS_TABLE + 8 * function;
+    let write = TEXT + STUB_SIZE;
+    let read = write + ROUTINE_SIZE;
+    let mut asm = Asm { rva: TEXT, bytes: Vec::new() };
+
+    // aligns the stack for the calls and leaves their shadow space
+    asm.op(&[0x48, 0x83, 0xec, 0x28]); // sub rsp, 40
+    for &(handle, at) in &[(STD_INPUT_HANDLE, STDIN), (STD_OUTPUT_HANDLE, STDOUT)] {
+        asm.op(&[0xb9]); // mov ecx, handle
+        put32(&mut asm.bytes, &[handle]);
+        asm.rip(&[0xff, 0x15], import(GET_STD_HANDLE)); // call [rip + GetStdHandle]
+        asm.rip(&[0x48, 0x89, 0x05], bss + at); // mov [rip + at], rax
+    }
+    asm.rip(&[0x48, 0x8d, 0x3d], bss); // lea rdi, [rip + frame]
+    asm.rip(&[0x48, 0x8d, 0x05], bss + CANCEL); // lea rax, [rip + cancel]
+    asm.op(&[0x48, 0x89, 0x07]); // mov [rdi], rax
+    asm.rip(&[0x48, 0x8d, 0x05], write); // lea rax, [rip + write]
+    asm.op(&[0x48, 0x89, 0x47, FRAME_WINDOWS_WRITE]); // mov [rdi + FRAME_WINDOWS_WRITE], rax
+    asm.rip(&[0x48, 0x8d, 0x05], read); // lea rax, [rip + read]
+    asm.op(&[0x48, 0x89, 0x47, FRAME_WINDOWS_READ]); // mov [rdi + FRAME_WINDOWS_READ], rax
+    asm.rip(&[0x48, 0x8d, 0x35], bss + TAPE); // lea rsi, [rip + tape]
+    asm.op(&[0x48, 0x89, 0x77, FRAME_TAPE_START]); // mov [rdi + FRAME_TAPE_START], rsi
+    asm.op(&[0x48, 0xb8]); // mov rax, tape_size
+    put64(&mut asm.bytes, &[tape_size]);
+    asm.op(&[0x48, 0x01, 0xf0]); // add rax, rsi
+    asm.op(&[0x48, 0x89, 0x47, FRAME_TAPE_END]); // mov [rdi + FRAME_TAPE_END], rax
+    asm.rip(&[0xe8], TEXT + STUB_SIZE + 2 * ROUTINE_SIZE); // call code
+    asm.op(&[0x89, 0xc1]); // mov ecx, eax
+    asm.rip(&[0xff, 0x15], import(EXIT_PROCESS)); // call [rip + ExitProcess]
+    debug_assert_eq!(asm.here(), write);
+
+    routine(&mut asm, import(WRITE_FILE), bss + STDOUT);
+    debug_assert_eq!(asm.here(), read);
+    routine(&mut asm, import(READ_FILE), bss + STDIN);
+    debug_assert_eq!(asm.here(), TEXT + STUB_SIZE + 2 * ROUTINE_SIZE);
+
+    asm.bytes
+}
+
+// The import descriptors, tables and names of .idata at `rva`
+fn idata(rva: u32) -> Vec<u8> {
+    let mut names = Vec::new();
+    let mut entries = Vec::new();
+    for name in &IMPORTS {
+        // a hint of 0 and the name, padded to an even length
+        entries.push(u64::from(rva + NAMES + names.len() as u32));
+        put16(&mut names, &[0]);
+        names.extend_from_slice(name.as_bytes());
+        names.push(0);
+        if names.len() % 2 == 1 {
+            names.push(0);
+        }
+    }
+    entries.push(0);
+    let dll = rva + NAMES + names.len() as u32;
+    names.extend_from_slice(b"kernel32.dll\0");
+
+    let mut out = Vec::new();
+    put32(&mut out, &[rva + LOOKUP_TABLE, 0, 0, dll, rva + ADDRESS_TABLE]);
+    put32(&mut out, &[0; 5]);
+    // the loader overwrites the address table with the functions'
+    // addresses, the lookup table stays as it is
+    put64(&mut out, &entries);
+    put64(&mut out, &entries);
+    out.extend_from_slice(&names);
+
+    out
+}
+
+fn put_section(out: &mut Vec<u8>, name: &str, virtual_size: u32, rva: u32, raw: (u32, u32), flags: u32) {
+    let mut padded = [0; 8];
+    padded[..name.len()].copy_from_slice(name.as_bytes());
+    out.extend_from_slice(&padded);
+    put32(out, &[virtual_size, rva, raw.0, raw.1, 0, 0]);
+    put16(out, &[0, 0]);
+    put32(out, &[flags]);
+}
+
+// An executable running `code`, generated with `Syscalls::Windows`, on a
+// tape of `tape_size` cells. It exits with 0 once the program is done, or
+// with the `STATUS_` value the code returned, 1 and 2 for the pointer
+// moving off the tape.
+pub fn executable(code: &[u8], tape_size: usize) -> Vec<u8> {
+    let text_size = STUB_SIZE + 2 * ROUTINE_SIZE + code.len() as u32;
+    let idata_rva = align(TEXT + text_size, SECTION_ALIGNMENT);
+    let imports = idata(idata_rva);
+    let bss = align(idata_rva + imports.len() as u32, SECTION_ALIGNMENT);
+    let bss_size = TAPE + tape_size as u32;
+    let image_size = align(bss + bss_size, SECTION_ALIGNMENT);
+
+    let text_raw = (align(text_size, FILE_ALIGNMENT), HEADERS_SIZE);
+    let idata_raw = (align(imports.len() as u32, FILE_ALIGNMENT), text_raw.1 + text_raw.0);
+
+    let mut out = Vec::new();
+    // the DOS header, of which only the magic and where the PE header is
+    // matter
+    out.extend_from_slice(b"MZ");
+    out.resize(0x3c, 0);
+    put32(&mut out, &[PE_OFFSET]);
+    out.extend_from_slice(b"PE\0\0");
+    put16(&mut out, &[IMAGE_FILE_MACHINE_AMD64, SECTIONS]);
+    put32(&mut out, &[0, 0, 0]);
+    put16(&mut out, &[OPTIONAL_HEADER_SIZE, IMAGE_FILE_CHARACTERISTICS]);
+
+    // the optional header of PE32+
+    put16(&mut out, &[0x20b, 0]);
+    put32(&mut out, &[text_raw.0, idata_raw.0, align(bss_size, FILE_ALIGNMENT), TEXT, TEXT]);
+    put64(&mut out, &[IMAGE_BASE]);
+    put32(&mut out, &[SECTION_ALIGNMENT, FILE_ALIGNMENT]);
+    // the versions of the system, the image and the subsystem, 6.0 for the
+    // system being Vista or later
+    put16(&mut out, &[6, 0, 0, 0, 6, 0]);
+    put32(&mut out, &[0, image_size, HEADERS_SIZE, 0]);
+    put16(&mut out, &[IMAGE_SUBSYSTEM_WINDOWS_CUI, IMAGE_DLL_CHARACTERISTICS]);
+    // the stack's and the heap's reserve and commit
+    put64(&mut out, &[0x10_0000, 0x1000, 0x10_0000, 0x1000]);
+    put32(&mut out, &[0, 16]);
+    let mut directories = [(0, 0); 16];
+    directories[IMAGE_DIRECTORY_ENTRY_IMPORT] = (idata_rva, LOOKUP_TABLE);
+    directories[IMAGE_DIRECTORY_ENTRY_IAT] = (idata_rva + ADDRESS_TABLE, NAMES - ADDRESS_TABLE);
+    for &(rva, size) in &directories {
+        put32(&mut out, &[rva, size]);
+    }
+
+    put_section(&mut out, ".text", text_size, TEXT, text_raw, IMAGE_SCN_TEXT);
+    put_section(&mut out, ".idata", imports.len() as u32, idata_rva, idata_raw, IMAGE_SCN_IDATA);
+    put_section(&mut out, ".bss", bss_size, bss, (0, 0), IMAGE_SCN_BSS);
+    debug_assert!(out.len() as u32 <= HEADERS_SIZE);
+
+    out.resize(text_raw.1 as usize, 0);
+    out.extend_from_slice(&stub(idata_rva, bss, tape_size as u64));
+    out.extend_from_slice(code);
+    out.resize(idata_raw.1 as usize, 0);
+    out.extend_from_slice(&imports);
+    out.resize((idata_raw.1 + idata_raw.0) as usize, 0);
+
+    out
+}
+
+
+#[test]
+fn test_executable() {
+    use brainfuck::{compile_insts, parse, CodegenOptions, Syscalls};
+
+    let u16_at = |bytes: &[u8], at: usize| u16::from(bytes[at]) | u16::from(bytes[at + 1]) << 8;
+    let u32_at = |bytes: &[u8], at: usize| u32::from(u16_at(bytes, at)) | u32::from(u16_at(bytes, at + 2)) << 16;
+
+    let options = CodegenOptions { syscalls: Syscalls::Windows, ..CodegenOptions::default() };
+    let code = compile_insts(&parse("+.").unwrap(), &options).unwrap();
+    let file = executable(&code, 100);
+
+    assert_eq!(&file[..2], b"MZ");
+    let pe = u32_at(&file, 0x3c) as usize;
+    assert_eq!(&file[pe..pe + 4], b"PE\0\0");
+    assert_eq!((u16_at(&file, pe + 4), u16_at(&file, pe + 6)), (IMAGE_FILE_MACHINE_AMD64, SECTIONS));
+    let optional = pe + 24;
+    assert_eq!(u16_at(&file, pe + 20) as usize, 240);
+    assert_eq!(u16_at(&file, optional), 0x20b);
+    assert_eq!(u32_at(&file, optional + 16), TEXT);
+    assert_eq!(u32_at(&file, optional + 56), 0x4000);
+    assert_eq!(u32_at(&file, optional + 60), HEADERS_SIZE);
+    assert_eq!(file.len(), 0x600);
+
+    // the sections, where the file and the directories say
+    let sections = optional + 240;
+    assert_eq!(&file[sections..sections + 8], b".text\0\0\0");
+    assert_eq!(u32_at(&file, sections + 8), STUB_SIZE + 2 * ROUTINE_SIZE + code.len() as u32);
+    let idata = sections + 40;
+    assert_eq!(&file[idata..idata + 8], b".idata\0\0");
+    assert_eq!((u32_at(&file, idata + 12), u32_at(&file, idata + 20)), (0x2000, 0x400));
+    assert_eq!(u32_at(&file, optional + 112 + 8), 0x2000);
+    assert_eq!(u32_at(&file, optional + 112 + 12 * 8), 0x2000 + ADDRESS_TABLE);
+    let bss = sections + 80;
+    assert_eq!((u32_at(&file, bss + 8), u32_at(&file, bss + 12), u32_at(&file, bss + 16)), (TAPE + 100, 0x3000, 0));
+
+    // the code after the stub, which calls it
+    let start = (HEADERS_SIZE + STUB_SIZE + 2 * ROUTINE_SIZE) as usize;
+    assert_eq!(&file[start..start + code.len()], &code[..]);
+    let call = HEADERS_SIZE as usize + STUB_SIZE as usize - 13;
+    assert_eq!(file[call], 0xe8);
+    assert_eq!(call + 5 + u32_at(&file, call + 1) as usize, start);
+
+    // kernel32 and the first function it's asked for, by name
+    let imports = &file[0x400..];
+    let dll = u32_at(imports, 12) as usize - 0x2000;
+    assert_eq!(&imports[dll..dll + 13], b"kernel32.dll\0");
+    let first = u32_at(imports, ADDRESS_TABLE as usize) as usize - 0x2000;
+    assert_eq!(&imports[first + 2..first + 15], b"GetStdHandle\0");
+    assert_eq!(u32_at(imports, 16), 0x2000 + ADDRESS_TABLE);
+}
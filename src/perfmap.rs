@@ -0,0 +1,124 @@
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use brainfuck::{Inst, Span};
+use brainfuck::Inst::*;
+
+
+// A named range of generated code, relative to its start
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub offset: usize,
+    pub len: usize,
+    pub name: String,
+}
+
+// Where `perf` looks for the symbols of a process' JIT code
+pub fn default_path() -> PathBuf {
+    PathBuf::from(format!("/tmp/perf-{}.map", process::id()))
+}
+
+// Symbols covering the whole code without overlapping, so that `perf` can
+// tell them apart: the code of every loop is named after the loop, except
+// for the parts in nested loops, which have names of their own. Everything
+// outside of loops is `bf_program`. With spans, loop names include where the
+// loop starts in the source, like `bf_loop_17_src_1032`.
+//
+// `offsets` are where the code of every instruction starts, followed by
+// where the code after the last one starts.
+pub fn symbols(insts: &[Inst], offsets: &[u32], code_len: usize, spans: Option<&[Span]>) -> Vec<Symbol> {
+    let name = |loops: &[usize]| match (loops.last(), spans) {
+        (Some(&i), Some(spans)) => format!("bf_loop_{}_src_{}", i, spans[i].start),
+        (Some(&i), None) => format!("bf_loop_{}", i),
+        (None, _) => "bf_program".to_string(),
+    };
+
+    let mut symbols = Vec::new();
+    let mut push = |start: usize, end: usize, name: String| {
+        if end > start {
+            symbols.push(Symbol { offset: start, len: end - start, name });
+        }
+    };
+
+    let mut loops = Vec::new();
+    let mut start = 0;
+    for (i, inst) in insts.iter().enumerate() {
+        match *inst {
+            JmpFwd(_) => {
+                push(start, offsets[i] as usize, name(&loops));
+                loops.push(i);
+                start = offsets[i] as usize;
+            }
+            JmpBack(_) => {
+                push(start, offsets[i + 1] as usize, name(&loops));
+                loops.pop();
+                start = offsets[i + 1] as usize;
+            }
+            _ => {}
+        }
+    }
+    push(start, code_len, name(&loops));
+
+    symbols
+}
+
+// Appends the symbols of code mapped at `base` in the format of perf's
+// /tmp/perf-<pid>.map: start and size in hex, then the name
+pub fn write_symbols<W: Write>(base: usize, symbols: &[Symbol], mut out: W) -> io::Result<()> {
+    for symbol in symbols {
+        writeln!(out, "{:x} {:x} {}", base + symbol.offset, symbol.len, symbol.name)?;
+    }
+
+    Ok(())
+}
+
+// Like `write_symbols`, appending to the file at `path` in a single write,
+// so that runs on other threads don't interleave with it
+pub fn append(path: &Path, base: usize, symbols: &[Symbol]) -> io::Result<()> {
+    let mut entries = Vec::new();
+    write_symbols(base, symbols, &mut entries)?;
+    OpenOptions::new().create(true).append(true).open(path)?.write_all(&entries)
+}
+
+
+#[cfg(test)]
+use brainfuck::parse_with_spans;
+
+#[test]
+fn test_symbols() {
+    // instructions of 1 byte each after a 3 byte prologue, to keep it readable
+    let (insts, spans) = parse_with_spans("+ [>[-]<-]  [.]").unwrap();
+    let offsets: Vec<u32> = (0..insts.len() + 1).map(|i| 3 + i as u32).collect();
+    let named = symbols(&insts, &offsets, 20, Some(&spans));
+
+    let expected = [
+        (0, 4, "bf_program"),
+        (4, 2, "bf_loop_1_src_2"),
+        (6, 3, "bf_loop_3_src_4"),
+        (9, 3, "bf_loop_1_src_2"),
+        (12, 3, "bf_loop_9_src_12"),
+        (15, 5, "bf_program"),
+    ];
+    let actual: Vec<_> = named.iter().map(|s| (s.offset, s.len, &s.name[..])).collect();
+    assert_eq!(actual, expected);
+
+    // bytecode has no source
+    let names: Vec<_> = symbols(&insts, &offsets, 20, None).into_iter().map(|s| s.name).collect();
+    assert_eq!(names[1..3], ["bf_loop_1", "bf_loop_3"]);
+}
+
+#[test]
+fn test_write_symbols() {
+    let symbols = [
+        Symbol { offset: 0, len: 3, name: "bf_program".to_string() },
+        Symbol { offset: 3, len: 0x1a, name: "bf_loop_0".to_string() },
+    ];
+    let mut out = Vec::new();
+    write_symbols(0x7f00_0000_1000, &symbols, &mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "7f0000001000 3 bf_program\n7f0000001003 1a bf_loop_0\n"
+    );
+}
@@ -0,0 +1,122 @@
+// Programs run as a pipeline for `pipe`, like brainfuck filters chained in
+// a shell: what one prints is what the next one reads. Every program runs
+// as generated code on a thread of its own, see `SharedProgram::run_fds`,
+// connected to the next by a pipe. A pipe only holds so much, a program
+// printing faster than the next one reads waits for it once it's full.
+use std::io;
+use std::os::unix::io::{AsRawFd, OwnedFd, RawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+use brainfuck::{RuntimeError, SharedProgram};
+
+
+// Why the program at `stage` in the pipeline, counted from 0, failed
+#[derive(Debug)]
+pub struct StageError {
+    pub stage: usize,
+    pub error: RuntimeError,
+}
+
+// Runs `programs` as a pipeline, the first one reading from `input` and the
+// last one writing to `output`. Once one fails the others are cancelled the
+// next time a loop of theirs goes round, one waiting for input that never
+// comes can't be. Fails with the failure of the earliest stage that didn't
+// just get cancelled or, like in a shell, fail to print to a stage that
+// was done reading.
+pub fn run(programs: &[SharedProgram], input: RawFd, output: RawFd) -> Result<(), StageError> {
+    let failed = &AtomicBool::new(false);
+
+    // the ends of the pipes every stage reads from and writes to, `None`
+    // for `input` and `output`. The stages own them, so a stage that's done
+    // closes them, which the next one reads as the end of its input.
+    let mut ends: Vec<(Option<OwnedFd>, Option<OwnedFd>)> = programs.iter().map(|_| (None, None)).collect();
+    for stage in 1..programs.len() {
+        let (reader, writer) = io::pipe().map_err(|e| StageError { stage, error: e.into() })?;
+        ends[stage - 1].1 = Some(writer.into());
+        ends[stage].0 = Some(reader.into());
+    }
+
+    let results: Vec<Result<Vec<u8>, RuntimeError>> = thread::scope(|scope| {
+        let stages: Vec<_> = programs.iter().zip(ends)
+            .map(|(program, (reader, writer))| {
+                scope.spawn(move || {
+                    let input = reader.as_ref().map_or(input, AsRawFd::as_raw_fd);
+                    let output = writer.as_ref().map_or(output, AsRawFd::as_raw_fd);
+                    let result = program.run_fds(input, output, failed);
+                    if result.is_err() {
+                        failed.store(true, Ordering::SeqCst);
+                    }
+                    result
+                })
+            })
+            .collect();
+        stages.into_iter().map(|stage| stage.join().expect("pipeline stage panicked")).collect()
+    });
+
+    let mut errors: Vec<StageError> = results.into_iter().enumerate()
+        .filter_map(|(stage, result)| result.err().map(|error| StageError { stage, error }))
+        .collect();
+    let follows = |e: &StageError| match e.error {
+        RuntimeError::Cancelled => true,
+        RuntimeError::Io(ref io) => e.stage + 1 < programs.len() && io.kind() == io::ErrorKind::BrokenPipe,
+        _ => false,
+    };
+    match errors.iter().position(|e| !follows(e)) {
+        Some(first) => Err(errors.swap_remove(first)),
+        None => Ok(()),
+    }
+}
+
+
+#[cfg(test)]
+use brainfuck::Brainfuck;
+
+// Runs `sources` as a pipeline with `input`, returns what the last one printed
+#[cfg(test)]
+fn run_piped(sources: &[&str], input: &[u8]) -> Result<Vec<u8>, StageError> {
+    use std::io::{Read, Write};
+
+    let programs: Vec<SharedProgram> = sources.iter()
+        .map(|source| Brainfuck::new(source).unwrap().share().unwrap())
+        .collect();
+    let (in_reader, mut in_writer) = io::pipe().unwrap();
+    let (mut out_reader, out_writer) = io::pipe().unwrap();
+    thread::scope(|scope| {
+        scope.spawn(move || in_writer.write_all(input));
+        let reader = scope.spawn(move || {
+            let mut output = Vec::new();
+            out_reader.read_to_end(&mut output).map(|_| output)
+        });
+        let result = run(&programs, in_reader.as_raw_fd(), out_writer.as_raw_fd());
+        drop((in_reader, out_writer));
+        let output = reader.join().unwrap().unwrap();
+        result.map(|_| output)
+    })
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_pipeline() {
+    let rot13 = include_str!("../tests/fixtures/rot13.b");
+    let text = b"The Quick Brown Fox, 1234\n";
+    assert_eq!(run_piped(&[rot13], text).unwrap(), b"Gur Dhvpx Oebja Sbk, 1234\n");
+    assert_eq!(run_piped(&[rot13, rot13], text).unwrap(), text);
+    assert_eq!(run_piped(&[rot13, rot13, rot13], text).unwrap(), b"Gur Dhvpx Oebja Sbk, 1234\n");
+
+    // more than a pipe holds, the stages have to wait for each other
+    let long: Vec<u8> = (0..200_000).map(|i| b'a' + (i % 26) as u8).collect();
+    assert_eq!(run_piped(&[rot13, rot13], &long).unwrap(), long);
+
+    // the second stage fails, the first one that waits for it to read and
+    // the third one that waits for it to print don't hang
+    let err = run_piped(&["+[,.]", "<", ",[.,]"], b"abc").unwrap_err();
+    assert_eq!(err.stage, 1);
+    assert!(matches!(err.error, RuntimeError::PointerUnderflow { inst_index: 0 }));
+    let err = run_piped(&["+[>+]", rot13], b"").unwrap_err();
+    assert_eq!(err.stage, 0);
+    assert!(matches!(err.error, RuntimeError::PointerOverflow { .. }));
+
+    // a stage printing after the next one is done isn't at fault
+    assert_eq!(run_piped(&["+[.]", ",."], b"").unwrap(), [1]);
+}
@@ -0,0 +1,206 @@
+// Where a run spent its time, loop by loop, as JSON for tools tracking it
+// from run to run and as collapsed stacks for flamegraphs. Everything is derived from the execution counts of the
+// interpreter's coverage: a loop was entered as often as its `[` executed
+// and went round as often as its `]` did. There are no timings of loops,
+// counting steps already slows the run down too much for them to mean
+// anything, only the `RunStats` of the whole run may come along.
+use std::collections::BTreeMap;
+use std::io::{self, Read};
+
+use brainfuck::{Inst, Span};
+use brainfuck::Inst::*;
+use runstats::RunStats;
+
+
+// Bumped whenever the JSON changes in a way readers have to know about
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoopProfile {
+    // index of the loop's `[`
+    pub start: usize,
+    // from its `[` up to and including its `]`, if the source is known
+    pub span: Option<Span>,
+    pub entries: u64,
+    pub iterations: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Profile {
+    // in the order of their `[`
+    pub loops: Vec<LoopProfile>,
+    pub instructions: u64,
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    // what the run the counts are from did, as `"run"` if known
+    pub run: Option<RunStats>,
+}
+
+impl Profile {
+    // `counts` are how often each of `insts` executed, `spans` where they
+    // are in the source
+    pub fn new(insts: &[Inst], spans: Option<&[Span]>, counts: &[u64], input_bytes: u64) -> Profile {
+        let loops = insts.iter().enumerate()
+            .filter_map(|(i, inst)| match *inst {
+                JmpFwd(end) => Some(LoopProfile {
+                    start: i,
+                    span: spans.map(|spans| Span { start: spans[i].start, end: spans[end].end }),
+                    entries: counts[i],
+                    iterations: counts[end],
+                }),
+                _ => None,
+            })
+            .collect();
+        let output_bytes = insts.iter().zip(counts)
+            .filter(|&(inst, _)| *inst == PrintCell)
+            .map(|(_, &count)| count)
+            .sum();
+
+        Profile { loops, instructions: counts.iter().sum(), input_bytes, output_bytes, run: None }
+    }
+
+    pub fn to_json(&self) -> String {
+        let loops: Vec<String> = self.loops.iter()
+            .map(|l| {
+                let span = match l.span {
+                    Some(span) => format!("{{\"start\":{},\"end\":{}}}", span.start, span.end),
+                    None => "null".to_string(),
+                };
+                format!(
+                    "{{\"index\":{},\"span\":{},\"entries\":{},\"iterations\":{}}}",
+                    l.start, span, l.entries, l.iterations
+                )
+            })
+            .collect();
+
+        let run = match self.run {
+            Some(ref run) => format!(",\"run\":{}", run.to_json()),
+            None => String::new(),
+        };
+        format!(
+            "{{\"version\":{},\"instructions\":{},\"input_bytes\":{},\"output_bytes\":{},\"loops\":[{}]{}}}",
+            SCHEMA_VERSION, self.instructions, self.input_bytes, self.output_bytes, loops.join(","), run
+        )
+    }
+}
+
+// The executed instructions as collapsed stacks for flamegraph tools, one
+// line per nesting of loops like `main;loop@12;loop@30 4512`. Loops are
+// named by the offset of their `[` in the source, or by its index without
+// one. Every instruction counts for the innermost loop it's in, which
+// includes the loop's own `[` and `]`, and only for it: the count of a
+// line is what ran in that loop and not in one nested in it. Instructions
+// outside of loops count for `main`. Lines that never ran are left out.
+pub fn to_folded(insts: &[Inst], spans: Option<&[Span]>, counts: &[u64]) -> String {
+    let mut stack = vec!["main".to_string()];
+    let mut open = Vec::new();
+    // by the innermost loop's `[`, which is what tells the nestings apart
+    let mut exclusive: BTreeMap<Option<usize>, (String, u64)> = BTreeMap::new();
+
+    for (i, inst) in insts.iter().enumerate() {
+        if let JmpFwd(_) = *inst {
+            stack.push(match spans {
+                Some(spans) => format!("loop@{}", spans[i].start),
+                None => format!("loop#{}", i),
+            });
+            open.push(i);
+        }
+        exclusive.entry(open.last().cloned()).or_insert_with(|| (stack.join(";"), 0)).1 += counts[i];
+        if let JmpBack(_) = *inst {
+            stack.pop();
+            open.pop();
+        }
+    }
+
+    exclusive.values()
+        .filter(|&&(_, count)| count > 0)
+        .map(|(stack, count)| format!("{} {}\n", stack, count))
+        .collect()
+}
+
+// Passes on what `input` reads, counting the bytes
+pub struct CountingReader<R> {
+    input: R,
+    count: u64,
+}
+
+impl<R: Read> CountingReader<R> {
+    pub fn new(input: R) -> CountingReader<R> {
+        CountingReader { input, count: 0 }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.input.read(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+
+#[cfg(test)]
+use brainfuck::parse_with_spans;
+#[cfg(test)]
+use interp::Interp;
+
+#[test]
+fn test_profile() {
+    let source = "+++[>++[-]<-]\n,[.[-],]";
+    let (insts, spans) = parse_with_spans(source).unwrap();
+    let mut interp = Interp::new(&insts, 4);
+    interp.set_coverage(true);
+    let mut input = CountingReader::new(&b"ab"[..]);
+    let mut output = Vec::new();
+    interp.run(&mut input, &mut output).unwrap();
+    assert_eq!(output, b"ab");
+
+    let profile = Profile::new(&insts, Some(&spans), interp.coverage().unwrap(), input.count());
+    assert_eq!(profile.loops.len(), 4);
+    assert_eq!((profile.loops[0].entries, profile.loops[0].iterations), (1, 3));
+    assert_eq!((profile.loops[1].entries, profile.loops[1].iterations), (3, 6));
+    assert_eq!((profile.loops[2].entries, profile.loops[2].iterations), (1, 2));
+    // 'a' and 'b' cleared one by one
+    assert_eq!((profile.loops[3].entries, profile.loops[3].iterations), (2, 97 + 98));
+    assert_eq!((profile.input_bytes, profile.output_bytes), (2, 2));
+    assert_eq!(
+        profile.to_json(),
+        "{\"version\":1,\"instructions\":432,\"input_bytes\":2,\"output_bytes\":2,\"loops\":[\
+         {\"index\":1,\"span\":{\"start\":3,\"end\":13},\"entries\":1,\"iterations\":3},\
+         {\"index\":4,\"span\":{\"start\":7,\"end\":10},\"entries\":3,\"iterations\":6},\
+         {\"index\":11,\"span\":{\"start\":15,\"end\":22},\"entries\":1,\"iterations\":2},\
+         {\"index\":13,\"span\":{\"start\":17,\"end\":20},\"entries\":2,\"iterations\":195}]}"
+    );
+
+    let mut profile = Profile::new(&insts, None, interp.coverage().unwrap(), 2);
+    assert!(profile.to_json().contains("{\"index\":1,\"span\":null,"));
+    // the run counts the same instructions
+    profile.run = Some(interp.stats());
+    assert!(profile.to_json().ends_with(
+        "\"iterations\":195}],\"run\":{\"instructions\":432,\"bytes_read\":2,\"bytes_written\":2,\
+         \"max_pointer\":1,\"final_pointer\":0,\"wall_time_ns\":0}}"
+    ));
+}
+
+#[test]
+fn test_to_folded() {
+    // 3 times round the outer loop, 4 times round the inner one each time
+    let source = "+++[>++++[-]<-]";
+    let (insts, spans) = parse_with_spans(source).unwrap();
+    let mut interp = Interp::new(&insts, 8);
+    interp.set_coverage(true);
+    interp.run(io::empty(), io::sink()).unwrap();
+    let counts = interp.coverage().unwrap();
+
+    // the outer loop runs its `[` once and `>`, `++++`, `<`, `-` and `]`
+    // 3 times, the inner one its `[` 3 times and `-` and `]` 12 times
+    assert_eq!(
+        to_folded(&insts, Some(&spans), counts),
+        "main 1\nmain;loop@3 16\nmain;loop@3;loop@9 27\n"
+    );
+    assert!(to_folded(&insts, None, counts).ends_with("main;loop#1;loop#4 27\n"));
+}
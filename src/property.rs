@@ -0,0 +1,168 @@
+// Property tests on random programs: the interpreter and the generated code
+// have to agree on what a program prints, how it fails and what it leaves on
+// the tape with every `ArithMode`, and so does tiered execution. Lowering it
+// back to source has to parse to the same instructions. Programs come from
+// `generator` and end by construction, failing ones are shrunk before they're
+// reported.
+//
+// BRAINFUCK_PROPERTY_CASES sets the number of programs, BRAINFUCK_PROPERTY_SEED
+// where the generator starts, a failure reports the seed it came from.
+use std::env;
+
+use brainfuck::{parse, to_source, ArithMode, Brainfuck, RuntimeError};
+use generator::{source, terminates, Node, ProgramGenerator, DEFAULT_MAX_DEPTH};
+use interp::Interp;
+
+
+const DEFAULT_CASES: usize = 64;
+const DEFAULT_SEED: u64 = 0x5eed_b7a1_4f00_d1e5;
+
+const TAPE_SIZE: usize = 16;
+const MAX_INPUT: usize = 8;
+
+// A program that terminates, see `generator`, with its input
+#[derive(Debug, Clone, PartialEq)]
+struct Case {
+    program: Vec<Node>,
+    input: Vec<u8>,
+}
+
+fn generate(generator: &mut ProgramGenerator) -> Case {
+    let program = generator.terminating_nodes();
+    let input = generator.input(MAX_INPUT);
+    Case { program, input }
+}
+
+// What's wrong with the case, if anything
+fn failure(case: &Case) -> Option<String> {
+    let insts = parse(&source(&case.program)).unwrap();
+    let lowered = to_source(&insts);
+    if parse(&lowered).unwrap() != insts {
+        return Some(format!("{:?} doesn't parse back to the same instructions", lowered));
+    }
+
+    // loops only ever count their cell down to 0, they end in every mode
+    for &mode in &[ArithMode::Wrap, ArithMode::Saturate, ArithMode::Trap] {
+        let mut interp = Interp::new(&insts, TAPE_SIZE);
+        interp.set_arith_mode(mode);
+        let mut output = Vec::new();
+        let interpreted = interp.run(&case.input[..], &mut output).map(|_| (output, interp.tape().to_vec()));
+
+        let mut bf = Brainfuck::from_insts(insts.clone()).unwrap();
+        bf.set_tape_size(TAPE_SIZE).unwrap();
+        bf.set_arith_mode(mode).unwrap();
+        let jitted = bf.share().unwrap().run(&case.input).map(|execution| (execution.output, execution.tape));
+        if let Some(failure) = compare(mode, "the generated code", &interpreted, jitted) {
+            return Some(failure);
+        }
+
+        // compiling every loop right away and once some went round
+        let mut tiered = bf.tiered();
+        for &threshold in &[0, 3] {
+            tiered.set_threshold(threshold);
+            let run = tiered.run_piped(&case.input).map(|execution| (execution.output, execution.tape));
+            if let Some(failure) = compare(mode, "tiered execution", &interpreted, run) {
+                return Some(failure);
+            }
+        }
+    }
+
+    None
+}
+
+type Run = Result<(Vec<u8>, Vec<u8>), RuntimeError>;
+
+// What's wrong with a run of what gives `other` compared to the interpreter's
+fn compare(mode: ArithMode, other: &str, interpreted: &Run, run: Run) -> Option<String> {
+    match (interpreted, run) {
+        (Ok(ref a), Ok(ref b)) if a == b => None,
+        (Err(ref a), Err(ref b)) if a.to_string() == b.to_string() => None,
+        (a, b) => Some(format!("{:?}: the interpreter gives {:?}, {} {:?}", mode, a, other, b)),
+    }
+}
+
+// Every program with a node less or a loop replaced by its body, programs
+// without one of the outermost loops first
+fn smaller(nodes: &[Node]) -> Vec<Vec<Node>> {
+    let (mut without_loops, mut rest) = (Vec::new(), Vec::new());
+    for (i, node) in nodes.iter().enumerate() {
+        let mut without = nodes.to_vec();
+        without.remove(i);
+        match *node {
+            Node::Loop(ref body) => {
+                without_loops.push(without);
+                let mut unwrapped = nodes[..i].to_vec();
+                unwrapped.extend_from_slice(body);
+                unwrapped.extend_from_slice(&nodes[i + 1..]);
+                rest.push(unwrapped);
+                for body in smaller(body) {
+                    let mut program = nodes.to_vec();
+                    program[i] = Node::Loop(body);
+                    rest.push(program);
+                }
+            }
+            _ => rest.push(without),
+        }
+    }
+    without_loops.extend(rest);
+    without_loops
+}
+
+// Takes smaller valid cases that still fail as long as there are any
+fn shrink<F: Fn(&Case) -> bool>(mut case: Case, fails: F) -> Case {
+    'shrinking: loop {
+        let programs = smaller(&case.program).into_iter()
+            .filter(|program| terminates(program, TAPE_SIZE, DEFAULT_MAX_DEPTH))
+            .map(|program| Case { program, input: case.input.clone() });
+        let inputs = (0..case.input.len()).map(|i| {
+            let mut input = case.input.clone();
+            input.remove(i);
+            Case { program: case.program.clone(), input }
+        });
+        for candidate in programs.chain(inputs) {
+            if fails(&candidate) {
+                case = candidate;
+                continue 'shrinking;
+            }
+        }
+        return case;
+    }
+}
+
+#[test]
+fn test_shrink() {
+    // pretend that printing is broken
+    let case = Case {
+        program: vec![
+            Node::Add(2),
+            Node::Loop(vec![Node::Move(3), Node::Print, Node::Read, Node::Move(-3), Node::Sub(1)]),
+            Node::Move(1),
+            Node::Print,
+        ],
+        input: b"abc".to_vec(),
+    };
+    let shrunk = shrink(case, |case| source(&case.program).contains('.'));
+    assert_eq!(shrunk, Case { program: vec![Node::Print], input: Vec::new() });
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_random_programs() {
+    let cases = env::var("BRAINFUCK_PROPERTY_CASES").ok().and_then(|n| n.parse().ok())
+        .unwrap_or(DEFAULT_CASES);
+    let seed = env::var("BRAINFUCK_PROPERTY_SEED").ok()
+        .and_then(|seed| u64::from_str_radix(seed.trim_start_matches("0x"), 16).ok())
+        .unwrap_or(DEFAULT_SEED);
+
+    let mut generator = ProgramGenerator::new(seed).tape_size(TAPE_SIZE);
+    for i in 0..cases {
+        let case = generate(&mut generator);
+        if failure(&case).is_some() {
+            let shrunk = shrink(case, |case| failure(case).is_some());
+            panic!(
+                "case {} from seed {:#x} fails, shrunk to {:?} with input {:?}: {}",
+                i, seed, source(&shrunk.program), shrunk.input, failure(&shrunk).unwrap()
+            );
+        }
+    }
+}
@@ -0,0 +1,82 @@
+// Input of a run as the program read it, for reproducing a session: what
+// `Recorder` passes on is written down as it goes, `Replay` hands it back.
+// Both sit between the program and any buffering of its input, so what is
+// recorded is exactly what `,` got. The end of the input isn't part of a
+// recording, a program that read up to it reads past the recording when
+// replayed.
+use std::io::{self, Read, Write};
+
+
+// Reads from `input`, writing every byte read to `record`
+pub struct Recorder<R, W> {
+    input: R,
+    record: W,
+}
+
+impl<R: Read, W: Write> Recorder<R, W> {
+    pub fn new(input: R, record: W) -> Recorder<R, W> {
+        Recorder { input, record }
+    }
+}
+
+impl<R: Read, W: Write> Read for Recorder<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.input.read(buf)?;
+        self.record.write_all(&buf[..n])?;
+        Ok(n)
+    }
+}
+
+// Reads the recorded bytes, then from `rest` or, without it, fails: the
+// program went on reading where the recording ended
+pub struct Replay<R> {
+    recorded: io::Cursor<Vec<u8>>,
+    rest: Option<R>,
+}
+
+impl<R: Read> Replay<R> {
+    pub fn new(recorded: Vec<u8>, rest: Option<R>) -> Replay<R> {
+        Replay { recorded: io::Cursor::new(recorded), rest }
+    }
+}
+
+impl<R: Read> Read for Replay<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.recorded.read(buf)? {
+            0 if !buf.is_empty() => match self.rest {
+                Some(ref mut rest) => rest.read(buf),
+                None => Err(io::Error::other(format!(
+                    "the program read more than the {} recorded bytes", self.recorded.get_ref().len()
+                ))),
+            },
+            n => Ok(n),
+        }
+    }
+}
+
+
+#[test]
+fn test_record_and_replay() {
+    let mut record = Vec::new();
+    let mut input = Vec::new();
+    let mut recorder = Recorder::new(&b"abc"[..], &mut record);
+    let mut byte = [0];
+    recorder.read_exact(&mut byte).unwrap();
+    input.push(byte[0]);
+    recorder.read_exact(&mut byte).unwrap();
+    input.push(byte[0]);
+    // only what was read
+    assert_eq!(record, b"ab");
+
+    let mut replay = Replay::new(record, None::<&[u8]>);
+    let mut replayed = [0; 2];
+    replay.read_exact(&mut replayed).unwrap();
+    assert_eq!(replayed, &input[..]);
+    assert_eq!(replay.read(&mut byte).unwrap_err().to_string(), "the program read more than the 2 recorded bytes");
+    assert_eq!(replay.read(&mut []).unwrap(), 0);
+
+    let mut replay = Replay::new(b"ab".to_vec(), Some(&b"c"[..]));
+    let mut all = Vec::new();
+    replay.read_to_end(&mut all).unwrap();
+    assert_eq!(all, b"abc");
+}
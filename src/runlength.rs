@@ -1,14 +1,21 @@
-use std::iter::{Iterator, Peekable};
+use std::iter::{Iterator, DoubleEndedIterator};
 use std::cmp::Eq;
+use std::io::{self, Read};
 
 
 // An iterator that yields the run length and the element iself
 // 
 // Iterator element type is `(usize, I::Item)`
+//
+// Elements already taken from either end of `iter` but not yet part of a
+// yielded run are kept in `front`/`back`, so the forward and backward cursors
+// never split a run between them.
 pub struct RunLength<I>
     where I: Iterator
 {
-    iter: Peekable<I>
+    iter: I,
+    front: Option<I::Item>,
+    back: Option<I::Item>,
 }
 
 impl<I, T> Iterator for RunLength<I>
@@ -17,16 +24,52 @@ impl<I, T> Iterator for RunLength<I>
     type Item = (usize, I::Item);
 
     fn next(&mut self) -> Option<(usize, I::Item)> {
-        let current = match self.iter.next() {
-            Some(current) => current,
-            None => return None,
-        };
+        let current = self.front.take()
+            .or_else(|| self.iter.next())
+            .or_else(|| self.back.take())?;
 
         let mut length = 1;
 
-        while self.iter.peek() == Some(&current) {
-            length += 1;
-            self.iter.next();
+        loop {
+            match self.iter.next().or_else(|| self.back.take()) {
+                Some(ref next) if *next == current => length += 1,
+                next => {
+                    self.front = next;
+                    break;
+                }
+            }
+        }
+
+        Some((length, current))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let pending = self.front.is_some() as usize + self.back.is_some() as usize;
+        let (lower, upper) = self.iter.size_hint();
+
+        let lower = if lower > 0 || pending > 0 { 1 } else { 0 };
+        (lower, upper.and_then(|upper| upper.checked_add(pending)))
+    }
+}
+
+impl<I, T> DoubleEndedIterator for RunLength<I>
+    where I: DoubleEndedIterator<Item=T>, T: Eq
+{
+    fn next_back(&mut self) -> Option<(usize, I::Item)> {
+        let current = self.back.take()
+            .or_else(|| self.iter.next_back())
+            .or_else(|| self.front.take())?;
+
+        let mut length = 1;
+
+        loop {
+            match self.iter.next_back().or_else(|| self.front.take()) {
+                Some(ref next) if *next == current => length += 1,
+                next => {
+                    self.back = next;
+                    break;
+                }
+            }
         }
 
         Some((length, current))
@@ -37,7 +80,7 @@ impl<I> RunLength<I>
     where I: Iterator
 {
     fn new(i: I) -> RunLength<I> {
-        RunLength { iter: i.peekable() }
+        RunLength { iter: i, front: None, back: None }
     }
 }
 
@@ -52,6 +95,105 @@ pub trait RunLengthIterator: Iterator {
 impl<T: ?Sized> RunLengthIterator for T where T: Iterator { }
 
 
+// Like `RunLength`, but over the bytes of an `io::Read`
+//
+// The reader is consumed in chunks, runs spanning a chunk boundary are merged.
+// Iterator element type is `io::Result<(usize, u8)>`
+pub struct RunLengthBytes<R>
+    where R: Read
+{
+    reader: R,
+    buf: Box<[u8]>,
+    pos: usize,
+    len: usize,
+    error: Option<io::Error>,
+}
+
+impl<R> RunLengthBytes<R>
+    where R: Read
+{
+    pub fn new(reader: R) -> RunLengthBytes<R> {
+        RunLengthBytes::with_capacity(8 * 1024, reader)
+    }
+
+    pub fn with_capacity(capacity: usize, reader: R) -> RunLengthBytes<R> {
+        assert!(capacity > 0, "chunk capacity must be non-zero");
+
+        RunLengthBytes {
+            reader,
+            buf: vec![0; capacity].into_boxed_slice(),
+            pos: 0,
+            len: 0,
+            error: None,
+        }
+    }
+
+    // Refills the buffer, returns `false` on EOF
+    fn fill_buf(&mut self) -> io::Result<bool> {
+        loop {
+            match self.reader.read(&mut self.buf) {
+                Ok(0) => return Ok(false),
+                Ok(n) => {
+                    self.pos = 0;
+                    self.len = n;
+                    return Ok(true);
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl<R> Iterator for RunLengthBytes<R>
+    where R: Read
+{
+    type Item = io::Result<(usize, u8)>;
+
+    fn next(&mut self) -> Option<io::Result<(usize, u8)>> {
+        if let Some(e) = self.error.take() {
+            return Some(Err(e));
+        }
+
+        if self.pos == self.len {
+            match self.fill_buf() {
+                Ok(true) => {}
+                Ok(false) => return None,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        let current = self.buf[self.pos];
+        let mut length = 1;
+        self.pos += 1;
+
+        loop {
+            while self.pos < self.len && self.buf[self.pos] == current {
+                length += 1;
+                self.pos += 1;
+            }
+
+            if self.pos < self.len {
+                break;
+            }
+
+            // the run reaches the end of the chunk and may continue in the next one,
+            // an error is reported after the run collected so far
+            match self.fill_buf() {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => {
+                    self.error = Some(e);
+                    break;
+                }
+            }
+        }
+
+        Some(Ok((length, current)))
+    }
+}
+
+
 #[test]
 fn test_run_length() {
     let numbers = [
@@ -68,3 +210,123 @@ fn test_run_length() {
     assert_eq!(None, "".chars().run_length().next());
     assert_eq!(Some((1, '1')), "1".chars().run_length().next());
 }
+
+
+#[test]
+fn test_run_length_size_hint() {
+    assert_eq!((0, Some(0)), "".chars().run_length().size_hint());
+    assert_eq!((1, Some(3)), [1, 1, 2].iter().run_length().size_hint());
+
+    let mut runs = [1, 1, 2].iter().run_length();
+    runs.next();
+    assert_eq!((1, Some(1)), runs.size_hint());
+    runs.next();
+    assert_eq!((0, Some(0)), runs.size_hint());
+
+    // an unbounded inner iterator with a pending element must not overflow
+    let mut runs = ::std::iter::once(1).chain(::std::iter::repeat(2)).run_length();
+    assert_eq!(Some((1, 1)), runs.next());
+    assert_eq!((1, None), runs.size_hint());
+}
+
+// xorshift, good enough to shuffle test inputs around deterministically
+#[cfg(test)]
+fn test_rng(seed: u64) -> impl FnMut() -> u64 {
+    let mut state = seed;
+    move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    }
+}
+
+#[test]
+fn test_run_length_rev() {
+    let mut rng = test_rng(0x2545_f491_4f6c_dd1d);
+
+    for _ in 0..1000 {
+        let len = (rng() % 32) as usize;
+        let seq: Vec<u64> = (0..len).map(|_| rng() % 3).collect();
+
+        let mut expected: Vec<_> = seq.iter().run_length().collect();
+        expected.reverse();
+        let reversed: Vec<_> = seq.iter().run_length().rev().collect();
+        assert_eq!(expected, reversed);
+
+        // alternating between both ends must see the same runs as well
+        let forward: Vec<_> = seq.iter().run_length().collect();
+        let mut runs = seq.iter().run_length();
+        let (mut head, mut tail) = (Vec::new(), Vec::new());
+        loop {
+            let from_back = rng() & 1 == 0;
+            let run = if from_back { runs.next_back() } else { runs.next() };
+            match run {
+                Some(run) if from_back => tail.push(run),
+                Some(run) => head.push(run),
+                None => break,
+            }
+        }
+        tail.reverse();
+        head.extend(tail);
+        assert_eq!(forward, head);
+    }
+}
+
+#[cfg(test)]
+struct ChunkedReader<'a> {
+    data: &'a [u8],
+    chunk: usize,
+    fail_at: Option<usize>,
+    consumed: usize,
+}
+
+#[cfg(test)]
+impl<'a> Read for ChunkedReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.fail_at == Some(self.consumed) {
+            self.fail_at = None;
+            return Err(io::Error::other("injected"));
+        }
+
+        let mut n = self.chunk.min(buf.len()).min(self.data.len());
+        if let Some(fail_at) = self.fail_at {
+            n = n.min(fail_at - self.consumed);
+        }
+        buf[..n].copy_from_slice(&self.data[..n]);
+        self.data = &self.data[n..];
+        self.consumed += n;
+
+        Ok(n)
+    }
+}
+
+#[test]
+fn test_run_length_bytes() {
+    let data = b"+++++>>[-]<<....,,";
+    let expected: Vec<_> = data.iter().cloned().run_length().collect();
+
+    for &chunk in &[1, 2, 3, 7, 64] {
+        let reader = ChunkedReader { data, chunk, fail_at: None, consumed: 0 };
+        let runs: Vec<_> = RunLengthBytes::with_capacity(chunk, reader)
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(expected, runs);
+    }
+
+    let reader = ChunkedReader { data: b"", chunk: 1, fail_at: None, consumed: 0 };
+    assert!(RunLengthBytes::new(reader).next().is_none());
+}
+
+#[test]
+fn test_run_length_bytes_error() {
+    let reader = ChunkedReader { data: b"aaaabb", chunk: 1, fail_at: Some(2), consumed: 0 };
+    let mut runs = RunLengthBytes::with_capacity(1, reader);
+
+    // the run interrupted by the error is reported first, the error after it
+    assert_eq!((2, b'a'), runs.next().unwrap().unwrap());
+    assert_eq!(io::ErrorKind::Other, runs.next().unwrap().unwrap_err().kind());
+    assert_eq!((2, b'a'), runs.next().unwrap().unwrap());
+    assert_eq!((2, b'b'), runs.next().unwrap().unwrap());
+    assert!(runs.next().is_none());
+}
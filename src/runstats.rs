@@ -0,0 +1,85 @@
+use std::fmt::Write;
+use std::time::Duration;
+
+
+// What a run did, as far as whatever ran it kept track. The interpreter
+// counts everything as it goes, which costs next to nothing. Generated code
+// has no counting compiled into it, of its runs only the wall time is known.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RunStats {
+    // Instructions executed, a run of `+` or `>` being one
+    pub instructions: Option<u64>,
+    pub bytes_read: Option<u64>,
+    pub bytes_written: Option<u64>,
+    // The cell furthest from the start of the tape the pointer got to
+    pub max_pointer: Option<usize>,
+    pub final_pointer: Option<usize>,
+    pub wall_time: Duration,
+}
+
+impl RunStats {
+    pub fn to_text(self) -> String {
+        fn known<T: ToString>(value: Option<T>) -> String {
+            value.map_or("-".to_string(), |value| value.to_string())
+        }
+
+        let mut out = String::new();
+        writeln!(out, "{:<16}{:>12}", "instructions", known(self.instructions)).unwrap();
+        writeln!(out, "{:<16}{:>12}", "bytes read", known(self.bytes_read)).unwrap();
+        writeln!(out, "{:<16}{:>12}", "bytes written", known(self.bytes_written)).unwrap();
+        writeln!(out, "{:<16}{:>12}", "max pointer", known(self.max_pointer)).unwrap();
+        writeln!(out, "{:<16}{:>12}", "final pointer", known(self.final_pointer)).unwrap();
+        writeln!(out, "{:<16}{:>12}", "wall time", format!("{:?}", self.wall_time)).unwrap();
+
+        out
+    }
+
+    // What isn't known is null
+    pub fn to_json(self) -> String {
+        fn known<T: ToString>(value: Option<T>) -> String {
+            value.map_or("null".to_string(), |value| value.to_string())
+        }
+
+        format!(
+            "{{\"instructions\":{},\"bytes_read\":{},\"bytes_written\":{},\"max_pointer\":{},\
+             \"final_pointer\":{},\"wall_time_ns\":{}}}",
+            known(self.instructions), known(self.bytes_read), known(self.bytes_written),
+            known(self.max_pointer), known(self.final_pointer), self.wall_time.as_nanos()
+        )
+    }
+}
+
+
+#[test]
+fn test_run_stats() {
+    let stats = RunStats {
+        instructions: Some(16),
+        bytes_read: Some(2),
+        bytes_written: Some(2),
+        max_pointer: Some(1),
+        final_pointer: Some(0),
+        wall_time: Duration::from_micros(1500),
+    };
+    assert_eq!(stats.to_text(), "\
+instructions              16
+bytes read                 2
+bytes written              2
+max pointer                1
+final pointer              0
+wall time              1.5ms
+");
+    assert_eq!(
+        stats.to_json(),
+        "{\"instructions\":16,\"bytes_read\":2,\"bytes_written\":2,\"max_pointer\":1,\"final_pointer\":0,\
+         \"wall_time_ns\":1500000}"
+    );
+
+    // generated code only knows the time
+    let stats = RunStats { wall_time: Duration::from_secs(2), ..RunStats::default() };
+    assert_eq!(
+        stats.to_json(),
+        "{\"instructions\":null,\"bytes_read\":null,\"bytes_written\":null,\"max_pointer\":null,\
+         \"final_pointer\":null,\"wall_time_ns\":2000000000}"
+    );
+    assert!(stats.to_text().starts_with("instructions               -\n"));
+}
@@ -0,0 +1,152 @@
+use std::fmt::Write;
+
+use brainfuck::{min_tape_size, to_source, Inst};
+use brainfuck::Inst::*;
+use optimize::dead_loops;
+use runlength::RunLengthIterator;
+
+
+const COMMANDS: [char; 8] = ['>', '<', '+', '-', '.', ',', '[', ']'];
+
+// Static metrics of a parsed program
+#[derive(Debug, PartialEq, Eq)]
+pub struct ProgramStats {
+    pub commands: usize,
+    // Occurrences of each command, in the order of `COMMANDS`
+    pub histogram: [usize; 8],
+    pub loops: usize,
+    pub max_depth: usize,
+    pub longest_run: usize,
+    // Sum of all pointer moves, regardless of how often they execute
+    pub pointer_drift: isize,
+    // Loops that can never be entered, including the ones nested in them
+    pub dead_loops: usize,
+    pub min_tape_size: usize,
+}
+
+impl ProgramStats {
+    pub fn new(insts: &[Inst]) -> ProgramStats {
+        let mut histogram = [0; 8];
+        let mut longest_run = 0;
+        let (mut depth, mut max_depth) = (0, 0);
+        let mut pointer_drift = 0;
+
+        for (length, c) in to_source(insts).chars().run_length() {
+            // the commands of a dialect aren't in the histogram
+            if let Some(i) = COMMANDS.iter().position(|&command| command == c) {
+                histogram[i] += length;
+            }
+            longest_run = longest_run.max(length);
+        }
+
+        for inst in insts {
+            match *inst {
+                IncPtr(a) => pointer_drift += a as isize,
+                DecPtr(a) => pointer_drift -= a as isize,
+                JmpFwd(_) => {
+                    depth += 1;
+                    max_depth = max_depth.max(depth);
+                }
+                JmpBack(_) => depth -= 1,
+                _ => {}
+            }
+        }
+
+        let dead_loops = dead_loops(insts).into_iter()
+            .map(|i| match insts[i] {
+                JmpFwd(n) => insts[i..n].iter().filter(|inst| matches!(**inst, JmpFwd(_))).count(),
+                _ => unreachable!(),
+            })
+            .sum();
+
+        ProgramStats {
+            commands: histogram.iter().sum(),
+            histogram,
+            loops: histogram[6],
+            max_depth,
+            longest_run,
+            pointer_drift,
+            dead_loops,
+            min_tape_size: min_tape_size(insts),
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+
+        writeln!(out, "{:<16}{:>8}", "commands", self.commands).unwrap();
+        for (c, count) in COMMANDS.iter().zip(&self.histogram) {
+            writeln!(out, "  {:<14}{:>8}", c, count).unwrap();
+        }
+        writeln!(out, "{:<16}{:>8}", "loops", self.loops).unwrap();
+        writeln!(out, "{:<16}{:>8}", "max depth", self.max_depth).unwrap();
+        writeln!(out, "{:<16}{:>8}", "longest run", self.longest_run).unwrap();
+        writeln!(out, "{:<16}{:>+8}", "pointer drift", self.pointer_drift).unwrap();
+        writeln!(out, "{:<16}{:>8}", "dead loops", self.dead_loops).unwrap();
+        writeln!(out, "{:<16}{:>8}", "min tape size", self.min_tape_size).unwrap();
+
+        out
+    }
+
+    pub fn to_json(&self) -> String {
+        let histogram: Vec<String> = COMMANDS.iter().zip(&self.histogram)
+            .map(|(c, count)| format!("\"{}\":{}", c, count))
+            .collect();
+
+        format!(
+            "{{\"commands\":{},\"histogram\":{{{}}},\"loops\":{},\"max_depth\":{},\
+             \"longest_run\":{},\"pointer_drift\":{},\"dead_loops\":{},\"min_tape_size\":{}}}",
+            self.commands, histogram.join(","), self.loops, self.max_depth,
+            self.longest_run, self.pointer_drift, self.dead_loops, self.min_tape_size
+        )
+    }
+}
+
+
+#[cfg(test)]
+use brainfuck::parse;
+
+#[test]
+fn test_stats_hello() {
+    let stats = ProgramStats::new(&parse(include_str!("../tests/fixtures/hello.b")).unwrap());
+
+    assert_eq!(stats.to_text(), "\
+commands             106
+  >                   18
+  <                    8
+  +                   40
+  -                   21
+  .                   13
+  ,                    0
+  [                    3
+  ]                    3
+loops                  3
+max depth              2
+longest run            8
+pointer drift        +10
+dead loops             0
+min tape size          1
+");
+}
+
+#[test]
+fn test_stats_rot13() {
+    let stats = ProgramStats::new(&parse(include_str!("../tests/fixtures/rot13.b")).unwrap());
+
+    assert_eq!(
+        stats.to_json(),
+        "{\"commands\":190,\"histogram\":{\">\":39,\"<\":33,\"+\":41,\"-\":26,\".\":1,\",\":2,\
+         \"[\":24,\"]\":24},\"loops\":24,\"max_depth\":5,\"longest_run\":12,\
+         \"pointer_drift\":6,\"dead_loops\":0,\"min_tape_size\":1}"
+    );
+}
+
+#[test]
+fn test_stats_dead_loops() {
+    let stats = ProgramStats::new(&parse("[[.]]>>>[-]+[-][[]]<").unwrap());
+
+    assert_eq!(stats.dead_loops, 5);
+    assert_eq!(stats.loops, 6);
+    assert_eq!(stats.pointer_drift, 2);
+    assert_eq!(stats.min_tape_size, 1);
+}
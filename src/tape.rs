@@ -0,0 +1,269 @@
+// The tapes runs of generated code allocate, on the heap or, for large
+// tapes and if asked for, in a mapping of their own backed by huge pages,
+// see `TapeAlloc`, or a file mapped as the tape, see
+// `Brainfuck::set_tape_file`
+#[cfg(not(target_family = "wasm"))]
+use std::fs::File;
+use std::io;
+use std::ops::{Deref, DerefMut};
+#[cfg(not(target_family = "wasm"))]
+use std::os::unix::io::AsRawFd;
+#[cfg(not(target_family = "wasm"))]
+use std::{ptr, slice};
+
+#[cfg(not(target_family = "wasm"))]
+use libc;
+
+use brainfuck::TapeAlloc;
+
+
+// Size of a huge page on x86-64, smaller tapes always go on the heap
+pub const HUGE_PAGE: usize = 2 << 20;
+
+pub enum Tape {
+    Heap(Vec<u8>),
+    #[cfg(target_os = "linux")]
+    Mapped(Mapping),
+    #[cfg(not(target_family = "wasm"))]
+    File(FileMapping),
+}
+
+// Cells mapped at a multiple of `HUGE_PAGE`, unmapped when dropped
+#[cfg(target_os = "linux")]
+pub struct Mapping {
+    base: *mut u8,
+    len: usize,
+    // `len` rounded up to whole huge pages
+    mapped: usize,
+    // what the mapping ended up being
+    alloc: TapeAlloc,
+}
+
+// Only ever accessed through the `Tape` owning it
+#[cfg(target_os = "linux")]
+unsafe impl Send for Mapping {}
+#[cfg(target_os = "linux")]
+unsafe impl Sync for Mapping {}
+
+// The cells of a file, mapped shared so what's written to them ends up in
+// the file, unmapped when dropped
+#[cfg(not(target_family = "wasm"))]
+pub struct FileMapping {
+    base: *mut u8,
+    len: usize,
+}
+
+// Only ever accessed through the `Tape` owning it
+#[cfg(not(target_family = "wasm"))]
+unsafe impl Send for FileMapping {}
+#[cfg(not(target_family = "wasm"))]
+unsafe impl Sync for FileMapping {}
+
+#[cfg(target_os = "linux")]
+fn map(len: usize, flags: libc::c_int) -> Option<*mut u8> {
+    let base = unsafe {
+        libc::mmap(
+            ptr::null_mut(), len, libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | flags, -1, 0
+        )
+    };
+    if base == libc::MAP_FAILED { None } else { Some(base as *mut u8) }
+}
+
+#[cfg(target_os = "linux")]
+fn unmap(base: *mut u8, len: usize) {
+    if len > 0 {
+        unsafe { libc::munmap(base as *mut libc::c_void, len) };
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Mapping {
+    // `None` if nothing could be mapped, a mapping without huge pages if
+    // the kernel doesn't do them
+    fn new(len: usize, alloc: TapeAlloc) -> Option<Mapping> {
+        let mapped = len.div_ceil(HUGE_PAGE) * HUGE_PAGE;
+
+        // fails unless the pool has enough pages, without reserving them
+        // the first access could fail instead
+        if alloc == TapeAlloc::HugeTlb {
+            if let Some(base) = map(mapped, libc::MAP_HUGETLB) {
+                return Some(Mapping { base, len, mapped, alloc });
+            }
+        }
+
+        // transparent huge pages only back aligned ranges, a huge page more
+        // than needed is mapped and what's around the aligned part cut off
+        let unaligned = map(mapped + HUGE_PAGE, libc::MAP_NORESERVE)?;
+        let head = unaligned.align_offset(HUGE_PAGE);
+        let base = unaligned.wrapping_add(head);
+        unmap(unaligned, head);
+        unmap(base.wrapping_add(mapped), HUGE_PAGE - head);
+
+        let advised = unsafe { libc::madvise(base as *mut libc::c_void, mapped, libc::MADV_HUGEPAGE) } == 0;
+        let alloc = if advised { TapeAlloc::Huge } else { TapeAlloc::Default };
+        Some(Mapping { base, len, mapped, alloc })
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unmap(self.base, self.mapped);
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl FileMapping {
+    // The first `len` bytes of `file`, which has to be open for reading and
+    // writing and at least that long
+    fn new(file: &File, len: usize) -> io::Result<FileMapping> {
+        let base = unsafe {
+            libc::mmap(
+                ptr::null_mut(), len, libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED, file.as_raw_fd(), 0
+            )
+        };
+        if base == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(FileMapping { base: base as *mut u8, len })
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl Drop for FileMapping {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.base as *mut libc::c_void, self.len) };
+    }
+}
+
+impl Tape {
+    // `size` cells set to zero, allocated as `alloc` asks for where that
+    // works out and on the heap otherwise
+    pub fn new(size: usize, alloc: TapeAlloc) -> Tape {
+        #[cfg(target_os = "linux")]
+        if alloc != TapeAlloc::Default && size >= HUGE_PAGE {
+            if let Some(mapping) = Mapping::new(size, alloc) {
+                return Tape::Mapped(mapping);
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = alloc;
+
+        Tape::Heap(vec![0; size])
+    }
+
+    // `size` cells holding what `file` does, written to as the tape is.
+    // The file has to be exactly that long.
+    #[cfg(not(target_family = "wasm"))]
+    pub fn map_file(file: &File, size: usize) -> io::Result<Tape> {
+        FileMapping::new(file, size).map(Tape::File)
+    }
+
+    // Writes what changed on a tape mapped from a file back to it, nothing
+    // to do for any other tape
+    pub fn flush(&self) -> io::Result<()> {
+        match *self {
+            #[cfg(not(target_family = "wasm"))]
+            Tape::File(ref mapping) => {
+                if unsafe { libc::msync(mapping.base as *mut libc::c_void, mapping.len, libc::MS_SYNC) } != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // How the tape was actually allocated
+    pub fn alloc(&self) -> TapeAlloc {
+        match *self {
+            Tape::Heap(_) => TapeAlloc::Default,
+            #[cfg(target_os = "linux")]
+            Tape::Mapped(ref mapping) => mapping.alloc,
+            #[cfg(not(target_family = "wasm"))]
+            Tape::File(_) => TapeAlloc::Default,
+        }
+    }
+
+    pub fn into_vec(self) -> Vec<u8> {
+        match self {
+            Tape::Heap(cells) => cells,
+            #[cfg(not(target_family = "wasm"))]
+            tape => tape.to_vec(),
+        }
+    }
+}
+
+impl Default for Tape {
+    fn default() -> Tape {
+        Tape::Heap(Vec::new())
+    }
+}
+
+impl From<Vec<u8>> for Tape {
+    fn from(cells: Vec<u8>) -> Tape {
+        Tape::Heap(cells)
+    }
+}
+
+// Copies go on the heap
+impl Clone for Tape {
+    fn clone(&self) -> Tape {
+        Tape::Heap(self.to_vec())
+    }
+}
+
+impl Deref for Tape {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match *self {
+            Tape::Heap(ref cells) => cells,
+            #[cfg(target_os = "linux")]
+            Tape::Mapped(ref mapping) => unsafe { slice::from_raw_parts(mapping.base, mapping.len) },
+            #[cfg(not(target_family = "wasm"))]
+            Tape::File(ref mapping) => unsafe { slice::from_raw_parts(mapping.base, mapping.len) },
+        }
+    }
+}
+
+impl DerefMut for Tape {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        match *self {
+            Tape::Heap(ref mut cells) => cells,
+            #[cfg(target_os = "linux")]
+            Tape::Mapped(ref mut mapping) => unsafe { slice::from_raw_parts_mut(mapping.base, mapping.len) },
+            #[cfg(not(target_family = "wasm"))]
+            Tape::File(ref mut mapping) => unsafe { slice::from_raw_parts_mut(mapping.base, mapping.len) },
+        }
+    }
+}
+
+
+#[test]
+fn test_tape() {
+    let small = Tape::new(100, TapeAlloc::Huge);
+    assert_eq!(small.alloc(), TapeAlloc::Default);
+    assert_eq!(*small, [0; 100][..]);
+
+    for &alloc in &[TapeAlloc::Default, TapeAlloc::Huge, TapeAlloc::HugeTlb] {
+        let size = 3 * HUGE_PAGE + 5;
+        let mut tape = Tape::new(size, alloc);
+        assert_eq!(tape.len(), size);
+        assert!(tape.iter().all(|&cell| cell == 0));
+        tape[size - 1] = 1;
+        tape[HUGE_PAGE] = 2;
+        if tape.alloc() != TapeAlloc::Default {
+            assert_eq!(tape.as_ptr() as usize % HUGE_PAGE, 0);
+        }
+
+        let copy = tape.clone();
+        assert_eq!(copy.alloc(), TapeAlloc::Default);
+        assert!(copy[..] == tape[..]);
+        let cells = tape.into_vec();
+        assert_eq!((cells[HUGE_PAGE], cells[size - 1]), (2, 1));
+    }
+}
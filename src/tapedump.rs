@@ -0,0 +1,143 @@
+use std::io::{self, Write};
+
+use brainfuck::write_hexdump;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TapeFormat {
+    Hex,
+    Raw,
+    // one line per cell that isn't zero
+    NonZero,
+}
+
+// Length of the tape up to and including the last cell that isn't zero,
+// everything after it was most likely never touched
+pub fn used_len(tape: &[u8]) -> usize {
+    tape.iter().rposition(|&cell| cell != 0).map_or(0, |i| i + 1)
+}
+
+// Writes `tape` in `format`, without the zero cells at its end unless
+// `full` is set
+pub fn write_tape<W: Write>(tape: &[u8], format: TapeFormat, full: bool, out: W) -> io::Result<()> {
+    write_tape_from(tape, 0, format, full, out)
+}
+
+// Like `write_tape` for the cells of a `twosided::TwoSidedTape`, the first
+// one being cell `first`. Cells before 0 are labeled with their negative
+// index, and unless `full` is set the zero cells before the first one that
+// isn't are left out as well, down to cell 0. Raw dumps have no labels and
+// start at whatever cell comes first.
+pub fn write_tape_from<W: Write>(
+    tape: &[u8], first: isize, format: TapeFormat, full: bool, mut out: W,
+) -> io::Result<()> {
+    let (first, tape) = if full {
+        (first, tape)
+    } else {
+        let tape = &tape[..used_len(tape)];
+        // cells before 0 that are zero, up to the first one that isn't
+        let before_zero = (-first).max(0) as usize;
+        let skip = tape.iter().take(before_zero).position(|&cell| cell != 0).unwrap_or(before_zero);
+        (first + skip as isize, &tape[skip.min(tape.len())..])
+    };
+
+    match format {
+        TapeFormat::Hex if first == 0 => write_hexdump(tape, out),
+        TapeFormat::Hex => {
+            for (line, chunk) in tape.chunks(16).enumerate() {
+                let mut hex = String::new();
+                for i in 0..16 {
+                    match chunk.get(i) {
+                        Some(byte) => hex.push_str(&format!("{:02x} ", byte)),
+                        None => hex.push_str("   "),
+                    }
+                    if i == 7 {
+                        hex.push(' ');
+                    }
+                }
+                let ascii: String = chunk.iter()
+                    .map(|&byte| if (0x20..0x7f).contains(&byte) { byte as char } else { '.' })
+                    .collect();
+                writeln!(out, "{}  {} |{}|", signed_offset(first + line as isize * 16), hex, ascii)?;
+            }
+            writeln!(out, "{}", signed_offset(first + tape.len() as isize))
+        }
+        TapeFormat::Raw => out.write_all(tape),
+        TapeFormat::NonZero => {
+            for (i, &cell) in tape.iter().enumerate().filter(|&(_, &cell)| cell != 0) {
+                let i = first + i as isize;
+                if (0x20..0x7f).contains(&cell) {
+                    writeln!(out, "cell {} = {} '{}'", i, cell, cell as char)?;
+                } else {
+                    writeln!(out, "cell {} = {}", i, cell)?;
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+// Offsets of hexdump lines, with a `-` in front of the ones before cell 0
+fn signed_offset(offset: isize) -> String {
+    if offset < 0 { format!("-{:08x}", -offset) } else { format!(" {:08x}", offset) }
+}
+
+#[cfg(test)]
+fn tape_to_string(tape: &[u8], format: TapeFormat, full: bool) -> String {
+    let mut out = Vec::new();
+    write_tape(tape, format, full, &mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn test_used_len() {
+    assert_eq!(used_len(&[]), 0);
+    assert_eq!(used_len(&[0, 0]), 0);
+    assert_eq!(used_len(&[0, 7, 0, 1, 0, 0]), 4);
+}
+
+#[test]
+fn test_write_tape() {
+    let mut tape = vec![0; 100];
+    tape[..3].copy_from_slice(b"Hi!");
+    tape[14] = 10;
+
+    assert_eq!(
+        tape_to_string(&tape, TapeFormat::Hex, false),
+        "00000000  48 69 21 00 00 00 00 00  00 00 00 00 00 00 0a     |Hi!............|\n\
+         0000000f\n"
+    );
+    assert!(tape_to_string(&tape, TapeFormat::Hex, true).ends_with("00000064\n"));
+
+    let mut raw = Vec::new();
+    write_tape(&tape, TapeFormat::Raw, false, &mut raw).unwrap();
+    assert_eq!(raw, &tape[..15]);
+
+    assert_eq!(
+        tape_to_string(&tape, TapeFormat::NonZero, false),
+        "cell 0 = 72 'H'\ncell 1 = 105 'i'\ncell 2 = 33 '!'\ncell 14 = 10\n"
+    );
+    assert_eq!(tape_to_string(&[0; 8], TapeFormat::NonZero, true), "");
+}
+
+#[test]
+fn test_write_tape_from() {
+    let mut tape = vec![0; 64];
+    tape[20] = b'A';
+    tape[40] = 1;
+    let dump = |format, full| {
+        let mut out = Vec::new();
+        write_tape_from(&tape, -32, format, full, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    };
+
+    assert_eq!(dump(TapeFormat::NonZero, false), "cell -12 = 65 'A'\ncell 8 = 1\n");
+    assert_eq!(
+        dump(TapeFormat::Hex, false),
+        "-0000000c  41 00 00 00 00 00 00 00  00 00 00 00 00 00 00 00  |A...............|\n \
+         00000004  00 00 00 00 01                                    |.....|\n \
+         00000009\n"
+    );
+    assert!(dump(TapeFormat::Hex, true).starts_with("-00000020  00"));
+    assert!(dump(TapeFormat::Hex, true).ends_with(" 00000020\n"));
+}
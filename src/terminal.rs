@@ -0,0 +1,302 @@
+use std::cell::UnsafeCell;
+use std::io;
+use std::mem;
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+
+use libc;
+
+
+// Signals that end the process while the terminal is in raw mode or its
+// cursor hidden, the terminal is restored before they take effect
+const SIGNALS: [libc::c_int; 3] = [libc::SIGINT, libc::SIGTERM, libc::SIGHUP];
+
+// The settings to restore from a signal handler, which can't get at the
+// guard. Written before `ACTIVE` is set and only read while it is.
+struct Saved(UnsafeCell<Option<(RawFd, libc::termios)>>);
+
+unsafe impl Sync for Saved {}
+
+static SAVED: Saved = Saved(UnsafeCell::new(None));
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+// The terminal a `HiddenCursor` hid the cursor of, -1 for none, and
+// whether it's on the alternate screen
+static CURSOR: AtomicI32 = AtomicI32::new(-1);
+static ALTERNATE: AtomicBool = AtomicBool::new(false);
+
+const HIDE_CURSOR: &[u8] = b"\x1b[?25l";
+const SHOW_CURSOR: &[u8] = b"\x1b[?25h";
+const ENTER_ALTERNATE: &[u8] = b"\x1b[?1049h";
+const LEAVE_ALTERNATE: &[u8] = b"\x1b[?1049l";
+
+// Keeps a terminal in non-canonical, no-echo mode, so that every keypress
+// is readable right away, and restores the original settings when dropped,
+// also while unwinding from a panic. Ctrl-C and friends still raise their
+// signals, which restore the settings before ending the process.
+pub struct RawInput {
+    fd: RawFd,
+    original: libc::termios,
+    handlers: Vec<(libc::c_int, libc::sigaction)>,
+}
+
+extern "C" fn restore_and_reraise(signal: libc::c_int) {
+    // only async-signal-safe calls in here
+    unsafe {
+        if ACTIVE.swap(false, Ordering::SeqCst) {
+            if let Some((fd, ref original)) = *SAVED.0.get() {
+                libc::tcsetattr(fd, libc::TCSANOW, original);
+            }
+        }
+        let fd = CURSOR.swap(-1, Ordering::SeqCst);
+        if fd >= 0 {
+            libc::write(fd, SHOW_CURSOR.as_ptr() as *const libc::c_void, SHOW_CURSOR.len());
+            if ALTERNATE.swap(false, Ordering::SeqCst) {
+                libc::write(fd, LEAVE_ALTERNATE.as_ptr() as *const libc::c_void, LEAVE_ALTERNATE.len());
+            }
+        }
+        libc::signal(signal, libc::SIG_DFL);
+        libc::raise(signal);
+    }
+}
+
+impl RawInput {
+    // Switches `fd` to raw input, `None` if it isn't a terminal. Only one
+    // terminal can be in raw mode at a time.
+    pub fn enable(fd: RawFd) -> io::Result<Option<RawInput>> {
+        if unsafe { libc::isatty(fd) } != 1 {
+            return Ok(None);
+        }
+
+        let mut original: libc::termios = unsafe { mem::zeroed() };
+        if unsafe { libc::tcgetattr(fd, &mut original) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if ACTIVE.swap(true, Ordering::SeqCst) {
+            return Err(io::Error::other("a terminal is already in raw mode"));
+        }
+        unsafe {
+            *SAVED.0.get() = Some((fd, original));
+        }
+
+        // from here on dropping the guard undoes everything
+        let guard = RawInput { fd, original, handlers: install_handlers() };
+
+        let mut raw = original;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Some(guard))
+    }
+}
+
+impl Drop for RawInput {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetattr(self.fd, libc::TCSANOW, &self.original);
+            ACTIVE.store(false, Ordering::SeqCst);
+        }
+        restore_handlers(&self.handlers);
+    }
+}
+
+// Keeps the cursor of a terminal hidden, for drawing on it, and shows it
+// again when dropped or when a signal ends the process. From `full_screen`
+// the drawing also happens on the alternate screen, which is left again
+// the same way, with the terminal as it was before.
+pub struct HiddenCursor {
+    fd: RawFd,
+    alternate: bool,
+    handlers: Vec<(libc::c_int, libc::sigaction)>,
+}
+
+impl HiddenCursor {
+    // Hides the cursor of `fd`, `None` if it isn't a terminal. Whatever was
+    // buffered for `fd` has to be flushed before either this or the drop.
+    pub fn hide(fd: RawFd) -> io::Result<Option<HiddenCursor>> {
+        HiddenCursor::new(fd, false)
+    }
+
+    // Like `hide`, but also switches to the alternate screen
+    pub fn full_screen(fd: RawFd) -> io::Result<Option<HiddenCursor>> {
+        HiddenCursor::new(fd, true)
+    }
+
+    fn new(fd: RawFd, alternate: bool) -> io::Result<Option<HiddenCursor>> {
+        if unsafe { libc::isatty(fd) } != 1 {
+            return Ok(None);
+        }
+        if CURSOR.compare_exchange(-1, fd, Ordering::SeqCst, Ordering::SeqCst).is_err() {
+            return Err(io::Error::other("a cursor is already hidden"));
+        }
+        ALTERNATE.store(alternate, Ordering::SeqCst);
+        let guard = HiddenCursor { fd, alternate, handlers: install_handlers() };
+        if alternate {
+            write_all(fd, ENTER_ALTERNATE)?;
+        }
+        write_all(fd, HIDE_CURSOR)?;
+
+        Ok(Some(guard))
+    }
+}
+
+impl Drop for HiddenCursor {
+    fn drop(&mut self) {
+        let _ = write_all(self.fd, SHOW_CURSOR);
+        if self.alternate {
+            let _ = write_all(self.fd, LEAVE_ALTERNATE);
+        }
+        ALTERNATE.store(false, Ordering::SeqCst);
+        CURSOR.store(-1, Ordering::SeqCst);
+        restore_handlers(&self.handlers);
+    }
+}
+
+// Columns and rows of the terminal `fd`, `None` if it isn't one
+pub fn size(fd: RawFd) -> Option<(usize, usize)> {
+    let mut size: libc::winsize = unsafe { mem::zeroed() };
+    if unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) } != 0 || size.ws_col == 0 || size.ws_row == 0 {
+        return None;
+    }
+
+    Some((size.ws_col as usize, size.ws_row as usize))
+}
+
+fn write_all(fd: RawFd, mut bytes: &[u8]) -> io::Result<()> {
+    while !bytes.is_empty() {
+        let n = unsafe { libc::write(fd, bytes.as_ptr() as *const libc::c_void, bytes.len()) };
+        if n < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+            continue;
+        }
+        bytes = &bytes[n as usize..];
+    }
+
+    Ok(())
+}
+
+// Points `SIGNALS` at `restore_and_reraise`, returns the handlers they had
+fn install_handlers() -> Vec<(libc::c_int, libc::sigaction)> {
+    let mut handlers = Vec::new();
+    for &signal in &SIGNALS {
+        unsafe {
+            let mut action: libc::sigaction = mem::zeroed();
+            action.sa_sigaction = restore_and_reraise as *const () as libc::sighandler_t;
+            libc::sigemptyset(&mut action.sa_mask);
+            let mut previous: libc::sigaction = mem::zeroed();
+            if libc::sigaction(signal, &action, &mut previous) == 0 {
+                handlers.push((signal, previous));
+            }
+        }
+    }
+
+    handlers
+}
+
+fn restore_handlers(handlers: &[(libc::c_int, libc::sigaction)]) {
+    for &(signal, ref previous) in handlers {
+        unsafe {
+            libc::sigaction(signal, previous, std::ptr::null_mut());
+        }
+    }
+}
+
+
+// The two ends of a fresh pseudo terminal, to test on without a real one
+#[cfg(test)]
+fn open_pty() -> (RawFd, RawFd) {
+    use std::ffi::CStr;
+
+    unsafe {
+        let master = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+        assert!(master >= 0, "{}", io::Error::last_os_error());
+        assert_eq!(libc::grantpt(master), 0);
+        assert_eq!(libc::unlockpt(master), 0);
+        let mut name = [0 as libc::c_char; 128];
+        assert_eq!(libc::ptsname_r(master, name.as_mut_ptr(), name.len()), 0);
+        let slave = libc::open(CStr::from_ptr(name.as_ptr()).as_ptr(), libc::O_RDWR | libc::O_NOCTTY);
+        assert!(slave >= 0, "{}", io::Error::last_os_error());
+        (master, slave)
+    }
+}
+
+#[cfg(test)]
+fn local_flags(fd: RawFd) -> libc::tcflag_t {
+    let mut settings: libc::termios = unsafe { mem::zeroed() };
+    assert_eq!(unsafe { libc::tcgetattr(fd, &mut settings) }, 0);
+    settings.c_lflag
+}
+
+#[test]
+fn test_raw_input_restores() {
+    let (master, slave) = open_pty();
+    let before = local_flags(slave);
+    assert_ne!(before & libc::ICANON, 0);
+
+    let guard = RawInput::enable(slave).unwrap().unwrap();
+    assert_eq!(local_flags(slave) & (libc::ICANON | libc::ECHO), 0);
+    assert!(RawInput::enable(slave).is_err());
+    drop(guard);
+    assert_eq!(local_flags(slave), before);
+
+    // also when unwinding
+    let result = std::panic::catch_unwind(|| {
+        let _guard = RawInput::enable(slave).unwrap().unwrap();
+        panic!("runtime error");
+    });
+    assert!(result.is_err());
+    assert_eq!(local_flags(slave), before);
+
+    // anything else is left alone
+    let mut pipe = [0; 2];
+    assert_eq!(unsafe { libc::pipe(pipe.as_mut_ptr()) }, 0);
+    assert!(RawInput::enable(pipe[0]).unwrap().is_none());
+
+    for &fd in &[master, slave, pipe[0], pipe[1]] {
+        unsafe { libc::close(fd) };
+    }
+}
+
+#[test]
+fn test_hidden_cursor_restores() {
+    let (master, slave) = open_pty();
+
+    let guard = HiddenCursor::hide(slave).unwrap().unwrap();
+    assert!(HiddenCursor::hide(slave).is_err());
+    drop(guard);
+    let mut written = Vec::new();
+    while written.len() < HIDE_CURSOR.len() + SHOW_CURSOR.len() {
+        let mut buf = [0; 64];
+        let n = unsafe { libc::read(master, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        assert!(n > 0, "{}", io::Error::last_os_error());
+        written.extend_from_slice(&buf[..n as usize]);
+    }
+    assert_eq!(written, [HIDE_CURSOR, SHOW_CURSOR].concat());
+
+    let guard = HiddenCursor::full_screen(slave).unwrap().unwrap();
+    drop(guard);
+    let expected = [ENTER_ALTERNATE, HIDE_CURSOR, SHOW_CURSOR, LEAVE_ALTERNATE].concat();
+    let mut written = Vec::new();
+    while written.len() < expected.len() {
+        let mut buf = [0; 64];
+        let n = unsafe { libc::read(master, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        assert!(n > 0, "{}", io::Error::last_os_error());
+        written.extend_from_slice(&buf[..n as usize]);
+    }
+    assert_eq!(written, expected);
+
+    // anything else is left alone
+    let mut pipe = [0; 2];
+    assert_eq!(unsafe { libc::pipe(pipe.as_mut_ptr()) }, 0);
+    assert!(HiddenCursor::hide(pipe[1]).unwrap().is_none());
+
+    for &fd in &[master, slave, pipe[0], pipe[1]] {
+        unsafe { libc::close(fd) };
+    }
+}
@@ -0,0 +1,215 @@
+// Every step of a run in the interpreter as JSON Lines, for `--trace-json`,
+// for tools drawing a run their own way. A header object comes first, then
+// an object for every step, or every `sample`th one, and an object for the
+// error if the run failed:
+//
+//     {"trace":1,"program":"5f0c6e3a9d1b2c47","tape_size":30000,"pointer_start":0,"arith":"wrap","sample":1}
+//     {"step":0,"index":0,"op":"ReadChar","ptr":0,"cell":104,"input":104}
+//     {"step":1,"index":1,"op":"IncVal(1)","ptr":0,"cell":105}
+//     {"step":2,"index":2,"op":"PrintCell","ptr":0,"cell":105,"output":105}
+//     {"error":"pointer-underflow","message":"pointer moved below the start of the tape at instruction 3"}
+//
+// `trace` is the version of this schema, `program` the program's
+// `Brainfuck::fingerprint` in hex. `step` counts the steps before the one
+// described, `index` is its instruction as in `--dump` and `op` that
+// instruction. `ptr` is the cell it executed on and `cell` the value that
+// cell was left with. `output` is the byte a `.` printed, `input` the byte a
+// `,` read, neither is there otherwise, nor `input` at the end of the input.
+// `error` is the code of the error as in `--diagnostics json`. Fields may be
+// added to objects, any other change comes with a new version.
+//
+// A path ending in `.gz` gets the trace gzipped, a trace of a long run is
+// large.
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use brainfuck::{ArithMode, Brainfuck, RuntimeError};
+use diagnostics::{json_string, ErrorCode};
+
+
+pub const VERSION: u32 = 1;
+
+// Where a trace goes
+pub enum TraceWriter {
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+}
+
+impl TraceWriter {
+    // Creates the file at `path`, gzipped if it ends in `.gz`
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<TraceWriter> {
+        let path = path.as_ref();
+        let file = BufWriter::new(File::create(path)?);
+        Ok(match path.extension() {
+            Some(extension) if extension == "gz" => TraceWriter::Gzip(GzEncoder::new(file, Compression::default())),
+            _ => TraceWriter::Plain(file),
+        })
+    }
+
+    // Writes out what's buffered and, gzipped, the end of the stream
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            TraceWriter::Plain(mut file) => file.flush(),
+            TraceWriter::Gzip(gzip) => gzip.finish()?.flush(),
+        }
+    }
+}
+
+impl Write for TraceWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            TraceWriter::Plain(ref mut file) => file.write(buf),
+            TraceWriter::Gzip(ref mut gzip) => gzip.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            TraceWriter::Plain(ref mut file) => file.flush(),
+            TraceWriter::Gzip(ref mut gzip) => gzip.flush(),
+        }
+    }
+}
+
+fn arith_name(arith: ArithMode) -> &'static str {
+    match arith {
+        ArithMode::Wrap => "wrap",
+        ArithMode::Saturate => "saturate",
+        ArithMode::Trap => "trap",
+        #[cfg(feature = "bignum")]
+        ArithMode::Unbounded(_) => "unbounded",
+    }
+}
+
+// Runs `bf` in the interpreter on `input`, writing what it prints to
+// `output` and every `sample`th step to `trace`, and returns the tape it
+// left behind with how the run went. Fails if writing either fails.
+pub fn run<R: Read, W: Write, T: Write>(
+    bf: &Brainfuck, input: R, mut output: W, sample: u64, mut trace: T
+) -> io::Result<(Vec<u8>, Result<(), RuntimeError>)> {
+    writeln!(
+        trace, "{{\"trace\":{},\"program\":\"{:016x}\",\"tape_size\":{},\"pointer_start\":{},\"arith\":\"{}\",\"sample\":{}}}",
+        VERSION, bf.fingerprint(), bf.tape_size(), bf.pointer_start(), arith_name(bf.arith_mode()), sample
+    )?;
+
+    let mut steps = bf.steps(input);
+    let mut result = Ok(());
+    for (count, step) in (&mut steps).enumerate() {
+        let step = match step {
+            Ok(step) => step,
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        };
+        if let Some(byte) = step.output {
+            output.write_all(&[byte])?;
+        }
+        if (count as u64).is_multiple_of(sample) {
+            write!(
+                trace, "{{\"step\":{},\"index\":{},\"op\":{},\"ptr\":{},\"cell\":{}",
+                count, step.index, json_string(&format!("{:?}", step.inst)), step.ptr, step.cell_after
+            )?;
+            if let Some(byte) = step.output {
+                write!(trace, ",\"output\":{}", byte)?;
+            }
+            if let Some(byte) = step.input {
+                write!(trace, ",\"input\":{}", byte)?;
+            }
+            writeln!(trace, "}}")?;
+        }
+    }
+    if let Err(ref e) = result {
+        writeln!(trace, "{{\"error\":{},\"message\":{}}}", json_string(e.code()), json_string(&e.to_string()))?;
+    }
+    output.flush()?;
+
+    Ok((steps.interp().tape().to_vec(), result))
+}
+
+
+// The value of `field` in a line of a trace, as it's written there
+#[cfg(test)]
+fn field<'a>(line: &'a str, field: &str) -> Option<&'a str> {
+    let start = line.find(&format!("\"{}\":", field))? + field.len() + 3;
+    let rest = &line[start..];
+    Some(&rest[..rest.find([',', '}']).unwrap()])
+}
+
+// Checks a trace of `source` run on `input` against its steps
+#[cfg(test)]
+fn replay(source: &str, input: &[u8], sample: u64, trace: &str) {
+    let bf = Brainfuck::new(source).unwrap();
+    let mut lines = trace.lines();
+    let header = lines.next().unwrap();
+    assert_eq!(field(header, "trace"), Some("1"));
+    assert_eq!(field(header, "program"), Some(&*format!("\"{:016x}\"", bf.fingerprint())));
+    assert_eq!(field(header, "sample"), Some(&*sample.to_string()));
+
+    let mut steps: Vec<_> = bf.steps(input).collect();
+    let error = match steps.last() {
+        Some(Err(_)) => steps.pop().unwrap().err(),
+        _ => None,
+    };
+    let mut expected = steps.iter().map(|step| step.as_ref().unwrap()).enumerate().step_by(sample as usize);
+    for line in lines {
+        if let Some(code) = field(line, "error") {
+            assert_eq!(Some(code), error.as_ref().map(|e| json_string(e.code())).as_deref());
+            continue;
+        }
+        let (number, step) = expected.next().unwrap_or_else(|| panic!("more steps traced than taken: {}", line));
+        assert_eq!(field(line, "step"), Some(&*number.to_string()));
+        assert_eq!(field(line, "index"), Some(&*step.index.to_string()));
+        assert_eq!(field(line, "op"), Some(&*json_string(&format!("{:?}", step.inst))));
+        assert_eq!(field(line, "ptr"), Some(&*step.ptr.to_string()));
+        assert_eq!(field(line, "cell"), Some(&*step.cell_after.to_string()));
+        assert_eq!(field(line, "output"), step.output.map(|byte| byte.to_string()).as_deref());
+        assert_eq!(field(line, "input"), step.input.map(|byte| byte.to_string()).as_deref());
+    }
+    assert!(expected.next().is_none(), "fewer steps traced than taken");
+}
+
+#[test]
+fn test_trace() {
+    let rot13 = include_str!("../tests/fixtures/rot13.b");
+    for &sample in &[1, 7] {
+        let (mut output, mut trace) = (Vec::new(), Vec::new());
+        let (_, result) = run(&Brainfuck::new(rot13).unwrap(), &b"Hi"[..], &mut output, sample, &mut trace).unwrap();
+        result.unwrap();
+        assert_eq!(output, b"Uv");
+        replay(rot13, b"Hi", sample, &String::from_utf8(trace).unwrap());
+    }
+
+    // the error ends the trace, the tape is left as it failed
+    let (mut output, mut trace) = (Vec::new(), Vec::new());
+    let (tape, result) = run(&Brainfuck::new("+.<").unwrap(), io::empty(), &mut output, 1, &mut trace).unwrap();
+    assert!(matches!(result, Err(RuntimeError::PointerUnderflow { inst_index: 2 })));
+    assert_eq!((output, tape[0]), (vec![1], 1));
+    let trace = String::from_utf8(trace).unwrap();
+    replay("+.<", b"", 1, &trace);
+    assert!(trace.ends_with("{\"error\":\"pointer-underflow\",\"message\":\"pointer moved below the start \
+                             of the tape at instruction 2\"}\n"), "{}", trace);
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_trace_gzip() {
+    use flate2::read::GzDecoder;
+
+    let rot13 = include_str!("../tests/fixtures/rot13.b");
+    let path = std::env::temp_dir().join(format!("brainfuck-trace-{}.jsonl.gz", std::process::id()));
+    let mut trace = TraceWriter::create(&path).unwrap();
+    run(&Brainfuck::new(rot13).unwrap(), &b"abc"[..], io::sink(), 1, &mut trace).unwrap().1.unwrap();
+    trace.finish().unwrap();
+
+    let compressed = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(compressed[..2], [0x1f, 0x8b]);
+    let mut text = String::new();
+    GzDecoder::new(&compressed[..]).read_to_string(&mut text).unwrap();
+    replay(rot13, b"abc", 1, &text);
+}
@@ -0,0 +1,626 @@
+// A full-screen debugger for `brainfuck-jit debug`, on top of `Debugger`:
+// the source with the next instruction highlighted, what the program
+// printed, the cells around the pointer and a command line. Everything is
+// drawn into a `Screen`, a grid of characters written to the terminal as a
+// whole, so what ends up where can be tested without a terminal.
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::ops::Range;
+
+use brainfuck::{Inst, Span};
+use debugger::{Debugger, Event};
+
+
+// Below this the panes don't fit
+pub const MIN_WIDTH: usize = 40;
+pub const MIN_HEIGHT: usize = 12;
+// The title, the cells' numbers, values and characters
+const TAPE_HEIGHT: usize = 4;
+const CELL_WIDTH: usize = 5;
+// The line numbers in front of the source
+const GUTTER: usize = 5;
+
+const KEYS: &str = "s step  c continue  : command  q quit";
+const COMMANDS: &str = "step [N], continue, break OFFSET [if CONDITION], clear, watch CELL[..END], \
+                        rwatch CELL[..END], unwatch, set CELL VALUE, ptr CELL, input TEXT, eof, quit";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    Normal,
+    Reverse,
+    Underline,
+}
+
+// What's on the terminal, row by row
+pub struct Screen {
+    width: usize,
+    height: usize,
+    cells: Vec<(char, Style)>,
+}
+
+impl Screen {
+    pub fn new(width: usize, height: usize) -> Screen {
+        Screen { width, height, cells: vec![(' ', Style::Normal); width * height] }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    // Writes `text` from column `x` of row `y` on, cut off at the edge
+    pub fn put(&mut self, x: usize, y: usize, text: &str, style: Style) {
+        if y >= self.height {
+            return;
+        }
+        for (i, c) in text.chars().enumerate().take(self.width.saturating_sub(x)) {
+            self.cells[y * self.width + x + i] = (c, style);
+        }
+    }
+
+    // Row `y` as text, without the spaces at its end
+    pub fn line(&self, y: usize) -> String {
+        let row: String = self.cells[y * self.width..(y + 1) * self.width].iter().map(|&(c, _)| c).collect();
+        row.trim_end().to_string()
+    }
+
+    pub fn style(&self, x: usize, y: usize) -> Style {
+        self.cells[y * self.width + x].1
+    }
+
+    // Draws the screen over the whole terminal
+    pub fn write_ansi<W: Write>(&self, mut out: W) -> io::Result<()> {
+        for y in 0..self.height {
+            write!(out, "\x1b[{};1H", y + 1)?;
+            let mut current = Style::Normal;
+            for &(c, style) in &self.cells[y * self.width..(y + 1) * self.width] {
+                if style != current {
+                    out.write_all(match style {
+                        Style::Normal => b"\x1b[0m",
+                        Style::Reverse => b"\x1b[0;7m",
+                        Style::Underline => b"\x1b[0;4m",
+                    })?;
+                    current = style;
+                }
+                write!(out, "{}", c)?;
+            }
+            if current != Style::Normal {
+                out.write_all(b"\x1b[0m")?;
+            }
+        }
+
+        out.flush()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+// Where the panes go, every one of them starting with a title row except
+// the status and command lines
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    pub source: Rect,
+    pub output: Rect,
+    pub tape: Rect,
+    pub status: Rect,
+    pub command: Rect,
+}
+
+// The source on the left and the output on the right, with a column in
+// between, the tape under them and the two lines at the bottom. `None` if
+// the screen is too small.
+pub fn layout(width: usize, height: usize) -> Option<Layout> {
+    if width < MIN_WIDTH || height < MIN_HEIGHT {
+        return None;
+    }
+    let top = height - TAPE_HEIGHT - 2;
+    let output_width = width / 3;
+
+    Some(Layout {
+        source: Rect { x: 0, y: 0, width: width - output_width - 1, height: top },
+        output: Rect { x: width - output_width, y: 0, width: output_width, height: top },
+        tape: Rect { x: 0, y: top, width, height: TAPE_HEIGHT },
+        status: Rect { x: 0, y: height - 2, width, height: 1 },
+        command: Rect { x: 0, y: height - 1, width, height: 1 },
+    })
+}
+
+// The program's input as typed in so far, reading more waits for it
+#[derive(Default)]
+struct PendingInput {
+    bytes: VecDeque<u8>,
+    // whether reading past the end is the end of input
+    closed: bool,
+}
+
+impl Read for PendingInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.bytes.is_empty() {
+            return if self.closed { Ok(0) } else { Err(io::ErrorKind::WouldBlock.into()) };
+        }
+        let n = buf.len().min(self.bytes.len());
+        for (slot, byte) in buf.iter_mut().zip(self.bytes.drain(..n)) {
+            *slot = byte;
+        }
+
+        Ok(n)
+    }
+}
+
+// A program being debugged and what's shown of it
+pub struct Session<'a> {
+    debugger: Debugger<'a>,
+    insts: &'a [Inst],
+    spans: &'a [Span],
+    code: &'a str,
+    input: PendingInput,
+    output: Vec<u8>,
+    // only for showing them, the debugger has its own
+    breakpoints: Vec<usize>,
+    watches: Vec<Range<usize>>,
+    // what the last command did
+    message: String,
+    // the command being typed, `None` while keys are commands themselves
+    command: Option<String>,
+}
+
+impl<'a> Session<'a> {
+    // `spans` as returned by `parse_with_spans` for `insts` from `code`
+    pub fn new(insts: &'a [Inst], spans: &'a [Span], code: &'a str, tape_size: usize) -> Session<'a> {
+        Session {
+            debugger: Debugger::new(insts, spans, tape_size),
+            insts,
+            spans,
+            code,
+            input: PendingInput::default(),
+            output: Vec::new(),
+            breakpoints: Vec::new(),
+            watches: Vec::new(),
+            message: String::new(),
+            command: None,
+        }
+    }
+
+    // Adds `bytes` to the program's input
+    pub fn add_input(&mut self, bytes: &[u8]) {
+        self.input.bytes.extend(bytes);
+    }
+
+    // Reads after the input so far see its end instead of waiting for more
+    pub fn close_input(&mut self) {
+        self.input.closed = true;
+    }
+
+    pub fn debugger(&self) -> &Debugger<'a> {
+        &self.debugger
+    }
+
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    // Handles a key, false once the session is over
+    pub fn key(&mut self, key: u8) -> bool {
+        match self.command.take() {
+            Some(mut line) => {
+                match key {
+                    b'\r' | b'\n' => return self.execute(&line),
+                    // escape
+                    0x1b => {}
+                    // backspace
+                    0x7f | 0x08 => {
+                        line.pop();
+                        self.command = Some(line);
+                    }
+                    0x20..=0x7e => {
+                        line.push(key as char);
+                        self.command = Some(line);
+                    }
+                    _ => self.command = Some(line),
+                }
+                true
+            }
+            None => match key {
+                b's' => self.execute("step"),
+                b'c' => self.execute("continue"),
+                b'q' => false,
+                b':' => {
+                    self.command = Some(String::new());
+                    true
+                }
+                _ => true,
+            },
+        }
+    }
+
+    // Runs a line of the command line, false for `quit`
+    pub fn execute(&mut self, line: &str) -> bool {
+        let line = line.trim();
+        if line == "q" || line == "quit" {
+            return false;
+        }
+        self.message = self.command(line).unwrap_or_else(|e| format!("error: {}", e));
+
+        true
+    }
+
+    fn command(&mut self, line: &str) -> Result<String, String> {
+        let (name, args) = match line.find(' ') {
+            Some(i) => (&line[..i], line[i + 1..].trim()),
+            None => (line, ""),
+        };
+
+        match name {
+            "s" | "step" => {
+                let count = if args.is_empty() { 1 } else { number(args)? };
+                let mut event = Event::Stepped;
+                for _ in 0..count {
+                    event = self.debugger.step(&mut self.input, &mut self.output);
+                    if !matches!(event, Event::Stepped) {
+                        break;
+                    }
+                }
+                Ok(self.describe(event))
+            }
+            "c" | "continue" => {
+                let event = self.debugger.cont(&mut self.input, &mut self.output);
+                Ok(self.describe(event))
+            }
+            "b" | "break" => {
+                let (offset, condition) = match args.find(" if ") {
+                    Some(i) => (&args[..i], Some(args[i + 4..].trim())),
+                    None => (args, None),
+                };
+                let offset = number(offset)?;
+                let inst_index = self.spans.iter().position(|span| span.end > offset)
+                    .ok_or_else(|| format!("no instruction at or after offset {}", offset))?;
+                match condition {
+                    Some(condition) => self.debugger.break_when(inst_index, condition).map_err(|e| e.to_string())?,
+                    None => self.debugger.break_at(inst_index),
+                }
+                self.breakpoints.push(inst_index);
+                Ok(format!("breakpoint at offset {}, instruction {}", self.spans[inst_index].start, inst_index))
+            }
+            "clear" => {
+                self.debugger.clear_breakpoints();
+                self.breakpoints.clear();
+                Ok("no breakpoints".to_string())
+            }
+            "w" | "watch" | "rwatch" => {
+                let cells = self.cells(args)?;
+                if name == "rwatch" {
+                    self.debugger.watch_reads(cells.clone());
+                } else {
+                    self.debugger.watch_range(cells.clone());
+                }
+                self.watches.push(cells.clone());
+                Ok(format!("watching cells {}..{}", cells.start, cells.end))
+            }
+            "unwatch" => {
+                self.debugger.clear_watches();
+                self.watches.clear();
+                Ok("no watchpoints".to_string())
+            }
+            "set" => {
+                let mut args = args.split_whitespace();
+                let (cell, value) = match (args.next(), args.next(), args.next()) {
+                    (Some(cell), Some(value), None) => (number(cell)?, value),
+                    _ => return Err("usage: set CELL VALUE".to_string()),
+                };
+                let value = value.parse::<u8>().map_err(|_| format!("invalid cell value '{}'", value))?;
+                let tape = self.debugger.interp_mut().tape_mut();
+                let len = tape.len();
+                *tape.get_mut(cell).ok_or_else(|| format!("cell {} outside of the tape of {} cells", cell, len))? = value;
+                Ok(format!("cell {} set to {}", cell, value))
+            }
+            "ptr" => {
+                let cell = number(args)?;
+                let len = self.debugger.interp().tape().len();
+                if cell >= len {
+                    return Err(format!("cell {} outside of the tape of {} cells", cell, len));
+                }
+                self.debugger.interp_mut().set_ptr(cell);
+                Ok(format!("pointer at cell {}", cell))
+            }
+            "input" => {
+                self.add_input(args.as_bytes());
+                self.add_input(b"\n");
+                Ok(format!("{} bytes of input waiting", self.input.bytes.len()))
+            }
+            "eof" => {
+                self.close_input();
+                Ok("input ends after what's waiting".to_string())
+            }
+            "help" | "" => Ok(COMMANDS.to_string()),
+            _ => Err(format!("unknown command '{}', try {}", name, COMMANDS)),
+        }
+    }
+
+    // A range of cells like `3` or `3..5`, on the tape
+    fn cells(&self, arg: &str) -> Result<Range<usize>, String> {
+        let cells = match arg.find("..") {
+            Some(i) => number(&arg[..i])?..number(&arg[i + 2..])?,
+            None => number(arg).map(|cell| cell..cell + 1)?,
+        };
+        let len = self.debugger.interp().tape().len();
+        if cells.start >= cells.end || cells.end > len {
+            return Err(format!("no cells {}..{} on the tape of {} cells", cells.start, cells.end, len));
+        }
+
+        Ok(cells)
+    }
+
+    fn describe(&self, event: Event) -> String {
+        match event {
+            Event::Stepped => format!("stepped to instruction {}", self.debugger.interp().pc()),
+            Event::Breakpoint { inst_index } => {
+                format!("breakpoint at offset {}, instruction {}", self.spans[inst_index].start, inst_index)
+            }
+            Event::Watchpoint(hit) if hit.old == hit.new => {
+                format!("cell {} read at offset {}", hit.cell, hit.span.start)
+            }
+            Event::Watchpoint(hit) => {
+                format!("cell {} went from {} to {} at offset {}", hit.cell, hit.old, hit.new, hit.span.start)
+            }
+            Event::Finished => "finished".to_string(),
+            Event::NeedsInput => "waiting for input, type it with :input TEXT or end it with :eof".to_string(),
+            Event::Error(e) => format!("error: {}", e),
+            Event::StartOfHistory => "at the start of the history".to_string(),
+        }
+    }
+
+    // Draws everything onto `screen`
+    pub fn draw(&self, screen: &mut Screen) {
+        let layout = match layout(screen.width(), screen.height()) {
+            Some(layout) => layout,
+            None => {
+                screen.put(0, 0, "terminal too small", Style::Normal);
+                return;
+            }
+        };
+
+        self.draw_source(screen, layout.source);
+        for y in layout.source.y..layout.source.y + layout.source.height {
+            screen.put(layout.source.width, y, "│", Style::Normal);
+        }
+        self.draw_output(screen, layout.output);
+        self.draw_tape(screen, layout.tape);
+        screen.put(layout.status.x, layout.status.y, &self.message, Style::Normal);
+        match self.command {
+            Some(ref line) => {
+                screen.put(layout.command.x, layout.command.y, &format!(":{}", line), Style::Normal);
+                screen.put(layout.command.x + 1 + line.chars().count(), layout.command.y, " ", Style::Reverse);
+            }
+            None => screen.put(layout.command.x, layout.command.y, KEYS, Style::Underline),
+        }
+    }
+
+    fn draw_source(&self, screen: &mut Screen, rect: Rect) {
+        let pc = self.debugger.interp().pc();
+        let current = self.spans.get(pc).filter(|_| pc < self.insts.len()).cloned();
+        let title = match current {
+            Some(span) => format!(" source, next #{} at offset {}", pc, span.start),
+            None => " source, finished".to_string(),
+        };
+        screen.put(rect.x, rect.y, &format!("{:<1$}", title, rect.width), Style::Reverse);
+
+        // the line of the next instruction in the middle, if it can be
+        let lines: Vec<(usize, &str)> = self.code.split('\n')
+            .scan(0, |start, line| {
+                let offset = *start;
+                *start += line.len() + 1;
+                Some((offset, line))
+            })
+            .collect();
+        let current_line = current.map_or(0, |span| self.code[..span.start].matches('\n').count());
+        let rows = rect.height - 1;
+        let first = current_line.saturating_sub(rows / 2).min(lines.len().saturating_sub(rows));
+        // and its column in view
+        let text_width = rect.width.saturating_sub(GUTTER);
+        let shift = current.map_or(0, |span| {
+            let (start, line) = lines[current_line];
+            let column = line[..span.start - start].chars().count();
+            if column < text_width { 0 } else { column - text_width * 2 / 3 }
+        });
+
+        for (row, &(start, line)) in lines[first..].iter().take(rows).enumerate() {
+            let y = rect.y + 1 + row;
+            screen.put(rect.x, y, &format!("{:>1$} ", first + row + 1, GUTTER - 1), Style::Normal);
+            for (x, (i, c)) in line.char_indices().skip(shift).take(text_width).enumerate() {
+                let offset = start + i;
+                let inside = |span: &Span| span.start <= offset && offset < span.end;
+                let style = if current.as_ref().is_some_and(inside) {
+                    Style::Reverse
+                } else if self.breakpoints.iter().any(|&b| inside(&self.spans[b])) {
+                    Style::Underline
+                } else {
+                    Style::Normal
+                };
+                let c = if c.is_control() { ' ' } else { c };
+                screen.put(rect.x + GUTTER + x, y, &c.to_string(), style);
+            }
+        }
+    }
+
+    fn draw_output(&self, screen: &mut Screen, rect: Rect) {
+        let title = format!(" output, {} bytes", self.output.len());
+        screen.put(rect.x, rect.y, &format!("{:<1$}", title, rect.width), Style::Reverse);
+
+        // the last lines, long ones wrapped
+        let text = String::from_utf8_lossy(&self.output);
+        let mut rows = Vec::new();
+        for line in text.split('\n') {
+            let chars: Vec<char> = line.chars().map(|c| if c.is_control() { ' ' } else { c }).collect();
+            if chars.is_empty() {
+                rows.push(String::new());
+            }
+            rows.extend(chars.chunks(rect.width).map(|chunk| chunk.iter().collect::<String>()));
+        }
+        let skip = rows.len().saturating_sub(rect.height - 1);
+        for (row, line) in rows[skip..].iter().enumerate() {
+            screen.put(rect.x, rect.y + 1 + row, line, Style::Normal);
+        }
+    }
+
+    fn draw_tape(&self, screen: &mut Screen, rect: Rect) {
+        let interp = self.debugger.interp();
+        let (tape, ptr) = (interp.tape(), interp.ptr());
+        let title = format!(" tape, pointer at {}", ptr);
+        screen.put(rect.x, rect.y, &format!("{:<1$}", title, rect.width), Style::Reverse);
+
+        let count = rect.width / CELL_WIDTH;
+        let first = ptr.saturating_sub(count / 2).min(tape.len().saturating_sub(count));
+        for (column, cell) in (first..tape.len()).take(count).enumerate() {
+            let x = rect.x + column * CELL_WIDTH;
+            let value = tape[cell];
+            let style = if cell == ptr {
+                Style::Reverse
+            } else if self.watches.iter().any(|cells| cells.start <= cell && cell < cells.end) {
+                Style::Underline
+            } else {
+                Style::Normal
+            };
+            let c = if value.is_ascii_graphic() { value as char } else { ' ' };
+            screen.put(x, rect.y + 1, &format!("{:>1$}", cell, CELL_WIDTH), Style::Normal);
+            screen.put(x, rect.y + 2, &format!("{:>1$}", value, CELL_WIDTH), style);
+            screen.put(x, rect.y + 3, &format!("{:>1$}", c, CELL_WIDTH), Style::Normal);
+        }
+    }
+}
+
+fn number(arg: &str) -> Result<usize, String> {
+    arg.trim().parse().map_err(|_| format!("invalid number '{}'", arg.trim()))
+}
+
+// Draws `session` on `out` and hands it every key read from `keys`, until
+// it's quit or the keys run out. `size` is asked for the terminal's size
+// before every frame, it may have changed in between.
+pub fn run<R: Read, W: Write>(
+    session: &mut Session, mut keys: R, mut out: W, size: impl Fn() -> (usize, usize)
+) -> io::Result<()> {
+    loop {
+        let (width, height) = size();
+        let mut screen = Screen::new(width, height);
+        session.draw(&mut screen);
+        screen.write_ansi(&mut out)?;
+
+        let mut key = [0];
+        match keys.read(&mut key) {
+            Ok(0) => return Ok(()),
+            Ok(_) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+        if !session.key(key[0]) {
+            return Ok(());
+        }
+    }
+}
+
+
+#[cfg(test)]
+use brainfuck::parse_with_spans;
+
+#[test]
+fn test_layout() {
+    assert_eq!(layout(MIN_WIDTH - 1, 40), None);
+    assert_eq!(layout(80, MIN_HEIGHT - 1), None);
+
+    let layout = layout(90, 30).unwrap();
+    assert_eq!(layout.source, Rect { x: 0, y: 0, width: 59, height: 24 });
+    assert_eq!(layout.output, Rect { x: 60, y: 0, width: 30, height: 24 });
+    assert_eq!(layout.tape, Rect { x: 0, y: 24, width: 90, height: 4 });
+    assert_eq!((layout.status.y, layout.command.y), (28, 29));
+}
+
+#[test]
+fn test_draw() {
+    let code = "prints A\n++++++++[>++++++++<-]>+.\n,.";
+    let (insts, spans) = parse_with_spans(code).unwrap();
+    let mut session = Session::new(&insts, &spans, code, 16);
+
+    let mut screen = Screen::new(60, 14);
+    session.draw(&mut screen);
+    assert_eq!(screen.line(0), format!("{:<39}│ output, 0 bytes", " source, next #0 at offset 9"));
+    assert_eq!(screen.line(2), format!("{:<39}│", "   2 ++++++++[>++++++++<-]>+."));
+    // the next instruction highlighted
+    assert_eq!((screen.style(4, 2), screen.style(5, 2), screen.style(12, 2)), (Style::Normal, Style::Reverse, Style::Reverse));
+    assert_eq!(screen.style(13, 2), Style::Normal);
+    assert_eq!(screen.line(8), " tape, pointer at 0");
+    assert_eq!(screen.line(9), "    0    1    2    3    4    5    6    7    8    9   10   11");
+    assert_eq!(screen.style(4, 10), Style::Reverse);
+    assert_eq!(screen.line(13), KEYS);
+
+    // a breakpoint on the `.`, and a watch on cell 1
+    assert!(session.execute("break 32"));
+    assert_eq!(session.message(), "breakpoint at offset 32, instruction 9");
+    let mut screen = Screen::new(60, 14);
+    session.draw(&mut screen);
+    assert_eq!(screen.style(28, 2), Style::Underline);
+    assert!(session.execute("watch 1"));
+    assert!(session.key(b'c'));
+    assert_eq!(session.message(), "cell 1 went from 0 to 8 at offset 19");
+    assert!(session.execute("unwatch"));
+    assert!(session.key(b'c'));
+    assert_eq!(session.message(), "breakpoint at offset 32, instruction 9");
+    let mut screen = Screen::new(60, 14);
+    session.draw(&mut screen);
+    assert!(screen.line(10).starts_with("    0   65    0"), "{}", screen.line(10));
+    assert_eq!(screen.line(11), "         A");
+    assert_eq!((screen.style(4, 10), screen.style(9, 10)), (Style::Normal, Style::Reverse));
+    assert_eq!(screen.style(28, 2), Style::Reverse);
+
+    assert!(session.key(b's'));
+    assert!(session.key(b's'));
+    assert_eq!(session.message(), "waiting for input, type it with :input TEXT or end it with :eof");
+    // typed on the command line
+    for &key in b":input xyz\r" {
+        assert!(session.key(key));
+    }
+    assert!(session.execute("set 0 66"));
+    assert!(session.execute("continue"));
+    assert_eq!(session.message(), "finished");
+    assert_eq!(session.output(), b"Ax");
+    assert_eq!(session.debugger().interp().tape()[..2], [66, b'x']);
+
+    let mut screen = Screen::new(60, 14);
+    session.draw(&mut screen);
+    assert_eq!(screen.line(0), format!("{:<39}│ output, 2 bytes", " source, finished"));
+    assert_eq!(screen.line(1).split('│').nth(1), Some("Ax"));
+
+    assert!(session.execute("ptr 99"));
+    assert_eq!(session.message(), "error: cell 99 outside of the tape of 16 cells");
+    assert!(!session.key(b'q'));
+
+    let mut screen = Screen::new(20, 5);
+    session.draw(&mut screen);
+    assert_eq!(screen.line(0), "terminal too small");
+}
+
+#[test]
+fn test_run() {
+    let code = "+[>+<-]";
+    let (insts, spans) = parse_with_spans(code).unwrap();
+    let mut session = Session::new(&insts, &spans, code, 8);
+    let mut out = Vec::new();
+    run(&mut session, &b"ss:step 9\rq"[..], &mut out, || (40, 12)).unwrap();
+
+    assert_eq!(session.message(), "finished");
+    let out = String::from_utf8(out).unwrap();
+    // a frame before every key
+    assert_eq!(out.matches("\x1b[1;1H").count(), 11);
+    assert!(out.contains("\x1b[12;1H\x1b[0;4ms step  c continue  : command  q quit"), "{:?}", out);
+}
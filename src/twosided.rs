@@ -0,0 +1,223 @@
+// A tape going on without end to both sides, for programs from archives
+// that move left of where they start. Cells are allocated as the pointer
+// gets to them, a chunk at a time, so a program touching cell -1000 and
+// +1000 has about that many cells and not a tape of a fixed size. Only
+// the interpreter here runs programs on it, the generated code needs the
+// bounds of its tape.
+use std::io::{self, Read, Write};
+
+use brainfuck::{ArithMode, ExtOp, Inst, RuntimeError};
+use brainfuck::Inst::*;
+use memory::MemoryBudget;
+
+
+// How far the tape goes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum TapeModel {
+    // `Brainfuck::tape_size` cells from 0 on, moving past either end fails
+    #[default]
+    Bounded,
+    // Every cell from minus to plus infinity, see `TwoSidedInterp`
+    Unbounded,
+}
+
+// Cells are added this many at a time
+const CHUNK: usize = 4096;
+
+// The cells from 0 on and the ones before 0, from -1 down
+#[derive(Debug)]
+pub struct TwoSidedTape {
+    right: Vec<u8>,
+    left: Vec<u8>,
+    budget: MemoryBudget,
+}
+
+impl TwoSidedTape {
+    pub fn new(budget: MemoryBudget) -> TwoSidedTape {
+        TwoSidedTape { right: Vec::new(), left: Vec::new(), budget }
+    }
+
+    // The cell at `index`, all cells start out as 0
+    pub fn get(&self, index: isize) -> u8 {
+        let cells = if index < 0 { &self.left } else { &self.right };
+        cells.get(side_index(index)).cloned().unwrap_or(0)
+    }
+
+    pub fn cell_mut(&mut self, index: isize) -> Result<&mut u8, RuntimeError> {
+        let i = side_index(index);
+        let budget = &self.budget;
+        let cells = if index < 0 { &mut self.left } else { &mut self.right };
+        if i >= cells.len() {
+            let len = (i / CHUNK + 1) * CHUNK;
+            budget.charge(len - cells.len())?;
+            cells.resize(len, 0);
+        }
+        Ok(&mut cells[i])
+    }
+
+    // The cells allocated so far, the first one being at the index returned
+    // with them. That's all that was ever touched and some more.
+    pub fn cells(&self) -> (isize, Vec<u8>) {
+        let mut cells: Vec<u8> = self.left.iter().rev().cloned().collect();
+        cells.extend_from_slice(&self.right);
+        (-(self.left.len() as isize), cells)
+    }
+}
+
+impl Drop for TwoSidedTape {
+    fn drop(&mut self) {
+        self.budget.release(self.left.len() + self.right.len());
+    }
+}
+
+// Where a cell is in the `Vec` of its side
+fn side_index(index: isize) -> usize {
+    if index < 0 { (-(index + 1)) as usize } else { index as usize }
+}
+
+// Runs programs on a `TwoSidedTape`, with the cells doing what `arith` says
+// like `interp::Interp`. The pointer starts on cell 0.
+pub struct TwoSidedInterp<'a> {
+    insts: &'a [Inst],
+    tape: TwoSidedTape,
+    ptr: isize,
+    pc: usize,
+    arith: ArithMode,
+    // the register of Extended Type I's `$` and `!`
+    storage: u8,
+}
+
+impl<'a> TwoSidedInterp<'a> {
+    pub fn new(insts: &'a [Inst], arith: ArithMode, budget: MemoryBudget) -> TwoSidedInterp<'a> {
+        TwoSidedInterp { insts, tape: TwoSidedTape::new(budget), ptr: 0, pc: 0, arith, storage: 0 }
+    }
+
+    pub fn run<R: Read, W: Write>(&mut self, mut input: R, mut output: W) -> Result<(), RuntimeError> {
+        while let Some(inst) = self.insts.get(self.pc) {
+            let cell = self.tape.get(self.ptr);
+            match *inst {
+                IncPtr(a) => self.ptr += a as isize,
+                DecPtr(a) => self.ptr -= a as isize,
+                IncVal(a) => match self.arith.add(cell, a) {
+                    Some(value) => *self.tape.cell_mut(self.ptr)? = value,
+                    None => return Err(RuntimeError::CellOverflow { inst_index: self.pc }),
+                },
+                DecVal(a) => match self.arith.sub(cell, a) {
+                    Some(value) => *self.tape.cell_mut(self.ptr)? = value,
+                    None => return Err(RuntimeError::CellUnderflow { inst_index: self.pc }),
+                },
+                PrintCell => output.write_all(&[cell])?,
+                ReadChar => {
+                    let mut byte = [0];
+                    loop {
+                        match input.read(&mut byte) {
+                            Ok(0) => break,
+                            Ok(_) => {
+                                *self.tape.cell_mut(self.ptr)? = byte[0];
+                                break;
+                            }
+                            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                            Err(e) => return Err(e.into()),
+                        }
+                    }
+                }
+                JmpFwd(n) => {
+                    if cell == 0 {
+                        self.pc = n;
+                    }
+                }
+                JmpBack(n) => {
+                    if cell != 0 {
+                        self.pc = n;
+                    }
+                }
+                Ext(ExtOp::End) => {
+                    self.pc = self.insts.len();
+                    break;
+                }
+                Ext(op) => {
+                    let mut value = cell;
+                    op.apply(&mut value, &mut self.storage);
+                    if value != cell {
+                        *self.tape.cell_mut(self.ptr)? = value;
+                    }
+                }
+                // the tape has no indices host functions could take
+                HostCall => return Err(RuntimeError::UnregisteredHostCall { id: cell, inst_index: self.pc }),
+            }
+            self.pc += 1;
+        }
+
+        Ok(())
+    }
+
+    pub fn tape(&self) -> &TwoSidedTape {
+        &self.tape
+    }
+
+    pub fn tape_mut(&mut self) -> &mut TwoSidedTape {
+        &mut self.tape
+    }
+
+    pub fn ptr(&self) -> isize {
+        self.ptr
+    }
+
+    pub fn set_ptr(&mut self, ptr: isize) {
+        self.ptr = ptr;
+    }
+}
+
+
+#[cfg(test)]
+use brainfuck::parse;
+
+#[test]
+fn test_two_sided_tape() {
+    let mut tape = TwoSidedTape::new(MemoryBudget::unlimited());
+    assert_eq!(tape.cells(), (0, vec![]));
+    assert_eq!(tape.get(-5), 0);
+    *tape.cell_mut(-1).unwrap() = 1;
+    *tape.cell_mut(0).unwrap() = 2;
+    *tape.cell_mut(CHUNK as isize).unwrap() = 3;
+    assert_eq!((tape.get(-1), tape.get(0), tape.get(CHUNK as isize)), (1, 2, 3));
+
+    let (first, cells) = tape.cells();
+    assert_eq!(first, -(CHUNK as isize));
+    assert_eq!(cells.len(), 3 * CHUNK);
+    assert_eq!(&cells[CHUNK - 1..CHUNK + 1], [1, 2]);
+
+    let budget = MemoryBudget::new(CHUNK);
+    let mut tape = TwoSidedTape::new(budget.clone());
+    tape.cell_mut(-1).unwrap();
+    assert!(matches!(tape.cell_mut(0), Err(RuntimeError::MemoryLimitExceeded { .. })));
+    drop(tape);
+    assert_eq!(budget.used(), 0);
+}
+
+#[test]
+fn test_two_sided_interp() {
+    // writes 7 at -1000 and 9 at +1000, and prints them from the other end
+    let source = format!("{}+++++++{}+++++++++.{}.", "<".repeat(1000), ">".repeat(2000), "<".repeat(2000));
+    let insts = parse(&source).unwrap();
+    let mut interp = TwoSidedInterp::new(&insts, ArithMode::Wrap, MemoryBudget::unlimited());
+    let mut output = Vec::new();
+    interp.run(&b""[..], &mut output).unwrap();
+    assert_eq!(output, [9, 7]);
+    assert_eq!(interp.ptr(), -1000);
+    assert_eq!((interp.tape().get(-1000), interp.tape().get(1000)), (7, 9));
+    // only around the two cells
+    assert_eq!(interp.tape().cells().1.len(), 2 * CHUNK);
+
+    // `[<]` runs off the start of any bounded tape
+    let insts = parse("<<<+>+>+[<]-.").unwrap();
+    let mut interp = TwoSidedInterp::new(&insts, ArithMode::Wrap, MemoryBudget::unlimited());
+    let mut output = Vec::new();
+    interp.run(&b""[..], &mut output).unwrap();
+    assert_eq!(output, [255]);
+    assert_eq!(interp.ptr(), -4);
+
+    let insts = parse("<-").unwrap();
+    let result = TwoSidedInterp::new(&insts, ArithMode::Trap, MemoryBudget::unlimited()).run(io::empty(), io::sink());
+    assert!(matches!(result, Err(RuntimeError::CellUnderflow { inst_index: 1 })));
+}
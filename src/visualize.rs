@@ -0,0 +1,261 @@
+// Watching a program run in the interpreter, for `--visualize`: the cells
+// around the pointer, the instruction about to execute with where it is in
+// the source, the number of steps so far and the latest output. Frames are
+// drawn into any `Write` by `draw_frame`, separately from stepping, and
+// `run` only decides which steps get one. On a terminal every frame is
+// drawn over the previous one, otherwise only the last is.
+use std::io::{self, Read, Write};
+use std::thread;
+use std::time::Duration;
+
+use brainfuck::{to_source, Inst, RuntimeError, Span};
+use interp::Interp;
+
+
+// Cells shown on either side of the pointer
+const RADIUS: usize = 8;
+// Characters of the source line shown on either side of the instruction
+const SOURCE_RADIUS: usize = 32;
+// The latest bytes of output shown
+const OUTPUT_TAIL: usize = 48;
+
+pub const DEFAULT_DELAY: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy)]
+pub struct VisualizeOptions {
+    // steps from one frame to the next
+    pub every: u64,
+    // pause after drawing a frame
+    pub delay: Duration,
+    // whether frames are drawn over each other with escape sequences while
+    // the program runs, or only the last one as plain text
+    pub live: bool,
+}
+
+// The state of a run to draw
+#[derive(Debug, Clone, Copy)]
+pub struct Frame<'a> {
+    pub tape: &'a [u8],
+    pub ptr: usize,
+    // index of the instruction about to execute, `None` once finished
+    pub next: Option<usize>,
+    pub steps: u64,
+    pub output: &'a [u8],
+}
+
+// A program's source with where its instructions are in it
+#[derive(Debug, Clone, Copy)]
+pub struct Source<'a> {
+    pub code: &'a str,
+    pub spans: &'a [Span],
+}
+
+// Draws `frame` as lines of text, with the pointer's cell in reverse video
+// and every line clearing what's left of it on the screen if `ansi`
+pub fn draw_frame<W: Write>(
+    frame: &Frame, insts: &[Inst], source: Option<&Source>, ansi: bool, mut out: W
+) -> io::Result<()> {
+    let eol = if ansi { "\x1b[K\n" } else { "\n" };
+    let first = frame.ptr.saturating_sub(RADIUS);
+    let last = (frame.ptr + RADIUS + 1).min(frame.tape.len());
+
+    let state = if frame.next.is_some() { "" } else { ", finished" };
+    write!(out, "step {}{}{}", frame.steps, state, eol)?;
+
+    write!(out, "cell ")?;
+    for i in first..last {
+        write!(out, "{:>5}", i)?;
+    }
+    write!(out, "{}value", eol)?;
+    for i in first..last {
+        if i == frame.ptr && ansi {
+            write!(out, " \x1b[7m{:>4}\x1b[0m", frame.tape[i])?;
+        } else {
+            write!(out, "{:>5}", frame.tape[i])?;
+        }
+    }
+    write!(out, "{}     {}^^^^{}", eol, " ".repeat(5 * (frame.ptr - first) + 1), eol)?;
+
+    match frame.next {
+        Some(index) => {
+            write!(out, "next #{} {}", index, to_source(&insts[index..index + 1]))?;
+            match source {
+                Some(source) => {
+                    let span = source.spans[index];
+                    let (line, column, text, caret) = context(source.code, span);
+                    write!(out, " at {}:{}{}  {}{}  {}{}", line, column, eol, text, eol, caret, eol)?;
+                }
+                None => write!(out, "{}", eol)?,
+            }
+        }
+        None => write!(out, "next -{}", eol)?,
+    }
+
+    let tail = &frame.output[frame.output.len().saturating_sub(OUTPUT_TAIL)..];
+    let escaped: String = tail.iter().flat_map(|&byte| std::ascii::escape_default(byte)).map(char::from).collect();
+    write!(out, "output \"{}\"{}", escaped, eol)
+}
+
+// Line and column of `span`, counted from 1, its line cut down to the part
+// around it and carets under it in that part
+fn context(code: &str, span: Span) -> (usize, usize, String, String) {
+    let line_start = code[..span.start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = code[span.start..].find('\n').map_or(code.len(), |i| span.start + i);
+    let line = code[..span.start].matches('\n').count() + 1;
+    let column = code[line_start..span.start].chars().count();
+    let width = code[span.start..span.end.min(line_end)].chars().count().max(1);
+
+    let skip = column.saturating_sub(SOURCE_RADIUS);
+    let text: String = code[line_start..line_end].chars()
+        .skip(skip)
+        .take(column - skip + width + SOURCE_RADIUS)
+        .map(|c| if c == '\t' { ' ' } else { c })
+        .collect();
+    let caret = format!("{}{}", " ".repeat(column - skip), "^".repeat(width));
+
+    (line, column + 1, text, caret)
+}
+
+// Runs `interp` to the end one `Interp::step` at a time, drawing frames of
+// it to `screen`. Live, the first frame is drawn before anything executes,
+// then one after every `options.every` steps, and the program's output is
+// only written to `output` once it's done. Otherwise it's written as
+// printed. Either way the last frame shows where the run ended, also when
+// it failed.
+pub fn run<R: Read, W: Write, S: Write>(
+    interp: &mut Interp, mut input: R, source: Option<&Source>, options: &VisualizeOptions,
+    mut output: W, mut screen: S,
+) -> io::Result<Result<(), RuntimeError>> {
+    let mut printed = Vec::new();
+    let mut steps = 0;
+
+    let draw = |interp: &Interp, steps, printed: &[u8], screen: &mut S| -> io::Result<()> {
+        let frame = Frame {
+            tape: interp.tape(),
+            ptr: interp.ptr(),
+            next: Some(interp.pc()).filter(|&pc| pc < interp.insts().len()),
+            steps,
+            output: printed,
+        };
+        if options.live {
+            write!(screen, "\x1b[H")?;
+        }
+        draw_frame(&frame, interp.insts(), source, options.live, &mut *screen)?;
+        if options.live {
+            write!(screen, "\x1b[J")?;
+        }
+        screen.flush()
+    };
+
+    if options.live {
+        write!(screen, "\x1b[2J")?;
+        draw(interp, steps, &printed, &mut screen)?;
+        thread::sleep(options.delay);
+    }
+    let result = loop {
+        match interp.step(&mut input, io::sink()) {
+            Ok(Some(step)) => {
+                steps += 1;
+                if let Some(byte) = step.output {
+                    printed.push(byte);
+                    if !options.live {
+                        output.write_all(&[byte])?;
+                    }
+                }
+                if options.live && steps % options.every.max(1) == 0 {
+                    draw(interp, steps, &printed, &mut screen)?;
+                    thread::sleep(options.delay);
+                }
+            }
+            Ok(None) => break Ok(()),
+            Err(e) => break Err(e),
+        }
+    };
+
+    draw(interp, steps, &printed, &mut screen)?;
+    if options.live {
+        output.write_all(&printed)?;
+    }
+    output.flush()?;
+
+    Ok(result)
+}
+
+#[cfg(test)]
+use brainfuck::parse_with_spans;
+
+#[test]
+fn test_draw_frame() {
+    let code = "++\n>+++[-.]";
+    let (insts, spans) = parse_with_spans(code).unwrap();
+    let source = Source { code, spans: &spans };
+    let tape = [2, 3, 0, 0];
+    let frame = Frame { tape: &tape, ptr: 1, next: Some(3), steps: 7, output: b"a\n" };
+
+    let mut screen = Vec::new();
+    draw_frame(&frame, &insts, Some(&source), false, &mut screen).unwrap();
+    assert_eq!(
+        String::from_utf8(screen).unwrap(),
+        "step 7\n\
+         cell     0    1    2    3\n\
+         value    2    3    0    0\n           ^^^^\n\
+         next #3 [ at 2:5\n  >+++[-.]\n      ^\n\
+         output \"a\\n\"\n"
+    );
+
+    // without source, finished and on a terminal
+    let frame = Frame { next: None, ..frame };
+    let mut screen = Vec::new();
+    draw_frame(&frame, &insts, None, true, &mut screen).unwrap();
+    let screen = String::from_utf8(screen).unwrap();
+    assert!(screen.starts_with("step 7, finished\x1b[K\n"), "{:?}", screen);
+    assert!(screen.contains("    2 \x1b[7m   3\x1b[0m    0"), "{:?}", screen);
+    assert!(screen.contains("next -\x1b[K\n"), "{:?}", screen);
+
+    // only the cells around the pointer
+    let tape = [0; 100];
+    let frame = Frame { tape: &tape, ptr: 50, next: Some(0), steps: 0, output: b"" };
+    let mut screen = Vec::new();
+    draw_frame(&frame, &insts, None, false, &mut screen).unwrap();
+    let screen = String::from_utf8(screen).unwrap();
+    assert!(screen.contains("\ncell    42   43"), "{:?}", screen);
+    assert!(screen.contains("   58\nvalue"), "{:?}", screen);
+    assert!(screen.contains("next #0 ++\n"), "{:?}", screen);
+}
+
+#[test]
+fn test_run() {
+    let code = "+++[>++<-]>.";
+    let (insts, spans) = parse_with_spans(code).unwrap();
+    let source = Source { code, spans: &spans };
+    let visualize = |live: bool, every: u64| {
+        let options = VisualizeOptions { every, delay: Duration::from_millis(0), live };
+        let (mut output, mut screen) = (Vec::new(), Vec::new());
+        let mut interp = Interp::new(&insts, 8);
+        run(&mut interp, io::empty(), Some(&source), &options, &mut output, &mut screen).unwrap().unwrap();
+        (output, String::from_utf8(screen).unwrap())
+    };
+
+    // a frame before the first step, after every 4 and at the end
+    let (output, screen) = visualize(true, 4);
+    assert_eq!(output, b"\x06");
+    let steps = 1 + 1 + 3 * 5 + 2;
+    assert_eq!(screen.matches("\x1b[H").count(), 1 + steps / 4 + 1);
+    assert!(screen.starts_with("\x1b[2J\x1b[Hstep 0\x1b[K\n"), "{:?}", screen);
+    assert!(screen.contains(&format!("step {}, finished", steps)), "{:?}", screen);
+
+    // just the summary
+    let (output, screen) = visualize(false, 1);
+    assert_eq!(output, b"\x06");
+    assert_eq!(screen.matches("step ").count(), 1);
+    assert!(screen.starts_with(&format!("step {}, finished\n", steps)), "{:?}", screen);
+    assert!(screen.ends_with("output \"\\x06\"\n"), "{:?}", screen);
+
+    // the last frame shows where it failed
+    let insts = parse_with_spans("<").unwrap().0;
+    let options = VisualizeOptions { every: 1, delay: Duration::from_millis(0), live: false };
+    let mut screen = Vec::new();
+    let result = run(&mut Interp::new(&insts, 8), io::empty(), None, &options, io::sink(), &mut screen).unwrap();
+    assert!(result.is_err());
+    assert!(String::from_utf8(screen).unwrap().starts_with("step 0\n"));
+}
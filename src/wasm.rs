@@ -0,0 +1,124 @@
+// The interface of a browser playground, for the `wasm` feature. Programs
+// are only parsed, never compiled to machine code, and run in the
+// interpreter with a budget of steps, so that a page running one can't
+// hang. Errors come with where they are in the source, for marking it in
+// an editor.
+//
+// The `#[wasm_bindgen]` exports wrapping it are in the crate in wasm/, built
+// for wasm32-unknown-unknown without the JIT.
+use std::fmt;
+
+use brainfuck::{parse_with_spans, CompileError, Inst, RuntimeError, Span, DEFAULT_TAPE_SIZE};
+use interp::{Interp, StepOutcome};
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WasmError {
+    pub message: String,
+    // where the error is in the source if known, in bytes
+    pub offset: Option<usize>,
+    // and as line and column counted from 1, the column in characters
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+impl WasmError {
+    fn new(message: String, source: &str, offset: Option<usize>) -> WasmError {
+        let position = offset.map(|offset| {
+            let before = &source[..offset];
+            let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+            (before.matches('\n').count() + 1, before[line_start..].chars().count() + 1)
+        });
+
+        WasmError { message, offset, line: position.map(|(line, _)| line), column: position.map(|(_, column)| column) }
+    }
+}
+
+impl fmt::Display for WasmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => write!(f, "{}:{}: {}", line, column, self.message),
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+pub struct WasmProgram {
+    source: String,
+    insts: Vec<Inst>,
+    spans: Vec<Span>,
+}
+
+impl WasmProgram {
+    // Only the first of several errors, an editor gets the others by
+    // fixing that one
+    pub fn new(source: &str) -> Result<WasmProgram, WasmError> {
+        let (insts, spans) = parse_with_spans(source).map_err(|e| {
+            let e = match e {
+                CompileError::Multiple(mut errors) => errors.remove(0),
+                e => e,
+            };
+            WasmError::new(e.to_string(), source, e.offset())
+        })?;
+
+        Ok(WasmProgram { source: source.to_string(), insts, spans })
+    }
+
+    // Runs the program on a fresh tape with `input` as all of its input and
+    // returns what it printed, failing once it took more than `max_steps`
+    // steps
+    pub fn run(&self, input: &[u8], max_steps: usize) -> Result<Vec<u8>, WasmError> {
+        let mut interp = Interp::new(&self.insts, DEFAULT_TAPE_SIZE);
+        let mut input = input;
+        let mut output = Vec::new();
+
+        let e = match interp.run_for(max_steps, &mut input, &mut output) {
+            StepOutcome::Finished => return Ok(output),
+            // the input never runs out, it ends
+            StepOutcome::Paused | StepOutcome::NeedsInput => {
+                let offset = self.spans.get(interp.pc()).map(|span| span.start);
+                return Err(WasmError::new(format!("still running after {} steps", max_steps), &self.source, offset));
+            }
+            StepOutcome::Error(e) => e,
+        };
+        let offset = match e {
+            RuntimeError::PointerUnderflow { inst_index }
+            | RuntimeError::PointerOverflow { inst_index }
+            | RuntimeError::CellOverflow { inst_index }
+            | RuntimeError::CellUnderflow { inst_index }
+            | RuntimeError::NonTerminatingLoop { inst_index } => Some(self.spans[inst_index].start),
+            _ => None,
+        };
+
+        Err(WasmError::new(e.to_string(), &self.source, offset))
+    }
+
+    // The instructions as listed by `--dump`
+    pub fn dump(&self) -> String {
+        let mut dump = Vec::new();
+        ::dump::write_plain(&self.insts, &mut dump).unwrap();
+        String::from_utf8(dump).unwrap()
+    }
+}
+
+
+#[test]
+fn test_wasm_program() {
+    let hello = include_str!("../tests/fixtures/hello.b");
+    let program = WasmProgram::new(hello).unwrap();
+    assert_eq!(program.run(b"", 1_000_000).unwrap(), b"Hello World!\n");
+    assert!(program.dump().starts_with("0: IncVal(8)\n"), "{}", program.dump());
+
+    let e = WasmProgram::new("+\n+]").err().unwrap();
+    assert_eq!(e, WasmError { message: "unmatched ']'".to_string(), offset: Some(3), line: Some(2), column: Some(2) });
+    assert_eq!(e.to_string(), "2:2: unmatched ']'");
+
+    let program = WasmProgram::new("+[]\n ,[.[-],]").unwrap();
+    let e = program.run(b"", 1000).err().unwrap();
+    assert_eq!((e.message.as_str(), e.line), ("still running after 1000 steps", Some(1)));
+    let program = WasmProgram::new(",[.[-],]").unwrap();
+    assert_eq!(program.run(b"echo", 10_000).unwrap(), b"echo");
+
+    let e = WasmProgram::new("  <").unwrap().run(b"", 10).err().unwrap();
+    assert_eq!((e.offset, e.line, e.column), (Some(2), Some(1), Some(3)));
+}
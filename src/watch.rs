@@ -0,0 +1,91 @@
+// Running a program again whenever its file changes, for `--watch`. The
+// directory of the file is watched rather than the file itself, as editors
+// often save by writing a new file and renaming it over the old one, which
+// a watch on the old file wouldn't see. Saves in quick succession are taken
+// as one: nothing may happen to the file for `SETTLE` before the program
+// runs again.
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::time::Duration;
+
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult, Debouncer};
+use notify_debouncer_mini::notify::{RecommendedWatcher, RecursiveMode};
+
+
+const SETTLE: Duration = Duration::from_millis(100);
+
+pub struct Watcher {
+    // stops watching when dropped
+    _debouncer: Debouncer<RecommendedWatcher>,
+    events: Receiver<DebounceEventResult>,
+    path: PathBuf,
+}
+
+fn other(e: notify_debouncer_mini::notify::Error) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+impl Watcher {
+    // Starts watching `path`, which has to exist
+    pub fn new(path: &Path) -> io::Result<Watcher> {
+        let path = path.canonicalize()?;
+        let dir = path.parent().unwrap_or(Path::new("/")).to_path_buf();
+
+        let (sender, events) = mpsc::channel();
+        let mut debouncer = new_debouncer(SETTLE, sender).map_err(other)?;
+        debouncer.watcher().watch(&dir, RecursiveMode::NonRecursive).map_err(other)?;
+
+        Ok(Watcher { _debouncer: debouncer, events, path })
+    }
+
+    // Blocks until the file changed and was then left alone for a while.
+    // Changes since the last call count, including those while the program
+    // ran.
+    pub fn wait_for_change(&self) -> io::Result<()> {
+        loop {
+            let events = match self.events.recv() {
+                Ok(events) => events.map_err(other)?,
+                Err(_) => return Err(io::Error::other("stopped watching")),
+            };
+            if events.iter().any(|event| event.path == self.path) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+
+#[test]
+fn test_wait_for_change() {
+    use std::{fs, thread};
+
+    let dir = std::env::temp_dir().join(format!("brainfuck-watch-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("watched.b");
+    fs::write(&path, "+").unwrap();
+    let watcher = Watcher::new(&path).unwrap();
+
+    // other files in the directory don't count
+    fs::write(dir.join("other.b"), "-").unwrap();
+    let writer = {
+        let path = path.clone();
+        thread::spawn(move || {
+            thread::sleep(SETTLE * 3);
+            // a burst of saves, the last one replacing the file
+            for source in &["++", "+++"] {
+                fs::write(&path, source).unwrap();
+                thread::sleep(Duration::from_millis(20));
+            }
+            fs::write(path.with_extension("tmp"), "++++").unwrap();
+            fs::rename(path.with_extension("tmp"), &path).unwrap();
+        })
+    };
+    watcher.wait_for_change().unwrap();
+    // not before the last save
+    assert!(writer.is_finished());
+    writer.join().unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "++++");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,66 @@
+/* Exercises the C interface, exits with 0 if everything works */
+#include <stdio.h>
+#include <string.h>
+
+#include "brainfuck.h"
+
+struct buffer {
+  char data[64];
+  size_t len;
+  const char *input;
+};
+
+static int put(void *user_data, int byte) {
+  struct buffer *buffer = user_data;
+  if (buffer->len == sizeof buffer->data) {
+    return -1;
+  }
+  buffer->data[buffer->len++] = (char)byte;
+  return byte;
+}
+
+static int get(void *user_data) {
+  struct buffer *buffer = user_data;
+  return *buffer->input ? (unsigned char)*buffer->input++ : -1;
+}
+
+#define CHECK(condition) \
+  if (!(condition)) { \
+    fprintf(stderr, "%s:%d: %s failed\n", __FILE__, __LINE__, #condition); \
+    return 1; \
+  }
+
+int main(void) {
+  /* upper-cases its input, clearing the cell as EOF leaves it alone */
+  const char *source = ",[--------------------------------.[-],]";
+  bf_program *program = NULL;
+  bf_options options = { 16, 0 };
+  CHECK(bf_compile(source, strlen(source), &options, &program) == BF_OK);
+
+  struct buffer buffer = { { 0 }, 0, "abc" };
+  bf_io_callbacks io = { &buffer, put, get };
+  CHECK(bf_run(program, &io) == BF_OK);
+  CHECK(buffer.len == 3 && memcmp(buffer.data, "ABC", 3) == 0);
+  bf_program_free(program);
+
+  program = NULL;
+  CHECK(bf_compile("[[]", 3, NULL, &program) == BF_COMPILE_ERROR);
+  CHECK(program == NULL);
+  CHECK(strcmp(bf_last_error_message(), "unclosed '[' at byte 0") == 0);
+
+  /* runs out of output space */
+  source = "+[.]";
+  CHECK(bf_compile(source, strlen(source), NULL, &program) == BF_OK);
+  buffer.len = 0;
+  CHECK(bf_run(program, &io) == BF_RUNTIME_ERROR);
+  CHECK(strcmp(bf_last_error_message(), "putc callback failed") == 0);
+  bf_program_free(program);
+
+  /* generated code writing to stdout */
+  source = "++++++++[>++++++++<-]>+.+.";
+  CHECK(bf_compile(source, strlen(source), NULL, &program) == BF_OK);
+  CHECK(bf_run(program, NULL) == BF_OK);
+  bf_program_free(program);
+
+  return 0;
+}
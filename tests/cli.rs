@@ -0,0 +1,1273 @@
+extern crate libc;
+
+use std::io::{Read, Write};
+use std::process::{Command, Output, Stdio};
+
+fn brainfuck(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_brainfuck"))
+        .args(args)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .unwrap()
+}
+
+fn brainfuck_with_input(args: &[&str], input: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_brainfuck"))
+        .args(args)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn test_check() {
+    let out = brainfuck(&["check", "tests/fixtures/hello.b"]);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "checked 1 file(s): 1 passed, 0 failed\n");
+    assert!(out.stderr.is_empty());
+
+    // the broken file comes first, the good one still has to be checked
+    let out = brainfuck(&["check", "tests/fixtures/unbalanced.b", "tests/fixtures/hello.b"]);
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "checked 2 file(s): 1 passed, 1 failed\n");
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        "tests/fixtures/unbalanced.b:1:8: error: unmatched ']'\n"
+    );
+}
+
+#[test]
+fn test_bracket_errors() {
+    // every error is reported, not just the first one
+    let out = brainfuck(&["tests/fixtures/brackets.b"]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(out.stdout.is_empty());
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        "tests/fixtures/brackets.b:2:8: error: unmatched ']'\n\
+         tests/fixtures/brackets.b:8:2: error: unclosed '['\n"
+    );
+}
+
+// Checks that every line of `stderr` is a diagnostic with all the fields, in
+// order, and returns the lines
+fn json_diagnostics(stderr: &[u8]) -> Vec<String> {
+    const KEYS: [&str; 10] = [
+        "severity", "code", "message", "file", "offset", "line", "column", "end_offset", "end_line", "end_column",
+    ];
+
+    let stderr = String::from_utf8_lossy(stderr);
+    assert!(stderr.ends_with('\n'), "{:?}", stderr);
+    let lines: Vec<String> = stderr.lines().map(str::to_string).collect();
+    for line in &lines {
+        assert!(line.starts_with("{\"severity\":\"error\",\"code\":\""), "{}", line);
+        assert!(line.ends_with('}'), "{}", line);
+        let mut rest = &line[..];
+        for key in &KEYS {
+            let at = rest.find(&format!("\"{}\":", key)).unwrap_or_else(|| panic!("no {} in {}", key, line));
+            rest = &rest[at + key.len() + 3..];
+            if ["offset", "line", "column", "end_offset", "end_line", "end_column"].contains(key) {
+                let value = &rest[..rest.find([',', '}']).unwrap()];
+                assert!(value == "null" || value.parse::<usize>().is_ok(), "{} is {} in {}", key, value, line);
+            }
+        }
+    }
+    lines
+}
+
+#[test]
+fn test_json_diagnostics() {
+    // compile errors point at the bracket
+    let out = brainfuck(&["check", "--diagnostics", "json", "tests/fixtures/brackets.b", "tests/fixtures/hello.b"]);
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "checked 2 file(s): 1 passed, 1 failed\n");
+    assert_eq!(
+        json_diagnostics(&out.stderr),
+        [
+            "{\"severity\":\"error\",\"code\":\"unmatched-close\",\"message\":\"unmatched ']'\",\
+             \"file\":\"tests/fixtures/brackets.b\",\"offset\":27,\"line\":2,\"column\":8,\
+             \"end_offset\":28,\"end_line\":2,\"end_column\":9}",
+            "{\"severity\":\"error\",\"code\":\"unclosed-open\",\"message\":\"unclosed '['\",\
+             \"file\":\"tests/fixtures/brackets.b\",\"offset\":87,\"line\":8,\"column\":2,\
+             \"end_offset\":88,\"end_line\":8,\"end_column\":3}",
+        ]
+    );
+
+    // runtime errors, with and without a place in the source
+    let out = brainfuck(&["--diagnostics", "json", "--detect-livelock", "tests/fixtures/livelock.b"]);
+    assert_eq!(out.status.code(), Some(2));
+    let lines = json_diagnostics(&out.stderr);
+    assert_eq!(lines.len(), 1);
+    assert!(lines[0].contains("\"code\":\"non-terminating-loop\""), "{}", lines[0]);
+    assert!(lines[0].contains("\"offset\":55,\"line\":5,\"column\":3,"), "{}", lines[0]);
+
+    let out = brainfuck(&["--diagnostics", "json", "tests/fixtures/underflow.b"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(
+        json_diagnostics(&out.stderr),
+        [
+            "{\"severity\":\"error\",\"code\":\"pointer-underflow\",\
+             \"message\":\"pointer moved below the start of the tape at instruction 2\",\"file\":null,\
+             \"offset\":null,\"line\":null,\"column\":null,\"end_offset\":null,\"end_line\":null,\
+             \"end_column\":null}",
+        ]
+    );
+
+    // options and files that can't be used
+    let out = brainfuck(&["--diagnostics", "json", "--tape-size", "lots", "tests/fixtures/hello.b"]);
+    assert_eq!(out.status.code(), Some(2));
+    let lines = json_diagnostics(&out.stderr);
+    assert_eq!(lines.len(), 1);
+    assert!(lines[0].contains("\"code\":\"invalid-option\""), "{}", lines[0]);
+
+    let out = brainfuck(&["--diagnostics", "json", "tests/fixtures/missing.b"]);
+    assert_eq!(out.status.code(), Some(3));
+    let lines = json_diagnostics(&out.stderr);
+    assert_eq!(lines.len(), 1);
+    assert!(lines[0].contains("\"code\":\"io\""), "{}", lines[0]);
+    assert!(lines[0].contains("\"file\":\"tests/fixtures/missing.b\""), "{}", lines[0]);
+}
+
+#[test]
+fn test_diagnostics_out() {
+    use std::{env, fs};
+
+    let path = env::temp_dir().join(format!("brainfuck-cli-{}-diagnostics.jsonl", std::process::id()));
+    let path = path.to_str().unwrap();
+    let out = brainfuck(&["check", "--diagnostics", "json", "--diagnostics-out", path, "tests/fixtures/unbalanced.b"]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(out.stderr.is_empty());
+    let lines = json_diagnostics(&fs::read(path).unwrap());
+    fs::remove_file(path).unwrap();
+    assert_eq!(lines.len(), 1);
+    assert!(lines[0].contains("\"offset\":7,\"line\":1,\"column\":8,"), "{}", lines[0]);
+
+    // people get the usual format, just somewhere else
+    let out = brainfuck(&["--diagnostics-out", path, "tests/fixtures/unbalanced.b"]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(out.stderr.is_empty());
+    let report = fs::read_to_string(path).unwrap();
+    fs::remove_file(path).unwrap();
+    assert_eq!(report, "tests/fixtures/unbalanced.b:1:8: error: unmatched ']'\n");
+}
+
+#[test]
+fn test_check_never_runs() {
+    // hello.b prints when executed, only the summary may show up
+    let out = brainfuck(&["check", "tests/fixtures/hello.b", "tests/fixtures/hello.b"]);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "checked 2 file(s): 2 passed, 0 failed\n");
+}
+
+fn temp_copy(fixture: &str, name: &str) -> String {
+    use std::{env, fs};
+
+    let path = env::temp_dir().join(format!("brainfuck-cli-{}-{}", std::process::id(), name));
+    fs::copy(format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), fixture), &path).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+#[test]
+fn test_fmt() {
+    use std::fs;
+
+    let path = temp_copy("hello.b", "fmt.b");
+    let original = fs::read_to_string(&path).unwrap();
+
+    let out = brainfuck(&["fmt", "--check", &path]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&out.stderr).ends_with(": would reformat\n"));
+    assert_eq!(fs::read_to_string(&path).unwrap(), original);
+
+    let out = brainfuck(&["fmt", "--width", "20", &path]);
+    assert_eq!(out.status.code(), Some(0));
+    let formatted = fs::read_to_string(&path).unwrap();
+    assert!(formatted.lines().all(|line| line.len() <= 20));
+
+    assert_eq!(brainfuck(&["fmt", "--check", "--width", "20", &path]).status.code(), Some(0));
+
+    // still the same program
+    let out = brainfuck(&[&path]);
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "Hello World!\n");
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_minify() {
+    use std::fs;
+
+    let out = brainfuck(&["minify", "tests/fixtures/unoptimized.b"]);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stdout),
+        "++++++++>+<[>++++++++<-]>.[-]<,.[-]++++++++++.\n"
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        "tests/fixtures/unoptimized.b: 363 -> 46 characters\n"
+    );
+
+    let path = temp_copy("hello.b", "minify.b");
+    let out = brainfuck(&["minify", "tests/fixtures/hello.b", "-o", &path]);
+    assert_eq!(out.status.code(), Some(0));
+    assert!(out.stdout.is_empty());
+    let out = brainfuck(&[&path]);
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "Hello World!\n");
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_diff() {
+    use std::{env, fs};
+
+    // only the comments and the layout differ
+    let out = brainfuck(&["diff", "tests/fixtures/hello.b", "tests/fixtures/hello_commented.b"]);
+    assert_eq!(out.status.code(), Some(0));
+    assert!(out.stdout.is_empty());
+    assert!(out.stderr.is_empty());
+
+    let a = env::temp_dir().join(format!("brainfuck-cli-{}-diff-a.b", std::process::id()));
+    let b = env::temp_dir().join(format!("brainfuck-cli-{}-diff-b.b", std::process::id()));
+    let (a, b) = (a.to_str().unwrap(), b.to_str().unwrap());
+    fs::write(a, "+++[>++<-]>.\n").unwrap();
+    fs::write(b, "+++[>+++<-]\n>.,\n").unwrap();
+    let out = brainfuck(&["diff", a, b]);
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stdout),
+        format!("{}:1:6: changed IncVal(2) to IncVal(3) at {}:1:6\n{}:2:3: inserted ReadChar\n", a, b, b)
+    );
+
+    // the same after optimizing, as minify writes it
+    fs::write(b, "++++++++>+<[>++++++++<-]>.[-]<,.[-]++++++++++.\n").unwrap();
+    let out = brainfuck(&["diff", "tests/fixtures/unoptimized.b", b]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(!out.stdout.is_empty());
+    let out = brainfuck(&["diff", "--semantic-only", "tests/fixtures/unoptimized.b", b]);
+    assert_eq!(out.status.code(), Some(0));
+    assert!(out.stdout.is_empty());
+
+    fs::remove_file(a).unwrap();
+    fs::remove_file(b).unwrap();
+    let out = brainfuck(&["diff", "tests/fixtures/hello.b", a]);
+    assert_eq!(out.status.code(), Some(3));
+}
+
+#[test]
+fn test_stats() {
+    let out = brainfuck(&["stats", "--format", "json", "tests/fixtures/unoptimized.b"]);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stdout),
+        "{\"commands\":66,\"histogram\":{\">\":5,\"<\":5,\"+\":34,\"-\":6,\".\":4,\",\":2,\
+         \"[\":5,\"]\":5},\"loops\":5,\"max_depth\":1,\"longest_run\":10,\
+         \"pointer_drift\":0,\"dead_loops\":2,\"min_tape_size\":1}\n"
+    );
+
+    let out = brainfuck(&["stats", "tests/fixtures/unbalanced.b"]);
+    assert_eq!(out.status.code(), Some(1));
+}
+
+#[test]
+fn test_detect_livelock() {
+    let out = brainfuck(&["--detect-livelock", "tests/fixtures/livelock.b"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(out.stdout, b"*");
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        "tests/fixtures/livelock.b:5:3: error: loop at instruction 9 never terminates\n"
+    );
+
+    let out = brainfuck(&["--detect-livelock", "tests/fixtures/hello.b"]);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(out.stdout, b"Hello World!\n");
+}
+
+#[test]
+fn test_coverage() {
+    let out = brainfuck(&["--coverage", "tests/fixtures/branch.b"]);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(out.stdout, [0]);
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        "tests/fixtures/branch.b: coverage\n\
+         ,[>+<[-]]>.\n\
+         \x20 ^^^^^^^\n\
+         coverage: 36.4% (4/11 commands executed)\n"
+    );
+
+    // counts accumulate over runs with different input
+    let counts = std::env::temp_dir().join(format!("brainfuck-cli-{}-coverage", std::process::id()));
+    let counts = counts.to_str().unwrap();
+    let out = brainfuck(&["--coverage-out", counts, "tests/fixtures/branch.b"]);
+    assert_eq!(out.status.code(), Some(0));
+    assert!(out.stderr.is_empty());
+
+    let out = brainfuck_with_input(&["--coverage", "--coverage-out", counts, "tests/fixtures/branch.b"], b"x");
+    std::fs::remove_file(counts).unwrap();
+    assert_eq!(out.stdout, [1]);
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        "tests/fixtures/branch.b: coverage\n\
+         coverage: 100.0% (11/11 commands executed)\n"
+    );
+}
+
+#[test]
+fn test_heatmap() {
+    let out = brainfuck_with_input(&["--heatmap", "tests/fixtures/branch.b"], b"x");
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(out.stdout, [1]);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.starts_with("\x1b[0;2mread a byte"));
+    // the clear loop runs 'x' = 120 times, everything else once
+    assert!(stderr.ends_with(
+        "\x1b[0;30;48;5;21m,[>+<[\x1b[0;30;48;5;196m-]\x1b[0;30;48;5;21m]>.\x1b[0m\n"
+    ));
+
+    let html = std::env::temp_dir().join(format!("brainfuck-cli-{}-heatmap.html", std::process::id()));
+    let out = brainfuck(&["--heatmap-html", html.to_str().unwrap(), "tests/fixtures/branch.b"]);
+    assert_eq!(out.status.code(), Some(0));
+    assert!(out.stderr.is_empty());
+    let page = std::fs::read_to_string(&html).unwrap();
+    std::fs::remove_file(&html).unwrap();
+    assert!(page.contains("<title>tests/fixtures/branch.b</title>"));
+    assert!(page.contains("<span class=\"b7\">,[</span><span class=\"b0\">&gt;+&lt;[-]]</span>"), "{}", page);
+}
+
+#[test]
+fn test_profile_out() {
+    let profile = std::env::temp_dir().join(format!("brainfuck-cli-{}-profile.json", std::process::id()));
+    let out = brainfuck_with_input(&["--profile-out", profile.to_str().unwrap(), "tests/fixtures/branch.b"], b"x");
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(out.stdout, [1]);
+    let json = std::fs::read_to_string(&profile).unwrap();
+    std::fs::remove_file(&profile).unwrap();
+    // the clear loop runs 'x' = 120 times, the run took whatever time it took
+    assert!(json.starts_with(
+        "{\"version\":1,\"instructions\":249,\"input_bytes\":1,\"output_bytes\":1,\"loops\":[\
+         {\"index\":1,\"span\":{\"start\":39,\"end\":47},\"entries\":1,\"iterations\":1},\
+         {\"index\":5,\"span\":{\"start\":43,\"end\":46},\"entries\":1,\"iterations\":120}],\
+         \"run\":{\"instructions\":249,\"bytes_read\":1,\"bytes_written\":1,\"max_pointer\":1,\
+         \"final_pointer\":1,\"wall_time_ns\":"
+    ), "{}", json);
+    assert!(json.ends_with("}}"), "{}", json);
+}
+
+#[test]
+fn test_run_stats() {
+    // the interpreter counts everything
+    let out = brainfuck_with_input(&["--detect-livelock", "--stats=json", "tests/fixtures/branch.b"], b"x");
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(out.stdout, [1]);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.starts_with(
+        "{\"instructions\":249,\"bytes_read\":1,\"bytes_written\":1,\"max_pointer\":1,\"final_pointer\":1,\
+         \"wall_time_ns\":"
+    ), "{}", stderr);
+    assert!(stderr.ends_with("}\n"), "{}", stderr);
+
+    // generated code only knows how long it took
+    let out = brainfuck(&["--stats", "tests/fixtures/hello.b"]);
+    assert_eq!(out.status.code(), Some(0));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.starts_with("instructions               -\nbytes read                 -\n"), "{}", stderr);
+    assert!(stderr.contains("\nwall time   "), "{}", stderr);
+
+    let out = brainfuck(&["--stats", "--batch-lines", "tests/fixtures/hello.b"]);
+    assert_ne!(out.status.code(), Some(0));
+}
+
+#[test]
+fn test_profile_folded() {
+    let folded = std::env::temp_dir().join(format!("brainfuck-cli-{}-profile.folded", std::process::id()));
+    let out = brainfuck_with_input(&["--profile-folded", folded.to_str().unwrap(), "tests/fixtures/branch.b"], b"x");
+    assert_eq!(out.status.code(), Some(0));
+    let stacks = std::fs::read_to_string(&folded).unwrap();
+    std::fs::remove_file(&folded).unwrap();
+    // `,` and `>.` outside, `[>+<` and `]` once, the clear loop's `[` once and `-]` 120 times
+    assert_eq!(stacks, "main 3\nmain;loop@39 5\nmain;loop@39;loop@43 241\n");
+}
+
+#[test]
+fn test_watch() {
+    use std::io::Read;
+
+    let program = std::env::temp_dir().join(format!("brainfuck-cli-{}-watch.b", std::process::id()));
+    std::fs::write(&program, format!("{}.", "+".repeat(65))).unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_brainfuck"))
+        .args(["--watch", program.to_str().unwrap()])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdout = child.stdout.take().unwrap();
+    let mut byte = [0];
+    stdout.read_exact(&mut byte).unwrap();
+    assert_eq!(byte, *b"A");
+
+    // a compile error doesn't end watching, the save after it runs again
+    std::fs::write(&program, "[").unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    std::fs::write(&program, format!("{}.", "+".repeat(66))).unwrap();
+    stdout.read_exact(&mut byte).unwrap();
+    assert_eq!(byte, *b"B");
+
+    child.kill().unwrap();
+    let out = child.wait_with_output().unwrap();
+    std::fs::remove_file(&program).unwrap();
+    assert!(String::from_utf8_lossy(&out.stderr).contains("exited with 1, waiting for changes"));
+}
+
+#[test]
+fn test_batch_lines() {
+    let lines = std::env::temp_dir().join(format!("brainfuck-cli-{}-lines.txt", std::process::id()));
+    std::fs::write(&lines, "Hello\nWorld!\n\nabc").unwrap();
+    let arg = format!("--batch-lines={}", lines.to_str().unwrap());
+    let out = brainfuck(&[&arg, "tests/fixtures/rot13.b"]);
+    std::fs::remove_file(&lines).unwrap();
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "Uryyb\nJbeyq!\n\nnop\n");
+
+    let out = brainfuck_with_input(&["--batch-lines", "--keep-newline", "tests/fixtures/rot13.b"], b"ab\ncd");
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "no\n\npq\n");
+
+    // prints a line backwards, lines of more than 2 bytes don't fit
+    let program = std::env::temp_dir().join(format!("brainfuck-cli-{}-reverse.b", std::process::id()));
+    let program = program.to_str().unwrap();
+    std::fs::write(program, ">,[>,]<[.<]").unwrap();
+    let out = brainfuck_with_input(&["--batch-lines", "--tape-size", "4", program], b"ab\nabc\ncd\n");
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(out.stdout, b"ba\n");
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        "<stdin>:2: error: pointer moved past the end of the tape at instruction 3\n"
+    );
+    let out = brainfuck_with_input(
+        &["--batch-lines", "--keep-going", "--tape-size", "4", program], b"ab\nabc\ncd\n"
+    );
+    std::fs::remove_file(program).unwrap();
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(out.stdout, b"ba\ndc\n");
+}
+
+#[test]
+fn test_batch_lines_jobs() {
+    let input: String = (0..100).map(|i| format!("line {}\n", i)).collect();
+    let sequential = brainfuck_with_input(&["--batch-lines", "tests/fixtures/rot13.b"], input.as_bytes());
+    let parallel = brainfuck_with_input(&["--batch-lines", "--jobs", "4", "tests/fixtures/rot13.b"], input.as_bytes());
+    assert_eq!(parallel.status.code(), Some(0));
+    assert_eq!(parallel.stdout, sequential.stdout);
+    assert!(String::from_utf8_lossy(&parallel.stdout).ends_with("yvar 98\nyvar 99\n"));
+
+    let out = brainfuck_with_input(&["--batch-lines", "--jobs", "0", "tests/fixtures/rot13.b"], b"");
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(String::from_utf8_lossy(&out.stderr), "error: invalid number of jobs '0'\n");
+}
+
+#[test]
+fn test_pipe() {
+    let input = b"Hello, World!\nabc xyz\n";
+    let out = brainfuck_with_input(&["pipe", "tests/fixtures/rot13.b", "tests/fixtures/rot13.b"], input);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(out.stdout, input);
+
+    let out = brainfuck_with_input(&["pipe", "tests/fixtures/rot13.b", "tests/fixtures/underflow.b"], input);
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        "tests/fixtures/underflow.b: error: stage 2 failed: pointer moved below the start of the tape \
+         at instruction 2\n"
+    );
+
+    let out = brainfuck(&["pipe", "tests/fixtures/rot13.b"]);
+    assert_eq!(out.status.code(), Some(1));
+}
+
+#[test]
+fn test_test_runner() {
+    let out = brainfuck(&["test", "tests/suite", "--engine", "both", "-j", "2"]);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stdout),
+        "hello ... ok\nrot13 ... ok\n\nran 2 test(s): 2 passed, 0 failed\n"
+    );
+
+    let dir = std::env::temp_dir().join(format!("brainfuck-cli-{}-suite", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("echo.b"), ",[.[-],]").unwrap();
+    std::fs::write(dir.join("echo.in"), "one\ntwo\nthree\n").unwrap();
+    std::fs::write(dir.join("echo.expected"), "one\n2\nthree\n").unwrap();
+    std::fs::write(dir.join("forever.b"), "+[]").unwrap();
+    std::fs::write(dir.join("forever.expected"), "").unwrap();
+    // no .expected, not a test
+    std::fs::write(dir.join("helper.b"), "+").unwrap();
+
+    let out = brainfuck(&["test", dir.to_str().unwrap(), "--engine", "interp", "--steps", "10000"]);
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stdout),
+        "echo ... FAILED\nforever ... FAILED\n\n\
+         ---- echo ----\n\
+         the interpreter printed something else\n\
+         --- expected\n\
+         +++ actual\n\
+         @@ -1,3 +1,3 @@\n \
+         one\n\
+         -2\n\
+         +two\n \
+         three\n\n\
+         ---- forever ----\n\
+         the interpreter is still running after 10000 steps\n\n\
+         ran 2 test(s): 0 passed, 2 failed\n"
+    );
+}
+
+#[test]
+fn test_compile_bytecode() {
+    let source = temp_copy("rot13.b", "compile.b");
+    let compiled = source.replace(".b", ".bfc");
+
+    let out = brainfuck(&["compile", &source]);
+    assert_eq!(out.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&out.stderr).contains(&format!("-> {} (", compiled)));
+
+    // runs like the source, without it
+    std::fs::remove_file(&source).unwrap();
+    let out = brainfuck_with_input(&[&compiled], b"Hello, abc-XYZ");
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(out.stdout, b"Uryyb, nop-KLM");
+
+    let out = brainfuck(&["--coverage", &compiled]);
+    assert_eq!(out.status.code(), Some(1));
+
+    let mut bytes = std::fs::read(&compiled).unwrap();
+    let last = bytes.len() - 1;
+    bytes[last] ^= 1;
+    std::fs::write(&compiled, &bytes).unwrap();
+    let out = brainfuck(&[&compiled]);
+    std::fs::remove_file(&compiled).unwrap();
+    assert_eq!(out.status.code(), Some(1));
+    assert!(out.stdout.is_empty());
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        format!("{}: error: corrupted bytecode: checksum mismatch\n", compiled)
+    );
+}
+
+#[test]
+fn test_compile_ir() {
+    let source = temp_copy("rot13.b", "compile-ir.b");
+    let ir = source.replace(".b", ".ir");
+
+    let out = brainfuck(&["compile", "--emit", "ir", &source]);
+    std::fs::remove_file(&source).unwrap();
+    assert_eq!(out.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&out.stderr).contains(&format!("-> {} (", ir)));
+
+    let out = brainfuck_with_input(&[&ir], b"Hello, abc-XYZ");
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(out.stdout, b"Uryyb, nop-KLM");
+
+    // an edit that breaks it is reported at its line
+    let mut text = std::fs::read_to_string(&ir).unwrap();
+    text.push_str("}\n");
+    let line = text.lines().count();
+    let edited = ir.replace(".ir", ".txt");
+    std::fs::write(&edited, &text).unwrap();
+    std::fs::remove_file(&ir).unwrap();
+    let out = brainfuck(&["--lang", "ir", &edited]);
+    std::fs::remove_file(&edited).unwrap();
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        format!("{}: error: line {}: '}}' without a loop to close\n", edited, line)
+    );
+}
+
+#[test]
+fn test_codegen_error_at_run_time() {
+    let path = std::env::temp_dir().join(format!("brainfuck-cli-{}-far.ir", std::process::id()));
+    let path = path.to_str().unwrap();
+    std::fs::write(path, "add +1\nmove +2147483648\nprint\n").unwrap();
+
+    // only running needs the code
+    let dump = brainfuck(&["--lang", "ir", "--dump", path]);
+    let run = brainfuck(&["--lang", "ir", path]);
+    std::fs::remove_file(path).unwrap();
+    assert_eq!(dump.status.code(), Some(0));
+    assert!(!dump.stdout.is_empty());
+    assert_eq!(run.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&run.stderr),
+        format!("{}: error: pointer move at instruction 1 too large (2147483648 cells)\n", path)
+    );
+}
+
+#[test]
+fn test_compile_listing() {
+    let source = temp_copy("hello.b", "compile-listing.b");
+    let listing = source.replace(".b", ".lst");
+
+    let out = brainfuck(&["compile", "--emit", "listing", &source]);
+    std::fs::remove_file(&source).unwrap();
+    assert_eq!(out.status.code(), Some(0));
+    let text = std::fs::read_to_string(&listing).unwrap();
+    std::fs::remove_file(&listing).unwrap();
+    assert!(text.starts_with("       (prologue)\n          0000  49 89 f9"), "{}", text);
+    assert!(text.contains("       0: IncVal(8)\n          0003  80 06 08"), "{}", text);
+    assert!(text.contains("syscall\n"));
+    assert!(!text.contains("db 0x"));
+}
+
+#[test]
+fn test_compile_code() {
+    let source = temp_copy("hello.b", "compile-code.b");
+    let code = source.replace(".b", ".bin");
+
+    let out = brainfuck(&["compile", "--emit", "code", &source]);
+    std::fs::remove_file(&source).unwrap();
+    assert_eq!(out.status.code(), Some(0));
+    let bytes = std::fs::read(&code).unwrap();
+    std::fs::remove_file(&code).unwrap();
+    assert!(bytes.starts_with(&[0x49, 0x89, 0xf9]));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        format!("{}: machine code -> {} ({} bytes)\n", source, code, bytes.len())
+    );
+
+    let out = brainfuck(&["compile", "--emit", "code", "-o", &code, "tests/fixtures/unbalanced.b"]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(!std::path::Path::new(&code).exists());
+    assert_eq!(String::from_utf8_lossy(&out.stderr), "tests/fixtures/unbalanced.b:1:8: error: unmatched ']'\n");
+}
+
+#[test]
+fn test_compile_macho() {
+    let source = temp_copy("hello.b", "compile-macho.b");
+    let executable = source.replace(".b", "");
+
+    let out = brainfuck(&["compile", "--emit", "macho", &source]);
+    std::fs::remove_file(&source).unwrap();
+    assert_eq!(out.status.code(), Some(0));
+    let bytes = std::fs::read(&executable).unwrap();
+    assert!(bytes.starts_with(&[0xcf, 0xfa, 0xed, 0xfe, 0x07, 0x00, 0x00, 0x01]));
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&executable).unwrap().permissions().mode();
+        assert_eq!(mode & 0o111, 0o111);
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        let out = std::process::Command::new(&executable).output().unwrap();
+        assert_eq!(out.status.code(), Some(0));
+        assert_eq!(out.stdout, b"Hello World!\n");
+    }
+    std::fs::remove_file(&executable).unwrap();
+}
+
+#[test]
+fn test_compile_pe() {
+    let source = temp_copy("hello.b", "compile-pe.b");
+    let executable = source.replace(".b", ".exe");
+
+    let out = brainfuck(&["compile", "--emit", "pe", &source]);
+    std::fs::remove_file(&source).unwrap();
+    assert_eq!(out.status.code(), Some(0));
+    let bytes = std::fs::read(&executable).unwrap();
+    assert!(bytes.starts_with(b"MZ"));
+    assert_eq!(&bytes[0x40..0x46], b"PE\0\0\x64\x86");
+
+    #[cfg(windows)]
+    {
+        let out = std::process::Command::new(&executable).output().unwrap();
+        assert_eq!(out.status.code(), Some(0));
+        assert_eq!(out.stdout, b"Hello World!\n");
+    }
+    std::fs::remove_file(&executable).unwrap();
+}
+
+#[test]
+fn test_brainloller() {
+    // the image's pixels snake down it, printing "Hi"
+    let out = brainfuck(&["tests/fixtures/hi.png"]);
+    if cfg!(feature = "brainloller") {
+        assert_eq!(out.status.code(), Some(0));
+        assert_eq!(out.stdout, b"Hi");
+    } else {
+        assert_eq!(out.status.code(), Some(1));
+        assert_eq!(
+            String::from_utf8_lossy(&out.stderr),
+            "tests/fixtures/hi.png: error: reading images needs the brainloller feature\n"
+        );
+    }
+}
+
+#[test]
+fn test_record_input() {
+    use std::{env, fs};
+
+    let dir = env::temp_dir();
+    let program = dir.join(format!("brainfuck-cli-{}-record.b", std::process::id()));
+    let record = dir.join(format!("brainfuck-cli-{}-record.input", std::process::id()));
+    let (program, record) = (program.to_str().unwrap(), record.to_str().unwrap());
+    fs::write(program, ",+.,+.,+.").unwrap();
+
+    // only what the program read, however much stdin had to offer
+    let session = brainfuck_with_input(&["--record-input", record, program], b"abcdef");
+    assert_eq!(session.status.code(), Some(0));
+    assert_eq!(session.stdout, b"bcd");
+    assert_eq!(fs::read(record).unwrap(), b"abc");
+
+    let out = brainfuck(&["--replay-input", record, program]);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(out.stdout, session.stdout);
+
+    fs::write(program, ",+.,+.,+.,+.").unwrap();
+    let out = brainfuck(&["--replay-input", record, program]);
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(out.stdout, b"bcd");
+    assert_eq!(String::from_utf8_lossy(&out.stderr), "error: the program read more than the 3 recorded bytes\n");
+    let out = brainfuck_with_input(&["--replay-input", record, "--replay-then-stdin", program], b"xyz");
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(out.stdout, b"bcdy");
+
+    fs::remove_file(program).unwrap();
+    fs::remove_file(record).unwrap();
+}
+
+#[test]
+fn test_dump_jit() {
+    // not a terminal, so the raw bytes
+    let out = brainfuck(&["--dump-jit", "tests/fixtures/hello.b"]);
+    assert_eq!(out.status.code(), Some(0));
+    assert!(out.stdout.starts_with(&[0x49, 0x89, 0xf9]));
+    // ending in the stub of the last pointer check, jumping back to the epilogue
+    assert_eq!(out.stdout[out.stdout.len() - 5], 0xe9);
+
+    let out = brainfuck(&["--raw", "tests/fixtures/hello.b"]);
+    assert_ne!(out.status.code(), Some(0));
+    assert!(out.stdout.is_empty());
+}
+
+#[test]
+fn test_raw_input_without_terminal() {
+    let out = brainfuck_with_input(&["--raw-input", "tests/fixtures/rot13.b"], b"abc");
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(out.stdout, b"nop");
+}
+
+#[test]
+fn test_tape_init() {
+    let init = std::env::temp_dir().join(format!("brainfuck-cli-{}-tape", std::process::id()));
+    std::fs::write(&init, b"\0HAL\0").unwrap();
+    let init = init.to_str().unwrap();
+
+    // increments and prints every cell up to the terminator
+    let program = "tests/fixtures/increment.b";
+    let out = brainfuck(&["--tape-init", init, "--pointer-start", "1", program]);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(out.stdout, b"IBM");
+
+    // same in the interpreter
+    let out = brainfuck(&["--tape-init", init, "--pointer-start", "1", "--detect-livelock", program]);
+    assert_eq!(out.stdout, b"IBM");
+
+    let out = brainfuck(&["--tape-init", init, "--tape-size", "4", program]);
+    std::fs::remove_file(init).unwrap();
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        format!("{}: error: initial tape of 5 cells doesn't fit on the tape of 4\n", init)
+    );
+}
+
+#[test]
+fn test_tape_dump() {
+    let out = brainfuck(&["--tape-dump", "tests/fixtures/hello.b"]);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(out.stdout, b"Hello World!\n");
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        "00000000  00 00 48 64 57 21 0a                              |..HdW!.|\n\
+         00000007\n"
+    );
+
+    let dump = std::env::temp_dir().join(format!("brainfuck-cli-{}-dump", std::process::id()));
+    let arg = format!("--tape-dump={}", dump.to_str().unwrap());
+    let out = brainfuck(&[&arg, "--tape-dump-format", "nonzero", "--detect-livelock", "tests/fixtures/hello.b"]);
+    assert_eq!(out.status.code(), Some(0));
+    assert!(out.stderr.is_empty());
+    assert_eq!(
+        std::fs::read_to_string(&dump).unwrap(),
+        "cell 2 = 72 'H'\ncell 3 = 100 'd'\ncell 4 = 87 'W'\ncell 5 = 33 '!'\ncell 6 = 10\n"
+    );
+    std::fs::remove_file(&dump).unwrap();
+}
+
+#[test]
+fn test_sandbox() {
+    let out = brainfuck(&["--sandbox", "tests/fixtures/hello.b"]);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(out.stdout, b"Hello World!\n");
+
+    let out = brainfuck_with_input(&["--sandbox", "tests/fixtures/rot13.b"], b"abc");
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(out.stdout, b"nop");
+
+    // nothing could be written after the run
+    let out = brainfuck(&["--sandbox", "--tape-dump", "tests/fixtures/hello.b"]);
+    assert_eq!(out.status.code(), Some(1));
+
+    // errors are still reported from inside the sandbox
+    let out = brainfuck(&["--sandbox", "tests/fixtures/underflow.b"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        "error: pointer moved below the start of the tape at instruction 2\n"
+    );
+}
+
+#[test]
+fn test_pointer_underflow() {
+    let out = brainfuck(&["tests/fixtures/underflow.b"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        "error: pointer moved below the start of the tape at instruction 2\n"
+    );
+}
+
+#[test]
+fn test_crash_report() {
+    // generated code only tells which instruction failed
+    let out = brainfuck(&["--crash-report", "tests/fixtures/underflow.b"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        "error: pointer moved below the start of the tape at instruction 2\n\
+         crash report: pointer moved below the start of the tape at instruction 2\n\
+         at tests/fixtures/underflow.b:2:3, instruction 2 DecPtr(1)\n\
+         2 | +[<]\n  \
+           |   ^\n\
+         pointer unknown, generated code doesn't keep it\n\
+         steps unknown, generated code doesn't count them\n"
+    );
+
+    // the interpreter knows everything
+    let report = std::env::temp_dir().join(format!("brainfuck-cli-{}-crash.json", std::process::id()));
+    let report = report.to_str().unwrap();
+    let out = brainfuck(&[
+        "--detect-livelock", &format!("--crash-report={}", report), "--crash-report-format", "json",
+        "tests/fixtures/underflow.b",
+    ]);
+    let json = std::fs::read_to_string(report).unwrap();
+    std::fs::remove_file(report).unwrap();
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        "error: pointer moved below the start of the tape at instruction 2\n"
+    );
+    assert!(json.starts_with(
+        "{\"code\":\"pointer-underflow\",\"message\":\"pointer moved below the start of the tape at instruction 2\",\
+         \"file\":\"tests/fixtures/underflow.b\",\"inst_index\":2,\"inst\":\"DecPtr(1)\",\"offset\":37,\
+         \"line\":2,\"column\":3,\"pointer\":0,\"steps\":2,\"cells\":{\"start\":0,\"values\":[1,0,"
+    ), "{}", json);
+    assert!(json.ends_with(
+        "\"recent\":[{\"index\":0,\"inst\":\"IncVal(1)\",\"offset\":35},\
+         {\"index\":1,\"inst\":\"JmpFwd(3)\",\"offset\":36}]}\n"
+    ), "{}", json);
+}
+
+#[test]
+fn test_tape_guard() {
+    let out = brainfuck(&["--tape-size", "4", "tests/fixtures/off_by_one.b"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        "error: pointer moved past the end of the tape at instruction 3\n"
+    );
+
+    // the guard lets it finish, but not unnoticed
+    let out = brainfuck(&["--tape-size", "4", "--tape-guard=2", "tests/fixtures/off_by_one.b"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        "error: the program changed cells of the guards off the tape, from 4 to 4, its results \
+         can't be trusted\n"
+    );
+
+    let out = brainfuck(&["--tape-guard=many", "tests/fixtures/hello.b"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(String::from_utf8_lossy(&out.stderr), "error: invalid tape guard 'many'\n");
+}
+
+#[test]
+fn test_tape_file() {
+    let path = std::env::temp_dir().join(format!("brainfuck-cli-{}-tape-file", std::process::id()));
+    std::fs::write(&path, b"\0HAL\0").unwrap();
+    let path = path.to_str().unwrap();
+
+    // increments and prints every cell up to the terminator, in the file too
+    let program = "tests/fixtures/increment.b";
+    let out = brainfuck(&["--tape-file", path, "--tape-size", "5", "--pointer-start", "1", program]);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(out.stdout, b"IBM");
+    assert_eq!(std::fs::read(path).unwrap(), b"\0IBM\0");
+
+    let out = brainfuck(&["--tape-file", path, "--pointer-start", "1", program]);
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(String::from_utf8_lossy(&out.stderr), "error: tape file of 5 bytes for a tape of 30000 cells\n");
+
+    let out = brainfuck(&[
+        "--tape-file", path, "--tape-file-resize", "--tape-size", "8", "--pointer-start", "1", program
+    ]);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(out.stdout, b"JCN");
+    assert_eq!(std::fs::read(path).unwrap(), b"\0JCN\0\0\0\0");
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_tape_model_unbounded() {
+    let out = brainfuck(&[
+        "--tape-model", "unbounded", "--tape-dump", "--tape-dump-format", "nonzero", "tests/fixtures/underflow.b",
+    ]);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&out.stderr), "cell 0 = 1\n");
+
+    let program = std::env::temp_dir().join(format!("brainfuck-cli-{}-two-sided.b", std::process::id()));
+    let program = program.to_str().unwrap();
+    std::fs::write(program, "<<+++>>>++").unwrap();
+    let out = brainfuck(&["--tape-model", "unbounded", "--tape-dump", program]);
+    std::fs::remove_file(program).unwrap();
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        "-00000002  03 00 00 02                                       |....|\n \
+         00000002\n"
+    );
+
+    let out = brainfuck(&["--tape-model", "unbounded", "--tape-size", "100", "tests/fixtures/hello.b"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(String::from_utf8_lossy(&out.stderr), "error: --tape-size needs a tape with ends\n");
+}
+
+#[test]
+fn test_arith() {
+    let out = brainfuck(&["tests/fixtures/below_zero.b"]);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(out.stdout, [255]);
+
+    let out = brainfuck(&["--arith", "saturate", "tests/fixtures/below_zero.b"]);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(out.stdout, [0]);
+
+    // the same in the interpreter
+    for args in [&["--arith", "trap"][..], &["--arith", "trap", "--detect-livelock"]] {
+        let out = brainfuck(&[args, &["tests/fixtures/below_zero.b"]].concat());
+        assert_eq!(out.status.code(), Some(2));
+        assert!(out.stdout.is_empty());
+        assert_eq!(String::from_utf8_lossy(&out.stderr), "error: cell went below 0 at instruction 0\n");
+    }
+}
+
+#[cfg(feature = "bignum")]
+#[test]
+fn test_arith_unbounded() {
+    let out = brainfuck(&["--arith", "unbounded", "tests/fixtures/below_zero.b"]);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(out.stdout, [0]);
+
+    let out = brainfuck(&["--arith", "unbounded-trap", "tests/fixtures/below_zero.b"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(String::from_utf8_lossy(&out.stderr), "error: cell went below 0 at instruction 0\n");
+
+    // 256 isn't 0, but prints as it
+    let program = std::env::temp_dir().join(format!("brainfuck-cli-{}-unbounded.b", std::process::id()));
+    let program = program.to_str().unwrap();
+    std::fs::write(program, format!("{}[.[-]]", "+".repeat(256))).unwrap();
+    let out = brainfuck(&["--arith", "unbounded", program]);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(out.stdout, [0]);
+    let out = brainfuck(&[program]);
+    std::fs::remove_file(program).unwrap();
+    assert!(out.stdout.is_empty());
+
+    let out = brainfuck(&["--arith", "unbounded", "--precompute", "tests/fixtures/hello.b"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(String::from_utf8_lossy(&out.stderr), "error: --precompute needs cells of 8 bits\n");
+}
+
+#[test]
+fn test_max_memory() {
+    let out = brainfuck(&["--max-memory", "1K", "tests/fixtures/hello.b"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        "error: memory limit of 1024 bytes exceeded, 30000 bytes needed\n"
+    );
+    let out = brainfuck(&["--max-memory", "1K", "--detect-livelock", "tests/fixtures/hello.b"]);
+    assert_eq!(out.status.code(), Some(2));
+
+    let out = brainfuck(&["--max-memory", "1K", "--tape-size", "1024", "tests/fixtures/hello.b"]);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(out.stdout, b"Hello World!\n");
+
+    let out = brainfuck(&["--max-memory", "lots", "tests/fixtures/hello.b"]);
+    assert_eq!(out.status.code(), Some(2));
+}
+
+#[test]
+fn test_perf_map() {
+    let child = Command::new(env!("CARGO_BIN_EXE_brainfuck"))
+        .args(["--perf-map", "tests/fixtures/hello.b"])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let map = format!("/tmp/perf-{}.map", child.id());
+    let out = child.wait_with_output().unwrap();
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(out.stdout, b"Hello World!\n");
+
+    let entries = std::fs::read_to_string(&map).unwrap();
+    std::fs::remove_file(&map).unwrap();
+    let names: Vec<&str> = entries.lines().map(|line| line.rsplit(' ').next().unwrap()).collect();
+    assert_eq!(names.first(), Some(&"bf_program"));
+    assert!(names.contains(&"bf_loop_1_src_8"), "{:?}", names);
+}
+
+#[test]
+fn test_verbose() {
+    let out = brainfuck(&["--verbose", "tests/fixtures/hello.b"]);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(out.stdout, b"Hello World!\n");
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    let lines: Vec<_> = stderr.lines().collect();
+    assert_eq!(lines.len(), 2, "{}", stderr);
+    assert!(lines[0].starts_with("tests/fixtures/hello.b: compiled 59 instructions to "), "{}", stderr);
+    assert!(lines[1].starts_with("tests/fixtures/hello.b: ran in "), "{}", stderr);
+
+    // nothing without it
+    assert!(brainfuck(&["tests/fixtures/hello.b"]).stderr.is_empty());
+
+    // how the tape ended up allocated, if anything else was asked for
+    let out = brainfuck(&["--verbose", "--tape-alloc", "huge", "--tape-size", "4194304", "tests/fixtures/hello.b"]);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(out.stdout, b"Hello World!\n");
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    let last = stderr.lines().last().unwrap();
+    assert!(last.starts_with("tests/fixtures/hello.b: tape allocated as "), "{}", stderr);
+}
+
+#[test]
+fn test_precompute() {
+    let out = brainfuck(&["--precompute", "--verbose", "tests/fixtures/hello.b"]);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(out.stdout, b"Hello World!\n");
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("tests/fixtures/hello.b: precomputed 13 bytes of output in "), "{}", stderr);
+    let out = brainfuck(&["--precompute", "--tape-dump-format", "nonzero", "--tape-dump", "tests/fixtures/hello.b"]);
+    assert_eq!(out.stderr, brainfuck(&["--tape-dump-format", "nonzero", "--tape-dump", "tests/fixtures/hello.b"]).stderr);
+
+    // falling back to the generated code
+    let out = brainfuck(&["--precompute=100", "--verbose", "tests/programs/squares.b"]);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(out.stdout, std::fs::read("tests/programs/squares.out").unwrap());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("squares.b: not precomputed, still running after 100 steps in "), "{}", stderr);
+    let out = brainfuck_with_input(&["--precompute", "--verbose", "tests/fixtures/rot13.b"], b"abc");
+    assert_eq!(out.stdout, b"nop");
+    assert!(String::from_utf8_lossy(&out.stderr).contains("not precomputed, the program reads input"));
+
+    let out = brainfuck(&["--precompute=lots", "tests/fixtures/hello.b"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(out.stderr, b"error: invalid step budget 'lots'\n");
+}
+
+#[test]
+fn test_dump() {
+    // the plain dump is what `Brainfuck::dump` always printed
+    let out = brainfuck(&["--dump", "tests/fixtures/rot13.b"]);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(out.stdout, std::fs::read("tests/fixtures/rot13.dump").unwrap());
+    assert_eq!(brainfuck(&["--dump", "--color", "never", "tests/fixtures/rot13.b"]).stdout, out.stdout);
+
+    let out = brainfuck(&["--dump", "--color", "always", "tests/fixtures/rot13.b"]);
+    assert_eq!(out.status.code(), Some(0));
+    let colored = String::from_utf8(out.stdout).unwrap();
+    assert!(colored.starts_with("\x1b[2m0:\x1b[0m DecVal\x1b[2m(1)\x1b[0m\n"), "{}", colored);
+    assert!(colored.contains("\x1b[1;33m[ ──▶ 139\x1b[0m"), "{}", colored);
+}
+
+#[test]
+fn test_dump_labels() {
+    let out = brainfuck(&["--dump", "--dump-labels", "tests/fixtures/increment.b"]);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stdout),
+        "L0: [\n    IncVal(1)\n    PrintCell\n    IncPtr(1)\n] -> L0\n\nL0: instruction 0, source 47..48\n"
+    );
+
+    assert_eq!(brainfuck(&["--dump-labels", "tests/fixtures/increment.b"]).status.code(), Some(1));
+}
+
+#[test]
+fn test_visualize() {
+    // not a terminal, so the output as usual and a summary on stderr
+    let out = brainfuck_with_input(&["--visualize", "tests/fixtures/rot13.b"], b"Hi");
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(out.stdout, b"Uv");
+    let summary = String::from_utf8(out.stderr).unwrap();
+    assert!(summary.starts_with("step 4279, finished\ncell     0    1"), "{}", summary);
+    assert!(summary.ends_with("next -\noutput \"Uv\"\n"), "{}", summary);
+
+    let out = brainfuck(&["--visualize", "--visualize-every", "0", "tests/fixtures/rot13.b"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(out.stderr, b"error: invalid --visualize-every '0'\n");
+    assert_eq!(brainfuck(&["--visualize", "--coverage", "tests/fixtures/rot13.b"]).status.code(), Some(1));
+}
+
+#[test]
+fn test_trace_json() {
+    let path = std::env::temp_dir().join(format!("brainfuck-cli-{}-trace.jsonl", std::process::id()));
+    let path = path.to_str().unwrap();
+
+    // the output as usual, a line for the header and every fifth step
+    let out = brainfuck_with_input(&["--trace-json", path, "--trace-sample", "5", "tests/fixtures/rot13.b"], b"Hi");
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(out.stdout, b"Uv");
+    let trace = std::fs::read_to_string(path).unwrap();
+    std::fs::remove_file(path).unwrap();
+    assert!(trace.starts_with("{\"trace\":1,\"program\":\""), "{}", trace);
+    assert!(trace.contains("\"tape_size\":30000,\"pointer_start\":0,\"arith\":\"wrap\",\"sample\":5}\n"));
+    assert_eq!(trace.lines().count(), 1 + 4279 / 5 + 1);
+
+    let out = brainfuck(&["--trace-json", path, "--trace-sample", "0", "tests/fixtures/rot13.b"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(out.stderr, b"error: invalid --trace-sample '0'\n");
+    assert_eq!(brainfuck(&["--trace-json", path, "--visualize", "tests/fixtures/rot13.b"]).status.code(), Some(1));
+}
+
+#[test]
+fn test_report_html() {
+    let dir = std::env::temp_dir();
+    let page = dir.join(format!("brainfuck-cli-{}-report.html", std::process::id()));
+    let page = page.to_str().unwrap();
+
+    let out = brainfuck_with_input(&["--report-html", page, "tests/fixtures/rot13.b"], b"Hi");
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(out.stdout, b"Uv");
+    let html = std::fs::read_to_string(page).unwrap();
+    std::fs::remove_file(page).unwrap();
+    assert!(html.starts_with("<!DOCTYPE html>\n"));
+    assert!(html.contains("<title>tests/fixtures/rot13.b</title>"));
+    assert!(html.contains("<script type=\"application/json\" id=\"run\">{\"tape_size\":30000,"));
+
+    // a run too long for a page gets none
+    let program = dir.join(format!("brainfuck-cli-{}-report.b", std::process::id()));
+    std::fs::write(&program, "+[]").unwrap();
+    let out = brainfuck(&["--report-html", page, program.to_str().unwrap()]);
+    std::fs::remove_file(&program).unwrap();
+    assert_eq!(out.status.code(), Some(2));
+    assert!(String::from_utf8(out.stderr).unwrap()
+        .contains("the run takes more than 50000 steps, too many for --report-html"));
+    assert!(!std::path::Path::new(page).exists());
+    assert_eq!(brainfuck(&["--report-html", page, "--trace-json", page, "tests/fixtures/rot13.b"]).status.code(), Some(1));
+}
+
+#[test]
+fn test_debug_needs_terminal() {
+    let out = brainfuck(&["debug", "tests/fixtures/hello.b"]);
+    assert_eq!(out.status.code(), Some(3));
+    assert_eq!(out.stderr, b"error: debug needs a terminal\n");
+}
+
+#[test]
+fn test_lsp() {
+    let frame = |body: &str| format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+    let input = [
+        r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"capabilities":{}}}"#,
+        r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#,
+        r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///x.b",
+           "languageId":"brainfuck","version":1,"text":"+[>+<-]]"}}}"#,
+        r#"{"jsonrpc":"2.0","id":2,"method":"shutdown"}"#,
+        r#"{"jsonrpc":"2.0","method":"exit"}"#,
+    ]
+    .iter()
+    .map(|body| frame(body))
+    .collect::<String>();
+
+    let out = brainfuck_with_input(&["lsp"], input.as_bytes());
+    assert_eq!(out.status.code(), Some(0));
+    assert!(out.stderr.is_empty());
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    let mut messages = Vec::new();
+    let mut rest = &stdout[..];
+    while !rest.is_empty() {
+        let header_end = rest.find("\r\n\r\n").unwrap();
+        let length: usize = rest["Content-Length: ".len()..header_end].parse().unwrap();
+        messages.push(&rest[header_end + 4..header_end + 4 + length]);
+        rest = &rest[header_end + 4 + length..];
+    }
+    assert_eq!(messages.len(), 3);
+    assert!(messages[0].starts_with(r#"{"jsonrpc":"2.0","id":1,"result":{"capabilities":"#), "{}", messages[0]);
+    assert_eq!(
+        messages[1],
+        r#"{"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///x.b","diagnostics":[{"range":{"start":{"line":0,"character":7},"end":{"line":0,"character":8}},"severity":1,"code":"unmatched-close","source":"brainfuck","message":"unmatched ']'"}]}}"#
+    );
+    assert_eq!(messages[2], r#"{"jsonrpc":"2.0","id":2,"result":null}"#);
+
+    // exiting before shutting down is an error
+    let out = brainfuck_with_input(&["lsp"], frame(r#"{"jsonrpc":"2.0","method":"exit"}"#).as_bytes());
+    assert_eq!(out.status.code(), Some(1));
+    assert!(out.stdout.is_empty());
+}
+
+// Runs `forever.b` with `args` until it printed its line, interrupts it and
+// returns how it exited and what it wrote to stderr
+fn interrupt_forever(args: &[&str]) -> (Option<i32>, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_brainfuck"))
+        .args(args)
+        .arg("tests/fixtures/forever.b")
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut printed = [0; 2];
+    child.stdout.take().unwrap().read_exact(&mut printed).unwrap();
+    assert_eq!(printed, *b"0\n");
+    unsafe {
+        libc::kill(child.id() as libc::pid_t, libc::SIGINT);
+    }
+    let out = child.wait_with_output().unwrap();
+
+    (out.status.code(), String::from_utf8_lossy(&out.stderr).into_owned())
+}
+
+#[test]
+fn test_interrupt() {
+    // the interpreter knows how far it got, the `]` of the endless loop
+    let (status, stderr) = interrupt_forever(&["--detect-livelock"]);
+    assert_eq!(status, Some(2));
+    assert!(stderr.starts_with("tests/fixtures/forever.b:1:39: error: interrupted after "), "{}", stderr);
+    assert!(stderr.ends_with(" steps at source offset 38\n"), "{}", stderr);
+
+    let (status, stderr) = interrupt_forever(&[]);
+    assert_eq!(status, Some(2));
+    assert!(stderr.starts_with("tests/fixtures/forever.b: error: interrupted after "), "{}", stderr);
+    assert!(stderr.ends_with(", generated code counts no steps\n"), "{}", stderr);
+}
@@ -0,0 +1,34 @@
+// Builds tests/c/ffi.c against the shared library and runs it, only with
+// the `ffi` feature and a C compiler around
+#![cfg(feature = "ffi")]
+
+use std::path::Path;
+use std::process::Command;
+
+#[test]
+fn test_c_program() {
+    if Command::new("cc").arg("--version").output().is_err() {
+        eprintln!("no C compiler, skipping");
+        return;
+    }
+
+    // the copy of the shared library next to the binary may come from a
+    // build without the feature, the one in deps/ is from this build
+    let lib_dir = Path::new(env!("CARGO_BIN_EXE_brainfuck")).parent().unwrap().join("deps");
+    let exe = std::env::temp_dir().join(format!("brainfuck-ffi-{}", std::process::id()));
+    let status = Command::new("cc")
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args(["-Wall", "-Werror", "-Iinclude", "tests/c/ffi.c", "-o"])
+        .arg(&exe)
+        .arg(format!("-L{}", lib_dir.display()))
+        .arg("-lbrainfuck")
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    // cargo points the library path at the copy next to the binary
+    let out = Command::new(&exe).env("LD_LIBRARY_PATH", &lib_dir).output().unwrap();
+    std::fs::remove_file(&exe).unwrap();
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(out.stdout, b"AB");
+}
@@ -0,0 +1,2 @@
+prints the cell after taking one from zero
+-.
@@ -0,0 +1,8 @@
+A loop closed twice
++[>+<-]]
+
+this one is fine
+++[->+<]
+
+and this one is never closed
+>[-
@@ -0,0 +1,2 @@
+read a byte and print 1 if it was set
+,[>+<[-]]>.
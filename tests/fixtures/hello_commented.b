@@ -0,0 +1,17 @@
+hello dot b with comments and its own layout
+
+++++++++ [                     eight times
+    >++++ [                    four times
+        >++ >+++ >+++ >+       the letters and the space
+        <<<<-
+    ]
+    >+ >+ >- >>+
+    [<]                        back to the counter
+    <-
+]
+>>.                            H
+>---.                          e
++++++++..+++.                  llo
+>>.                            space
+<-. <. +++. ------. --------.  World
+>>+. >++.                      the bang and a newline
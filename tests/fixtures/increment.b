@@ -0,0 +1,2 @@
+increment every cell up to a zero and print it
+[+.>]
@@ -0,0 +1,5 @@
+print a star
+++++++[>+++++++<-]>.
+
+then spin forever
+  [-+]
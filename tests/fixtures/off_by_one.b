@@ -0,0 +1,2 @@
+steps one cell off the right end of a tape of four cells and back
++[->>>>+<]
@@ -0,0 +1,8 @@
+[ a comment loop that mentions commands: +-<>., ]
+++++++++++ >+< -- >< +-        cell 0 = 8 / cell 1 = 1
+[>++++++++<-]                  cell 1 = 65
+>.                             print A
+[-][ dead as the cell is zero ]
+<,.                            echo one byte
+[+++]                          clears for any value
++++++ +++++ .                  print a newline
@@ -0,0 +1,71 @@
+// Builds the command line tool for WASI and runs it under wasmtime, only
+// with wasmtime and the wasm32-wasip1 target around
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+fn wasmtime(wasm: &Path, args: &[&str], input: &[u8]) -> Output {
+    // the repository is the preopened directory, paths are relative to it
+    let mut child = Command::new("wasmtime")
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args(["run", "--dir", "."])
+        .arg(wasm)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn build() -> Option<PathBuf> {
+    if Command::new("wasmtime").arg("--version").output().is_err() {
+        eprintln!("no wasmtime, skipping");
+        return None;
+    }
+    let installed = Command::new("rustup").args(["target", "list", "--installed"]).output();
+    if !installed.is_ok_and(|out| String::from_utf8_lossy(&out.stdout).lines().any(|t| t == "wasm32-wasip1")) {
+        eprintln!("no wasm32-wasip1 target, skipping");
+        return None;
+    }
+
+    // a target directory of its own, so this doesn't wait on the build
+    // running the tests
+    let target_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("wasi");
+    let status = Command::new(env!("CARGO"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args(["build", "--bin", "brainfuck", "--target", "wasm32-wasip1", "--target-dir"])
+        .arg(&target_dir)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    Some(target_dir.join("wasm32-wasip1/debug/brainfuck.wasm"))
+}
+
+#[test]
+fn test_wasi() {
+    let wasm = match build() {
+        Some(wasm) => wasm,
+        None => return,
+    };
+
+    let out = wasmtime(&wasm, &["tests/fixtures/hello.b"], b"");
+    assert_eq!(out.status.code(), Some(0), "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(out.stdout, b"Hello World!\n");
+
+    let out = wasmtime(&wasm, &["tests/fixtures/rot13.b"], b"abc");
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(out.stdout, b"nop");
+
+    // compile errors still point into the source
+    let out = wasmtime(&wasm, &["tests/fixtures/unbalanced.b"], b"");
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&out.stderr), "tests/fixtures/unbalanced.b:1:8: error: unmatched ']'\n");
+
+    let out = wasmtime(&wasm, &["--perf-map", "tests/fixtures/hello.b"], b"");
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(String::from_utf8_lossy(&out.stderr), "error: --perf-map isn't supported under WASI\n");
+}
@@ -0,0 +1,64 @@
+// The `brainfuck_wasm` JavaScript module, built with wasm-pack, see README.md
+use wasm_bindgen::prelude::*;
+
+use brainfuck::wasm::{WasmError, WasmProgram};
+
+
+// Where parsing or running a program went wrong, thrown as an exception
+#[wasm_bindgen]
+#[derive(Debug)]
+pub struct ProgramError(WasmError);
+
+#[wasm_bindgen]
+impl ProgramError {
+    #[wasm_bindgen(getter)]
+    pub fn message(&self) -> String {
+        self.0.message.clone()
+    }
+
+    // in bytes of the source, if known
+    #[wasm_bindgen(getter)]
+    pub fn offset(&self) -> Option<usize> {
+        self.0.offset
+    }
+
+    // counted from 1, the column in characters
+    #[wasm_bindgen(getter)]
+    pub fn line(&self) -> Option<usize> {
+        self.0.line
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn column(&self) -> Option<usize> {
+        self.0.column
+    }
+
+    // "line:column: message"
+    #[wasm_bindgen(js_name = toString)]
+    pub fn describe(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+// A parsed program, run in the interpreter with a budget of steps
+#[wasm_bindgen]
+pub struct Program(WasmProgram);
+
+#[wasm_bindgen]
+impl Program {
+    #[wasm_bindgen(constructor)]
+    pub fn new(source: &str) -> Result<Program, ProgramError> {
+        WasmProgram::new(source).map(Program).map_err(ProgramError)
+    }
+
+    // Returns what the program printed with `input` as all of its input,
+    // throws once it took more than `max_steps` steps
+    pub fn run(&self, input: &[u8], max_steps: usize) -> Result<Vec<u8>, ProgramError> {
+        self.0.run(input, max_steps).map_err(ProgramError)
+    }
+
+    // The instructions as listed by `--dump`
+    pub fn dump(&self) -> String {
+        self.0.dump()
+    }
+}
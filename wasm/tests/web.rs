@@ -0,0 +1,26 @@
+// `wasm-pack test --node`, runs in the interpreter compiled to WebAssembly
+use wasm_bindgen_test::wasm_bindgen_test;
+
+use brainfuck_wasm::Program;
+
+
+#[wasm_bindgen_test]
+fn test_program() {
+    let program = Program::new(include_str!("../../tests/fixtures/hello.b")).unwrap();
+    assert_eq!(program.run(b"", 1_000_000).unwrap(), b"Hello World!\n");
+    assert!(program.dump().starts_with("0: IncVal(8)\n"));
+
+    let program = Program::new(",[.[-],]").unwrap();
+    assert_eq!(program.run(b"echo", 10_000).unwrap(), b"echo");
+}
+
+#[wasm_bindgen_test]
+fn test_program_error() {
+    let e = Program::new("+\n+]").err().unwrap();
+    assert_eq!((e.message(), e.offset(), e.line(), e.column()), ("unmatched ']'".to_string(), Some(3), Some(2), Some(2)));
+    assert_eq!(e.describe(), "2:2: unmatched ']'");
+
+    let e = Program::new("+[]").unwrap().run(b"", 1000).err().unwrap();
+    assert_eq!(e.message(), "still running after 1000 steps");
+    assert_eq!(e.line(), Some(1));
+}